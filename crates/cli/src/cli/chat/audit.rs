@@ -0,0 +1,359 @@
+//! The audit log of the tools used in chats, kept with `chat.audit.enabled` for those who need to
+//! account for what was run on their machine: a JSON object per line appended to
+//! `chat.audit.path`, `audit.jsonl` in the data directory by default, for every tool the model
+//! asked to use, whether it ran or not. Shown with `q chat audit`.
+
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use eyre::Result;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tracing::warn;
+
+use super::cli::ChatAudit;
+use super::session::format_local_time;
+use super::tool_output;
+use super::tools::{
+    InvokeOutput,
+    OutputKind,
+};
+use super::util::shared_writer::SharedWriter;
+use crate::database::Database;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::util::directories;
+
+/// How the use of a tool was decided on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// Ran without asking, being trusted or only reading.
+    Trusted,
+    /// Ran once the user accepted it.
+    Approved,
+    /// Not run, the user answering something else than accepting it.
+    Rejected,
+    /// Not run, being disabled or denied by the user or by `deniedTools`.
+    Denied,
+    /// Not run, being able to change things in read-only mode.
+    ReadOnly,
+    /// Not run, only described in a dry run.
+    DryRun,
+    /// Not run, a `pre_tool_use` hook stopping it.
+    StoppedByHook,
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Trusted => write!(f, "trusted"),
+            Decision::Approved => write!(f, "approved"),
+            Decision::Rejected => write!(f, "rejected"),
+            Decision::Denied => write!(f, "denied"),
+            Decision::ReadOnly => write!(f, "read-only"),
+            Decision::DryRun => write!(f, "dry run"),
+            Decision::StoppedByHook => write!(f, "stopped by hook"),
+        }
+    }
+}
+
+/// How a tool that ran went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub duration: Duration,
+    pub success: bool,
+    /// The exit status of the command of `execute_bash` and of the tools of `mcp.json`.
+    pub exit_status: Option<String>,
+    /// The SHA-256 of the output, or of the error, in hex.
+    pub output_sha256: String,
+}
+
+impl Outcome {
+    pub fn of(result: &Result<InvokeOutput>, duration: Duration) -> Self {
+        let (success, exit_status, output) = match result {
+            Ok(output) => {
+                let exit_status = match &output.output {
+                    OutputKind::Json(json) => json.get("exit_status").map(|status| match status {
+                        Value::String(status) => status.clone(),
+                        status => status.to_string(),
+                    }),
+                    _ => None,
+                };
+                (true, exit_status, tool_output::full_text(&output.output))
+            },
+            Err(err) => (false, None, err.to_string()),
+        };
+        Self {
+            duration,
+            success,
+            exit_status,
+            output_sha256: Sha256::digest(output.as_bytes())
+                .iter()
+                .fold(String::new(), |mut hex, byte| {
+                    let _ = write!(hex, "{byte:02x}");
+                    hex
+                }),
+        }
+    }
+}
+
+/// A line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub time: String,
+    pub conversation_id: String,
+    pub tool_use_id: String,
+    pub tool: String,
+    /// The arguments as the model gave them.
+    pub arguments: Value,
+    pub decision: Decision,
+    /// The fields below are only there for the tools that ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_sha256: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(
+        conversation_id: &str,
+        tool_use_id: &str,
+        tool: &str,
+        arguments: &Value,
+        decision: Decision,
+        outcome: Option<&Outcome>,
+    ) -> Self {
+        Self {
+            time: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            conversation_id: conversation_id.to_string(),
+            tool_use_id: tool_use_id.to_string(),
+            tool: tool.to_string(),
+            arguments: arguments.clone(),
+            decision,
+            duration_ms: outcome.map(|outcome| outcome.duration.as_millis() as u64),
+            success: outcome.map(|outcome| outcome.success),
+            exit_status: outcome.and_then(|outcome| outcome.exit_status.clone()),
+            output_sha256: outcome.map(|outcome| outcome.output_sha256.clone()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    /// The log of `settings`, `None` unless `chat.audit.enabled` is on.
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        if !settings.get_bool(Setting::ChatAuditEnabled).unwrap_or(false) {
+            return None;
+        }
+        match path(settings) {
+            Ok(path) => Some(Self { path }),
+            Err(err) => {
+                warn!(?err, "Failed to find where to keep the audit log");
+                None
+            },
+        }
+    }
+
+    /// Appends `record`, only ever adding to the file. Failing to write is logged, so that the chat
+    /// can go on.
+    pub fn append(&self, record: &AuditRecord) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!(?err, "Failed to serialize an audit record");
+                return;
+            },
+        };
+        if let Some(parent) = self.path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Err(err) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{line}"))
+        {
+            warn!(?err, path = %self.path.display(), "Failed to append to the audit log");
+        }
+    }
+}
+
+/// `chat.audit.path`, or the default path of the audit log.
+pub fn path(settings: &Settings) -> Result<PathBuf> {
+    match settings.get_string(Setting::ChatAuditPath) {
+        Some(path) => Ok(PathBuf::from(shellexpand::tilde(&path).as_ref())),
+        None => Ok(directories::chat_audit_log_path()?),
+    }
+}
+
+/// The records of `log` matching the filters of `args`, at most the last `args.limit`. Lines that
+/// aren't records are skipped.
+fn read_records(log: &str, args: &ChatAudit, now: OffsetDateTime) -> Vec<AuditRecord> {
+    let since = args.since.map(|since| now - since);
+    let records = log
+        .lines()
+        .filter_map(|line| serde_json::from_str::<AuditRecord>(line).ok())
+        .filter(|record| args.tool.as_ref().is_none_or(|tool| &record.tool == tool))
+        .filter(|record| {
+            since.is_none_or(|since| OffsetDateTime::parse(&record.time, &Rfc3339).is_ok_and(|time| time >= since))
+        })
+        .collect::<Vec<_>>();
+    let skip = records.len().saturating_sub(args.limit);
+    records.into_iter().skip(skip).collect()
+}
+
+/// One line for `record`: when, the decision, the tool and what it was given.
+fn format_record(record: &AuditRecord) -> String {
+    let time = OffsetDateTime::parse(&record.time, &Rfc3339).map_or_else(|_| record.time.clone(), format_local_time);
+    let mut line = format!("{time}  {:<15}  {}", record.decision.to_string(), record.tool);
+    match (&record.exit_status, record.success) {
+        (Some(exit_status), _) => line.push_str(&format!("  exit {exit_status}")),
+        (None, Some(false)) => line.push_str("  failed"),
+        _ => (),
+    }
+    if let Some(duration_ms) = record.duration_ms {
+        line.push_str(&format!("  {:.1}s", duration_ms as f64 / 1000.0));
+    }
+    let arguments = match ["command", "path", "query"]
+        .iter()
+        .find_map(|key| record.arguments.get(key).and_then(Value::as_str))
+    {
+        Some(argument) => argument.to_string(),
+        None => record.arguments.to_string(),
+    };
+    let arguments = arguments.split_whitespace().collect::<Vec<_>>().join(" ");
+    match arguments.char_indices().nth(80) {
+        Some((end, _)) => line.push_str(&format!("\n    {}...", &arguments[..end])),
+        None => line.push_str(&format!("\n    {arguments}")),
+    }
+    line
+}
+
+pub async fn execute_audit(database: &Database, args: ChatAudit) -> Result<ExitCode> {
+    let mut output = SharedWriter::stdout();
+    let path = path(&database.settings)?;
+    let log = match std::fs::read_to_string(&path) {
+        Ok(log) => log,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            writeln!(
+                output,
+                "\nNo audit log at {}, keep one with: q settings chat.audit.enabled true\n",
+                path.display()
+            )?;
+            output.flush()?;
+            return Ok(ExitCode::FAILURE);
+        },
+        Err(err) => return Err(err.into()),
+    };
+
+    let records = read_records(&log, &args, OffsetDateTime::now_utc());
+    if args.json {
+        for record in &records {
+            writeln!(output, "{}", serde_json::to_string(record)?)?;
+        }
+    } else if records.is_empty() {
+        writeln!(output, "\nNo tool use in the audit log matches\n")?;
+    } else {
+        writeln!(output)?;
+        for record in &records {
+            writeln!(output, "{}", format_record(record))?;
+        }
+        writeln!(output)?;
+    }
+
+    output.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog {
+            path: dir.path().join("nested").join("audit.jsonl"),
+        };
+        let command = serde_json::json!({ "command": "ls -la" });
+        let output = InvokeOutput {
+            output: OutputKind::Json(serde_json::json!({ "exit_status": "0", "stdout": "file", "stderr": "" })),
+        };
+        let outcome = Outcome::of(&Ok(output), Duration::from_millis(1200));
+        assert_eq!(outcome.exit_status.as_deref(), Some("0"));
+        assert_eq!(outcome.output_sha256.len(), 64);
+
+        log.append(&AuditRecord::new(
+            "conv",
+            "t1",
+            "execute_bash",
+            &command,
+            Decision::Approved,
+            Some(&outcome),
+        ));
+        log.append(&AuditRecord::new(
+            "conv",
+            "t2",
+            "fs_write",
+            &serde_json::json!({ "path": "README.md" }),
+            Decision::Rejected,
+            None,
+        ));
+
+        let content = std::fs::read_to_string(&log.path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        let mut args = ChatAudit {
+            tool: None,
+            since: None,
+            limit: 50,
+            json: false,
+        };
+        let records = read_records(&content, &args, OffsetDateTime::now_utc());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].duration_ms, Some(1200));
+        assert_eq!(records[1].decision, Decision::Rejected);
+        assert_eq!(records[1].success, None);
+        assert!(format_record(&records[0]).contains("approved         execute_bash  exit 0  1.2s\n    ls -la"));
+
+        args.tool = Some("fs_write".to_string());
+        let records = read_records(&content, &args, OffsetDateTime::now_utc());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].tool_use_id, "t2");
+
+        args.tool = None;
+        args.limit = 1;
+        assert_eq!(
+            read_records(&content, &args, OffsetDateTime::now_utc())[0].tool_use_id,
+            "t2"
+        );
+
+        args.limit = 50;
+        args.since = Some(Duration::from_secs(60 * 60));
+        let later = OffsetDateTime::now_utc() + Duration::from_secs(2 * 60 * 60);
+        assert!(read_records(&content, &args, later).is_empty());
+    }
+}
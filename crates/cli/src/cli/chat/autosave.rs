@@ -0,0 +1,290 @@
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use serde::Deserialize;
+use tracing::warn;
+
+use super::conversation_state::ConversationState;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+
+/// How many turns pass between autosaves, unless `chat.autosave.turns` is set.
+const DEFAULT_TURNS: usize = 5;
+
+/// How many autosaves are kept across sessions, the oldest being replaced first.
+const SLOTS: usize = 3;
+
+/// An autosave, along with the process that wrote it.
+#[derive(Deserialize)]
+struct Snapshot {
+    pid: u32,
+    conversation: ConversationState,
+}
+
+/// A conversation autosaved by a session that didn't exit cleanly, see
+/// [Autosaver::find_unclean_exit].
+pub struct UncleanExit {
+    pub path: PathBuf,
+    pub conversation: ConversationState,
+}
+
+/// Snapshots the conversation every `chat.autosave.turns` turns (0 disables it), so that it can
+/// be resumed after the terminal is closed or Q is killed.
+///
+/// Each session writes to its own slot, which is removed when the session exits cleanly. A slot
+/// that is left behind by a process that isn't running anymore means that it exited uncleanly.
+#[derive(Debug)]
+pub struct Autosaver {
+    every: usize,
+    turns: usize,
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Autosaver {
+    pub fn new(settings: &Settings, dir: PathBuf, conversation_id: &str) -> Self {
+        Self {
+            every: settings
+                .get_int(Setting::ChatAutosaveTurns)
+                .and_then(|turns| usize::try_from(turns).ok())
+                .unwrap_or(DEFAULT_TURNS),
+            turns: 0,
+            path: dir.join(format!("{conversation_id}.json")),
+            dir,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.every > 0
+    }
+
+    /// Counts a completed turn, saving `state` when enough of them passed since the last save.
+    pub async fn turn_completed(&mut self, ctx: &Context, state: &ConversationState) {
+        self.turns += 1;
+        if !self.enabled() || self.turns % self.every != 0 {
+            return;
+        }
+        if let Err(err) = self.save(ctx, state).await {
+            warn!(?err, path = ?self.path, "Failed to autosave the conversation");
+        }
+    }
+
+    async fn save(&self, ctx: &Context, state: &ConversationState) -> eyre::Result<()> {
+        let contents = serde_json::to_string(&serde_json::json!({
+            "pid": std::process::id(),
+            "conversation": state,
+        }))?;
+        ctx.fs().create_dir_all(&self.dir).await?;
+        ctx.fs().write(&self.path, contents).await?;
+
+        // Make room by dropping the oldest slots
+        for stale in slots(ctx, &self.dir).await?.into_iter().skip(SLOTS) {
+            let _ = ctx.fs().remove_file(stale).await;
+        }
+        Ok(())
+    }
+
+    /// Removes the slot of this session, called when it exits cleanly.
+    pub async fn discard(&self, ctx: &Context) {
+        if ctx.fs().exists(&self.path) {
+            let _ = ctx.fs().remove_file(&self.path).await;
+        }
+    }
+
+    /// The most recent conversation autosaved by a session that is no longer running and didn't
+    /// remove its slot when exiting.
+    pub async fn find_unclean_exit(&self, ctx: &Context) -> Option<UncleanExit> {
+        if !self.enabled() {
+            return None;
+        }
+        for path in slots(ctx, &self.dir).await.ok()? {
+            let Ok(contents) = ctx.fs().read_to_string(&path).await else {
+                continue;
+            };
+            let Ok(snapshot) = serde_json::from_str::<Snapshot>(&contents) else {
+                continue;
+            };
+            if path == self.path || snapshot.conversation.history().is_empty() || is_running(snapshot.pid) {
+                continue;
+            }
+            return Some(UncleanExit {
+                path,
+                conversation: snapshot.conversation,
+            });
+        }
+        None
+    }
+}
+
+/// A conversation autosaved by a session, listed by `q chat sessions`.
+pub struct Autosave {
+    pub path: PathBuf,
+    pub conversation: ConversationState,
+    pub modified: Option<SystemTime>,
+    /// Whether the session that saved it is still running, otherwise it exited uncleanly.
+    pub running: bool,
+}
+
+/// The conversations autosaved in `dir`, the most recent first.
+pub async fn list_autosaves(ctx: &Context, dir: &Path) -> std::io::Result<Vec<Autosave>> {
+    let mut autosaves = Vec::new();
+    for path in slots(ctx, dir).await? {
+        let Ok(contents) = ctx.fs().read_to_string(&path).await else {
+            continue;
+        };
+        let Ok(snapshot) = serde_json::from_str::<Snapshot>(&contents) else {
+            continue;
+        };
+        autosaves.push(Autosave {
+            modified: ctx
+                .fs()
+                .symlink_metadata(&path)
+                .await
+                .ok()
+                .and_then(|metadata| metadata.modified().ok()),
+            running: is_running(snapshot.pid),
+            conversation: snapshot.conversation,
+            path,
+        });
+    }
+    Ok(autosaves)
+}
+
+/// Deletes the autosaves in `dir` of sessions that aren't running anymore and that were last saved
+/// more than `older_than` before `now`, or only lists them with `dry_run`.
+pub async fn prune_autosaves(
+    ctx: &Context,
+    dir: &Path,
+    older_than: Duration,
+    now: SystemTime,
+    dry_run: bool,
+) -> std::io::Result<Vec<Autosave>> {
+    let mut pruned = Vec::new();
+    for autosave in list_autosaves(ctx, dir).await? {
+        let expired = autosave
+            .modified
+            .is_some_and(|modified| now.duration_since(modified).is_ok_and(|age| age > older_than));
+        if autosave.running || !expired {
+            continue;
+        }
+        if !dry_run {
+            ctx.fs().remove_file(&autosave.path).await?;
+        }
+        pruned.push(autosave);
+    }
+    Ok(pruned)
+}
+
+/// The autosaves in `dir`, the most recent first.
+async fn slots(ctx: &Context, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut slots = Vec::new();
+    let mut entries = match ctx.fs().read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        // Joined to `dir` rather than using the entry's path, which is outside of a chroot
+        let path = dir.join(entry.file_name());
+        if path.extension().is_some_and(|extension| extension == "json") {
+            let modified = entry
+                .metadata()
+                .await
+                .ok()
+                .and_then(|metadata| metadata.modified().ok());
+            slots.push((modified, path));
+        }
+    }
+    slots.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(slots.into_iter().map(|(_, path)| path).collect())
+}
+
+fn is_running(pid: u32) -> bool {
+    let pid = sysinfo::Pid::from_u32(pid);
+    let mut system = sysinfo::System::new();
+    system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+    system.process(pid).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cli::chat::message::AssistantMessage;
+    use crate::cli::chat::tool_manager::ToolManager;
+    use crate::database::Database;
+
+    #[test]
+    fn test_is_running() {
+        assert!(is_running(std::process::id()));
+        assert!(!is_running(u32::MAX - 1));
+    }
+
+    #[tokio::test]
+    async fn test_autosaver() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut database = Database::new().await.unwrap();
+        database.settings.set(Setting::ChatAutosaveTurns, 0).await.unwrap();
+        assert!(!Autosaver::new(&database.settings, PathBuf::from("/autosave"), "id").enabled());
+
+        database.settings.set(Setting::ChatAutosaveTurns, 2).await.unwrap();
+        let mut autosaver = Autosaver::new(&database.settings, PathBuf::from("/autosave"), "id");
+        let mut state = ConversationState::new(
+            Arc::clone(&ctx),
+            "id",
+            HashMap::new(),
+            None,
+            None,
+            ToolManager::default(),
+        )
+        .await;
+        state.set_next_user_message("hello".to_string()).await;
+        state.push_assistant_message(AssistantMessage::new_response(None, "hi".to_string()), &mut database);
+
+        autosaver.turn_completed(&ctx, &state).await;
+        assert!(!ctx.fs().exists("/autosave/id.json"));
+        autosaver.turn_completed(&ctx, &state).await;
+        assert!(ctx.fs().exists("/autosave/id.json"));
+
+        // The slot of this session doesn't count as an unclean exit, but one left by a process
+        // that isn't running does
+        assert!(autosaver.find_unclean_exit(&ctx).await.is_none());
+        let contents = serde_json::json!({ "pid": u32::MAX - 1, "conversation": state }).to_string();
+        ctx.fs().write("/autosave/other.json", contents).await.unwrap();
+        let unclean = autosaver.find_unclean_exit(&ctx).await.unwrap();
+        assert_eq!(unclean.path, PathBuf::from("/autosave/other.json"));
+        assert_eq!(unclean.conversation.history().len(), 1);
+
+        // Only autosaves of sessions that aren't running anymore expire
+        let dir = Path::new("/autosave");
+        let later = SystemTime::now() + Duration::from_secs(60 * 60);
+        let expired = prune_autosaves(&ctx, dir, Duration::from_secs(2 * 60 * 60), later, false)
+            .await
+            .unwrap();
+        assert!(expired.is_empty());
+        let expired = prune_autosaves(&ctx, dir, Duration::from_secs(60), later, true)
+            .await
+            .unwrap();
+        assert_eq!(expired.len(), 1);
+        assert!(ctx.fs().exists("/autosave/other.json"));
+        prune_autosaves(&ctx, dir, Duration::from_secs(60), later, false)
+            .await
+            .unwrap();
+        assert!(!ctx.fs().exists("/autosave/other.json"));
+        assert!(ctx.fs().exists("/autosave/id.json"));
+
+        autosaver.discard(&ctx).await;
+        assert!(!ctx.fs().exists("/autosave/id.json"));
+    }
+}
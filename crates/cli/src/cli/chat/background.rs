@@ -0,0 +1,299 @@
+//! Commands the model starts in the background with the `background` argument of `execute_bash`,
+//! e.g. dev servers and builds in watch mode, which keep running while the conversation goes on.
+//! They are listed with `/ps`, their output shown with `/logs` and stopped with `/kill`, and the
+//! model reads their recent output with the `process_output` tool. Those still running when the
+//! chat ends are killed.
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::process::Stdio;
+use std::sync::{
+    Arc,
+    Mutex,
+    Weak,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use eyre::{
+    Context as _,
+    Result,
+};
+use tokio::io::{
+    AsyncBufReadExt,
+    AsyncRead,
+    BufReader,
+};
+use tracing::error;
+
+use super::tools::environment::ToolEnv;
+use super::tools::execute_bash::command_line;
+use super::tools::sandbox::Sandbox;
+use crate::util::process::{
+    Pid,
+    kill_process_group,
+};
+
+/// How many lines of the output of each command are kept, the oldest being dropped.
+const MAX_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessStatus {
+    Running,
+    /// With the exit code, `None` when terminated by a signal.
+    Exited(Option<i32>),
+    /// Killed with `/kill`, or as the chat ended.
+    Killed,
+}
+
+impl Display for ProcessStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessStatus::Running => write!(f, "running"),
+            ProcessStatus::Exited(Some(code)) => write!(f, "exited with status {code}"),
+            ProcessStatus::Exited(None) => write!(f, "terminated by a signal"),
+            ProcessStatus::Killed => write!(f, "killed"),
+        }
+    }
+}
+
+/// What is known of a background command, without its output.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub id: usize,
+    pub command: String,
+    pub pid: Option<u32>,
+    pub started: Instant,
+    pub status: ProcessStatus,
+}
+
+#[derive(Debug)]
+struct Process {
+    info: ProcessInfo,
+    /// The last [MAX_LINES] lines of stdout and stderr, in the order they came.
+    output: VecDeque<String>,
+}
+
+impl Process {
+    /// Kills the process group of the command, started in one of its own.
+    fn kill(&mut self) {
+        if self.info.status != ProcessStatus::Running {
+            return;
+        }
+        if let Some(pid) = self.info.pid {
+            if let Err(err) = kill_process_group(Pid::from_u32(pid)) {
+                error!(%err, "Failed to kill the background command");
+            }
+        }
+        self.info.status = ProcessStatus::Killed;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    last_id: usize,
+    processes: Vec<Process>,
+}
+
+impl Table {
+    fn get_mut(&mut self, id: usize) -> Option<&mut Process> {
+        self.processes.iter_mut().find(|process| process.info.id == id)
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        for process in &mut self.processes {
+            process.kill();
+        }
+    }
+}
+
+/// The background commands of a chat, numbered from 1 in the order they were started. Clones
+/// share the table, which the tasks reading the output of the commands only hold weakly so that
+/// the commands are killed along with the chat.
+#[derive(Debug, Clone, Default)]
+pub struct BackgroundProcesses(Arc<Mutex<Table>>);
+
+impl BackgroundProcesses {
+    /// Starts `command` as [run_command](super::tools::execute_bash::run_command) would without
+    /// waiting for it, returning its id.
+    pub fn spawn(&self, command: &str, sandbox: Option<&Sandbox>, env: &ToolEnv) -> Result<usize> {
+        let command_line = command_line(command, sandbox);
+        let mut cmd = tokio::process::Command::new(&command_line[0]);
+        cmd.args(&command_line[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        env.apply(&mut cmd);
+        #[cfg(unix)]
+        cmd.process_group(0);
+        let mut child = cmd
+            .spawn()
+            .wrap_err_with(|| format!("Unable to spawn command '{}'", command))?;
+
+        let id = {
+            let mut table = self.0.lock().unwrap();
+            table.last_id += 1;
+            let id = table.last_id;
+            table.processes.push(Process {
+                info: ProcessInfo {
+                    id,
+                    command: command.to_string(),
+                    pid: child.id(),
+                    started: Instant::now(),
+                    status: ProcessStatus::Running,
+                },
+                output: VecDeque::new(),
+            });
+            id
+        };
+
+        let table = Arc::downgrade(&self.0);
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(read_output(table.clone(), id, stdout));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(read_output(table.clone(), id, stderr));
+        }
+        tokio::spawn(async move {
+            let exit_status = child.wait().await;
+            let Some(table) = table.upgrade() else {
+                return;
+            };
+            let mut table = table.lock().unwrap();
+            if let Some(process) = table.get_mut(id) {
+                if process.info.status == ProcessStatus::Running {
+                    process.info.status = ProcessStatus::Exited(exit_status.ok().and_then(|status| status.code()));
+                    // Whatever it left running in its group isn't tracked anymore
+                    process.info.pid = None;
+                }
+            }
+        });
+        Ok(id)
+    }
+
+    pub fn list(&self) -> Vec<ProcessInfo> {
+        let table = self.0.lock().unwrap();
+        table.processes.iter().map(|process| process.info.clone()).collect()
+    }
+
+    pub fn get(&self, id: usize) -> Option<ProcessInfo> {
+        let mut table = self.0.lock().unwrap();
+        table.get_mut(id).map(|process| process.info.clone())
+    }
+
+    /// The last `lines` lines of output of the command `id`, `None` when there's no such command.
+    pub fn output(&self, id: usize, lines: usize) -> Option<Vec<String>> {
+        let mut table = self.0.lock().unwrap();
+        let process = table.get_mut(id)?;
+        let skip = process.output.len().saturating_sub(lines);
+        Some(process.output.iter().skip(skip).cloned().collect())
+    }
+
+    /// Kills the command `id`, returning what it was before, `None` when there's no such command.
+    pub fn kill(&self, id: usize) -> Option<ProcessStatus> {
+        let mut table = self.0.lock().unwrap();
+        let process = table.get_mut(id)?;
+        let status = process.info.status;
+        process.kill();
+        Some(status)
+    }
+
+    /// Waits for the command `id` to exit for at most `timeout`, returning its status then.
+    pub async fn wait(&self, id: usize, timeout: Duration) -> Option<ProcessStatus> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let status = self.get(id)?.status;
+            if status != ProcessStatus::Running || Instant::now() >= deadline {
+                return Some(status);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+/// Adds the lines of `output` to those of the command `id` until it closes or the table is gone.
+async fn read_output(table: Weak<Mutex<Table>>, id: usize, output: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(output).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                error!(%err, "Failed to read the output of a background command");
+                break;
+            },
+        };
+        let Some(table) = table.upgrade() else {
+            break;
+        };
+        let mut table = table.lock().unwrap();
+        if let Some(process) = table.get_mut(id) {
+            if process.output.len() >= MAX_LINES {
+                process.output.pop_front();
+            }
+            process.output.push_back(line);
+        }
+    }
+}
+
+/// `elapsed` rounded to the second, e.g. `2m 13s`.
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_background_processes() {
+        let processes = BackgroundProcesses::default();
+        let env = ToolEnv::default();
+
+        let id = processes.spawn("echo one; echo two >&2; exit 3", None, &env).unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(
+            processes.wait(id, Duration::from_secs(10)).await,
+            Some(ProcessStatus::Exited(Some(3)))
+        );
+        // The output may come in after the exit
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while processes.output(id, 10).unwrap().len() < 2 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let mut output = processes.output(id, 10).unwrap();
+        output.sort();
+        assert_eq!(output, ["one", "two"]);
+        assert_eq!(processes.output(id, 1).unwrap().len(), 1);
+
+        let id = processes.spawn("sleep 60", None, &env).unwrap();
+        assert_eq!(id, 2);
+        assert_eq!(
+            processes.wait(id, Duration::from_millis(100)).await,
+            Some(ProcessStatus::Running)
+        );
+        assert_eq!(processes.kill(id), Some(ProcessStatus::Running));
+        assert_eq!(processes.get(id).unwrap().status, ProcessStatus::Killed);
+        assert_eq!(processes.kill(id), Some(ProcessStatus::Killed));
+
+        assert_eq!(processes.list().len(), 2);
+        assert!(processes.output(3, 10).is_none());
+        assert!(processes.kill(3).is_none());
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_millis(900)), "0s");
+        assert_eq!(format_elapsed(Duration::from_secs(133)), "2m 13s");
+        assert_eq!(format_elapsed(Duration::from_secs(7500)), "2h 5m");
+    }
+}
@@ -0,0 +1,185 @@
+use super::conversation_state::ConversationState;
+use super::session::is_session_name;
+
+/// The name of the branch a chat starts on.
+pub const MAIN_BRANCH: &str = "main";
+
+/// Versions of the conversation made with `/fork` and conversations started with `/new`, of
+/// which one is chatted on at a time.
+#[derive(Debug)]
+pub struct Branches {
+    /// The name of the branch being chatted on, whose state is the conversation.
+    current: String,
+    /// The other branches, in the order they were left.
+    others: Vec<Branch>,
+}
+
+/// A branch that is not chatted on.
+#[derive(Debug)]
+pub struct Branch {
+    pub name: String,
+    pub state: ConversationState,
+    /// The name the branch was last saved or loaded with, see `/save`.
+    pub session_name: Option<String>,
+}
+
+impl Default for Branches {
+    fn default() -> Self {
+        Self {
+            current: MAIN_BRANCH.to_string(),
+            others: Vec::new(),
+        }
+    }
+}
+
+impl Branches {
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    pub fn others(&self) -> &[Branch] {
+        &self.others
+    }
+
+    /// Makes a branch called `name` from `state`, the conversation of the current branch, and
+    /// makes it current. Returns the name of the new branch, `fork-<n>` without `name`.
+    pub fn fork(
+        &mut self,
+        name: Option<String>,
+        state: &ConversationState,
+        session_name: Option<String>,
+    ) -> Result<String, String> {
+        self.add(name, "fork", state, session_name)
+    }
+
+    /// Makes a branch called `name` for a new conversation and makes it current, keeping `state`
+    /// as the conversation of the branch that was. Returns the name of the new branch, `chat-<n>`
+    /// without `name`.
+    pub fn new_conversation(
+        &mut self,
+        name: Option<String>,
+        state: &ConversationState,
+        session_name: Option<String>,
+    ) -> Result<String, String> {
+        self.add(name, "chat", state, session_name)
+    }
+
+    fn add(
+        &mut self,
+        name: Option<String>,
+        prefix: &str,
+        state: &ConversationState,
+        session_name: Option<String>,
+    ) -> Result<String, String> {
+        let name = match name {
+            Some(name) if !is_session_name(&name) => {
+                return Err(format!(
+                    "'{name}' is not a valid branch name, use only letters, digits, - and _"
+                ));
+            },
+            Some(name) if self.exists(&name) => return Err(format!("A branch called {name} already exists")),
+            Some(name) => name,
+            None => (1..)
+                .map(|n| format!("{prefix}-{n}"))
+                .find(|name| !self.exists(name))
+                .unwrap_or_default(),
+        };
+
+        let current = std::mem::replace(&mut self.current, name.clone());
+        self.others.push(Branch {
+            name: current,
+            state: state.clone(),
+            session_name,
+        });
+        Ok(name)
+    }
+
+    /// Makes the branch called `name` current, keeping `state` as the conversation of the branch
+    /// that was. Returns the branch to continue with.
+    pub fn switch(
+        &mut self,
+        name: &str,
+        state: &ConversationState,
+        session_name: Option<String>,
+    ) -> Result<Branch, String> {
+        if name == self.current {
+            return Err(format!("Already on the branch {name}"));
+        }
+        let Some(index) = self.others.iter().position(|branch| branch.name == name) else {
+            let mut names = self
+                .others
+                .iter()
+                .map(|branch| branch.name.as_str())
+                .collect::<Vec<_>>();
+            names.push(&self.current);
+            return Err(format!("No branch is called {name}. Branches: {}", names.join(", ")));
+        };
+
+        let branch = self.others.remove(index);
+        let current = std::mem::replace(&mut self.current, name.to_string());
+        self.others.push(Branch {
+            name: current,
+            state: state.clone(),
+            session_name,
+        });
+        Ok(branch)
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.current == name || self.others.iter().any(|branch| branch.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::cli::chat::tool_manager::ToolManager;
+    use crate::platform::Context;
+
+    #[tokio::test]
+    async fn test_branches() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let state = ConversationState::new(ctx, "id", HashMap::new(), None, None, ToolManager::default()).await;
+        let mut branches = Branches::default();
+        assert_eq!(branches.current(), MAIN_BRANCH);
+
+        assert_eq!(
+            branches.fork(None, &state, Some("saved".to_string())),
+            Ok("fork-1".to_string())
+        );
+        assert_eq!(
+            branches.fork(Some("sqs".to_string()), &state, None),
+            Ok("sqs".to_string())
+        );
+        assert!(branches.fork(Some("main".to_string()), &state, None).is_err());
+        assert!(branches.fork(Some("not valid".to_string()), &state, None).is_err());
+        assert_eq!(branches.current(), "sqs");
+
+        assert!(branches.switch("sqs", &state, None).is_err());
+        let err = branches.switch("missing", &state, None).unwrap_err();
+        assert!(err.contains("main, fork-1, sqs"));
+
+        let branch = branches.switch(MAIN_BRANCH, &state, None).unwrap();
+        assert_eq!(branch.session_name.as_deref(), Some("saved"));
+        assert_eq!(branches.current(), MAIN_BRANCH);
+        let names = branches
+            .others()
+            .iter()
+            .map(|branch| branch.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["fork-1", "sqs"]);
+
+        assert_eq!(branches.fork(None, &state, None), Ok("fork-2".to_string()));
+
+        assert_eq!(branches.new_conversation(None, &state, None), Ok("chat-1".to_string()));
+        assert!(
+            branches
+                .new_conversation(Some("sqs".to_string()), &state, None)
+                .is_err()
+        );
+        assert!(branches.switch("fork-2", &state, None).is_ok());
+        assert_eq!(branches.current(), "fork-2");
+    }
+}
@@ -0,0 +1,153 @@
+//! Copies of the files `fs_write` changes, taken before their first change in each turn, so that
+//! what the model did can be undone with `/revert`.
+
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use eyre::Result;
+use tracing::warn;
+
+use crate::platform::Context;
+
+/// The files changed while answering one prompt.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// The number of the prompt in the session, from 1.
+    pub turn: usize,
+    pub prompt: String,
+    /// The contents of the files before the turn changed them, `None` for the files it created.
+    pub files: BTreeMap<PathBuf, Option<Vec<u8>>>,
+}
+
+/// The checkpoints of the session, in the order of the turns.
+#[derive(Debug, Default)]
+pub struct Checkpoints {
+    turn: usize,
+    prompt: String,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Checkpoints {
+    /// Starts the turn answering `prompt`, the files changed from now on being part of it.
+    pub fn start_turn(&mut self, prompt: &str) {
+        self.turn += 1;
+        self.prompt = prompt.to_string();
+    }
+
+    /// The turns that changed files.
+    pub fn list(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Copies `path` before it is changed, unless it already was in this turn.
+    pub async fn snapshot(&mut self, ctx: &Context, path: &Path) {
+        if self
+            .checkpoints
+            .last()
+            .is_none_or(|checkpoint| checkpoint.turn != self.turn)
+        {
+            self.checkpoints.push(Checkpoint {
+                turn: self.turn,
+                prompt: self.prompt.clone(),
+                files: BTreeMap::new(),
+            });
+        }
+        let Some(checkpoint) = self.checkpoints.last_mut() else {
+            return;
+        };
+        if checkpoint.files.contains_key(path) {
+            return;
+        }
+        let contents = match ctx.fs().exists(path) {
+            true => match ctx.fs().read(path).await {
+                Ok(contents) => Some(contents),
+                Err(err) => {
+                    // Better to not restore the file than to delete it
+                    warn!(?err, ?path, "Failed to copy the file for the checkpoint");
+                    return;
+                },
+            },
+            false => None,
+        };
+        checkpoint.files.insert(path.to_path_buf(), contents);
+    }
+
+    /// The checkpoints undone by [Self::revert] to `turn`, the last first.
+    pub fn since(&self, turn: usize) -> impl Iterator<Item = &Checkpoint> {
+        self.checkpoints
+            .iter()
+            .rev()
+            .take_while(move |checkpoint| checkpoint.turn >= turn)
+    }
+
+    /// Restores the files to how they were before `turn`, undoing the changes of that turn and
+    /// the next ones. Returns the files restored.
+    pub async fn revert(&mut self, ctx: &Context, turn: usize) -> Result<Vec<PathBuf>> {
+        let mut restored = Vec::new();
+        while self
+            .checkpoints
+            .last()
+            .is_some_and(|checkpoint| checkpoint.turn >= turn)
+        {
+            let Some(checkpoint) = self.checkpoints.pop() else {
+                break;
+            };
+            for (path, contents) in checkpoint.files {
+                match contents {
+                    Some(contents) => ctx.fs().write(&path, contents).await?,
+                    None if ctx.fs().exists(&path) => ctx.fs().remove_file(&path).await?,
+                    None => (),
+                }
+                if !restored.contains(&path) {
+                    restored.push(path);
+                }
+            }
+        }
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_revert() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let fs = ctx.fs();
+        fs.write("/main.rs", "fn main() {}\n").await.unwrap();
+        let mut checkpoints = Checkpoints::default();
+
+        checkpoints.start_turn("add logging");
+        checkpoints.snapshot(&ctx, Path::new("/main.rs")).await;
+        fs.write("/main.rs", "fn main() { log(); }\n").await.unwrap();
+        checkpoints.snapshot(&ctx, Path::new("/log.rs")).await;
+        fs.write("/log.rs", "fn log() {}\n").await.unwrap();
+        // Only the contents before the first change of the turn are kept
+        checkpoints.snapshot(&ctx, Path::new("/main.rs")).await;
+        fs.write("/main.rs", "fn main() { log(); log(); }\n").await.unwrap();
+
+        checkpoints.start_turn("what does log do?");
+        checkpoints.start_turn("log twice");
+        checkpoints.snapshot(&ctx, Path::new("/log.rs")).await;
+        fs.write("/log.rs", "fn log() { log2(); }\n").await.unwrap();
+
+        assert_eq!(checkpoints.list().iter().map(|c| c.turn).collect::<Vec<_>>(), vec![
+            1, 3
+        ]);
+        assert_eq!(checkpoints.since(2).count(), 1);
+
+        assert_eq!(checkpoints.revert(&ctx, 3).await.unwrap(), vec![PathBuf::from(
+            "/log.rs"
+        )]);
+        assert_eq!(fs.read_to_string("/log.rs").await.unwrap(), "fn log() {}\n");
+
+        checkpoints.revert(&ctx, 0).await.unwrap();
+        assert_eq!(fs.read_to_string("/main.rs").await.unwrap(), "fn main() {}\n");
+        assert!(!fs.exists("/log.rs"));
+        assert!(checkpoints.list().is_empty());
+    }
+}
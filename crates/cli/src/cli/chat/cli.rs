@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use clap::{
     Args,
@@ -17,9 +18,11 @@ pub struct Chat {
     /// prompt requests permissions to use a tool, unless --trust-all-tools is also used.
     #[arg(long)]
     pub no_interactive: bool,
-    /// Resumes the previous conversation from this directory.
-    #[arg(short, long)]
-    pub resume: bool,
+    /// Resumes the previous conversation from this directory, or the conversation saved as NAME
+    /// with /save.
+    #[arg(short, long, value_name = "NAME", num_args = 0..=1)]
+    #[allow(clippy::option_option)] // How clap tells a flag without its value from a missing flag
+    pub resume: Option<Option<String>>,
     /// The first question to ask
     pub input: Option<String>,
     /// Context profile to use
@@ -32,6 +35,228 @@ pub struct Chat {
     /// '--trust-tools=fs_read,fs_write', trust no tools: '--trust-tools='
     #[arg(long, value_delimiter = ',', value_name = "TOOL_NAMES")]
     pub trust_tools: Option<Vec<String>>,
+    /// Editor command used by /editor and Ctrl+F for this session, e.g. 'code --wait'.
+    /// Takes precedence over the chat.editMode.editor setting, $VISUAL and $EDITOR.
+    #[arg(long, value_name = "COMMAND")]
+    pub editor: Option<String>,
+    /// Print a single static line instead of animating the spinner while waiting on a
+    /// response, e.g. when recording the screen. Same as chat.spinner.style plain.
+    #[arg(long)]
+    pub no_spinner: bool,
+    /// Show the commands and file changes the model asks for without running or writing them,
+    /// to review its plan. Same as /dry-run on.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Refuse the tools that can change anything: file writes, shell commands other than a few
+    /// that only read, and the tools of MCP servers not marked read-only. Same as /readonly on.
+    #[arg(long)]
+    pub read_only: bool,
+    #[command(subcommand)]
+    pub subcommand: Option<ChatSubcommand>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum ChatSubcommand {
+    /// Export a conversation to Markdown, HTML or JSON
+    Export(ChatExport),
+    /// List, delete and rename the conversations saved with /save
+    Sessions(ChatSessions),
+    /// Search the messages of the conversations saved with /save
+    Search(ChatSearch),
+    /// Import a conversation exported from ChatGPT, Claude or as Markdown, to resume it
+    Import(ChatImport),
+    /// Show a saved conversation again turn by turn, with its tool uses
+    Replay(ChatReplay),
+    /// Show the tools used in chats, from the audit log kept with chat.audit.enabled
+    Audit(ChatAudit),
+    /// Save secrets for the settings to refer to as {{secret:NAME}}, e.g. in
+    /// tools.httpRequest.headers
+    Secrets(ChatSecrets),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ChatSecrets {
+    #[command(subcommand)]
+    pub action: SecretsAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum SecretsAction {
+    /// Save the secret NAME, its value typed without being shown or read from stdin
+    Set { name: String },
+    /// Delete the secret NAME
+    #[command(alias = "rm")]
+    Delete { name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ChatAudit {
+    /// Only show the uses of the tool TOOL, e.g. execute_bash
+    #[arg(long)]
+    pub tool: Option<String>,
+    /// Only show the tools used in the last AGE, e.g. 30d, 12h or 2w
+    #[arg(long, value_name = "AGE", value_parser = parse_age)]
+    pub since: Option<Duration>,
+    /// Show at most the last LIMIT tool uses
+    #[arg(short = 'n', long, default_value_t = 50)]
+    pub limit: usize,
+    /// Print the records as they are in the log, a JSON object per line
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ChatReplay {
+    /// The conversation saved as SESSION with /save
+    pub session: String,
+    /// Show the next turn after SECONDS, instead of on a keypress
+    #[arg(long, value_name = "SECONDS")]
+    pub delay: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ChatImport {
+    /// The export, e.g. conversations.json from ChatGPT or Claude, or a Markdown transcript
+    pub file: String,
+    /// The name to save the conversation as, made from its title when missing
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Import the conversation whose title contains TITLE, instead of the most recent one of
+    /// the export
+    #[arg(long)]
+    pub title: Option<String>,
+    /// Replace the conversation already saved as the name
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ChatSearch {
+    /// The words to search for, each matching the start of a word in a message
+    #[arg(default_value = "")]
+    pub query: String,
+    /// Only search the conversations tagged TAG with /tag
+    #[arg(long)]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ChatSessions {
+    #[command(subcommand)]
+    pub action: Option<SessionsAction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum SessionsAction {
+    /// List the saved and autosaved conversations, the default
+    List,
+    /// Delete a saved conversation
+    #[command(alias = "rm")]
+    Delete { name: String },
+    /// Rename a saved conversation
+    #[command(alias = "mv")]
+    Rename {
+        name: String,
+        new_name: String,
+        /// Replace the conversation already saved as NEW_NAME
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Delete the saved and autosaved conversations that are too old, or the oldest ones once
+    /// they take too much space. Defaults to --older-than chat.sessions.retention
+    Prune {
+        /// Delete the conversations saved before AGE ago, e.g. 30d, 12h or 2w
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<Duration>,
+        /// Delete the oldest saved conversations until the rest take at most SIZE, e.g. 500MB
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// List what would be deleted without deleting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Replicate the saved conversations and the prompt history with chat.sync.url, the copy
+    /// saved last winning
+    Sync,
+}
+
+/// Parses an age like `30d`, in minutes (`m`), hours (`h`), days (`d`) or weeks (`w`).
+pub fn parse_age(arg: &str) -> Result<Duration, String> {
+    let arg = arg.trim();
+    let (value, unit) = arg.split_at(arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len()));
+    let seconds = match unit.trim() {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("'{arg}' is not an age, use e.g. 90m, 12h, 30d or 2w")),
+    };
+    match value.parse::<u64>() {
+        Ok(value) => Ok(Duration::from_secs(value * seconds)),
+        Err(_) => Err(format!("'{arg}' is not an age, use e.g. 90m, 12h, 30d or 2w")),
+    }
+}
+
+/// Parses a size like `500MB`, in bytes without a unit.
+pub fn parse_size(arg: &str) -> Result<u64, String> {
+    let arg = arg.trim();
+    let (value, unit) = arg.split_at(arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len()));
+    let bytes = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("'{arg}' is not a size, use e.g. 800KB, 500MB or 1GB")),
+    };
+    match value.parse::<u64>() {
+        Ok(value) => Ok(value * bytes),
+        Err(_) => Err(format!("'{arg}' is not a size, use e.g. 800KB, 500MB or 1GB")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ChatExport {
+    /// The conversation saved as NAME with /save, instead of the last conversation from the
+    /// current directory
+    #[arg(long, value_name = "NAME")]
+    pub session: Option<String>,
+    /// Where to write the export, printed to STDOUT when missing
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// The format of the export, guessed from the extension of --output when missing and
+    /// Markdown otherwise
+    #[arg(long, value_enum)]
+    pub format: Option<ExportFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    #[value(alias = "md")]
+    Markdown,
+    Html,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Index {
+    /// Chunk and index the files of the workspace, replacing its previous index
+    Build {
+        /// The directory to index, the root of the git repository of the current directory by
+        /// default
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Show what the index of the workspace contains and when it was built
+    Status,
+    /// Show the chunks of the index most relevant to QUERY, as retrieved for a prompt in chat
+    Search {
+        query: String,
+        /// How many chunks to show, chat.index.topK by default
+        #[arg(long, short = 'k')]
+        top_k: Option<usize>,
+    },
+    /// Delete the index of the workspace
+    Clear,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
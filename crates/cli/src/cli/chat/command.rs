@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 use std::io::Write;
+use std::path::Path;
 
 use clap::{
     Parser,
@@ -11,11 +12,21 @@ use crossterm::{
     style,
 };
 use eyre::Result;
+use rustyline::EditMode;
 use serde::{
     Deserialize,
     Serialize,
 };
 
+use super::cli::ExportFormat;
+use super::conversation_state::CompactStrategy;
+use super::export::format_for_path;
+use super::git_diff::{
+    DIFF_RULE,
+    STAGED_DIFF_RULE,
+};
+use super::session::is_session_name;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Command {
     Ask {
@@ -25,6 +36,8 @@ pub enum Command {
         command: String,
     },
     Clear,
+    /// Clear the terminal, keeping the conversation.
+    ClearScreen,
     Help,
     Issue {
         prompt: Option<String>,
@@ -39,10 +52,115 @@ pub enum Command {
     PromptEditor {
         initial_text: Option<String>,
     },
+    /// Edit message `index` (1-based) in the editor and fork the conversation from there. Lists the
+    /// messages when no index is given.
+    EditMessage {
+        index: Option<usize>,
+    },
+    /// Quote an excerpt of the last response in the next prompt.
+    Quote,
+    /// Copy the last response, or its code block `index` (1-based), to the clipboard.
+    Copy {
+        index: Option<usize>,
+    },
+    /// Read the clipboard into the next prompt, or attach it to the context as `name` with
+    /// `as_context`.
+    Paste {
+        as_context: bool,
+        name: Option<String>,
+    },
+    /// Search the prompts and responses of the conversation for lines matching a regex.
+    Find {
+        pattern: String,
+    },
+    /// Show the last response in `$PAGER`.
+    Page,
+    /// Show the full output of the tool use `tool_use_id`, the last one shortened without it, or
+    /// attach it to the context with `attach`.
+    Expand {
+        tool_use_id: Option<String>,
+        attach: bool,
+    },
+    /// Show how many tool results are cached, or forget them with `clear`.
+    Cache {
+        clear: bool,
+    },
+    /// List the commands started in the background.
+    Ps,
+    /// Show the last `lines` lines of output of the background command `id`.
+    Logs {
+        id: usize,
+        lines: Option<usize>,
+    },
+    /// Stop the background command `id` along with what it started.
+    Kill {
+        id: usize,
+    },
+    /// Remove the last `count` exchanges from the conversation, each being a prompt along with the
+    /// responses and tool uses that followed it.
+    Undo {
+        count: usize,
+    },
+    /// List the turns whose file changes can be reverted.
+    Checkpoints,
+    /// Undo the file changes of turn `turn` and the next ones, or of all the turns without it.
+    Revert {
+        turn: Option<usize>,
+    },
+    /// Drop the last response and send the prompt that led to it again. With `fresh`, the model
+    /// is also asked for a different answer.
+    Retry {
+        fresh: bool,
+    },
+    /// Switch between vi and emacs key bindings.
+    SetMode {
+        mode: EditMode,
+    },
+    /// Turn multi-line mode on or off, where Enter inserts a newline and an empty line submits.
+    /// Without `enabled`, the mode is toggled.
+    Multiline {
+        enabled: Option<bool>,
+    },
+    /// Turn dry-run mode on or off, where the tools that need confirmation are only described.
+    /// Without `enabled`, the mode is toggled.
+    DryRun {
+        enabled: Option<bool>,
+    },
+    /// Turn read-only mode on or off, where the tools that can change anything are refused.
+    /// Without `enabled`, the mode is toggled.
+    ReadOnly {
+        enabled: Option<bool>,
+    },
+    Draft {
+        subcommand: DraftSubcommand,
+    },
+    Rules {
+        subcommand: RulesSubcommand,
+    },
+    System {
+        subcommand: SystemSubcommand,
+    },
+    Redaction {
+        subcommand: RedactionSubcommand,
+    },
     Compact {
         prompt: Option<String>,
         show_summary: bool,
         help: bool,
+        strategy: CompactStrategy,
+    },
+    /// Pin message `index` (1-based) so that compaction never drops it, or with `remove`, unpin
+    /// the `index`-th pin. Lists the messages when no index is given.
+    Pin {
+        index: Option<usize>,
+        remove: bool,
+    },
+    /// List the pinned messages.
+    Pins,
+    /// Remove the messages numbered from `range.0` to `range.1` (1-based, inclusive) from the
+    /// conversation sent to the model. Lists the messages when no range is given.
+    Forget {
+        range: Option<(usize, usize)>,
     },
     Tools {
         subcommand: Option<ToolsSubcommand>,
@@ -58,6 +176,42 @@ pub enum Command {
         path: String,
         force: bool,
     },
+    /// Restore a conversation saved with `/save <name>`, or list them without `name`.
+    LoadSession {
+        name: Option<String>,
+    },
+    /// Save the conversation under `name`, or under the name it was last saved or loaded with.
+    SaveSession {
+        name: Option<String>,
+        force: bool,
+    },
+    /// Write a transcript of the conversation to `path`, in the format matching its extension.
+    Export {
+        path: String,
+        format: ExportFormat,
+        force: bool,
+    },
+    /// Share the conversation without its secrets as a gist, or as an HTML file with `html`.
+    Share {
+        html: bool,
+    },
+    /// Copy the conversation into a new branch called `name` and continue on it.
+    Fork {
+        name: Option<String>,
+    },
+    /// Start a new conversation called `name`, keeping this one as a branch to switch back to.
+    New {
+        name: Option<String>,
+    },
+    /// Continue on the branch called `name`, or list the branches without `name`.
+    Branches {
+        name: Option<String>,
+    },
+    /// Add `tags` to the conversation, or remove them with `remove`. Lists the tags without any.
+    Tag {
+        tags: Vec<String>,
+        remove: bool,
+    },
     Mcp,
 }
 
@@ -120,12 +274,18 @@ pub enum HooksSubcommand {
     Add {
         name: String,
 
-        #[arg(long, value_parser = ["per_prompt", "conversation_start"])]
+        #[arg(long, value_parser = ["per_prompt", "conversation_start", "pre_tool_use", "post_tool_use"])]
         trigger: String,
 
         #[arg(long, value_parser = clap::value_parser!(String))]
         command: String,
 
+        #[arg(long = "tool")]
+        tools: Vec<String>,
+
+        #[arg(long)]
+        append_output: bool,
+
         #[arg(long)]
         global: bool,
     },
@@ -169,6 +329,12 @@ pub enum ContextSubcommand {
         force: bool,
         paths: Vec<String>,
     },
+    AddUrl {
+        url: String,
+    },
+    AddCmd {
+        command: String,
+    },
     Remove {
         global: bool,
         paths: Vec<String>,
@@ -176,6 +342,9 @@ pub enum ContextSubcommand {
     Clear {
         global: bool,
     },
+    Save,
+    Reset,
+    Diff,
     Hooks {
         subcommand: Option<HooksSubcommand>,
     },
@@ -183,7 +352,9 @@ pub enum ContextSubcommand {
 }
 
 impl ContextSubcommand {
-    const ADD_USAGE: &str = "/context add [--global] [--force] <path1> [path2...]";
+    const ADD_CMD_USAGE: &str = "/context add-cmd <command>";
+    const ADD_URL_USAGE: &str = "/context add-url <url>";
+    const ADD_USAGE: &str = "/context add [--global] [--force] [--diff [--staged]] <path1> [path2...]";
     const AVAILABLE_COMMANDS: &str = color_print::cstr! {"<cyan!>Available commands</cyan!>
   <em>help</em>                           <black!>Show an explanation for the context command</black!>
 
@@ -192,25 +363,46 @@ impl ContextSubcommand {
                                           <black!>          configurations and last conversation summary </black!>
 
   <em>add [--global] [--force] <<paths...>></em>
-                                 <black!>Add context rules (filenames or glob patterns)</black!>
+                                 <black!>Add context rules (filenames, directories or glob patterns)</black!>
+                                 <black!>PDFs and Word documents are added as text, some pages with design.pdf#page=2-5</black!>
                                  <black!>--global: Add to global rules (available in all profiles)</black!>
                                  <black!>--force: Include even if matched files exceed size limits</black!>
+                                 <black!>--diff: Include the output of git diff, updated every prompt</black!>
+                                 <black!>--staged: Include the staged changes instead</black!>
+
+  <em>add-url <<url>></em>                  <black!>Fetch a page and include its text for this conversation</black!>
+                                 <black!>Pages can also be added with @https://... in prompts</black!>
+
+  <em>add-cmd <<command>></em>              <black!>Run a shell command and include its output for this conversation</black!>
+                                 <black!>Running it again replaces its earlier output</black!>
 
-  <em>rm [--global] <<paths...>></em>       <black!>Remove specified rules from current profile</black!>
+  <em>rm [--global] <<paths...>></em>       <black!>Remove specified rules from current profile (or remove)</black!>
                                  <black!>--global: Remove specified rules globally</black!>
 
   <em>clear [--global]</em>               <black!>Remove all rules from current profile</black!>
                                  <black!>--global: Remove global rules</black!>
 
+  <em>save</em>                           <black!>Save the profile rules to .amazonq/context.json, restored</black!>
+                                 <black!>when chatting in this directory again</black!>
+
+  <em>reset</em>                          <black!>Delete .amazonq/context.json and go back to the profile rules</black!>
+
+  <em>diff</em>                           <black!>Show which context files changed between the last two requests,</black!>
+                                 <black!>and how</black!>
+
   <em>hooks</em>                          <black!>View and manage context hooks</black!>"};
     const CLEAR_USAGE: &str = "/context clear [--global]";
+    const DIFF_USAGE: &str = "/context diff";
     const HOOKS_AVAILABLE_COMMANDS: &str = color_print::cstr! {"<cyan!>Available subcommands</cyan!>
   <em>hooks help</em>                         <black!>Show an explanation for context hooks commands</black!>
 
   <em>hooks add [--global] <<name>></em>        <black!>Add a new command context hook</black!>
                                          <black!>--global: Add to global hooks</black!>
-         <em>--trigger <<trigger>></em>           <black!>When to trigger the hook, valid options: `per_prompt` or `conversation_start`</black!>
+         <em>--trigger <<trigger>></em>           <black!>When to trigger the hook, valid options: `per_prompt`, `conversation_start`,</black!>
+                                         <black!>`pre_tool_use` or `post_tool_use`</black!>
          <em>--command <<command>></em>             <black!>Shell command to execute</black!>
+         <em>--tool <<pattern>></em>                <black!>Only run a tool use hook for these tools, e.g. fs_write or 'execute_bash(git *)'</black!>
+         <em>--append-output</em>                 <black!>Add the output of a tool use hook to the tool result sent to Amazon Q</black!>
 
   <em>hooks rm [--global] <<name>></em>         <black!>Remove an existing context hook</black!>
                                          <black!>--global: Remove from global hooks</black!>
@@ -226,7 +418,9 @@ impl ContextSubcommand {
 
   <em>hooks disable-all [--global]</em>       <black!>Disable all existing context hooks</black!>
                                          <black!>--global: Disable all in global hooks</black!>"};
-    const REMOVE_USAGE: &str = "/context rm [--global] <path1> [path2...]";
+    const REMOVE_USAGE: &str = "/context rm [--global] [--diff [--staged]] <path1> [path2...]";
+    const RESET_USAGE: &str = "/context reset";
+    const SAVE_USAGE: &str = "/context save";
     const SHOW_USAGE: &str = "/context show [--expand]";
 
     fn usage_msg(header: impl AsRef<str>) -> String {
@@ -254,9 +448,13 @@ the output as context to Amazon Q.
 
 <cyan!>Notes</cyan!>
 • You can add specific files or use glob patterns (e.g., "*.py", "src/**/*.js")
+• Directories include all their files, recursively
+• /context add --diff lets you ask to review your changes, /context rm --diff to stop
+• Files ignored by .gitignore, larger than 512 KB or not text are left out
 • Profile rules apply only to the current profile
 • Global rules apply across all profiles
 • Context is preserved between chat sessions
+• Rules saved with /context save replace the profile rules in this directory
 "#,
             Self::AVAILABLE_COMMANDS
         )
@@ -283,17 +481,190 @@ in global or local profiles.
 • Hooks are executed in parallel
 • 'conversation_start' hooks run on the first user prompt and are attached once to the conversation history sent to Amazon Q
 • 'per_prompt' hooks run on each user prompt and are attached to the prompt, but are not stored in conversation history
+• 'pre_tool_use' hooks run before a tool, one after the other, and stop it by exiting with an error
+• 'post_tool_use' hooks run after a tool, e.g. a linter after fs_write
+• Tool use hooks read the tool use as JSON on stdin, with its tool_name, tool_input and tool_response
 "#,
             Self::HOOKS_AVAILABLE_COMMANDS
         )
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DraftSubcommand {
+    Restore,
+    Help,
+}
+
+impl DraftSubcommand {
+    const AVAILABLE_COMMANDS: &str = color_print::cstr! {"<cyan!>Available subcommands</cyan!>
+  <em>help</em>                           <black!>Show an explanation for the draft command</black!>
+  <em>restore</em>                        <black!>Load the most recent unsent draft into the prompt</black!>"};
+    const BASE_COMMAND: &str = color_print::cstr! {"<cyan!>Usage: /draft [SUBCOMMAND]</cyan!>
+
+<cyan!>Description</cyan!>
+  Manage the prompt drafted with /editor or Ctrl(^) + f.
+  The draft is kept until it's submitted, even if the editor fails or the session crashes."};
+
+    fn usage_msg(header: impl AsRef<str>) -> String {
+        format!(
+            "{}\n\n{}\n\n{}",
+            header.as_ref(),
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+
+    pub fn help_text() -> String {
+        color_print::cformat!(
+            r#"
+<magenta,em>Drafts</magenta,em>
+
+{}
+
+{}"#,
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RulesSubcommand {
+    List,
+    Enable { names: Vec<String> },
+    Disable { names: Vec<String> },
+    Help,
+}
+
+impl RulesSubcommand {
+    const AVAILABLE_COMMANDS: &str = color_print::cstr! {"<cyan!>Available subcommands</cyan!>
+  <em>help</em>                           <black!>Show an explanation for the rules command</black!>
+  <em>list</em>                           <black!>List the project rules and whether they are enabled</black!>
+  <em>enable <<name>>...</em>              <black!>Include rules in the context again</black!>
+  <em>disable <<name>>...</em>             <black!>Leave rules out of the context for this conversation</black!>"};
+    const BASE_COMMAND: &str = color_print::cstr! {"<cyan!>Usage: /rules [SUBCOMMAND]</cyan!>
+
+<cyan!>Description</cyan!>
+  Project rules are the markdown files of the .amazonq/rules directory of your project,
+  found in the current directory or its parents up to the root of the git repository.
+  They are added to the context of every conversation, before the other context files.
+  Names are the paths of the files in the rules directory, the .md can be left out."};
+
+    fn usage_msg(header: impl AsRef<str>) -> String {
+        format!(
+            "{}\n\n{}\n\n{}",
+            header.as_ref(),
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+
+    pub fn help_text() -> String {
+        color_print::cformat!(
+            r#"
+<magenta,em>Project rules</magenta,em>
+
+{}
+
+{}"#,
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemSubcommand {
+    Show,
+    Edit,
+    Help,
+}
+
+impl SystemSubcommand {
+    const AVAILABLE_COMMANDS: &str = color_print::cstr! {"<cyan!>Available subcommands</cyan!>
+  <em>help</em>                           <black!>Show an explanation for the system command</black!>
+  <em>show</em>                           <black!>Show the system prompt and where it is from</black!>
+  <em>edit</em>                           <black!>Edit the system prompt of the current profile in $EDITOR</black!>"};
+    const BASE_COMMAND: &str = color_print::cstr! {"<cyan!>Usage: /system [SUBCOMMAND]</cyan!>
+
+<cyan!>Description</cyan!>
+  The system prompt holds instructions for every conversation, like always answering in
+  Portuguese or preferring CDK over Terraform. It is sent before the other context and kept
+  by /clear, unlike the rules of a project.
+  Each profile can have its own, the others use chat.systemPrompt, the instructions or the
+  path of a file with them. Save an empty prompt to remove the one of the profile."};
+
+    fn usage_msg(header: impl AsRef<str>) -> String {
+        format!(
+            "{}\n\n{}\n\n{}",
+            header.as_ref(),
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+
+    pub fn help_text() -> String {
+        color_print::cformat!(
+            r#"
+<magenta,em>System prompt</magenta,em>
+
+{}
+
+{}"#,
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedactionSubcommand {
+    Show,
+    Help,
+}
+
+impl RedactionSubcommand {
+    const AVAILABLE_COMMANDS: &str = color_print::cstr! {"<cyan!>Available subcommands</cyan!>
+  <em>help</em>                           <black!>Show an explanation for the redaction command</black!>
+  <em>show</em>                           <black!>List the secrets masked in this session</black!>"};
+    const BASE_COMMAND: &str = color_print::cstr! {"<cyan!>Usage: /redaction [SUBCOMMAND]</cyan!>
+
+<cyan!>Description</cyan!>
+  Secrets such as AWS keys, bearer tokens and private key blocks are masked in your prompts
+  and context files before they are sent. Add your own regexes with chat.redaction.patterns,
+  or turn masking off with q settings chat.redaction.enabled false."};
+
+    fn usage_msg(header: impl AsRef<str>) -> String {
+        format!(
+            "{}\n\n{}\n\n{}",
+            header.as_ref(),
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+
+    pub fn help_text() -> String {
+        color_print::cformat!(
+            r#"
+<magenta,em>Redaction</magenta,em>
+
+{}
+
+{}"#,
+            Self::BASE_COMMAND,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ToolsSubcommand {
     Schema,
     Trust { tool_names: HashSet<String> },
     Untrust { tool_names: HashSet<String> },
+    Enable { tool_names: HashSet<String> },
+    Disable { tool_names: HashSet<String> },
     TrustAll,
     Reset,
     ResetSingle { tool_name: String },
@@ -306,8 +677,10 @@ impl ToolsSubcommand {
   <em>schema</em>                         <black!>Show the input schema for all available tools</black!>
   <em>trust <<tools...>></em>               <black!>Trust a specific tool or tools for the session</black!>
   <em>untrust <<tools...>></em>             <black!>Revert a tool or tools to per-request confirmation</black!>
+  <em>enable <<tools...>></em>              <black!>Make disabled tools available to the model again</black!>
+  <em>disable <<tools...>></em>             <black!>Hide tools from the model for the session</black!>
   <em>trustall</em>                       <black!>Trust all tools (equivalent to deprecated /acceptall)</black!>
-  <em>reset</em>                          <black!>Reset all tools to default permission levels</black!>
+  <em>reset</em>                          <black!>Reset all tools to default permission levels, saved decisions included</black!>
   <em>reset <<tool name>></em>              <black!>Reset a single tool to default permission level</black!>"};
     const BASE_COMMAND: &str = color_print::cstr! {"<cyan!>Usage: /tools [SUBCOMMAND]</cyan!>
 
@@ -333,6 +706,10 @@ impl ToolsSubcommand {
 By default, Amazon Q will ask for your permission to use certain tools. You can control which tools you
 trust so that no confirmation is required. These settings will last only for this session.
 
+When asked, answer 'a' to always allow a tool or 'd' to always deny it: the decision is saved to the
+tools.trusted or tools.denied setting, and applies to the next sessions as well. Denied tools never run,
+the model being told that you denied them. Resetting a tool forgets the decision saved for it.
+
 {}
 
 {}"#,
@@ -446,11 +823,13 @@ impl Command {
 
             return Ok(match parts[0].to_lowercase().as_str() {
                 "clear" => Self::Clear,
+                "clear-screen" => Self::ClearScreen,
                 "help" => Self::Help,
                 "compact" => {
                     let mut prompt = None;
                     let show_summary = true;
                     let mut help = false;
+                    let mut strategy = CompactStrategy::default();
 
                     // Check if "help" is the first subcommand
                     if parts.len() > 1 && parts[1].to_lowercase() == "help" {
@@ -458,7 +837,25 @@ impl Command {
                     } else {
                         let mut remaining_parts = Vec::new();
 
-                        remaining_parts.extend_from_slice(&parts[1..]);
+                        let mut args = parts[1..].iter();
+                        while let Some(arg) = args.next() {
+                            match *arg {
+                                "--keep-last" => {
+                                    strategy.keep_last = match args.next().map(|n| n.parse::<usize>()) {
+                                        Some(Ok(n)) if n > 0 => n,
+                                        _ => {
+                                            return Err(
+                                                "--keep-last takes a number of messages of at least 1, e.g. /compact --keep-last 3"
+                                                    .to_string(),
+                                            );
+                                        },
+                                    };
+                                },
+                                "--preserve-code" => strategy.preserve_code = true,
+                                "--aggressive" => strategy.aggressive = true,
+                                _ => remaining_parts.push(*arg),
+                            }
+                        }
 
                         // If we have remaining parts after parsing flags, join them as the prompt
                         if !remaining_parts.is_empty() {
@@ -470,8 +867,36 @@ impl Command {
                         prompt,
                         show_summary,
                         help,
+                        strategy,
                     }
                 },
+                "pin" => {
+                    let remove = parts.contains(&"-d") || parts.contains(&"--remove");
+                    let index = match parts[1..].iter().find(|arg| !matches!(**arg, "-d" | "--remove")) {
+                        Some(index) => match index.parse::<usize>() {
+                            Ok(index) if index > 0 => Some(index),
+                            _ => return Err(format!("Invalid message number: {}. Usage: /pin <n>", index)),
+                        },
+                        None if remove => {
+                            return Err("Missing the pin to remove, see /pins. Usage: /pin --remove <n>".to_string());
+                        },
+                        None => None,
+                    };
+                    Self::Pin { index, remove }
+                },
+                "pins" => Self::Pins,
+                "forget" => Self::Forget {
+                    range: match parts.get(1) {
+                        Some(range) => {
+                            let (first, last) = range.split_once('-').unwrap_or((range, range));
+                            match (first.parse::<usize>(), last.parse::<usize>()) {
+                                (Ok(first), Ok(last)) if first > 0 && first <= last => Some((first, last)),
+                                _ => return Err(format!("Invalid messages: {}. Usage: /forget <n>[-m]", range)),
+                            }
+                        },
+                        None => None,
+                    },
+                },
                 "acceptall" => {
                     let _ = queue!(
                         output,
@@ -485,14 +910,212 @@ impl Command {
                     }
                 },
                 "editor" => {
-                    if parts.len() > 1 {
-                        Self::PromptEditor {
-                            initial_text: Some(parts[1..].join(" ")),
-                        }
-                    } else {
-                        Self::PromptEditor { initial_text: None }
+                    // Keep the seed text verbatim (including its internal whitespace) rather than
+                    // re-joining the whitespace-split parts.
+                    let initial_text = command[parts[0].len()..].trim();
+                    Self::PromptEditor {
+                        initial_text: (!initial_text.is_empty()).then(|| initial_text.to_string()),
                     }
                 },
+                "edit" => match parts.get(1) {
+                    Some(index) => match index.parse::<usize>() {
+                        Ok(index) if index > 0 => Self::EditMessage { index: Some(index) },
+                        _ => return Err(format!("Invalid message number: {}. Usage: /edit <n>", index)),
+                    },
+                    None => Self::EditMessage { index: None },
+                },
+                "quote" => Self::Quote,
+                "copy" => match parts.get(1) {
+                    Some(index) => match index.parse::<usize>() {
+                        Ok(index) if index > 0 => Self::Copy { index: Some(index) },
+                        _ => return Err(format!("Invalid code block number: {}. Usage: /copy [n]", index)),
+                    },
+                    None => Self::Copy { index: None },
+                },
+                "paste" => match &parts[1..] {
+                    [] => Self::Paste {
+                        as_context: false,
+                        name: None,
+                    },
+                    ["--as-context"] => Self::Paste {
+                        as_context: true,
+                        name: None,
+                    },
+                    ["--as-context", name] => Self::Paste {
+                        as_context: true,
+                        name: Some((*name).to_string()),
+                    },
+                    _ => return Err("Usage: /paste [--as-context [name]]".to_string()),
+                },
+                "page" => Self::Page,
+                "expand" => match &parts[1..] {
+                    [] => Self::Expand {
+                        tool_use_id: None,
+                        attach: false,
+                    },
+                    ["--attach"] => Self::Expand {
+                        tool_use_id: None,
+                        attach: true,
+                    },
+                    [id] => Self::Expand {
+                        tool_use_id: Some((*id).to_string()),
+                        attach: false,
+                    },
+                    [id, "--attach"] | ["--attach", id] => Self::Expand {
+                        tool_use_id: Some((*id).to_string()),
+                        attach: true,
+                    },
+                    _ => return Err("Usage: /expand [tool-use-id] [--attach]".to_string()),
+                },
+                "cache" => Self::Cache {
+                    clear: match parts.get(1).copied() {
+                        None => false,
+                        Some("clear") => true,
+                        Some(_) => return Err("Usage: /cache [clear]".to_string()),
+                    },
+                },
+                "ps" => Self::Ps,
+                "logs" => {
+                    let usage = "Usage: /logs <id> [lines], see /ps for the ids";
+                    let id = match parts.get(1).map(|id| id.parse::<usize>()) {
+                        Some(Ok(id)) => id,
+                        _ => return Err(usage.to_string()),
+                    };
+                    let lines = match parts.get(2).map(|lines| lines.parse::<usize>()) {
+                        None => None,
+                        Some(Ok(lines)) if lines > 0 => Some(lines),
+                        Some(_) => return Err(format!("Invalid number of lines: {}. {usage}", parts[2])),
+                    };
+                    Self::Logs { id, lines }
+                },
+                "kill" => match parts.get(1).map(|id| id.parse::<usize>()) {
+                    Some(Ok(id)) => Self::Kill { id },
+                    _ => return Err("Usage: /kill <id>, see /ps for the ids".to_string()),
+                },
+                "find" => {
+                    // Keep the pattern verbatim, its whitespace may be significant
+                    let pattern = command[parts[0].len()..].trim();
+                    if pattern.is_empty() {
+                        return Err("Missing a pattern. Usage: /find <regex>".to_string());
+                    }
+                    Self::Find {
+                        pattern: pattern.to_string(),
+                    }
+                },
+                "undo" => Self::Undo {
+                    count: match parts.get(1) {
+                        Some(count) => match count.parse::<usize>() {
+                            Ok(count) if count > 0 => count,
+                            _ => return Err(format!("Invalid number of exchanges: {}. Usage: /undo [n]", count)),
+                        },
+                        None => 1,
+                    },
+                },
+                "checkpoint" | "checkpoints" => match parts.get(1).copied() {
+                    None | Some("list") => Self::Checkpoints,
+                    Some(_) => return Err("Usage: /checkpoint [list]".to_string()),
+                },
+                "revert" => Self::Revert {
+                    turn: match parts.get(1).copied() {
+                        Some("all") => None,
+                        Some(turn) => match turn.parse::<usize>() {
+                            Ok(turn) if turn > 0 => Some(turn),
+                            _ => return Err(format!("Invalid turn: {}. Usage: /revert <turn>|all", turn)),
+                        },
+                        None => return Err("Usage: /revert <turn>|all, see /checkpoint list for the turns".to_string()),
+                    },
+                },
+                "retry" => Self::Retry {
+                    fresh: match parts.get(1).copied() {
+                        None => false,
+                        Some("--fresh") => true,
+                        Some(_) => return Err("Usage: /retry [--fresh]".to_string()),
+                    },
+                },
+                "set-mode" => Self::SetMode {
+                    mode: match parts.get(1).map(|mode| mode.to_lowercase()).as_deref() {
+                        Some("vi" | "vim") => EditMode::Vi,
+                        Some("emacs") => EditMode::Emacs,
+                        _ => return Err("Usage: /set-mode vi|emacs".to_string()),
+                    },
+                },
+                "multiline" => Self::Multiline {
+                    enabled: match parts.get(1).map(|enabled| enabled.to_lowercase()).as_deref() {
+                        None => None,
+                        Some("on") => Some(true),
+                        Some("off") => Some(false),
+                        Some(_) => return Err("Usage: /multiline [on|off]".to_string()),
+                    },
+                },
+                "dry-run" => Self::DryRun {
+                    enabled: match parts.get(1).map(|enabled| enabled.to_lowercase()).as_deref() {
+                        None => None,
+                        Some("on") => Some(true),
+                        Some("off") => Some(false),
+                        Some(_) => return Err("Usage: /dry-run [on|off]".to_string()),
+                    },
+                },
+                "readonly" => Self::ReadOnly {
+                    enabled: match parts.get(1).map(|enabled| enabled.to_lowercase()).as_deref() {
+                        None => None,
+                        Some("on") => Some(true),
+                        Some("off") => Some(false),
+                        Some(_) => return Err("Usage: /readonly [on|off]".to_string()),
+                    },
+                },
+                "draft" => Self::Draft {
+                    subcommand: match parts.get(1).map(|s| s.to_lowercase()).as_deref() {
+                        Some("restore") => DraftSubcommand::Restore,
+                        Some("help") | None => DraftSubcommand::Help,
+                        Some(other) => {
+                            return Err(DraftSubcommand::usage_msg(format!("Unknown subcommand '{}'\n", other)));
+                        },
+                    },
+                },
+                "redaction" => Self::Redaction {
+                    subcommand: match parts.get(1).map(|s| s.to_lowercase()).as_deref() {
+                        Some("show") | None => RedactionSubcommand::Show,
+                        Some("help") => RedactionSubcommand::Help,
+                        Some(other) => {
+                            return Err(RedactionSubcommand::usage_msg(format!(
+                                "Unknown subcommand '{}'\n",
+                                other
+                            )));
+                        },
+                    },
+                },
+                "system" => Self::System {
+                    subcommand: match parts.get(1).map(|s| s.to_lowercase()).as_deref() {
+                        Some("show") | None => SystemSubcommand::Show,
+                        Some("edit") => SystemSubcommand::Edit,
+                        Some("help") => SystemSubcommand::Help,
+                        Some(other) => {
+                            return Err(SystemSubcommand::usage_msg(format!("Unknown subcommand '{}'\n", other)));
+                        },
+                    },
+                },
+                "rules" => Self::Rules {
+                    subcommand: match parts.get(1).map(|s| s.to_lowercase()).as_deref() {
+                        Some("list") | None => RulesSubcommand::List,
+                        Some(subcommand @ ("enable" | "disable")) => {
+                            let names = parts[2..].iter().map(|name| (*name).to_string()).collect::<Vec<_>>();
+                            if names.is_empty() {
+                                return Err(RulesSubcommand::usage_msg(format!(
+                                    "Expected at least one rule name for {}\n",
+                                    subcommand
+                                )));
+                            }
+                            match subcommand {
+                                "enable" => RulesSubcommand::Enable { names },
+                                _ => RulesSubcommand::Disable { names },
+                            }
+                        },
+                        Some("help") => RulesSubcommand::Help,
+                        Some(other) => {
+                            return Err(RulesSubcommand::usage_msg(format!("Unknown subcommand '{}'\n", other)));
+                        },
+                    },
+                },
                 "issue" => {
                     if parts.len() > 1 {
                         Self::Issue {
@@ -618,15 +1241,23 @@ impl Command {
                                 None => return Err("Failed to parse quoted arguments".to_string()),
                             };
 
+                            let (mut diff, mut staged) = (false, false);
                             for arg in &args {
                                 if arg == "--global" {
                                     global = true;
                                 } else if arg == "--force" || arg == "-f" {
                                     force = true;
+                                } else if arg == "--diff" {
+                                    diff = true;
+                                } else if arg == "--staged" {
+                                    staged = true;
                                 } else {
                                     paths.push(arg.to_string());
                                 }
                             }
+                            if diff || staged {
+                                paths.push(if staged { STAGED_DIFF_RULE } else { DIFF_RULE }.to_string());
+                            }
 
                             if paths.is_empty() {
                                 usage_err!(ContextSubcommand::ADD_USAGE);
@@ -636,7 +1267,35 @@ impl Command {
                                 subcommand: ContextSubcommand::Add { global, force, paths },
                             }
                         },
-                        "rm" => {
+                        "add-url" => {
+                            let [url] = &parts[2..] else {
+                                usage_err!(ContextSubcommand::ADD_URL_USAGE);
+                            };
+                            Self::Context {
+                                subcommand: ContextSubcommand::AddUrl {
+                                    url: (*url).to_string(),
+                                },
+                            }
+                        },
+                        "add-cmd" => {
+                            // The rest of the input as typed, spacing and quotes within it included
+                            let rest = command.split_once("add-cmd").map_or("", |(_, rest)| rest.trim());
+                            let rest = match (rest.chars().next(), rest.chars().last()) {
+                                (Some(first @ ('"' | '\'')), Some(last)) if rest.len() > 1 && first == last => {
+                                    &rest[1..rest.len() - 1]
+                                },
+                                _ => rest,
+                            };
+                            if rest.trim().is_empty() {
+                                usage_err!(ContextSubcommand::ADD_CMD_USAGE);
+                            }
+                            Self::Context {
+                                subcommand: ContextSubcommand::AddCmd {
+                                    command: rest.trim().to_string(),
+                                },
+                            }
+                        },
+                        "rm" | "remove" => {
                             // Parse rm command with paths and --global flag
                             let mut global = false;
                             let mut paths = Vec::new();
@@ -645,13 +1304,21 @@ impl Command {
                                 None => return Err("Failed to parse quoted arguments".to_string()),
                             };
 
+                            let (mut diff, mut staged) = (false, false);
                             for arg in &args {
                                 if arg == "--global" {
                                     global = true;
+                                } else if arg == "--diff" {
+                                    diff = true;
+                                } else if arg == "--staged" {
+                                    staged = true;
                                 } else {
                                     paths.push(arg.to_string());
                                 }
                             }
+                            if diff || staged {
+                                paths.push(if staged { STAGED_DIFF_RULE } else { DIFF_RULE }.to_string());
+                            }
 
                             if paths.is_empty() {
                                 usage_err!(ContextSubcommand::REMOVE_USAGE);
@@ -677,6 +1344,30 @@ impl Command {
                                 subcommand: ContextSubcommand::Clear { global },
                             }
                         },
+                        "save" => {
+                            if parts.len() > 2 {
+                                usage_err!(ContextSubcommand::SAVE_USAGE);
+                            }
+                            Self::Context {
+                                subcommand: ContextSubcommand::Save,
+                            }
+                        },
+                        "reset" => {
+                            if parts.len() > 2 {
+                                usage_err!(ContextSubcommand::RESET_USAGE);
+                            }
+                            Self::Context {
+                                subcommand: ContextSubcommand::Reset,
+                            }
+                        },
+                        "diff" => {
+                            if parts.len() > 2 {
+                                usage_err!(ContextSubcommand::DIFF_USAGE);
+                            }
+                            Self::Context {
+                                subcommand: ContextSubcommand::Diff,
+                            }
+                        },
                         "help" => Self::Context {
                             subcommand: ContextSubcommand::Help,
                         },
@@ -762,6 +1453,23 @@ impl Command {
                                 subcommand: Some(ToolsSubcommand::Untrust { tool_names }),
                             }
                         },
+                        subcommand @ ("enable" | "disable") => {
+                            let tool_names = parts[2..]
+                                .iter()
+                                .map(|part| (*part).to_string())
+                                .collect::<HashSet<_>>();
+                            if tool_names.is_empty() {
+                                return Err(ToolsSubcommand::usage_msg(format!(
+                                    "Please use /tools {subcommand} <tool1> <tool2>."
+                                )));
+                            }
+                            Self::Tools {
+                                subcommand: Some(match subcommand {
+                                    "enable" => ToolsSubcommand::Enable { tool_names },
+                                    _ => ToolsSubcommand::Disable { tool_names },
+                                }),
+                            }
+                        },
                         "trustall" => Self::Tools {
                             subcommand: Some(ToolsSubcommand::TrustAll),
                         },
@@ -819,24 +1527,76 @@ impl Command {
                     }
                 },
                 "usage" => Self::Usage,
-                "load" => {
-                    let Some(path) = parts.get(1) else {
-                        return Err("path is required".to_string());
-                    };
-                    Self::Load {
+                "load" => match parts.get(1) {
+                    None => Self::LoadSession { name: None },
+                    Some(name) if is_session_name(name) => Self::LoadSession {
+                        name: Some((*name).to_string()),
+                    },
+                    Some(path) => Self::Load {
                         path: (*path).to_string(),
-                    }
+                    },
                 },
                 "save" => {
                     let force = parts.contains(&"-f") || parts.contains(&"--force");
-                    let Some(path) = parts.get(1) else {
-                        return Err("path is required".to_string());
+                    match parts[1..].iter().find(|arg| !matches!(**arg, "-f" | "--force")) {
+                        None => Self::SaveSession { name: None, force },
+                        Some(name) if is_session_name(name) => Self::SaveSession {
+                            name: Some((*name).to_string()),
+                            force,
+                        },
+                        Some(path) => {
+                            let mut path = (*path).to_string();
+                            if !path.ends_with(".json") {
+                                path.push_str(".json");
+                            }
+                            Self::Save { path, force }
+                        },
+                    }
+                },
+                "export" => {
+                    let force = parts.contains(&"-f") || parts.contains(&"--force");
+                    let Some(path) = parts[1..].iter().find(|arg| !matches!(**arg, "-f" | "--force")) else {
+                        return Err("Missing a path. Usage: /export <path> [--force]".to_string());
+                    };
+                    let Some(format) = format_for_path(Path::new(path)) else {
+                        return Err(format!(
+                            "Unknown format for {path}. Use a .md, .html or .json extension, e.g. /export chat.md"
+                        ));
                     };
-                    let mut path = (*path).to_string();
-                    if !path.ends_with(".json") {
-                        path.push_str(".json");
+                    Self::Export {
+                        path: (*path).to_string(),
+                        format,
+                        force,
+                    }
+                },
+                "share" => match parts.get(1) {
+                    None => Self::Share { html: false },
+                    Some(&"--html") => Self::Share { html: true },
+                    Some(arg) => return Err(format!("Unknown option {arg}. Usage: /share [--html]")),
+                },
+                "fork" => Self::Fork {
+                    name: parts.get(1).map(|name| (*name).to_string()),
+                },
+                "new" => Self::New {
+                    name: parts.get(1).map(|name| (*name).to_string()),
+                },
+                "branches" | "switch" => Self::Branches {
+                    name: parts.get(1).map(|name| (*name).to_string()),
+                },
+                "tag" => {
+                    let remove = parts.contains(&"-d") || parts.contains(&"--remove");
+                    let tags = parts[1..]
+                        .iter()
+                        .filter(|arg| !matches!(**arg, "-d" | "--remove"))
+                        .map(|tag| tag.to_lowercase())
+                        .collect::<Vec<_>>();
+                    if let Some(tag) = tags.iter().find(|tag| !is_session_name(tag)) {
+                        return Err(format!("'{tag}' is not a valid tag, use only letters, digits, - and _"));
+                    }
+                    if remove && tags.is_empty() {
+                        return Err("Missing the tags to remove. Usage: /tag --remove <tag>".to_string());
                     }
-                    Self::Save { path, force }
+                    Self::Tag { tags, remove }
                 },
                 "mcp" => Self::Mcp,
                 unknown_command => {
@@ -939,19 +1699,216 @@ mod tests {
         }
         macro_rules! compact {
             ($prompt:expr, $show_summary:expr) => {
+                compact!($prompt, $show_summary, CompactStrategy::default())
+            };
+            ($prompt:expr, $show_summary:expr, $strategy:expr) => {
                 Command::Compact {
                     prompt: $prompt,
                     show_summary: $show_summary,
                     help: false,
+                    strategy: $strategy,
                 }
             };
         }
         let tests = &[
+            ("/clear", Command::Clear),
+            ("/clear-screen", Command::ClearScreen),
+            ("/editor", Command::PromptEditor { initial_text: None }),
+            ("/editor fix  this function", Command::PromptEditor {
+                initial_text: Some("fix  this function".to_string()),
+            }),
+            ("/edit", Command::EditMessage { index: None }),
+            ("/edit 2", Command::EditMessage { index: Some(2) }),
+            ("/quote", Command::Quote),
+            ("/copy", Command::Copy { index: None }),
+            ("/copy 2", Command::Copy { index: Some(2) }),
+            ("/paste", Command::Paste {
+                as_context: false,
+                name: None,
+            }),
+            ("/paste --as-context build-log", Command::Paste {
+                as_context: true,
+                name: Some("build-log".to_string()),
+            }),
+            ("/page", Command::Page),
+            ("/find todo", Command::Find {
+                pattern: "todo".to_string(),
+            }),
+            ("/find fn  main\\(", Command::Find {
+                pattern: "fn  main\\(".to_string(),
+            }),
+            ("/undo", Command::Undo { count: 1 }),
+            ("/undo 3", Command::Undo { count: 3 }),
+            ("/retry", Command::Retry { fresh: false }),
+            ("/save", Command::SaveSession {
+                name: None,
+                force: false,
+            }),
+            ("/save -f debugging", Command::SaveSession {
+                name: Some("debugging".to_string()),
+                force: true,
+            }),
+            ("/save ./debugging", Command::Save {
+                path: "./debugging.json".to_string(),
+                force: false,
+            }),
+            ("/load", Command::LoadSession { name: None }),
+            ("/load debugging", Command::LoadSession {
+                name: Some("debugging".to_string()),
+            }),
+            ("/load debugging.json", Command::Load {
+                path: "debugging.json".to_string(),
+            }),
+            ("/export chat.md", Command::Export {
+                path: "chat.md".to_string(),
+                format: ExportFormat::Markdown,
+                force: false,
+            }),
+            ("/export -f out/chat.HTML", Command::Export {
+                path: "out/chat.HTML".to_string(),
+                format: ExportFormat::Html,
+                force: true,
+            }),
+            ("/export chat.json --force", Command::Export {
+                path: "chat.json".to_string(),
+                format: ExportFormat::Json,
+                force: true,
+            }),
+            ("/share", Command::Share { html: false }),
+            ("/share --html", Command::Share { html: true }),
+            ("/fork", Command::Fork { name: None }),
+            ("/fork sqs", Command::Fork {
+                name: Some("sqs".to_string()),
+            }),
+            ("/branches", Command::Branches { name: None }),
+            ("/tag", Command::Tag {
+                tags: vec![],
+                remove: false,
+            }),
+            ("/tag Infra dynamodb", Command::Tag {
+                tags: vec!["infra".to_string(), "dynamodb".to_string()],
+                remove: false,
+            }),
+            ("/tag -d infra", Command::Tag {
+                tags: vec!["infra".to_string()],
+                remove: true,
+            }),
+            ("/branches main", Command::Branches {
+                name: Some("main".to_string()),
+            }),
+            ("/switch main", Command::Branches {
+                name: Some("main".to_string()),
+            }),
+            ("/new", Command::New { name: None }),
+            ("/new design", Command::New {
+                name: Some("design".to_string()),
+            }),
+            ("/expand", Command::Expand {
+                tool_use_id: None,
+                attach: false,
+            }),
+            ("/expand tooluse_abc --attach", Command::Expand {
+                tool_use_id: Some("tooluse_abc".to_string()),
+                attach: true,
+            }),
+            ("/cache", Command::Cache { clear: false }),
+            ("/cache clear", Command::Cache { clear: true }),
+            ("/ps", Command::Ps),
+            ("/logs 2", Command::Logs { id: 2, lines: None }),
+            ("/logs 2 200", Command::Logs {
+                id: 2,
+                lines: Some(200),
+            }),
+            ("/kill 2", Command::Kill { id: 2 }),
+            ("/checkpoint", Command::Checkpoints),
+            ("/checkpoint list", Command::Checkpoints),
+            ("/revert 3", Command::Revert { turn: Some(3) }),
+            ("/revert all", Command::Revert { turn: None }),
+            ("/retry --fresh", Command::Retry { fresh: true }),
+            ("/set-mode vi", Command::SetMode { mode: EditMode::Vi }),
+            ("/set-mode Emacs", Command::SetMode { mode: EditMode::Emacs }),
+            ("/multiline", Command::Multiline { enabled: None }),
+            ("/multiline on", Command::Multiline { enabled: Some(true) }),
+            ("/multiline OFF", Command::Multiline { enabled: Some(false) }),
+            ("/dry-run", Command::DryRun { enabled: None }),
+            ("/dry-run on", Command::DryRun { enabled: Some(true) }),
+            ("/dry-run off", Command::DryRun { enabled: Some(false) }),
+            ("/readonly", Command::ReadOnly { enabled: None }),
+            ("/readonly ON", Command::ReadOnly { enabled: Some(true) }),
+            ("/readonly off", Command::ReadOnly { enabled: Some(false) }),
+            ("/draft", Command::Draft {
+                subcommand: DraftSubcommand::Help,
+            }),
+            ("/draft restore", Command::Draft {
+                subcommand: DraftSubcommand::Restore,
+            }),
+            ("/rules", Command::Rules {
+                subcommand: RulesSubcommand::List,
+            }),
+            ("/system", Command::System {
+                subcommand: SystemSubcommand::Show,
+            }),
+            ("/system edit", Command::System {
+                subcommand: SystemSubcommand::Edit,
+            }),
+            ("/redaction", Command::Redaction {
+                subcommand: RedactionSubcommand::Show,
+            }),
+            ("/redaction show", Command::Redaction {
+                subcommand: RedactionSubcommand::Show,
+            }),
+            ("/redaction help", Command::Redaction {
+                subcommand: RedactionSubcommand::Help,
+            }),
+            ("/rules help", Command::Rules {
+                subcommand: RulesSubcommand::Help,
+            }),
+            ("/rules disable testing style.md", Command::Rules {
+                subcommand: RulesSubcommand::Disable {
+                    names: vec!["testing".to_string(), "style.md".to_string()],
+                },
+            }),
+            ("/rules enable testing", Command::Rules {
+                subcommand: RulesSubcommand::Enable {
+                    names: vec!["testing".to_string()],
+                },
+            }),
             ("/compact", compact!(None, true)),
             (
                 "/compact custom prompt",
                 compact!(Some("custom prompt".to_string()), true),
             ),
+            (
+                "/compact --keep-last 3 --preserve-code focus on the parser",
+                compact!(Some("focus on the parser".to_string()), true, CompactStrategy {
+                    keep_last: 3,
+                    preserve_code: true,
+                    aggressive: false,
+                }),
+            ),
+            (
+                "/compact --aggressive",
+                compact!(None, true, CompactStrategy {
+                    aggressive: true,
+                    ..Default::default()
+                }),
+            ),
+            ("/pin", Command::Pin {
+                index: None,
+                remove: false,
+            }),
+            ("/pin 2", Command::Pin {
+                index: Some(2),
+                remove: false,
+            }),
+            ("/pin -d 1", Command::Pin {
+                index: Some(1),
+                remove: true,
+            }),
+            ("/pins", Command::Pins),
+            ("/forget", Command::Forget { range: None }),
+            ("/forget 3", Command::Forget { range: Some((3, 3)) }),
+            ("/forget 2-4", Command::Forget { range: Some((2, 4)) }),
             ("/profile list", profile!(ProfileSubcommand::List)),
             (
                 "/profile create new_profile",
@@ -1013,11 +1970,72 @@ mod tests {
                     paths: vec!["p1".into(), "p2".into()]
                 }),
             ),
+            (
+                "/context add --diff",
+                context!(ContextSubcommand::Add {
+                    global: false,
+                    force: false,
+                    paths: vec![DIFF_RULE.into()]
+                }),
+            ),
+            (
+                "/context add --global --diff --staged",
+                context!(ContextSubcommand::Add {
+                    global: true,
+                    force: false,
+                    paths: vec![STAGED_DIFF_RULE.into()]
+                }),
+            ),
+            (
+                "/context rm --diff",
+                context!(ContextSubcommand::Remove {
+                    global: false,
+                    paths: vec![DIFF_RULE.into()]
+                }),
+            ),
+            (
+                "/context remove p1",
+                context!(ContextSubcommand::Remove {
+                    global: false,
+                    paths: vec!["p1".into()]
+                }),
+            ),
             ("/context clear", context!(ContextSubcommand::Clear { global: false })),
+            (
+                "/context add-url https://docs.rs/tokio",
+                context!(ContextSubcommand::AddUrl {
+                    url: "https://docs.rs/tokio".to_string()
+                }),
+            ),
+            (
+                "/context add-cmd \"kubectl get pods  -A\"",
+                context!(ContextSubcommand::AddCmd {
+                    command: "kubectl get pods  -A".to_string()
+                }),
+            ),
+            (
+                "/context add-cmd git log -1 --format='%h %s'",
+                context!(ContextSubcommand::AddCmd {
+                    command: "git log -1 --format='%h %s'".to_string()
+                }),
+            ),
+            ("/context save", context!(ContextSubcommand::Save)),
+            ("/context reset", context!(ContextSubcommand::Reset)),
+            ("/context diff", context!(ContextSubcommand::Diff)),
             (
                 "/context clear --global",
                 context!(ContextSubcommand::Clear { global: true }),
             ),
+            ("/tools disable execute_bash", Command::Tools {
+                subcommand: Some(ToolsSubcommand::Disable {
+                    tool_names: HashSet::from(["execute_bash".to_string()]),
+                }),
+            }),
+            ("/tools enable execute_bash", Command::Tools {
+                subcommand: Some(ToolsSubcommand::Enable {
+                    tool_names: HashSet::from(["execute_bash".to_string()]),
+                }),
+            }),
             ("/issue", Command::Issue { prompt: None }),
             ("/issue there was an error in the chat", Command::Issue {
                 prompt: Some("there was an error in the chat".to_string()),
@@ -1036,7 +2054,22 @@ mod tests {
                         name: "test".to_string(),
                         global: true,
                         trigger: "per_prompt".to_string(),
-                        command: "echo 1".to_string()
+                        command: "echo 1".to_string(),
+                        tools: vec![],
+                        append_output: false,
+                    })
+                }),
+            ),
+            (
+                "/context hooks add lint --trigger post_tool_use --command 'cargo clippy' --tool fs_write --tool 'execute_bash(cargo *)' --append-output",
+                context!(ContextSubcommand::Hooks {
+                    subcommand: Some(HooksSubcommand::Add {
+                        name: "lint".to_string(),
+                        global: false,
+                        trigger: "post_tool_use".to_string(),
+                        command: "cargo clippy".to_string(),
+                        tools: vec!["fs_write".to_string(), "execute_bash(cargo *)".to_string()],
+                        append_output: true,
                     })
                 }),
             ),
@@ -0,0 +1,99 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use eyre::{
+    Result,
+    bail,
+};
+use time::OffsetDateTime;
+use time::macros::format_description;
+
+use super::consts::COMMAND_CONTEXT_MAX_CHARS;
+use super::util::truncate_safe;
+
+/// How long a command added with `/context add-cmd` can run.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The name the output of `command` is attached as, replacing its earlier output.
+pub fn attachment_name(command: &str) -> String {
+    format!("$ {command}")
+}
+
+/// Runs `command` with bash in `dir`, returning its output to attach to the context: when it ran
+/// and how it exited, its stdout, then its stderr. At most [COMMAND_CONTEXT_MAX_CHARS] of it.
+pub async fn run_for_context(command: &str, dir: &Path) -> Result<String> {
+    let run_at = OffsetDateTime::now_utc()
+        .format(format_description!("[year]-[month]-[day] [hour]:[minute]:[second] UTC"))
+        .unwrap_or_default();
+    let output = tokio::process::Command::new("bash")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(COMMAND_TIMEOUT, output).await {
+        Ok(output) => output?,
+        Err(_) => bail!("{command} didn't finish within {} seconds", COMMAND_TIMEOUT.as_secs()),
+    };
+
+    let status = match output.status.code() {
+        Some(code) => format!("exit status {code}"),
+        None => "killed by a signal".to_string(),
+    };
+    let mut text = format!("{}\n(run at {run_at}, {status})\n", attachment_name(command));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stdout.trim().is_empty() && stderr.trim().is_empty() {
+        text.push_str("(no output)\n");
+    }
+    if !stdout.trim().is_empty() {
+        text.push_str(stdout.trim_end());
+        text.push('\n');
+    }
+    if !stderr.trim().is_empty() {
+        text.push_str("[stderr]\n");
+        text.push_str(stderr.trim_end());
+        text.push('\n');
+    }
+    Ok(match text.len() > COMMAND_CONTEXT_MAX_CHARS {
+        true => format!(
+            "{}\n[... truncated to fit the context]",
+            truncate_safe(&text, COMMAND_CONTEXT_MAX_CHARS)
+        ),
+        false => text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_for_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = run_for_context("echo ready; echo 'not found' >&2; exit 3", dir.path())
+            .await
+            .unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("$ echo ready; echo 'not found' >&2; exit 3"));
+        let status = lines.next().unwrap();
+        assert!(status.starts_with("(run at ") && status.ends_with(" UTC, exit status 3)"));
+        assert_eq!(lines.collect::<Vec<_>>(), vec!["ready", "[stderr]", "not found"]);
+
+        let text = run_for_context("pwd", dir.path()).await.unwrap();
+        assert!(
+            text.trim_end()
+                .ends_with(&*dir.path().file_name().unwrap().to_string_lossy())
+        );
+        assert!(
+            run_for_context("true", dir.path())
+                .await
+                .unwrap()
+                .ends_with("(no output)\n")
+        );
+    }
+}
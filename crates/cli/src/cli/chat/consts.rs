@@ -18,6 +18,39 @@ pub const CONTEXT_WINDOW_SIZE: usize = 200_000;
 
 pub const CONTEXT_FILES_MAX_SIZE: usize = 150_000;
 
+/// In bytes, files matched by context rules that are larger are left out.
+pub const CONTEXT_FILE_MAX_BYTES: u64 = 512 * 1024;
+
+/// In bytes, PDFs and Word documents matched by context rules that are larger are left out.
+/// Their text is held to [CONTEXT_FILE_MAX_BYTES].
+pub const DOCUMENT_FILE_MAX_BYTES: u64 = 32 * 1024 * 1024;
+
+/// In characters, the most a `git diff` added with `/context add --diff` takes.
+pub const DIFF_CONTEXT_MAX_CHARS: usize = 40_000;
+
+/// In characters, the most the project overview of `chat.autoContext` takes.
+pub const PROJECT_CONTEXT_MAX_CHARS: usize = 6_000;
+
+/// In characters, the most the text of a page added with `/context add-url` takes.
+pub const URL_CONTEXT_MAX_CHARS: usize = 30_000;
+
+/// In characters, the most the output of a command added with `/context add-cmd` takes.
+pub const COMMAND_CONTEXT_MAX_CHARS: usize = 30_000;
+
+/// In tokens, prompts estimated to be larger than this along with their context need to be
+/// confirmed before sending. Above what context files alone can take.
+pub const LARGE_PROMPT_THRESHOLD: usize = 60_000;
+
+/// In percent of [CONTEXT_WINDOW_SIZE], requests estimated above this first compact the older
+/// messages of the history.
+pub const AUTO_COMPACT_THRESHOLD: usize = 85;
+
+/// How many of the most recent messages are left out of automatic compaction.
+pub const AUTO_COMPACT_KEEP_LAST: usize = 2;
+
+/// How many prompts in a conversation gets its title, see [super::session::generate_title].
+pub const TITLE_AFTER_PROMPTS: usize = 2;
+
 pub const MAX_CHARS: usize = TokenCounter::token_to_chars(CONTEXT_WINDOW_SIZE); // Character-based warning threshold
 
 pub const DUMMY_TOOL_NAME: &str = "dummy";
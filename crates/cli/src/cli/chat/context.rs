@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{
+    BTreeSet,
+    HashMap,
+};
 use std::io::Write;
 use std::path::{
     Path,
@@ -10,7 +13,10 @@ use eyre::{
     Result,
     eyre,
 };
-use glob::glob;
+use glob::{
+    MatchOptions,
+    Pattern,
+};
 use regex::Regex;
 use serde::{
     Deserialize,
@@ -18,12 +24,38 @@ use serde::{
 };
 use tracing::debug;
 
-use super::consts::CONTEXT_FILES_MAX_SIZE;
+use super::consts::{
+    CONTEXT_FILE_MAX_BYTES,
+    CONTEXT_FILES_MAX_SIZE,
+    DIFF_CONTEXT_MAX_CHARS,
+    DOCUMENT_FILE_MAX_BYTES,
+};
+use super::context_budget::{
+    RankSignals,
+    fit_to_budget,
+};
+use super::context_cache;
+use super::document::{
+    DocumentKind,
+    PageRange,
+    extract_text,
+    split_page_range,
+};
+use super::git_diff::{
+    diff_rule,
+    git_diff,
+    truncate_diff,
+};
 use super::hooks::{
     Hook,
     HookExecutor,
+    HookTrigger,
+};
+use super::system_prompt::{
+    PROFILE_SYSTEM_PROMPT_FILENAME,
+    SystemPrompt,
+    SystemPromptSource,
 };
-use super::util::drop_matched_context_files;
 use crate::platform::Context;
 use crate::util::directories;
 
@@ -59,6 +91,27 @@ pub struct ContextManager {
     /// Context configuration for the current profile.
     pub profile_config: ContextConfig,
 
+    /// The `.amazonq/context.json` of the workspace that [Self::profile_config] was loaded from
+    /// instead of the profile, see [Self::save_workspace].
+    #[serde(default)]
+    pub workspace_config_path: Option<PathBuf>,
+
+    /// The pages added with `/context add-url` or `@https://...` in prompts, and the text
+    /// attached with `/paste --as-context`, as (name, text) pairs. Kept for the conversation only.
+    #[serde(default)]
+    pub attachments: Vec<(String, String)>,
+
+    /// The project rules turned off with `/rules disable`, see [Self::rules].
+    #[serde(default)]
+    pub disabled_rules: BTreeSet<String>,
+
+    /// The contents of each context file as of the last request sent and of the one before it,
+    /// see [Self::take_updated_files] and [Self::diff_sent_files].
+    #[serde(skip)]
+    sent_files: HashMap<String, String>,
+    #[serde(skip)]
+    previous_sent_files: HashMap<String, String>,
+
     #[serde(skip)]
     pub hook_executor: HookExecutor,
 }
@@ -88,7 +141,10 @@ impl ContextManager {
 
         let global_config = load_global_config(&ctx).await?;
         let current_profile = "default".to_string();
-        let profile_config = load_profile_config(&ctx, &current_profile).await?;
+        let (profile_config, workspace_config_path) = match load_workspace_config(&ctx).await? {
+            Some((config, path)) => (config, Some(path)),
+            None => (load_profile_config(&ctx, &current_profile).await?, None),
+        };
 
         Ok(Self {
             ctx,
@@ -96,6 +152,11 @@ impl ContextManager {
             global_config,
             current_profile,
             profile_config,
+            workspace_config_path,
+            attachments: Vec::new(),
+            disabled_rules: BTreeSet::new(),
+            sent_files: HashMap::new(),
+            previous_sent_files: HashMap::new(),
             hook_executor: HookExecutor::new(),
         })
     }
@@ -104,7 +165,7 @@ impl ContextManager {
     ///
     /// # Arguments
     /// * `global` - If true, save the global configuration; otherwise, save the current profile
-    ///   configuration
+    ///   configuration, to the workspace when it was loaded from there
     ///
     /// # Returns
     /// A Result indicating success or an error
@@ -116,7 +177,10 @@ impl ContextManager {
 
             self.ctx.fs().write(&global_path, contents).await?;
         } else {
-            let profile_path = profile_context_path(&self.ctx, &self.current_profile)?;
+            let profile_path = match &self.workspace_config_path {
+                Some(path) => path.clone(),
+                None => profile_context_path(&self.ctx, &self.current_profile)?,
+            };
             if let Some(parent) = profile_path.parent() {
                 self.ctx.fs().create_dir_all(parent).await?;
             }
@@ -132,10 +196,46 @@ impl ContextManager {
     /// Reloads the global and profile config from disk.
     pub async fn reload_config(&mut self) -> Result<()> {
         self.global_config = load_global_config(&self.ctx).await?;
-        self.profile_config = load_profile_config(&self.ctx, &self.current_profile).await?;
+        self.profile_config = match &self.workspace_config_path {
+            Some(path) if self.ctx.fs().exists(path) => load_config_file(&self.ctx, path).await?,
+            _ => {
+                self.workspace_config_path = None;
+                load_profile_config(&self.ctx, &self.current_profile).await?
+            },
+        };
         Ok(())
     }
 
+    /// Saves the rules of the current profile to `.amazonq/context.json` in the current
+    /// directory, so that chat sessions started there restore them. Changes to the profile rules
+    /// are saved there from then on.
+    ///
+    /// # Returns
+    /// A Result containing the path of the file or an error
+    pub async fn save_workspace(&mut self) -> Result<PathBuf> {
+        let path = workspace_context_path(&self.ctx)?;
+        self.workspace_config_path = Some(path.clone());
+        self.save_config(false).await?;
+        Ok(path)
+    }
+
+    /// Deletes the `.amazonq/context.json` of the current directory and goes back to the rules
+    /// of the current profile.
+    ///
+    /// # Returns
+    /// A Result containing whether there was a file to delete, or an error
+    pub async fn reset_workspace(&mut self) -> Result<bool> {
+        let path = workspace_context_path(&self.ctx)?;
+        let existed = self.ctx.fs().exists(&path);
+        if existed {
+            self.ctx.fs().remove_file(&path).await?;
+        }
+        self.workspace_config_path = None;
+        self.hook_executor.profile_cache.clear();
+        self.profile_config = load_profile_config(&self.ctx, &self.current_profile).await?;
+        Ok(existed)
+    }
+
     /// Add paths to the context configuration.
     ///
     /// # Arguments
@@ -145,12 +245,13 @@ impl ContextManager {
     /// * `force` - If true, skip validation that the path exists
     ///
     /// # Returns
-    /// A Result indicating success or an error
-    pub async fn add_paths(&mut self, paths: Vec<String>, global: bool, force: bool) -> Result<()> {
+    /// A Result containing the matched files that are left out of the context, or an error
+    pub async fn add_paths(&mut self, paths: Vec<String>, global: bool, force: bool) -> Result<Vec<SkippedFile>> {
         let mut all_paths = self.global_config.paths.clone();
         all_paths.append(&mut self.profile_config.paths.clone());
 
         // Validate paths exist before adding them
+        let mut skipped = Vec::new();
         if !force {
             let mut context_files = Vec::new();
 
@@ -159,7 +260,7 @@ impl ContextManager {
                 // We're using a temporary context_files vector just for validation
                 // Pass is_validation=true to ensure we error if glob patterns don't match any files
                 match process_path(&self.ctx, path, &mut context_files, true).await {
-                    Ok(_) => {}, // Path is valid
+                    Ok(path_skipped) => skipped.extend(path_skipped), // Path is valid
                     Err(e) => return Err(eyre!("Invalid path '{}': {}. Use --force to add anyway.", path, e)),
                 }
             }
@@ -180,7 +281,7 @@ impl ContextManager {
         // Save the updated configuration
         self.save_config(global).await?;
 
-        Ok(())
+        Ok(skipped)
     }
 
     /// Remove paths from the context configuration.
@@ -193,12 +294,14 @@ impl ContextManager {
     /// # Returns
     /// A Result indicating success or an error
     pub async fn remove_paths(&mut self, paths: Vec<String>, global: bool) -> Result<()> {
+        // Attachments aren't part of either config
+        let attachments_len = self.attachments.len();
+        self.attachments.retain(|(name, _)| !paths.contains(name));
+        let mut removed_any = self.attachments.len() < attachments_len;
+
         // Get reference to the appropriate config
         let config = self.get_config_mut(global);
 
-        // Track if any paths were removed
-        let mut removed_any = false;
-
         // Remove each path if it exists
         for path in paths {
             let original_len = config.paths.len();
@@ -299,6 +402,7 @@ impl ContextManager {
             self.global_config.paths.clear();
         } else {
             self.profile_config.paths.clear();
+            self.attachments.clear();
         }
 
         // Save the updated configuration
@@ -422,6 +526,7 @@ impl ContextManager {
             // Update the current profile
             self.current_profile = name.to_string();
             self.profile_config = profile_config;
+            self.workspace_config_path = None;
 
             return Ok(());
         }
@@ -435,6 +540,7 @@ impl ContextManager {
         // Update the current profile
         self.current_profile = name.to_string();
         self.profile_config = load_profile_config(&self.ctx, name).await?;
+        self.workspace_config_path = None;
 
         Ok(())
     }
@@ -457,6 +563,7 @@ impl ContextManager {
             .await?;
         self.collect_context_files(&self.profile_config.paths, &mut context_files)
             .await?;
+        context_files.extend(self.attachments.iter().cloned());
 
         context_files.sort_by(|a, b| a.0.cmp(&b.0));
         context_files.dedup_by(|a, b| a.0 == b.0);
@@ -464,12 +571,140 @@ impl ContextManager {
         Ok(context_files)
     }
 
+    /// Attach `text` to the context as `name`, e.g. the URL of the page it is the text of,
+    /// replacing what was attached under that name before.
+    pub fn attach(&mut self, name: String, text: String) {
+        match self.attachments.iter_mut().find(|(attached, _)| *attached == name) {
+            Some((_, attached_text)) => *attached_text = text,
+            None => self.attachments.push((name, text)),
+        }
+    }
+
     pub async fn get_context_files_by_path(&self, path: &str) -> Result<Vec<(String, String)>> {
         let mut context_files = Vec::new();
         process_path(&self.ctx, path, &mut context_files, true).await?;
+        self.retain_non_rules(&mut context_files);
         Ok(context_files)
     }
 
+    /// Get the markdown files of the `.amazonq/rules` directory of the workspace, see
+    /// [rules_dir], along with whether each is enabled. Rules are always part of the context
+    /// unless disabled, whatever the context rules.
+    pub async fn rules(&self) -> Result<Vec<ProjectRule>> {
+        let Some(dir) = rules_dir(&self.ctx) else {
+            return Ok(Vec::new());
+        };
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        for path in walk_dir(&self.ctx, &dir, None).await? {
+            if path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("md"))
+            {
+                add_file_to_context(&self.ctx, &path, None, &mut files, &mut skipped).await?;
+            }
+        }
+        Ok(files
+            .into_iter()
+            .map(|(path, content)| {
+                let name = Path::new(&path)
+                    .strip_prefix(&dir)
+                    .map_or_else(|_| path.clone(), |name| name.to_string_lossy().to_string());
+                ProjectRule {
+                    enabled: !self.disabled_rules.contains(&name),
+                    name,
+                    path,
+                    content,
+                }
+            })
+            .collect())
+    }
+
+    /// Enable or disable the project rules called `names`, with or without their `.md`
+    /// extension.
+    ///
+    /// # Returns
+    /// A Result containing the names of the rules, or an error when one of them doesn't exist
+    pub async fn set_rules_enabled(&mut self, names: &[String], enabled: bool) -> Result<Vec<String>> {
+        let rules = self.rules().await?;
+        let mut resolved = Vec::new();
+        for name in names {
+            let rule = rules
+                .iter()
+                .find(|rule| rule.name == *name || rule.name.strip_suffix(".md") == Some(name))
+                .ok_or_else(|| eyre!("Rule '{}' not found, see /rules", name))?;
+            resolved.push(rule.name.clone());
+        }
+        for name in &resolved {
+            match enabled {
+                true => self.disabled_rules.remove(name),
+                false => self.disabled_rules.insert(name.clone()),
+            };
+        }
+        Ok(resolved)
+    }
+
+    /// Leaves the project rules out of `context_files`, those being added on their own.
+    fn retain_non_rules(&self, context_files: &mut Vec<(String, String)>) {
+        if let Some(dir) = rules_dir(&self.ctx) {
+            context_files.retain(|(path, _)| !Path::new(path).starts_with(&dir));
+        }
+    }
+
+    /// Get the files matched by the global and profile rules that are left out of the context,
+    /// because they are too large or not text.
+    pub async fn get_skipped_context_files(&self) -> Result<Vec<SkippedFile>> {
+        let mut skipped = Vec::new();
+        let mut context_files = Vec::new();
+        for path in self.global_config.paths.iter().chain(&self.profile_config.paths) {
+            skipped.extend(process_path(&self.ctx, path, &mut context_files, false).await?);
+        }
+        skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        skipped.dedup();
+        Ok(skipped)
+    }
+
+    /// Get the context files of `files`, those read for a request, that changed since this was
+    /// last called, e.g. edited between two requests. Files that weren't in the context the last
+    /// time aren't included.
+    pub fn take_updated_files(&mut self, files: Vec<(String, String)>) -> Vec<String> {
+        let files = files.into_iter().collect::<HashMap<_, _>>();
+        let mut updated = files
+            .iter()
+            .filter(|(filename, content)| self.sent_files.get(*filename).is_some_and(|sent| sent != *content))
+            .map(|(filename, _)| filename.clone())
+            .collect::<Vec<_>>();
+        updated.sort();
+        self.previous_sent_files = std::mem::replace(&mut self.sent_files, files);
+        updated
+    }
+
+    /// How the context files changed between the last two requests sent, see `/context diff`.
+    pub fn diff_sent_files(&self) -> Vec<ContextFileChange> {
+        let paths = self
+            .previous_sent_files
+            .keys()
+            .chain(self.sent_files.keys())
+            .collect::<BTreeSet<_>>();
+        paths
+            .into_iter()
+            .filter_map(
+                |path| match (self.previous_sent_files.get(path), self.sent_files.get(path)) {
+                    (None, Some(_)) => Some(ContextFileChange::Added(path.clone())),
+                    (Some(_), None) => Some(ContextFileChange::Removed(path.clone())),
+                    (Some(previous), Some(sent)) if previous != sent => {
+                        let diff = similar::TextDiff::from_lines(previous, sent)
+                            .unified_diff()
+                            .header(&format!("a/{path}"), &format!("b/{path}"))
+                            .to_string();
+                        Some(ContextFileChange::Modified(path.clone(), diff))
+                    },
+                    _ => None,
+                },
+            )
+            .collect()
+    }
+
     /// Get all context files from the global configuration.
     pub async fn get_global_context_files(&self) -> Result<Vec<(String, String)>> {
         let mut context_files = Vec::new();
@@ -490,17 +725,67 @@ impl ContextManager {
         Ok(context_files)
     }
 
-    /// Collects context files and optionally drops files if the total size exceeds the limit.
-    /// Returns (files_to_use, dropped_files)
-    pub async fn collect_context_files_with_limit(&self) -> Result<(Vec<(String, String)>, Vec<(String, String)>)> {
-        let mut files = self.get_context_files().await?;
+    /// The file with the system prompt of the current profile, see `/system edit`.
+    pub fn system_prompt_path(&self) -> Result<PathBuf> {
+        Ok(profile_dir_path(&self.ctx, &self.current_profile)?.join(PROFILE_SYSTEM_PROMPT_FILENAME))
+    }
 
-        let dropped_files = drop_matched_context_files(&mut files, self.max_context_files_size).unwrap_or_default();
+    /// The system prompt of the current profile, or `default` from `chat.systemPrompt` for a
+    /// profile without one.
+    pub async fn system_prompt(&self, default: Option<&str>) -> Option<SystemPrompt> {
+        if let Ok(path) = self.system_prompt_path() {
+            if let Ok(text) = self.ctx.fs().read_to_string(&path).await {
+                if !text.trim().is_empty() {
+                    return Some(SystemPrompt {
+                        text: text.trim().to_string(),
+                        source: SystemPromptSource::Profile(path),
+                    });
+                }
+            }
+        }
+        default.map(|text| SystemPrompt {
+            text: text.to_string(),
+            source: SystemPromptSource::Setting,
+        })
+    }
 
-        // remove dropped files from files
-        files.retain(|file| !dropped_files.iter().any(|dropped| dropped.0 == file.0));
+    /// The most tokens of context files sent, see [Self::collect_context_files_with_limit].
+    pub fn max_context_files_size(&self) -> usize {
+        self.max_context_files_size
+    }
 
-        Ok((files, dropped_files))
+    /// Collects context files and drops the lowest ranked if the total size exceeds the limit,
+    /// ranked by whether they were added by name, how relevant they are to `prompt` and how
+    /// recently they changed.
+    /// Returns (files_to_use, dropped_files), the dropped files from the highest ranked
+    pub async fn collect_context_files_with_limit(
+        &self,
+        prompt: Option<&str>,
+    ) -> Result<(Vec<(String, String)>, Vec<(String, String)>)> {
+        let files = self.get_context_files().await?;
+
+        let mut signals = RankSignals {
+            prompt,
+            ..Default::default()
+        };
+        for path in self.global_config.paths.iter().chain(&self.profile_config.paths) {
+            if diff_rule(path).is_some() || path.contains(['*', '?', '[']) {
+                continue;
+            }
+            if let Ok(resolved) = split_page_range(path).and_then(|(path, _)| resolve_path(&self.ctx, path)) {
+                signals.explicit.push(resolved);
+            }
+        }
+        for (filename, _) in &files {
+            if let Ok(modified) = tokio::fs::metadata(filename)
+                .await
+                .and_then(|metadata| metadata.modified())
+            {
+                signals.modified.insert(filename.clone(), modified);
+            }
+        }
+
+        Ok(fit_to_budget(files, &signals, self.max_context_files_size))
     }
 
     async fn collect_context_files(&self, paths: &[String], context_files: &mut Vec<(String, String)>) -> Result<()> {
@@ -508,6 +793,7 @@ impl ContextManager {
             // Use is_validation=false to handle non-matching globs gracefully
             process_path(&self.ctx, path, context_files, false).await?;
         }
+        self.retain_non_rules(context_files);
         Ok(())
     }
 
@@ -584,7 +870,8 @@ impl ContextManager {
         self.save_config(global).await
     }
 
-    /// Run all the currently enabled hooks from both the global and profile contexts.
+    /// Run all the currently enabled hooks from both the global and profile contexts, but for the
+    /// tool use ones, see [Self::tool_hooks].
     /// Skipped hooks (disabled) will not appear in the output.
     /// # Arguments
     /// * `updates` - output stream to write hook run status to if Some, else do nothing if None
@@ -600,15 +887,41 @@ impl ContextManager {
         ];
 
         for (hook_list, is_global) in configs {
-            hooks.extend(hook_list.iter_mut().map(|(name, h)| {
-                h.name = name.to_string();
-                h.is_global = is_global;
-                &*h
-            }));
+            hooks.extend(
+                hook_list
+                    .iter_mut()
+                    .filter(|(_, h)| !h.trigger.is_tool_use())
+                    .map(|(name, h)| {
+                        h.name = name.to_string();
+                        h.is_global = is_global;
+                        &*h
+                    }),
+            );
         }
 
         self.hook_executor.run_hooks(hooks, updates).await
     }
+
+    /// The enabled hooks of `trigger`, a tool use one, the global ones first and each in the order
+    /// of their names.
+    pub fn tool_hooks(&self, trigger: HookTrigger) -> Vec<Hook> {
+        let configs = [(&self.global_config.hooks, true), (&self.profile_config.hooks, false)];
+        let mut hooks = Vec::new();
+        for (hook_list, is_global) in configs {
+            let mut names = hook_list
+                .iter()
+                .filter(|(_, h)| h.trigger == trigger && !h.disabled)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>();
+            names.sort();
+            hooks.extend(names.into_iter().map(|name| Hook {
+                name: name.clone(),
+                is_global,
+                ..hook_list[name].clone()
+            }));
+        }
+        hooks
+    }
 }
 
 fn profile_dir_path(ctx: &Context, profile_name: &str) -> Result<PathBuf> {
@@ -622,6 +935,62 @@ pub fn profile_context_path(ctx: &Context, profile_name: &str) -> Result<PathBuf
         .join("context.json"))
 }
 
+/// A markdown file of the `.amazonq/rules` directory of the workspace, see
+/// [ContextManager::rules].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectRule {
+    /// The path of the file in the rules directory, e.g. `rust/errors.md`.
+    pub name: String,
+    pub path: String,
+    pub content: String,
+    pub enabled: bool,
+}
+
+/// The `.amazonq/rules` directory of the workspace: the first one in the current directory or
+/// its parents, up to the root of the git repository or the home directory.
+pub fn rules_dir(ctx: &Context) -> Option<PathBuf> {
+    let cwd = ctx.env().current_dir().ok()?;
+    let home = ctx.env().home();
+    for dir in cwd.ancestors() {
+        if home.as_deref() == Some(dir) && dir != cwd {
+            break;
+        }
+        // Chrooted, like the paths of context files
+        let dir = ctx.fs().chroot_path(dir);
+        let rules = dir.join(".amazonq").join("rules");
+        if rules.is_dir() {
+            return Some(rules);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// The path of the context configuration saved for the workspace, in the current directory.
+pub fn workspace_context_path(ctx: &Context) -> Result<PathBuf> {
+    Ok(ctx.env().current_dir()?.join(".amazonq").join("context.json"))
+}
+
+/// Load the context configuration saved for the workspace, along with its path.
+///
+/// Returns None if the workspace has none.
+async fn load_workspace_config(ctx: &Context) -> Result<Option<(ContextConfig, PathBuf)>> {
+    let workspace_path = workspace_context_path(ctx)?;
+    debug!(?workspace_path, "loading workspace config");
+    if ctx.fs().exists(&workspace_path) {
+        Ok(Some((load_config_file(ctx, &workspace_path).await?, workspace_path)))
+    } else {
+        Ok(None)
+    }
+}
+
+async fn load_config_file(ctx: &Context, path: &Path) -> Result<ContextConfig> {
+    let contents = ctx.fs().read_to_string(path).await?;
+    serde_json::from_str(&contents).map_err(|e| eyre!("Failed to parse {}: {}", path.display(), e))
+}
+
 /// Load the global context configuration.
 ///
 /// If the global configuration file doesn't exist, returns a default configuration.
@@ -663,14 +1032,55 @@ async fn load_profile_config(ctx: &Context, profile_name: &str) -> Result<Contex
     }
 }
 
+/// A file matched by a context rule but left out of the context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Larger than [CONTEXT_FILE_MAX_BYTES], along with its size in bytes.
+    TooLarge(u64),
+    /// Not UTF-8 text, e.g. an image or a binary.
+    NotText,
+    /// A PDF or Word document no text could be extracted from, e.g. a scanned one.
+    NoText,
+}
+
+impl std::fmt::Display for SkippedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reason {
+            SkipReason::TooLarge(size) => write!(f, "{} ({} KB, over the limit)", self.path, size.div_ceil(1024)),
+            SkipReason::NotText => write!(f, "{} (not text)", self.path),
+            SkipReason::NoText => write!(f, "{} (no text found in the document)", self.path),
+        }
+    }
+}
+
+/// How a context file changed from a request to the next, see
+/// [ContextManager::diff_sent_files].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextFileChange {
+    Added(String),
+    Removed(String),
+    /// Along with the unified diff of its contents.
+    Modified(String, String),
+}
+
 /// Process a path, handling glob patterns and file types.
 ///
 /// This method:
-/// 1. Expands the path (handling ~ for home directory)
+/// 1. Expands the path (handling ~ for home directory), unless it is a diff rule like
+///    [super::git_diff::DIFF_RULE], which adds the output of `git diff`
 /// 2. If the path contains glob patterns, expands them
 /// 3. For each resulting path, adds the file to the context collection
-/// 4. Handles directories by including all files in the directory (non-recursive)
-/// 5. With force=true, includes paths that don't exist yet
+/// 4. Handles directories by including all the files under the directory, recursively
+/// 5. Leaves out the files ignored by `.gitignore` files when expanding globs and directories, as
+///    well as the files that are too large or not text
+///
+/// Files are added in the order of their paths.
 ///
 /// # Arguments
 /// * `path` - The path to process
@@ -678,13 +1088,9 @@ async fn load_profile_config(ctx: &Context, profile_name: &str) -> Result<Contex
 /// * `is_validation` - If true, error when glob patterns don't match; if false, silently skip
 ///
 /// # Returns
-/// A Result indicating success or an error
-async fn process_path(
-    ctx: &Context,
-    path: &str,
-    context_files: &mut Vec<(String, String)>,
-    is_validation: bool,
-) -> Result<()> {
+/// A Result containing the files that were left out, or an error
+/// The absolute path of the file, directory or glob pattern of the context rule `path`.
+fn resolve_path(ctx: &Context, path: &str) -> Result<String> {
     // Expand ~ to home directory
     let expanded_path = if path.starts_with('~') {
         if let Some(home_dir) = ctx.env().home() {
@@ -708,50 +1114,80 @@ async fn process_path(
     };
 
     // Required in chroot testing scenarios so that we can use `Path::exists`.
-    let full_path = ctx.fs().chroot_path_str(full_path);
+    Ok(ctx.fs().chroot_path_str(full_path))
+}
+
+async fn process_path(
+    ctx: &Context,
+    path: &str,
+    context_files: &mut Vec<(String, String)>,
+    is_validation: bool,
+) -> Result<Vec<SkippedFile>> {
+    // The diff of the repository, run again every time the context is collected
+    if let Some(staged) = diff_rule(path) {
+        let diff = match git_diff(&ctx.env().current_dir()?, staged).await {
+            Ok(diff) => diff,
+            Err(e) if is_validation => return Err(e),
+            Err(e) => {
+                debug!(?e, "skipping the diff context rule");
+                return Ok(Vec::new());
+            },
+        };
+        if !diff.is_empty() {
+            context_files.push((path.replace(':', " "), truncate_diff(&diff, DIFF_CONTEXT_MAX_CHARS)));
+        }
+        return Ok(Vec::new());
+    }
+
+    // The pages of a PDF, from a rule like design.pdf#page=2-5
+    let (path, pages) = split_page_range(path)?;
+
+    let full_path = resolve_path(ctx, path)?;
 
+    let mut skipped = Vec::new();
     // Check if the path contains glob patterns
     if full_path.contains('*') || full_path.contains('?') || full_path.contains('[') {
-        // Expand glob pattern
-        match glob(&full_path) {
-            Ok(entries) => {
-                let mut found_any = false;
-
-                for entry in entries {
-                    match entry {
-                        Ok(path) => {
-                            if path.is_file() {
-                                add_file_to_context(ctx, &path, context_files).await?;
-                                found_any = true;
-                            }
-                        },
-                        Err(e) => return Err(eyre!("Glob error: {}", e)),
-                    }
+        let pattern = Pattern::new(&full_path).map_err(|e| eyre!("Invalid glob pattern '{}': {}", full_path, e))?;
+
+        // Walk the directory before the first component with a pattern, only as deep as the
+        // pattern goes unless it has **
+        let mut base = PathBuf::new();
+        let mut depth: usize = 0;
+        for component in Path::new(&full_path).components() {
+            let component = component.as_os_str().to_string_lossy();
+            if depth > 0 || component.contains(['*', '?', '[']) {
+                depth += 1;
+            } else {
+                base.push(component.as_ref());
+            }
+        }
+        let max_depth = (!full_path.contains("**")).then(|| depth.saturating_sub(1));
+
+        let mut found_any = false;
+        if base.is_dir() {
+            for path in walk_dir(ctx, &base, max_depth).await? {
+                if pattern.matches_path_with(&path, GLOB_OPTIONS) {
+                    add_file_to_context(ctx, &path, None, context_files, &mut skipped).await?;
+                    found_any = true;
                 }
+            }
+        }
 
-                if !found_any && is_validation {
-                    // When validating paths (e.g., for /context add), error if no files match
-                    return Err(eyre!("No files found matching glob pattern '{}'", full_path));
-                }
-                // When just showing expanded files (e.g., for /context show --expand),
-                // silently skip non-matching patterns (don't add anything to context_files)
-            },
-            Err(e) => return Err(eyre!("Invalid glob pattern '{}': {}", full_path, e)),
+        if !found_any && is_validation {
+            // When validating paths (e.g., for /context add), error if no files match
+            return Err(eyre!("No files found matching glob pattern '{}'", full_path));
         }
+        // When just showing expanded files (e.g., for /context show --expand),
+        // silently skip non-matching patterns (don't add anything to context_files)
     } else {
         // Regular path
         let path = Path::new(&full_path);
         if path.exists() {
             if path.is_file() {
-                add_file_to_context(ctx, path, context_files).await?;
+                add_file_to_context(ctx, path, pages, context_files, &mut skipped).await?;
             } else if path.is_dir() {
-                // For directories, add all files in the directory (non-recursive)
-                let mut read_dir = ctx.fs().read_dir(path).await?;
-                while let Some(entry) = read_dir.next_entry().await? {
-                    let path = entry.path();
-                    if path.is_file() {
-                        add_file_to_context(ctx, &path, context_files).await?;
-                    }
+                for path in walk_dir(ctx, path, None).await? {
+                    add_file_to_context(ctx, &path, None, context_files, &mut skipped).await?;
                 }
             }
         } else if is_validation {
@@ -760,25 +1196,213 @@ async fn process_path(
         }
     }
 
-    Ok(())
+    Ok(skipped)
+}
+
+/// How context rules and `.gitignore` patterns match paths: `*` doesn't match `/`, but does
+/// match names starting with `.`.
+pub const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// The files under `dir` that aren't ignored by `.gitignore` files, sorted by path. Only goes
+/// `max_depth` directories down when given.
+///
+/// The `.gitignore` files that apply are those under `dir`, and those of its parents up to the
+/// root of the git repository it is in.
+pub async fn walk_dir(ctx: &Context, dir: &Path, max_depth: Option<usize>) -> Result<Vec<PathBuf>> {
+    let mut gitignore = Gitignore::default();
+    if let Some(root) = dir.ancestors().find(|ancestor| ancestor.join(".git").exists()) {
+        let mut parents = dir
+            .ancestors()
+            .skip(1)
+            .take_while(|parent| parent.starts_with(root))
+            .collect::<Vec<_>>();
+        parents.reverse();
+        for parent in parents {
+            gitignore.add_file(ctx, parent).await;
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![(dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        gitignore.add_file(ctx, &dir).await;
+        let mut read_dir = ctx.fs().read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            // Symlinks to directories aren't followed, so that loops can't happen
+            let is_dir = entry.file_type().await?.is_dir();
+            if path.file_name().is_some_and(|name| name == ".git") || gitignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if max_depth.is_none_or(|max_depth| depth < max_depth) {
+                    dirs.push((path, depth + 1));
+                }
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The patterns of the `.gitignore` files found while walking a directory.
+#[derive(Debug, Default)]
+pub struct Gitignore {
+    rules: Vec<GitignoreRule>,
+}
+
+#[derive(Debug)]
+struct GitignoreRule {
+    /// The directory of the `.gitignore` file, which the pattern is relative to.
+    base: PathBuf,
+    pattern: Pattern,
+    /// Whether the rule re-includes what the previous ones ignored, with `!`.
+    negated: bool,
+    /// Whether the rule only matches directories, with a trailing `/`.
+    dir_only: bool,
+}
+
+impl Gitignore {
+    /// Adds the patterns of the `.gitignore` file in `dir`, if any.
+    pub async fn add_file(&mut self, ctx: &Context, dir: &Path) {
+        if let Ok(contents) = ctx.fs().read_to_string(dir.join(".gitignore")).await {
+            self.add(dir, &contents);
+        }
+    }
+
+    fn add(&mut self, base: &Path, contents: &str) {
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            // Patterns with a slash are relative to the .gitignore, the others match at any depth
+            let pattern = match line.contains('/') {
+                true => line.trim_start_matches('/').to_string(),
+                false => format!("**/{line}"),
+            };
+            match Pattern::new(&pattern) {
+                Ok(pattern) => self.rules.push(GitignoreRule {
+                    base: base.to_path_buf(),
+                    pattern,
+                    negated,
+                    dir_only,
+                }),
+                Err(err) => debug!(?err, pattern, "skipping invalid .gitignore pattern"),
+            }
+        }
+    }
+
+    /// Whether `path` is ignored, the last rule matching it deciding.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && path
+                        .strip_prefix(&rule.base)
+                        .is_ok_and(|relative| rule.pattern.matches_path_with(relative, GLOB_OPTIONS))
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
 }
 
 /// Add a file to the context collection.
 ///
 /// This method:
-/// 1. Reads the content of the file
-/// 2. Adds the (filename, content) pair to the context collection
+/// 1. Reads the content of the file, unless it is larger than [CONTEXT_FILE_MAX_BYTES], or extracts
+///    the text of PDFs and Word documents up to [DOCUMENT_FILE_MAX_BYTES]
+/// 2. Adds the (filename, content) pair to the context collection, or to `skipped` when the file is
+///    too large or not text
 ///
 /// # Arguments
 /// * `path` - The path to the file
+/// * `pages` - The pages to add when the file is a PDF, all of them otherwise
 /// * `context_files` - The collection to add the file to
+/// * `skipped` - The files left out
 ///
 /// # Returns
 /// A Result indicating success or an error
-async fn add_file_to_context(ctx: &Context, path: &Path, context_files: &mut Vec<(String, String)>) -> Result<()> {
-    let filename = path.to_string_lossy().to_string();
-    let content = ctx.fs().read_to_string(path).await?;
-    context_files.push((filename, content));
+async fn add_file_to_context(
+    ctx: &Context,
+    path: &Path,
+    pages: Option<PageRange>,
+    context_files: &mut Vec<(String, String)>,
+    skipped: &mut Vec<SkippedFile>,
+) -> Result<()> {
+    let mut filename = path.to_string_lossy().to_string();
+    let size = path.metadata().map_or(0, |metadata| metadata.len());
+    let kind = DocumentKind::of(path);
+    let max_size = match kind {
+        Some(_) => DOCUMENT_FILE_MAX_BYTES,
+        None => CONTEXT_FILE_MAX_BYTES,
+    };
+    if size > max_size {
+        skipped.push(SkippedFile {
+            path: filename,
+            reason: SkipReason::TooLarge(size),
+        });
+        return Ok(());
+    }
+
+    if let Some(kind) = kind {
+        if let Some(pages) = pages {
+            filename.push_str(&pages.to_string());
+        }
+        let bytes = ctx.fs().read(path).await?;
+        let key = context_cache::document_key(&bytes, pages);
+        let text = match context_cache::get(ctx, &key, None).await {
+            Some(text) => Ok(text),
+            None => {
+                let text = tokio::task::spawn_blocking(move || extract_text(kind, &bytes, pages)).await?;
+                if let Ok(text) = &text {
+                    if !text.trim().is_empty() {
+                        context_cache::put(ctx, &key, text).await;
+                    }
+                }
+                text
+            },
+        };
+        match text {
+            Ok(text) if text.len() as u64 > CONTEXT_FILE_MAX_BYTES => skipped.push(SkippedFile {
+                path: filename,
+                reason: SkipReason::TooLarge(text.len() as u64),
+            }),
+            Ok(text) if !text.trim().is_empty() => context_files.push((filename, text)),
+            result => {
+                debug!(?filename, err = ?result.err(), "no text extracted from the document");
+                skipped.push(SkippedFile {
+                    path: filename,
+                    reason: SkipReason::NoText,
+                });
+            },
+        }
+        return Ok(());
+    }
+
+    match ctx.fs().read_to_string(path).await {
+        Ok(content) => context_files.push((filename, content)),
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidData => skipped.push(SkippedFile {
+            path: filename,
+            reason: SkipReason::NotText,
+        }),
+        Err(err) => return Err(err.into()),
+    }
     Ok(())
 }
 
@@ -897,7 +1521,7 @@ mod tests {
             .await?;
         manager.add_paths(vec!["test/*.md".to_string()], false, false).await?;
 
-        let (used, dropped) = manager.collect_context_files_with_limit().await.unwrap();
+        let (used, dropped) = manager.collect_context_files_with_limit(None).await.unwrap();
 
         assert!(used.len() + dropped.len() == 2);
         assert!(used.len() == 1);
@@ -938,6 +1562,301 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_directory_and_glob_paths() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let ctx: Arc<Context> = Arc::clone(&manager.ctx);
+
+        ctx.fs().create_dir_all("repo/.git").await?;
+        ctx.fs().create_dir_all("repo/src/nested").await?;
+        ctx.fs().create_dir_all("repo/target").await?;
+        ctx.fs().write("repo/.gitignore", "target/\n*.log\n!keep.log\n").await?;
+        ctx.fs().write("repo/.git/HEAD", "ref: refs/heads/main").await?;
+        ctx.fs().write("repo/src/.gitignore", "/generated.rs\n").await?;
+        ctx.fs().write("repo/src/main.rs", "main").await?;
+        ctx.fs().write("repo/src/generated.rs", "generated").await?;
+        ctx.fs().write("repo/src/nested/lib.rs", "lib").await?;
+        ctx.fs().write("repo/src/nested/generated.rs", "nested").await?;
+        ctx.fs().write("repo/src/debug.log", "debug").await?;
+        ctx.fs().write("repo/src/keep.log", "keep").await?;
+        ctx.fs().write("repo/target/out.rs", "out").await?;
+        ctx.fs().write("repo/image.png", [0xff, 0xd8, 0xff]).await?;
+        ctx.fs()
+            .write("repo/large.md", "a".repeat(CONTEXT_FILE_MAX_BYTES as usize + 1))
+            .await?;
+
+        let names = |files: Vec<(String, String)>| {
+            files
+                .into_iter()
+                .map(|(path, _)| path.rsplit_once("/repo/").unwrap().1.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(manager.get_context_files_by_path("repo/src/**/*.rs").await?),
+            vec!["src/main.rs", "src/nested/generated.rs", "src/nested/lib.rs",]
+        );
+        assert_eq!(names(manager.get_context_files_by_path("repo/src/*.rs").await?), vec![
+            "src/main.rs"
+        ]);
+        assert_eq!(names(manager.get_context_files_by_path("repo").await?), vec![
+            ".gitignore",
+            "src/.gitignore",
+            "src/keep.log",
+            "src/main.rs",
+            "src/nested/generated.rs",
+            "src/nested/lib.rs",
+        ]);
+
+        let skipped = manager.add_paths(vec!["repo".to_string()], false, false).await?;
+        assert_eq!(
+            skipped
+                .iter()
+                .map(|file| (file.path.rsplit_once("/repo/").unwrap().1, file.reason))
+                .collect::<Vec<_>>(),
+            vec![
+                ("image.png", SkipReason::NotText),
+                ("large.md", SkipReason::TooLarge(CONTEXT_FILE_MAX_BYTES + 1)),
+            ]
+        );
+        assert_eq!(manager.get_skipped_context_files().await?, skipped);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_documents() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let ctx: Arc<Context> = Arc::clone(&manager.ctx);
+        let page = |contents: &str, text: &str| {
+            format!(
+                "<< /Type /Page /Parent 2 0 R /Contents {contents} 0 R >>\nendobj\n{contents} 0 obj\n<< /Length {} >>\nstream\nBT ({text}) Tj ET\nendstream\nendobj\n",
+                text.len() + 11
+            )
+        };
+        let pdf = format!(
+            "%PDF-1.4\n1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n2 0 obj\n<< /Type /Pages /Kids [3 0 R 5 0 R] /Count 2 >>\nendobj\n3 0 obj\n{}5 0 obj\n{}%%EOF\n",
+            page("4", "Goals"),
+            page("6", "Design")
+        );
+        ctx.fs().write("design.pdf", pdf).await?;
+        ctx.fs().write("scan.pdf", "%PDF-1.4\n%%EOF\n").await?;
+
+        let files = manager.get_context_files_by_path("design.pdf").await?;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].1, "[Page 1]\nGoals\n\n[Page 2]\nDesign");
+        let files = manager.get_context_files_by_path("design.pdf#page=2").await?;
+        assert!(files[0].0.ends_with("design.pdf#page=2"));
+        assert_eq!(files[0].1, "[Page 2]\nDesign");
+
+        let skipped = manager.add_paths(vec!["scan.pdf".to_string()], false, false).await?;
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].reason, SkipReason::NoText);
+        assert!(manager.get_context_files().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_take_updated_files() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let ctx: Arc<Context> = Arc::clone(&manager.ctx);
+        ctx.fs().write("a.md", "a").await?;
+        ctx.fs().write("b.md", "b").await?;
+        manager.add_paths(vec!["a.md".to_string()], false, false).await?;
+
+        let files = manager.get_context_files().await?;
+        assert!(manager.take_updated_files(files).is_empty());
+        ctx.fs().write("a.md", "a, edited").await?;
+        manager.add_paths(vec!["b.md".to_string()], false, false).await?;
+        let files = manager.get_context_files().await?;
+        let updated = manager.take_updated_files(files);
+        assert_eq!(updated.len(), 1);
+        assert!(updated[0].ends_with("a.md"));
+        let files = manager.get_context_files().await?;
+        assert!(manager.take_updated_files(files).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diff_sent_files() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        manager.attach("a.md".to_string(), "one\ntwo\n".to_string());
+        manager.attach("b.md".to_string(), "b\n".to_string());
+        assert!(manager.diff_sent_files().is_empty());
+
+        manager.take_updated_files(manager.get_context_files().await?);
+        assert_eq!(manager.diff_sent_files(), vec![
+            ContextFileChange::Added("a.md".to_string()),
+            ContextFileChange::Added("b.md".to_string()),
+        ]);
+
+        manager.attach("a.md".to_string(), "one\nthree\n".to_string());
+        manager.remove_paths(vec!["b.md".to_string()], false).await?;
+        manager.attach("c.md".to_string(), "c\n".to_string());
+        manager.take_updated_files(manager.get_context_files().await?);
+        let changes = manager.diff_sent_files();
+        assert_eq!(changes.len(), 3);
+        let ContextFileChange::Modified(path, diff) = &changes[0] else {
+            panic!("a.md should be modified: {changes:?}");
+        };
+        assert_eq!(path, "a.md");
+        assert!(diff.starts_with("--- a/a.md\n+++ b/a.md\n@@"));
+        assert!(diff.contains("\n-two\n+three\n"));
+        assert_eq!(changes[1..], [
+            ContextFileChange::Removed("b.md".to_string()),
+            ContextFileChange::Added("c.md".to_string()),
+        ]);
+
+        manager.take_updated_files(manager.get_context_files().await?);
+        assert!(manager.diff_sent_files().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_attachments() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        manager.attach("https://docs.rs/tokio".to_string(), "Tokio".to_string());
+        manager.attach("https://docs.rs/tokio".to_string(), "Tokio, updated".to_string());
+        assert_eq!(manager.get_context_files().await?, vec![(
+            "https://docs.rs/tokio".to_string(),
+            "Tokio, updated".to_string()
+        )]);
+
+        manager
+            .remove_paths(vec!["https://docs.rs/tokio".to_string()], false)
+            .await?;
+        assert!(manager.get_context_files().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_workspace_config() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let ctx: Arc<Context> = Arc::clone(&manager.ctx);
+        ctx.fs().write("notes.md", "notes").await?;
+        ctx.fs().write("todo.md", "todo").await?;
+        manager.add_paths(vec!["notes.md".to_string()], false, false).await?;
+
+        let path = manager.save_workspace().await?;
+        assert_eq!(path, workspace_context_path(&ctx)?);
+        assert!(ctx.fs().exists(&path));
+
+        // Sessions started in the same directory restore the rules, and save changes there
+        let mut manager = ContextManager::new(Arc::clone(&ctx), None).await?;
+        assert_eq!(manager.workspace_config_path, Some(path.clone()));
+        assert_eq!(manager.profile_config.paths, vec!["notes.md"]);
+        manager.add_paths(vec!["todo.md".to_string()], false, false).await?;
+        assert_eq!(load_config_file(&ctx, &path).await?.paths, vec!["notes.md", "todo.md"]);
+        assert_eq!(load_profile_config(&ctx, "default").await?.paths, vec!["notes.md"]);
+
+        assert!(manager.reset_workspace().await?);
+        assert!(!ctx.fs().exists(&path));
+        assert_eq!(manager.workspace_config_path, None);
+        assert_eq!(manager.profile_config.paths, vec!["notes.md"]);
+        assert!(!manager.reset_workspace().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let ctx: Arc<Context> = Arc::clone(&manager.ctx);
+        assert_eq!(manager.system_prompt(None).await, None);
+        assert_eq!(
+            manager.system_prompt(Some("Prefer CDK")).await,
+            Some(SystemPrompt {
+                text: "Prefer CDK".to_string(),
+                source: SystemPromptSource::Setting,
+            })
+        );
+
+        // The prompt of the profile takes the place of the setting
+        manager.create_profile("portuguese").await?;
+        manager.switch_profile("portuguese").await?;
+        let path = manager.system_prompt_path()?;
+        ctx.fs().write(&path, "Always answer in Portuguese.\n").await?;
+        assert_eq!(
+            manager.system_prompt(Some("Prefer CDK")).await,
+            Some(SystemPrompt {
+                text: "Always answer in Portuguese.".to_string(),
+                source: SystemPromptSource::Profile(path),
+            })
+        );
+        manager.switch_profile("default").await?;
+        assert_eq!(
+            manager
+                .system_prompt(Some("Prefer CDK"))
+                .await
+                .map(|prompt| prompt.text),
+            Some("Prefer CDK".to_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rules() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let ctx: Arc<Context> = Arc::clone(&manager.ctx);
+        assert!(manager.rules().await?.is_empty());
+
+        ctx.fs().create_dir_all(".amazonq/rules/testing").await?;
+        ctx.fs().write(".amazonq/rules/style.md", "Use tabs").await?;
+        ctx.fs()
+            .write(".amazonq/rules/testing/unit.md", "Test everything")
+            .await?;
+        ctx.fs().write(".amazonq/rules/notes.txt", "Not a rule").await?;
+        ctx.fs().write("README.md", "Readme").await?;
+        manager.add_paths(vec!["*.md".to_string()], false, false).await?;
+
+        let rules = manager.rules().await?;
+        let names = rules.iter().map(|rule| rule.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["style.md", "testing/unit.md"]);
+        assert!(rules.iter().all(|rule| rule.enabled));
+        assert_eq!(rules[1].content, "Test everything");
+
+        // The rules are not context files, even when matched by the default global rule
+        let files = manager.get_context_files().await?;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].0.ends_with("README.md"));
+
+        let names = manager.set_rules_enabled(&["testing/unit".to_string()], false).await?;
+        assert_eq!(names, vec!["testing/unit.md"]);
+        let rules = manager.rules().await?;
+        assert!(rules[0].enabled && !rules[1].enabled);
+        assert!(
+            manager
+                .set_rules_enabled(&["missing".to_string()], false)
+                .await
+                .is_err()
+        );
+        manager
+            .set_rules_enabled(&["testing/unit.md".to_string()], true)
+            .await?;
+        assert!(manager.disabled_rules.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gitignore() {
+        let mut gitignore = Gitignore::default();
+        gitignore.add(
+            Path::new("/repo"),
+            "# comment\n\nbuild/\n/root.txt\ndocs/*.pdf\n*.tmp\n!keep.tmp\n",
+        );
+        gitignore.add(Path::new("/repo/sub"), "local\n");
+
+        assert!(gitignore.is_ignored(Path::new("/repo/build"), true));
+        assert!(gitignore.is_ignored(Path::new("/repo/a/build"), true));
+        assert!(!gitignore.is_ignored(Path::new("/repo/build"), false));
+        assert!(gitignore.is_ignored(Path::new("/repo/root.txt"), false));
+        assert!(!gitignore.is_ignored(Path::new("/repo/a/root.txt"), false));
+        assert!(gitignore.is_ignored(Path::new("/repo/docs/guide.pdf"), false));
+        assert!(!gitignore.is_ignored(Path::new("/repo/docs/a/guide.pdf"), false));
+        assert!(gitignore.is_ignored(Path::new("/repo/a/b/c.tmp"), false));
+        assert!(!gitignore.is_ignored(Path::new("/repo/a/keep.tmp"), false));
+        assert!(gitignore.is_ignored(Path::new("/repo/sub/local"), false));
+        assert!(!gitignore.is_ignored(Path::new("/repo/local"), false));
+        assert!(!gitignore.is_ignored(Path::new("/elsewhere/x.tmp"), false));
+    }
+
     #[tokio::test]
     async fn test_add_hook() -> Result<()> {
         let mut manager = create_test_context_manager(None).await?;
@@ -1053,4 +1972,30 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_tool_hooks() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let lint = Hook::new_inline_hook(HookTrigger::PostToolUse, "cargo clippy".to_string());
+        let policy = Hook::new_inline_hook(HookTrigger::PreToolUse, "./policy.sh".to_string());
+        let mut disabled = Hook::new_inline_hook(HookTrigger::PostToolUse, "echo off".to_string());
+        disabled.disabled = true;
+
+        manager.add_hook("lint".to_string(), lint, false).await?;
+        manager.add_hook("policy".to_string(), policy, true).await?;
+        manager.add_hook("disabled".to_string(), disabled, false).await?;
+
+        // Only run around tools, not with the prompts
+        assert!(manager.run_hooks(None::<&mut Stdout>).await.is_empty());
+
+        let hooks = manager.tool_hooks(HookTrigger::PostToolUse);
+        assert_eq!(hooks.len(), 1);
+        assert_eq!(hooks[0].name, "lint");
+        assert!(!hooks[0].is_global);
+        let hooks = manager.tool_hooks(HookTrigger::PreToolUse);
+        assert_eq!(hooks.len(), 1);
+        assert!(hooks[0].is_global);
+
+        Ok(())
+    }
 }
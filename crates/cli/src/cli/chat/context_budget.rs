@@ -0,0 +1,250 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::token_counter::TokenCounter;
+
+/// Context files, by name.
+type Files = Vec<(String, String)>;
+
+/// Words too common to tell which files a prompt is about.
+pub const STOP_WORDS: &[&str] = &[
+    "about", "and", "are", "can", "does", "file", "files", "for", "from", "how", "into", "not", "the", "this", "that",
+    "what", "when", "where", "which", "why", "with", "you", "your",
+];
+
+/// What context files are ranked by when they don't all fit in the budget, see
+/// [fit_to_budget].
+#[derive(Debug, Default)]
+pub struct RankSignals<'a> {
+    /// The prompt about to be sent. Files mentioning its words rank higher, their paths even more.
+    pub prompt: Option<&'a str>,
+    /// The paths added to the context by name, rather than matched by a glob. Their files, and
+    /// those of the directories among them, rank first.
+    pub explicit: Vec<String>,
+    /// When each file was last modified, recent ones ranking higher. Files without a time, like
+    /// attachments, count as the most recent.
+    pub modified: HashMap<String, SystemTime>,
+}
+
+impl RankSignals<'_> {
+    fn is_explicit(&self, name: &str) -> bool {
+        // The pages of a document are part of it
+        let path = name.split_once("#page=").map_or(name, |(path, _)| path);
+        self.explicit
+            .iter()
+            .any(|explicit| Path::new(path).starts_with(explicit))
+    }
+}
+
+/// The lowercase words of `text` that can tell what it is about.
+fn keywords(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| word.chars().count() >= 3)
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// The scores of `files`, the highest for the files to keep first: 2 for explicit files, up to 1
+/// for the relevance to the prompt and up to 0.5 for recency.
+fn scores(files: &[(String, String)], signals: &RankSignals<'_>) -> Vec<f64> {
+    let prompt_keywords = signals.prompt.map(keywords).unwrap_or_default();
+
+    // From the oldest file to the most recent one
+    let mut by_age = (0..files.len()).collect::<Vec<_>>();
+    by_age.sort_by_key(|&index| {
+        signals
+            .modified
+            .get(&files[index].0)
+            .copied()
+            .unwrap_or_else(SystemTime::now)
+    });
+    let mut recency = vec![1.0; files.len()];
+    if files.len() > 1 {
+        for (age_rank, &index) in by_age.iter().enumerate() {
+            recency[index] = age_rank as f64 / (files.len() - 1) as f64;
+        }
+    }
+
+    files
+        .iter()
+        .zip(recency)
+        .map(|((name, content), recency)| {
+            let relevance = match prompt_keywords.is_empty() {
+                true => 0.0,
+                false => {
+                    let name_keywords = keywords(name);
+                    let content = content.to_lowercase();
+                    let matched = prompt_keywords
+                        .iter()
+                        .map(
+                            |keyword| match (name_keywords.contains(keyword), content.contains(keyword.as_str())) {
+                                (true, _) => 1.0,
+                                (false, true) => 0.5,
+                                (false, false) => 0.0,
+                            },
+                        )
+                        .sum::<f64>();
+                    matched / prompt_keywords.len() as f64
+                },
+            };
+            let explicit = if signals.is_explicit(name) { 2.0 } else { 0.0 };
+            explicit + relevance + 0.5 * recency
+        })
+        .collect()
+}
+
+/// Splits `files` into those to send and those left out to stay within `limit` tokens. The
+/// files are kept from the highest ranked by `signals`, in their order, and a file that doesn't
+/// fit leaves room for smaller ones ranked lower.
+pub fn fit_to_budget(files: Files, signals: &RankSignals<'_>, limit: usize) -> (Files, Files) {
+    let tokens = files
+        .iter()
+        .map(|(_, content)| TokenCounter::count_tokens(content))
+        .collect::<Vec<_>>();
+    if tokens.iter().sum::<usize>() <= limit {
+        return (files, Vec::new());
+    }
+
+    let scores = scores(&files, signals);
+    let mut by_rank = (0..files.len()).collect::<Vec<_>>();
+    by_rank.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    let mut total = 0;
+    let dropped_indices = by_rank
+        .into_iter()
+        .filter(|&index| {
+            let fits = total + tokens[index] <= limit;
+            if fits {
+                total += tokens[index];
+            }
+            !fits
+        })
+        .collect::<Vec<_>>();
+
+    // The dropped files from the highest ranked, the first to bring back
+    let mut files = files.into_iter().map(Some).collect::<Vec<_>>();
+    let dropped = dropped_indices
+        .into_iter()
+        .filter_map(|index| files[index].take())
+        .collect();
+    (files.into_iter().flatten().collect(), dropped)
+}
+
+/// The warning telling which files [fit_to_budget] left out to stay within `limit` tokens.
+pub fn dropped_files_warning(dropped: &[(String, String)], limit: usize) -> String {
+    const LISTED: usize = 3;
+    let mut listed = dropped
+        .iter()
+        .rev()
+        .take(LISTED)
+        .map(|(name, content)| format!("{} (~{} tkns)", name, TokenCounter::count_tokens(content)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if dropped.len() > LISTED {
+        listed.push_str(&format!(" and {} more", dropped.len() - LISTED));
+    }
+    format!(
+        "Context files exceed the limit of {limit} tokens, left out the lowest ranked by how they were added, relevance to your prompt and recency: {listed}."
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn file(name: &str, tokens: usize) -> (String, String) {
+        (
+            name.to_string(),
+            "word ".repeat(tokens * TokenCounter::TOKEN_TO_CHAR_RATIO / 5),
+        )
+    }
+
+    fn names(files: &[(String, String)]) -> Vec<&str> {
+        files.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_dropped_files_warning() {
+        let dropped = vec![file("a.md", 40), file("b.md", 20)];
+        assert_eq!(
+            dropped_files_warning(&dropped, 100),
+            "Context files exceed the limit of 100 tokens, left out the lowest ranked by how they were added, relevance to your prompt and recency: b.md (~20 tkns), a.md (~40 tkns)."
+        );
+        let dropped = (0..5).map(|index| file(&format!("{index}.md"), 10)).collect::<Vec<_>>();
+        assert!(
+            dropped_files_warning(&dropped, 10)
+                .ends_with("4.md (~10 tkns), 3.md (~10 tkns), 2.md (~10 tkns) and 2 more.")
+        );
+    }
+
+    #[test]
+    fn test_keywords() {
+        let mut words = keywords("How does the Parser handle src/lexer_v2.rs?")
+            .into_iter()
+            .collect::<Vec<_>>();
+        words.sort();
+        assert_eq!(words, vec!["handle", "lexer_v2", "parser", "src"]);
+    }
+
+    #[test]
+    fn test_fit_to_budget() {
+        let files = vec![file("/repo/a.md", 40), file("/repo/b.md", 40), file("/repo/c.md", 40)];
+        let (kept, dropped) = fit_to_budget(files.clone(), &RankSignals::default(), 200);
+        assert_eq!((kept.len(), dropped.len()), (3, 0));
+
+        // The most recent files without other signals
+        let now = SystemTime::now();
+        let signals = RankSignals {
+            modified: HashMap::from([
+                ("/repo/a.md".to_string(), now),
+                ("/repo/b.md".to_string(), now - Duration::from_secs(3600)),
+                ("/repo/c.md".to_string(), now - Duration::from_secs(60)),
+            ]),
+            ..Default::default()
+        };
+        let (kept, dropped) = fit_to_budget(files.clone(), &signals, 90);
+        assert_eq!(names(&kept), vec!["/repo/a.md", "/repo/c.md"]);
+        assert_eq!(names(&dropped), vec!["/repo/b.md"]);
+
+        // Files the prompt is about rank higher than recent ones
+        let signals = RankSignals {
+            prompt: Some("Why is the lexer slow?"),
+            ..signals
+        };
+        let mut files = files;
+        files[1].1.push_str(" lexer slow");
+        let (kept, dropped) = fit_to_budget(files.clone(), &signals, 90);
+        assert_eq!(names(&kept), vec!["/repo/a.md", "/repo/b.md"]);
+        assert_eq!(names(&dropped), vec!["/repo/c.md"]);
+
+        // Files added by name rank first, with the other files of their directory
+        let files = vec![
+            file("/repo/docs/guide.md", 40),
+            file("/repo/notes.md", 40),
+            file("/repo/spec.pdf#page=2", 40),
+        ];
+        let signals = RankSignals {
+            explicit: vec!["/repo/docs".to_string(), "/repo/spec.pdf".to_string()],
+            ..Default::default()
+        };
+        let (kept, dropped) = fit_to_budget(files, &signals, 90);
+        assert_eq!(names(&kept), vec!["/repo/docs/guide.md", "/repo/spec.pdf#page=2"]);
+        assert_eq!(names(&dropped), vec!["/repo/notes.md"]);
+
+        // A file too large to keep leaves room for the others
+        let files = vec![file("/repo/large.md", 100), file("/repo/small.md", 20)];
+        let signals = RankSignals {
+            explicit: vec!["/repo/large.md".to_string()],
+            ..Default::default()
+        };
+        let (kept, dropped) = fit_to_budget(files, &signals, 50);
+        assert_eq!(names(&kept), vec!["/repo/small.md"]);
+        assert_eq!(names(&dropped), vec!["/repo/large.md"]);
+    }
+}
@@ -0,0 +1,141 @@
+//! The text extracted from documents and fetched from URLs for the context, saved under the data
+//! directory so that adding the same material again, in this session or a later one, doesn't
+//! parse or download it again.
+//!
+//! Entries are keyed by the hash of what they are made from: the contents of a document along
+//! with the pages taken out of it, or the URL of a page. Those not used in [MAX_AGE] are removed
+//! whenever an entry is saved.
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use sha2::{
+    Digest,
+    Sha256,
+};
+use tracing::debug;
+
+use super::document::PageRange;
+use crate::platform::Context;
+use crate::util::directories;
+
+/// How long the text of a page is reused before it is fetched again, pages changing unlike the
+/// contents of a document.
+pub const URL_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long an entry is kept without being used.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The key of the text extracted from `bytes`, the contents of a document, restricted to `pages`.
+pub fn document_key(bytes: &[u8], pages: Option<PageRange>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"document\0");
+    if let Some(pages) = pages {
+        hasher.update(pages.to_string().as_bytes());
+    }
+    hasher.update(b"\0");
+    hasher.update(bytes);
+    hex(&hasher.finalize())
+}
+
+/// The key of the text of the page at `url`.
+pub fn url_key(url: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"url\0");
+    hasher.update(url.as_bytes());
+    hex(&hasher.finalize())
+}
+
+fn hex(hash: &[u8]) -> String {
+    hash.iter().fold(String::new(), |mut name, byte| {
+        name.push_str(&format!("{byte:02x}"));
+        name
+    })
+}
+
+fn entry_path(key: &str) -> Option<PathBuf> {
+    Some(directories::chat_context_cache_dir().ok()?.join(format!("{key}.txt")))
+}
+
+/// The text saved under `key`, unless it was saved more than `max_age` ago.
+pub async fn get(ctx: &Context, key: &str, max_age: Option<Duration>) -> Option<String> {
+    let path = entry_path(key)?;
+    if let Some(max_age) = max_age {
+        let modified = ctx.fs().symlink_metadata(&path).await.ok()?.modified().ok()?;
+        if modified.elapsed().unwrap_or_default() > max_age {
+            return None;
+        }
+    }
+    let text = ctx.fs().read_to_string(&path).await.ok()?;
+    debug!(?path, "using the cached context");
+    Some(text)
+}
+
+/// Saves `text` under `key`, the cache being an optimization that failing to write to is only
+/// logged.
+pub async fn put(ctx: &Context, key: &str, text: &str) {
+    let Ok(dir) = directories::chat_context_cache_dir() else {
+        return;
+    };
+    if let Err(err) = ctx.fs().create_dir_all(&dir).await {
+        debug!(?err, "failed to create the context cache directory");
+        return;
+    }
+    if let Err(err) = ctx.fs().write(dir.join(format!("{key}.txt")), text).await {
+        debug!(?err, "failed to cache the context");
+    }
+    prune(ctx, &dir).await;
+}
+
+/// Removes the entries of `dir` not used in [MAX_AGE].
+async fn prune(ctx: &Context, dir: &Path) {
+    let Ok(mut entries) = ctx.fs().read_dir(dir).await else {
+        return;
+    };
+    let now = SystemTime::now();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let accessed = entry
+            .metadata()
+            .await
+            .ok()
+            .and_then(|metadata| metadata.accessed().or_else(|_| metadata.modified()).ok());
+        if accessed.is_some_and(|accessed| now.duration_since(accessed).unwrap_or_default() > MAX_AGE) {
+            // Joined to `dir` rather than using the entry's path, which is outside of a chroot
+            let _ = ctx.fs().remove_file(dir.join(entry.file_name())).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let pages = Some(PageRange { first: 2, last: 5 });
+        assert_eq!(document_key(b"%PDF-1.4", None), document_key(b"%PDF-1.4", None));
+        assert_ne!(document_key(b"%PDF-1.4", None), document_key(b"%PDF-1.4", pages));
+        assert_ne!(document_key(b"%PDF-1.4", None), document_key(b"%PDF-1.5", None));
+        assert_ne!(url_key("https://example.com"), url_key("https://example.org"));
+        assert_eq!(url_key("https://example.com").len(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_get_and_put() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let key = url_key("https://example.com/cached");
+        assert_eq!(get(&ctx, &key, None).await, None);
+        put(&ctx, &key, "Example Domain").await;
+        assert_eq!(get(&ctx, &key, None).await.as_deref(), Some("Example Domain"));
+        assert_eq!(
+            get(&ctx, &key, Some(URL_MAX_AGE)).await.as_deref(),
+            Some("Example Domain")
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::{
+    BTreeSet,
     HashMap,
     HashSet,
     VecDeque,
@@ -22,16 +23,19 @@ use tracing::{
 };
 
 use super::consts::{
+    CONTEXT_FILES_MAX_SIZE,
     DUMMY_TOOL_NAME,
     MAX_CHARS,
     MAX_CONVERSATION_STATE_HISTORY_LEN,
     MAX_USER_MESSAGE_SIZE,
 };
 use super::context::ContextManager;
+use super::context_budget::dropped_files_warning;
 use super::hooks::{
     Hook,
     HookTrigger,
 };
+use super::index::IndexRetriever;
 use super::message::{
     AssistantMessage,
     ToolUseResult,
@@ -40,6 +44,7 @@ use super::message::{
     UserMessageContent,
     build_env_state,
 };
+use super::redaction::Redactor;
 use super::token_counter::{
     CharCount,
     CharCounter,
@@ -51,6 +56,7 @@ use super::tools::{
     ToolOrigin,
     ToolSpec,
 };
+use super::usage::SessionUsage;
 use super::util::{
     serde_value_to_document,
     truncate_safe,
@@ -101,8 +107,43 @@ pub struct ConversationState {
     pub tool_manager: ToolManager,
     /// Cached value representing the length of the user context message.
     context_message_length: Option<usize>,
+    /// The context files read for the last context message, see
+    /// [ContextManager::take_updated_files].
+    #[serde(skip)]
+    context_files: Option<Vec<(String, String)>>,
     /// Stores the latest conversation summary created by /compact
     latest_summary: Option<String>,
+    /// Added with /tag to find the conversation with `q chat search --tag` once saved.
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    /// Set once the first prompts tell what the conversation is about, see
+    /// [super::session::generate_title].
+    #[serde(default)]
+    title: Option<String>,
+    /// Estimated tokens sent and received so far, shown by /usage.
+    #[serde(default)]
+    usage: SessionUsage,
+    /// Messages pinned with /pin, sent verbatim with every request so that neither compaction nor
+    /// truncation of the history drops them.
+    #[serde(default)]
+    pins: Vec<String>,
+    /// An overview of the project the chat was started in, from `chat.autoContext`. Detected
+    /// again when the conversation is resumed.
+    #[serde(skip)]
+    project_context: Option<String>,
+    /// Masks the secrets of prompts and context files, set from the settings of the session.
+    #[serde(skip)]
+    pub redactor: Redactor,
+    /// The system prompt of the profiles without their own, from `chat.systemPrompt`.
+    #[serde(skip)]
+    pub default_system_prompt: Option<String>,
+    /// Retrieves the parts of the workspace relevant to each prompt, when it was indexed with
+    /// `q index build`.
+    #[serde(skip)]
+    pub index_retriever: Option<IndexRetriever>,
+    /// The tools turned off with `/tools disable`, left out of [Self::tools] for the session.
+    #[serde(skip)]
+    pub disabled_tools: HashSet<String>,
     #[serde(skip)]
     pub updates: Option<SharedWriter>,
 }
@@ -155,7 +196,17 @@ impl ConversationState {
             context_manager,
             tool_manager,
             context_message_length: None,
+            context_files: None,
             latest_summary: None,
+            tags: BTreeSet::new(),
+            title: None,
+            usage: SessionUsage::default(),
+            pins: Vec::new(),
+            project_context: None,
+            redactor: Redactor::default(),
+            default_system_prompt: None,
+            index_retriever: None,
+            disabled_tools: HashSet::new(),
             updates,
         }
     }
@@ -199,12 +250,126 @@ impl ConversationState {
         &self.history
     }
 
-    /// Clears the conversation history and optionally the summary.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    pub fn usage(&self) -> &SessionUsage {
+        &self.usage
+    }
+
+    pub fn set_project_context(&mut self, project_context: Option<String>) {
+        self.project_context = project_context;
+    }
+
+    pub fn pins(&self) -> &[String] {
+        &self.pins
+    }
+
+    /// Pins `content`, returning whether it wasn't pinned already.
+    pub fn pin(&mut self, content: String) -> bool {
+        if self.pins.contains(&content) {
+            return false;
+        }
+        self.pins.push(content);
+        true
+    }
+
+    /// Unpins the `index`-th pin of [Self::pins], returning it.
+    pub fn unpin(&mut self, index: usize) -> Option<String> {
+        (index < self.pins.len()).then(|| self.pins.remove(index))
+    }
+
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    /// Adds `tag`, returning whether it was new.
+    pub fn add_tag(&mut self, tag: String) -> bool {
+        self.tags.insert(tag)
+    }
+
+    /// Removes `tag`, returning whether the conversation had it.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        self.tags.remove(tag)
+    }
+
+    /// Returns the prompts the user typed, along with their index in [Self::history].
+    ///
+    /// Tool use results are skipped, so the n-th item is the n-th message the user wrote.
+    pub fn user_prompts(&self) -> Vec<(usize, &str)> {
+        self.history
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (user, _))| user.prompt().map(|prompt| (i, prompt)))
+            .collect()
+    }
+
+    /// Whether the result of the tool use `tool_use_id` is still in the history sent, not having
+    /// been undone or summarized.
+    pub fn has_tool_result(&self, tool_use_id: &str) -> bool {
+        self.history
+            .range(self.valid_history_range.0..self.valid_history_range.1)
+            .filter_map(|(user, _)| user.tool_use_results())
+            .flatten()
+            .any(|result| result.tool_use_id == tool_use_id)
+    }
+
+    /// Drops the history from `index` onwards (along with any pending message) so that the
+    /// conversation continues from that point.
+    pub fn truncate_history(&mut self, index: usize) {
+        self.next_message = None;
+        self.history.truncate(index);
+        self.valid_history_range = (0, self.history.len());
+    }
+
+    /// Removes the messages in `range` of the history so that they aren't sent anymore, returning
+    /// them. The range is expected to span whole exchanges, from a prompt up to the next one.
+    pub fn forget_history(&mut self, range: std::ops::Range<usize>) -> Vec<(UserMessage, AssistantMessage)> {
+        let forgotten = self.history.drain(range).collect();
+        self.valid_history_range = (0, self.history.len());
+        forgotten
+    }
+
+    /// A conversation without any messages, keeping the tools and context files of this one, see
+    /// `/new`.
+    pub fn new_conversation(&self, conversation_id: &str) -> Self {
+        Self {
+            conversation_id: conversation_id.to_string(),
+            next_message: None,
+            history: VecDeque::new(),
+            valid_history_range: Default::default(),
+            transcript: VecDeque::with_capacity(MAX_CONVERSATION_STATE_HISTORY_LEN),
+            tools: self.tools.clone(),
+            context_manager: self.context_manager.clone(),
+            tool_manager: ToolManager::default(),
+            context_message_length: None,
+            context_files: None,
+            latest_summary: None,
+            tags: BTreeSet::new(),
+            title: None,
+            usage: SessionUsage::default(),
+            pins: Vec::new(),
+            project_context: self.project_context.clone(),
+            redactor: self.redactor.clone(),
+            default_system_prompt: self.default_system_prompt.clone(),
+            index_retriever: self.index_retriever.clone(),
+            disabled_tools: self.disabled_tools.clone(),
+            updates: self.updates.clone(),
+        }
+    }
+
+    /// Clears the conversation history and optionally the summary along with the pins.
     pub fn clear(&mut self, preserve_summary: bool) {
         self.next_message = None;
         self.history.clear();
         if !preserve_summary {
             self.latest_summary = None;
+            self.pins.clear();
         }
     }
 
@@ -237,6 +402,16 @@ impl ConversationState {
         Some(last_msg.content.to_string())
     }
 
+    /// Appends a turn to the history as is, e.g. one imported from another assistant.
+    pub fn push_history_entry(&mut self, user: UserMessage, assistant: AssistantMessage) {
+        if let Some(prompt) = user.prompt() {
+            self.append_user_transcript(prompt);
+        }
+        self.append_assistant_transcript(&assistant);
+        self.history.push_back((user, assistant));
+        self.valid_history_range = (0, self.history.len());
+    }
+
     pub fn next_user_message(&self) -> Option<&UserMessage> {
         self.next_message.as_ref()
     }
@@ -267,6 +442,17 @@ impl ConversationState {
         debug_assert!(self.next_message.is_some(), "next_message should exist");
         let next_user_message = self.next_message.take().expect("next user message should exist");
 
+        // Estimate the request that was answered from the history, the cached context and the tools
+        let input = self
+            .history
+            .range(self.valid_history_range.0..self.valid_history_range.1)
+            .fold(next_user_message.char_count(), |acc, (user, assistant)| {
+                acc + user.char_count() + assistant.char_count()
+            })
+            + self.context_message_length.unwrap_or_default().into()
+            + tools_char_count(&self.tools);
+        self.usage.record(input, message.char_count());
+
         self.append_assistant_transcript(&message);
         self.history.push_back((next_user_message, message));
 
@@ -443,13 +629,20 @@ impl ConversationState {
         self.history.drain(self.valid_history_range.1..);
         self.history.drain(..self.valid_history_range.0);
 
+        let limit = self
+            .context_manager
+            .as_ref()
+            .map_or(CONTEXT_FILES_MAX_SIZE, ContextManager::max_context_files_size);
         let context = self.backend_conversation_state(run_hooks, false).await;
         if !context.dropped_context_files.is_empty() {
             let mut output = SharedWriter::stdout();
             execute!(
                 output,
                 style::SetForegroundColor(Color::DarkYellow),
-                style::Print("\nSome context files are dropped due to size limit, please run "),
+                style::Print(format!(
+                    "\n{} Run ",
+                    dropped_files_warning(&context.dropped_context_files, limit)
+                )),
                 style::SetForegroundColor(Color::DarkGreen),
                 style::Print("/context show "),
                 style::SetForegroundColor(Color::DarkYellow),
@@ -458,10 +651,31 @@ impl ConversationState {
             )
             .ok();
         }
-
-        context
+        let state = context
             .into_fig_conversation_state()
-            .expect("unable to construct conversation state")
+            .expect("unable to construct conversation state");
+
+        // Context files are read again for every request, edits made since the last one included
+        let updated_files = match (self.context_manager.as_mut(), self.context_files.take()) {
+            (Some(context_manager), Some(files)) => context_manager.take_updated_files(files),
+            _ => Vec::new(),
+        };
+        if !updated_files.is_empty() {
+            let mut output = SharedWriter::stdout();
+            execute!(
+                output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(
+                    "\n↻ {} context file{} updated since the last message\n",
+                    updated_files.len(),
+                    if updated_files.len() == 1 { "" } else { "s" }
+                )),
+                style::SetForegroundColor(style::Color::Reset)
+            )
+            .ok();
+        }
+
+        state
     }
 
     pub async fn update_state(&mut self, force_update: bool) {
@@ -470,11 +684,32 @@ impl ConversationState {
             return;
         }
         self.tool_manager.update().await;
+        self.rebuild_tools();
+        self.tool_manager.has_new_stuff.store(false, Ordering::Release);
+        // We call this in [Self::enforce_conversation_invariants] as well. But we need to call it
+        // here as well because when it's being called in [Self::enforce_conversation_invariants]
+        // it is only checking the last entry.
+        self.enforce_tool_use_history_invariants();
+    }
+
+    /// Turns the tool named `name` on or off for the session, see `/tools enable` and `/tools
+    /// disable`.
+    pub fn set_tool_enabled(&mut self, name: &str, enabled: bool) {
+        match enabled {
+            true => self.disabled_tools.remove(name),
+            false => self.disabled_tools.insert(name.to_string()),
+        };
+        self.rebuild_tools();
+    }
+
+    /// Sets [Self::tools] from the schema of the tool manager, the disabled tools left out.
+    fn rebuild_tools(&mut self) {
         // TODO: make this more targeted so we don't have to clone the entire list of tools
         self.tools = self
             .tool_manager
             .schema
             .values()
+            .filter(|v| !self.disabled_tools.contains(&v.name))
             .fold(HashMap::<ToolOrigin, Vec<Tool>>::new(), |mut acc, v| {
                 let tool = Tool::ToolSpecification(ToolSpecification {
                     name: v.name.clone(),
@@ -486,11 +721,6 @@ impl ConversationState {
                     .or_insert(vec![tool]);
                 acc
             });
-        self.tool_manager.has_new_stuff.store(false, Ordering::Release);
-        // We call this in [Self::enforce_conversation_invariants] as well. But we need to call it
-        // here as well because when it's being called in [Self::enforce_conversation_invariants]
-        // it is only checking the last entry.
-        self.enforce_tool_use_history_invariants();
     }
 
     /// Returns a conversation state representation which reflects the exact conversation to send
@@ -540,8 +770,14 @@ impl ConversationState {
 
     /// Returns a [FigConversationState] capable of replacing the history of the current
     /// conversation with a summary generated by the model.
-    pub async fn create_summary_request(&mut self, custom_prompt: Option<impl AsRef<str>>) -> FigConversationState {
-        let summary_content = match custom_prompt {
+    ///
+    /// The last `strategy.keep_last` messages in the history are left out of the summary.
+    pub async fn create_summary_request(
+        &mut self,
+        custom_prompt: Option<impl AsRef<str>>,
+        strategy: CompactStrategy,
+    ) -> FigConversationState {
+        let mut summary_content = match custom_prompt {
             Some(custom_prompt) => {
                 // Make the custom instructions much more prominent and directive
                 format!(
@@ -583,15 +819,27 @@ impl ConversationState {
                         FILTER OUT CHAT CONVENTIONS (greetings, offers to help, etc).".to_string()
             },
         };
+        if strategy.preserve_code {
+            summary_content.push_str(
+                "\n\nPRESERVE CODE: Reproduce every code snippet, command and file path shared in the conversation \
+                verbatim in a ## CODE section. Do not paraphrase or shorten code.",
+            );
+        }
+        if strategy.aggressive {
+            summary_content.push_str(
+                "\n\nBE AGGRESSIVE: Keep the summary as short as possible. Only include the current task, decisions made \
+                and facts needed to continue. Omit tool output, intermediate steps and anything already resolved.",
+            );
+        }
 
         let conv_state = self.backend_conversation_state(false, true).await;
 
-        // Include everything but the last message in the history.
+        // Include everything but the last messages kept by the strategy in the history.
         let history_len = conv_state.history.len();
-        let history = if history_len < 2 {
+        let history = if history_len <= strategy.keep_last {
             vec![]
         } else {
-            flatten_history(conv_state.history.take(history_len.saturating_sub(1)))
+            flatten_history(conv_state.history.take(history_len - strategy.keep_last))
         };
 
         let mut summary_message = UserInputMessage {
@@ -618,14 +866,16 @@ impl ConversationState {
         }
     }
 
-    pub fn replace_history_with_summary(&mut self, summary: String) {
-        self.history.drain(..(self.history.len().saturating_sub(1)));
+    /// Replaces the history with `summary`, keeping the last `keep_last` messages.
+    pub fn replace_history_with_summary(&mut self, summary: String, keep_last: usize) {
+        self.history
+            .drain(..(self.history.len().saturating_sub(keep_last.max(1))));
         self.latest_summary = Some(summary);
-        // If the last message contains tool results, then we add the results to the content field
-        // instead. This is required to avoid validation errors.
+        // If the first kept message contains tool results, then we add the results to the content
+        // field instead. This is required to avoid validation errors.
         // TODO: this can break since the max user content size is less than the max tool response
         // size! Alternative could be to set the last tool use as part of the context messages.
-        if let Some((user, _)) = self.history.back_mut() {
+        if let Some((user, _)) = self.history.front_mut() {
             if let Some(tool_results) = user.tool_use_results() {
                 let tool_content: Vec<String> = tool_results
                     .iter()
@@ -672,6 +922,39 @@ impl ConversationState {
     ) -> (Option<Vec<(UserMessage, AssistantMessage)>>, Vec<(String, String)>) {
         let mut context_content = String::new();
         let mut dropped_context_files = Vec::new();
+
+        // The instructions of the user for every conversation come first, then the project rules,
+        // before anything they could be overridden by
+        if let Some(context_manager) = &self.context_manager {
+            if let Some(system_prompt) = context_manager
+                .system_prompt(self.default_system_prompt.as_deref())
+                .await
+            {
+                context_content.push_str(CONTEXT_ENTRY_START_HEADER);
+                context_content.push_str("These are the instructions the user gave for all their conversations. YOU MUST follow them unless the user asks otherwise.\n\n");
+                context_content.push_str(&system_prompt.text);
+                context_content.push('\n');
+                context_content.push_str(CONTEXT_ENTRY_END_HEADER);
+            }
+        }
+        if let Some(context_manager) = &self.context_manager {
+            match context_manager.rules().await {
+                Ok(rules) if rules.iter().any(|rule| rule.enabled) => {
+                    context_content.push_str(CONTEXT_ENTRY_START_HEADER);
+                    context_content.push_str("These are the rules of the project the user is working in, from its .amazonq/rules directory. YOU MUST follow them when working on the project.\n\n");
+                    for rule in rules.iter().filter(|rule| rule.enabled) {
+                        let (content, _) = self.redactor.redact(&rule.content, &rule.path);
+                        context_content.push_str(&format!("[{}]\n{}\n", rule.path, content));
+                    }
+                    context_content.push_str(CONTEXT_ENTRY_END_HEADER);
+                },
+                Ok(_) => (),
+                Err(e) => {
+                    warn!("Failed to get project rules: {}", e);
+                },
+            }
+        }
+
         if let Some(summary) = &self.latest_summary {
             context_content.push_str(CONTEXT_ENTRY_START_HEADER);
             context_content.push_str("This summary contains ALL relevant information from our previous conversation including tool uses, results, code analysis, and file operations. YOU MUST reference this information when answering questions and explicitly acknowledge specific details from the summary when they're relevant to the current question.\n\n");
@@ -681,21 +964,33 @@ impl ConversationState {
             context_content.push_str(CONTEXT_ENTRY_END_HEADER);
         }
 
+        // Pinned messages may have been dropped from the history, so they are always repeated
+        if !self.pins.is_empty() {
+            context_content.push_str(CONTEXT_ENTRY_START_HEADER);
+            context_content.push_str("The user pinned these messages from our conversation. They are quoted verbatim and YOU MUST keep following them, even when the rest of the conversation was summarized.\n\n");
+            for pin in &self.pins {
+                context_content.push_str(&format!("PINNED MESSAGE:\n{}\n\n", pin));
+            }
+            context_content.push_str(CONTEXT_ENTRY_END_HEADER);
+        }
+
         // Add context files if available
+        self.context_files = None;
         if let Some(context_manager) = self.context_manager.as_mut() {
-            match context_manager.collect_context_files_with_limit().await {
+            let prompt = self.next_message.as_ref().and_then(UserMessage::prompt);
+            match context_manager.collect_context_files_with_limit(prompt).await {
                 Ok((files_to_use, files_dropped)) => {
-                    if !files_dropped.is_empty() {
-                        dropped_context_files.extend(files_dropped);
-                    }
-
                     if !files_to_use.is_empty() {
                         context_content.push_str(CONTEXT_ENTRY_START_HEADER);
-                        for (filename, content) in files_to_use {
+                        for (filename, content) in &files_to_use {
+                            let (content, _) = self.redactor.redact(content, filename);
                             context_content.push_str(&format!("[{}]\n{}\n", filename, content));
                         }
                         context_content.push_str(CONTEXT_ENTRY_END_HEADER);
                     }
+
+                    dropped_context_files.extend(files_dropped.iter().cloned());
+                    self.context_files = Some(files_to_use.into_iter().chain(files_dropped).collect());
                 },
                 Err(e) => {
                     warn!("Failed to get context files: {}", e);
@@ -703,6 +998,30 @@ impl ConversationState {
             }
         }
 
+        if let (Some(retriever), Some(prompt)) = (
+            &self.index_retriever,
+            self.next_message.as_ref().and_then(UserMessage::prompt),
+        ) {
+            let chunks = retriever.retrieve(prompt);
+            if !chunks.is_empty() {
+                context_content.push_str(CONTEXT_ENTRY_START_HEADER);
+                context_content.push_str("These are the parts of the files of the workspace most relevant to the prompt, retrieved from its index. They can be out of date, read the files for details.\n\n");
+                for chunk in chunks {
+                    let (content, _) = self.redactor.redact(&chunk.text, &chunk.path);
+                    context_content.push_str(&format!("[{}]\n{}\n", chunk.source(), content));
+                }
+                context_content.push_str(CONTEXT_ENTRY_END_HEADER);
+            }
+        }
+
+        if let Some(project_context) = &self.project_context {
+            context_content.push_str(CONTEXT_ENTRY_START_HEADER);
+            context_content.push_str("This is an overview of the project the user started the chat in, detected from its files. It can be out of date, read the files for details.\n\n");
+            context_content.push_str(project_context);
+            context_content.push('\n');
+            context_content.push_str(CONTEXT_ENTRY_END_HEADER);
+        }
+
         if let Some(context) = conversation_start_context {
             context_content.push_str(&context);
         }
@@ -727,6 +1046,15 @@ impl ConversationState {
         self.backend_conversation_state(false, true).await.char_count()
     }
 
+    /// Like [Self::calculate_char_count], along with the next user message about to be sent.
+    pub async fn calculate_next_request_char_count(&mut self) -> CharCount {
+        let state = self.backend_conversation_state(false, true).await;
+        let next_message = state
+            .next_user_message
+            .map_or(CharCount::from(0), |message| message.char_count());
+        state.char_count() + next_message
+    }
+
     /// Get the current token warning level
     pub async fn get_token_warning_level(&mut self) -> TokenWarningLevel {
         let total_chars = self.calculate_char_count().await;
@@ -889,6 +1217,28 @@ impl
     }
 }
 
+/// Which parts of the history are summarized by [ConversationState::create_summary_request], and
+/// how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactStrategy {
+    /// How many of the most recent messages are kept as they are instead of summarized.
+    pub keep_last: usize,
+    /// Whether code from the conversation is copied into the summary verbatim.
+    pub preserve_code: bool,
+    /// Whether the summary should be as short as possible.
+    pub aggressive: bool,
+}
+
+impl Default for CompactStrategy {
+    fn default() -> Self {
+        Self {
+            keep_last: 1,
+            preserve_code: false,
+            aggressive: false,
+        }
+    }
+}
+
 /// Reflects a detailed accounting of the context window utilization for a given conversation.
 #[derive(Debug, Clone, Copy)]
 pub struct ConversationSize {
@@ -897,6 +1247,16 @@ pub struct ConversationSize {
     pub assistant_messages: CharCount,
 }
 
+/// The size of the specification of `tools` sent along with each request.
+pub fn tools_char_count(tools: &HashMap<ToolOrigin, Vec<Tool>>) -> CharCount {
+    tools
+        .values()
+        .flatten()
+        .map(|tool| serde_json::to_string(tool).map_or(0, |json| json.len()))
+        .sum::<usize>()
+        .into()
+}
+
 /// Converts a list of user/assistant message pairs into a flattened list of ChatMessage.
 fn flatten_history<'a, T>(history: T) -> Vec<ChatMessage>
 where
@@ -947,6 +1307,7 @@ fn format_hook_context<'a>(hook_results: impl IntoIterator<Item = &'a (Hook, Str
 mod tests {
     use super::super::context::{
         AMAZONQ_FILENAME,
+        ContextFileChange,
         profile_context_path,
     };
     use super::super::message::AssistantToolUse;
@@ -1074,6 +1435,109 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_conversation_state_truncate_history() {
+        let mut database = Database::new().await.unwrap();
+        let mut output = SharedWriter::null();
+
+        let mut tool_manager = ToolManager::default();
+        let mut conversation_state = ConversationState::new(
+            Context::new(),
+            "fake_conv_id",
+            tool_manager.load_tools(&database, &mut output).await.unwrap(),
+            None,
+            None,
+            tool_manager,
+        )
+        .await;
+
+        for i in 0..3 {
+            conversation_state.set_next_user_message(format!("prompt {i}")).await;
+            conversation_state.as_sendable_conversation_state(true).await;
+            conversation_state
+                .push_assistant_message(AssistantMessage::new_response(None, i.to_string()), &mut database);
+        }
+        assert_eq!(conversation_state.user_prompts(), vec![
+            (0, "prompt 0"),
+            (1, "prompt 1"),
+            (2, "prompt 2")
+        ]);
+
+        let forgotten = conversation_state.forget_history(1..2);
+        assert_eq!(forgotten[0].0.prompt(), Some("prompt 1"));
+        assert_eq!(conversation_state.user_prompts(), vec![
+            (0, "prompt 0"),
+            (1, "prompt 2")
+        ]);
+
+        conversation_state.truncate_history(1);
+        assert_eq!(conversation_state.user_prompts(), vec![(0, "prompt 0")]);
+        assert!(conversation_state.next_user_message().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_conversation_state_compact_keep_last() {
+        let mut database = Database::new().await.unwrap();
+        let mut output = SharedWriter::null();
+
+        let mut tool_manager = ToolManager::default();
+        let mut conversation_state = ConversationState::new(
+            Context::new(),
+            "fake_conv_id",
+            tool_manager.load_tools(&database, &mut output).await.unwrap(),
+            None,
+            None,
+            tool_manager,
+        )
+        .await;
+
+        for i in 0..4 {
+            conversation_state.set_next_user_message(format!("prompt {i}")).await;
+            conversation_state.as_sendable_conversation_state(true).await;
+            conversation_state
+                .push_assistant_message(AssistantMessage::new_response(None, i.to_string()), &mut database);
+        }
+
+        let strategy = CompactStrategy {
+            keep_last: 2,
+            preserve_code: true,
+            aggressive: false,
+        };
+        let request = conversation_state
+            .create_summary_request(None::<String>, strategy)
+            .await;
+        // Two user and assistant messages are summarized.
+        assert_eq!(request.history.unwrap().len(), 4);
+        assert!(request.user_input_message.content.contains("PRESERVE CODE"));
+        assert!(!request.user_input_message.content.contains("BE AGGRESSIVE"));
+
+        conversation_state.replace_history_with_summary("summary".to_string(), strategy.keep_last);
+        assert_eq!(conversation_state.user_prompts(), vec![
+            (0, "prompt 2"),
+            (1, "prompt 3")
+        ]);
+        assert_eq!(conversation_state.latest_summary(), Some("summary"));
+
+        assert!(conversation_state.pin("Never touch the prod database".to_string()));
+        assert!(!conversation_state.pin("Never touch the prod database".to_string()));
+        conversation_state.replace_history_with_summary("summary".to_string(), 1);
+        let state = conversation_state.backend_conversation_state(false, true).await;
+        let (context, _) = &state.context_messages.unwrap()[0];
+        assert!(context.prompt().unwrap().contains("Never touch the prod database"));
+        assert_eq!(
+            conversation_state.unpin(0).as_deref(),
+            Some("Never touch the prod database")
+        );
+        assert_eq!(conversation_state.unpin(0), None);
+
+        let prompt = "next prompt".to_string();
+        conversation_state.set_next_user_message(prompt.clone()).await;
+        assert_eq!(
+            *conversation_state.calculate_next_request_char_count().await,
+            *conversation_state.calculate_char_count().await + prompt.len()
+        );
+    }
+
     #[tokio::test]
     async fn test_conversation_state_history_handling_with_tool_results() {
         let mut database = Database::new().await.unwrap();
@@ -1190,6 +1654,11 @@ mod tests {
             }
 
             assert_conversation_state_invariants(s, i);
+            // The files read for the context message are those noted as sent
+            if i == 0 {
+                let changes = conversation_state.context_manager.as_ref().unwrap().diff_sent_files();
+                assert!(matches!(&changes[..], [ContextFileChange::Added(path)] if path.ends_with(AMAZONQ_FILENAME)));
+            }
 
             conversation_state
                 .push_assistant_message(AssistantMessage::new_response(None, i.to_string()), &mut database);
@@ -0,0 +1,1161 @@
+//! Text extraction from PDFs and Word documents, so that they can be added as context files.
+//!
+//! Only what is needed to get the text out is parsed: for PDFs, the objects of the file and of
+//! its object streams, the page tree, and the text operators of content streams, decoded through
+//! the `ToUnicode` maps of fonts. For Word documents, `word/document.xml` out of the zip archive.
+
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use eyre::{
+    Result,
+    bail,
+    eyre,
+};
+use flate2::read::{
+    DeflateDecoder,
+    ZlibDecoder,
+};
+use regex::Regex;
+use regex::bytes::Regex as BytesRegex;
+
+use super::url_context::decode_entities;
+
+/// The kinds of documents text is extracted from, instead of being read as text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    Pdf,
+    Docx,
+}
+
+impl DocumentKind {
+    /// The kind of the document at `path`, from its extension.
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "pdf" => Some(Self::Pdf),
+            "docx" => Some(Self::Docx),
+            _ => None,
+        }
+    }
+}
+
+/// The pages of a PDF to add, from a `#page=3` or `#page=2-5` suffix of the context rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl PageRange {
+    fn contains(&self, page: usize) -> bool {
+        (self.first..=self.last).contains(&page)
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.first == self.last {
+            true => write!(f, "#page={}", self.first),
+            false => write!(f, "#page={}-{}", self.first, self.last),
+        }
+    }
+}
+
+static PAGE_FRAGMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.+\.(?i:pdf))#page=(\d+)(?:-(\d+))?$").expect("valid regex"));
+
+/// Splits the `#page=...` suffix off a context rule for a PDF. Rules without one are returned
+/// as they are.
+pub fn split_page_range(rule: &str) -> Result<(&str, Option<PageRange>)> {
+    let Some(captures) = PAGE_FRAGMENT.captures(rule) else {
+        return Ok((rule, None));
+    };
+    let first = captures[2].parse::<usize>()?;
+    let last = match captures.get(3) {
+        Some(last) => last.as_str().parse::<usize>()?,
+        None => first,
+    };
+    if first == 0 || last < first {
+        bail!("invalid page range {first}-{last}, pages are numbered from 1");
+    }
+    let path = captures.get(1).map_or(rule, |path| path.as_str());
+    Ok((path, Some(PageRange { first, last })))
+}
+
+/// The text of a document, or of the given pages of a PDF. Each page of a PDF starts with a
+/// `[Page N]` line.
+pub fn extract_text(kind: DocumentKind, bytes: &[u8], pages: Option<PageRange>) -> Result<String> {
+    match kind {
+        DocumentKind::Pdf => {
+            let text = Pdf::parse(bytes)?
+                .page_texts()
+                .into_iter()
+                .enumerate()
+                .map(|(index, text)| (index + 1, text))
+                .filter(|(page, text)| pages.is_none_or(|pages| pages.contains(*page)) && !text.is_empty())
+                .map(|(page, text)| format!("[Page {page}]\n{text}"))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            Ok(text)
+        },
+        DocumentKind::Docx => {
+            if pages.is_some() {
+                bail!("page ranges can only be given for PDFs");
+            }
+            let document = read_zip_entry(bytes, "word/document.xml")?;
+            Ok(docx_text(&String::from_utf8_lossy(&document)))
+        },
+    }
+}
+
+/// A value of a PDF file, or an operator of a content stream.
+#[derive(Debug, Clone, PartialEq)]
+enum Object {
+    Number(f64),
+    String(Vec<u8>),
+    Name(String),
+    Array(Vec<Object>),
+    Dict(HashMap<String, Object>),
+    Ref(u32),
+    Operator(String),
+}
+
+impl Object {
+    fn as_dict(&self) -> Option<&HashMap<String, Object>> {
+        match self {
+            Self::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        match self {
+            Self::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the objects of PDF files and content streams, one token at a time.
+struct Lexer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+enum Token<'a> {
+    Object(Object),
+    Keyword(&'a [u8]),
+    ArrayStart,
+    ArrayEnd,
+    DictStart,
+    DictEnd,
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r' | b'\n' | b'\x0c' | b'\0')
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    matches!(
+        byte,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+impl<'a> Lexer<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&byte) = self.data.get(self.pos) {
+            if is_whitespace(byte) {
+                self.pos += 1;
+            } else if byte == b'%' {
+                while self
+                    .data
+                    .get(self.pos)
+                    .is_some_and(|&byte| byte != b'\n' && byte != b'\r')
+                {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn regular(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|&byte| !is_whitespace(byte) && !is_delimiter(byte))
+        {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn next_token(&mut self) -> Option<Token<'a>> {
+        self.skip_whitespace();
+        let byte = *self.data.get(self.pos)?;
+        let next = self.data.get(self.pos + 1).copied();
+        Some(match byte {
+            b'(' => {
+                self.pos += 1;
+                Token::Object(Object::String(self.literal_string()))
+            },
+            b'<' if next == Some(b'<') => {
+                self.pos += 2;
+                Token::DictStart
+            },
+            b'>' if next == Some(b'>') => {
+                self.pos += 2;
+                Token::DictEnd
+            },
+            b'<' => {
+                self.pos += 1;
+                Token::Object(Object::String(self.hex_string()))
+            },
+            b'[' => {
+                self.pos += 1;
+                Token::ArrayStart
+            },
+            b']' => {
+                self.pos += 1;
+                Token::ArrayEnd
+            },
+            b'/' => {
+                self.pos += 1;
+                let name = String::from_utf8_lossy(self.regular()).into_owned();
+                Token::Object(Object::Name(name))
+            },
+            b')' | b'>' | b'{' | b'}' => {
+                self.pos += 1;
+                Token::Keyword(&self.data[self.pos - 1..self.pos])
+            },
+            _ => {
+                let word = self.regular();
+                let number = std::str::from_utf8(word).ok().and_then(|word| word.parse::<f64>().ok());
+                match number {
+                    Some(number) if !word.is_empty() => Token::Object(Object::Number(number)),
+                    _ => Token::Keyword(word),
+                }
+            },
+        })
+    }
+
+    fn literal_string(&mut self) -> Vec<u8> {
+        let mut string = Vec::new();
+        let mut depth = 0;
+        while let Some(&byte) = self.data.get(self.pos) {
+            self.pos += 1;
+            match byte {
+                b'(' => depth += 1,
+                b')' if depth == 0 => break,
+                b')' => depth -= 1,
+                b'\\' => {
+                    let Some(&escaped) = self.data.get(self.pos) else {
+                        break;
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => string.push(b'\n'),
+                        b'r' => string.push(b'\r'),
+                        b't' => string.push(b'\t'),
+                        b'b' => string.push(b'\x08'),
+                        b'f' => string.push(b'\x0c'),
+                        b'0'..=b'7' => {
+                            let mut code = u32::from(escaped - b'0');
+                            for _ in 0..2 {
+                                match self.data.get(self.pos) {
+                                    Some(&digit @ b'0'..=b'7') => {
+                                        code = code * 8 + u32::from(digit - b'0');
+                                        self.pos += 1;
+                                    },
+                                    _ => break,
+                                }
+                            }
+                            string.push(code as u8);
+                        },
+                        // A backslash at the end of a line continues the string on the next one
+                        b'\r' => {
+                            if self.data.get(self.pos) == Some(&b'\n') {
+                                self.pos += 1;
+                            }
+                        },
+                        b'\n' => {},
+                        _ => string.push(escaped),
+                    }
+                    continue;
+                },
+                _ => {},
+            }
+            string.push(byte);
+        }
+        string
+    }
+
+    fn hex_string(&mut self) -> Vec<u8> {
+        let mut digits = Vec::new();
+        while let Some(&byte) = self.data.get(self.pos) {
+            self.pos += 1;
+            if byte == b'>' {
+                break;
+            }
+            if let Some(digit) = (byte as char).to_digit(16) {
+                digits.push(digit as u8);
+            }
+        }
+        if digits.len() % 2 == 1 {
+            digits.push(0);
+        }
+        digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect()
+    }
+
+    /// The next object, with `n 0 R` references in arrays and dictionaries as [Object::Ref].
+    /// Keywords are returned as [Object::Operator].
+    fn next_object(&mut self) -> Option<Object> {
+        let token = self.next_token()?;
+        self.object_from(token)
+    }
+
+    fn object_from(&mut self, token: Token<'a>) -> Option<Object> {
+        match token {
+            Token::Object(object) => Some(object),
+            Token::Keyword(keyword) => Some(Object::Operator(String::from_utf8_lossy(keyword).into_owned())),
+            Token::ArrayStart => Some(Object::Array(self.items(|token| matches!(token, Token::ArrayEnd)))),
+            Token::DictStart => {
+                let items = self.items(|token| matches!(token, Token::DictEnd));
+                let mut dict = HashMap::new();
+                let mut items = items.into_iter();
+                while let Some(key) = items.next() {
+                    if let (Object::Name(key), Some(value)) = (key, items.next()) {
+                        dict.insert(key, value);
+                    }
+                }
+                Some(Object::Dict(dict))
+            },
+            Token::ArrayEnd | Token::DictEnd => None,
+        }
+    }
+
+    fn items(&mut self, is_end: impl Fn(&Token<'a>) -> bool) -> Vec<Object> {
+        let mut items = Vec::new();
+        while let Some(token) = self.next_token() {
+            if is_end(&token) {
+                break;
+            }
+            match self.object_from(token) {
+                Some(Object::Operator(operator)) if operator == "R" => {
+                    if let [.., Object::Number(number), Object::Number(_)] = items[..] {
+                        items.truncate(items.len() - 2);
+                        items.push(Object::Ref(number as u32));
+                    }
+                },
+                Some(object) => items.push(object),
+                None => break,
+            }
+        }
+        items
+    }
+
+    /// Skips the data of an inline image, after its `ID` operator.
+    fn skip_inline_image(&mut self) {
+        while self.pos + 2 < self.data.len() {
+            let window = &self.data[self.pos..];
+            if is_whitespace(window[0])
+                && window[1..].starts_with(b"EI")
+                && window.get(3).is_none_or(|&byte| is_whitespace(byte))
+            {
+                self.pos += 3;
+                return;
+            }
+            self.pos += 1;
+        }
+        self.pos = self.data.len();
+    }
+}
+
+static OBJECT_START: LazyLock<BytesRegex> =
+    LazyLock::new(|| BytesRegex::new(r"(?-u)(\d+)\s+\d+\s+obj\b").expect("valid regex"));
+
+/// An object of a PDF file, along with its stream when it has one.
+struct PdfObject<'a> {
+    value: Object,
+    stream: Option<&'a [u8]>,
+}
+
+/// The font a string is shown with: how many bytes each character code takes, and the text of
+/// each code.
+#[derive(Debug, Default, Clone)]
+struct Font {
+    code_bytes: usize,
+    to_unicode: HashMap<u32, String>,
+}
+
+impl PdfObject<'_> {
+    /// The data of the stream, decompressed. [None] for filters other than `FlateDecode`, those
+    /// being for images.
+    fn decoded_stream(&self) -> Option<Vec<u8>> {
+        let stream = self.stream?;
+        let filters = match self.value.as_dict()?.get("Filter") {
+            None => Vec::new(),
+            Some(Object::Name(filter)) => vec![filter.as_str()],
+            Some(Object::Array(filters)) => filters.iter().filter_map(Object::as_name).collect(),
+            Some(_) => return None,
+        };
+        let mut data = stream.to_vec();
+        for filter in filters {
+            data = match filter {
+                "FlateDecode" | "Fl" => inflate(ZlibDecoder::new(&data[..]))?,
+                _ => return None,
+            };
+        }
+        Some(data)
+    }
+}
+
+struct Pdf<'a> {
+    objects: HashMap<u32, PdfObject<'a>>,
+}
+
+/// How many levels of the page tree are followed, against loops in malformed files.
+const PAGE_TREE_MAX_DEPTH: usize = 32;
+
+impl<'a> Pdf<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self> {
+        if !bytes.starts_with(b"%PDF-") {
+            bail!("not a PDF file");
+        }
+        let mut objects = HashMap::new();
+        for captures in OBJECT_START.captures_iter(bytes) {
+            let Some(number) = std::str::from_utf8(&captures[1]).ok().and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            let mut lexer = Lexer::new(bytes, captures.get(0).map_or(0, |m| m.end()));
+            // Objects left empty aren't kept, so that those of object streams can be used
+            let Some(value) = lexer
+                .next_object()
+                .filter(|value| !matches!(value, Object::Operator(_)))
+            else {
+                continue;
+            };
+            let stream = stream_data(bytes, &mut lexer, &value);
+            // The objects of incremental updates come later in the file and replace earlier ones
+            objects.insert(number, PdfObject { value, stream });
+        }
+        let mut pdf = Self { objects };
+        if pdf
+            .objects
+            .values()
+            .any(|object| object.value.as_dict().is_some_and(|dict| dict.contains_key("Encrypt")))
+        {
+            bail!("encrypted PDFs aren't supported");
+        }
+        pdf.parse_object_streams();
+        Ok(pdf)
+    }
+
+    /// Adds the objects stored in object streams, which most recent PDFs keep their pages and
+    /// fonts in.
+    fn parse_object_streams(&mut self) {
+        let mut stored = Vec::new();
+        for object in self.objects.values() {
+            let Some(dict) = object.value.as_dict() else {
+                continue;
+            };
+            if dict.get("Type").and_then(Object::as_name) != Some("ObjStm") {
+                continue;
+            }
+            let (Some(count), Some(first)) = (
+                dict.get("N").and_then(Object::as_number),
+                dict.get("First").and_then(Object::as_number),
+            ) else {
+                continue;
+            };
+            let Some(data) = object.decoded_stream() else {
+                continue;
+            };
+            let first = first as usize;
+            let mut header = Lexer::new(&data[..first.min(data.len())], 0);
+            for _ in 0..count as usize {
+                let (Some(Object::Number(number)), Some(Object::Number(offset))) =
+                    (header.next_object(), header.next_object())
+                else {
+                    break;
+                };
+                if let Some(value) = Lexer::new(&data, first + offset as usize).next_object() {
+                    stored.push((number as u32, value));
+                }
+            }
+        }
+        for (number, value) in stored {
+            self.objects.entry(number).or_insert(PdfObject { value, stream: None });
+        }
+    }
+
+    fn get<'s>(&'s self, object: &'s Object) -> Option<&'s Object> {
+        match object {
+            Object::Ref(number) => self.objects.get(number).map(|object| &object.value),
+            object => Some(object),
+        }
+    }
+
+    fn get_dict<'s>(&'s self, object: Option<&'s Object>) -> Option<&'s HashMap<String, Object>> {
+        match object? {
+            Object::Ref(number) => self.objects.get(number)?.value.as_dict(),
+            object => object.as_dict(),
+        }
+    }
+
+    /// The text of each page, in the order of the page tree.
+    fn page_texts(&self) -> Vec<String> {
+        let mut pages = Vec::new();
+        let root = self
+            .objects
+            .values()
+            .find_map(|object| {
+                let dict = object.value.as_dict()?;
+                (dict.get("Type").and_then(Object::as_name) == Some("Catalog")).then(|| dict.get("Pages"))?
+            })
+            .and_then(|pages| match pages {
+                Object::Ref(number) => Some(*number),
+                _ => None,
+            });
+        let mut visited = HashSet::new();
+        if let Some(root) = root {
+            self.collect_pages(root, None, 0, &mut visited, &mut pages);
+        }
+        // Without a page tree, e.g. in a damaged file, pages are taken in the order of their objects
+        if pages.is_empty() {
+            let mut numbers = self
+                .objects
+                .iter()
+                .filter(|(_, object)| {
+                    object
+                        .value
+                        .as_dict()
+                        .and_then(|dict| dict.get("Type"))
+                        .and_then(Object::as_name)
+                        == Some("Page")
+                })
+                .map(|(number, _)| *number)
+                .collect::<Vec<_>>();
+            numbers.sort_unstable();
+            for number in numbers {
+                self.collect_pages(number, None, 0, &mut visited, &mut pages);
+            }
+        }
+
+        let mut fonts = HashMap::new();
+        pages
+            .into_iter()
+            .map(|(page, resources)| self.page_text(page, resources, &mut fonts))
+            .collect()
+    }
+
+    /// Adds the pages under `node` of the page tree, along with the resources they inherit.
+    fn collect_pages<'s>(
+        &'s self,
+        node: u32,
+        resources: Option<&'s Object>,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        pages: &mut Vec<(&'s HashMap<String, Object>, Option<&'s Object>)>,
+    ) {
+        if depth > PAGE_TREE_MAX_DEPTH || !visited.insert(node) {
+            return;
+        }
+        let Some(dict) = self.objects.get(&node).and_then(|object| object.value.as_dict()) else {
+            return;
+        };
+        let resources = dict.get("Resources").or(resources);
+        match dict.get("Kids") {
+            Some(Object::Array(kids)) => {
+                for kid in kids {
+                    if let Object::Ref(kid) = kid {
+                        self.collect_pages(*kid, resources, depth + 1, visited, pages);
+                    }
+                }
+            },
+            _ => pages.push((dict, resources)),
+        }
+    }
+
+    fn page_text(
+        &self,
+        page: &HashMap<String, Object>,
+        resources: Option<&Object>,
+        fonts: &mut HashMap<u32, Font>,
+    ) -> String {
+        let contents = match page.get("Contents") {
+            Some(Object::Array(contents)) => contents.iter().collect(),
+            Some(contents) => vec![contents],
+            None => Vec::new(),
+        };
+        let mut content = Vec::new();
+        for stream in contents {
+            let Object::Ref(number) = stream else {
+                continue;
+            };
+            if let Some(data) = self.objects.get(number).and_then(PdfObject::decoded_stream) {
+                content.extend(data);
+                content.push(b'\n');
+            }
+        }
+
+        let page_fonts = self
+            .get_dict(self.get_dict(resources).and_then(|resources| resources.get("Font")))
+            .map(|page_fonts| {
+                page_fonts
+                    .iter()
+                    .map(|(name, font)| {
+                        let font = match font {
+                            Object::Ref(number) => fonts.entry(*number).or_insert_with(|| self.font(font)).clone(),
+                            font => self.font(font),
+                        };
+                        (name.clone(), font)
+                    })
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+        content_text(&content, &page_fonts)
+    }
+
+    fn font(&self, font: &Object) -> Font {
+        let Some(dict) = self.get(font).and_then(Object::as_dict) else {
+            return Font::default();
+        };
+        let code_bytes = match dict.get("Subtype").and_then(Object::as_name) {
+            Some("Type0") => 2,
+            _ => 1,
+        };
+        let mut font = Font {
+            code_bytes,
+            to_unicode: HashMap::new(),
+        };
+        if let Some(Object::Ref(number)) = dict.get("ToUnicode") {
+            if let Some(cmap) = self.objects.get(number).and_then(PdfObject::decoded_stream) {
+                parse_to_unicode(&cmap, &mut font);
+            }
+        }
+        font
+    }
+}
+
+/// The data of the stream of an object, after its dictionary.
+fn stream_data<'a>(bytes: &'a [u8], lexer: &mut Lexer<'a>, value: &Object) -> Option<&'a [u8]> {
+    lexer.skip_whitespace();
+    if !bytes[lexer.pos..].starts_with(b"stream") {
+        return None;
+    }
+    let mut start = lexer.pos + "stream".len();
+    if bytes[start..].starts_with(b"\r\n") {
+        start += 2;
+    } else if bytes[start..].starts_with(b"\n") || bytes[start..].starts_with(b"\r") {
+        start += 1;
+    }
+
+    // The length given by the dictionary is trusted when the stream does end there
+    if let Some(length) = value
+        .as_dict()
+        .and_then(|dict| dict.get("Length"))
+        .and_then(Object::as_number)
+    {
+        let end = start + length as usize;
+        if let Some(after) = bytes.get(end..) {
+            let after = &after[after.iter().take_while(|&&byte| is_whitespace(byte)).count()..];
+            if after.starts_with(b"endstream") {
+                return Some(&bytes[start..end]);
+            }
+        }
+    }
+    let end = start + find(&bytes[start..], b"endstream")?;
+    let data = &bytes[start..end];
+    Some(data.strip_suffix(b"\r\n").or(data.strip_suffix(b"\n")).unwrap_or(data))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Everything `decoder` can decode, even when the data is cut short.
+fn inflate(mut decoder: impl Read) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    match decoder.read_to_end(&mut data) {
+        Ok(_) => Some(data),
+        Err(_) if !data.is_empty() => Some(data),
+        Err(_) => None,
+    }
+}
+
+fn parse_to_unicode(cmap: &[u8], font: &mut Font) {
+    let mut lexer = Lexer::new(cmap, 0);
+    let mut operands = Vec::new();
+    while let Some(object) = lexer.next_object() {
+        let Object::Operator(operator) = object else {
+            operands.push(object);
+            continue;
+        };
+        match operator.as_str() {
+            "endcodespacerange" => {
+                if let Some(Object::String(low)) = operands.first() {
+                    font.code_bytes = low.len().clamp(1, 4);
+                }
+            },
+            "endbfchar" => {
+                for pair in operands.chunks(2) {
+                    if let [Object::String(code), Object::String(text)] = pair {
+                        font.to_unicode.insert(code_of(code), utf16_text(text));
+                    }
+                }
+            },
+            "endbfrange" => {
+                for range in operands.chunks(3) {
+                    let [Object::String(low), Object::String(high), destination] = range else {
+                        continue;
+                    };
+                    let (low, high) = (code_of(low), code_of(high));
+                    // Ranges are at most one byte wide, the last byte of the codes changing
+                    for (offset, code) in (low..=high.min(low.saturating_add(0xff))).enumerate() {
+                        let text = match destination {
+                            Object::String(first) => {
+                                let mut units = utf16_units(first);
+                                if let Some(last) = units.last_mut() {
+                                    *last = last.wrapping_add(offset as u16);
+                                }
+                                String::from_utf16_lossy(&units)
+                            },
+                            Object::Array(texts) => match texts.get(offset) {
+                                Some(Object::String(text)) => utf16_text(text),
+                                _ => continue,
+                            },
+                            _ => continue,
+                        };
+                        font.to_unicode.insert(code, text);
+                    }
+                }
+            },
+            _ => {},
+        }
+        if operator.starts_with("end") || operator.starts_with("begin") {
+            operands.clear();
+        }
+    }
+}
+
+fn code_of(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |code, &byte| code << 8 | u32::from(byte))
+}
+
+fn utf16_units(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks(2)
+        .map(|pair| u16::from(pair[0]) << 8 | u16::from(pair.get(1).copied().unwrap_or(0)))
+        .collect()
+}
+
+fn utf16_text(bytes: &[u8]) -> String {
+    String::from_utf16_lossy(&utf16_units(bytes))
+}
+
+/// The text of a string shown with `font`. Fonts without a `ToUnicode` map are read as
+/// WinAnsiEncoding, the most common encoding of simple fonts.
+fn decode_string(bytes: &[u8], font: Option<&Font>, text: &mut String) {
+    let code_bytes = font.map_or(1, |font| font.code_bytes.max(1));
+    for code in bytes.chunks(code_bytes) {
+        let code = code_of(code);
+        if let Some(mapped) = font.and_then(|font| font.to_unicode.get(&code)) {
+            text.push_str(mapped);
+        } else if code_bytes == 1 {
+            if let Some(char) = win_ansi_char(code as u8) {
+                text.push(char);
+            }
+        }
+    }
+}
+
+fn win_ansi_char(byte: u8) -> Option<char> {
+    Some(match byte {
+        b'\t' | b'\n' | b'\r' => ' ',
+        0x00..=0x1f | 0x7f => return None,
+        0x80 => '€',
+        0x85 => '…',
+        0x91 => '‘',
+        0x92 => '’',
+        0x93 => '“',
+        0x94 => '”',
+        0x95 => '•',
+        0x96 => '–',
+        0x97 => '—',
+        0x99 => '™',
+        0x81..=0x9f => return None,
+        byte => char::from(byte),
+    })
+}
+
+/// In thousandths of the font size, gaps in `TJ` arrays larger than this are read as spaces.
+const TJ_SPACE_THRESHOLD: f64 = 180.0;
+
+/// The text shown by a content stream, a line for each line of text on the page.
+fn content_text(content: &[u8], fonts: &HashMap<String, Font>) -> String {
+    let mut lexer = Lexer::new(content, 0);
+    let mut operands = Vec::new();
+    let mut font = None;
+    let mut line_y = None;
+    let mut text = String::new();
+    let new_line = |text: &mut String| {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+    };
+
+    while let Some(object) = lexer.next_object() {
+        let Object::Operator(operator) = object else {
+            operands.push(object);
+            continue;
+        };
+        match operator.as_str() {
+            "Tf" => {
+                if let [.., Object::Name(name), _] = &operands[..] {
+                    font = fonts.get(name);
+                }
+            },
+            "Td" | "TD" => {
+                if let [.., Object::Number(_), Object::Number(y)] = operands[..] {
+                    if y.abs() > f64::EPSILON {
+                        new_line(&mut text);
+                    } else if !text.ends_with([' ', '\n']) && !text.is_empty() {
+                        text.push(' ');
+                    }
+                }
+            },
+            "Tm" => {
+                if let [.., Object::Number(y)] = operands[..] {
+                    if line_y.is_some_and(|line_y: f64| (line_y - y).abs() > f64::EPSILON) {
+                        new_line(&mut text);
+                    }
+                    line_y = Some(y);
+                }
+            },
+            "T*" => new_line(&mut text),
+            "Tj" | "'" | "\"" => {
+                if operator != "Tj" {
+                    new_line(&mut text);
+                }
+                if let Some(Object::String(string)) = operands.last() {
+                    decode_string(string, font, &mut text);
+                }
+            },
+            "TJ" => {
+                if let Some(Object::Array(items)) = operands.last() {
+                    for item in items {
+                        match item {
+                            Object::String(string) => decode_string(string, font, &mut text),
+                            Object::Number(gap) if -gap > TJ_SPACE_THRESHOLD && !text.ends_with(' ') => {
+                                text.push(' ');
+                            },
+                            _ => {},
+                        }
+                    }
+                }
+            },
+            "ET" => line_y = None,
+            "ID" => lexer.skip_inline_image(),
+            _ => {},
+        }
+        operands.clear();
+    }
+
+    text.lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The data of the file `name` in the zip archive `bytes`, from the central directory at the
+/// end of the archive.
+fn read_zip_entry(bytes: &[u8], name: &str) -> Result<Vec<u8>> {
+    const END_SIGNATURE: &[u8] = b"PK\x05\x06";
+    const ENTRY_SIGNATURE: &[u8] = b"PK\x01\x02";
+    let u16_at = |offset: usize| -> Result<usize> {
+        let bytes = bytes
+            .get(offset..offset + 2)
+            .ok_or_else(|| eyre!("truncated zip archive"))?;
+        Ok(usize::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+    };
+    let u32_at = |offset: usize| -> Result<usize> {
+        let bytes = bytes
+            .get(offset..offset + 4)
+            .ok_or_else(|| eyre!("truncated zip archive"))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+
+    let Some(end) = bytes.windows(4).rposition(|window| window == END_SIGNATURE) else {
+        bail!("not a Word document");
+    };
+    let entries = u16_at(end + 10)?;
+    let mut offset = u32_at(end + 16)?;
+    for _ in 0..entries {
+        if bytes.get(offset..offset + 4) != Some(ENTRY_SIGNATURE) {
+            bail!("invalid zip archive");
+        }
+        let method = u16_at(offset + 10)?;
+        let compressed_size = u32_at(offset + 20)?;
+        let name_len = u16_at(offset + 28)?;
+        let extra_len = u16_at(offset + 30)?;
+        let comment_len = u16_at(offset + 32)?;
+        let local_offset = u32_at(offset + 42)?;
+        let entry_name = bytes.get(offset + 46..offset + 46 + name_len).unwrap_or_default();
+        offset += 46 + name_len + extra_len + comment_len;
+        if entry_name != name.as_bytes() {
+            continue;
+        }
+
+        let start = local_offset + 30 + u16_at(local_offset + 26)? + u16_at(local_offset + 28)?;
+        let data = bytes
+            .get(start..start + compressed_size)
+            .ok_or_else(|| eyre!("truncated zip archive"))?;
+        return match method {
+            0 => Ok(data.to_vec()),
+            8 => inflate(DeflateDecoder::new(data)).ok_or_else(|| eyre!("invalid compressed data in {name}")),
+            _ => bail!("unsupported compression of {name}"),
+        };
+    }
+    bail!("not a Word document, {name} is missing")
+}
+
+static DOCX_PARAGRAPH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<w:p[ >].*?</w:p>|<w:p/>").expect("valid regex"));
+static DOCX_RUN_TEXT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<w:t(?: [^>]*)?>(.*?)</w:t>|<w:(tab|br|cr)\b[^>]*/>").expect("valid regex"));
+static DOCX_HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<w:pStyle w:val="(?:Heading|heading )?([1-6]|Title)""#).expect("valid regex"));
+static DOCX_BLANK_LINES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\n{3,}").expect("valid regex"));
+
+/// The text of `word/document.xml`, a line for each paragraph. Headings and list items are
+/// written as in markdown, and tables a row per line with their cells separated by `|`.
+fn docx_text(document: &str) -> String {
+    let mut lines = Vec::new();
+    let mut row = Vec::new();
+    for paragraph in DOCX_PARAGRAPH.find_iter(document) {
+        let mut line = String::new();
+        for run in DOCX_RUN_TEXT.captures_iter(paragraph.as_str()) {
+            match (run.get(1), run.get(2).map(|tag| tag.as_str())) {
+                (Some(text), _) => line.push_str(&decode_entities(text.as_str())),
+                (None, Some("tab")) => line.push('\t'),
+                (None, _) => line.push('\n'),
+            }
+        }
+
+        // The last paragraph of each cell ends it
+        let rest = document[paragraph.end()..].trim_start();
+        if let Some(rest) = rest.strip_prefix("</w:tc>") {
+            row.push(line.trim().to_string());
+            if rest.trim_start().starts_with("</w:tr>") {
+                lines.push(format!("| {} |", row.join(" | ")));
+                row.clear();
+            }
+            continue;
+        }
+
+        let prefix = match DOCX_HEADING.captures(paragraph.as_str()) {
+            Some(level) => format!("{} ", "#".repeat(level[1].parse().unwrap_or(1))),
+            None if paragraph.as_str().contains("<w:numPr>") => "- ".to_string(),
+            None => String::new(),
+        };
+        lines.push(format!("{prefix}{}", line.trim_end()));
+    }
+    DOCX_BLANK_LINES
+        .replace_all(lines.join("\n").trim(), "\n\n")
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::{
+        DeflateEncoder,
+        ZlibEncoder,
+    };
+
+    use super::*;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A PDF with the given objects, numbered from 1. The catalog is expected to be the first.
+    fn pdf(objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.7\n".to_vec();
+        for (index, object) in objects.iter().enumerate() {
+            pdf.extend(format!("{} 0 obj\n", index + 1).as_bytes());
+            pdf.extend(object);
+            pdf.extend(b"\nendobj\n");
+        }
+        pdf.extend(b"trailer\n<< /Root 1 0 R >>\n%%EOF\n");
+        pdf
+    }
+
+    fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+        let mut object = format!("<< {dict} /Length {} >>\nstream\n", data.len()).into_bytes();
+        object.extend(data);
+        object.extend(b"\nendstream");
+        object
+    }
+
+    #[test]
+    fn test_split_page_range() {
+        assert_eq!(split_page_range("docs/design.pdf").unwrap(), ("docs/design.pdf", None));
+        assert_eq!(
+            split_page_range("design.PDF#page=3").unwrap(),
+            ("design.PDF", Some(PageRange { first: 3, last: 3 }))
+        );
+        assert_eq!(
+            split_page_range("design.pdf#page=2-5").unwrap(),
+            ("design.pdf", Some(PageRange { first: 2, last: 5 }))
+        );
+        assert_eq!(split_page_range("notes.md#page=2").unwrap(), ("notes.md#page=2", None));
+        assert!(split_page_range("design.pdf#page=5-2").is_err());
+        assert!(split_page_range("design.pdf#page=0").is_err());
+        assert_eq!(PageRange { first: 2, last: 5 }.to_string(), "#page=2-5");
+    }
+
+    #[test]
+    fn test_pdf_text() {
+        let first_page = b"BT /F1 12 Tf 72 720 Td (Design \\(draft\\)) Tj 0 -14 Td [(Go)-20(als)-300(first)] TJ ET\n";
+        let cmap = b"/CIDInit /ProcSet findresource begin\n1 begincodespacerange <0000> <FFFF> endcodespacerange\n2 beginbfchar <0001> <0048> <0002> <0069> endbfchar\n1 beginbfrange <0010> <0012> <0061> endbfrange\nendcmap\n";
+        let second_page = b"BT /F2 10 Tf 1 0 0 1 72 700 Tm <00010002> Tj 1 0 0 1 72 680 Tm <001000110012> Tj ET";
+        let bytes = pdf(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R 5 0 R] /Count 3 /Resources << /Font << /F1 8 0 R /F2 9 0 R >> >> >>"
+                .to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Contents 6 0 R >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Contents [7 0 R] >>".to_vec(),
+            stream("", first_page),
+            stream("/Filter /FlateDecode", &zlib(second_page)),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_vec(),
+            b"<< /Type /Font /Subtype /Type0 /ToUnicode 10 0 R >>".to_vec(),
+            stream("/Filter [/FlateDecode]", &zlib(cmap)),
+        ]);
+
+        assert_eq!(
+            extract_text(DocumentKind::Pdf, &bytes, None).unwrap(),
+            "[Page 1]\nDesign (draft)\nGoals first\n\n[Page 3]\nHi\nabc"
+        );
+        assert_eq!(
+            extract_text(DocumentKind::Pdf, &bytes, Some(PageRange { first: 2, last: 3 })).unwrap(),
+            "[Page 3]\nHi\nabc"
+        );
+        assert!(extract_text(DocumentKind::Pdf, b"PK\x03\x04", None).is_err());
+    }
+
+    #[test]
+    fn test_pdf_object_streams() {
+        let pages = "<< /Type /Pages /Kids [4 0 R] /Count 1 >> ";
+        let header = format!("3 0 4 {} ", pages.len());
+        let stored = format!("{header}{pages}<< /Type /Page /Contents 5 0 R >>");
+        let bytes = pdf(&[
+            b"<< /Type /Catalog /Pages 3 0 R >>".to_vec(),
+            stream(
+                &format!("/Type /ObjStm /N 2 /First {} /Filter /FlateDecode", header.len()),
+                &zlib(stored.as_bytes()),
+            ),
+            Vec::new(),
+            Vec::new(),
+            stream("", b"BT (Stored) Tj ET"),
+        ]);
+        assert_eq!(
+            extract_text(DocumentKind::Pdf, &bytes, None).unwrap(),
+            "[Page 1]\nStored"
+        );
+    }
+
+    /// A zip archive with the given files, the first stored and the others compressed.
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        for (index, (name, contents)) in files.iter().enumerate() {
+            let (method, data) = match index {
+                0 => (0u16, contents.as_bytes().to_vec()),
+                _ => {
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(contents.as_bytes()).unwrap();
+                    (8, encoder.finish().unwrap())
+                },
+            };
+            let offset = archive.len() as u32;
+            archive.extend(b"PK\x03\x04\x14\0\0\0");
+            archive.extend(method.to_le_bytes());
+            archive.extend([0; 8]);
+            archive.extend((data.len() as u32).to_le_bytes());
+            archive.extend((contents.len() as u32).to_le_bytes());
+            archive.extend((name.len() as u16).to_le_bytes());
+            archive.extend([0; 2]);
+            archive.extend(name.as_bytes());
+            archive.extend(&data);
+
+            directory.extend(b"PK\x01\x02\x14\0\x14\0\0\0");
+            directory.extend(method.to_le_bytes());
+            directory.extend([0; 8]);
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend((contents.len() as u32).to_le_bytes());
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let directory_offset = archive.len() as u32;
+        archive.extend(&directory);
+        archive.extend(b"PK\x05\x06\0\0\0\0");
+        archive.extend((files.len() as u16).to_le_bytes());
+        archive.extend((files.len() as u16).to_le_bytes());
+        archive.extend((directory.len() as u32).to_le_bytes());
+        archive.extend(directory_offset.to_le_bytes());
+        archive.extend([0; 2]);
+        archive
+    }
+
+    #[test]
+    fn test_docx_text() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+<w:p><w:pPr><w:pStyle w:val="Title"/></w:pPr><w:r><w:t>Sync design</w:t></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Goals</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Keep sessions </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t>in sync</w:t></w:r><w:r><w:t xml:space="preserve"> &amp; fast.</w:t></w:r></w:p>
+<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>S3</w:t></w:r></w:p>
+<w:p/>
+<w:tbl><w:tr><w:tc><w:p><w:r><w:t>Backend</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Auth</w:t></w:r></w:p></w:tc></w:tr>
+<w:tr><w:tc><w:p><w:r><w:t>WebDAV</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Basic</w:t></w:r><w:r><w:tab/><w:t>or none</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+</w:body></w:document>"#;
+        let bytes = zip(&[("[Content_Types].xml", "<Types/>"), ("word/document.xml", document)]);
+        assert_eq!(
+            extract_text(DocumentKind::Docx, &bytes, None).unwrap(),
+            "# Sync design\n## Goals\nKeep sessions in sync & fast.\n- S3\n\n| Backend | Auth |\n| WebDAV | Basic\tor none |"
+        );
+        assert!(extract_text(DocumentKind::Docx, &zip(&[("other.xml", "")]), None).is_err());
+        assert!(extract_text(DocumentKind::Docx, &bytes, Some(PageRange { first: 1, last: 1 })).is_err());
+    }
+}
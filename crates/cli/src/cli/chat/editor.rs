@@ -0,0 +1,1446 @@
+use std::collections::HashSet;
+use std::hash::{
+    DefaultHasher,
+    Hash,
+    Hasher,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::Command as ProcessCommand;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+};
+use std::{
+    env,
+    fs,
+};
+
+use crossterm::execute;
+use crossterm::style::{
+    self,
+    Color,
+};
+use rustyline::{
+    Cmd,
+    ConditionalEventHandler,
+    Event,
+    EventContext,
+    Movement,
+    RepeatCount,
+};
+use tracing::warn;
+
+use super::ChatError;
+use super::util::truncate_safe;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::diagnostics::EditorDiagnostic;
+use crate::util::directories;
+use crate::util::process::{
+    self,
+    Pid,
+};
+
+#[cfg(unix)]
+const DEFAULT_EDITOR: &str = "vi";
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+
+const SCRATCH_PREFIX: &str = "scratch-";
+const SCRATCH_EXTENSION: &str = "md";
+
+/// Editors that exit faster than this without touching the file are assumed to have handed the file
+/// off to a GUI window (e.g. `code` without `--wait`), and the file is watched instead.
+const DETACHED_EDITOR_THRESHOLD: Duration = Duration::from_millis(500);
+/// How often the scratch file is checked for changes in watch mode.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the file has to stay unchanged after a save before it's submitted in watch mode.
+const DEFAULT_WATCH_GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Gives up on a detached editor that never saves, unless Enter or Ctrl+C stopped the wait before.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Written above the response opened by [EditorLauncher::quote_last_response].
+const QUOTE_HEADER: &str =
+    "<!-- Delete everything you don't want to quote, what's left is quoted in your next prompt -->\n\n";
+
+/// Opens the read-only block quoting the previous exchange at the top of the scratch file.
+const HISTORY_HEADER: &str = "<!-- Previous exchange, for reference only. This comment is removed before sending.";
+/// Longest message quoted in the history block, in bytes.
+const MAX_HISTORY_MESSAGE_LEN: usize = 4000;
+
+/// Written to the scratch file when `chat.editMode.template` is `true`. Its comments and the
+/// sections left empty are stripped before the prompt is submitted.
+const DEFAULT_TEMPLATE: &str = "\
+<!-- The comments of this template and its empty sections are removed before the prompt is sent -->
+## Goal
+<!-- What should Q help you with? -->
+
+## Constraints
+<!-- Libraries, style or behavior to keep in mind -->
+
+## Files to consider
+<!-- Paths, modules or symbols relevant to the task -->
+";
+
+/// What the user did with the buffer opened by [EditorLauncher::launch_system_editor].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditorOutput {
+    /// The buffer was saved, with its normalized content.
+    Edited(String),
+    /// The editor was closed without saving, e.g. with `:q!`.
+    Cancelled,
+}
+
+/// Where [EditorCommand] was resolved from, in order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorSource {
+    Flag,
+    Setting,
+    Visual,
+    Editor,
+    Default,
+}
+
+impl std::fmt::Display for EditorSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditorSource::Flag => write!(f, "--editor"),
+            EditorSource::Setting => write!(f, "{}", Setting::ChatEditModeEditor.as_ref()),
+            EditorSource::Visual => write!(f, "$VISUAL"),
+            EditorSource::Editor => write!(f, "$EDITOR"),
+            EditorSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// The editor command line, e.g. `code --wait`, and where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditorCommand {
+    pub command: String,
+    pub source: EditorSource,
+}
+
+impl EditorCommand {
+    /// Resolves the editor as `--editor` > `chat.editMode.editor` > `$VISUAL` > `$EDITOR` >
+    /// platform default.
+    pub fn resolve(flag: Option<&str>, settings: &Settings) -> Self {
+        Self::from_candidates([
+            (flag.map(str::to_owned), EditorSource::Flag),
+            (settings.get_string(Setting::ChatEditModeEditor), EditorSource::Setting),
+            (env::var("VISUAL").ok(), EditorSource::Visual),
+            (env::var("EDITOR").ok(), EditorSource::Editor),
+        ])
+    }
+
+    /// Picks the first candidate that isn't blank.
+    fn from_candidates(candidates: impl IntoIterator<Item = (Option<String>, EditorSource)>) -> Self {
+        candidates
+            .into_iter()
+            .find_map(|(command, source)| {
+                command
+                    .filter(|command| !command.trim().is_empty())
+                    .map(|command| Self { command, source })
+            })
+            .unwrap_or_else(|| Self {
+                command: DEFAULT_EDITOR.to_owned(),
+                source: EditorSource::Default,
+            })
+    }
+
+    /// Describes the command as it would be run, for diagnostics.
+    pub fn diagnostic(&self) -> EditorDiagnostic {
+        let path = Path::new("<file>");
+        let parsed = editor_command(&self.command, path).map(|cmd| {
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        });
+
+        EditorDiagnostic {
+            command: self.command.clone(),
+            source: self.source.to_string(),
+            parsed: parsed.as_ref().ok().cloned(),
+            error: parsed.err().map(|err| err.to_string()),
+        }
+    }
+}
+
+impl Default for EditorCommand {
+    fn default() -> Self {
+        Self::from_candidates([
+            (env::var("VISUAL").ok(), EditorSource::Visual),
+            (env::var("EDITOR").ok(), EditorSource::Editor),
+        ])
+    }
+}
+
+/// The extension of the file opened in the editor, see `chat.editMode.fileExtension`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScratchExtension {
+    /// Always use this extension, e.g. `txt`.
+    Fixed(String),
+    /// Use `md`, unless the prompt is a single fenced code block in a known language. The code is
+    /// then opened without its fence in a file with that language's extension.
+    Auto,
+}
+
+/// Modification time and content hash of the scratch file, used to tell whether the editor wrote
+/// to it at all.
+#[derive(Debug, PartialEq, Eq)]
+struct ScratchSnapshot {
+    modified: Option<SystemTime>,
+    hash: u64,
+}
+
+/// The last exchange of the conversation, kept up to date by the chat loop.
+#[derive(Debug, Default)]
+struct ConversationTail {
+    user: Option<String>,
+    assistant: Option<String>,
+}
+
+/// Opens the user's preferred editor, shared by the `/editor` command and the Ctrl+F keybinding.
+///
+/// Every launch within a chat session reuses the same scratch file so that the editor keeps its
+/// undo history, and so that an unsent draft survives a crash and can be recovered on restart.
+#[derive(Debug, Clone)]
+pub struct EditorLauncher {
+    scratch_path: PathBuf,
+    /// Written to the scratch file whenever a new prompt is started.
+    template: Option<String>,
+    /// Input with more lines than this is handed to the editor instead of being submitted.
+    auto_open_lines: Option<usize>,
+    /// Shared by the clones of the launcher.
+    conversation_tail: Arc<Mutex<ConversationTail>>,
+    /// Whether the last exchange is quoted as a comment at the top of the buffer.
+    include_history: bool,
+    /// How long a detached GUI editor has to stop saving before its content is used.
+    watch_grace_period: Duration,
+    editor: EditorCommand,
+    /// Whether prompts made of a single code block are opened as a source file.
+    detect_code: bool,
+}
+
+impl EditorLauncher {
+    /// The launcher of the session `session_id`, whose scratch file is kept in `scratch_dir`
+    /// along with those of the other sessions.
+    pub fn new(session_id: &str, scratch_dir: &Path) -> Self {
+        // The process is in the name, for drafts to only be recovered from sessions that ended
+        let file_name = format!(
+            "{SCRATCH_PREFIX}{session_id}-{}.{SCRATCH_EXTENSION}",
+            std::process::id()
+        );
+        Self::with_scratch_path(scratch_dir.join(file_name))
+    }
+
+    /// Where the scratch files are kept, the state directory or else the temp dir.
+    pub fn default_scratch_dir() -> PathBuf {
+        directories::chat_scratch_dir().unwrap_or_else(|err| {
+            warn!(
+                ?err,
+                "Failed to resolve the state directory, falling back to the temp dir"
+            );
+            env::temp_dir().join("amazon-q")
+        })
+    }
+
+    pub fn with_scratch_path(scratch_path: PathBuf) -> Self {
+        Self {
+            scratch_path,
+            template: None,
+            auto_open_lines: None,
+            conversation_tail: Default::default(),
+            include_history: false,
+            watch_grace_period: DEFAULT_WATCH_GRACE_PERIOD,
+            editor: EditorCommand::default(),
+            detect_code: false,
+        }
+    }
+
+    pub fn with_extension(mut self, extension: Option<ScratchExtension>) -> Self {
+        match extension {
+            Some(ScratchExtension::Fixed(extension)) => {
+                self.scratch_path.set_extension(extension);
+            },
+            Some(ScratchExtension::Auto) => self.detect_code = true,
+            None => (),
+        }
+        self
+    }
+
+    /// Resolves `chat.editMode.fileExtension`, either `auto` or an extension such as `txt`.
+    pub fn extension_from_settings(settings: &Settings) -> Option<ScratchExtension> {
+        let extension = settings.get_string(Setting::ChatEditModeFileExtension)?;
+        match extension.trim().trim_start_matches('.') {
+            "" => None,
+            "auto" => Some(ScratchExtension::Auto),
+            extension if extension.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                Some(ScratchExtension::Fixed(extension.to_string()))
+            },
+            _ => {
+                warn!(%extension, "Ignoring invalid editor file extension");
+                None
+            },
+        }
+    }
+
+    pub fn with_editor(mut self, editor: EditorCommand) -> Self {
+        self.editor = editor;
+        self
+    }
+
+    pub fn with_watch_grace_period(mut self, grace_period: Option<Duration>) -> Self {
+        self.watch_grace_period = grace_period.unwrap_or(DEFAULT_WATCH_GRACE_PERIOD);
+        self
+    }
+
+    /// Resolves `chat.editMode.watchGracePeriod`, in milliseconds.
+    pub fn watch_grace_period_from_settings(settings: &Settings) -> Option<Duration> {
+        settings
+            .get_int(Setting::ChatEditModeWatchGracePeriod)
+            .and_then(|ms| u64::try_from(ms).ok())
+            .map(Duration::from_millis)
+    }
+
+    pub fn with_history(mut self, include_history: bool) -> Self {
+        self.include_history = include_history;
+        self
+    }
+
+    /// Records the last exchange, so that it can be quoted in the next editor buffer.
+    pub fn set_conversation_tail(&self, user: Option<&str>, assistant: Option<&str>) {
+        if let Ok(mut tail) = self.conversation_tail.lock() {
+            *tail = ConversationTail {
+                user: user.map(str::to_string),
+                assistant: assistant.map(str::to_string),
+            };
+        }
+    }
+
+    /// The most recent assistant response, if any.
+    pub fn last_response(&self) -> Option<String> {
+        self.conversation_tail
+            .lock()
+            .ok()?
+            .assistant
+            .clone()
+            .filter(|response| !response.trim().is_empty())
+    }
+
+    /// The comment quoting the last exchange, written at the top of the scratch file when
+    /// `chat.editMode.includeHistory` is enabled.
+    fn history_header(&self) -> Option<String> {
+        if !self.include_history {
+            return None;
+        }
+        let tail = self.conversation_tail.lock().ok()?;
+
+        let quote = |label: &str, message: Option<&str>| {
+            message.map(str::trim).filter(|m| !m.is_empty()).map(|message| {
+                let truncated = truncate_safe(message, MAX_HISTORY_MESSAGE_LEN);
+                let ellipsis = if truncated.len() < message.len() { "..." } else { "" };
+                // Keep the quoted text from closing the comment early
+                format!("{label}:\n{}{ellipsis}\n", truncated.replace("-->", "-- >"))
+            })
+        };
+        let sections = [
+            quote("You", tail.user.as_deref()),
+            quote("Amazon Q", tail.assistant.as_deref()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        (!sections.is_empty()).then(|| format!("{HISTORY_HEADER}\n\n{}-->\n\n", sections.join("\n")))
+    }
+
+    pub fn with_template(mut self, template: Option<String>) -> Self {
+        self.template = template;
+        self
+    }
+
+    pub fn with_auto_open_lines(mut self, auto_open_lines: Option<usize>) -> Self {
+        self.auto_open_lines = auto_open_lines.filter(|lines| *lines > 0);
+        self
+    }
+
+    /// Resolves `chat.editMode.autoOpenLines`.
+    pub fn auto_open_lines_from_settings(settings: &Settings) -> Option<usize> {
+        settings
+            .get_int(Setting::ChatEditModeAutoOpenLines)
+            .and_then(|lines| usize::try_from(lines).ok())
+    }
+
+    /// Whether `input` is long enough that it should be edited in the editor rather than submitted
+    /// straight from the prompt. Slash and bash commands are always submitted as-is.
+    pub fn should_auto_open(&self, input: &str) -> bool {
+        let is_command = input.starts_with('/') || input.starts_with('!');
+        self.auto_open_lines
+            .is_some_and(|max_lines| !is_command && input.lines().count() > max_lines)
+    }
+
+    /// Resolves `chat.editMode.template`, either `true` for the built-in template or the path to a
+    /// custom template file.
+    pub fn template_from_settings(settings: &Settings) -> Option<String> {
+        match settings.get(Setting::ChatEditModeTemplate)? {
+            serde_json::Value::Bool(true) => Some(DEFAULT_TEMPLATE.to_string()),
+            serde_json::Value::String(path) => {
+                let path = shellexpand::tilde(path);
+                match fs::read_to_string(path.as_ref()) {
+                    Ok(template) => Some(template),
+                    Err(err) => {
+                        warn!(?err, %path, "Failed to read the editor template");
+                        None
+                    },
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub fn scratch_path(&self) -> &Path {
+        &self.scratch_path
+    }
+
+    /// Opens the editor resolved by [EditorCommand::resolve] on the session scratch file and
+    /// returns its content once the editor exits, normalized with [normalize_editor_content].
+    ///
+    /// `initial_text` replaces the scratch content when provided, otherwise the previous draft (if
+    /// any) is kept. Returns [EditorOutput::Cancelled] if the file was left untouched.
+    pub async fn launch_system_editor(&self, initial_text: Option<String>) -> Result<EditorOutput, ChatError> {
+        let scratch = self
+            .prepare_scratch(initial_text)
+            .map_err(|e| ChatError::Custom(format!("Failed to create scratch file: {}", e).into()))?;
+
+        if self.detect_code {
+            if let Some((language, extension, code)) = single_code_block(strip_history_header(&scratch)) {
+                return self.launch_code_editor(language, extension, code).await;
+            }
+        }
+
+        if !self
+            .open_in_editor(&self.scratch_path)
+            .await
+            .map_err(|err| self.draft_kept(err))?
+        {
+            return Ok(EditorOutput::Cancelled);
+        }
+
+        // Read the content back, the scratch file is kept until the draft is submitted
+        let content = fs::read_to_string(&self.scratch_path)
+            .map_err(|e| ChatError::Custom(format!("Failed to read scratch file: {}", e).into()))?;
+
+        Ok(EditorOutput::Edited(self.process(&content)))
+    }
+
+    /// Opens `code` without its fence in a file with the language's `extension`, so that the editor
+    /// highlights it, and fences it again once saved.
+    async fn launch_code_editor(&self, language: &str, extension: &str, code: &str) -> Result<EditorOutput, ChatError> {
+        let file_stem = self.scratch_path.file_stem().unwrap_or_default().to_string_lossy();
+        let code_path = self
+            .scratch_path
+            .with_file_name(format!("code-{file_stem}.{extension}"));
+        fs::write(&code_path, format!("{code}\n"))?;
+
+        let saved = self.open_in_editor(&code_path).await;
+        let content = fs::read_to_string(&code_path);
+        let _ = fs::remove_file(&code_path);
+
+        let code = normalize_editor_content(&content?);
+        let prompt = match code.is_empty() {
+            true => String::new(),
+            false => format!("```{language}\n{code}\n```"),
+        };
+        // Kept as the draft until it's submitted like any other prompt, even if the editor failed
+        fs::write(&self.scratch_path, &prompt)?;
+
+        if !saved.map_err(|err| self.draft_kept(err))? {
+            return Ok(EditorOutput::Cancelled);
+        }
+        Ok(EditorOutput::Edited(prompt))
+    }
+
+    /// Adds where to find the draft to an editor failure, the scratch file is never removed when
+    /// the editor fails.
+    fn draft_kept(&self, err: ChatError) -> ChatError {
+        ChatError::Custom(
+            format!(
+                "{err}. Your draft was kept in {}, use /draft restore to load it",
+                self.scratch_path.display()
+            )
+            .into(),
+        )
+    }
+
+    /// Carries out what a keybinding asked for, returning the buffer to prompt with next, `None`
+    /// to keep the one the key was pressed on.
+    pub async fn run_request(&self, request: &EditorRequest) -> Result<Option<String>, ChatError> {
+        match request {
+            EditorRequest::Compose(line) => {
+                let initial_text = (!line.is_empty()).then(|| line.clone());
+                Ok(match self.launch_system_editor(initial_text).await? {
+                    EditorOutput::Edited(content) => Some(content),
+                    // The editor quit without saving, keep the line as it was
+                    EditorOutput::Cancelled => None,
+                })
+            },
+            EditorRequest::Quote(line) => Ok(match self.quote_last_response().await? {
+                EditorOutput::Edited(quote) if !quote.is_empty() => Some(format!("{quote}{line}")),
+                _ => None,
+            }),
+        }
+    }
+
+    /// The draft of this session, or else the most recent one left behind by a previous session.
+    pub fn restore_draft(&self) -> Option<String> {
+        self.draft().or_else(|| self.recover_orphaned_draft())
+    }
+
+    /// Opens the last assistant response in the editor, and returns whatever the user kept of it
+    /// formatted as a markdown quote block.
+    pub async fn quote_last_response(&self) -> Result<EditorOutput, ChatError> {
+        let response = self
+            .last_response()
+            .ok_or_else(|| ChatError::Custom("There is no response to quote yet".into()))?;
+
+        // A separate file, so that the draft in the scratch file is left alone
+        let file_name = self.scratch_path.file_name().unwrap_or_default().to_string_lossy();
+        let quote_path = self.scratch_path.with_file_name(format!("quote-{file_name}"));
+        if let Some(parent) = quote_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&quote_path, format!("{QUOTE_HEADER}{}\n", response.trim()))?;
+
+        let saved = self.open_in_editor(&quote_path).await;
+        let content = fs::read_to_string(&quote_path);
+        let _ = fs::remove_file(&quote_path);
+
+        if !saved? {
+            return Ok(EditorOutput::Cancelled);
+        }
+        let excerpt = normalize_editor_content(&strip_comments(&content?));
+        Ok(EditorOutput::Edited(format_quote(trim_blank_lines(&excerpt))))
+    }
+
+    /// Opens `path` in the editor, created with `initial_text` if it doesn't exist yet, e.g. for
+    /// `/system edit`.
+    ///
+    /// Returns whether the file was saved.
+    pub async fn edit_file(&self, path: &Path, initial_text: &str) -> Result<bool, ChatError> {
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, initial_text)?;
+        }
+        self.open_in_editor(path).await
+    }
+
+    /// Runs the editor on `path` and waits for it to finish.
+    ///
+    /// Returns whether the file was saved.
+    async fn open_in_editor(&self, path: &Path) -> Result<bool, ChatError> {
+        let cmd = editor_command(&self.editor.command, path)?;
+        let before = snapshot(path);
+
+        let started = Instant::now();
+        let status = tokio::process::Command::from(cmd)
+            .status()
+            .await
+            .map_err(|e| ChatError::Custom(format!("Failed to open editor: {}", e).into()))?;
+
+        if !status.success() {
+            return Err(ChatError::Custom("Editor exited with non-zero status".into()));
+        }
+
+        if snapshot(path) == before {
+            if started.elapsed() >= DETACHED_EDITOR_THRESHOLD {
+                return Ok(false);
+            }
+
+            // The editor returned right away, most likely a GUI editor running in the background
+            execute!(
+                std::io::stderr(),
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print("Waiting for the editor, save the file to submit your prompt or press Enter to stop...\n"),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+            return Ok(wait_for_save(path, &before, self.watch_grace_period, WATCH_TIMEOUT).await);
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the unsent draft currently stored in the scratch file, if any.
+    pub fn draft(&self) -> Option<String> {
+        fs::read_to_string(&self.scratch_path)
+            .ok()
+            .map(|content| self.process(&content))
+            .filter(|content| !content.is_empty())
+    }
+
+    /// Empties the scratch file once its content has been sent, keeping the file itself so the
+    /// editor's undo history stays attached to it.
+    pub fn discard_draft(&self) {
+        if self.scratch_path.exists() {
+            if let Err(err) = fs::write(&self.scratch_path, "") {
+                warn!(?err, "Failed to clear the editor scratch file");
+            }
+        }
+    }
+
+    /// Empties the scratch file if `input` is the draft it holds.
+    pub fn discard_draft_if_submitted(&self, input: &str) {
+        if self.draft().is_some_and(|draft| draft == input.trim()) {
+            self.discard_draft();
+        }
+    }
+
+    /// Adopts the most recent non-empty draft left behind by a previous session, e.g. after a
+    /// crash. The drafts of the sessions still running are left to them.
+    ///
+    /// Returns the recovered draft, which becomes the content of this session's scratch file.
+    pub fn recover_orphaned_draft(&self) -> Option<String> {
+        if self.draft().is_some() {
+            return None;
+        }
+
+        let dir = self.scratch_path.parent()?;
+        let (orphan, _) = fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path != &self.scratch_path && is_scratch_file(path))
+            .filter(|path| scratch_owner(path).is_some_and(|pid| !process::is_running(pid)))
+            .filter_map(|path| {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            })
+            .filter(|(path, _)| fs::read_to_string(path).is_ok_and(|c| !self.process(&c).is_empty()))
+            .max_by_key(|(_, modified)| *modified)?;
+
+        if let Err(err) = fs::rename(&orphan, &self.scratch_path) {
+            warn!(?err, ?orphan, "Failed to recover the editor draft");
+            return None;
+        }
+
+        self.draft()
+    }
+
+    /// Removes the scratch file when the session ends, unless it still holds an unsent draft.
+    pub fn cleanup(&self) {
+        if self.draft().is_none() {
+            let _ = fs::remove_file(&self.scratch_path);
+        }
+    }
+
+    /// Writes the content the editor is opened with to the scratch file, and returns it.
+    fn prepare_scratch(&self, initial_text: Option<String>) -> std::io::Result<String> {
+        if let Some(parent) = self.scratch_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let template = self.template.as_deref().unwrap_or_default();
+        let body = match initial_text {
+            Some(text) if template.is_empty() => text,
+            Some(text) => format!("{text}\n\n{template}"),
+            None if self.draft().is_none() => template.to_string(),
+            // Keep the draft, but refresh the quoted history above it
+            None => strip_history_header(&fs::read_to_string(&self.scratch_path)?).to_string(),
+        };
+
+        let header = self.history_header().unwrap_or_default();
+        let scratch = format!("{header}{body}");
+        fs::write(&self.scratch_path, &scratch)?;
+        Ok(scratch)
+    }
+
+    /// Turns the raw scratch content into prompt input.
+    fn process(&self, content: &str) -> String {
+        if let Some(template) = &self.template {
+            let content = normalize_editor_content(&strip_template(strip_history_header(content), template));
+            trim_blank_lines(&content).to_string()
+        } else if self.include_history {
+            trim_blank_lines(&normalize_editor_content(&strip_comments(content))).to_string()
+        } else {
+            normalize_editor_content(content)
+        }
+    }
+}
+
+/// Removes every `<!-- -->` comment from `content`.
+pub fn strip_comments(content: &str) -> String {
+    let mut uncommented = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("<!--") {
+        uncommented.push_str(&rest[..start]);
+        rest = match rest[start..].find("-->") {
+            Some(end) => &rest[start + end + "-->".len()..],
+            // An unterminated comment runs to the end of the buffer
+            None => "",
+        };
+    }
+    uncommented.push_str(rest);
+    uncommented
+}
+
+/// Removes the history block written by [EditorLauncher::set_conversation_tail], if `content`
+/// starts with one.
+fn strip_history_header(content: &str) -> &str {
+    match content.strip_prefix(HISTORY_HEADER) {
+        Some(rest) => rest
+            .find("-->")
+            .map_or("", |end| rest[end + "-->".len()..].trim_start_matches(['\r', '\n'])),
+        None => content,
+    }
+}
+
+/// Removes what's left of `template` in `content`: its lines as they were written, and its
+/// markdown headings whose section was left empty. What's in code blocks is left alone.
+pub fn strip_template(content: &str, template: &str) -> String {
+    let template_lines = template
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<HashSet<_>>();
+    // Each line, along with whether it's one of the template's
+    let mut in_code = false;
+    let lines = content
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+                return (line, false);
+            }
+            (line, !in_code && template_lines.contains(line.trim()))
+        })
+        .collect::<Vec<_>>();
+    let is_heading = |(line, of_template): &(&str, bool)| *of_template && line.trim_start().starts_with('#');
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(i, entry)| match entry {
+            (_, false) => true,
+            heading if is_heading(heading) => lines[i + 1..]
+                .iter()
+                .take_while(|next| !is_heading(next))
+                .any(|(next, of_template)| !of_template && !next.trim().is_empty()),
+            _ => false,
+        })
+        .map(|(_, (line, _))| *line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Builds the process that opens `path` with the editor described by `editor_cmd`.
+///
+/// The command is split like a shell would, so that e.g. `EDITOR="code --wait"` works.
+#[cfg(unix)]
+fn editor_command(editor_cmd: &str, path: &Path) -> Result<ProcessCommand, ChatError> {
+    let mut parts =
+        shlex::split(editor_cmd).ok_or_else(|| ChatError::Custom("Failed to parse the editor command".into()))?;
+
+    if parts.is_empty() {
+        return Err(ChatError::Custom("The editor command is empty".into()));
+    }
+
+    let mut cmd = ProcessCommand::new(parts.remove(0));
+    // Add any arguments that were part of the editor command, then the file path last
+    cmd.args(parts).arg(path);
+    Ok(cmd)
+}
+
+/// Builds the process that opens `path` with the editor described by `editor_cmd`.
+///
+/// `%EDITOR%` commonly holds unquoted paths with backslashes and spaces which can't be split with
+/// POSIX rules, so the command line is handed to `cmd /C` with the program and the file path
+/// quoted, see [quote_program].
+#[cfg(windows)]
+fn editor_command(editor_cmd: &str, path: &Path) -> Result<ProcessCommand, ChatError> {
+    use std::os::windows::process::CommandExt;
+
+    let editor_cmd = editor_cmd.trim();
+    if editor_cmd.is_empty() {
+        return Err(ChatError::Custom("The editor command is empty".into()));
+    }
+
+    let editor_cmd = quote_program(editor_cmd, |program| {
+        program.is_file() || program.with_extension("exe").is_file()
+    });
+    let mut cmd = ProcessCommand::new("cmd");
+    // `/S` makes cmd strip exactly the outer pair of quotes and keep the rest of the line intact
+    cmd.args(["/S", "/C"])
+        .raw_arg(format!("\"{} \"{}\"\"", editor_cmd, path.display()));
+    Ok(cmd)
+}
+
+/// Quotes the program of `editor_cmd` for `cmd`, unless it already is. The program of an unquoted
+/// path with spaces, e.g. `C:\Program Files\Notepad++\notepad++.exe -multiInst`, runs up to the
+/// longest prefix that `is_file`, or else to the first space.
+#[cfg(windows)]
+fn quote_program(editor_cmd: &str, is_file: impl Fn(&Path) -> bool) -> String {
+    if editor_cmd.starts_with('"') {
+        return editor_cmd.to_string();
+    }
+    let mut ends = editor_cmd.match_indices(' ').map(|(i, _)| i).collect::<Vec<_>>();
+    ends.push(editor_cmd.len());
+    let end = ends
+        .iter()
+        .rev()
+        .copied()
+        .find(|end| is_file(Path::new(&editor_cmd[..*end])))
+        .unwrap_or(ends[0]);
+    format!("\"{}\"{}", &editor_cmd[..end], &editor_cmd[end..])
+}
+
+/// Polls `path` until it has been saved and then left alone for `grace_period`.
+///
+/// Returns false if nothing was saved within `timeout`, or if the wait was stopped with Enter or
+/// Ctrl+C.
+async fn wait_for_save(path: &Path, before: &ScratchSnapshot, grace_period: Duration, timeout: Duration) -> bool {
+    tokio::select! {
+        saved = poll_for_save(path, before, grace_period, timeout) => saved,
+        _ = enter_pressed() => false,
+        _ = tokio::signal::ctrl_c() => false,
+    }
+}
+
+async fn poll_for_save(path: &Path, before: &ScratchSnapshot, grace_period: Duration, timeout: Duration) -> bool {
+    let started = Instant::now();
+    let mut last = snapshot(path);
+    let mut last_change = (last != *before).then(Instant::now);
+    // Poll at least as often as the grace period so short periods are honored
+    let poll_interval = WATCH_POLL_INTERVAL.min(grace_period).max(Duration::from_millis(10));
+
+    loop {
+        match last_change {
+            Some(changed) if changed.elapsed() >= grace_period => return true,
+            None if started.elapsed() >= timeout => return false,
+            _ => tokio::time::sleep(poll_interval).await,
+        }
+
+        let current = snapshot(path);
+        if current != last {
+            last = current;
+            last_change = Some(Instant::now());
+        }
+    }
+}
+
+/// Resolves once a line is entered in the terminal, which is only readable then. Never resolves
+/// when stdin isn't a terminal.
+async fn enter_pressed() {
+    #[cfg(unix)]
+    if std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        use std::os::fd::AsRawFd;
+
+        let fd = std::io::stdin().as_raw_fd();
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pollfd, 1, 0) } > 0 {
+                // Consume the line, so that it isn't read by the next prompt
+                let mut buf = [0; 256];
+                let _ = nix::unistd::read(fd, &mut buf);
+                return;
+            }
+            tokio::time::sleep(WATCH_POLL_INTERVAL).await;
+        }
+    }
+    std::future::pending::<()>().await;
+}
+
+fn snapshot(path: &Path) -> ScratchSnapshot {
+    let mut hasher = DefaultHasher::new();
+    fs::read(path).unwrap_or_default().hash(&mut hasher);
+    ScratchSnapshot {
+        modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+        hash: hasher.finish(),
+    }
+}
+
+fn is_scratch_file(path: &Path) -> bool {
+    path.extension().is_some()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(SCRATCH_PREFIX))
+}
+
+/// The process of the session the scratch file at `path` belongs to, at the end of its name.
+fn scratch_owner(path: &Path) -> Option<Pid> {
+    let stem = path.file_stem()?.to_str()?.strip_prefix(SCRATCH_PREFIX)?;
+    let (_, pid) = stem.rsplit_once('-')?;
+    pid.parse().ok().map(Pid::from_u32)
+}
+
+/// Returns the language, its file extension and the code of `content` if it's made of a single
+/// fenced code block in a language with a known extension.
+fn single_code_block(content: &str) -> Option<(&str, &'static str, &str)> {
+    let (language, rest) = content.trim().strip_prefix("```")?.split_once('\n')?;
+    let language = language.trim();
+    let code = rest.strip_suffix("```")?;
+    // A fence in between means there's more than one block, with text around them
+    if code.lines().any(|line| line.trim_start().starts_with("```")) {
+        return None;
+    }
+
+    let extension = match language.to_lowercase().as_str() {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "javascript" | "js" => "js",
+        "jsx" => "jsx",
+        "typescript" | "ts" => "ts",
+        "tsx" => "tsx",
+        "go" | "golang" => "go",
+        "java" => "java",
+        "kotlin" | "kt" => "kt",
+        "swift" => "swift",
+        "c" => "c",
+        "cpp" | "c++" => "cpp",
+        "csharp" | "cs" | "c#" => "cs",
+        "ruby" | "rb" => "rb",
+        "php" => "php",
+        "sh" | "bash" | "shell" | "zsh" => "sh",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "sql" => "sql",
+        "html" => "html",
+        "css" => "css",
+        _ => return None,
+    };
+
+    Some((language, extension, code.trim_end_matches(['\r', '\n'])))
+}
+
+/// Normalizes what the editor wrote so that it can be used as prompt input as-is.
+///
+/// Line endings are converted to `\n` and the trailing newline editors append is dropped.
+/// Everything else, including indentation, trailing spaces and blank lines, is preserved exactly,
+/// a buffer left blank being empty.
+pub fn normalize_editor_content(content: &str) -> String {
+    if content.trim().is_empty() {
+        return String::new();
+    }
+    let content = content.replace("\r\n", "\n").replace('\r', "\n");
+    match content.strip_suffix('\n') {
+        Some(content) => content.to_string(),
+        None => content,
+    }
+}
+
+/// `content` without the blank lines around it, left by the template or the comments removed.
+fn trim_blank_lines(content: &str) -> &str {
+    let Some(first) = content.find(|c: char| !c.is_whitespace()) else {
+        return "";
+    };
+    let start = content[..first].rfind('\n').map_or(0, |newline| newline + 1);
+    let last = content.rfind(|c: char| !c.is_whitespace()).unwrap_or(first);
+    let end = content[last..]
+        .find('\n')
+        .map_or(content.len(), |newline| last + newline);
+    &content[start..end]
+}
+
+/// Formats `excerpt` as a markdown quote block followed by an empty line to type the reply in.
+pub fn format_quote(excerpt: &str) -> String {
+    if excerpt.is_empty() {
+        return String::new();
+    }
+
+    let quoted = excerpt
+        .lines()
+        .map(|line| match line.is_empty() {
+            true => ">".to_string(),
+            false => format!("> {line}"),
+        })
+        .collect::<Vec<_>>();
+    format!("{}\n\n", quoted.join("\n"))
+}
+
+/// Builds the command that swaps the current input buffer with the content returned by the editor.
+pub fn create_line_replacement_command(content: String) -> Cmd {
+    Cmd::Replace(Movement::WholeBuffer, Some(content))
+}
+
+/// What a keybinding asked for, carried out by the chat loop once the prompt has ended and the
+/// terminal is out of raw mode, see [EditorLauncher::run_request].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditorRequest {
+    /// Compose the prompt in the editor, seeded with the buffer.
+    Compose(String),
+    /// Quote an excerpt of the last response above the buffer.
+    Quote(String),
+}
+
+impl EditorRequest {
+    /// The buffer when the key was pressed.
+    pub fn line(&self) -> &str {
+        match self {
+            EditorRequest::Compose(line) | EditorRequest::Quote(line) => line,
+        }
+    }
+}
+
+/// Where the keybinding handlers leave their [EditorRequest] for the chat loop.
+pub type EditorRequests = Arc<Mutex<Option<EditorRequest>>>;
+
+/// Opens the system editor from the prompt, seeded with whatever has been typed so far.
+///
+/// Bound to Ctrl+F, in place of moving forward a character which the right arrow does too, and to
+/// Ctrl+X Ctrl+E as in bash, so that long prompts can be composed without leaving the chat. The
+/// prompt ends to give the editor the terminal, and comes back with what was saved. A single undo
+/// brings back what was typed before the round trip.
+pub struct EditorEventHandler(pub EditorRequests);
+
+impl ConditionalEventHandler for EditorEventHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        request(&self.0, EditorRequest::Compose(ctx.line().to_string()))
+    }
+}
+
+/// Opens the last response in the system editor and puts what's kept of it in the buffer as a
+/// quote, above whatever has been typed so far.
+///
+/// Bound to Ctrl+G, the keybinding counterpart of `/quote`.
+pub struct QuoteEventHandler(pub EditorRequests);
+
+impl ConditionalEventHandler for QuoteEventHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        request(&self.0, EditorRequest::Quote(ctx.line().to_string()))
+    }
+}
+
+/// Leaves `request` for the chat loop and ends the prompt, as an interrupt that it tells apart by
+/// the request.
+fn request(requests: &EditorRequests, request: EditorRequest) -> Option<Cmd> {
+    let mut requests = requests.lock().ok()?;
+    *requests = Some(request);
+    Some(Cmd::Interrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_line_replacement_command() {
+        assert_eq!(
+            create_line_replacement_command("hello\n\n  world".to_string()),
+            Cmd::Replace(Movement::WholeBuffer, Some("hello\n\n  world".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_normalize_editor_content() {
+        let cases = [
+            ("My content", "My content"),
+            ("My content with newline\n", "My content with newline"),
+            ("", ""),
+            ("\n\n  \n", ""),
+            ("first\n\n\nsecond\n", "first\n\n\nsecond"),
+            ("\n\n    indented code\n  more\n\n", "\n\n    indented code\n  more\n"),
+            ("windows\r\n\r\nline endings\r\n", "windows\n\nline endings"),
+            // Two trailing spaces are a hard line break in markdown
+            ("hard  \nbreak  kept   \n", "hard  \nbreak  kept   "),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                normalize_editor_content(input),
+                expected,
+                "Failed for input: {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_scratch_draft_lifecycle() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("draft", scratch_dir.path());
+        assert!(launcher.draft().is_none());
+
+        launcher.prepare_scratch(Some("my draft\n".to_string())).unwrap();
+        assert_eq!(launcher.draft(), Some("my draft".to_string()));
+
+        // Reopening without initial text keeps the draft
+        launcher.prepare_scratch(None).unwrap();
+        assert_eq!(launcher.draft(), Some("my draft".to_string()));
+
+        launcher.discard_draft_if_submitted("something else");
+        assert_eq!(launcher.draft(), Some("my draft".to_string()));
+
+        launcher.discard_draft_if_submitted("my draft");
+        assert!(launcher.draft().is_none());
+        assert!(launcher.scratch_path().exists());
+
+        launcher.cleanup();
+        assert!(!launcher.scratch_path().exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_editor_command() {
+        let path = Path::new("/tmp/scratch-test.md");
+
+        let cmd = editor_command("vi", path).unwrap();
+        assert_eq!(cmd.get_program(), "vi");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), vec![path.as_os_str()]);
+
+        let cmd = editor_command("'/opt/My Editor/bin/edit' --wait", path).unwrap();
+        assert_eq!(cmd.get_program(), "/opt/My Editor/bin/edit");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), vec![
+            "--wait".as_ref(),
+            path.as_os_str()
+        ]);
+
+        assert!(editor_command("", path).is_err());
+        assert!(editor_command("'unterminated", path).is_err());
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_editor_command() {
+        use std::ffi::OsStr;
+
+        let path = Path::new(r"C:\Users\me\scratch-test.md");
+
+        let cmd = editor_command(r"C:\Program Files\Notepad++\notepad++.exe -multiInst", path).unwrap();
+        assert_eq!(cmd.get_program(), "cmd");
+        let args = cmd.get_args().collect::<Vec<_>>();
+        assert_eq!(args[..2], [OsStr::new("/S"), OsStr::new("/C")]);
+
+        // The program is quoted, up to the file it names when its path has spaces
+        let dir = env::temp_dir().join(format!("q chat test {}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let editor = dir.join("edit.exe");
+        fs::write(&editor, "").unwrap();
+        let cmd = editor_command(&format!("{} --wait", editor.display()), path).unwrap();
+        assert_eq!(
+            cmd.get_args().nth(2).unwrap(),
+            OsStr::new(&format!("\"\"{}\" --wait \"{}\"\"", editor.display(), path.display()))
+        );
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(editor_command("  ", path).is_err());
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_quote_program() {
+        let is_file = |path: &Path| path == Path::new(r"C:\Program Files\Notepad++\notepad++.exe");
+        assert_eq!(
+            quote_program(r"C:\Program Files\Notepad++\notepad++.exe -multiInst", is_file),
+            r#""C:\Program Files\Notepad++\notepad++.exe" -multiInst"#
+        );
+        assert_eq!(quote_program("code --wait", is_file), r#""code" --wait"#);
+        assert_eq!(quote_program("notepad", is_file), r#""notepad""#);
+        assert_eq!(
+            quote_program(r#""C:\Program Files\Vim\gvim.exe" -f"#, is_file),
+            r#""C:\Program Files\Vim\gvim.exe" -f"#
+        );
+    }
+
+    #[test]
+    fn test_strip_template() {
+        assert_eq!(
+            trim_blank_lines(&strip_template(DEFAULT_TEMPLATE, DEFAULT_TEMPLATE)),
+            ""
+        );
+
+        let filled = DEFAULT_TEMPLATE.replace(
+            "<!-- What should Q help you with? -->",
+            "Add retries to the upload client",
+        );
+        assert_eq!(
+            trim_blank_lines(&strip_template(&filled, DEFAULT_TEMPLATE)),
+            "## Goal\nAdd retries to the upload client"
+        );
+
+        // What the user wrote is kept, comments and headings included, and code blocks as they are
+        let written = "\
+Fix the build
+<!-- TODO: mention CI -->
+# Not from the template
+```c
+#include <stdio.h>
+<!-- Libraries, style or behavior to keep in mind -->
+```";
+        let filled = DEFAULT_TEMPLATE.replace("<!-- What should Q help you with? -->", written);
+        assert_eq!(
+            trim_blank_lines(&strip_template(&filled, DEFAULT_TEMPLATE)),
+            format!("## Goal\n{written}")
+        );
+    }
+
+    #[test]
+    fn test_template_is_written_for_new_prompts() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher =
+            EditorLauncher::new("template", scratch_dir.path()).with_template(Some(DEFAULT_TEMPLATE.to_string()));
+
+        launcher.prepare_scratch(None).unwrap();
+        assert_eq!(fs::read_to_string(launcher.scratch_path()).unwrap(), DEFAULT_TEMPLATE);
+        // An untouched template is not a draft
+        assert!(launcher.draft().is_none());
+
+        launcher.prepare_scratch(Some("fix this function".to_string())).unwrap();
+        assert_eq!(launcher.draft(), Some("fix this function".to_string()));
+    }
+
+    #[test]
+    fn test_format_quote() {
+        assert_eq!(format_quote(""), "");
+        assert_eq!(format_quote("one line"), "> one line\n\n");
+        assert_eq!(format_quote("first\n\n  second"), "> first\n>\n>   second\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_last_response() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("quote", scratch_dir.path());
+        assert!(launcher.last_response().is_none());
+        assert!(launcher.quote_last_response().await.is_err());
+
+        // Clones share the conversation tail
+        launcher.clone().set_conversation_tail(Some("question"), Some("answer"));
+        assert_eq!(launcher.last_response(), Some("answer".to_string()));
+
+        launcher.set_conversation_tail(Some("question"), Some("  "));
+        assert!(launcher.last_response().is_none());
+    }
+
+    #[test]
+    fn test_editor_command_precedence() {
+        let resolved = EditorCommand::from_candidates([
+            (None, EditorSource::Flag),
+            (Some("code --wait".to_string()), EditorSource::Setting),
+            (Some("nvim".to_string()), EditorSource::Visual),
+        ]);
+        assert_eq!(resolved, EditorCommand {
+            command: "code --wait".to_string(),
+            source: EditorSource::Setting,
+        });
+
+        // Blank values fall through to the next candidate
+        let resolved = EditorCommand::from_candidates([
+            (Some("  ".to_string()), EditorSource::Flag),
+            (Some("nvim".to_string()), EditorSource::Editor),
+        ]);
+        assert_eq!(resolved.source, EditorSource::Editor);
+
+        let resolved = EditorCommand::from_candidates([(None, EditorSource::Visual)]);
+        assert_eq!(resolved.command, DEFAULT_EDITOR);
+        assert_eq!(resolved.source, EditorSource::Default);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_editor_diagnostic() {
+        let diagnostic = EditorCommand {
+            command: "code --wait".to_string(),
+            source: EditorSource::Flag,
+        }
+        .diagnostic();
+        assert_eq!(diagnostic.source, "--editor");
+        assert_eq!(
+            diagnostic.parsed,
+            Some(vec!["code".to_string(), "--wait".to_string(), "<file>".to_string()])
+        );
+        assert!(diagnostic.error.is_none());
+
+        let diagnostic = EditorCommand {
+            command: "'unterminated".to_string(),
+            source: EditorSource::Setting,
+        }
+        .diagnostic();
+        assert!(diagnostic.parsed.is_none());
+        assert!(diagnostic.error.is_some());
+    }
+
+    #[test]
+    fn test_should_auto_open() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("auto-open", scratch_dir.path());
+        assert!(!launcher.should_auto_open("a\nb\nc\nd"));
+
+        let launcher = launcher.with_auto_open_lines(Some(3));
+        assert!(!launcher.should_auto_open("a\nb\nc"));
+        assert!(launcher.should_auto_open("a\nb\nc\nd"));
+        assert!(!launcher.should_auto_open("/compact a\nb\nc\nd"));
+        assert!(!launcher.should_auto_open("!echo a\nb\nc\nd"));
+
+        // Zero disables the threshold rather than opening the editor on every prompt
+        let launcher = launcher.with_auto_open_lines(Some(0));
+        assert!(!launcher.should_auto_open("a\nb\nc\nd"));
+    }
+
+    #[test]
+    fn test_history_is_quoted_and_stripped() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("history", scratch_dir.path()).with_history(true);
+        launcher.set_conversation_tail(Some("what does this do?"), Some("It parses <!-- html --> comments"));
+
+        launcher.prepare_scratch(Some("follow up".to_string())).unwrap();
+        let raw = fs::read_to_string(launcher.scratch_path()).unwrap();
+        assert!(raw.starts_with(HISTORY_HEADER));
+        assert!(raw.contains("You:\nwhat does this do?"));
+        assert!(raw.contains("It parses <!-- html -- > comments"));
+        assert_eq!(launcher.draft(), Some("follow up".to_string()));
+
+        // The draft is kept when reopening, with the history refreshed rather than duplicated
+        launcher.set_conversation_tail(Some("next question"), None);
+        launcher.prepare_scratch(None).unwrap();
+        let raw = fs::read_to_string(launcher.scratch_path()).unwrap();
+        assert_eq!(raw.matches(HISTORY_HEADER).count(), 1);
+        assert!(raw.contains("next question"));
+        assert!(!raw.contains("what does this do?"));
+        assert_eq!(launcher.draft(), Some("follow up".to_string()));
+    }
+
+    #[test]
+    fn test_history_disabled() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("no-history", scratch_dir.path());
+        launcher.set_conversation_tail(Some("question"), Some("answer"));
+        launcher
+            .prepare_scratch(Some("keep <!-- this -->".to_string()))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(launcher.scratch_path()).unwrap(),
+            "keep <!-- this -->"
+        );
+        assert_eq!(launcher.draft(), Some("keep <!-- this -->".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_save() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("watch", scratch_dir.path());
+        launcher.prepare_scratch(None).unwrap();
+        let before = snapshot(launcher.scratch_path());
+
+        // Nothing saved within the timeout
+        assert!(
+            !wait_for_save(
+                launcher.scratch_path(),
+                &before,
+                Duration::from_millis(50),
+                Duration::from_millis(100)
+            )
+            .await
+        );
+
+        let path = launcher.scratch_path().to_path_buf();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            fs::write(&path, "saved from a gui editor").unwrap();
+        });
+        assert!(
+            wait_for_save(
+                launcher.scratch_path(),
+                &before,
+                Duration::from_millis(100),
+                Duration::from_secs(5)
+            )
+            .await
+        );
+        writer.join().unwrap();
+        assert_eq!(launcher.draft(), Some("saved from a gui editor".to_string()));
+    }
+
+    #[test]
+    fn test_scratch_snapshot_detects_writes() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("snapshot", scratch_dir.path());
+        launcher.prepare_scratch(Some("draft".to_string())).unwrap();
+        let before = snapshot(launcher.scratch_path());
+        assert_eq!(snapshot(launcher.scratch_path()), before);
+
+        fs::write(launcher.scratch_path(), "edited draft").unwrap();
+        assert_ne!(snapshot(launcher.scratch_path()), before);
+    }
+
+    #[test]
+    fn test_recover_orphaned_draft() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("current", scratch_dir.path());
+        let dir = scratch_dir.path();
+        let dead = u32::MAX - 1;
+        fs::write(dir.join(format!("scratch-empty-{dead}.md")), "  \n").unwrap();
+        fs::write(dir.join(format!("scratch-crashed-{dead}.md")), "unsent prompt").unwrap();
+        fs::write(dir.join("notes.md"), "not a draft").unwrap();
+        // Nor are the drafts of running sessions, or of unknown ones
+        let running = dir.join(format!("scratch-running-{}.md", std::process::id()));
+        fs::write(&running, "being written").unwrap();
+        fs::write(dir.join("scratch-unknown.md"), "whose?").unwrap();
+
+        assert_eq!(launcher.recover_orphaned_draft(), Some("unsent prompt".to_string()));
+        assert!(!dir.join(format!("scratch-crashed-{dead}.md")).exists());
+        assert!(dir.join("notes.md").exists());
+        assert!(running.exists());
+
+        // Nothing left to recover once the draft has been sent
+        launcher.discard_draft();
+        let other = EditorLauncher::with_scratch_path(dir.join(format!("scratch-other-{dead}.md")));
+        assert!(other.recover_orphaned_draft().is_none());
+    }
+
+    #[test]
+    fn test_restore_draft() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("restore", scratch_dir.path());
+        assert!(launcher.restore_draft().is_none());
+
+        launcher.prepare_scratch(Some("unsent".to_string())).unwrap();
+        assert_eq!(launcher.restore_draft(), Some("unsent".to_string()));
+
+        let err = launcher.draft_kept(ChatError::Custom("Editor exited with non-zero status".into()));
+        assert!(err.to_string().contains(&launcher.scratch_path().display().to_string()));
+    }
+
+    #[test]
+    fn test_single_code_block() {
+        assert_eq!(
+            single_code_block("```rust\nfn main() {\n    todo!()\n}\n```\n"),
+            Some(("rust", "rs", "fn main() {\n    todo!()\n}"))
+        );
+        assert_eq!(single_code_block("```Python\n```"), Some(("Python", "py", "")));
+
+        assert_eq!(single_code_block("```\nno language\n```"), None);
+        assert_eq!(single_code_block("```cobol\nDISPLAY 'HI'.\n```"), None);
+        assert_eq!(single_code_block("fix this:\n```rust\nfn main() {}\n```"), None);
+        assert_eq!(single_code_block("```rust\na\n```\nand\n```rust\nb\n```"), None);
+    }
+
+    #[test]
+    fn test_scratch_extension() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let launcher = EditorLauncher::new("extension", scratch_dir.path())
+            .with_extension(Some(ScratchExtension::Fixed("txt".to_string())));
+        assert_eq!(launcher.scratch_path().extension().unwrap(), "txt");
+        assert!(is_scratch_file(launcher.scratch_path()));
+        assert!(!launcher.detect_code);
+
+        let launcher =
+            EditorLauncher::new("extension", scratch_dir.path()).with_extension(Some(ScratchExtension::Auto));
+        assert_eq!(launcher.scratch_path().extension().unwrap(), SCRATCH_EXTENSION);
+        assert!(launcher.detect_code);
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+
+use super::cli::ExportFormat;
+use super::conversation_state::ConversationState;
+use super::message::{
+    ToolUseResult,
+    ToolUseResultBlock,
+    UserMessageContent,
+};
+use super::util::truncate_safe;
+use crate::api_client::model::ToolResultStatus;
+
+/// How much of each tool result the Markdown and HTML exports keep, the JSON export keeps all of
+/// it.
+const MAX_TOOL_RESULT_BYTES: usize = 4000;
+
+/// The format matching the extension of `path`, if any.
+pub fn format_for_path(path: &Path) -> Option<ExportFormat> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "md" | "markdown" => Some(ExportFormat::Markdown),
+        "html" | "htm" => Some(ExportFormat::Html),
+        "json" => Some(ExportFormat::Json),
+        _ => None,
+    }
+}
+
+/// Renders the history of `state` as `format`.
+pub fn export(state: &ConversationState, format: ExportFormat) -> eyre::Result<String> {
+    Ok(match format {
+        ExportFormat::Markdown => to_markdown(state),
+        ExportFormat::Html => to_html(state),
+        ExportFormat::Json => serde_json::to_string_pretty(&Transcript::new(state))?,
+    })
+}
+
+fn to_markdown(state: &ConversationState) -> String {
+    let mut markdown = String::from("# Amazon Q conversation\n\n");
+    let _ = writeln!(
+        markdown,
+        "_Conversation {}, exported {}_\n",
+        state.conversation_id(),
+        format_timestamp(OffsetDateTime::now_utc())
+    );
+
+    // Tool results only have the id of their tool use
+    let mut tool_names = HashMap::new();
+    for (user, assistant) in state.history() {
+        let heading = match user.timestamp {
+            Some(timestamp) => format!("## You ({})\n\n", format_timestamp(timestamp)),
+            None => "## You\n\n".to_string(),
+        };
+        match &user.content {
+            UserMessageContent::Prompt { prompt } => {
+                let _ = write!(markdown, "{heading}{prompt}\n\n");
+            },
+            UserMessageContent::CancelledToolUses {
+                prompt,
+                tool_use_results,
+            } => {
+                markdown.push_str(&heading);
+                markdown.push_str("_Cancelled the tool uses_\n\n");
+                write_tool_results(&mut markdown, &tool_names, tool_use_results);
+                if let Some(prompt) = prompt {
+                    let _ = write!(markdown, "{prompt}\n\n");
+                }
+            },
+            UserMessageContent::ToolUseResults { tool_use_results } => {
+                write_tool_results(&mut markdown, &tool_names, tool_use_results);
+            },
+        }
+
+        markdown.push_str("## Amazon Q\n\n");
+        if !assistant.content().trim().is_empty() {
+            let _ = write!(markdown, "{}\n\n", assistant.content().trim_end());
+        }
+        for tool_use in assistant.tool_uses().unwrap_or_default() {
+            tool_names.insert(tool_use.id.as_str(), tool_use.name.as_str());
+            let args = serde_json::to_string_pretty(&tool_use.args).unwrap_or_default();
+            let _ = write!(
+                markdown,
+                "**Tool use: `{}`**\n\n{}\n",
+                tool_use.name,
+                fenced(&args, "json")
+            );
+        }
+    }
+    markdown
+}
+
+fn write_tool_results(markdown: &mut String, tool_names: &HashMap<&str, &str>, results: &[ToolUseResult]) {
+    for result in results {
+        let name = tool_names.get(result.tool_use_id.as_str()).unwrap_or(&"tool");
+        let status = match result.status {
+            ToolResultStatus::Success => "",
+            ToolResultStatus::Error => " (failed)",
+        };
+        let _ = write!(markdown, "**Result of `{name}`{status}**\n\n");
+        for block in &result.content {
+            let (content, lang) = match block {
+                ToolUseResultBlock::Text(text) => (text.clone(), ""),
+                ToolUseResultBlock::Json(json) => (serde_json::to_string_pretty(json).unwrap_or_default(), "json"),
+            };
+            let truncated = truncate_safe(&content, MAX_TOOL_RESULT_BYTES);
+            match truncated.len() < content.len() {
+                true => {
+                    let _ = write!(markdown, "{}_Truncated_\n\n", fenced(truncated, lang));
+                },
+                false => {
+                    let _ = writeln!(markdown, "{}", fenced(truncated, lang));
+                },
+            }
+        }
+    }
+}
+
+/// Wraps `content` in a code block, with a fence longer than any run of backticks in it.
+fn fenced(content: &str, lang: &str) -> String {
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or_default();
+    let fence = "`".repeat((longest_run + 1).max(3));
+    format!("{fence}{lang}\n{}\n{fence}\n", content.trim_end_matches('\n'))
+}
+
+fn format_timestamp(timestamp: OffsetDateTime) -> String {
+    let format = format_description!("[year]-[month]-[day] [hour]:[minute] UTC");
+    timestamp
+        .to_offset(time::UtcOffset::UTC)
+        .format(format)
+        .unwrap_or_default()
+}
+
+/// Renders the Markdown export as a standalone page. Only the subset of Markdown the export and
+/// most responses use is handled: headings, code blocks, inline code and bold text, the rest is
+/// kept as is.
+fn to_html(state: &ConversationState) -> String {
+    let mut body = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    // The fence of the code block being rendered
+    let mut code_fence: Option<&str> = None;
+
+    let markdown = to_markdown(state);
+    for line in markdown.lines() {
+        if let Some(fence) = code_fence {
+            if line.trim() == fence {
+                body.push_str("</code></pre>\n");
+                code_fence = None;
+            } else {
+                let _ = writeln!(body, "{}", escape_html(line));
+            }
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            flush_paragraph(&mut body, &mut paragraph);
+            let fence_len = trimmed.chars().take_while(|c| *c == '`').count();
+            let (fence, lang) = trimmed.split_at(fence_len);
+            match lang.trim() {
+                "" => body.push_str("<pre><code>"),
+                lang => {
+                    let _ = write!(body, "<pre><code class=\"language-{}\">", escape_html(lang));
+                },
+            }
+            code_fence = Some(fence);
+        } else if let Some((level, heading)) = heading(trimmed) {
+            flush_paragraph(&mut body, &mut paragraph);
+            let _ = writeln!(body, "<h{level}>{}</h{level}>", inline_html(heading));
+        } else if trimmed.is_empty() {
+            flush_paragraph(&mut body, &mut paragraph);
+        } else {
+            paragraph.push(line);
+        }
+    }
+    flush_paragraph(&mut body, &mut paragraph);
+    if code_fence.is_some() {
+        body.push_str("</code></pre>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Amazon Q conversation {}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 50em; margin: auto; padding: 1em; line-height: 1.5; }}
+p {{ white-space: pre-wrap; }}
+pre {{ background: #f4f4f4; padding: 0.75em; overflow-x: auto; }}
+code {{ font-family: monospace; }}
+</style>
+</head>
+<body>
+{body}</body>
+</html>
+",
+        escape_html(state.conversation_id())
+    )
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    match (1..=6).contains(&level) {
+        true => line[level..].strip_prefix(' ').map(|heading| (level, heading)),
+        false => None,
+    }
+}
+
+fn flush_paragraph(body: &mut String, paragraph: &mut Vec<&str>) {
+    if !paragraph.is_empty() {
+        let _ = writeln!(body, "<p>{}</p>", inline_html(&paragraph.join("\n")));
+        paragraph.clear();
+    }
+}
+
+/// Escapes `text`, turning `code` spans and **bold** text into their tags.
+fn inline_html(text: &str) -> String {
+    let mut html = String::new();
+    for (i, part) in text.split('`').enumerate() {
+        // Odd parts are between backticks
+        if i % 2 == 1 {
+            let _ = write!(html, "<code>{}</code>", escape_html(part));
+            continue;
+        }
+        for (j, part) in escape_html(part).split("**").enumerate() {
+            match j % 2 {
+                1 => {
+                    let _ = write!(html, "<strong>{part}</strong>");
+                },
+                _ => html.push_str(part),
+            }
+        }
+    }
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The JSON export, which keeps the tool uses and their full results.
+#[derive(Serialize)]
+struct Transcript<'a> {
+    conversation_id: &'a str,
+    exported_at: String,
+    messages: Vec<TranscriptMessage<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "role", rename_all = "snake_case")]
+enum TranscriptMessage<'a> {
+    User {
+        timestamp: Option<String>,
+        prompt: Option<&'a str>,
+        tool_results: Vec<TranscriptToolResult<'a>>,
+    },
+    Assistant {
+        message_id: Option<&'a str>,
+        content: &'a str,
+        tool_uses: Vec<TranscriptToolUse<'a>>,
+    },
+}
+
+#[derive(Serialize)]
+struct TranscriptToolUse<'a> {
+    id: &'a str,
+    name: &'a str,
+    args: &'a serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct TranscriptToolResult<'a> {
+    tool_use_id: &'a str,
+    success: bool,
+    content: Vec<&'a serde_json::Value>,
+    text: Vec<&'a str>,
+}
+
+impl<'a> Transcript<'a> {
+    fn new(state: &'a ConversationState) -> Self {
+        let mut messages = Vec::new();
+        for (user, assistant) in state.history() {
+            let (prompt, results) = match &user.content {
+                UserMessageContent::Prompt { prompt } => (Some(prompt.as_str()), &[][..]),
+                UserMessageContent::CancelledToolUses {
+                    prompt,
+                    tool_use_results,
+                } => (prompt.as_deref(), tool_use_results.as_slice()),
+                UserMessageContent::ToolUseResults { tool_use_results } => (None, tool_use_results.as_slice()),
+            };
+            messages.push(TranscriptMessage::User {
+                timestamp: user.timestamp.and_then(|timestamp| timestamp.format(&Rfc3339).ok()),
+                prompt,
+                tool_results: results
+                    .iter()
+                    .map(|result| TranscriptToolResult {
+                        tool_use_id: &result.tool_use_id,
+                        success: matches!(result.status, ToolResultStatus::Success),
+                        content: result
+                            .content
+                            .iter()
+                            .filter_map(|block| match block {
+                                ToolUseResultBlock::Json(json) => Some(json),
+                                ToolUseResultBlock::Text(_) => None,
+                            })
+                            .collect(),
+                        text: result
+                            .content
+                            .iter()
+                            .filter_map(|block| match block {
+                                ToolUseResultBlock::Text(text) => Some(text.as_str()),
+                                ToolUseResultBlock::Json(_) => None,
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            });
+            messages.push(TranscriptMessage::Assistant {
+                message_id: assistant.message_id(),
+                content: assistant.content(),
+                tool_uses: assistant
+                    .tool_uses()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|tool_use| TranscriptToolUse {
+                        id: &tool_use.id,
+                        name: &tool_use.name,
+                        args: &tool_use.args,
+                    })
+                    .collect(),
+            });
+        }
+        Self {
+            conversation_id: state.conversation_id(),
+            exported_at: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            messages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::cli::chat::message::{
+        AssistantMessage,
+        AssistantToolUse,
+    };
+    use crate::cli::chat::tool_manager::ToolManager;
+    use crate::database::Database;
+    use crate::platform::Context;
+
+    async fn conversation() -> ConversationState {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut database = Database::new().await.unwrap();
+        let mut state = ConversationState::new(ctx, "id", HashMap::new(), None, None, ToolManager::default()).await;
+        state.set_next_user_message("What is in <main.rs>?".to_string()).await;
+        state.push_assistant_message(
+            AssistantMessage::new_tool_use(None, "Let me look.".to_string(), vec![AssistantToolUse {
+                id: "1".to_string(),
+                name: "fs_read".to_string(),
+                args: serde_json::json!({ "path": "main.rs" }),
+                ..Default::default()
+            }]),
+            &mut database,
+        );
+        state.add_tool_results(vec![ToolUseResult {
+            tool_use_id: "1".to_string(),
+            content: vec![ToolUseResultBlock::Text("```rust\nfn main() {}\n```".to_string())],
+            status: ToolResultStatus::Success,
+        }]);
+        state.push_assistant_message(
+            AssistantMessage::new_response(None, "It has an empty `main`.".to_string()),
+            &mut database,
+        );
+        state
+    }
+
+    #[test]
+    fn test_format_for_path() {
+        assert_eq!(format_for_path(Path::new("chat.MD")), Some(ExportFormat::Markdown));
+        assert_eq!(format_for_path(Path::new("chat.htm")), Some(ExportFormat::Html));
+        assert_eq!(format_for_path(Path::new("chat.json")), Some(ExportFormat::Json));
+        assert_eq!(format_for_path(Path::new("chat.txt")), None);
+        assert_eq!(format_for_path(Path::new("chat")), None);
+    }
+
+    #[test]
+    fn test_fenced() {
+        assert_eq!(fenced("a\n", "sh"), "```sh\na\n```\n");
+        assert_eq!(fenced("```rust\n```", ""), "````\n```rust\n```\n````\n");
+    }
+
+    #[tokio::test]
+    async fn test_export_markdown() {
+        let markdown = export(&conversation().await, ExportFormat::Markdown).unwrap();
+        assert!(markdown.contains("## You ("));
+        assert!(markdown.contains("What is in <main.rs>?"));
+        assert!(markdown.contains("**Tool use: `fs_read`**\n\n```json\n{\n  \"path\": \"main.rs\"\n}\n```"));
+        assert!(markdown.contains("**Result of `fs_read`**\n\n````\n```rust\nfn main() {}\n```\n````"));
+        assert!(markdown.contains("It has an empty `main`."));
+    }
+
+    #[tokio::test]
+    async fn test_export_html() {
+        let html = export(&conversation().await, ExportFormat::Html).unwrap();
+        assert!(html.contains("<p>What is in &lt;main.rs&gt;?</p>"));
+        assert!(html.contains("<pre><code class=\"language-json\">"));
+        assert!(html.contains("<pre><code>```rust\nfn main() {}\n```\n</code></pre>"));
+        assert!(html.contains("<p>It has an empty <code>main</code>.</p>"));
+    }
+
+    #[tokio::test]
+    async fn test_export_json() {
+        let json = export(&conversation().await, ExportFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["conversation_id"], "id");
+        let messages = json["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0]["role"], "user");
+        assert!(messages[0]["timestamp"].is_string());
+        assert_eq!(messages[1]["tool_uses"][0]["name"], "fs_read");
+        assert_eq!(messages[2]["tool_results"][0]["tool_use_id"], "1");
+        assert_eq!(messages[2]["tool_results"][0]["text"][0], "```rust\nfn main() {}\n```");
+        assert_eq!(messages[3]["content"], "It has an empty `main`.");
+    }
+
+    #[test]
+    fn test_inline_html() {
+        assert_eq!(
+            inline_html("a **b** `<c>`"),
+            "a <strong>b</strong> <code>&lt;c&gt;</code>"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use regex::Regex;
+
+/// Lines shown before and after a matching line.
+const CONTEXT_LINES: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Author {
+    User,
+    Assistant,
+}
+
+/// A line of the conversation matching a `/find` pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindMatch {
+    /// The index of the exchange in the conversation history.
+    pub history_index: usize,
+    /// The number of the prompt the match belongs to, as used by `/edit <n>`. Responses and tool
+    /// uses belong to the prompt before them.
+    pub message: usize,
+    pub author: Author,
+    /// The number of the fenced code block containing the match within its message, as used by
+    /// `/copy <n>`.
+    pub code_block: Option<usize>,
+    /// The matching line along with the lines around it.
+    pub context: Vec<String>,
+    /// The position of the matching line in [Self::context].
+    pub line: usize,
+}
+
+/// Searches the prompts and responses of `history`, given as `(prompt, response)` for each
+/// exchange, for lines matching `pattern`.
+pub fn find_in_conversation<'a>(
+    history: impl IntoIterator<Item = (Option<&'a str>, &'a str)>,
+    pattern: &Regex,
+) -> Vec<FindMatch> {
+    let mut matches = Vec::new();
+    let mut message = 0;
+    for (history_index, (prompt, response)) in history.into_iter().enumerate() {
+        if let Some(prompt) = prompt {
+            message += 1;
+            find_in_message(prompt, pattern, |found| {
+                matches.push(found.into_match(history_index, message, Author::User));
+            });
+        }
+        find_in_message(response, pattern, |found| {
+            matches.push(found.into_match(history_index, message.max(1), Author::Assistant));
+        });
+    }
+    matches
+}
+
+struct LineMatch {
+    code_block: Option<usize>,
+    context: Vec<String>,
+    line: usize,
+}
+
+impl LineMatch {
+    fn into_match(self, history_index: usize, message: usize, author: Author) -> FindMatch {
+        FindMatch {
+            history_index,
+            message,
+            author,
+            code_block: self.code_block,
+            context: self.context,
+            line: self.line,
+        }
+    }
+}
+
+fn find_in_message(text: &str, pattern: &Regex, mut on_match: impl FnMut(LineMatch)) {
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut code_blocks = 0;
+    let mut in_code_block = false;
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim_start().starts_with("```") {
+            if !in_code_block {
+                code_blocks += 1;
+            }
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if !pattern.is_match(line) {
+            continue;
+        }
+
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(lines.len());
+        on_match(LineMatch {
+            code_block: in_code_block.then_some(code_blocks),
+            context: lines[start..end].iter().map(|line| (*line).to_owned()).collect(),
+            line: i - start,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_in_conversation() {
+        let history = [
+            (
+                Some("how do I parse json?"),
+                "Use serde:\n```rust\nlet value: Value = serde_json::from_str(s)?;\n```",
+            ),
+            (None, "The JSON file was read."),
+            (Some("thanks"), "You're welcome!"),
+        ];
+        let matches = find_in_conversation(history, &Regex::new("(?i)json").unwrap());
+
+        assert_eq!(matches, vec![
+            FindMatch {
+                history_index: 0,
+                message: 1,
+                author: Author::User,
+                code_block: None,
+                context: vec!["how do I parse json?".to_string()],
+                line: 0,
+            },
+            FindMatch {
+                history_index: 0,
+                message: 1,
+                author: Author::Assistant,
+                code_block: Some(1),
+                context: vec![
+                    "```rust".to_string(),
+                    "let value: Value = serde_json::from_str(s)?;".to_string(),
+                    "```".to_string(),
+                ],
+                line: 1,
+            },
+            FindMatch {
+                history_index: 1,
+                message: 1,
+                author: Author::Assistant,
+                code_block: None,
+                context: vec!["The JSON file was read.".to_string()],
+                line: 0,
+            },
+        ]);
+
+        assert!(find_in_conversation(history, &Regex::new("yaml").unwrap()).is_empty());
+    }
+}
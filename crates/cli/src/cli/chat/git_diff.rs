@@ -0,0 +1,140 @@
+use std::path::Path;
+
+use eyre::{
+    Result,
+    bail,
+};
+
+/// The context rule adding the changes of the working tree, see `/context add --diff`.
+pub const DIFF_RULE: &str = "git:diff";
+
+/// The context rule adding the staged changes, see `/context add --diff --staged`.
+pub const STAGED_DIFF_RULE: &str = "git:diff --staged";
+
+/// Whether `rule` adds a diff to the context, and whether it's the staged one.
+pub fn diff_rule(rule: &str) -> Option<bool> {
+    match rule {
+        DIFF_RULE => Some(false),
+        STAGED_DIFF_RULE => Some(true),
+        _ => None,
+    }
+}
+
+/// The output of `git diff` in `dir`, or of `git diff --staged` with `staged`.
+pub async fn git_diff(dir: &Path, staged: bool) -> Result<String> {
+    let mut command = tokio::process::Command::new("git");
+    command.current_dir(dir).args(["diff", "--no-color", "--no-ext-diff"]);
+    if staged {
+        command.arg("--staged");
+    }
+    let output = command.output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "git diff failed: {}",
+            stderr.lines().next().unwrap_or("not a git repository")
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `diff` in at most about `max_chars`. The diffs of the smallest files are kept whole, those
+/// of the others are replaced by a line saying how many lines they change.
+pub fn truncate_diff(diff: &str, max_chars: usize) -> String {
+    if diff.len() <= max_chars {
+        return diff.to_string();
+    }
+
+    // Each `diff --git` line starts the diff of another file
+    let mut files = Vec::new();
+    let mut start = 0;
+    for (offset, _) in diff.match_indices("diff --git ") {
+        if offset != 0 && diff.as_bytes()[offset - 1] == b'\n' {
+            files.push(&diff[start..offset]);
+            start = offset;
+        }
+    }
+    files.push(&diff[start..]);
+
+    let summaries = files.iter().map(|file| summarize(file)).collect::<Vec<_>>();
+    let mut budget = max_chars.saturating_sub(summaries.iter().map(String::len).sum());
+    let mut by_size = (0..files.len()).collect::<Vec<_>>();
+    by_size.sort_by_key(|&index| files[index].len());
+    let mut kept = vec![false; files.len()];
+    for index in by_size {
+        // The whole diff takes the place of its summary
+        let extra = files[index].len().saturating_sub(summaries[index].len());
+        if extra > budget {
+            break;
+        }
+        budget -= extra;
+        kept[index] = true;
+    }
+
+    files
+        .iter()
+        .zip(summaries)
+        .zip(kept)
+        .map(|((file, summary), kept)| match kept {
+            true => (*file).to_string(),
+            false => summary,
+        })
+        .collect()
+}
+
+/// The first line of the diff of a file, and how many lines it changes.
+fn summarize(file: &str) -> String {
+    let header = file.lines().next().unwrap_or_default();
+    let (mut added, mut removed) = (0, 0);
+    for line in file.lines() {
+        if line.starts_with('+') && !line.starts_with("+++") {
+            added += 1;
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            removed += 1;
+        }
+    }
+    format!("{header}\n[{added} lines added and {removed} removed, left out to fit the context]\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_diff(name: &str, lines: usize) -> String {
+        let mut diff = format!(
+            "diff --git a/{name} b/{name}\nindex 1111111..2222222 100644\n--- a/{name}\n+++ b/{name}\n@@ -1,{lines} +1,{lines} @@\n"
+        );
+        for line in 0..lines {
+            diff.push_str(&format!("-old {line}\n+new {line}\n"));
+        }
+        diff
+    }
+
+    #[test]
+    fn test_diff_rule() {
+        assert_eq!(diff_rule(DIFF_RULE), Some(false));
+        assert_eq!(diff_rule(STAGED_DIFF_RULE), Some(true));
+        assert_eq!(diff_rule("src/**/*.rs"), None);
+    }
+
+    #[test]
+    fn test_truncate_diff() {
+        let small = file_diff("small.rs", 2);
+        let large = file_diff("large.rs", 200);
+        let medium = file_diff("medium.rs", 10);
+        let diff = format!("{small}{large}{medium}");
+        assert_eq!(truncate_diff(&diff, diff.len()), diff);
+
+        let truncated = truncate_diff(&diff, small.len() + medium.len() + 200);
+        assert_eq!(
+            truncated,
+            format!(
+                "{small}diff --git a/large.rs b/large.rs\n[200 lines added and 200 removed, left out to fit the context]\n{medium}"
+            )
+        );
+
+        let truncated = truncate_diff(&diff, small.len() + summarize(&large).len() + summarize(&medium).len());
+        assert!(truncated.starts_with(&small));
+        assert!(truncated.ends_with("[10 lines added and 10 removed, left out to fit the context]\n"));
+    }
+}
@@ -34,8 +34,13 @@ use spinners::{
     Spinner,
     Spinners,
 };
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
 
+use super::tools::Tool;
+use super::tools::rules::ToolPattern;
 use super::util::truncate_safe;
+use crate::platform::Context;
 
 const DEFAULT_TIMEOUT_MS: u64 = 30_000;
 const DEFAULT_MAX_OUTPUT_SIZE: usize = 1024 * 10;
@@ -66,6 +71,15 @@ pub struct Hook {
     /// The bash command to execute
     pub command: Option<String>, // For inline hooks
 
+    /// The tools a [HookTrigger::PreToolUse] or [HookTrigger::PostToolUse] hook runs for, as
+    /// patterns like those of `allowedTools` in `mcp.json`, all of them when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+
+    /// Whether the output of a tool use hook is added to the result of the tool sent to the model
+    #[serde(default)]
+    pub append_output: bool,
+
     // Internal data
     #[serde(skip)]
     pub name: String,
@@ -83,6 +97,8 @@ impl Hook {
             max_output_size: Self::default_max_output_size(),
             cache_ttl_seconds: Self::default_cache_ttl_seconds(),
             command: Some(command),
+            tools: Vec::new(),
+            append_output: false,
             is_global: false,
             name: "new hook".to_string(),
         }
@@ -103,6 +119,19 @@ impl Hook {
     fn default_cache_ttl_seconds() -> u64 {
         DEFAULT_CACHE_TTL_SECONDS
     }
+
+    /// Whether the hook runs for the use of `tool`, named `tool_name`. A pattern in error matches
+    /// every tool, rather than a policy hook being skipped.
+    pub fn runs_for(&self, ctx: &Context, tool_name: &str, tool: &Tool) -> bool {
+        self.tools.is_empty()
+            || self.tools.iter().any(|pattern| match ToolPattern::parse(pattern) {
+                Ok(pattern) => pattern.matches(ctx, tool_name, tool),
+                Err(err) => {
+                    warn!(?err, hook = %self.name, "Invalid tool pattern in the hook");
+                    true
+                },
+            })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -117,6 +146,43 @@ pub enum HookType {
 pub enum HookTrigger {
     ConversationStart,
     PerPrompt,
+    /// Before a tool runs, the hook stopping it by exiting with an error
+    PreToolUse,
+    /// After a tool runs
+    PostToolUse,
+}
+
+impl HookTrigger {
+    pub const ALL: [HookTrigger; 4] = [
+        HookTrigger::ConversationStart,
+        HookTrigger::PerPrompt,
+        HookTrigger::PreToolUse,
+        HookTrigger::PostToolUse,
+    ];
+
+    pub fn is_tool_use(&self) -> bool {
+        matches!(self, HookTrigger::PreToolUse | HookTrigger::PostToolUse)
+    }
+}
+
+impl std::fmt::Display for HookTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookTrigger::ConversationStart => write!(f, "conversation_start"),
+            HookTrigger::PerPrompt => write!(f, "per_prompt"),
+            HookTrigger::PreToolUse => write!(f, "pre_tool_use"),
+            HookTrigger::PostToolUse => write!(f, "post_tool_use"),
+        }
+    }
+}
+
+/// What a [HookTrigger::PreToolUse] or [HookTrigger::PostToolUse] hook printed.
+#[derive(Debug, Clone)]
+pub struct ToolHookOutput {
+    /// Whether the hook exited with 0, a [HookTrigger::PreToolUse] hook stopping the tool otherwise
+    pub success: bool,
+    /// Its stdout, followed by its stderr when it failed
+    pub output: String,
 }
 
 #[derive(Debug, Clone)]
@@ -270,7 +336,9 @@ impl HookExecutor {
         results.iter().skip(start_cache_index).for_each(|(_, (hook, output))| {
             let expiry = match hook.trigger {
                 HookTrigger::ConversationStart => None,
-                HookTrigger::PerPrompt => Some(Instant::now() + Duration::from_secs(hook.cache_ttl_seconds)),
+                HookTrigger::PerPrompt | HookTrigger::PreToolUse | HookTrigger::PostToolUse => {
+                    Some(Instant::now() + Duration::from_secs(hook.cache_ttl_seconds))
+                },
             };
             self.insert_cache(hook, CachedHook {
                 output: output.clone(),
@@ -309,17 +377,7 @@ impl HookExecutor {
             Ok(result) => {
                 let result = result?;
                 if result.status.success() {
-                    let stdout = result.stdout.to_str_lossy();
-                    let stdout = format!(
-                        "{}{}",
-                        truncate_safe(&stdout, hook.max_output_size),
-                        if stdout.len() > hook.max_output_size {
-                            " ... truncated"
-                        } else {
-                            ""
-                        }
-                    );
-                    Ok(stdout)
+                    Ok(truncate_output(&result.stdout.to_str_lossy(), hook.max_output_size))
                 } else {
                     Err(eyre!("command returned non-zero exit code: {}", result.status))
                 }
@@ -328,6 +386,56 @@ impl HookExecutor {
         }
     }
 
+    /// Runs `hook` for a tool use, with `event` describing it written to its stdin as JSON. Never
+    /// cached, each use of a tool being different.
+    pub async fn run_tool_hook(&self, hook: &Hook, event: &serde_json::Value) -> ToolHookOutput {
+        let failed = |output: String| ToolHookOutput { success: false, output };
+        let Some(command) = hook.command.as_ref() else {
+            return failed("no command specified".to_string());
+        };
+
+        let child = tokio::process::Command::new("bash")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => return failed(format!("failed to run the command: {err}")),
+        };
+        let stdin = child.stdin.take();
+        let event = event.to_string();
+        let run = async move {
+            if let Some(mut stdin) = stdin {
+                // A hook not reading the event closes its stdin, which isn't an error
+                let _ = stdin.write_all(event.as_bytes()).await;
+            }
+            child.wait_with_output().await
+        };
+
+        match tokio::time::timeout(Duration::from_millis(hook.timeout_ms), run).await {
+            Ok(Ok(result)) => {
+                let mut output = result.stdout.to_str_lossy().into_owned();
+                if !result.status.success() {
+                    output.push_str(&result.stderr.to_str_lossy());
+                    if output.trim().is_empty() {
+                        output = format!("exited with {}", result.status);
+                    }
+                }
+                ToolHookOutput {
+                    success: result.status.success(),
+                    output: truncate_output(&output, hook.max_output_size),
+                }
+            },
+            Ok(Err(err)) => failed(format!("failed to run the command: {err}")),
+            // Dropping the child kills it
+            Err(_) => failed(format!("command timed out after {} ms", hook.timeout_ms)),
+        }
+    }
+
     /// Will return a cached hook's output if it exists and isn't expired.
     fn get_cache(&self, hook: &Hook) -> Option<String> {
         let cache = if hook.is_global {
@@ -360,6 +468,18 @@ impl HookExecutor {
     }
 }
 
+fn truncate_output(output: &str, max_output_size: usize) -> String {
+    format!(
+        "{}{}",
+        truncate_safe(output, max_output_size),
+        if output.len() > max_output_size {
+            " ... truncated"
+        } else {
+            ""
+        }
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Stdout;
@@ -541,6 +661,33 @@ mod tests {
         assert_eq!(executor.get_cache(&hook), None);
     }
 
+    #[tokio::test]
+    async fn test_run_tool_hook() {
+        let executor = HookExecutor::new();
+        let event = serde_json::json!({ "tool_name": "execute_bash", "tool_input": { "command": "git push" } });
+
+        // Reads the tool use from its stdin and stops the tool by failing
+        let policy = Hook::new_inline_hook(
+            HookTrigger::PreToolUse,
+            "grep -q 'git push' && { echo 'no pushing'; echo 'denied' >&2; exit 1; } || echo ok".to_string(),
+        );
+        let run = executor.run_tool_hook(&policy, &event).await;
+        assert!(!run.success);
+        assert_eq!(run.output, "no pushing\ndenied\n");
+
+        let run = executor
+            .run_tool_hook(&policy, &serde_json::json!({ "tool_name": "fs_read" }))
+            .await;
+        assert!(run.success);
+        assert_eq!(run.output, "ok\n");
+
+        let mut slow = Hook::new_inline_hook(HookTrigger::PreToolUse, "sleep 2".to_string());
+        slow.timeout_ms = 100;
+        let run = executor.run_tool_hook(&slow, &event).await;
+        assert!(!run.success);
+        assert!(run.output.contains("timed out"));
+    }
+
     #[tokio::test]
     async fn test_max_output_size() {
         let mut executor = HookExecutor::new();
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use eyre::{
+    Result,
+    bail,
+};
+use rand::distr::{
+    Alphanumeric,
+    SampleString,
+};
+use serde_json::Value;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use super::cli::ChatImport;
+use super::conversation_state::ConversationState;
+use super::message::{
+    AssistantMessage,
+    UserMessage,
+};
+use super::session::{
+    default_session_name,
+    is_session_name,
+    name_from_title,
+    save_session,
+    session_path,
+};
+use super::tool_manager::ToolManager;
+use super::util::shared_writer::SharedWriter;
+use crate::platform::Context;
+use crate::util::{
+    CLI_BINARY_NAME,
+    directories,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Message {
+    role: Role,
+    text: String,
+    timestamp: Option<OffsetDateTime>,
+}
+
+/// A conversation exported from another assistant.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Transcript {
+    title: Option<String>,
+    updated: Option<OffsetDateTime>,
+    messages: Vec<Message>,
+}
+
+/// Reads the conversations of an export: a JSON file from ChatGPT, Claude or `q chat export`, or
+/// a Markdown transcript with a heading or bold line naming who speaks before each message.
+fn parse(contents: &str) -> Result<Vec<Transcript>> {
+    let Ok(json) = serde_json::from_str::<Value>(contents) else {
+        return Ok(vec![parse_markdown(contents)]);
+    };
+    let conversations = match json {
+        Value::Array(conversations) => conversations,
+        conversation => vec![conversation],
+    };
+    conversations
+        .iter()
+        .map(|conversation| {
+            if conversation.get("mapping").is_some() {
+                Ok(parse_chatgpt(conversation))
+            } else if let Some(messages) = conversation.get("chat_messages") {
+                Ok(parse_claude(conversation, messages))
+            } else if let Some(messages) = conversation.get("messages") {
+                Ok(parse_q(messages))
+            } else {
+                bail!("Unknown JSON format, expected an export from ChatGPT, Claude or {CLI_BINARY_NAME} chat export")
+            }
+        })
+        .collect()
+}
+
+/// ChatGPT keeps every edit of a conversation as a tree of messages, of which `current_node` is
+/// the last message of the version that is shown.
+fn parse_chatgpt(conversation: &Value) -> Transcript {
+    let mapping = &conversation["mapping"];
+    let mut messages = Vec::new();
+    let mut node = conversation["current_node"].as_str();
+    while let Some(id) = node {
+        let message = &mapping[id]["message"];
+        let role = match message["author"]["role"].as_str() {
+            Some("user") => Some(Role::User),
+            Some("assistant") => Some(Role::Assistant),
+            _ => None,
+        };
+        let text = message["content"]["parts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if let Some(role) = role {
+            messages.push(Message {
+                role,
+                text,
+                timestamp: unix_timestamp(&message["create_time"]),
+            });
+        }
+        node = mapping[id]["parent"].as_str();
+    }
+    messages.reverse();
+
+    Transcript {
+        title: conversation["title"].as_str().map(str::to_string),
+        updated: unix_timestamp(&conversation["update_time"]),
+        messages,
+    }
+}
+
+fn parse_claude(conversation: &Value, messages: &Value) -> Transcript {
+    let messages = messages
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|message| {
+            let role = match message["sender"].as_str()? {
+                "human" => Role::User,
+                "assistant" => Role::Assistant,
+                _ => return None,
+            };
+            let text = match message["text"].as_str() {
+                Some(text) if !text.is_empty() => text.to_string(),
+                _ => message["content"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|content| content["text"].as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            };
+            Some(Message {
+                role,
+                text,
+                timestamp: rfc3339_timestamp(&message["created_at"]),
+            })
+        })
+        .collect();
+
+    Transcript {
+        title: conversation["name"].as_str().map(str::to_string),
+        updated: rfc3339_timestamp(&conversation["updated_at"]),
+        messages,
+    }
+}
+
+/// The JSON written by `q chat export`, of which tool results are left out.
+fn parse_q(messages: &Value) -> Transcript {
+    let messages = messages
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|message| {
+            let (role, text) = match message["role"].as_str()? {
+                "user" => (Role::User, message["prompt"].as_str()?),
+                "assistant" => (Role::Assistant, message["content"].as_str()?),
+                _ => return None,
+            };
+            Some(Message {
+                role,
+                text: text.to_string(),
+                timestamp: rfc3339_timestamp(&message["timestamp"]),
+            })
+        })
+        .collect();
+
+    Transcript {
+        messages,
+        ..Default::default()
+    }
+}
+
+fn parse_markdown(contents: &str) -> Transcript {
+    let mut transcript = Transcript::default();
+    let mut current: Option<Message> = None;
+    let mut in_code_block = false;
+
+    for line in contents.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+        let speaker = match in_code_block {
+            true => None,
+            false => speaker(line),
+        };
+        match (speaker, current.as_mut()) {
+            (Some((role, rest)), _) => {
+                transcript.messages.extend(current.take());
+                current = Some(Message {
+                    role,
+                    text: rest.to_string(),
+                    timestamp: None,
+                });
+            },
+            (None, Some(message)) => {
+                message.text.push('\n');
+                message.text.push_str(line);
+            },
+            // Before the first message, only the title is kept
+            (None, None) => {
+                if let Some(title) = line.strip_prefix("# ") {
+                    transcript.title.get_or_insert_with(|| title.trim().to_string());
+                }
+            },
+        }
+    }
+    transcript.messages.extend(current);
+    transcript
+}
+
+/// Who speaks from `line` on, e.g. `## User`, `**Assistant:**` or `## You (2025-04-30 15:30)`,
+/// along with the rest of the line.
+fn speaker(line: &str) -> Option<(Role, &str)> {
+    let line = line.trim();
+    let (label, rest) = if line.starts_with('#') {
+        (line.trim_start_matches('#'), "")
+    } else {
+        line.strip_prefix("**")?.split_once("**")?
+    };
+    let label = label.split(" (").next().unwrap_or_default();
+    let role = match label.trim().trim_end_matches(':').to_lowercase().as_str() {
+        "you" | "user" | "human" | "me" => Role::User,
+        "assistant" | "amazon q" | "q" | "chatgpt" | "claude" | "ai" => Role::Assistant,
+        _ => return None,
+    };
+    Some((role, rest.trim_start_matches(':').trim()))
+}
+
+fn unix_timestamp(value: &Value) -> Option<OffsetDateTime> {
+    let seconds = value.as_f64()?;
+    OffsetDateTime::from_unix_timestamp_nanos((seconds * 1e9) as i128).ok()
+}
+
+fn rfc3339_timestamp(value: &Value) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(value.as_str()?, &Rfc3339).ok()
+}
+
+/// Pairs the messages of `transcript` into turns: consecutive messages of the same role are
+/// joined, and messages before the first prompt or after the last response are left out.
+fn turns(transcript: Transcript) -> (Vec<(Message, Message)>, usize) {
+    let mut merged: Vec<Message> = Vec::new();
+    for message in transcript.messages {
+        if message.text.trim().is_empty() {
+            continue;
+        }
+        match merged.last_mut() {
+            Some(last) if last.role == message.role => {
+                last.text.push_str("\n\n");
+                last.text.push_str(message.text.trim());
+            },
+            None if message.role == Role::Assistant => (),
+            _ => merged.push(Message {
+                text: message.text.trim().to_string(),
+                ..message
+            }),
+        }
+    }
+
+    let mut turns = Vec::new();
+    let mut messages = merged.into_iter();
+    let mut skipped = 0;
+    while let Some(user) = messages.next() {
+        match messages.next() {
+            Some(assistant) => turns.push((user, assistant)),
+            None => skipped += 1,
+        }
+    }
+    (turns, skipped)
+}
+
+pub async fn execute_import(args: ChatImport) -> Result<ExitCode> {
+    let ctx = Context::new();
+    let mut output = SharedWriter::stdout();
+
+    let contents = ctx.fs().read_to_string(&args.file).await?;
+    let mut transcripts = parse(&contents)?;
+    let count = transcripts.len();
+    if let Some(title) = &args.title {
+        let title = title.to_lowercase();
+        transcripts.retain(|transcript| {
+            transcript
+                .title
+                .as_ref()
+                .is_some_and(|candidate| candidate.to_lowercase().contains(&title))
+        });
+    }
+    // The most recent conversation is imported from exports with several of them
+    let Some(transcript) = transcripts.into_iter().max_by_key(|transcript| transcript.updated) else {
+        bail!("No conversation in {} has a title matching the --title", args.file);
+    };
+
+    let name = match args.name {
+        Some(name) if !is_session_name(&name) => {
+            bail!("'{name}' is not a valid name, use only letters, digits, - and _")
+        },
+        Some(name) => name,
+        None => transcript
+            .title
+            .as_deref()
+            .and_then(name_from_title)
+            .or_else(|| {
+                Path::new(&args.file)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(name_from_title)
+            })
+            .unwrap_or_else(default_session_name),
+    };
+    let dir = directories::chat_sessions_dir()?;
+    if ctx.fs().exists(session_path(&dir, &name)) && !args.force {
+        bail!("A conversation is already saved as '{name}'. Pass --name to choose another, or --force to replace it");
+    }
+
+    let title = transcript.title.clone();
+    let (turns, skipped) = turns(transcript);
+    if turns.is_empty() {
+        bail!("No messages to import from {}", args.file);
+    }
+
+    let conversation_id = Alphanumeric.sample_string(&mut rand::rng(), 9);
+    let mut state = ConversationState::new(
+        Arc::clone(&ctx),
+        &conversation_id,
+        HashMap::new(),
+        None,
+        None,
+        ToolManager::default(),
+    )
+    .await;
+    for (user, assistant) in &turns {
+        let mut prompt = UserMessage::new_prompt(user.text.clone());
+        prompt.timestamp = user.timestamp.or(prompt.timestamp);
+        state.push_history_entry(prompt, AssistantMessage::new_response(None, assistant.text.clone()));
+    }
+    if let Some(title) = &title {
+        state.set_title(title.clone());
+    }
+    save_session(&ctx, &dir, &name, &state).await?;
+
+    let from = title.map(|title| format!(" from '{title}'")).unwrap_or_default();
+    writeln!(output, "\n✓ Imported {} messages{from} as {name}", turns.len() * 2)?;
+    if skipped > 0 {
+        writeln!(output, "  The last prompt had no response and was left out")?;
+    }
+    if count > 1 && args.title.is_none() {
+        writeln!(
+            output,
+            "  The file has {count} conversations, this is the most recent. Pick another with --title"
+        )?;
+    }
+    writeln!(output, "\nContinue it with {CLI_BINARY_NAME} chat --resume {name}\n")?;
+    output.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(transcript: &Transcript) -> Vec<(Role, &str)> {
+        transcript
+            .messages
+            .iter()
+            .map(|message| (message.role, message.text.trim()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_chatgpt() {
+        let json = serde_json::json!([{
+            "title": "SQS or SNS",
+            "update_time": 1_714_490_000.5,
+            "current_node": "c",
+            "mapping": {
+                "root": { "message": null, "parent": null },
+                "s": { "message": { "author": { "role": "system" }, "content": { "parts": [""] } }, "parent": "root" },
+                "a": {
+                    "message": { "author": { "role": "user" }, "content": { "parts": ["SQS or SNS?"] }, "create_time": 1_714_490_000.0 },
+                    "parent": "s"
+                },
+                "b": { "message": { "author": { "role": "assistant" }, "content": { "parts": ["It depends."] } }, "parent": "a" },
+                "edited": { "message": { "author": { "role": "assistant" }, "content": { "parts": ["SNS."] } }, "parent": "a" },
+                "c": { "message": { "author": { "role": "user" }, "content": { "parts": ["On what?"] } }, "parent": "b" }
+            }
+        }]);
+        let transcripts = parse(&json.to_string()).unwrap();
+        assert_eq!(transcripts[0].title.as_deref(), Some("SQS or SNS"));
+        assert_eq!(texts(&transcripts[0]), vec![
+            (Role::User, "SQS or SNS?"),
+            (Role::Assistant, "It depends."),
+            (Role::User, "On what?"),
+        ]);
+        assert!(transcripts[0].messages[0].timestamp.is_some());
+    }
+
+    #[test]
+    fn test_parse_claude() {
+        let json = serde_json::json!([{
+            "name": "Terraform state",
+            "updated_at": "2025-04-30T15:30:00.000000Z",
+            "chat_messages": [
+                { "sender": "human", "text": "Where is my state?", "created_at": "2025-04-30T15:29:00Z" },
+                { "sender": "assistant", "text": "", "content": [{ "type": "text", "text": "In S3." }] }
+            ]
+        }]);
+        let transcripts = parse(&json.to_string()).unwrap();
+        assert_eq!(transcripts[0].title.as_deref(), Some("Terraform state"));
+        assert!(transcripts[0].updated.is_some());
+        assert_eq!(texts(&transcripts[0]), vec![
+            (Role::User, "Where is my state?"),
+            (Role::Assistant, "In S3."),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_markdown() {
+        let markdown = "# Debugging
+
+## You (2025-04-30 15:30 UTC)
+
+Why does this fail?
+
+```md
+## User
+not a speaker
+```
+
+**Assistant:** Because of the cache.
+Clear it.
+";
+        let transcripts = parse(markdown).unwrap();
+        assert_eq!(transcripts[0].title.as_deref(), Some("Debugging"));
+        assert_eq!(texts(&transcripts[0]), vec![
+            (Role::User, "Why does this fail?\n\n```md\n## User\nnot a speaker\n```"),
+            (Role::Assistant, "Because of the cache.\nClear it."),
+        ]);
+    }
+
+    #[test]
+    fn test_turns() {
+        let message = |role, text: &str| Message {
+            role,
+            text: text.to_string(),
+            timestamp: None,
+        };
+        let (turns, skipped) = turns(Transcript {
+            messages: vec![
+                message(Role::Assistant, "Hi, how can I help?"),
+                message(Role::User, "One"),
+                message(Role::User, "Two"),
+                message(Role::Assistant, "Three"),
+                message(Role::User, "   "),
+                message(Role::User, "Four"),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(skipped, 1);
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].0.text, "One\n\nTwo");
+        assert_eq!(turns[0].1.text, "Three");
+    }
+}
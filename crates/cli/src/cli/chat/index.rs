@@ -0,0 +1,580 @@
+use std::collections::BTreeMap;
+use std::io::Write as _;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use eyre::{
+    Result,
+    bail,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use super::cli::Index;
+use super::context::walk_dir;
+use super::context_budget::STOP_WORDS;
+use super::session::format_local_time;
+use super::util::shared_writer::SharedWriter;
+use super::util::truncate_safe;
+use crate::database::Database;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+use crate::util::directories;
+
+/// How many chunks are retrieved for each prompt, unless `chat.index.topK` is set.
+const DEFAULT_TOP_K: usize = 5;
+
+/// The lines of each chunk, the last [CHUNK_OVERLAP] of them starting the next one as well so that
+/// code around the end of a chunk can be found along with what comes before and after it.
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 10;
+
+/// In characters, the most of a chunk that is indexed and sent, for files with very long lines.
+const MAX_CHUNK_CHARS: usize = 4_000;
+
+/// In bytes, larger files are skipped, being generated or data more often than not.
+const MAX_FILE_SIZE: u64 = 512 * 1024;
+
+/// Files as large as they are unhelpful to find code in.
+const SKIPPED_FILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "pnpm-lock.yaml",
+    "yarn.lock",
+    "go.sum",
+];
+
+/// The number of buckets terms are hashed into, the dimensions of the vectors.
+const DIMENSIONS: u32 = 1 << 16;
+
+/// Chunks less similar than this to a prompt aren't retrieved, sharing little more than a word.
+const MIN_SIMILARITY: f32 = 0.05;
+
+/// A sparse vector, the weights of its non-zero dimensions in order.
+type Vector = Vec<(u32, f32)>;
+
+/// Lines of a file of the workspace, retrieved for the prompts they are similar to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    /// The path of the file, relative to the root of the workspace.
+    pub path: String,
+    /// The first and last lines of the chunk, from 1.
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    vector: Vector,
+}
+
+impl Chunk {
+    /// Where the chunk is from, e.g. `src/retry.rs:41-80`.
+    pub fn source(&self) -> String {
+        format!("{}:{}-{}", self.path, self.start_line, self.end_line)
+    }
+}
+
+/// The files of a workspace split into chunks, each embedded into a vector so that those similar
+/// to a prompt can be retrieved. Built with `q index build` and kept in
+/// [directories::chat_index_dir].
+///
+/// The vectors are TF-IDF weights of the words of the chunks and their paths, hashed into
+/// [DIMENSIONS] buckets. They are computed locally, nothing is sent to build the index.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkspaceIndex {
+    pub root: PathBuf,
+    pub built_at: SystemTime,
+    pub files: usize,
+    /// How many chunks each bucket has terms in, for the IDF of the terms of prompts.
+    document_frequency: BTreeMap<u32, u32>,
+    pub chunks: Vec<Chunk>,
+}
+
+impl WorkspaceIndex {
+    /// Indexes the files under `root` that aren't ignored by `.gitignore` files.
+    pub async fn build(ctx: &Context, root: &Path) -> Result<Self> {
+        let mut files = Vec::new();
+        for path in walk_dir(ctx, root, None).await? {
+            if path
+                .file_name()
+                .is_some_and(|name| SKIPPED_FILES.iter().any(|skipped| name == *skipped))
+            {
+                continue;
+            }
+            if tokio::fs::metadata(&path).await?.len() > MAX_FILE_SIZE {
+                continue;
+            }
+            // Binary files are skipped
+            let Ok(text) = ctx.fs().read_to_string(&path).await else {
+                continue;
+            };
+            if text.contains('\0') || text.trim().is_empty() {
+                continue;
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            files.push((relative.to_string_lossy().to_string(), text));
+        }
+        Ok(Self::from_files(root.to_path_buf(), files))
+    }
+
+    /// Indexes `files`, given as their paths relative to `root` along with their contents.
+    pub fn from_files(root: PathBuf, files: Vec<(String, String)>) -> Self {
+        let mut chunks = Vec::new();
+        let mut counts = Vec::new();
+        for (path, text) in &files {
+            let lines = text.lines().collect::<Vec<_>>();
+            let mut start = 0;
+            while start < lines.len() {
+                let end = (start + CHUNK_LINES).min(lines.len());
+                let text = lines[start..end].join("\n");
+                let text = truncate_safe(&text, MAX_CHUNK_CHARS).to_string();
+                if !text.trim().is_empty() {
+                    counts.push(term_counts(&format!("{path}\n{text}")));
+                    chunks.push(Chunk {
+                        path: path.clone(),
+                        start_line: start + 1,
+                        end_line: end,
+                        text,
+                        vector: Vector::new(),
+                    });
+                }
+                if end == lines.len() {
+                    break;
+                }
+                start = end - CHUNK_OVERLAP;
+            }
+        }
+
+        let mut document_frequency = BTreeMap::new();
+        for bucket in counts.iter().flat_map(BTreeMap::keys) {
+            *document_frequency.entry(*bucket).or_insert(0) += 1;
+        }
+        let mut index = Self {
+            root,
+            built_at: SystemTime::now(),
+            files: files.len(),
+            document_frequency,
+            chunks,
+        };
+        let vectors = counts.iter().map(|counts| index.weigh(counts)).collect::<Vec<_>>();
+        for (chunk, vector) in index.chunks.iter_mut().zip(vectors) {
+            chunk.vector = vector;
+        }
+        index
+    }
+
+    /// The TF-IDF weights of `counts`, normalized so that the dot product of two vectors is their
+    /// cosine similarity.
+    fn weigh(&self, counts: &BTreeMap<u32, u32>) -> Vector {
+        let chunks = self.chunks.len() as f32;
+        let mut vector = counts
+            .iter()
+            .map(|(bucket, count)| {
+                let frequency = self.document_frequency.get(bucket).copied().unwrap_or(0) as f32;
+                let idf = ((chunks + 1.0) / (frequency + 1.0)).ln() + 1.0;
+                (*bucket, (1.0 + (*count as f32).ln()) * idf)
+            })
+            .collect::<Vector>();
+        let norm = vector.iter().map(|(_, weight)| weight * weight).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            for (_, weight) in &mut vector {
+                *weight /= norm;
+            }
+        }
+        vector
+    }
+
+    /// Up to `top_k` chunks the most similar to `query`, from the most similar, along with their
+    /// similarity. Chunks overlapping a more similar one of the same file are left out.
+    pub fn search(&self, query: &str, top_k: usize) -> Vec<(f32, &Chunk)> {
+        let query = self.weigh(&term_counts(query));
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut ranked = self
+            .chunks
+            .iter()
+            .map(|chunk| (similarity(&query, &chunk.vector), chunk))
+            .filter(|(similarity, _)| *similarity >= MIN_SIMILARITY)
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        let mut results: Vec<(f32, &Chunk)> = Vec::new();
+        for (similarity, chunk) in ranked {
+            if results.len() == top_k {
+                break;
+            }
+            let overlaps = results.iter().any(|(_, result)| {
+                result.path == chunk.path && result.start_line <= chunk.end_line && chunk.start_line <= result.end_line
+            });
+            if !overlaps {
+                results.push((similarity, chunk));
+            }
+        }
+        results
+    }
+
+    /// The index saved at `path`, if any.
+    pub async fn load(ctx: &Context, path: &Path) -> Result<Option<Self>> {
+        if !ctx.fs().exists(path) {
+            return Ok(None);
+        }
+        let contents = ctx.fs().read(path).await?;
+        Ok(Some(serde_json::from_slice(&contents)?))
+    }
+
+    pub async fn save(&self, ctx: &Context, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            ctx.fs().create_dir_all(parent).await?;
+        }
+        ctx.fs().write(path, serde_json::to_vec(self)?).await?;
+        Ok(())
+    }
+}
+
+/// How many times the terms of `text` hash into each bucket.
+fn term_counts(text: &str) -> BTreeMap<u32, u32> {
+    let mut counts = BTreeMap::new();
+    for term in terms(text) {
+        *counts.entry(fnv1a(&term) % DIMENSIONS).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// The lowercase words of `text`, with identifiers split into theirs: `retryPolicy` and
+/// `retry_policy` are about retries as much as `retry policy` is.
+fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        let mut start = 0;
+        let mut previous = None;
+        for (i, c) in word.char_indices() {
+            if c.is_uppercase() && previous.is_some_and(char::is_lowercase) {
+                terms.extend(term(&word[start..i]));
+                start = i;
+            }
+            previous = Some(c);
+        }
+        terms.extend(term(&word[start..]));
+    }
+    terms
+}
+
+/// `word` lowercased and without its most common suffixes, so that `parsed` and `parse` are the
+/// same term. `None` when it is too short or common to tell what a text is about.
+fn term(word: &str) -> Option<String> {
+    let word = word.to_lowercase();
+    if word.chars().count() < 3 || word.chars().all(|c| c.is_ascii_digit()) || STOP_WORDS.contains(&word.as_str()) {
+        return None;
+    }
+    let mut stem = word.as_str();
+    let mut replacement = "";
+    if let Some(base) = stem.strip_suffix("ies").or_else(|| stem.strip_suffix("ied")) {
+        (stem, replacement) = (base, "y");
+    } else if let Some(base) = stem.strip_suffix("ing").or_else(|| stem.strip_suffix("ed")) {
+        stem = base;
+    } else if let Some(base) = stem
+        .strip_suffix("es")
+        .filter(|base| ["s", "x", "z", "ch", "sh"].iter().any(|end| base.ends_with(end)))
+    {
+        stem = base;
+    } else if let Some(base) = stem.strip_suffix('s').filter(|base| !base.ends_with('s')) {
+        stem = base;
+    }
+    if stem.chars().count() < 3 {
+        return Some(word);
+    }
+    let stem = stem
+        .strip_suffix('e')
+        .filter(|base| base.chars().count() >= 4)
+        .unwrap_or(stem);
+    Some(format!("{stem}{replacement}"))
+}
+
+/// The 32-bit FNV-1a hash of `term`, the same across builds unlike the hashers of std.
+fn fnv1a(term: &str) -> u32 {
+    term.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+    })
+}
+
+/// The dot product of two sparse vectors.
+fn similarity(a: &[(u32, f32)], b: &[(u32, f32)]) -> f32 {
+    let (mut i, mut j, mut sum) = (0, 0, 0.0);
+    while i < a.len() && j < b.len() {
+        match a[i].0.cmp(&b[j].0) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                sum += a[i].1 * b[j].1;
+                i += 1;
+                j += 1;
+            },
+        }
+    }
+    sum
+}
+
+/// The root of the workspace `dir` is in: its git repository, or `dir` itself outside of one.
+pub fn workspace_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// Where the index of the workspace at `root` is saved.
+pub fn index_path(root: &Path) -> Result<PathBuf> {
+    let hash = Sha256::digest(root.to_string_lossy().as_bytes());
+    let name = hash.iter().take(8).fold(String::new(), |mut name, byte| {
+        name.push_str(&format!("{byte:02x}"));
+        name
+    });
+    Ok(directories::chat_index_dir()?.join(format!("{name}.json")))
+}
+
+/// `chat.index.topK`, 0 turning retrieval off.
+fn top_k(settings: &Settings) -> usize {
+    settings
+        .get_int(Setting::ChatIndexTopK)
+        .and_then(|top_k| usize::try_from(top_k).ok())
+        .unwrap_or(DEFAULT_TOP_K)
+}
+
+/// Retrieves the chunks of the index of the workspace relevant to each prompt of a chat, to add
+/// them to its context.
+#[derive(Debug, Clone)]
+pub struct IndexRetriever {
+    index: Arc<WorkspaceIndex>,
+    top_k: usize,
+}
+
+impl IndexRetriever {
+    /// The retriever of the workspace of the current directory, if it was indexed and
+    /// `chat.index.topK` isn't 0.
+    pub async fn load(ctx: &Context, settings: &Settings) -> Option<Self> {
+        let top_k = top_k(settings);
+        if top_k == 0 {
+            return None;
+        }
+        let root = workspace_root(&ctx.env().current_dir().ok()?);
+        match WorkspaceIndex::load(ctx, &index_path(&root).ok()?).await {
+            Ok(index) => index.map(|index| Self {
+                index: Arc::new(index),
+                top_k,
+            }),
+            Err(err) => {
+                warn!(
+                    ?err,
+                    "Failed to load the workspace index, rebuild it with q index build"
+                );
+                None
+            },
+        }
+    }
+
+    pub fn index(&self) -> &WorkspaceIndex {
+        &self.index
+    }
+
+    pub fn retrieve(&self, prompt: &str) -> Vec<&Chunk> {
+        self.index
+            .search(prompt, self.top_k)
+            .into_iter()
+            .map(|(_, chunk)| chunk)
+            .collect()
+    }
+}
+
+pub async fn execute_index(database: &Database, args: Index) -> Result<ExitCode> {
+    let ctx = Context::new();
+    let mut output = SharedWriter::stdout();
+    let cwd = ctx.env().current_dir()?;
+
+    match args {
+        Index::Build { path } => {
+            let root = match path {
+                Some(path) => ctx.fs().canonicalize(shellexpand::tilde(&path).as_ref()).await?,
+                None => workspace_root(&cwd),
+            };
+            if !root.is_dir() {
+                bail!("{} is not a directory", root.display());
+            }
+            writeln!(output, "\nIndexing {}...", root.display())?;
+            output.flush()?;
+            let index = WorkspaceIndex::build(&ctx, &root).await?;
+            index.save(&ctx, &index_path(&root)?).await?;
+            writeln!(
+                output,
+                "✓ Indexed {} chunks of {} files, retrieved in chats started in {}\n",
+                index.chunks.len(),
+                index.files,
+                root.display()
+            )?;
+        },
+        Index::Status => {
+            let root = workspace_root(&cwd);
+            match WorkspaceIndex::load(&ctx, &index_path(&root)?).await? {
+                Some(index) => {
+                    writeln!(
+                        output,
+                        "\n{}: {} chunks of {} files, built {}",
+                        index.root.display(),
+                        index.chunks.len(),
+                        index.files,
+                        format_local_time(OffsetDateTime::from(index.built_at))
+                    )?;
+                    match top_k(&database.settings) {
+                        0 => writeln!(
+                            output,
+                            "Retrieval is off, turn it on with: q settings chat.index.topK 5\n"
+                        )?,
+                        top_k => writeln!(output, "Up to {top_k} chunks are added to the context of each prompt\n")?,
+                    }
+                },
+                None => writeln!(
+                    output,
+                    "\n{} isn't indexed, index it with: q index build\n",
+                    root.display()
+                )?,
+            }
+        },
+        Index::Search { query, top_k: k } => {
+            let root = workspace_root(&cwd);
+            let Some(index) = WorkspaceIndex::load(&ctx, &index_path(&root)?).await? else {
+                bail!("{} isn't indexed, index it with: q index build", root.display());
+            };
+            let results = index.search(&query, k.unwrap_or_else(|| top_k(&database.settings).max(1)));
+            if results.is_empty() {
+                writeln!(output, "\nNo chunk is relevant to '{query}'\n")?;
+            }
+            for (similarity, chunk) in results {
+                writeln!(output, "\n{}  ({:.2})", chunk.source(), similarity)?;
+                for line in chunk.text.lines().take(3) {
+                    writeln!(output, "  {}", truncate_safe(line, 100))?;
+                }
+            }
+            writeln!(output)?;
+        },
+        Index::Clear => {
+            let root = workspace_root(&cwd);
+            let path = index_path(&root)?;
+            match ctx.fs().exists(&path) {
+                true => {
+                    ctx.fs().remove_file(&path).await?;
+                    writeln!(output, "\n✓ Deleted the index of {}\n", root.display())?;
+                },
+                false => writeln!(output, "\n{} isn't indexed\n", root.display())?,
+            }
+        },
+    }
+
+    output.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(results: &[(f32, &Chunk)]) -> Vec<String> {
+        results.iter().map(|(_, chunk)| chunk.source()).collect()
+    }
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(terms("fn retryPolicy(max_retries: u32) -> HTTPClient"), vec![
+            "retry",
+            "policy",
+            "max",
+            "retry",
+            "u32",
+            "httpclient"
+        ]);
+        assert_eq!(terms("Where is the retrying implemented?"), vec!["retry", "implement"]);
+        assert_eq!(terms("parse parsed parsing classes class modules module"), vec![
+            "pars", "pars", "pars", "class", "class", "modul", "modul"
+        ]);
+    }
+
+    #[test]
+    fn test_search() {
+        let long = (1..=100).map(|n| format!("let value_{n} = {n};")).collect::<Vec<_>>();
+        let mut long_with_retry = long.clone();
+        long_with_retry[59] = "let backoff = retry_with_backoff(request);".to_string();
+        let index = WorkspaceIndex::from_files(PathBuf::from("/repo"), vec![
+            (
+                "src/http/retry.rs".to_string(),
+                "pub fn with_retries(request: Request, attempts: u32) {\n    // exponential backoff\n}".to_string(),
+            ),
+            (
+                "src/parser.rs".to_string(),
+                "pub fn parse(tokens: &[Token]) -> Ast {\n    todo!()\n}".to_string(),
+            ),
+            ("src/values.rs".to_string(), long_with_retry.join("\n")),
+        ]);
+        assert_eq!(index.files, 3);
+        // 100 lines are split into 40 line chunks overlapping by 10
+        let chunks = index
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.path == "src/values.rs")
+            .map(|chunk| (chunk.start_line, chunk.end_line))
+            .collect::<Vec<_>>();
+        assert_eq!(chunks, vec![(1, 40), (31, 70), (61, 100)]);
+
+        let results = index.search("where is retry logic implemented?", 5);
+        assert_eq!(sources(&results), vec!["src/http/retry.rs:1-3", "src/values.rs:31-70"]);
+        assert!(results[0].0 > results[1].0);
+
+        assert_eq!(sources(&index.search("how are tokens parsed", 1)), vec![
+            "src/parser.rs:1-3"
+        ]);
+        assert!(index.search("kubernetes", 5).is_empty());
+        assert!(index.search("the", 5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_build() {
+        let ctx = Context::new();
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(root.join("src/retry.rs"), "fn retry() {}\n").unwrap();
+        std::fs::write(root.join("target/retry.rs"), "fn retry() {}\n").unwrap();
+        std::fs::write(root.join("Cargo.lock"), "retry\n").unwrap();
+        std::fs::write(root.join("logo.png"), b"\x89PNG\0\0retry").unwrap();
+
+        let index = WorkspaceIndex::build(&ctx, root).await.unwrap();
+        let mut paths = index.chunks.iter().map(|chunk| chunk.path.as_str()).collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, vec![".gitignore", "src/retry.rs"]);
+
+        let path = root.join("index/workspace.json");
+        index.save(&ctx, &path).await.unwrap();
+        let loaded = WorkspaceIndex::load(&ctx, &path).await.unwrap().unwrap();
+        assert_eq!(sources(&loaded.search("retry", 5)), vec!["src/retry.rs:1-1"]);
+        assert!(
+            WorkspaceIndex::load(&ctx, &root.join("missing.json"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
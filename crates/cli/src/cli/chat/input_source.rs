@@ -1,28 +1,70 @@
+use std::sync::atomic::Ordering;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
 use eyre::Result;
+use rustyline::EditMode;
+use rustyline::config::Configurer;
 use rustyline::error::ReadlineError;
+use tracing::warn;
 
-use super::prompt::rl;
+use super::editor::{
+    EditorEventHandler,
+    EditorRequest,
+    QuoteEventHandler,
+};
+use super::prompt::{
+    edit_mode_from_settings,
+    join_continuations,
+    rl,
+};
 #[cfg(unix)]
-use super::skim_integration::SkimCommandSelector;
+use super::skim_integration::{
+    SkimCommandSelector,
+    SkimHistorySelector,
+};
 use crate::database::Database;
+use crate::database::settings::Setting;
+use crate::util::directories;
 
 #[derive(Debug)]
 pub struct InputSource(inner::Inner);
 
 mod inner {
-    use rustyline::Editor;
+    use std::path::PathBuf;
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
     use rustyline::history::FileHistory;
+    use rustyline::{
+        EditMode,
+        Editor,
+    };
 
+    use super::super::editor::EditorRequests;
     use super::super::prompt::ChatHelper;
 
     #[derive(Debug)]
     pub enum Inner {
-        Readline(Editor<ChatHelper, FileHistory>),
-        #[allow(dead_code)]
-        Mock {
-            index: usize,
-            lines: Vec<String>,
+        Readline {
+            rl: Editor<ChatHelper, FileHistory>,
+            /// The file the history is persisted to.
+            history_path: Option<PathBuf>,
+            /// A copy of the history for the keybinding handlers, which can't access the editor.
+            history: Arc<Mutex<Vec<String>>>,
+            edit_mode: EditMode,
+            /// Left by the editor keybindings, which end the prompt to be carried out.
+            editor_requests: EditorRequests,
+            /// The buffer the first undo of the next prompt goes back to, see
+            /// [super::InputSource::undo_to].
+            undo_to: Option<String>,
         },
+        #[allow(dead_code)]
+        Mock { index: usize, lines: Vec<String> },
     }
 }
 
@@ -32,7 +74,73 @@ impl InputSource {
         sender: std::sync::mpsc::Sender<Option<String>>,
         receiver: std::sync::mpsc::Receiver<Vec<String>>,
     ) -> Result<Self> {
-        Ok(Self(inner::Inner::Readline(rl(database, sender, receiver)?)))
+        let mut rl = rl(database, sender, receiver)?;
+
+        let history_path = match database.settings.get_bool(Setting::ChatHistoryEnabled) {
+            Some(false) => None,
+            _ => directories::chat_history_path().ok(),
+        };
+        if let Some(path) = &history_path {
+            if let Some(parent) = path.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            if path.exists() {
+                if let Err(err) = rl.load_history(path) {
+                    warn!(?err, ?path, "Failed to load the chat history");
+                }
+            }
+        }
+
+        let history = Arc::new(Mutex::new(rl.history().iter().cloned().collect::<Vec<_>>()));
+        #[cfg(unix)]
+        rl.bind_sequence(
+            rustyline::KeyEvent::ctrl('r'),
+            rustyline::EventHandler::Conditional(Box::new(SkimHistorySelector::new(Arc::clone(&history)))),
+        );
+
+        Ok(Self(inner::Inner::Readline {
+            rl,
+            history_path,
+            history,
+            edit_mode: edit_mode_from_settings(&database.settings),
+            editor_requests: Default::default(),
+            undo_to: None,
+        }))
+    }
+
+    /// The current edit mode, `None` when not reading from a terminal.
+    pub fn edit_mode(&self) -> Option<EditMode> {
+        match &self.0 {
+            inner::Inner::Readline { edit_mode, .. } => Some(*edit_mode),
+            inner::Inner::Mock { .. } => None,
+        }
+    }
+
+    /// Switches between vi and emacs key bindings for the rest of the session.
+    pub fn set_edit_mode(&mut self, mode: EditMode) {
+        if let inner::Inner::Readline { rl, edit_mode, .. } = &mut self.0 {
+            rl.set_edit_mode(mode);
+            *edit_mode = mode;
+        }
+    }
+
+    /// Whether multi-line mode is on, see `/multiline`.
+    pub fn multiline(&self) -> bool {
+        match &self.0 {
+            inner::Inner::Readline { rl, .. } => rl
+                .helper()
+                .is_some_and(|helper| helper.validator.multiline.load(Ordering::Relaxed)),
+            inner::Inner::Mock { .. } => false,
+        }
+    }
+
+    /// Turns multi-line mode on or off for the rest of the session.
+    pub fn set_multiline(&mut self, enabled: bool) {
+        if let inner::Inner::Readline { rl, .. } = &mut self.0 {
+            if let Some(helper) = rl.helper() {
+                helper.validator.multiline.store(enabled, Ordering::Relaxed);
+            }
+        }
     }
 
     #[cfg(unix)]
@@ -47,9 +155,7 @@ impl InputSource {
             KeyEvent,
         };
 
-        use crate::database::settings::Setting;
-
-        if let inner::Inner::Readline(rl) = &mut self.0 {
+        if let inner::Inner::Readline { rl, .. } = &mut self.0 {
             let key_char = match database.settings.get_string(Setting::SkimCommandKey) {
                 Some(key) if key.len() == 1 => key.chars().next().unwrap_or('s'),
                 _ => 's', // Default to 's' if setting is missing or invalid
@@ -61,19 +167,108 @@ impl InputSource {
         }
     }
 
+    /// Binds Ctrl+F and Ctrl+X Ctrl+E to open the prompt in the system editor, and Ctrl+G to quote
+    /// the last response. These end the prompt, see [Self::take_editor_request].
+    pub fn put_editor_keybindings(&mut self) {
+        use rustyline::{
+            Event,
+            EventHandler,
+            KeyCode,
+            KeyEvent,
+            Modifiers,
+        };
+
+        if let inner::Inner::Readline {
+            rl, editor_requests, ..
+        } = &mut self.0
+        {
+            rl.bind_sequence(
+                KeyEvent(KeyCode::Char('f'), Modifiers::CTRL),
+                EventHandler::Conditional(Box::new(EditorEventHandler(Arc::clone(editor_requests)))),
+            );
+            rl.bind_sequence(
+                Event::KeySeq(vec![
+                    KeyEvent(KeyCode::Char('x'), Modifiers::CTRL),
+                    KeyEvent(KeyCode::Char('e'), Modifiers::CTRL),
+                ]),
+                EventHandler::Conditional(Box::new(EditorEventHandler(Arc::clone(editor_requests)))),
+            );
+            rl.bind_sequence(
+                KeyEvent(KeyCode::Char('g'), Modifiers::CTRL),
+                EventHandler::Conditional(Box::new(QuoteEventHandler(Arc::clone(editor_requests)))),
+            );
+        }
+    }
+
+    /// What the editor keybindings asked for when the last prompt ended, which then read as if
+    /// interrupted.
+    pub fn take_editor_request(&self) -> Option<EditorRequest> {
+        match &self.0 {
+            inner::Inner::Readline { editor_requests, .. } => editor_requests.lock().ok()?.take(),
+            inner::Inner::Mock { .. } => None,
+        }
+    }
+
+    /// Makes the first undo of the next prompt put back `before`, the buffer it replaced with the
+    /// one it starts with, e.g. after an editor round trip.
+    pub fn undo_to(&mut self, before: String) {
+        if let inner::Inner::Readline { undo_to, .. } = &mut self.0 {
+            *undo_to = Some(before);
+        }
+    }
+
     #[allow(dead_code)]
     pub fn new_mock(lines: Vec<String>) -> Self {
         Self(inner::Inner::Mock { index: 0, lines })
     }
 
+    #[allow(dead_code)]
     pub fn read_line(&mut self, prompt: Option<&str>) -> Result<Option<String>, ReadlineError> {
+        self.read_line_with_initial(prompt, None)
+    }
+
+    /// Like [Self::read_line], with `initial` already typed into the buffer.
+    pub fn read_line_with_initial(
+        &mut self,
+        prompt: Option<&str>,
+        initial: Option<&str>,
+    ) -> Result<Option<String>, ReadlineError> {
         match &mut self.0 {
-            inner::Inner::Readline(rl) => {
+            inner::Inner::Readline {
+                rl,
+                history_path,
+                history,
+                undo_to,
+                ..
+            } => {
                 let prompt = prompt.unwrap_or_default();
-                let curr_line = rl.readline(prompt);
+                if let Some(helper) = rl.helper() {
+                    helper.paste_tracker.reset();
+                    helper.line_undo.reset();
+                    helper.reset_workspace_files();
+                    if let (Some(before), Some(initial)) = (undo_to.take(), initial) {
+                        helper.line_undo.record_restore(initial, before);
+                    }
+                }
+                let curr_line = match initial {
+                    Some(initial) => rl.readline_with_initial(prompt, (initial, "")),
+                    None => rl.readline(prompt),
+                };
                 match curr_line {
                     Ok(line) => {
+                        let mut line = join_continuations(&line);
+                        // The empty line that submitted the prompt in multi-line mode
+                        line.truncate(line.trim_end_matches('\n').len());
                         let _ = rl.add_history_entry(line.as_str());
+                        // Appending right away keeps the history of concurrent sessions in order
+                        if let Some(path) = history_path {
+                            if let Err(err) = rl.append_history(path) {
+                                warn!(?err, ?path, "Failed to save the chat history");
+                            }
+                        }
+                        if let Ok(mut history) = history.lock() {
+                            *history = rl.history().iter().cloned().collect();
+                        }
                         Ok(Some(line))
                     },
                     Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
@@ -90,7 +285,7 @@ impl InputSource {
     // We're keeping this method for potential future use
     #[allow(dead_code)]
     pub fn set_buffer(&mut self, content: &str) {
-        if let inner::Inner::Readline(rl) = &mut self.0 {
+        if let inner::Inner::Readline { rl, .. } = &mut self.0 {
             // Add to history so user can access it with up arrow
             let _ = rl.add_history_entry(content);
         }
@@ -0,0 +1,306 @@
+use std::io::Write;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::thread::JoinHandle;
+
+use crossterm::style::{
+    self,
+    Color,
+};
+use crossterm::{
+    cursor,
+    queue,
+    terminal,
+};
+use futures::FutureExt;
+use tokio::sync::Notify;
+
+use super::notification::Focus;
+use super::util::truncate_safe;
+
+/// Watches the terminal for Esc while a response is streaming, so that it can be interrupted
+/// without Ctrl+C, and keeps listening while the tools of the response run without asking.
+///
+/// While listening, input is read without echo one key at a time. Output and Ctrl+C keep working
+/// as usual. Anything else typed in the meantime is shown with [EscapeListener::show_typed] and
+/// given back by [EscapeListener::stop]: lines submitted with Enter are queued to be sent once the
+/// current turn completes, and the rest pre-fills the next prompt. Focus changes reported by the
+/// terminal meanwhile update [Focus].
+pub struct EscapeListener {
+    pressed: Arc<Notify>,
+    /// What was typed so far, with [Self::changed] notified as more is.
+    typed: Arc<Mutex<Vec<u8>>>,
+    changed: Arc<Notify>,
+    echo: Mutex<Echo>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+    #[cfg(unix)]
+    original: nix::sys::termios::Termios,
+}
+
+/// What of the typed input is on the screen.
+#[derive(Debug, Default)]
+struct Echo {
+    /// How many of the queued lines were shown.
+    shown: usize,
+    /// Whether the line being typed is drawn on the current line.
+    drawn: bool,
+}
+
+impl EscapeListener {
+    /// Starts listening, or returns `None` if stdin isn't a terminal that can be listened to.
+    #[cfg(unix)]
+    pub fn start(focus: Arc<Focus>) -> Option<Self> {
+        use nix::sys::termios::{
+            LocalFlags,
+            SetArg,
+            SpecialCharacterIndices,
+            tcgetattr,
+            tcsetattr,
+        };
+
+        let stdin = std::io::stdin();
+        let original = tcgetattr(&stdin).ok()?;
+        let mut listening = original.clone();
+        listening.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO);
+        listening.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        listening.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        tcsetattr(&stdin, SetArg::TCSANOW, &listening).ok()?;
+        let _ = crossterm::execute!(std::io::stdout(), crossterm::event::EnableFocusChange);
+
+        let pressed = Arc::new(Notify::new());
+        let typed = Arc::new(Mutex::new(Vec::new()));
+        let changed = Arc::new(Notify::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = std::thread::spawn({
+            let pressed = Arc::clone(&pressed);
+            let typed = Arc::clone(&typed);
+            let changed = Arc::clone(&changed);
+            let stop = Arc::clone(&stop);
+            move || read_until_stopped(&pressed, &typed, &changed, &stop, &focus)
+        });
+
+        Some(Self {
+            pressed,
+            typed,
+            changed,
+            echo: Mutex::new(Echo::default()),
+            stop,
+            reader: Some(reader),
+            original,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn start(_focus: Arc<Focus>) -> Option<Self> {
+        None
+    }
+
+    /// Resolves once Esc was pressed.
+    pub async fn pressed(&self) {
+        self.pressed.notified().await;
+    }
+
+    /// Forgets a press of Esc that nothing waited for, e.g. while tools were running.
+    pub fn forget_pressed(&self) {
+        let _ = self.pressed.notified().now_or_never();
+    }
+
+    /// Resolves once more was typed.
+    pub async fn changed(&self) {
+        self.changed.notified().await;
+    }
+
+    fn typed(&self) -> TypedInput {
+        typed_input(&self.typed.lock().unwrap())
+    }
+
+    /// Shows the lines queued since last shown, then the line being typed after a `> `, which
+    /// stays on the current line until [Self::hide_typed]. `output` must be at the start of a line.
+    pub fn show_typed(&self, output: &mut impl Write) -> std::io::Result<()> {
+        self.echo(output, true)
+    }
+
+    /// Erases the line being typed, before more is written to `output`.
+    pub fn hide_typed(&self, output: &mut impl Write) -> std::io::Result<()> {
+        let mut echo = self.echo.lock().unwrap();
+        if std::mem::take(&mut echo.drawn) {
+            queue!(
+                output,
+                cursor::MoveToColumn(0),
+                terminal::Clear(terminal::ClearType::CurrentLine)
+            )?;
+        }
+        output.flush()
+    }
+
+    /// Shows the lines queued since last shown, leaving the line being typed for the next prompt.
+    pub fn show_queued(&self, output: &mut impl Write) -> std::io::Result<()> {
+        self.echo(output, false)
+    }
+
+    fn echo(&self, output: &mut impl Write, with_pending: bool) -> std::io::Result<()> {
+        self.hide_typed(output)?;
+        let typed = self.typed();
+        let mut echo = self.echo.lock().unwrap();
+        for message in typed.queued.iter().skip(echo.shown) {
+            queue!(
+                output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!("Queued: {}\n", truncate_safe(message, 80))),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        echo.shown = typed.queued.len();
+        if with_pending && !typed.pending.is_empty() {
+            // Only the end of a line longer than the terminal, which must fit on the current line
+            let width = terminal::size().map_or(80, |(columns, _)| columns as usize);
+            let max_chars = width.saturating_sub(3).max(1);
+            let skip = typed.pending.chars().count().saturating_sub(max_chars);
+            queue!(
+                output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print("> "),
+                style::SetForegroundColor(Color::Reset),
+                style::Print(typed.pending.chars().skip(skip).collect::<String>()),
+            )?;
+            echo.drawn = true;
+        }
+        output.flush()
+    }
+
+    /// Stops listening and restores the terminal, returning what was typed in the meantime.
+    pub fn stop(mut self) -> TypedInput {
+        self.release()
+    }
+
+    fn release(&mut self) -> TypedInput {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+
+        #[cfg(unix)]
+        {
+            let _ = crossterm::execute!(std::io::stdout(), crossterm::event::DisableFocusChange);
+            let _ = nix::sys::termios::tcsetattr(std::io::stdin(), nix::sys::termios::SetArg::TCSANOW, &self.original);
+        }
+
+        self.typed()
+    }
+}
+
+impl Drop for EscapeListener {
+    fn drop(&mut self) {
+        if self.reader.is_some() {
+            self.release();
+        }
+    }
+}
+
+/// Escape sequences are sent as a whole, so a lone Esc byte in a read is a press of the Esc key.
+#[cfg(unix)]
+fn read_until_stopped(pressed: &Notify, typed: &Mutex<Vec<u8>>, changed: &Notify, stop: &AtomicBool, focus: &Focus) {
+    use std::os::fd::AsRawFd;
+
+    let fd = std::io::stdin().as_raw_fd();
+    let mut buf = [0; 64];
+    while !stop.load(Ordering::Relaxed) {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Wake up regularly to notice when listening stops
+        let ready = unsafe { libc::poll(&mut pollfd, 1, 50) };
+        if ready <= 0 {
+            continue;
+        }
+
+        match nix::unistd::read(fd, &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(1) if buf[0] == 0x1b => pressed.notify_one(),
+            Ok(n) => {
+                focus.update_from(&buf[..n]);
+                typed.lock().unwrap().extend_from_slice(&buf[..n]);
+                changed.notify_one();
+            },
+        }
+    }
+}
+
+/// What was typed while an [EscapeListener] was listening.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TypedInput {
+    /// Lines submitted with Enter.
+    pub queued: Vec<String>,
+    /// The line being typed when listening stopped.
+    pub pending: String,
+}
+
+/// The printable text of what was `typed`, without escape sequences or other control keys.
+fn typed_input(typed: &[u8]) -> TypedInput {
+    let typed = String::from_utf8_lossy(typed);
+    let mut input = TypedInput::default();
+    let mut text = String::new();
+    let mut chars = typed.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' | '\n' => {
+                if !text.trim().is_empty() {
+                    input.queued.push(std::mem::take(&mut text));
+                }
+                text.clear();
+            },
+            // Skip the rest of an escape sequence, e.g. `\x1b[A` for the up arrow
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() || c == '~' {
+                            break;
+                        }
+                    }
+                }
+            },
+            '\x7f' | '\x08' => {
+                text.pop();
+            },
+            c if !c.is_control() => text.push(c),
+            _ => (),
+        }
+    }
+    input.pending = text;
+    input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(typed: &[u8]) -> String {
+        typed_input(typed).pending
+    }
+
+    #[test]
+    fn test_typed_input() {
+        assert_eq!(pending(b"run the tests"), "run the tests");
+        // Arrow keys and other control keys are dropped, backspace deletes
+        assert_eq!(pending(b"ab\x1b[Ac\x1b[3~d\x01"), "abcd");
+        assert_eq!(pending(b"teh\x7f\x7fhe"), "the");
+        assert_eq!(pending("café".as_bytes()), "café");
+        // So are focus reports
+        assert_eq!(pending(b"a\x1b[Ob\x1b[I"), "ab");
+
+        // Lines submitted with Enter are queued, empty ones are skipped
+        assert_eq!(typed_input(b"first\n\n  \rsecond\nthi"), TypedInput {
+            queued: vec!["first".to_string(), "second".to_string()],
+            pending: "thi".to_string(),
+        });
+    }
+}
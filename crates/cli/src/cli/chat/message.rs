@@ -4,6 +4,7 @@ use serde::{
     Deserialize,
     Serialize,
 };
+use time::OffsetDateTime;
 use tracing::error;
 
 use super::consts::MAX_CURRENT_WORKING_DIRECTORY_LEN;
@@ -37,6 +38,9 @@ pub struct UserMessage {
     pub env_context: UserEnvContext,
     pub content: UserMessageContent,
     pub images: Option<Vec<ImageBlock>>,
+    /// When the message was sent, missing from conversations saved before it was recorded.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub timestamp: Option<OffsetDateTime>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,6 +67,7 @@ impl UserMessage {
             images: None,
             additional_context: String::new(),
             env_context: UserEnvContext::generate_new(),
+            timestamp: Some(OffsetDateTime::now_utc()),
             content: UserMessageContent::Prompt { prompt },
         }
     }
@@ -72,6 +77,7 @@ impl UserMessage {
             images: None,
             additional_context: String::new(),
             env_context: UserEnvContext::generate_new(),
+            timestamp: Some(OffsetDateTime::now_utc()),
             content: UserMessageContent::CancelledToolUses {
                 prompt,
                 tool_use_results: tool_use_ids
@@ -91,6 +97,7 @@ impl UserMessage {
         Self {
             additional_context: String::new(),
             env_context: UserEnvContext::generate_new(),
+            timestamp: Some(OffsetDateTime::now_utc()),
             content: UserMessageContent::ToolUseResults {
                 tool_use_results: results,
             },
@@ -102,6 +109,7 @@ impl UserMessage {
         Self {
             additional_context: String::new(),
             env_context: UserEnvContext::generate_new(),
+            timestamp: Some(OffsetDateTime::now_utc()),
             content: UserMessageContent::ToolUseResults {
                 tool_use_results: results,
             },
@@ -268,6 +276,10 @@ impl UserEnvContext {
             env_state: Some(build_env_state()),
         }
     }
+
+    pub fn current_working_directory(&self) -> Option<&str> {
+        self.env_state.as_ref()?.current_working_directory.as_deref()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -407,4 +419,12 @@ mod tests {
         assert!(env_state.operating_system.as_ref().is_some_and(|os| !os.is_empty()));
         println!("{env_state:?}");
     }
+
+    #[test]
+    fn test_user_message_timestamp_default() {
+        let mut json = serde_json::to_value(UserMessage::new_prompt("hi".to_string())).unwrap();
+        json.as_object_mut().unwrap().remove("timestamp");
+        let message: UserMessage = serde_json::from_value(json).unwrap();
+        assert!(message.timestamp.is_none());
+    }
 }
@@ -1,25 +1,62 @@
+mod audit;
+mod autosave;
+mod background;
+mod branch;
+mod checkpoint;
 pub mod cli;
 mod command;
+mod command_context;
 mod consts;
 mod context;
+mod context_budget;
+mod context_cache;
 mod conversation_state;
+mod document;
+mod editor;
+mod export;
+mod find;
+mod git_diff;
 mod hooks;
+mod import;
+pub mod index;
 mod input_source;
+mod interrupt;
 pub mod mcp;
 mod message;
+mod notification;
+mod pager;
 mod parse;
 mod parser;
+mod project;
 mod prompt;
+mod redaction;
+mod replay;
+mod search;
+mod secrets;
 mod server_messenger;
+mod session;
+mod share;
 #[cfg(unix)]
 mod skim_integration;
+mod snippet;
+mod spinner;
+mod symbol_context;
+mod sync;
+mod system_prompt;
+mod theme;
 mod token_counter;
+mod tool_cache;
 mod tool_manager;
+mod tool_output;
 mod tools;
+mod transcript_log;
+mod url_context;
+mod usage;
 pub mod util;
 
 use std::borrow::Cow;
 use std::collections::{
+    BTreeMap,
     HashMap,
     HashSet,
     VecDeque,
@@ -29,30 +66,62 @@ use std::io::{
     Read,
     Write,
 };
-use std::process::{
-    Command as ProcessCommand,
-    ExitCode,
+use std::path::{
+    Path,
+    PathBuf,
 };
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::Duration;
-use std::{
-    env,
-    fs,
+use std::time::{
+    Duration,
+    Instant,
 };
 
+use audit::{
+    AuditLog,
+    AuditRecord,
+    Decision,
+    Outcome,
+};
+use autosave::Autosaver;
+use background::{
+    BackgroundProcesses,
+    ProcessStatus,
+    format_elapsed,
+};
+use branch::Branches;
+use checkpoint::Checkpoints;
 use command::{
     Command,
+    DraftSubcommand,
     PromptsSubcommand,
+    RedactionSubcommand,
+    RulesSubcommand,
+    SystemSubcommand,
     ToolsSubcommand,
 };
 use consts::{
+    AUTO_COMPACT_KEEP_LAST,
+    AUTO_COMPACT_THRESHOLD,
     CONTEXT_FILES_MAX_SIZE,
     CONTEXT_WINDOW_SIZE,
     DUMMY_TOOL_NAME,
+    LARGE_PROMPT_THRESHOLD,
+    TITLE_AFTER_PROMPTS,
 };
-use context::ContextManager;
+use context::{
+    ContextFileChange,
+    ContextManager,
+    SkippedFile,
+    rules_dir,
+};
+use context_budget::dropped_files_warning;
 pub use conversation_state::ConversationState;
-use conversation_state::TokenWarningLevel;
+use conversation_state::{
+    CompactStrategy,
+    TokenWarningLevel,
+    tools_char_count,
+};
 use crossterm::style::{
     Attribute,
     Color,
@@ -65,22 +134,39 @@ use crossterm::{
     style,
     terminal,
 };
+pub use editor::EditorCommand;
+use editor::{
+    EditorLauncher,
+    EditorOutput,
+    EditorRequest,
+};
 use eyre::{
     ErrReport,
     Result,
     bail,
 };
+use futures::stream::{
+    self,
+    StreamExt,
+};
 use hooks::{
     Hook,
     HookTrigger,
+    ToolHookOutput,
 };
+use index::IndexRetriever;
 use input_source::InputSource;
+use interrupt::EscapeListener;
 use message::{
     AssistantMessage,
     AssistantToolUse,
     ToolUseResult,
     ToolUseResultBlock,
 };
+use notification::{
+    Notification,
+    Notifier,
+};
 use parse::{
     ParseState,
     interpret_markdown,
@@ -93,18 +179,47 @@ use rand::distr::{
     Alphanumeric,
     SampleString,
 };
+use redaction::Redactor;
 use regex::Regex;
+use rustyline::EditMode;
 use serde_json::Map;
-use spinners::{
-    Spinner,
-    Spinners,
+use session::{
+    Resume,
+    Retention,
+    default_session_name,
+    format_local_time,
+    generate_title,
+    is_session_name,
+    list_sessions,
+    load_session,
+    name_from_title,
+    prune,
+    retention_setting,
+    save_session,
+    session_path,
+};
+use share::ShareTarget;
+use spinner::{
+    ChatSpinner,
+    SpinnerConfig,
 };
+use sync::{
+    SyncBackend,
+    SyncRemote,
+};
+use system_prompt::SystemPromptSource;
+use theme::Theme;
 use thiserror::Error;
 use token_counter::{
+    CharCount,
     TokenCount,
     TokenCounter,
 };
 use tokio::signal::ctrl_c;
+use tool_cache::{
+    Lookup,
+    ToolCache,
+};
 use tool_manager::{
     GetPromptError,
     LoadingRecord,
@@ -113,14 +228,29 @@ use tool_manager::{
     ToolManager,
     ToolManagerBuilder,
 };
+use tool_output::OutputLimits;
+use tools::environment::ToolEnv;
 use tools::gh_issue::GhIssueContext;
+use tools::github_issue::GithubIssueConfig;
+use tools::http_request::HttpConfig;
+use tools::rules::{
+    ToolPattern,
+    ToolRules,
+    covering_pattern,
+};
+use tools::sandbox::Sandbox;
+use tools::use_aws::AwsScope;
 use tools::{
+    ApprovalBatch,
+    InvokeOutput,
     OutputKind,
     QueuedTool,
     Tool,
-    ToolOrigin,
     ToolPermissions,
     ToolSpec,
+    ToolTimedOut,
+    ToolTimeouts,
+    sanitize_path_tool_arg,
 };
 use tracing::{
     debug,
@@ -129,7 +259,19 @@ use tracing::{
     trace,
     warn,
 };
+use transcript_log::{
+    LogEntry,
+    TranscriptLog,
+};
 use unicode_width::UnicodeWidthStr;
+use usage::{
+    UsageCategory,
+    print_context_window,
+};
+use util::clipboard::{
+    copy_to_clipboard,
+    paste_from_clipboard,
+};
 use util::images::RichImageBlock;
 use util::shared_writer::{
     NullWriter,
@@ -138,11 +280,10 @@ use util::shared_writer::{
 use util::ui::draw_box;
 use util::{
     animate_output,
-    drop_matched_context_files,
-    play_notification_bell,
+    fenced_code_blocks,
     region_check,
+    truncate_safe,
 };
-use uuid::Uuid;
 use winnow::Partial;
 use winnow::stream::Offset;
 
@@ -162,7 +303,10 @@ use crate::mcp_client::{
 use crate::platform::Context;
 use crate::telemetry::TelemetryThread;
 use crate::telemetry::core::ToolUseEventBuilder;
-use crate::util::CLI_BINARY_NAME;
+use crate::util::{
+    CLI_BINARY_NAME,
+    directories,
+};
 
 /// Help text for the compact command
 fn compact_help_text() -> String {
@@ -178,6 +322,11 @@ that may eventually reach memory constraints.
   <em>/compact</em>                   <black!>Summarize the conversation and clear history</black!>
   <em>/compact [prompt]</em>          <black!>Provide custom guidance for summarization</black!>
 
+<cyan!>Options</cyan!>
+  <em>--keep-last <<N>></em>            <black!>Keep the last N messages as they are (default: 1)</black!>
+  <em>--preserve-code</em>            <black!>Copy code from the conversation into the summary verbatim</black!>
+  <em>--aggressive</em>               <black!>Keep only what is needed to continue, for the smallest summary</black!>
+
 <cyan!>When to use</cyan!>
 • When you see the memory constraint warning message
 • When a conversation has been running for a long time
@@ -208,7 +357,7 @@ const RESUME_TEXT: &str = color_print::cstr! {"<em>Picking up where we left off.
 const ROTATING_TIPS: [&str; 13] = [
     color_print::cstr! {"You can resume the last conversation from your current directory by launching with <green!>q chat --resume</green!>"},
     color_print::cstr! {"Get notified whenever Q CLI finishes responding. Just run <green!>q settings chat.enableNotifications true</green!>"},
-    color_print::cstr! {"You can use <green!>/editor</green!> to edit your prompt with a vim-like experience"},
+    color_print::cstr! {"You can use <green!>/editor</green!> or <green!>ctrl + f</green!> to edit your prompt with a vim-like experience"},
     color_print::cstr! {"<green!>/usage</green!> shows you a visual breakdown of your current context window usage"},
     color_print::cstr! {"Get notified whenever Q CLI finishes responding. Just run <green!>q settings chat.enableNotifications true</green!>"},
     color_print::cstr! {"You can execute bash commands by typing <green!>!</green!> followed by the command"},
@@ -235,13 +384,38 @@ const HELP_TEXT: &str = color_print::cstr! {"
 
 <cyan,em>Commands:</cyan,em>
 <em>/clear</em>        <black!>Clear the conversation history</black!>
+<em>/clear-screen</em> <black!>Clear the screen without touching the conversation. Alternatively, [Ctrl(^) + l]</black!>
 <em>/issue</em>        <black!>Report an issue or make a feature request</black!>
-<em>/editor</em>       <black!>Open $EDITOR (defaults to vi) to compose a prompt</black!>
+<em>/editor</em>       <black!>Open $EDITOR (defaults to vi) to compose a prompt [initial text]</black!>
+<em>/edit</em>         <black!>Edit an earlier message in $EDITOR and continue the conversation from it [n]</black!>
+<em>/copy</em>         <black!>Copy the last response, or its nth code block, to the clipboard [n]</black!>
+<em>/paste</em>        <black!>Paste the clipboard into your next prompt, or attach it to the context as a name, for large logs [--as-context [name]]</black!>
+<em>/find</em>         <black!>Search the conversation for lines matching a regex, e.g. /find TODO</black!>
+<em>/page</em>         <black!>Show the last response in $PAGER, also available with chat.autopage</black!>
+<em>/expand</em>       <black!>Show the full output of a tool result shortened for the model, the last one by default [tool-use-id] [--attach]</black!>
+<em>/cache</em>        <black!>Show the results of fs_read, grep_search and glob_files reused while the files are unchanged [clear]</black!>
+<em>/ps</em>           <black!>List the commands the model started in the background, e.g. dev servers</black!>
+<em>/logs</em>         <black!>Show the last lines of output of a background command <<id>> [lines]</black!>
+<em>/kill</em>         <black!>Stop a background command and what it started <<id>></black!>
+<em>/undo</em>         <black!>Remove the last exchange(s) from the conversation [n]</black!>
+<em>/checkpoint</em>   <black!>List the turns whose file changes can be reverted [list]</black!>
+<em>/revert</em>       <black!>Undo the file changes made with fs_write in a turn and the next ones, or in all the turns [turn|all]</black!>
+<em>/retry</em>        <black!>Discard the last response and send your last message again [--fresh]</black!>
+<em>/quote</em>        <black!>Open the last response in $EDITOR and quote what you keep in your next prompt</black!>
+<em>/draft</em>        <black!>Restore the unsent draft from $EDITOR into the prompt</black!>
+  <em>restore</em>     <black!>Load the most recent draft, e.g. after the editor failed</black!>
+<em>/set-mode</em>     <black!>Switch between vi and emacs key bindings for this session [vi|emacs]</black!>
+<em>/multiline</em>    <black!>Make Enter insert a newline, and an empty line, Alt+Enter or Ctrl+D send the prompt [on|off]</black!>
+<em>/dry-run</em>      <black!>Only show the commands and file changes the model asks for, without running them [on|off]</black!>
+<em>/readonly</em>     <black!>Refuse the tools that can change anything, e.g. on a production machine [on|off]</black!>
 <em>/help</em>         <black!>Show this help dialogue</black!>
 <em>/quit</em>         <black!>Quit the application</black!>
 <em>/compact</em>      <black!>Summarize the conversation to free up context space</black!>
   <em>help</em>        <black!>Show help for the compact command</black!>
   <em>[prompt]</em>    <black!>Optional custom prompt to guide summarization</black!>
+<em>/pin</em>          <black!>Pin one of your messages so that it is kept verbatim through compaction, or list them [n] [--remove]</black!>
+<em>/pins</em>         <black!>List the pinned messages</black!>
+<em>/forget</em>       <black!>Stop sending one of your messages and what followed it to the model, e.g. a pasted secret [n|n-m]</black!>
 <em>/tools</em>        <black!>View and manage tools and permissions</black!>
   <em>help</em>        <black!>Show an explanation for the trust command</black!>
   <em>trust</em>       <black!>Trust a specific tool or tools for the session</black!>
@@ -264,26 +438,109 @@ const HELP_TEXT: &str = color_print::cstr! {"
   <em>help</em>        <black!>Show context help</black!>
   <em>show</em>        <black!>Display current context rules configuration [--expand]</black!>
   <em>add</em>         <black!>Add file(s) to context [--global] [--force]</black!>
+  <em>add-url</em>     <black!>Add the text of a web page to the context of this conversation, also with @https://... in prompts</black!>
+  <em>@path#name</em>  <black!>In prompts, add the definition of a function, type or class along with the imports of its file</black!>
+  <em>add-cmd</em>     <black!>Add the output of a shell command to the context of this conversation</black!>
   <em>rm</em>          <black!>Remove file(s) from context [--global]</black!>
   <em>clear</em>       <black!>Clear all files from current context [--global]</black!>
+  <em>diff</em>        <black!>Show which context files changed between the last two requests</black!>
   <em>hooks</em>       <black!>View and manage context hooks</black!>
+<em>/redaction</em>    <black!>Show the secrets masked in your prompts and context files before sending them [show]</black!>
+<em>/system</em>       <black!>Show or edit the instructions sent with every conversation of the profile, kept by /clear [show|edit]</black!>
+<em>/rules</em>        <black!>List the rules of the project from .amazonq/rules, added to the context [enable|disable <<name>>]</black!>
 <em>/usage</em>        <black!>Show current session's context window usage</black!>
-<em>/load</em>         <black!>Load conversation state from a JSON file</black!>
-<em>/save</em>         <black!>Save conversation state to a JSON file</black!>
+<em>/load</em>         <black!>Load a conversation saved with /save, or from a JSON file. Lists saved conversations [name|path]</black!>
+<em>/save</em>         <black!>Save the conversation by name to resume it with q chat --resume name, or to a JSON file [name|path] [--force]</black!>
+<em>/export</em>       <black!>Export the conversation to Markdown, HTML or JSON, from the extension of the path [--force]</black!>
+<em>/share</em>        <black!>Share the conversation without secrets as a secret gist with chat.share.githubToken, or as an HTML file [--html]</black!>
+<em>/fork</em>         <black!>Copy the conversation into a new branch to try another approach, and continue on it [name]</black!>
+<em>/new</em>          <black!>Start a new conversation with its own context, keeping this one as a branch [name]</black!>
+<em>/branches</em>     <black!>List the branches of the conversation, or switch to the named one [name]</black!>
+<em>/switch</em>       <black!>Switch to a branch or to a conversation started with /new <<name>></black!>
+<em>/tag</em>          <black!>Tag the conversation to find it with q chat search --tag once saved, or list its tags [tags] [--remove]</black!>
 
 <cyan,em>MCP:</cyan,em>
 <black!>You can now configure the Amazon Q CLI to use MCP servers. \nLearn how: https://docs.aws.amazon.com/en_us/amazonq/latest/qdeveloper-ug/command-line-mcp.html</black!>
 
 <cyan,em>Tips:</cyan,em>
 <em>!{command}</em>            <black!>Quickly execute a command in your current session</black!>
+<em>Enter while streaming</em> <black!>Queue a message to send once the current response and its tool uses complete</black!>
+<em>Esc</em>                   <black!>Stop the response being generated. Alternatively, [Ctrl(^) + c]</black!>
 <em>Ctrl(^) + j</em>           <black!>Insert new-line to provide multi-line prompt. Alternatively, [Alt(⌥) + Enter(⏎)]</black!>
+                      <black!>Lines ending with \\ and unclosed ``` code blocks also continue on the next line</black!>
+                      <black!>End a line with \\\\ for a literal \\, commands (/ and !) are sent as typed</black!>
+<em>Ctrl(^) + _</em>           <black!>Undo the last edit of the prompt, and redo it with [Alt(⌥) + _]</black!>
+<em>Ctrl(^) + f</em>           <black!>Open $EDITOR to compose the current prompt, also [Ctrl(^) + x, Ctrl(^) + e]. Alternatively, use /editor</black!>
+<em>Ctrl(^) + g</em>           <black!>Quote an excerpt of the last response in your prompt. Alternatively, use /quote</black!>
+<em>Ctrl(^) + r</em>           <black!>Fuzzy search your prompt history, including previous sessions</black!>
+<em>Right(→) or End</em>       <black!>Accept the dimmed suggestion from your prompt history</black!>
 <em>Ctrl(^) + s</em>           <black!>Fuzzy search commands and context files. Use Tab to select multiple items.</black!>
                       <black!>Change the keybind to ctrl+x with: q settings chat.skimCommandKey x (where x is any key)</black!>
+<em>chat.history.enabled</em>  <black!>Stop saving your prompts across sessions using: q settings chat.history.enabled false</black!>
+<em>chat.prompt</em>           <black!>Customize the prompt using e.g.: q settings chat.prompt '{profile} ({branch}) {context_percent}% > '</black!>
+                      <black!>Available variables: {profile}, {warning}, {branch}, {cwd}, {tokens_used}, {tokens_max}, {context_percent}</black!>
+<em>chat.notifyWhenAway</em>   <black!>Only notify when a turn longer than chat.notificationThreshold seconds (10 by default) ends</black!>
+                      <black!>while the terminal isn't focused, using: q settings chat.notifyWhenAway true</black!>
+<em>chat.largePromptThreshold</em> <black!>Confirm before sending prompts estimated above N tokens (60000 by default, 0 to disable)</black!>
+<em>chat.autopage</em>         <black!>Open responses longer than the screen in $PAGER using: q settings chat.autopage true</black!>
+<em>chat.snippets</em>         <black!>Type !name then Tab to expand a snippet from chat.snippets, a JSON object of names to text</black!>
+<em>chat.statusLine</em>       <black!>Show the profile, trusted tools and context usage above the prompt using: q settings chat.statusLine true</black!>
+<em>chat.multiline</em>        <black!>Start every session in multi-line mode (see /multiline) using: q settings chat.multiline true</black!>
+<em>chat.spinner.style</em>    <black!>Change the spinner using: q settings chat.spinner.style braille/dots/plain (plain prints one static line)</black!>
+<em>chat.autosave.turns</em>   <black!>Autosave the conversation every N turns to resume it after a crash (5 by default, 0 to disable)</black!>
+<em>chat.sessions.retention</em> <black!>Delete saved and autosaved conversations older than e.g. 30d when the chat starts, see q chat sessions prune</black!>
+<em>chat.share.githubToken</em> <black!>The GitHub token /share creates gists with, $GITHUB_TOKEN by default (needs the gist scope)</black!>
+<em>chat.sync.url</em>         <black!>Sync saved conversations and prompt history after /save, with s3://bucket/prefix or a WebDAV https:// URL</black!>
+<em>chat.sync.region</em>      <black!>The region of the chat.sync.url bucket, the default AWS region otherwise</black!>
+<em>chat.urlContext.allowedDomains</em> <black!>Add pages only from these domains and their subdomains, none until set, e.g.: q settings chat.urlContext.allowedDomains docs.rs,github.com</black!>
+<em>chat.index.topK</em>       <black!>How many chunks of the workspace index built with q index build are added for each prompt, 0 to stop</black!>
+<em>chat.sandbox.backend</em>  <black!>Run shell commands in docker, bubblewrap, firejail or as another user, writing files only in the workspace</black!>
+                      <black!>Configure with chat.sandbox.image, chat.sandbox.network false, chat.sandbox.workspace and chat.sandbox.user</black!>
+<em>tools.env.denylist</em>    <black!>Variables kept from the commands tools run, AWS_SECRET_ACCESS_KEY, *_TOKEN and the like by default</black!>
+                      <black!>Or pass only some with tools.env.allowlist, e.g.: q settings tools.env.allowlist 'PATH,HOME,LANG,LC_*'</black!>
+<em>tools.useAws.allowedOperations</em> <black!>The only AWS operations use_aws calls, e.g.: q settings tools.useAws.allowedOperations 'ecs:list-*,ecs:describe-*'</black!>
+                      <black!>Keep it to tools.useAws.profile and tools.useAws.regions, ask before every call with tools.useAws.askAlways true</black!>
+<em>tools.useAws.allowWrite</em> <black!>Let use_aws call operations that change things, asking first, using: q settings tools.useAws.allowWrite true</black!>
+<em>tools.httpRequest.allowedDomains</em> <black!>Let the model send HTTP requests to some domains, e.g.: q settings tools.httpRequest.allowedDomains api.example.com</black!>
+                      <black!>Add headers with tools.httpRequest.headers, '{{secret:NAME}}' in them replaced by: q chat secrets set NAME</black!>
+<em>tools.githubIssue.repo</em> <black!>Let the model file issues in a GitHub repository, asking first, e.g.: q settings tools.githubIssue.repo acme/app</black!>
+                      <black!>With a token saved by: q chat secrets set github-token</black!>
+<em>chat.shell.pty</em>        <black!>Run shell commands in a terminal you can type into: true for all, false for none, unset for those needing one</black!>
+<em>tools.timeoutMs</em>       <black!>Stop tools running for longer than N milliseconds, asking first whether to keep waiting (no limit by default)</black!>
+                      <black!>Set it for some tools with tools.timeouts, e.g.: q settings tools.timeouts '{\"execute_bash\": 600000}'</black!>
+<em>allowedTools</em>          <black!>In ~/.aws/amazonq/mcp.json, patterns of the tools run without asking, and never with deniedTools</black!>
+                      <black!>e.g.: \"allowedTools\": [\"@github/get_*\", \"execute_bash(git log*)\"], \"deniedTools\": [\"execute_bash(rm *)\"]</black!>
+<em>chat.systemPrompt</em>     <black!>Instructions for every conversation of the profiles without their own, or the path of a file with them</black!>
+<em>chat.redaction.patterns</em> <black!>More regexes of secrets to mask before sending, e.g.: q settings chat.redaction.patterns acme-[0-9a-f]{32}</black!>
+<em>chat.redaction.enabled</em> <black!>Stop masking secrets such as AWS keys in prompts and context files using: q settings chat.redaction.enabled false</black!>
+<em>chat.autoContext</em>      <black!>Stop telling new conversations about the project files using: q settings chat.autoContext false</black!>
+<em>chat.transcript.path</em>  <black!>Append every prompt, response and tool use to a log file (JSONL if it ends in .jsonl, text otherwise)</black!>
+<em>chat.audit.enabled</em>    <black!>Record every tool use, its approval and a hash of its output to an audit log, see q chat audit</black!>
+<em>chat.autoCompact.threshold</em> <black!>Summarize older messages once the context window is N% full (85 by default, 0 to disable)</black!>
+<em>chat.spinner.elapsed</em>  <black!>Show the time spent waiting next to the spinner using: q settings chat.spinner.elapsed true</black!>
+<em>chat.theme</em>            <black!>Change the colors using: q settings chat.theme dark/light/solarized/no-color (or a theme file)</black!>
 <em>chat.editMode</em>         <black!>Set editing mode (vim or emacs) using: q settings chat.editMode vi/emacs</black!>
+<em>chat.editMode.vi</em>      <black!>Use vi key bindings using: q settings chat.editMode.vi true</black!>
+<em>chat.editMode.editor</em>  <black!>Use a different editor than $VISUAL or $EDITOR using: q settings chat.editMode.editor 'code --wait'</black!>
+<em>chat.editMode.fileExtension</em> <black!>Open code prompts with syntax highlighting using: q settings chat.editMode.fileExtension auto (or e.g. txt)</black!>
+<em>chat.editMode.autoOpenLines</em> <black!>Edit prompts longer than N lines in $EDITOR using: q settings chat.editMode.autoOpenLines N</black!>
+<em>chat.editMode.includeHistory</em> <black!>Quote the last exchange as comments in $EDITOR using: q settings chat.editMode.includeHistory true</black!>
+<em>chat.editMode.template</em> <black!>Start editor prompts from a template using: q settings chat.editMode.template true (or a file path)</black!>
 
 "};
 
+/// How many lines of output of a background command `/logs` shows by default.
+const DEFAULT_LOG_LINES: usize = 50;
+
 const RESPONSE_TIMEOUT_CONTENT: &str = "Response timed out - message took too long to generate";
+const RESPONSE_INTERRUPTED_CONTENT: &str = "[The user interrupted this response]";
+/// Sent along with the prompt by `/retry --fresh`, since the sampling parameters can't be changed.
+const RETRY_FRESH_NOTE: &str =
+    "(A previous answer to this was discarded by the user. Take a different approach this time.)";
+/// How many tools without side effects asked for at once run at the same time.
+const MAX_PARALLEL_TOOLS: usize = 4;
+/// How soon after interrupting a response with Ctrl+C another Ctrl+C exits.
+const DOUBLE_CTRL_C_WINDOW: Duration = Duration::from_secs(1);
 const TRUST_ALL_TEXT: &str = color_print::cstr! {"<green!>All tools are now trusted (<red!>!</red!>). Amazon Q will execute tools <bold>without</bold> asking for confirmation.\
 \nAgents can sometimes do unexpected things so understand the risks.</green!>
 \nLearn more at https://docs.aws.amazon.com/amazonq/latest/qdeveloper-ug/command-line-chat-security.html#command-line-chat-trustall-safety"};
@@ -293,6 +550,17 @@ const CONTINUATION_LINE: &str = " ⋮ ";
 const PURPOSE_ARROW: &str = " ↳ ";
 
 pub async fn launch_chat(database: &mut Database, telemetry: &TelemetryThread, args: cli::Chat) -> Result<ExitCode> {
+    match args.subcommand {
+        Some(cli::ChatSubcommand::Export(args)) => return export_conversation(database, args).await,
+        Some(cli::ChatSubcommand::Sessions(args)) => return session::execute_sessions(database, args).await,
+        Some(cli::ChatSubcommand::Search(args)) => return search::execute_search(args).await,
+        Some(cli::ChatSubcommand::Import(args)) => return import::execute_import(args).await,
+        Some(cli::ChatSubcommand::Replay(args)) => return replay::execute_replay(database, args).await,
+        Some(cli::ChatSubcommand::Audit(args)) => return audit::execute_audit(database, args).await,
+        Some(cli::ChatSubcommand::Secrets(args)) => return secrets::execute_secrets(database, args).await,
+        None => (),
+    }
+
     let trust_tools = args.trust_tools.map(|mut tools| {
         if tools.len() == 1 && tools[0].is_empty() {
             tools.pop();
@@ -300,31 +568,85 @@ pub async fn launch_chat(database: &mut Database, telemetry: &TelemetryThread, a
         tools
     });
 
+    let (resume, input) = match (args.resume, args.input) {
+        (None, input) => (None, input),
+        (Some(None), input) => (Some(Resume::Directory), input),
+        // Before conversations could be saved by name, `q chat --resume "question"` asked about
+        // the last conversation from the current directory
+        (Some(Some(input)), None) if !is_session_name(&input) => (Some(Resume::Directory), Some(input)),
+        (Some(Some(name)), input) => (Some(Resume::Session(name)), input),
+    };
+
     chat(
         database,
         telemetry,
-        args.input,
+        input,
         args.no_interactive,
-        args.resume,
+        resume,
         args.accept_all,
         args.profile,
         args.trust_all_tools,
         trust_tools,
+        args.editor,
+        args.no_spinner,
+        args.dry_run,
+        args.read_only,
     )
     .await
 }
 
+/// Writes a transcript of a saved conversation for `q chat export`.
+async fn export_conversation(database: &mut Database, args: cli::ChatExport) -> Result<ExitCode> {
+    let ctx = Context::new();
+    let state = match &args.session {
+        Some(name) => load_session(&ctx, &directories::chat_sessions_dir()?, name).await?,
+        None => {
+            let cwd = std::env::current_dir()?;
+            match database.get_conversation_by_path(&cwd)? {
+                Some(state) => state,
+                None => bail!(
+                    "No conversation from {} to export. Pass --session to export a saved one",
+                    cwd.display()
+                ),
+            }
+        },
+    };
+
+    let format = match (args.format, &args.output) {
+        (Some(format), _) => format,
+        (None, Some(output)) => match export::format_for_path(Path::new(output)) {
+            Some(format) => format,
+            None => bail!("Unknown format for {output}. Use a .md, .html or .json extension, or pass --format"),
+        },
+        (None, None) => cli::ExportFormat::Markdown,
+    };
+    let contents = export::export(&state, format)?;
+    match args.output {
+        Some(output) => ctx.fs().write(&output, contents).await?,
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(contents.as_bytes())?;
+            stdout.flush()?;
+        },
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
 #[allow(clippy::too_many_arguments, clippy::fn_params_excessive_bools)]
 pub async fn chat(
     database: &mut Database,
     telemetry: &TelemetryThread,
     input: Option<String>,
     no_interactive: bool,
-    resume_conversation: bool,
+    resume: Option<Resume>,
     accept_all: bool,
     profile: Option<String>,
     trust_all_tools: bool,
     trust_tools: Option<Vec<String>>,
+    editor: Option<String>,
+    no_spinner: bool,
+    dry_run: bool,
+    read_only: bool,
 ) -> Result<ExitCode> {
     if !crate::util::system_info::in_cloudshell() && !crate::auth::is_logged_in(database).await {
         bail!(
@@ -408,6 +730,8 @@ pub async fn chat(
     } else {
         Box::new(NullWriter {})
     };
+    // Checked before the servers start, a pattern in error being better fixed than ignored
+    let tool_rules = ToolRules::new(&mcp_server_configs.allowed_tools, &mcp_server_configs.denied_tools)?;
     let mut tool_manager = ToolManagerBuilder::default()
         .mcp_server_config(mcp_server_configs)
         .prompt_list_sender(prompt_response_sender)
@@ -420,7 +744,8 @@ pub async fn chat(
 
     // Load permissions: persistent settings + CLI overrides
     let tool_permissions = ToolPermissions::from_database(database)
-        .with_cli_overrides((accept_all || trust_all_tools).then_some(true), trust_tools);
+        .with_cli_overrides((accept_all || trust_all_tools).then_some(true), trust_tools)
+        .with_rules(tool_rules);
 
     // Deprecation notice for --accept-all users
     if accept_all && interactive {
@@ -439,18 +764,27 @@ pub async fn chat(
         output,
         input,
         InputSource::new(database, prompt_request_sender, prompt_response_receiver)?,
-        interactive,
-        resume_conversation,
+        ChatFlags {
+            interactive,
+            no_spinner,
+            dry_run,
+            read_only,
+        },
+        resume,
         client,
         || terminal::window_size().map(|s| s.columns.into()).ok(),
         tool_manager,
         profile,
         tool_config,
         tool_permissions,
+        editor.as_deref(),
     )
     .await?;
 
     let result = chat.try_chat(database, telemetry).await.map(|_| ExitCode::SUCCESS);
+    if result.is_ok() {
+        chat.autosave.discard(&chat.ctx).await;
+    }
     drop(chat); // Explicit drop for clarity
 
     result
@@ -497,24 +831,125 @@ pub struct ChatContext {
     /// Whether we're starting a new conversation or continuing an old one.
     existing_conversation: bool,
     input_source: InputSource,
+    /// Launches the system editor on this session's scratch buffer.
+    editor: EditorLauncher,
+    /// Text to pre-fill the next prompt with, e.g. a quote from `/quote`.
+    pending_input: Option<String>,
+    /// Colors of the chat UI, see [Theme::from_settings].
+    theme: Theme,
+    /// Template of the prompt from `chat.prompt`, see [prompt::generate_templated_prompt].
+    prompt_template: Option<String>,
+    /// Notifies the user when a response is ready or a tool needs approval.
+    notifier: Notifier,
+    /// Snapshots the conversation every few turns, see `chat.autosave.turns`.
+    autosave: Autosaver,
+    /// Appends the prompts, responses and tool uses to `chat.transcript.path` as they happen.
+    transcript_log: Option<TranscriptLog>,
+    /// Records every tool the model asks to use to the audit log, see `chat.audit.enabled`.
+    audit_log: Option<AuditLog>,
+    /// Prompts estimated to take more tokens than this need to be confirmed, from
+    /// `chat.largePromptThreshold`. Disabled when 0.
+    large_prompt_threshold: usize,
+    /// Requests estimated to fill more of the context window than this percentage first compact
+    /// the older messages, from `chat.autoCompact.threshold`. Disabled when 0.
+    auto_compact_threshold: usize,
+    /// Conversations saved longer ago than this are deleted when the chat starts, from
+    /// `chat.sessions.retention`.
+    session_retention: Option<Duration>,
+    /// The token `/share` creates gists with, from `chat.share.githubToken`.
+    github_token: Option<String>,
+    /// Where saved conversations are synced after `/save`, from `chat.sync.url`.
+    sync_backend: Option<SyncBackend>,
+    /// The prompt history synced along with them.
+    sync_history_path: Option<PathBuf>,
+    /// The domains pages can be added to the context from, from `chat.urlContext.allowedDomains`.
+    url_allowed_domains: Vec<String>,
+    /// Whether to show the status line above the prompt, from `chat.statusLine`.
+    status_line: bool,
+    /// Whether to open responses longer than the terminal in the pager, from `chat.autopage`.
+    autopage: bool,
+    /// A response to open in the pager once it is done streaming, see [Self::autopage].
+    page_pending: Option<String>,
+    /// When a response was last interrupted with Ctrl+C, see [DOUBLE_CTRL_C_WINDOW].
+    interrupted_at: Option<Instant>,
+    /// Prompts submitted while a response was streaming or its tools running, sent one by one once
+    /// the current turn completes.
+    queued_messages: VecDeque<String>,
+    /// Listens to the terminal from when a response streams until the next prompt, across the
+    /// tools run meanwhile, see [Self::stop_typeahead].
+    typeahead: Option<EscapeListener>,
     interactive: bool,
     /// The client to use to interact with the model.
     client: StreamingClient,
     /// Width of the terminal, required for [ParseState].
     terminal_width_provider: fn() -> Option<usize>,
-    spinner: Option<Spinner>,
+    spinner: Option<ChatSpinner>,
+    /// How [Self::spinner] is drawn.
+    spinner_config: SpinnerConfig,
     /// [ConversationState].
     conversation_state: ConversationState,
     /// State to track tools that need confirmation.
     tool_permissions: ToolPermissions,
+    /// Whether the tools that need confirmation are only described, the model being told they
+    /// weren't run, see `/dry-run`.
+    dry_run: bool,
+    /// Whether the tools that can change anything are refused, see `/readonly`.
+    read_only: bool,
+    /// Where `execute_bash` runs commands and the files `fs_write` is kept to, from
+    /// `chat.sandbox.backend`.
+    sandbox: Option<Sandbox>,
+    /// The environment of the commands run by tools, from `tools.env.allowlist`,
+    /// `tools.env.denylist` and the `env` of `mcp.json`.
+    tool_env: ToolEnv,
+    /// The profile, regions and operations `use_aws` is kept to, from `tools.useAws`.
+    aws_scope: AwsScope,
+    /// The domains `http_request` can send requests to and the headers it adds, from
+    /// `tools.httpRequest`.
+    http_config: HttpConfig,
+    /// The repository `create_github_issue` files issues in, from `tools.githubIssue`.
+    github_issue_config: GithubIssueConfig,
+    /// Whether `execute_bash` runs commands under a pseudo-terminal, from `chat.shell.pty`, only
+    /// those the model says are interactive when unset.
+    shell_pty: Option<bool>,
+    /// The commands `execute_bash` started in the background, see `/ps`.
+    background_processes: BackgroundProcesses,
+    /// How long tools can run before they are stopped.
+    tool_timeouts: ToolTimeouts,
+    /// How much of the output of tools is added to the conversation.
+    tool_output_limits: OutputLimits,
+    /// The results of the tools that only read, reused while the files they read are unchanged.
+    tool_cache: ToolCache,
+    /// The last tool use whose output was shortened, shown by `/expand` by default.
+    last_shortened_tool_use: Option<String>,
     /// Telemetry events to be sent as part of the conversation.
     tool_use_telemetry_events: HashMap<String, ToolUseEventBuilder>,
     /// State used to keep track of tool use relation
     tool_use_status: ToolUseStatus,
+    /// The similar tool uses approved along with the one pending approval, if any.
+    approval_batch: Option<ApprovalBatch>,
     /// Any failed requests that could be useful for error report/debugging
     failed_request_ids: Vec<String>,
     /// Pending prompts to be sent
     pending_prompts: VecDeque<Prompt>,
+    /// The name the conversation was last saved or loaded with, see `/save`.
+    session_name: Option<String>,
+    /// The branches made with `/fork` and the conversations started with `/new`.
+    branches: Branches,
+    /// The files changed by `fs_write` in each turn, for `/revert`.
+    checkpoints: Checkpoints,
+}
+
+/// The switches of a chat, from the arguments of `q chat`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChatFlags {
+    /// Whether the user is there to answer, rather than input being piped in or given as argument.
+    pub interactive: bool,
+    /// Whether the spinner is hidden while waiting for a response, see [SpinnerConfig].
+    pub no_spinner: bool,
+    /// Whether the chat starts in dry run mode, see `/dry-run`.
+    pub dry_run: bool,
+    /// Whether the chat starts in read-only mode, see `/readonly`.
+    pub read_only: bool,
 }
 
 impl ChatContext {
@@ -525,25 +960,40 @@ impl ChatContext {
         conversation_id: &str,
         output: SharedWriter,
         mut input: Option<String>,
-        input_source: InputSource,
-        interactive: bool,
-        resume_conversation: bool,
+        mut input_source: InputSource,
+        flags: ChatFlags,
+        resume: Option<Resume>,
         client: StreamingClient,
         terminal_width_provider: fn() -> Option<usize>,
         tool_manager: ToolManager,
         profile: Option<String>,
         tool_config: HashMap<String, ToolSpec>,
         tool_permissions: ToolPermissions,
+        editor: Option<&str>,
     ) -> Result<Self> {
+        let ChatFlags {
+            interactive,
+            no_spinner,
+            dry_run,
+            read_only,
+        } = flags;
         let ctx_clone = Arc::clone(&ctx);
         let output_clone = output.clone();
+        let tool_env = ToolEnv::from_settings(&database.settings, tool_manager.env.clone())?;
 
         let mut existing_conversation = false;
-        let conversation_state = if resume_conversation {
-            let prior = std::env::current_dir()
-                .ok()
-                .and_then(|cwd| database.get_conversation_by_path(cwd).ok())
-                .flatten();
+        let session_name = match &resume {
+            Some(Resume::Session(name)) => Some(name.clone()),
+            _ => None,
+        };
+        let mut conversation_state = if let Some(resume) = resume {
+            let prior = match resume {
+                Resume::Directory => std::env::current_dir()
+                    .ok()
+                    .and_then(|cwd| database.get_conversation_by_path(cwd).ok())
+                    .flatten(),
+                Resume::Session(name) => Some(load_session(&ctx, &directories::chat_sessions_dir()?, &name).await?),
+            };
 
             // Only restore conversations where there were actual messages.
             // Prevents edge case where user clears conversation with --new, then exits without chatting.
@@ -580,28 +1030,114 @@ impl ChatContext {
             .await
         };
 
+        if database.settings.get_bool(Setting::ChatAutoContext).unwrap_or(true) {
+            if let Ok(cwd) = ctx.env().current_dir() {
+                conversation_state.set_project_context(project::project_overview(&ctx, &cwd).await);
+            }
+        }
+        conversation_state.redactor = Redactor::from_settings(&database.settings);
+        conversation_state.default_system_prompt = system_prompt::from_settings(&database.settings);
+        conversation_state.index_retriever = IndexRetriever::load(&ctx, &database.settings).await;
+
+        let scratch_dir = ctx.fs().chroot_path(EditorLauncher::default_scratch_dir());
+        let editor = EditorLauncher::new(conversation_id, &scratch_dir)
+            .with_editor(EditorCommand::resolve(editor, &database.settings))
+            .with_extension(EditorLauncher::extension_from_settings(&database.settings))
+            .with_template(EditorLauncher::template_from_settings(&database.settings))
+            .with_auto_open_lines(EditorLauncher::auto_open_lines_from_settings(&database.settings))
+            .with_watch_grace_period(EditorLauncher::watch_grace_period_from_settings(&database.settings))
+            .with_history(
+                database
+                    .settings
+                    .get_bool(Setting::ChatEditModeIncludeHistory)
+                    .unwrap_or(false),
+            );
+        input_source.put_editor_keybindings();
+
+        let theme = Theme::from_settings(&database.settings);
+        if theme.is_no_color() {
+            style::force_color_output(false);
+        }
+
+        let spinner_config = SpinnerConfig::resolve(no_spinner, &ctx, &database.settings);
+        let sandbox = Sandbox::from_settings(&database.settings, &ctx.env().current_dir()?)?;
+        let autosave = Autosaver::new(&database.settings, directories::chat_autosave_dir()?, conversation_id);
         Ok(Self {
             ctx,
             output,
             initial_input: input,
             existing_conversation,
             input_source,
+            editor,
+            pending_input: None,
+            theme,
+            prompt_template: database.settings.get_string(Setting::ChatPrompt),
+            notifier: Notifier::from_settings(&database.settings),
+            autosave,
+            transcript_log: TranscriptLog::from_settings(&database.settings),
+            audit_log: AuditLog::from_settings(&database.settings),
+            large_prompt_threshold: database
+                .settings
+                .get_int(Setting::ChatLargePromptThreshold)
+                .and_then(|tokens| usize::try_from(tokens).ok())
+                .unwrap_or(LARGE_PROMPT_THRESHOLD),
+            auto_compact_threshold: database
+                .settings
+                .get_int(Setting::ChatAutoCompactThreshold)
+                .and_then(|percent| usize::try_from(percent).ok())
+                .unwrap_or(AUTO_COMPACT_THRESHOLD),
+            session_retention: retention_setting(database),
+            github_token: database.settings.get_string(Setting::ChatShareGithubToken),
+            sync_backend: SyncBackend::from_settings(&database.settings)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!(?err, "Ignoring chat.sync.url");
+                    None
+                }),
+            sync_history_path: sync::history_path(&database.settings),
+            url_allowed_domains: url_context::allowed_domains(&database.settings),
+            status_line: database.settings.get_bool(Setting::ChatStatusLine).unwrap_or(false),
+            autopage: database.settings.get_bool(Setting::ChatAutopage).unwrap_or(false),
+            page_pending: None,
+            interrupted_at: None,
+            queued_messages: VecDeque::new(),
+            typeahead: None,
             interactive,
             client,
             terminal_width_provider,
             spinner: None,
+            spinner_config,
             tool_permissions,
+            dry_run,
+            read_only,
+            sandbox,
+            tool_env,
+            aws_scope: AwsScope::from_settings(&database.settings)?,
+            http_config: HttpConfig::from_settings(&database.settings),
+            github_issue_config: GithubIssueConfig::from_settings(&database.settings),
+            shell_pty: database.settings.get_bool(Setting::ChatShellPty),
+            background_processes: BackgroundProcesses::default(),
+            tool_timeouts: ToolTimeouts::from_settings(&database.settings),
+            tool_output_limits: OutputLimits::from_settings(&database.settings),
+            tool_cache: ToolCache::default(),
+            last_shortened_tool_use: None,
             conversation_state,
             tool_use_telemetry_events: HashMap::new(),
             tool_use_status: ToolUseStatus::Idle,
+            approval_batch: None,
             failed_request_ids: Vec::new(),
             pending_prompts: VecDeque::new(),
+            session_name,
+            branches: Branches::default(),
+            checkpoints: Checkpoints::default(),
         })
     }
 }
 
 impl Drop for ChatContext {
     fn drop(&mut self) {
+        self.editor.cleanup();
+
         if let Some(spinner) = &mut self.spinner {
             spinner.stop();
         }
@@ -659,6 +1195,8 @@ enum ChatState {
         show_summary: bool,
         /// Whether or not to show the /compact help text.
         help: bool,
+        /// Which messages are summarized, and how.
+        strategy: CompactStrategy,
     },
     /// Exit the chat.
     Exit,
@@ -675,57 +1213,6 @@ impl Default for ChatState {
 }
 
 impl ChatContext {
-    /// Opens the user's preferred editor to compose a prompt
-    fn open_editor(initial_text: Option<String>) -> Result<String, ChatError> {
-        // Create a temporary file with a unique name
-        let temp_dir = std::env::temp_dir();
-        let file_name = format!("q_prompt_{}.md", Uuid::new_v4());
-        let temp_file_path = temp_dir.join(file_name);
-
-        // Get the editor from environment variable or use a default
-        let editor_cmd = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
-
-        // Parse the editor command to handle arguments
-        let mut parts =
-            shlex::split(&editor_cmd).ok_or_else(|| ChatError::Custom("Failed to parse EDITOR command".into()))?;
-
-        if parts.is_empty() {
-            return Err(ChatError::Custom("EDITOR environment variable is empty".into()));
-        }
-
-        let editor_bin = parts.remove(0);
-
-        // Write initial content to the file if provided
-        let initial_content = initial_text.unwrap_or_default();
-        fs::write(&temp_file_path, &initial_content)
-            .map_err(|e| ChatError::Custom(format!("Failed to create temporary file: {}", e).into()))?;
-
-        // Open the editor with the parsed command and arguments
-        let mut cmd = ProcessCommand::new(editor_bin);
-        // Add any arguments that were part of the EDITOR variable
-        for arg in parts {
-            cmd.arg(arg);
-        }
-        // Add the file path as the last argument
-        let status = cmd
-            .arg(&temp_file_path)
-            .status()
-            .map_err(|e| ChatError::Custom(format!("Failed to open editor: {}", e).into()))?;
-
-        if !status.success() {
-            return Err(ChatError::Custom("Editor exited with non-zero status".into()));
-        }
-
-        // Read the content back
-        let content = fs::read_to_string(&temp_file_path)
-            .map_err(|e| ChatError::Custom(format!("Failed to read temporary file: {}", e).into()))?;
-
-        // Clean up the temporary file
-        let _ = fs::remove_file(&temp_file_path);
-
-        Ok(content.trim().to_string())
-    }
-
     async fn try_chat(&mut self, database: &mut Database, telemetry: &TelemetryThread) -> Result<()> {
         let is_small_screen = self.terminal_width() < GREETING_BREAK_POINT;
         if self.interactive && database.settings.get_bool(Setting::ChatGreetingEnabled).unwrap_or(true) {
@@ -777,6 +1264,29 @@ impl ChatContext {
             execute!(self.output, style::Print("\n"), style::SetForegroundColor(Color::Reset))?;
         }
 
+        if self.interactive && self.editor.recover_orphaned_draft().is_some() {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print("Recovered an unsent prompt draft from a previous session. Use "),
+                style::SetForegroundColor(Color::Green),
+                style::Print("/editor"),
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(format!(
+                    " to continue editing it ({}).\n\n",
+                    self.editor.scratch_path().display()
+                )),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+
+        if let Some(older_than) = self.session_retention {
+            self.prune_expired_sessions(older_than).await?;
+        }
+        if self.interactive && !self.existing_conversation && self.initial_input.is_none() {
+            self.offer_autosave_resume().await?;
+        }
+
         if self.interactive && self.all_tools_trusted() {
             queue!(
                 self.output,
@@ -786,6 +1296,32 @@ impl ChatContext {
                 ))
             )?;
         }
+        if self.dry_run {
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Green),
+                style::Print(
+                    "Dry-run mode is on: the commands and file changes the model asks for are shown but not run. Use "
+                ),
+                style::SetForegroundColor(Color::Reset),
+                style::Print("/dry-run off"),
+                style::SetForegroundColor(Color::Green),
+                style::Print(" to run them.\n\n"),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        if self.read_only {
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Green),
+                style::Print("Read-only mode is on: the tools that can change anything are refused. Use "),
+                style::SetForegroundColor(Color::Reset),
+                style::Print("/readonly off"),
+                style::SetForegroundColor(Color::Green),
+                style::Print(" to allow them.\n\n"),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
         self.output.flush()?;
 
         let mut next_state = Some(ChatState::PromptUser {
@@ -808,6 +1344,14 @@ impl ChatContext {
             let ctrl_c_stream = ctrl_c();
             debug!(?chat_state, "changing to state");
 
+            // Typing ahead goes on while the tools of a response run, up to the next prompt
+            if !matches!(
+                chat_state,
+                ChatState::ValidateTools(_) | ChatState::ExecuteTools(_) | ChatState::HandleResponseStream(_)
+            ) {
+                self.stop_typeahead()?;
+            }
+
             // Update conversation state with new tool information
             self.conversation_state.update_state(false).await;
 
@@ -831,7 +1375,7 @@ impl ChatContext {
                 } => {
                     let tool_uses_clone = tool_uses.clone();
                     tokio::select! {
-                        res = self.handle_input(telemetry, input, tool_uses, pending_tool_index) => res,
+                        res = self.handle_input(database, telemetry, input, tool_uses, pending_tool_index) => res,
                         Ok(_) = ctrl_c_stream => Err(ChatError::Interrupted { tool_uses: tool_uses_clone })
                     }
                 },
@@ -841,17 +1385,18 @@ impl ChatContext {
                     prompt,
                     show_summary,
                     help,
+                    strategy,
                 } => {
                     let tool_uses_clone = tool_uses.clone();
                     tokio::select! {
-                        res = self.compact_history(telemetry, tool_uses, pending_tool_index, prompt, show_summary, help) => res,
+                        res = self.compact_history(telemetry, tool_uses, pending_tool_index, prompt, show_summary, help, strategy) => res,
                         Ok(_) = ctrl_c_stream => Err(ChatError::Interrupted { tool_uses: tool_uses_clone })
                     }
                 },
                 ChatState::ExecuteTools(tool_uses) => {
                     let tool_uses_clone = tool_uses.clone();
                     tokio::select! {
-                        res = self.tool_use_execute(database, telemetry, tool_uses) => res,
+                        res = self.tool_use_execute(telemetry, tool_uses) => res,
                         Ok(_) = ctrl_c_stream => Err(ChatError::Interrupted { tool_uses: Some(tool_uses_clone) })
                     }
                 },
@@ -861,10 +1406,8 @@ impl ChatContext {
                         Ok(_) = ctrl_c_stream => Err(ChatError::Interrupted { tool_uses: None })
                     }
                 },
-                ChatState::HandleResponseStream(response) => tokio::select! {
-                    res = self.handle_response(database, telemetry, response) => res,
-                    Ok(_) = ctrl_c_stream => Err(ChatError::Interrupted { tool_uses: None })
-                },
+                // Interruptions are handled while receiving the response, to keep what was received
+                ChatState::HandleResponseStream(response) => self.handle_response(database, telemetry, response).await,
                 ChatState::Exit => return Ok(()),
             };
 
@@ -889,7 +1432,7 @@ impl ChatContext {
                         queue!(
                             self.output,
                             style::SetAttribute(Attribute::Bold),
-                            style::SetForegroundColor(Color::Red),
+                            style::SetForegroundColor(self.theme.error),
                         )?;
 
                         let report = eyre::Report::from($err);
@@ -954,7 +1497,7 @@ impl ChatContext {
                             if !self.conversation_state.can_create_summary_request().await {
                                 execute!(
                                     self.output,
-                                    style::SetForegroundColor(Color::Red),
+                                    style::SetForegroundColor(self.theme.error),
                                     style::Print("Your conversation is too large to continue.\n"),
                                     style::SetForegroundColor(Color::Reset),
                                     style::Print(format!("• Run {} to analyze your context usage\n", "/usage".green())),
@@ -987,6 +1530,7 @@ impl ChatContext {
                                 prompt: None,
                                 show_summary: false,
                                 help: false,
+                                strategy: CompactStrategy::default(),
                             });
                         },
                         crate::api_client::ApiClientError::QuotaBreach(msg) => {
@@ -1014,7 +1558,9 @@ impl ChatContext {
     /// Compacts the conversation history, replacing the history with a summary generated by the
     /// model.
     ///
-    /// The last two user messages in the history are not included in the compaction process.
+    /// The last `strategy.keep_last` messages in the history are not included in the compaction
+    /// process.
+    #[allow(clippy::too_many_arguments)]
     async fn compact_history(
         &mut self,
         telemetry: &TelemetryThread,
@@ -1023,6 +1569,7 @@ impl ChatContext {
         custom_prompt: Option<String>,
         show_summary: bool,
         help: bool,
+        strategy: CompactStrategy,
     ) -> Result<ChatState, ChatError> {
         let hist = self.conversation_state.history();
         debug!(?hist, "compacting history");
@@ -1043,7 +1590,7 @@ impl ChatContext {
             });
         }
 
-        if self.conversation_state.history().len() < 2 {
+        if self.conversation_state.history().len() <= strategy.keep_last {
             execute!(
                 self.output,
                 style::SetForegroundColor(Color::Yellow),
@@ -1058,14 +1605,16 @@ impl ChatContext {
             });
         }
 
+        let tokens_before = TokenCount::from(self.conversation_state.calculate_char_count().await);
+
         // Send a request for summarizing the history.
         let summary_state = self
             .conversation_state
-            .create_summary_request(custom_prompt.as_ref())
+            .create_summary_request(custom_prompt.as_ref(), strategy)
             .await;
         if self.interactive {
             execute!(self.output, cursor::Hide, style::Print("\n"))?;
-            self.spinner = Some(Spinner::new(Spinners::Dots, "Creating summary...".to_string()));
+            self.spinner = Some(self.spinner_config.start("Creating summary..."));
         }
         let response = self.client.send_message(summary_state).await;
 
@@ -1137,7 +1686,9 @@ impl ChatContext {
                 .ok();
         }
 
-        self.conversation_state.replace_history_with_summary(summary.clone());
+        self.conversation_state
+            .replace_history_with_summary(summary.clone(), strategy.keep_last);
+        let tokens_after = TokenCount::from(self.conversation_state.calculate_char_count().await);
 
         // Print output to the user.
         {
@@ -1149,12 +1700,28 @@ impl ChatContext {
             )?;
 
             let mut output = Vec::new();
+            execute!(
+                output,
+                style::Print(format!("• Tokens: ~{tokens_before} → ~{tokens_after}\n"))
+            )?;
             if let Some(custom_prompt) = &custom_prompt {
                 execute!(
                     output,
                     style::Print(format!("• Custom prompt applied: {}\n", custom_prompt))
                 )?;
             }
+            if strategy.keep_last > 1 {
+                execute!(
+                    output,
+                    style::Print(format!("• Kept the last {} messages\n", strategy.keep_last))
+                )?;
+            }
+            if strategy.preserve_code {
+                execute!(output, style::Print("• Code preserved verbatim\n"))?;
+            }
+            if strategy.aggressive {
+                execute!(output, style::Print("• Aggressive summary\n"))?;
+            }
             animate_output(&mut self.output, &output)?;
 
             // Display the summary if the show_summary flag is set
@@ -1233,26 +1800,109 @@ impl ChatContext {
             }
         }
 
+        // The turn is complete, send the next prompt that was queued while it was streaming
+        if pending_tool_index.is_none() {
+            if let Some(message) = self.queued_messages.pop_front() {
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(self.theme.prompt),
+                    style::Print("> "),
+                    style::SetForegroundColor(Color::Reset),
+                    style::Print(&message),
+                    style::Print("\n")
+                )?;
+                self.conversation_state.append_user_transcript(&message);
+                return Ok(ChatState::HandleInput {
+                    input: message,
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                });
+            }
+        }
+
         let show_tool_use_confirmation_dialog = !skip_printing_tools && pending_tool_index.is_some();
         if show_tool_use_confirmation_dialog {
-            execute!(
+            let batch = pending_tool_index.and_then(|index| self.pending_batch(index));
+            // fs_write can also be trusted with the file it changes only
+            let file_trust = batch.is_none()
+                && pending_tool_index
+                    .and_then(|index| tool_uses.get(index))
+                    .is_some_and(|tool_use| matches!(tool_use.tool, Tool::FsWrite(_)));
+            let pattern = batch.as_ref().and_then(|batch| batch.pattern.as_ref());
+            match &batch {
+                Some(batch) => queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!("\nAllow these {} actions? Use '", batch.indices.len())),
+                    style::SetForegroundColor(Color::Green),
+                    style::Print("y"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("' to allow all of them, '"),
+                )?,
+                None => queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("\nAllow this action? Use '"),
+                )?,
+            }
+            if let Some(pattern) = pattern {
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print("p"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!("' to trust {pattern} for the session, '")),
+                )?;
+            }
+            queue!(
                 self.output,
-                style::SetForegroundColor(Color::DarkGrey),
-                style::Print("\nAllow this action? Use '"),
                 style::SetForegroundColor(Color::Green),
                 style::Print("t"),
                 style::SetForegroundColor(Color::DarkGrey),
-                style::Print("' to trust (always allow) this tool for the session. ["),
+                style::Print("' to trust (always allow) this tool for the session, '"),
+            )?;
+            if file_trust {
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print("f"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("' to trust it with this file for the session, '"),
+                )?;
+            }
+            queue!(
+                self.output,
                 style::SetForegroundColor(Color::Green),
-                style::Print("y"),
+                style::Print("a"),
                 style::SetForegroundColor(Color::DarkGrey),
-                style::Print("/"),
+                style::Print("' to always allow it and '"),
                 style::SetForegroundColor(Color::Green),
-                style::Print("n"),
+                style::Print("d"),
                 style::SetForegroundColor(Color::DarkGrey),
-                style::Print("/"),
-                style::SetForegroundColor(Color::Green),
-                style::Print("t"),
+                style::Print("' to always deny it, in the next sessions as well. ["),
+            )?;
+            let choices = ["y", "n"]
+                .into_iter()
+                .chain(pattern.map(|_| "p"))
+                .chain(["t"])
+                .chain(file_trust.then_some("f"))
+                .chain(["a", "d"]);
+            for (i, choice) in choices.enumerate() {
+                if i > 0 {
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("/")
+                    )?;
+                }
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(choice)
+                )?;
+            }
+            execute!(
+                self.output,
                 style::SetForegroundColor(Color::DarkGrey),
                 style::Print("]:\n\n"),
                 style::SetForegroundColor(Color::Reset),
@@ -1275,17 +1925,47 @@ impl ChatContext {
             self.input_source
                 .put_skim_command_selector(database, Arc::new(context_manager.clone()), tool_names);
         }
+        // Let the editor quote the last exchange while composing the next prompt
+        let history = self.conversation_state.history();
+        self.editor.set_conversation_tail(
+            history.iter().rev().find_map(|(user, _)| user.prompt()),
+            history.back().map(|(_, assistant)| assistant.content()),
+        );
+
         execute!(
             self.output,
             style::SetForegroundColor(Color::Reset),
             style::SetAttribute(Attribute::Reset)
         )?;
-        let user_input = match self.read_user_input(&self.generate_tool_trust_prompt(), false) {
+        // Only refreshed once the turn is complete, not while tools wait for approval
+        if self.status_line && self.interactive && pending_tool_index.is_none() {
+            let status_line = self.generate_status_line().await;
+            execute!(self.output, style::Print(status_line), style::Print("\n"))?;
+        }
+        let prompt = self.generate_tool_trust_prompt().await;
+        let user_input = match self.read_user_input(&prompt, false).await {
             Some(input) => input,
             None => return Ok(ChatState::Exit),
         };
 
+        if pending_tool_index.is_none() && self.editor.should_auto_open(&user_input) {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(
+                    "\nInput has {} lines, opening it in the editor...\n",
+                    user_input.lines().count()
+                )),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+            return self
+                .compose_in_editor(Some(user_input), tool_uses, pending_tool_index)
+                .await;
+        }
+
         self.conversation_state.append_user_transcript(&user_input);
+        // A draft composed with Ctrl+F is sent once it's submitted from the prompt
+        self.editor.discard_draft_if_submitted(&user_input);
         Ok(ChatState::HandleInput {
             input: user_input,
             tool_uses: Some(tool_uses),
@@ -1293,20 +1973,106 @@ impl ChatContext {
         })
     }
 
+    /// Opens the system editor seeded with `initial_text` and submits the result as the next
+    /// prompt.
+    async fn compose_in_editor(
+        &mut self,
+        initial_text: Option<String>,
+        tool_uses: Vec<QueuedTool>,
+        pending_tool_index: Option<usize>,
+    ) -> Result<ChatState, ChatError> {
+        Ok(match self.editor.launch_system_editor(initial_text).await {
+            Ok(EditorOutput::Cancelled) => {
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Yellow),
+                    style::Print("\nEditor closed without saving, not submitting.\n\n"),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Ok(EditorOutput::Edited(content)) => {
+                if content.trim().is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("\nEmpty content from editor, not submitting.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+
+                    ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    }
+                } else {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print("\nContent loaded from editor. Submitting prompt...\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+
+                    // Display the content as if the user typed it
+                    execute!(
+                        self.output,
+                        style::SetAttribute(Attribute::Reset),
+                        style::SetForegroundColor(Color::Magenta),
+                        style::Print("> "),
+                        style::SetAttribute(Attribute::Reset),
+                        style::Print(&content),
+                        style::Print("\n")
+                    )?;
+
+                    // The draft is being sent, start the next one from a clean buffer
+                    self.editor.discard_draft();
+
+                    // Process the content as user input
+                    ChatState::HandleInput {
+                        input: content,
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                    }
+                }
+            },
+            Err(e) => {
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(self.theme.error),
+                    style::Print(format!("\nError opening editor: {}\n\n", e)),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+        })
+    }
+
     async fn handle_input(
         &mut self,
+        database: &mut Database,
         telemetry: &TelemetryThread,
         mut user_input: String,
         tool_uses: Option<Vec<QueuedTool>>,
         pending_tool_index: Option<usize>,
     ) -> Result<ChatState, ChatError> {
+        self.notifier.start_turn();
         let command_result = Command::parse(&user_input, &mut self.output);
 
         if let Err(error_message) = &command_result {
             // Display error message for command parsing errors
             execute!(
                 self.output,
-                style::SetForegroundColor(Color::Red),
+                style::SetForegroundColor(self.theme.error),
                 style::Print(format!("\nError: {}\n\n", error_message)),
                 style::SetForegroundColor(Color::Reset)
             )?;
@@ -1323,19 +2089,86 @@ impl ChatContext {
 
         Ok(match command {
             Command::Ask { prompt } => {
+                if self.interactive && !self.confirm_large_prompt(&prompt).await? {
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                }
+
                 // Check for a pending tool approval
                 if let Some(index) = pending_tool_index {
-                    let tool_use = &mut tool_uses[index];
+                    // The similar uses described along with the pending one get the same answer
+                    let batch = self.pending_batch(index);
+                    self.approval_batch = None;
+                    let indices = batch
+                        .as_ref()
+                        .map_or_else(|| vec![index], |batch| batch.indices.clone());
+                    let tool_use = &tool_uses[index];
 
                     let is_trust = ["t", "T"].contains(&prompt.as_str());
-                    if ["y", "Y"].contains(&prompt.as_str()) || is_trust {
+                    let is_file_trust = ["f", "F"].contains(&prompt.as_str())
+                        && batch.is_none()
+                        && matches!(tool_use.tool, Tool::FsWrite(_));
+                    let is_always_allow = ["a", "A"].contains(&prompt.as_str());
+                    let trusted_pattern = batch
+                        .and_then(|batch| batch.pattern)
+                        .filter(|_| ["p", "P"].contains(&prompt.as_str()));
+                    if ["y", "Y"].contains(&prompt.as_str())
+                        || is_trust
+                        || is_file_trust
+                        || is_always_allow
+                        || trusted_pattern.is_some()
+                    {
                         if is_trust {
                             self.tool_permissions.trust_tool(&tool_use.name);
+                        } else if let (true, Tool::FsWrite(fs_write)) = (is_file_trust, &tool_use.tool) {
+                            self.tool_permissions
+                                .trust_file(sanitize_path_tool_arg(&self.ctx, fs_write.path()));
+                        } else if is_always_allow {
+                            if let Err(err) = self
+                                .tool_permissions
+                                .always_allow(&mut database.settings, &tool_use.name)
+                                .await
+                            {
+                                warn!(?err, "Failed to save the tool as trusted");
+                            }
+                        } else if let Some(pattern) = trusted_pattern {
+                            match ToolPattern::parse(&pattern) {
+                                Ok(pattern) => self.tool_permissions.trust_pattern(pattern),
+                                Err(err) => warn!(?err, "Failed to trust the pattern of the tool uses"),
+                            }
+                        }
+                        for index in indices {
+                            tool_uses[index].accepted = true;
+                            tool_uses[index].approved = true;
                         }
-                        tool_use.accepted = true;
 
                         return Ok(ChatState::ExecuteTools(tool_uses));
                     }
+                    let is_deny = ["d", "D"].contains(&prompt.as_str());
+                    let decision = match is_deny {
+                        true => Decision::Denied,
+                        false => Decision::Rejected,
+                    };
+                    for &index in &indices {
+                        self.audit(&tool_uses[index], decision, None);
+                    }
+                    let tool_use = &tool_uses[index];
+                    if is_deny {
+                        if let Err(err) = self
+                            .tool_permissions
+                            .always_deny(&mut database.settings, &tool_use.name)
+                            .await
+                        {
+                            warn!(?err, "Failed to save the tool as denied");
+                        }
+                        user_input = format!(
+                            "I denied the use of the {} tool, in this conversation and the next ones.",
+                            tool_use.name
+                        );
+                    }
                 } else if !self.pending_prompts.is_empty() {
                     let prompts = self.pending_prompts.drain(0..).collect();
                     user_input = self
@@ -1347,10 +2180,35 @@ impl ChatContext {
                 // Otherwise continue with normal chat on 'n' or other responses
                 self.tool_use_status = ToolUseStatus::Idle;
 
+                let (redacted_input, redacted) = self.conversation_state.redactor.redact(&user_input, "prompt");
+                if redacted > 0 {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!(
+                            "Masked {} secret{} in your prompt before sending it, see /redaction show\n",
+                            redacted,
+                            if redacted == 1 { "" } else { "s" }
+                        )),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                }
+                self.log_transcript(&LogEntry::User {
+                    prompt: &redacted_input,
+                });
                 if pending_tool_index.is_some() {
-                    self.conversation_state.abandon_tool_use(tool_uses, user_input);
+                    self.conversation_state.abandon_tool_use(tool_uses, redacted_input);
                 } else {
-                    self.conversation_state.set_next_user_message(user_input).await;
+                    // The pages and definitions are added from the references as typed, and masked as
+                    // context files
+                    self.add_inline_urls(&user_input).await?;
+                    self.add_inline_symbols(&user_input).await?;
+                    self.checkpoints.start_turn(&user_input);
+                    self.conversation_state.set_next_user_message(redacted_input).await;
+                }
+
+                if let Some(state) = self.auto_compact().await? {
+                    return Ok(state);
                 }
 
                 let conv_state = self.conversation_state.as_sendable_conversation_state(true).await;
@@ -1361,7 +2219,7 @@ impl ChatContext {
                     queue!(self.output, style::SetForegroundColor(Color::Reset))?;
                     queue!(self.output, cursor::Hide)?;
                     execute!(self.output, style::Print("\n"))?;
-                    self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_owned()));
+                    self.spinner = Some(self.spinner_config.start("Thinking..."));
                 }
 
                 ChatState::HandleResponseStream(self.client.send_message(conv_state).await?)
@@ -1384,6 +2242,7 @@ impl ChatContext {
                     style::Print(
                         "\nAre you sure? This will erase the conversation history and context from hooks for the current session. "
                     ),
+                    style::Print("To only clear the screen, use /clear-screen or Ctrl+L instead. "),
                     style::Print("["),
                     style::SetForegroundColor(Color::Green),
                     style::Print("y"),
@@ -1397,7 +2256,7 @@ impl ChatContext {
                 )?;
 
                 // Setting `exit_on_single_ctrl_c` for better ux: exit the confirmation dialog rather than the CLI
-                let user_input = match self.read_user_input("> ".yellow().to_string().as_str(), true) {
+                let user_input = match self.read_user_input("> ".yellow().to_string().as_str(), true).await {
                     Some(input) => input,
                     None => "".to_string(),
                 };
@@ -1414,7 +2273,14 @@ impl ChatContext {
                         style::Print("\nConversation history cleared.\n\n"),
                         style::SetForegroundColor(Color::Reset)
                     )?;
-                }
+                } else {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nThe conversation history was kept.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                }
 
                 ChatState::PromptUser {
                     tool_uses: None,
@@ -1422,10 +2288,24 @@ impl ChatContext {
                     skip_printing_tools: true,
                 }
             },
+            Command::ClearScreen => {
+                execute!(
+                    self.output,
+                    terminal::Clear(terminal::ClearType::All),
+                    cursor::MoveTo(0, 0)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
             Command::Compact {
                 prompt,
                 show_summary,
                 help,
+                strategy,
             } => {
                 self.compact_history(
                     telemetry,
@@ -1434,6 +2314,7 @@ impl ChatContext {
                     prompt,
                     show_summary,
                     help,
+                    strategy,
                 )
                 .await?
             },
@@ -1458,765 +2339,710 @@ impl ChatContext {
                 }
             },
             Command::PromptEditor { initial_text } => {
-                match Self::open_editor(initial_text) {
-                    Ok(content) => {
-                        if content.trim().is_empty() {
+                self.compose_in_editor(initial_text, tool_uses, pending_tool_index)
+                    .await?
+            },
+            Command::Draft { subcommand } => {
+                match subcommand {
+                    DraftSubcommand::Restore => match self.editor.restore_draft() {
+                        Some(draft) => {
+                            execute!(
+                                self.output,
+                                style::SetForegroundColor(Color::Green),
+                                style::Print("\nDraft loaded into your next prompt.\n\n"),
+                                style::SetForegroundColor(Color::Reset)
+                            )?;
+                            self.pending_input = Some(draft);
+                        },
+                        None => {
                             execute!(
                                 self.output,
                                 style::SetForegroundColor(Color::Yellow),
-                                style::Print("\nEmpty content from editor, not submitting.\n\n"),
+                                style::Print("\nThere is no draft to restore.\n\n"),
                                 style::SetForegroundColor(Color::Reset)
                             )?;
+                        },
+                    },
+                    DraftSubcommand::Help => {
+                        execute!(
+                            self.output,
+                            style::Print("\n"),
+                            style::Print(DraftSubcommand::help_text()),
+                            style::Print("\n\n")
+                        )?;
+                    },
+                }
 
-                            ChatState::PromptUser {
-                                tool_uses: Some(tool_uses),
-                                pending_tool_index,
-                                skip_printing_tools: true,
-                            }
-                        } else {
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Redaction { subcommand } => {
+                match subcommand {
+                    RedactionSubcommand::Show => {
+                        let redactor = &self.conversation_state.redactor;
+                        if !redactor.is_enabled() {
                             execute!(
                                 self.output,
-                                style::SetForegroundColor(Color::Green),
-                                style::Print("\nContent loaded from editor. Submitting prompt...\n\n"),
+                                style::SetForegroundColor(Color::Yellow),
+                                style::Print(
+                                    "\nSecrets are not masked, turn masking on with: q settings chat.redaction.enabled true\n\n"
+                                ),
                                 style::SetForegroundColor(Color::Reset)
                             )?;
-
-                            // Display the content as if the user typed it
+                        } else {
                             execute!(
                                 self.output,
-                                style::SetAttribute(Attribute::Reset),
+                                style::SetAttribute(Attribute::Bold),
                                 style::SetForegroundColor(Color::Magenta),
-                                style::Print("> "),
+                                style::Print(format!(
+                                    "\n🔒 masked before sending ({} custom pattern{}):\n",
+                                    redactor.patterns().len(),
+                                    if redactor.patterns().len() == 1 { "" } else { "s" }
+                                )),
                                 style::SetAttribute(Attribute::Reset),
-                                style::Print(&content),
-                                style::Print("\n")
                             )?;
-
-                            // Process the content as user input
-                            ChatState::HandleInput {
-                                input: content,
-                                tool_uses: Some(tool_uses),
-                                pending_tool_index,
+                            if redactor.redactions().is_empty() {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print("    No secrets found so far\n"),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            }
+                            for redaction in redactor.redactions() {
+                                execute!(
+                                    self.output,
+                                    style::Print(format!("    {} ", redaction.kind)),
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print(format!("{} in {}\n", redaction.preview, redaction.source)),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            }
+                            if !redactor.invalid_patterns().is_empty() {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!(
+                                        "\nIgnored the invalid regexes of chat.redaction.patterns: {}\n",
+                                        redactor.invalid_patterns().join(", ")
+                                    )),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
                             }
+                            execute!(self.output, style::Print("\n"))?;
                         }
                     },
-                    Err(e) => {
+                    RedactionSubcommand::Help => {
                         execute!(
                             self.output,
-                            style::SetForegroundColor(Color::Red),
-                            style::Print(format!("\nError opening editor: {}\n\n", e)),
-                            style::SetForegroundColor(Color::Reset)
+                            style::Print("\n"),
+                            style::Print(RedactionSubcommand::help_text()),
+                            style::Print("\n\n")
                         )?;
-
-                        ChatState::PromptUser {
-                            tool_uses: Some(tool_uses),
-                            pending_tool_index,
-                            skip_printing_tools: true,
-                        }
                     },
                 }
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
             },
-            Command::Quit => ChatState::Exit,
-            Command::Profile { subcommand } => {
-                if let Some(context_manager) = &mut self.conversation_state.context_manager {
-                    macro_rules! print_err {
-                        ($err:expr) => {
-                            execute!(
-                                self.output,
-                                style::SetForegroundColor(Color::Red),
-                                style::Print(format!("\nError: {}\n\n", $err)),
-                                style::SetForegroundColor(Color::Reset)
-                            )?
+            Command::System { subcommand } => {
+                match subcommand {
+                    SystemSubcommand::Show => {
+                        let system_prompt = match &self.conversation_state.context_manager {
+                            Some(context_manager) => {
+                                context_manager
+                                    .system_prompt(self.conversation_state.default_system_prompt.as_deref())
+                                    .await
+                            },
+                            None => None,
                         };
-                    }
-
-                    match subcommand {
-                        command::ProfileSubcommand::List => {
-                            let profiles = match context_manager.list_profiles().await {
-                                Ok(profiles) => profiles,
-                                Err(e) => {
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Red),
-                                        style::Print(format!("\nError listing profiles: {}\n\n", e)),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                    vec![]
-                                },
-                            };
-
-                            execute!(self.output, style::Print("\n"))?;
-                            for profile in profiles {
-                                if profile == context_manager.current_profile {
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Green),
-                                        style::Print("* "),
-                                        style::Print(&profile),
-                                        style::SetForegroundColor(Color::Reset),
-                                        style::Print("\n")
-                                    )?;
-                                } else {
-                                    execute!(
-                                        self.output,
-                                        style::Print("  "),
-                                        style::Print(&profile),
-                                        style::Print("\n")
-                                    )?;
-                                }
-                            }
-                            execute!(self.output, style::Print("\n"))?;
-                        },
-                        command::ProfileSubcommand::Create { name } => {
-                            match context_manager.create_profile(&name).await {
-                                Ok(_) => {
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Green),
-                                        style::Print(format!("\nCreated profile: {}\n\n", name)),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                    context_manager
-                                        .switch_profile(&name)
-                                        .await
-                                        .map_err(|e| warn!(?e, "failed to switch to newly created profile"))
-                                        .ok();
-                                },
-                                Err(e) => print_err!(e),
-                            }
-                        },
-                        command::ProfileSubcommand::Delete { name } => {
-                            match context_manager.delete_profile(&name).await {
-                                Ok(_) => {
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Green),
-                                        style::Print(format!("\nDeleted profile: {}\n\n", name)),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                },
-                                Err(e) => print_err!(e),
-                            }
-                        },
-                        command::ProfileSubcommand::Set { name } => match context_manager.switch_profile(&name).await {
-                            Ok(_) => {
+                        match system_prompt {
+                            Some(system_prompt) => {
+                                let source = match &system_prompt.source {
+                                    SystemPromptSource::Profile(path) => format!("{}", path.display()),
+                                    SystemPromptSource::Setting => "chat.systemPrompt".to_string(),
+                                };
                                 execute!(
                                     self.output,
-                                    style::SetForegroundColor(Color::Green),
-                                    style::Print(format!("\nSwitched to profile: {}\n\n", name)),
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::Magenta),
+                                    style::Print(format!("\n🧭 system prompt ({}):\n", source)),
+                                    style::SetAttribute(Attribute::Reset),
+                                    style::Print(format!("{}\n", system_prompt.text)),
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print(format!(
+                                        "\n(~{} tkns) Sent with every message and kept by /clear, change it with /system edit.\n\n",
+                                        TokenCounter::count_tokens(&system_prompt.text)
+                                    )),
                                     style::SetForegroundColor(Color::Reset)
                                 )?;
                             },
-                            Err(e) => print_err!(e),
-                        },
-                        command::ProfileSubcommand::Rename { old_name, new_name } => {
-                            match context_manager.rename_profile(&old_name, &new_name).await {
-                                Ok(_) => {
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Green),
-                                        style::Print(format!("\nRenamed profile: {} -> {}\n\n", old_name, new_name)),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                },
-                                Err(e) => print_err!(e),
-                            }
+                            None => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print(
+                                        "\nNo system prompt. Write one for the current profile with /system edit, or for every profile with q settings chat.systemPrompt followed by the instructions or the path of a file.\n\n"
+                                    ),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            },
+                        }
+                    },
+                    SystemSubcommand::Edit => match self.edit_system_prompt().await {
+                        Ok(message) => {
+                            execute!(
+                                self.output,
+                                style::SetForegroundColor(Color::Green),
+                                style::Print(format!("\n{}\n\n", message)),
+                                style::SetForegroundColor(Color::Reset)
+                            )?;
                         },
-                        command::ProfileSubcommand::Help => {
+                        Err(e) => {
                             execute!(
                                 self.output,
-                                style::Print("\n"),
-                                style::Print(command::ProfileSubcommand::help_text()),
-                                style::Print("\n")
+                                style::SetForegroundColor(self.theme.error),
+                                style::Print(format!("\nError: {}\n\n", e)),
+                                style::SetForegroundColor(Color::Reset)
                             )?;
                         },
-                    }
+                    },
+                    SystemSubcommand::Help => {
+                        execute!(
+                            self.output,
+                            style::Print("\n"),
+                            style::Print(SystemSubcommand::help_text()),
+                            style::Print("\n\n")
+                        )?;
+                    },
                 }
+
                 ChatState::PromptUser {
                     tool_uses: Some(tool_uses),
                     pending_tool_index,
                     skip_printing_tools: true,
                 }
             },
-            Command::Context { subcommand } => {
-                if let Some(context_manager) = &mut self.conversation_state.context_manager {
-                    match subcommand {
-                        command::ContextSubcommand::Show { expand } => {
-                            fn map_chat_error(e: ErrReport) -> ChatError {
-                                ChatError::Custom(e.to_string().into())
-                            }
-                            // Display global context
+            Command::Rules { subcommand } => {
+                let Some(context_manager) = self.conversation_state.context_manager.as_mut() else {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print("\nContext management is not available.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                };
+
+                match subcommand {
+                    RulesSubcommand::List => match context_manager.rules().await {
+                        Ok(rules) if rules.is_empty() => {
+                            execute!(
+                                self.output,
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print(
+                                    "\nNo project rules found. Add markdown files to .amazonq/rules in your project for them to be followed in every conversation.\n\n"
+                                ),
+                                style::SetForegroundColor(Color::Reset)
+                            )?;
+                        },
+                        Ok(rules) => {
+                            let dir = rules_dir(&self.ctx).unwrap_or_default();
                             execute!(
                                 self.output,
                                 style::SetAttribute(Attribute::Bold),
                                 style::SetForegroundColor(Color::Magenta),
-                                style::Print("\n🌍 global:\n"),
+                                style::Print(format!("\n📏 project rules ({}):\n", dir.display())),
                                 style::SetAttribute(Attribute::Reset),
                             )?;
-                            let mut global_context_files = HashSet::new();
-                            let mut profile_context_files = HashSet::new();
-                            if context_manager.global_config.paths.is_empty() {
-                                execute!(
-                                    self.output,
-                                    style::SetForegroundColor(Color::DarkGrey),
-                                    style::Print("    <none>\n"),
-                                    style::SetForegroundColor(Color::Reset)
-                                )?;
-                            } else {
-                                for path in &context_manager.global_config.paths {
-                                    execute!(self.output, style::Print(format!("    {} ", path)))?;
-                                    if let Ok(context_files) = context_manager.get_context_files_by_path(path).await {
-                                        execute!(
-                                            self.output,
-                                            style::SetForegroundColor(Color::Green),
-                                            style::Print(format!(
-                                                "({} match{})",
-                                                context_files.len(),
-                                                if context_files.len() == 1 { "" } else { "es" }
-                                            )),
-                                            style::SetForegroundColor(Color::Reset)
-                                        )?;
-                                        global_context_files.extend(context_files);
-                                    }
-                                    execute!(self.output, style::Print("\n"))?;
+                            for rule in &rules {
+                                let tokens = TokenCounter::count_tokens(&rule.content);
+                                match rule.enabled {
+                                    true => execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print("    ✓ "),
+                                        style::SetForegroundColor(Color::Reset),
+                                        style::Print(&rule.name),
+                                        style::SetForegroundColor(Color::DarkGrey),
+                                        style::Print(format!(" (~{} tkns)\n", tokens)),
+                                        style::SetForegroundColor(Color::Reset),
+                                    )?,
+                                    false => execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::DarkGrey),
+                                        style::Print(format!("    ✗ {} (disabled)\n", rule.name)),
+                                        style::SetForegroundColor(Color::Reset),
+                                    )?,
                                 }
                             }
-
-                            if expand {
-                                queue!(
-                                    self.output,
-                                    style::SetAttribute(Attribute::Bold),
-                                    style::SetForegroundColor(Color::DarkYellow),
-                                    style::Print("\n    🔧 Hooks:\n")
-                                )?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
-                            }
-
-                            // Display profile context
                             execute!(
                                 self.output,
-                                style::SetAttribute(Attribute::Bold),
-                                style::SetForegroundColor(Color::Magenta),
-                                style::Print(format!("\n👤 profile ({}):\n", context_manager.current_profile)),
-                                style::SetAttribute(Attribute::Reset),
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print(
+                                    "\nToggle them for this conversation with /rules enable|disable <name>.\n\n"
+                                ),
+                                style::SetForegroundColor(Color::Reset)
                             )?;
-
-                            if context_manager.profile_config.paths.is_empty() {
+                        },
+                        Err(e) => {
+                            execute!(
+                                self.output,
+                                style::SetForegroundColor(self.theme.error),
+                                style::Print(format!("\nError: {}\n\n", e)),
+                                style::SetForegroundColor(Color::Reset)
+                            )?;
+                        },
+                    },
+                    RulesSubcommand::Enable { ref names } | RulesSubcommand::Disable { ref names } => {
+                        let enable = matches!(subcommand, RulesSubcommand::Enable { .. });
+                        match context_manager.set_rules_enabled(names, enable).await {
+                            Ok(names) => {
                                 execute!(
                                     self.output,
-                                    style::SetForegroundColor(Color::DarkGrey),
-                                    style::Print("    <none>\n\n"),
+                                    style::SetForegroundColor(Color::Green),
+                                    style::Print(format!(
+                                        "\n{} {} {}.\n\n",
+                                        if enable { "Enabled" } else { "Disabled" },
+                                        if names.len() == 1 { "rule" } else { "rules" },
+                                        names.join(", ")
+                                    )),
                                     style::SetForegroundColor(Color::Reset)
                                 )?;
-                            } else {
-                                for path in &context_manager.profile_config.paths {
-                                    execute!(self.output, style::Print(format!("    {} ", path)))?;
-                                    if let Ok(context_files) = context_manager.get_context_files_by_path(path).await {
-                                        execute!(
-                                            self.output,
-                                            style::SetForegroundColor(Color::Green),
-                                            style::Print(format!(
-                                                "({} match{})",
-                                                context_files.len(),
-                                                if context_files.len() == 1 { "" } else { "es" }
-                                            )),
-                                            style::SetForegroundColor(Color::Reset)
-                                        )?;
-                                        profile_context_files.extend(context_files);
-                                    }
-                                    execute!(self.output, style::Print("\n"))?;
-                                }
-                                execute!(self.output, style::Print("\n"))?;
-                            }
-
-                            if expand {
-                                queue!(
-                                    self.output,
-                                    style::SetAttribute(Attribute::Bold),
-                                    style::SetForegroundColor(Color::DarkYellow),
-                                    style::Print("    🔧 Hooks:\n")
-                                )?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
-                                execute!(self.output, style::Print("\n"))?;
-                            }
-
-                            if global_context_files.is_empty() && profile_context_files.is_empty() {
+                            },
+                            Err(e) => {
                                 execute!(
                                     self.output,
-                                    style::SetForegroundColor(Color::DarkGrey),
-                                    style::Print("No files in the current directory matched the rules above.\n\n"),
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!("\nError: {}\n\n", e)),
                                     style::SetForegroundColor(Color::Reset)
                                 )?;
-                            } else {
-                                let total = global_context_files.len() + profile_context_files.len();
-                                let total_tokens = global_context_files
-                                    .iter()
-                                    .map(|(_, content)| TokenCounter::count_tokens(content))
-                                    .sum::<usize>()
-                                    + profile_context_files
-                                        .iter()
-                                        .map(|(_, content)| TokenCounter::count_tokens(content))
-                                        .sum::<usize>();
+                            },
+                        }
+                    },
+                    RulesSubcommand::Help => {
+                        execute!(
+                            self.output,
+                            style::Print("\n"),
+                            style::Print(RulesSubcommand::help_text()),
+                            style::Print("\n\n")
+                        )?;
+                    },
+                }
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Multiline { enabled } => {
+                let enabled = enabled.unwrap_or(!self.input_source.multiline());
+                self.input_source.set_multiline(enabled);
+                let message = match enabled {
+                    true => {
+                        "\nMulti-line mode is on: Enter inserts a newline, and an empty line, Alt+Enter or Ctrl+D sends the prompt.\n"
+                    },
+                    false => "\nMulti-line mode is off: Enter sends the prompt.\n",
+                };
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(message),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!(
+                        "To keep it for new sessions, run: q settings chat.multiline {enabled}\n\n"
+                    )),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::DryRun { enabled } => {
+                self.dry_run = enabled.unwrap_or(!self.dry_run);
+                let message = match self.dry_run {
+                    true => {
+                        "\nDry-run mode is on: the commands and file changes the model asks for are shown but not run.\n\n"
+                    },
+                    false => "\nDry-run mode is off: tools run again once you accept them.\n\n",
+                };
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(message),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::ReadOnly { enabled } => {
+                self.read_only = enabled.unwrap_or(!self.read_only);
+                let message = match self.read_only {
+                    true => "\nRead-only mode is on: the tools that can change anything are refused.\n\n",
+                    false => "\nRead-only mode is off: all tools can run again once you accept them.\n\n",
+                };
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(message),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::SetMode { mode } => {
+                self.input_source.set_edit_mode(mode);
+                let (name, setting) = match mode {
+                    EditMode::Vi => ("vi", "true"),
+                    _ => ("emacs", "false"),
+                };
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(format!("\nSwitched to {name} key bindings for this session.\n")),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!(
+                        "To keep them for new sessions, run: q settings chat.editMode.vi {setting}\n\n"
+                    )),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Quote => {
+                match self.editor.quote_last_response().await {
+                    Ok(EditorOutput::Edited(quote)) if !quote.is_empty() => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print("\nQuote added to your next prompt.\n\n"),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                        self.pending_input = Some(quote);
+                    },
+                    Ok(_) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Yellow),
+                            style::Print("\nNothing quoted.\n\n"),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    },
+                    Err(e) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("\nError: {}\n\n", e)),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    },
+                }
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::EditMessage { index } => {
+                let prompts = self
+                    .conversation_state
+                    .user_prompts()
+                    .into_iter()
+                    .map(|(i, prompt)| (i, prompt.to_string()))
+                    .collect::<Vec<_>>();
+
+                let selected = index.and_then(|n| prompts.get(n - 1));
+                match (index, selected) {
+                    (None, _) if prompts.is_empty() => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Yellow),
+                            style::Print("\nNo messages to edit yet.\n\n"),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    },
+                    (None, _) => {
+                        execute!(self.output, style::Print("\n"))?;
+                        for (n, (_, prompt)) in prompts.iter().enumerate() {
+                            let first_line = prompt.lines().next().unwrap_or_default();
+                            execute!(
+                                self.output,
+                                style::SetForegroundColor(Color::Green),
+                                style::Print(format!("{:>3}. ", n + 1)),
+                                style::SetForegroundColor(Color::Reset),
+                                style::Print(truncate_safe(first_line, 100)),
+                                style::Print("\n")
+                            )?;
+                        }
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print("\nUse /edit <n> to edit a message and continue from it.\n\n"),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    },
+                    (Some(n), None) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!(
+                                "\nThere is no message {}, the conversation has {} message(s). Use /edit to list them.\n\n",
+                                n,
+                                prompts.len()
+                            )),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    },
+                    (Some(n), Some((history_index, prompt))) => {
+                        match self.editor.launch_system_editor(Some(prompt.clone())).await {
+                            Ok(EditorOutput::Edited(content)) if !content.trim().is_empty() => {
+                                // Fork the conversation: everything from the edited message onwards is dropped
+                                self.conversation_state.truncate_history(*history_index);
+                                self.editor.discard_draft();
                                 execute!(
                                     self.output,
                                     style::SetForegroundColor(Color::Green),
-                                    style::SetAttribute(Attribute::Bold),
                                     style::Print(format!(
-                                        "{} matched file{} in use:\n",
-                                        total,
-                                        if total == 1 { "" } else { "s" }
+                                        "\nConversation rewound to message {}. Submitting edited prompt...\n\n",
+                                        n
                                     )),
                                     style::SetForegroundColor(Color::Reset),
-                                    style::SetAttribute(Attribute::Reset)
+                                    style::SetForegroundColor(Color::Magenta),
+                                    style::Print("> "),
+                                    style::SetAttribute(Attribute::Reset),
+                                    style::Print(&content),
+                                    style::Print("\n")
                                 )?;
 
-                                for (filename, content) in &global_context_files {
-                                    let est_tokens = TokenCounter::count_tokens(content);
-                                    execute!(
-                                        self.output,
-                                        style::Print(format!("🌍 {} ", filename)),
-                                        style::SetForegroundColor(Color::DarkGrey),
-                                        style::Print(format!("(~{} tkns)\n", est_tokens)),
-                                        style::SetForegroundColor(Color::Reset),
-                                    )?;
-                                    if expand {
-                                        execute!(
-                                            self.output,
-                                            style::SetForegroundColor(Color::DarkGrey),
-                                            style::Print(format!("{}\n\n", content)),
-                                            style::SetForegroundColor(Color::Reset)
-                                        )?;
-                                    }
-                                }
-
-                                for (filename, content) in &profile_context_files {
-                                    let est_tokens = TokenCounter::count_tokens(content);
-                                    execute!(
-                                        self.output,
-                                        style::Print(format!("👤 {} ", filename)),
-                                        style::SetForegroundColor(Color::DarkGrey),
-                                        style::Print(format!("(~{} tkns)\n", est_tokens)),
-                                        style::SetForegroundColor(Color::Reset),
-                                    )?;
-                                    if expand {
-                                        execute!(
-                                            self.output,
-                                            style::SetForegroundColor(Color::DarkGrey),
-                                            style::Print(format!("{}\n\n", content)),
-                                            style::SetForegroundColor(Color::Reset)
-                                        )?;
-                                    }
-                                }
-
-                                if expand {
-                                    execute!(self.output, style::Print(format!("{}\n\n", "▔".repeat(3))),)?;
-                                }
-
-                                let mut combined_files: Vec<(String, String)> = global_context_files
-                                    .iter()
-                                    .chain(profile_context_files.iter())
-                                    .cloned()
-                                    .collect();
-
-                                let dropped_files =
-                                    drop_matched_context_files(&mut combined_files, CONTEXT_FILES_MAX_SIZE).ok();
-
-                                execute!(
-                                    self.output,
-                                    style::Print(format!("\nTotal: ~{} tokens\n\n", total_tokens))
-                                )?;
-
-                                if let Some(dropped_files) = dropped_files {
-                                    if !dropped_files.is_empty() {
-                                        execute!(
-                                            self.output,
-                                            style::SetForegroundColor(Color::DarkYellow),
-                                            style::Print(format!(
-                                                "Total token count exceeds limit: {}. The following files will be automatically dropped when interacting with Q. Consider removing them. \n\n",
-                                                CONTEXT_FILES_MAX_SIZE
-                                            )),
-                                            style::SetForegroundColor(Color::Reset)
-                                        )?;
-                                        let total_files = dropped_files.len();
-
-                                        let truncated_dropped_files = &dropped_files[..10];
-
-                                        for (filename, content) in truncated_dropped_files {
-                                            let est_tokens = TokenCounter::count_tokens(content);
-                                            execute!(
-                                                self.output,
-                                                style::Print(format!("{} ", filename)),
-                                                style::SetForegroundColor(Color::DarkGrey),
-                                                style::Print(format!("(~{} tkns)\n", est_tokens)),
-                                                style::SetForegroundColor(Color::Reset),
-                                            )?;
-                                        }
-
-                                        if total_files > 10 {
-                                            execute!(
-                                                self.output,
-                                                style::Print(format!("({} more files)\n", total_files - 10))
-                                            )?;
-                                        }
-                                    }
-                                }
-
-                                execute!(self.output, style::Print("\n"))?;
-                            }
-
-                            // Show last cached conversation summary if available, otherwise regenerate it
-                            if expand {
-                                if let Some(summary) = self.conversation_state.latest_summary() {
-                                    let border = "═".repeat(self.terminal_width().min(80));
-                                    execute!(
-                                        self.output,
-                                        style::Print("\n"),
-                                        style::SetForegroundColor(Color::Cyan),
-                                        style::Print(&border),
-                                        style::Print("\n"),
-                                        style::SetAttribute(Attribute::Bold),
-                                        style::Print("                       CONVERSATION SUMMARY"),
-                                        style::Print("\n"),
-                                        style::Print(&border),
-                                        style::SetAttribute(Attribute::Reset),
-                                        style::Print("\n\n"),
-                                        style::Print(&summary),
-                                        style::Print("\n\n\n")
-                                    )?;
-                                }
-                            }
-                        },
-                        command::ContextSubcommand::Add { global, force, paths } => {
-                            match context_manager.add_paths(paths.clone(), global, force).await {
-                                Ok(_) => {
-                                    let target = if global { "global" } else { "profile" };
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Green),
-                                        style::Print(format!(
-                                            "\nAdded {} path(s) to {} context.\n\n",
-                                            paths.len(),
-                                            target
-                                        )),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                },
-                                Err(e) => {
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Red),
-                                        style::Print(format!("\nError: {}\n\n", e)),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                },
-                            }
-                        },
-                        command::ContextSubcommand::Remove { global, paths } => {
-                            match context_manager.remove_paths(paths.clone(), global).await {
-                                Ok(_) => {
-                                    let target = if global { "global" } else { "profile" };
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Green),
-                                        style::Print(format!(
-                                            "\nRemoved {} path(s) from {} context.\n\n",
-                                            paths.len(),
-                                            target
-                                        )),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                },
-                                Err(e) => {
-                                    execute!(
-                                        self.output,
-                                        style::SetForegroundColor(Color::Red),
-                                        style::Print(format!("\nError: {}\n\n", e)),
-                                        style::SetForegroundColor(Color::Reset)
-                                    )?;
-                                },
-                            }
-                        },
-                        command::ContextSubcommand::Clear { global } => match context_manager.clear(global).await {
+                                // Any pending tool uses belonged to the discarded part of the conversation
+                                return Ok(ChatState::HandleInput {
+                                    input: content,
+                                    tool_uses: None,
+                                    pending_tool_index: None,
+                                });
+                            },
                             Ok(_) => {
-                                let target = if global {
-                                    "global".to_string()
-                                } else {
-                                    format!("profile '{}'", context_manager.current_profile)
-                                };
                                 execute!(
                                     self.output,
-                                    style::SetForegroundColor(Color::Green),
-                                    style::Print(format!("\nCleared context for {}\n\n", target)),
+                                    style::SetForegroundColor(Color::Yellow),
+                                    style::Print("\nMessage left unchanged, the conversation was not modified.\n\n"),
                                     style::SetForegroundColor(Color::Reset)
                                 )?;
                             },
                             Err(e) => {
                                 execute!(
                                     self.output,
-                                    style::SetForegroundColor(Color::Red),
-                                    style::Print(format!("\nError: {}\n\n", e)),
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!("\nError opening editor: {}\n\n", e)),
                                     style::SetForegroundColor(Color::Reset)
                                 )?;
                             },
-                        },
-                        command::ContextSubcommand::Help => {
-                            execute!(
-                                self.output,
-                                style::Print("\n"),
-                                style::Print(command::ContextSubcommand::help_text()),
-                                style::Print("\n")
-                            )?;
-                        },
-                        command::ContextSubcommand::Hooks { subcommand } => {
-                            fn map_chat_error(e: ErrReport) -> ChatError {
-                                ChatError::Custom(e.to_string().into())
-                            }
+                        }
+                    },
+                }
 
-                            let scope = |g: bool| if g { "global" } else { "profile" };
-                            if let Some(subcommand) = subcommand {
-                                match subcommand {
-                                    command::HooksSubcommand::Add {
-                                        name,
-                                        trigger,
-                                        command,
-                                        global,
-                                    } => {
-                                        let trigger = if trigger == "conversation_start" {
-                                            HookTrigger::ConversationStart
-                                        } else {
-                                            HookTrigger::PerPrompt
-                                        };
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Copy { index } => {
+                let response = self.last_response();
+                let copied = match (response, index) {
+                    (None, _) => Err("There is no response to copy yet.".to_string()),
+                    (Some(response), None) => Ok(("the last response".to_string(), response)),
+                    (Some(response), Some(n)) => {
+                        let blocks = fenced_code_blocks(&response);
+                        match blocks.get(n - 1) {
+                            Some(block) => Ok((format!("code block {n}"), block.clone())),
+                            None => Err(format!(
+                                "There is no code block {}, the last response has {} code block(s).",
+                                n,
+                                blocks.len()
+                            )),
+                        }
+                    },
+                };
 
-                                        let result = context_manager
-                                            .add_hook(name.clone(), Hook::new_inline_hook(trigger, command), global)
-                                            .await;
-                                        match result {
-                                            Ok(_) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Green),
-                                                    style::Print(format!(
-                                                        "\nAdded {} hook '{name}'.\n\n",
-                                                        scope(global)
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                            Err(e) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Red),
-                                                    style::Print(format!(
-                                                        "\nCannot add {} hook '{name}': {}\n\n",
-                                                        scope(global),
-                                                        e
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                        }
-                                    },
-                                    command::HooksSubcommand::Remove { name, global } => {
-                                        let result = context_manager.remove_hook(&name, global).await;
-                                        match result {
-                                            Ok(_) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Green),
-                                                    style::Print(format!(
-                                                        "\nRemoved {} hook '{name}'.\n\n",
-                                                        scope(global)
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                            Err(e) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Red),
-                                                    style::Print(format!(
-                                                        "\nCannot remove {} hook '{name}': {}\n\n",
-                                                        scope(global),
-                                                        e
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                        }
-                                    },
-                                    command::HooksSubcommand::Enable { name, global } => {
-                                        let result = context_manager.set_hook_disabled(&name, global, false).await;
-                                        match result {
-                                            Ok(_) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Green),
-                                                    style::Print(format!(
-                                                        "\nEnabled {} hook '{name}'.\n\n",
-                                                        scope(global)
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                            Err(e) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Red),
-                                                    style::Print(format!(
-                                                        "\nCannot enable {} hook '{name}': {}\n\n",
-                                                        scope(global),
-                                                        e
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                        }
-                                    },
-                                    command::HooksSubcommand::Disable { name, global } => {
-                                        let result = context_manager.set_hook_disabled(&name, global, true).await;
-                                        match result {
-                                            Ok(_) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Green),
-                                                    style::Print(format!(
-                                                        "\nDisabled {} hook '{name}'.\n\n",
-                                                        scope(global)
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                            Err(e) => {
-                                                execute!(
-                                                    self.output,
-                                                    style::SetForegroundColor(Color::Red),
-                                                    style::Print(format!(
-                                                        "\nCannot disable {} hook '{name}': {}\n\n",
-                                                        scope(global),
-                                                        e
-                                                    )),
-                                                    style::SetForegroundColor(Color::Reset)
-                                                )?;
-                                            },
-                                        }
-                                    },
-                                    command::HooksSubcommand::EnableAll { global } => {
-                                        context_manager
-                                            .set_all_hooks_disabled(global, false)
-                                            .await
-                                            .map_err(map_chat_error)?;
-                                        execute!(
-                                            self.output,
-                                            style::SetForegroundColor(Color::Green),
-                                            style::Print(format!("\nEnabled all {} hooks.\n\n", scope(global))),
-                                            style::SetForegroundColor(Color::Reset)
-                                        )?;
-                                    },
-                                    command::HooksSubcommand::DisableAll { global } => {
-                                        context_manager
-                                            .set_all_hooks_disabled(global, true)
-                                            .await
-                                            .map_err(map_chat_error)?;
-                                        execute!(
-                                            self.output,
-                                            style::SetForegroundColor(Color::Green),
-                                            style::Print(format!("\nDisabled all {} hooks.\n\n", scope(global))),
-                                            style::SetForegroundColor(Color::Reset)
-                                        )?;
-                                    },
-                                    command::HooksSubcommand::Help => {
-                                        execute!(
-                                            self.output,
-                                            style::Print("\n"),
-                                            style::Print(command::ContextSubcommand::hooks_help_text()),
-                                            style::Print("\n")
-                                        )?;
-                                    },
-                                }
-                            } else {
-                                queue!(
-                                    self.output,
-                                    style::SetAttribute(Attribute::Bold),
-                                    style::SetForegroundColor(Color::Magenta),
-                                    style::Print("\n🌍 global:\n"),
-                                    style::SetAttribute(Attribute::Reset),
-                                )?;
+                let result = copied.and_then(|(what, text)| {
+                    copy_to_clipboard(&self.ctx, &mut self.output, &text)
+                        .map(|clipboard| format!("Copied {what} to {clipboard}."))
+                        .map_err(|err| format!("Failed to copy {what}: {err}"))
+                });
+                match result {
+                    Ok(message) => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!("\n{message}\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                    Err(message) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\n{message}\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
 
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Paste { as_context, name } => {
+                let result = paste_from_clipboard(&self.ctx, &mut self.output)
+                    .map_err(|err| format!("Failed to read the clipboard: {err}"))
+                    .and_then(|(text, clipboard)| match text.trim().is_empty() {
+                        true => Err(format!("Nothing to paste, {clipboard} is empty.")),
+                        false => Ok((text, clipboard)),
+                    });
+                let result = result.and_then(|(text, clipboard)| {
+                    let lines = text.lines().count();
+                    if !as_context {
+                        self.pending_input = Some(text);
+                        return Ok(format!("Pasted {lines} line(s) from {clipboard} into your next prompt."));
+                    }
+                    let context_manager = self
+                        .conversation_state
+                        .context_manager
+                        .as_mut()
+                        .ok_or_else(|| "Context isn't available in this session.".to_string())?;
+                    let name = name.unwrap_or_else(|| {
+                        let count = context_manager
+                            .attachments
+                            .iter()
+                            .filter(|(name, _)| name.starts_with("clipboard-"))
+                            .count();
+                        format!("clipboard-{}", count + 1)
+                    });
+                    let tokens = TokenCounter::count_tokens(&text);
+                    context_manager.attach(name.clone(), text);
+                    Ok(format!(
+                        "Attached {lines} line(s) (~{tokens} tkns) from {clipboard} to the context as {name}. Remove it with /context rm {name}."
+                    ))
+                });
+                match result {
+                    Ok(message) => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!("\n{message}\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                    Err(message) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\n{message}\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
 
-                                queue!(
-                                    self.output,
-                                    style::SetAttribute(Attribute::Bold),
-                                    style::SetForegroundColor(Color::Magenta),
-                                    style::Print(format!("\n👤 profile ({}):\n", &context_manager.current_profile)),
-                                    style::SetAttribute(Attribute::Reset),
-                                )?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Find { pattern } => {
+                match Regex::new(&pattern) {
+                    Ok(regex) => self.print_find_matches(&regex)?,
+                    Err(err) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nInvalid pattern: {}\n\n", err)),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
 
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Page => {
+                match self.last_response() {
+                    Some(response) => self.page_response(&response)?,
+                    None => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("\nThere is no response to show yet.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
 
-                                execute!(
-                                    self.output,
-                                    style::Print(format!(
-                                        "\nUse {} to manage hooks.\n\n",
-                                        "/context hooks help".to_string().dark_green()
-                                    )),
-                                )?;
-                            }
-                        },
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Expand { tool_use_id, attach } => {
+                let result = match tool_use_id.or_else(|| self.last_shortened_tool_use.clone()) {
+                    Some(id) => tool_output::load(&self.ctx, &id)
+                        .await
+                        .map(|output| (id.clone(), output))
+                        .map_err(|err| format!("There is no saved output for {id}: {err}")),
+                    None => Err("No tool output was shortened in this session yet.".to_string()),
+                };
+                let result = result.and_then(|(id, output)| {
+                    if !attach {
+                        return pager::page(output.as_bytes()).map(|()| None).map_err(|err| err.to_string());
                     }
-                    // crate::telemetry::send_context_command_executed
-                } else {
-                    execute!(
+                    let context_manager = self
+                        .conversation_state
+                        .context_manager
+                        .as_mut()
+                        .ok_or_else(|| "Context isn't available in this session.".to_string())?;
+                    let name = format!("tool-output-{id}");
+                    let tokens = TokenCounter::count_tokens(&output);
+                    context_manager.attach(name.clone(), output);
+                    Ok(Some(format!(
+                        "Attached the full output (~{tokens} tkns) to the context as {name}. Remove it with /context rm {name}."
+                    )))
+                });
+                match result {
+                    Ok(Some(message)) => execute!(
                         self.output,
-                        style::SetForegroundColor(Color::Red),
-                        style::Print("\nContext management is not available.\n\n"),
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!("\n{message}\n\n")),
                         style::SetForegroundColor(Color::Reset)
-                    )?;
+                    )?,
+                    Ok(None) => (),
+                    Err(message) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\n{message}\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
                 }
 
                 ChatState::PromptUser {
@@ -2225,254 +3051,253 @@ impl ChatContext {
                     skip_printing_tools: true,
                 }
             },
-            Command::Tools { subcommand } => {
-                let existing_tools: HashSet<&String> = self
-                    .conversation_state
-                    .tools
-                    .values()
-                    .flatten()
-                    .map(|FigTool::ToolSpecification(spec)| &spec.name)
-                    .collect();
-
-                match subcommand {
-                    Some(ToolsSubcommand::Schema) => {
-                        let schema_json = serde_json::to_string_pretty(&self.conversation_state.tool_manager.schema)
-                            .map_err(|e| {
-                                ChatError::Custom(format!("Error converting tool schema to string: {e}").into())
-                            })?;
-                        queue!(self.output, style::Print(schema_json), style::Print("\n"))?;
+            Command::Cache { clear } => {
+                let message = match clear {
+                    true => {
+                        let count = self.tool_cache.count();
+                        self.tool_cache.clear();
+                        format!("Cleared {count} cached tool result(s), the next reads will run again.")
                     },
-                    Some(ToolsSubcommand::Trust { tool_names }) => {
-                        let (valid_tools, invalid_tools): (Vec<String>, Vec<String>) = tool_names
-                            .into_iter()
-                            .partition(|tool_name| existing_tools.contains(tool_name));
+                    false => format!(
+                        "{} tool result(s) cached, reused {} time(s) in this session.",
+                        self.tool_cache.count(),
+                        self.tool_cache.hits()
+                    ),
+                };
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(format!("\n{message}\n\n")),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
 
-                        if !invalid_tools.is_empty() {
-                            queue!(
-                                self.output,
-                                style::SetForegroundColor(Color::Red),
-                                style::Print(format!("\nCannot trust '{}', ", invalid_tools.join("', '"))),
-                                if invalid_tools.len() > 1 {
-                                    style::Print("they do not exist.")
-                                } else {
-                                    style::Print("it does not exist.")
-                                },
-                                style::SetForegroundColor(Color::Reset),
-                            )?;
-                        }
-                        if !valid_tools.is_empty() {
-                            valid_tools.iter().for_each(|t| self.tool_permissions.trust_tool(t));
-                            queue!(
-                                self.output,
-                                style::SetForegroundColor(Color::Green),
-                                if valid_tools.len() > 1 {
-                                    style::Print(format!("\nTools '{}' are ", valid_tools.join("', '")))
-                                } else {
-                                    style::Print(format!("\nTool '{}' is ", valid_tools[0]))
-                                },
-                                style::Print("now trusted. I will "),
-                                style::SetAttribute(Attribute::Bold),
-                                style::Print("not"),
-                                style::SetAttribute(Attribute::Reset),
-                                style::SetForegroundColor(Color::Green),
-                                style::Print(format!(
-                                    " ask for confirmation before running {}.",
-                                    if valid_tools.len() > 1 {
-                                        "these tools"
-                                    } else {
-                                        "this tool"
-                                    }
-                                )),
-                                style::SetForegroundColor(Color::Reset),
-                            )?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Ps => {
+                let processes = self.background_processes.list();
+                if processes.is_empty() {
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nNo command was started in the background.\n"),
+                    )?;
+                }
+                queue!(self.output, style::Print("\n"))?;
+                for process in processes {
+                    let color = match process.status {
+                        ProcessStatus::Running => Color::Green,
+                        _ => Color::DarkGrey,
+                    };
+                    queue!(
+                        self.output,
+                        style::Print(format!("{:>4}  ", process.id)),
+                        style::SetForegroundColor(color),
+                        style::Print(format!("{:<24}", process.status.to_string())),
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!("{:>8}  ", format_elapsed(process.started.elapsed()))),
+                        style::SetForegroundColor(Color::Reset),
+                        style::Print(format!("{}\n", process.command)),
+                    )?;
+                }
+                execute!(self.output, style::SetForegroundColor(Color::Reset), style::Print("\n"))?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Logs { id, lines } => {
+                let lines = lines.unwrap_or(DEFAULT_LOG_LINES);
+                match (
+                    self.background_processes.get(id),
+                    self.background_processes.output(id, lines),
+                ) {
+                    (Some(process), Some(output)) => {
+                        queue!(
+                            self.output,
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("\n{} ({}):\n", process.command, process.status)),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                        for line in output {
+                            queue!(self.output, style::Print(format!("{line}\n")))?;
                         }
+                        execute!(self.output, style::Print("\n"))?;
                     },
-                    Some(ToolsSubcommand::Untrust { tool_names }) => {
-                        let (valid_tools, invalid_tools): (Vec<String>, Vec<String>) = tool_names
-                            .into_iter()
-                            .partition(|tool_name| existing_tools.contains(tool_name));
+                    _ => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nThere's no background process {id}, see /ps.\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
 
-                        if !invalid_tools.is_empty() {
-                            queue!(
-                                self.output,
-                                style::SetForegroundColor(Color::Red),
-                                style::Print(format!("\nCannot untrust '{}', ", invalid_tools.join("', '"))),
-                                if invalid_tools.len() > 1 {
-                                    style::Print("they do not exist.")
-                                } else {
-                                    style::Print("it does not exist.")
-                                },
-                                style::SetForegroundColor(Color::Reset),
-                            )?;
-                        }
-                        if !valid_tools.is_empty() {
-                            valid_tools.iter().for_each(|t| self.tool_permissions.untrust_tool(t));
-                            queue!(
-                                self.output,
-                                style::SetForegroundColor(Color::Green),
-                                if valid_tools.len() > 1 {
-                                    style::Print(format!("\nTools '{}' are ", valid_tools.join("', '")))
-                                } else {
-                                    style::Print(format!("\nTool '{}' is ", valid_tools[0]))
-                                },
-                                style::Print("set to per-request confirmation."),
-                                style::SetForegroundColor(Color::Reset),
-                            )?;
-                        }
-                    },
-                    Some(ToolsSubcommand::TrustAll) => {
-                        self.conversation_state.tools.values().flatten().for_each(
-                            |FigTool::ToolSpecification(spec)| {
-                                self.tool_permissions.trust_tool(spec.name.as_str());
-                            },
-                        );
-                        queue!(self.output, style::Print(TRUST_ALL_TEXT),)?;
-                    },
-                    Some(ToolsSubcommand::Reset) => {
-                        self.tool_permissions.reset();
-                        queue!(
-                            self.output,
-                            style::SetForegroundColor(Color::Green),
-                            style::Print("\nReset all tools to the default permission levels."),
-                            style::SetForegroundColor(Color::Reset),
-                        )?;
-                    },
-                    Some(ToolsSubcommand::ResetSingle { tool_name }) => {
-                        if self.tool_permissions.has(&tool_name) || self.tool_permissions.trust_all {
-                            self.tool_permissions.reset_tool(&tool_name);
-                            queue!(
-                                self.output,
-                                style::SetForegroundColor(Color::Green),
-                                style::Print(format!("\nReset tool '{}' to the default permission level.", tool_name)),
-                                style::SetForegroundColor(Color::Reset),
-                            )?;
-                        } else {
-                            queue!(
-                                self.output,
-                                style::SetForegroundColor(Color::Red),
-                                style::Print(format!(
-                                    "\nTool '{}' does not exist or is already in default settings.",
-                                    tool_name
-                                )),
-                                style::SetForegroundColor(Color::Reset),
-                            )?;
-                        }
-                    },
-                    Some(ToolsSubcommand::Help) => {
-                        queue!(
-                            self.output,
-                            style::Print("\n"),
-                            style::Print(command::ToolsSubcommand::help_text()),
-                        )?;
-                    },
-                    None => {
-                        // No subcommand - print the current tools and their permissions.
-                        // Determine how to format the output nicely.
-                        let terminal_width = self.terminal_width();
-                        let longest = self
-                            .conversation_state
-                            .tools
-                            .values()
-                            .flatten()
-                            .map(|FigTool::ToolSpecification(spec)| spec.name.len())
-                            .max()
-                            .unwrap_or(0);
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Kill { id } => {
+                match (self.background_processes.get(id), self.background_processes.kill(id)) {
+                    (Some(process), Some(ProcessStatus::Running)) => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!("\nKilled background process {id} ({}).\n\n", process.command)),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                    (_, Some(status)) => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!("\nBackground process {id} already {status}.\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                    (_, None) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nThere's no background process {id}, see /ps.\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
 
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Checkpoints => {
+                let cwd = self.ctx.env().current_dir()?;
+                if self.checkpoints.list().is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nNo files were changed with fs_write in this session yet.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                }
+                for checkpoint in self.checkpoints.list() {
+                    let prompt = checkpoint.prompt.lines().next().unwrap_or_default();
+                    queue!(
+                        self.output,
+                        style::SetAttribute(Attribute::Bold),
+                        style::Print(format!("\nTurn {}", checkpoint.turn)),
+                        style::SetAttribute(Attribute::Reset),
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!(" > {}\n", truncate_safe(prompt, 80))),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    for (path, contents) in &checkpoint.files {
+                        let change = if contents.is_some() { "modified" } else { "created" };
                         queue!(
                             self.output,
-                            style::Print("\n"),
-                            style::SetAttribute(Attribute::Bold),
-                            style::Print({
-                                // Adding 2 because of "- " preceding every tool name
-                                let width = longest + 2 - "Tool".len() + 4;
-                                format!("Tool{:>width$}Permission", "", width = width)
-                            }),
-                            style::SetAttribute(Attribute::Reset),
-                            style::Print("\n"),
-                            style::Print("▔".repeat(terminal_width)),
+                            style::Print(format!("  {} ", tools::format_path(&cwd, path))),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("({change})\n")),
+                            style::SetForegroundColor(Color::Reset),
                         )?;
+                    }
+                }
+                if !self.checkpoints.list().is_empty() {
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(
+                            "\nUndo a turn and the ones after it with /revert <turn>. Only the changes made with fs_write are tracked.\n\n"
+                        ),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                }
+                self.output.flush()?;
 
-                        let mut origin_tools: Vec<_> = self.conversation_state.tools.iter().collect();
-
-                        // Built in tools always appear first.
-                        origin_tools.sort_by(|(origin_a, _), (origin_b, _)| match (origin_a, origin_b) {
-                            (ToolOrigin::Native, _) => std::cmp::Ordering::Less,
-                            (_, ToolOrigin::Native) => std::cmp::Ordering::Greater,
-                            (ToolOrigin::McpServer(name_a), ToolOrigin::McpServer(name_b)) => name_a.cmp(name_b),
-                        });
-
-                        for (origin, tools) in origin_tools.iter() {
-                            let mut sorted_tools: Vec<_> = tools
-                                .iter()
-                                .filter(|FigTool::ToolSpecification(spec)| spec.name != DUMMY_TOOL_NAME)
-                                .collect();
-
-                            sorted_tools.sort_by_key(|t| match t {
-                                FigTool::ToolSpecification(spec) => &spec.name,
-                            });
-
-                            let to_display =
-                                sorted_tools
-                                    .iter()
-                                    .fold(String::new(), |mut acc, FigTool::ToolSpecification(spec)| {
-                                        let width = longest - spec.name.len() + 4;
-                                        acc.push_str(
-                                            format!(
-                                                "- {}{:>width$}{}\n",
-                                                spec.name,
-                                                "",
-                                                self.tool_permissions.display_label(&spec.name),
-                                                width = width
-                                            )
-                                            .as_str(),
-                                        );
-                                        acc
-                                    });
-
-                            let _ = queue!(
-                                self.output,
-                                style::SetAttribute(Attribute::Bold),
-                                style::Print(format!("{}:\n", origin)),
-                                style::SetAttribute(Attribute::Reset),
-                                style::Print(to_display),
-                                style::Print("\n")
-                            );
-                        }
-
-                        let loading = self.conversation_state.tool_manager.pending_clients().await;
-                        if !loading.is_empty() {
-                            queue!(
-                                self.output,
-                                style::SetAttribute(Attribute::Bold),
-                                style::Print("Servers still loading"),
-                                style::SetAttribute(Attribute::Reset),
-                                style::Print("\n"),
-                                style::Print("▔".repeat(terminal_width)),
-                            )?;
-                            for client in loading {
-                                queue!(self.output, style::Print(format!(" - {client}")), style::Print("\n"))?;
-                            }
-                        }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Revert { turn } => {
+                let turn = turn.unwrap_or(0);
+                let cwd = self.ctx.env().current_dir()?;
+                let mut files = self
+                    .checkpoints
+                    .since(turn)
+                    .flat_map(|checkpoint| checkpoint.files.keys())
+                    .map(|path| tools::format_path(&cwd, path))
+                    .collect::<Vec<_>>();
+                files.sort();
+                files.dedup();
+                if files.is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("\nNo file changes to revert, see /checkpoint list.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                }
 
-                        queue!(
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("\nThese files will be restored to how they were before "),
+                    style::Print(match turn {
+                        0 => "the session".to_string(),
+                        turn => format!("turn {turn}"),
+                    }),
+                    style::Print(", losing any change made to them since:\n"),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+                for file in &files {
+                    queue!(self.output, style::Print(format!("  {file}\n")))?;
+                }
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("Are you sure? ["),
+                    style::SetForegroundColor(Color::Green),
+                    style::Print("y"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("/"),
+                    style::SetForegroundColor(Color::Green),
+                    style::Print("n"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("]:\n\n"),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+                let user_input = self
+                    .read_user_input("> ".yellow().to_string().as_str(), true)
+                    .await
+                    .unwrap_or_default();
+                if ["y", "Y"].contains(&user_input.as_str()) {
+                    match self.checkpoints.revert(&self.ctx, turn).await {
+                        Ok(restored) => execute!(
                             self.output,
-                            style::Print("\nTrusted tools will run without confirmation."),
-                            style::SetForegroundColor(Color::DarkGrey),
-                            style::Print(format!("\n{}\n", "* Default settings")),
-                            style::Print("\n💡 Use "),
                             style::SetForegroundColor(Color::Green),
-                            style::Print("/tools help"),
-                            style::SetForegroundColor(Color::Reset),
+                            style::Print(format!("\nRestored {} file(s).", restored.len())),
                             style::SetForegroundColor(Color::DarkGrey),
-                            style::Print(" to edit permissions.\n\n"),
-                            style::SetForegroundColor(Color::Reset),
-                        )?;
-                    },
-                };
-
-                // Put spacing between previous output as to not be overwritten by
-                // during PromptUser.
-                self.output.flush()?;
+                            style::Print(
+                                " The conversation still includes the changes, /undo removes the exchanges too.\n\n"
+                            ),
+                            style::SetForegroundColor(Color::Reset)
+                        )?,
+                        Err(err) => execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("\nFailed to restore the files: {err}\n\n")),
+                            style::SetForegroundColor(Color::Reset)
+                        )?,
+                    }
+                }
 
                 ChatState::PromptUser {
                     tool_uses: Some(tool_uses),
@@ -2480,1156 +3305,4415 @@ impl ChatContext {
                     skip_printing_tools: true,
                 }
             },
-            Command::Prompts { subcommand } => {
-                match subcommand {
-                    Some(PromptsSubcommand::Help) => {
-                        queue!(self.output, style::Print(command::PromptsSubcommand::help_text()))?;
-                    },
-                    Some(PromptsSubcommand::Get { mut get_command }) => {
-                        let orig_input = get_command.orig_input.take();
-                        let prompts = match self.conversation_state.tool_manager.get_prompt(get_command).await {
-                            Ok(resp) => resp,
-                            Err(e) => {
-                                match e {
-                                    GetPromptError::AmbiguousPrompt(prompt_name, alt_msg) => {
-                                        queue!(
-                                            self.output,
-                                            style::Print("\n"),
-                                            style::SetForegroundColor(Color::Yellow),
-                                            style::Print("Prompt "),
-                                            style::SetForegroundColor(Color::Cyan),
-                                            style::Print(prompt_name),
-                                            style::SetForegroundColor(Color::Yellow),
-                                            style::Print(" is ambiguous. Use one of the following "),
-                                            style::SetForegroundColor(Color::Cyan),
-                                            style::Print(alt_msg),
-                                            style::SetForegroundColor(Color::Reset),
-                                        )?;
-                                    },
-                                    GetPromptError::PromptNotFound(prompt_name) => {
-                                        queue!(
-                                            self.output,
-                                            style::Print("\n"),
-                                            style::SetForegroundColor(Color::Yellow),
-                                            style::Print("Prompt "),
-                                            style::SetForegroundColor(Color::Cyan),
-                                            style::Print(prompt_name),
-                                            style::SetForegroundColor(Color::Yellow),
-                                            style::Print(" not found. Use "),
-                                            style::SetForegroundColor(Color::Cyan),
-                                            style::Print("/prompts list"),
-                                            style::SetForegroundColor(Color::Yellow),
-                                            style::Print(" to see available prompts.\n"),
-                                            style::SetForegroundColor(Color::Reset),
-                                        )?;
-                                    },
-                                    _ => return Err(ChatError::Custom(e.to_string().into())),
+            Command::Undo { count } => {
+                let prompts = self.conversation_state.user_prompts();
+                let Some(&(history_index, _)) = prompts
+                    .len()
+                    .checked_sub(count)
+                    .and_then(|i| prompts.get(i))
+                    .or(prompts.first())
+                else {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("\nNothing to undo yet.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                };
+
+                let removed = self
+                    .conversation_state
+                    .history()
+                    .range(history_index..)
+                    .filter_map(|(user, assistant)| {
+                        let prompt = user.prompt()?.lines().next().unwrap_or_default();
+                        let response = assistant.content().lines().find(|line| !line.trim().is_empty());
+                        Some((
+                            truncate_safe(prompt, 80).to_string(),
+                            truncate_safe(response.unwrap_or_default(), 80).to_string(),
+                        ))
+                    })
+                    .collect::<Vec<_>>();
+                self.conversation_state.truncate_history(history_index);
+
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(format!(
+                        "\nRemoved the last {} exchange(s) from the conversation:\n",
+                        removed.len()
+                    )),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+                for (prompt, response) in &removed {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Magenta),
+                        style::Print("  > "),
+                        style::SetForegroundColor(Color::Reset),
+                        style::Print(format!("{prompt}\n")),
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!("    {response}\n")),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                }
+                execute!(self.output, style::Print("\n"))?;
+
+                // Any pending tool uses belonged to the removed exchanges
+                ChatState::PromptUser {
+                    tool_uses: None,
+                    pending_tool_index: None,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Retry { fresh } => {
+                let last_prompt = self
+                    .conversation_state
+                    .user_prompts()
+                    .last()
+                    .map(|(i, prompt)| (*i, (*prompt).to_string()));
+                let Some((history_index, prompt)) = last_prompt else {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("\nNo messages to retry yet.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                };
+
+                // Drop the response to the last prompt, including any tool uses that followed it
+                self.conversation_state.truncate_history(history_index);
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print("\nDiscarded the last response. Retrying...\n\n"),
+                    style::SetForegroundColor(Color::Magenta),
+                    style::Print("> "),
+                    style::SetAttribute(Attribute::Reset),
+                    style::Print(&prompt),
+                    style::Print("\n")
+                )?;
+
+                let input = match fresh {
+                    true => format!("{prompt}\n\n{RETRY_FRESH_NOTE}"),
+                    false => prompt,
+                };
+                ChatState::HandleInput {
+                    input,
+                    tool_uses: None,
+                    pending_tool_index: None,
+                }
+            },
+            Command::Quit => ChatState::Exit,
+            Command::Profile { subcommand } => {
+                if let Some(context_manager) = &mut self.conversation_state.context_manager {
+                    macro_rules! print_err {
+                        ($err:expr) => {
+                            execute!(
+                                self.output,
+                                style::SetForegroundColor(self.theme.error),
+                                style::Print(format!("\nError: {}\n\n", $err)),
+                                style::SetForegroundColor(Color::Reset)
+                            )?
+                        };
+                    }
+
+                    match subcommand {
+                        command::ProfileSubcommand::List => {
+                            let profiles = match context_manager.list_profiles().await {
+                                Ok(profiles) => profiles,
+                                Err(e) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(self.theme.error),
+                                        style::Print(format!("\nError listing profiles: {}\n\n", e)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                    vec![]
+                                },
+                            };
+
+                            execute!(self.output, style::Print("\n"))?;
+                            for profile in profiles {
+                                if profile == context_manager.current_profile {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print("* "),
+                                        style::Print(&profile),
+                                        style::SetForegroundColor(Color::Reset),
+                                        style::Print("\n")
+                                    )?;
+                                } else {
+                                    execute!(
+                                        self.output,
+                                        style::Print("  "),
+                                        style::Print(&profile),
+                                        style::Print("\n")
+                                    )?;
                                 }
-                                execute!(self.output, style::Print("\n"))?;
-                                return Ok(ChatState::PromptUser {
-                                    tool_uses: Some(tool_uses),
-                                    pending_tool_index,
-                                    skip_printing_tools: true,
-                                });
+                            }
+                            execute!(self.output, style::Print("\n"))?;
+                        },
+                        command::ProfileSubcommand::Create { name } => {
+                            match context_manager.create_profile(&name).await {
+                                Ok(_) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!("\nCreated profile: {}\n\n", name)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                    context_manager
+                                        .switch_profile(&name)
+                                        .await
+                                        .map_err(|e| warn!(?e, "failed to switch to newly created profile"))
+                                        .ok();
+                                },
+                                Err(e) => print_err!(e),
+                            }
+                        },
+                        command::ProfileSubcommand::Delete { name } => {
+                            match context_manager.delete_profile(&name).await {
+                                Ok(_) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!("\nDeleted profile: {}\n\n", name)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                                Err(e) => print_err!(e),
+                            }
+                        },
+                        command::ProfileSubcommand::Set { name } => match context_manager.switch_profile(&name).await {
+                            Ok(_) => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::Green),
+                                    style::Print(format!("\nSwitched to profile: {}\n\n", name)),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
                             },
-                        };
-                        if let Some(err) = prompts.error {
-                            // If we are running into error we should just display the error
-                            // and abort.
-                            let to_display = serde_json::json!(err);
-                            queue!(
+                            Err(e) => print_err!(e),
+                        },
+                        command::ProfileSubcommand::Rename { old_name, new_name } => {
+                            match context_manager.rename_profile(&old_name, &new_name).await {
+                                Ok(_) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!("\nRenamed profile: {} -> {}\n\n", old_name, new_name)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                                Err(e) => print_err!(e),
+                            }
+                        },
+                        command::ProfileSubcommand::Help => {
+                            execute!(
                                 self.output,
                                 style::Print("\n"),
+                                style::Print(command::ProfileSubcommand::help_text()),
+                                style::Print("\n")
+                            )?;
+                        },
+                    }
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Context { subcommand } => {
+                if let Some(context_manager) = &mut self.conversation_state.context_manager {
+                    match subcommand {
+                        command::ContextSubcommand::Show { expand } => {
+                            fn map_chat_error(e: ErrReport) -> ChatError {
+                                ChatError::Custom(e.to_string().into())
+                            }
+                            // Display global context
+                            execute!(
+                                self.output,
                                 style::SetAttribute(Attribute::Bold),
-                                style::Print("Error encountered while retrieving prompt:"),
+                                style::SetForegroundColor(Color::Magenta),
+                                style::Print("\n🌍 global:\n"),
                                 style::SetAttribute(Attribute::Reset),
-                                style::Print("\n"),
-                                style::SetForegroundColor(Color::Red),
-                                style::Print(
-                                    serde_json::to_string_pretty(&to_display)
-                                        .unwrap_or_else(|_| format!("{:?}", &to_display))
-                                ),
-                                style::SetForegroundColor(Color::Reset),
-                                style::Print("\n"),
                             )?;
-                        } else {
-                            let prompts = prompts
-                                .result
-                                .ok_or(ChatError::Custom("Result field missing from prompt/get request".into()))?;
-                            let prompts = serde_json::from_value::<PromptGetResult>(prompts).map_err(|e| {
-                                ChatError::Custom(format!("Failed to deserialize prompt/get result: {:?}", e).into())
-                            })?;
-                            self.pending_prompts.clear();
-                            self.pending_prompts.append(&mut VecDeque::from(prompts.messages));
-                            return Ok(ChatState::HandleInput {
-                                input: orig_input.unwrap_or_default(),
-                                tool_uses: Some(tool_uses),
-                                pending_tool_index,
-                            });
-                        }
-                    },
-                    subcommand => {
-                        let search_word = match subcommand {
-                            Some(PromptsSubcommand::List { search_word }) => search_word,
-                            _ => None,
-                        };
-                        let terminal_width = self.terminal_width();
-                        let mut prompts_wl = self.conversation_state.tool_manager.prompts.write().map_err(|e| {
-                            ChatError::Custom(
-                                format!("Poison error encountered while retrieving prompts: {}", e).into(),
-                            )
-                        })?;
-                        self.conversation_state.tool_manager.refresh_prompts(&mut prompts_wl)?;
-                        let mut longest_name = "";
-                        let arg_pos = {
-                            let optimal_case = UnicodeWidthStr::width(longest_name) + terminal_width / 4;
-                            if optimal_case > terminal_width {
-                                terminal_width / 3
+                            let mut global_context_files = HashSet::new();
+                            let mut profile_context_files = HashSet::new();
+                            if context_manager.global_config.paths.is_empty() {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print("    <none>\n"),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
                             } else {
-                                optimal_case
-                            }
-                        };
-                        queue!(
-                            self.output,
-                            style::Print("\n"),
-                            style::SetAttribute(Attribute::Bold),
-                            style::Print("Prompt"),
-                            style::SetAttribute(Attribute::Reset),
-                            style::Print({
-                                let name_width = UnicodeWidthStr::width("Prompt");
-                                let padding = arg_pos.saturating_sub(name_width);
-                                " ".repeat(padding)
-                            }),
-                            style::SetAttribute(Attribute::Bold),
-                            style::Print("Arguments (* = required)"),
-                            style::SetAttribute(Attribute::Reset),
-                            style::Print("\n"),
-                            style::Print(format!("{}\n", "▔".repeat(terminal_width))),
-                        )?;
-                        let mut prompts_by_server: Vec<_> = prompts_wl
-                            .iter()
-                            .fold(
-                                HashMap::<&String, Vec<&PromptBundle>>::new(),
-                                |mut acc, (prompt_name, bundles)| {
-                                    if prompt_name.contains(search_word.as_deref().unwrap_or("")) {
-                                        if prompt_name.len() > longest_name.len() {
-                                            longest_name = prompt_name.as_str();
-                                        }
-                                        for bundle in bundles {
-                                            acc.entry(&bundle.server_name)
-                                                .and_modify(|b| b.push(bundle))
-                                                .or_insert(vec![bundle]);
-                                        }
+                                for path in &context_manager.global_config.paths {
+                                    execute!(self.output, style::Print(format!("    {} ", path)))?;
+                                    if let Ok(context_files) = context_manager.get_context_files_by_path(path).await {
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::Green),
+                                            style::Print(format!(
+                                                "({} match{})",
+                                                context_files.len(),
+                                                if context_files.len() == 1 { "" } else { "es" }
+                                            )),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                        global_context_files.extend(context_files);
                                     }
-                                    acc
-                                },
-                            )
-                            .into_iter()
-                            .collect();
-                        prompts_by_server.sort_by_key(|(server_name, _)| server_name.as_str());
-
-                        for (i, (server_name, bundles)) in prompts_by_server.iter_mut().enumerate() {
-                            bundles.sort_by_key(|bundle| &bundle.prompt_get.name);
+                                    execute!(self.output, style::Print("\n"))?;
+                                }
+                            }
 
-                            if i > 0 {
-                                queue!(self.output, style::Print("\n"))?;
+                            if expand {
+                                queue!(
+                                    self.output,
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::DarkYellow),
+                                    style::Print("\n    🔧 Hooks:\n")
+                                )?;
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(&mut self.output, &context_manager.global_config.hooks, trigger)
+                                        .map_err(map_chat_error)?;
+                                }
                             }
-                            queue!(
+
+                            // Display profile context
+                            execute!(
                                 self.output,
                                 style::SetAttribute(Attribute::Bold),
-                                style::Print(server_name),
-                                style::Print(" (MCP):"),
+                                style::SetForegroundColor(Color::Magenta),
+                                style::Print(match &context_manager.workspace_config_path {
+                                    Some(path) => format!(
+                                        "\n👤 profile ({}, saved in {}):\n",
+                                        context_manager.current_profile,
+                                        path.display()
+                                    ),
+                                    None => format!("\n👤 profile ({}):\n", context_manager.current_profile),
+                                }),
                                 style::SetAttribute(Attribute::Reset),
-                                style::Print("\n"),
                             )?;
-                            for bundle in bundles {
+
+                            if context_manager.profile_config.paths.is_empty() {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print("    <none>\n\n"),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            } else {
+                                for path in &context_manager.profile_config.paths {
+                                    execute!(self.output, style::Print(format!("    {} ", path)))?;
+                                    if let Ok(context_files) = context_manager.get_context_files_by_path(path).await {
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::Green),
+                                            style::Print(format!(
+                                                "({} match{})",
+                                                context_files.len(),
+                                                if context_files.len() == 1 { "" } else { "es" }
+                                            )),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                        profile_context_files.extend(context_files);
+                                    }
+                                    execute!(self.output, style::Print("\n"))?;
+                                }
+                                execute!(self.output, style::Print("\n"))?;
+                            }
+
+                            if expand {
                                 queue!(
                                     self.output,
-                                    style::Print("- "),
-                                    style::Print(&bundle.prompt_get.name),
-                                    style::Print({
-                                        if bundle
-                                            .prompt_get
-                                            .arguments
-                                            .as_ref()
-                                            .is_some_and(|args| !args.is_empty())
-                                        {
-                                            let name_width = UnicodeWidthStr::width(bundle.prompt_get.name.as_str());
-                                            let padding =
-                                                arg_pos.saturating_sub(name_width) - UnicodeWidthStr::width("- ");
-                                            " ".repeat(padding)
-                                        } else {
-                                            "\n".to_owned()
-                                        }
-                                    })
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::DarkYellow),
+                                    style::Print("    🔧 Hooks:\n")
                                 )?;
-                                if let Some(args) = bundle.prompt_get.arguments.as_ref() {
-                                    for (i, arg) in args.iter().enumerate() {
-                                        queue!(
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(
+                                        &mut self.output,
+                                        &context_manager.profile_config.hooks,
+                                        trigger,
+                                    )
+                                    .map_err(map_chat_error)?;
+                                }
+                                execute!(self.output, style::Print("\n"))?;
+                            }
+
+                            if !context_manager.attachments.is_empty() {
+                                execute!(
+                                    self.output,
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::Magenta),
+                                    style::Print("📎 attached (this conversation only):\n"),
+                                    style::SetAttribute(Attribute::Reset),
+                                )?;
+                                for (name, text) in &context_manager.attachments {
+                                    execute!(
+                                        self.output,
+                                        style::Print(format!("    {} ", name)),
+                                        style::SetForegroundColor(Color::DarkGrey),
+                                        style::Print(format!("(~{} tkns)\n", TokenCounter::count_tokens(text))),
+                                        style::SetForegroundColor(Color::Reset),
+                                    )?;
+                                    if expand {
+                                        execute!(
                                             self.output,
                                             style::SetForegroundColor(Color::DarkGrey),
-                                            style::Print(match arg.required {
-                                                Some(true) => format!("{}*", arg.name),
-                                                _ => arg.name.clone(),
-                                            }),
-                                            style::SetForegroundColor(Color::Reset),
-                                            style::Print(if i < args.len() - 1 { ", " } else { "\n" }),
+                                            style::Print(format!("{}\n\n", text)),
+                                            style::SetForegroundColor(Color::Reset)
                                         )?;
                                     }
                                 }
+                                execute!(self.output, style::Print("\n"))?;
                             }
-                        }
+
+                            let rules = context_manager.rules().await.unwrap_or_default();
+                            if !rules.is_empty() {
+                                let enabled = rules.iter().filter(|rule| rule.enabled).count();
+                                execute!(
+                                    self.output,
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::Magenta),
+                                    style::Print("📏 project rules:\n"),
+                                    style::SetAttribute(Attribute::Reset),
+                                    style::Print(format!("    {} of {} enabled, see /rules\n\n", enabled, rules.len())),
+                                )?;
+                            }
+
+                            if let Some(retriever) = &self.conversation_state.index_retriever {
+                                let index = retriever.index();
+                                execute!(
+                                    self.output,
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::Magenta),
+                                    style::Print("🔎 workspace index:\n"),
+                                    style::SetAttribute(Attribute::Reset),
+                                    style::Print(format!(
+                                        "    {} chunks of {} files in {}, the most relevant added to each prompt\n\n",
+                                        index.chunks.len(),
+                                        index.files,
+                                        index.root.display()
+                                    )),
+                                )?;
+                            }
+
+                            if global_context_files.is_empty() && profile_context_files.is_empty() {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print("No files in the current directory matched the rules above.\n\n"),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            } else {
+                                let total = global_context_files.len() + profile_context_files.len();
+                                let total_tokens = global_context_files
+                                    .iter()
+                                    .map(|(_, content)| TokenCounter::count_tokens(content))
+                                    .sum::<usize>()
+                                    + profile_context_files
+                                        .iter()
+                                        .map(|(_, content)| TokenCounter::count_tokens(content))
+                                        .sum::<usize>();
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::Green),
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::Print(format!(
+                                        "{} matched file{} in use:\n",
+                                        total,
+                                        if total == 1 { "" } else { "s" }
+                                    )),
+                                    style::SetForegroundColor(Color::Reset),
+                                    style::SetAttribute(Attribute::Reset)
+                                )?;
+
+                                for (filename, content) in &global_context_files {
+                                    let est_tokens = TokenCounter::count_tokens(content);
+                                    execute!(
+                                        self.output,
+                                        style::Print(format!("🌍 {} ", filename)),
+                                        style::SetForegroundColor(Color::DarkGrey),
+                                        style::Print(format!("(~{} tkns)\n", est_tokens)),
+                                        style::SetForegroundColor(Color::Reset),
+                                    )?;
+                                    if expand {
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::DarkGrey),
+                                            style::Print(format!("{}\n\n", content)),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                    }
+                                }
+
+                                for (filename, content) in &profile_context_files {
+                                    let est_tokens = TokenCounter::count_tokens(content);
+                                    execute!(
+                                        self.output,
+                                        style::Print(format!("👤 {} ", filename)),
+                                        style::SetForegroundColor(Color::DarkGrey),
+                                        style::Print(format!("(~{} tkns)\n", est_tokens)),
+                                        style::SetForegroundColor(Color::Reset),
+                                    )?;
+                                    if expand {
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::DarkGrey),
+                                            style::Print(format!("{}\n\n", content)),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                    }
+                                }
+
+                                if expand {
+                                    execute!(self.output, style::Print(format!("{}\n\n", "▔".repeat(3))),)?;
+                                }
+
+                                // Ranked without a prompt, the files sent with one can differ
+                                let dropped_files = context_manager
+                                    .collect_context_files_with_limit(None)
+                                    .await
+                                    .ok()
+                                    .map(|(_, dropped)| dropped);
+
+                                execute!(
+                                    self.output,
+                                    style::Print(format!("\nTotal: ~{} tokens\n\n", total_tokens))
+                                )?;
+
+                                if let Some(dropped_files) = dropped_files {
+                                    if !dropped_files.is_empty() {
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::DarkYellow),
+                                            style::Print(format!(
+                                                "Total token count exceeds limit: {}. The following files will be automatically dropped when interacting with Q, unless your prompt is about them: those added by a glob rather than by name, then those least recently changed. Consider removing them. \n\n",
+                                                context_manager.max_context_files_size()
+                                            )),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                        let total_files = dropped_files.len();
+
+                                        let truncated_dropped_files = &dropped_files[..total_files.min(10)];
+
+                                        for (filename, content) in truncated_dropped_files {
+                                            let est_tokens = TokenCounter::count_tokens(content);
+                                            execute!(
+                                                self.output,
+                                                style::Print(format!("{} ", filename)),
+                                                style::SetForegroundColor(Color::DarkGrey),
+                                                style::Print(format!("(~{} tkns)\n", est_tokens)),
+                                                style::SetForegroundColor(Color::Reset),
+                                            )?;
+                                        }
+
+                                        if total_files > 10 {
+                                            execute!(
+                                                self.output,
+                                                style::Print(format!("({} more files)\n", total_files - 10))
+                                            )?;
+                                        }
+                                    }
+                                }
+
+                                execute!(self.output, style::Print("\n"))?;
+                            }
+
+                            if let Ok(skipped) = context_manager.get_skipped_context_files().await {
+                                print_skipped_context_files(&mut self.output, &skipped)?;
+                            }
+
+                            // Show last cached conversation summary if available, otherwise regenerate it
+                            if expand {
+                                if let Some(summary) = self.conversation_state.latest_summary() {
+                                    let border = "═".repeat(self.terminal_width().min(80));
+                                    execute!(
+                                        self.output,
+                                        style::Print("\n"),
+                                        style::SetForegroundColor(Color::Cyan),
+                                        style::Print(&border),
+                                        style::Print("\n"),
+                                        style::SetAttribute(Attribute::Bold),
+                                        style::Print("                       CONVERSATION SUMMARY"),
+                                        style::Print("\n"),
+                                        style::Print(&border),
+                                        style::SetAttribute(Attribute::Reset),
+                                        style::Print("\n\n"),
+                                        style::Print(&summary),
+                                        style::Print("\n\n\n")
+                                    )?;
+                                }
+                            }
+                        },
+                        command::ContextSubcommand::Add { global, force, paths } => {
+                            match context_manager.add_paths(paths.clone(), global, force).await {
+                                Ok(skipped) => {
+                                    let target = if global { "global" } else { "profile" };
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!(
+                                            "\nAdded {} path(s) to {} context.\n\n",
+                                            paths.len(),
+                                            target
+                                        )),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                    print_skipped_context_files(&mut self.output, &skipped)?;
+                                },
+                                Err(e) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(self.theme.error),
+                                        style::Print(format!("\nError: {}\n\n", e)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                            }
+                        },
+                        command::ContextSubcommand::AddUrl { url } => {
+                            match url_context::fetch_page(&self.ctx, &url, &self.url_allowed_domains).await {
+                                Ok(text) => {
+                                    let tokens = TokenCounter::count_tokens(&text);
+                                    context_manager.attach(url.clone(), text);
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!(
+                                            "\nAdded {} (~{} tkns) to the context of this conversation.\n\n",
+                                            url, tokens
+                                        )),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                                Err(e) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(self.theme.error),
+                                        style::Print(format!("\nError: {}\n\n", e)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                            }
+                        },
+                        command::ContextSubcommand::AddCmd { command } => {
+                            let cwd = self.ctx.env().current_dir()?;
+                            match command_context::run_for_context(&command, &cwd).await {
+                                Ok(text) => {
+                                    let tokens = TokenCounter::count_tokens(&text);
+                                    context_manager.attach(command_context::attachment_name(&command), text);
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!(
+                                            "\nAdded the output of {} (~{} tkns) to the context of this conversation.\n\n",
+                                            command, tokens
+                                        )),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                                Err(e) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(self.theme.error),
+                                        style::Print(format!("\nError: {}\n\n", e)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                            }
+                        },
+                        command::ContextSubcommand::Remove { global, paths } => {
+                            match context_manager.remove_paths(paths.clone(), global).await {
+                                Ok(_) => {
+                                    let target = if global { "global" } else { "profile" };
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!(
+                                            "\nRemoved {} path(s) from {} context.\n\n",
+                                            paths.len(),
+                                            target
+                                        )),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                                Err(e) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(self.theme.error),
+                                        style::Print(format!("\nError: {}\n\n", e)),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                },
+                            }
+                        },
+                        command::ContextSubcommand::Clear { global } => match context_manager.clear(global).await {
+                            Ok(_) => {
+                                let target = if global {
+                                    "global".to_string()
+                                } else {
+                                    format!("profile '{}'", context_manager.current_profile)
+                                };
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::Green),
+                                    style::Print(format!("\nCleared context for {}\n\n", target)),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            },
+                            Err(e) => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!("\nError: {}\n\n", e)),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            },
+                        },
+                        command::ContextSubcommand::Save => match context_manager.save_workspace().await {
+                            Ok(path) => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::Green),
+                                    style::Print(format!(
+                                        "\nSaved the rules of profile '{}' to {}\n",
+                                        context_manager.current_profile,
+                                        path.display()
+                                    )),
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print(
+                                        "Chat sessions started in this directory restore them, /context reset to stop\n\n"
+                                    ),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            },
+                            Err(e) => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!("\nError: {}\n\n", e)),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            },
+                        },
+                        command::ContextSubcommand::Reset => match context_manager.reset_workspace().await {
+                            Ok(existed) => {
+                                let message = match existed {
+                                    true => format!(
+                                        "\nDeleted the rules saved in this directory, back to profile '{}'\n\n",
+                                        context_manager.current_profile
+                                    ),
+                                    false => format!(
+                                        "\nNo rules saved in this directory, using profile '{}'\n\n",
+                                        context_manager.current_profile
+                                    ),
+                                };
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::Green),
+                                    style::Print(message),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            },
+                            Err(e) => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!("\nError: {}\n\n", e)),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            },
+                        },
+                        command::ContextSubcommand::Diff => {
+                            let changes = context_manager.diff_sent_files();
+                            if changes.is_empty() {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(Color::DarkGrey),
+                                    style::Print("\nNo context file changed between the last two requests.\n\n"),
+                                    style::SetForegroundColor(Color::Reset)
+                                )?;
+                            } else {
+                                queue!(self.output, style::Print("\n"))?;
+                                for change in changes {
+                                    match change {
+                                        ContextFileChange::Added(path) => queue!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::Green),
+                                            style::Print(format!("+ {} (added)\n", path))
+                                        )?,
+                                        ContextFileChange::Removed(path) => queue!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::Red),
+                                            style::Print(format!("- {} (removed)\n", path))
+                                        )?,
+                                        ContextFileChange::Modified(path, diff) => {
+                                            queue!(
+                                                self.output,
+                                                style::SetForegroundColor(Color::Yellow),
+                                                style::Print(format!("~ {} (modified)\n", path))
+                                            )?;
+                                            for line in diff.lines() {
+                                                let is_header = line.starts_with("---") || line.starts_with("+++");
+                                                let color = match line.chars().next() {
+                                                    _ if is_header => Color::DarkGrey,
+                                                    Some('+') => Color::Green,
+                                                    Some('-') => Color::Red,
+                                                    Some('@') => Color::Cyan,
+                                                    _ => Color::Reset,
+                                                };
+                                                queue!(
+                                                    self.output,
+                                                    style::SetForegroundColor(color),
+                                                    style::Print(format!("    {}\n", line))
+                                                )?;
+                                            }
+                                        },
+                                    }
+                                }
+                                execute!(self.output, style::SetForegroundColor(Color::Reset), style::Print("\n"))?;
+                            }
+                        },
+                        command::ContextSubcommand::Help => {
+                            execute!(
+                                self.output,
+                                style::Print("\n"),
+                                style::Print(command::ContextSubcommand::help_text()),
+                                style::Print("\n")
+                            )?;
+                        },
+                        command::ContextSubcommand::Hooks { subcommand } => {
+                            fn map_chat_error(e: ErrReport) -> ChatError {
+                                ChatError::Custom(e.to_string().into())
+                            }
+
+                            let scope = |g: bool| if g { "global" } else { "profile" };
+                            if let Some(subcommand) = subcommand {
+                                match subcommand {
+                                    command::HooksSubcommand::Add {
+                                        name,
+                                        trigger,
+                                        command,
+                                        tools,
+                                        append_output,
+                                        global,
+                                    } => {
+                                        let trigger = match trigger.as_str() {
+                                            "conversation_start" => HookTrigger::ConversationStart,
+                                            "pre_tool_use" => HookTrigger::PreToolUse,
+                                            "post_tool_use" => HookTrigger::PostToolUse,
+                                            _ => HookTrigger::PerPrompt,
+                                        };
+                                        let mut hook = Hook::new_inline_hook(trigger, command);
+                                        hook.tools = tools;
+                                        hook.append_output = append_output;
+
+                                        let result = match hook
+                                            .tools
+                                            .iter()
+                                            .try_for_each(|pattern| ToolPattern::parse(pattern).map(|_| ()))
+                                        {
+                                            Ok(()) => context_manager.add_hook(name.clone(), hook, global).await,
+                                            Err(err) => Err(err),
+                                        };
+                                        match result {
+                                            Ok(_) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(Color::Green),
+                                                    style::Print(format!(
+                                                        "\nAdded {} hook '{name}'.\n\n",
+                                                        scope(global)
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                            Err(e) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(self.theme.error),
+                                                    style::Print(format!(
+                                                        "\nCannot add {} hook '{name}': {}\n\n",
+                                                        scope(global),
+                                                        e
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                        }
+                                    },
+                                    command::HooksSubcommand::Remove { name, global } => {
+                                        let result = context_manager.remove_hook(&name, global).await;
+                                        match result {
+                                            Ok(_) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(Color::Green),
+                                                    style::Print(format!(
+                                                        "\nRemoved {} hook '{name}'.\n\n",
+                                                        scope(global)
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                            Err(e) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(self.theme.error),
+                                                    style::Print(format!(
+                                                        "\nCannot remove {} hook '{name}': {}\n\n",
+                                                        scope(global),
+                                                        e
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                        }
+                                    },
+                                    command::HooksSubcommand::Enable { name, global } => {
+                                        let result = context_manager.set_hook_disabled(&name, global, false).await;
+                                        match result {
+                                            Ok(_) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(Color::Green),
+                                                    style::Print(format!(
+                                                        "\nEnabled {} hook '{name}'.\n\n",
+                                                        scope(global)
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                            Err(e) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(self.theme.error),
+                                                    style::Print(format!(
+                                                        "\nCannot enable {} hook '{name}': {}\n\n",
+                                                        scope(global),
+                                                        e
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                        }
+                                    },
+                                    command::HooksSubcommand::Disable { name, global } => {
+                                        let result = context_manager.set_hook_disabled(&name, global, true).await;
+                                        match result {
+                                            Ok(_) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(Color::Green),
+                                                    style::Print(format!(
+                                                        "\nDisabled {} hook '{name}'.\n\n",
+                                                        scope(global)
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                            Err(e) => {
+                                                execute!(
+                                                    self.output,
+                                                    style::SetForegroundColor(self.theme.error),
+                                                    style::Print(format!(
+                                                        "\nCannot disable {} hook '{name}': {}\n\n",
+                                                        scope(global),
+                                                        e
+                                                    )),
+                                                    style::SetForegroundColor(Color::Reset)
+                                                )?;
+                                            },
+                                        }
+                                    },
+                                    command::HooksSubcommand::EnableAll { global } => {
+                                        context_manager
+                                            .set_all_hooks_disabled(global, false)
+                                            .await
+                                            .map_err(map_chat_error)?;
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::Green),
+                                            style::Print(format!("\nEnabled all {} hooks.\n\n", scope(global))),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                    },
+                                    command::HooksSubcommand::DisableAll { global } => {
+                                        context_manager
+                                            .set_all_hooks_disabled(global, true)
+                                            .await
+                                            .map_err(map_chat_error)?;
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::Green),
+                                            style::Print(format!("\nDisabled all {} hooks.\n\n", scope(global))),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                    },
+                                    command::HooksSubcommand::Help => {
+                                        execute!(
+                                            self.output,
+                                            style::Print("\n"),
+                                            style::Print(command::ContextSubcommand::hooks_help_text()),
+                                            style::Print("\n")
+                                        )?;
+                                    },
+                                }
+                            } else {
+                                queue!(
+                                    self.output,
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::Magenta),
+                                    style::Print("\n🌍 global:\n"),
+                                    style::SetAttribute(Attribute::Reset),
+                                )?;
+
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(&mut self.output, &context_manager.global_config.hooks, trigger)
+                                        .map_err(map_chat_error)?;
+                                }
+
+                                queue!(
+                                    self.output,
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::SetForegroundColor(Color::Magenta),
+                                    style::Print(format!("\n👤 profile ({}):\n", &context_manager.current_profile)),
+                                    style::SetAttribute(Attribute::Reset),
+                                )?;
+
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(
+                                        &mut self.output,
+                                        &context_manager.profile_config.hooks,
+                                        trigger,
+                                    )
+                                    .map_err(map_chat_error)?;
+                                }
+
+                                execute!(
+                                    self.output,
+                                    style::Print(format!(
+                                        "\nUse {} to manage hooks.\n\n",
+                                        "/context hooks help".to_string().dark_green()
+                                    )),
+                                )?;
+                            }
+                        },
+                    }
+                    // crate::telemetry::send_context_command_executed
+                } else {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print("\nContext management is not available.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                }
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Tools { subcommand } => {
+                // Disabled tools included
+                let existing_tools: HashSet<String> = self
+                    .conversation_state
+                    .tools
+                    .values()
+                    .flatten()
+                    .map(|FigTool::ToolSpecification(spec)| &spec.name)
+                    .chain(self.conversation_state.tool_manager.schema.keys())
+                    .filter(|name| *name != DUMMY_TOOL_NAME)
+                    .cloned()
+                    .collect();
+
+                match subcommand {
+                    Some(ToolsSubcommand::Schema) => {
+                        let schema_json = serde_json::to_string_pretty(&self.conversation_state.tool_manager.schema)
+                            .map_err(|e| {
+                                ChatError::Custom(format!("Error converting tool schema to string: {e}").into())
+                            })?;
+                        queue!(self.output, style::Print(schema_json), style::Print("\n"))?;
+                    },
+                    Some(ToolsSubcommand::Trust { tool_names }) => {
+                        let (valid_tools, invalid_tools): (Vec<String>, Vec<String>) = tool_names
+                            .into_iter()
+                            .partition(|tool_name| existing_tools.contains(tool_name));
+
+                        if !invalid_tools.is_empty() {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(self.theme.error),
+                                style::Print(format!("\nCannot trust '{}', ", invalid_tools.join("', '"))),
+                                if invalid_tools.len() > 1 {
+                                    style::Print("they do not exist.")
+                                } else {
+                                    style::Print("it does not exist.")
+                                },
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                        if !valid_tools.is_empty() {
+                            valid_tools.iter().for_each(|t| self.tool_permissions.trust_tool(t));
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::Green),
+                                if valid_tools.len() > 1 {
+                                    style::Print(format!("\nTools '{}' are ", valid_tools.join("', '")))
+                                } else {
+                                    style::Print(format!("\nTool '{}' is ", valid_tools[0]))
+                                },
+                                style::Print("now trusted. I will "),
+                                style::SetAttribute(Attribute::Bold),
+                                style::Print("not"),
+                                style::SetAttribute(Attribute::Reset),
+                                style::SetForegroundColor(Color::Green),
+                                style::Print(format!(
+                                    " ask for confirmation before running {}.",
+                                    if valid_tools.len() > 1 {
+                                        "these tools"
+                                    } else {
+                                        "this tool"
+                                    }
+                                )),
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                    },
+                    Some(ToolsSubcommand::Untrust { tool_names }) => {
+                        let (valid_tools, invalid_tools): (Vec<String>, Vec<String>) = tool_names
+                            .into_iter()
+                            .partition(|tool_name| existing_tools.contains(tool_name));
+
+                        if !invalid_tools.is_empty() {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(self.theme.error),
+                                style::Print(format!("\nCannot untrust '{}', ", invalid_tools.join("', '"))),
+                                if invalid_tools.len() > 1 {
+                                    style::Print("they do not exist.")
+                                } else {
+                                    style::Print("it does not exist.")
+                                },
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                        if !valid_tools.is_empty() {
+                            valid_tools.iter().for_each(|t| self.tool_permissions.untrust_tool(t));
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::Green),
+                                if valid_tools.len() > 1 {
+                                    style::Print(format!("\nTools '{}' are ", valid_tools.join("', '")))
+                                } else {
+                                    style::Print(format!("\nTool '{}' is ", valid_tools[0]))
+                                },
+                                style::Print("set to per-request confirmation."),
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                    },
+                    Some(ToolsSubcommand::Enable { tool_names }) => {
+                        self.set_tools_enabled(tool_names, &existing_tools, true)?;
+                    },
+                    Some(ToolsSubcommand::Disable { tool_names }) => {
+                        self.set_tools_enabled(tool_names, &existing_tools, false)?;
+                    },
+                    Some(ToolsSubcommand::TrustAll) => {
+                        self.conversation_state.tools.values().flatten().for_each(
+                            |FigTool::ToolSpecification(spec)| {
+                                self.tool_permissions.trust_tool(spec.name.as_str());
+                            },
+                        );
+                        queue!(self.output, style::Print(TRUST_ALL_TEXT),)?;
+                    },
+                    Some(ToolsSubcommand::Reset) => {
+                        self.tool_permissions.reset();
+                        if let Err(err) = ToolPermissions::forget_saved(&mut database.settings, None).await {
+                            warn!(?err, "Failed to forget the saved tool permissions");
+                        }
+                        queue!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print("\nReset all tools to the default permission levels."),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                    Some(ToolsSubcommand::ResetSingle { tool_name }) => {
+                        if self.tool_permissions.has(&tool_name) || self.tool_permissions.trust_all {
+                            self.tool_permissions.reset_tool(&tool_name);
+                            if let Err(err) =
+                                ToolPermissions::forget_saved(&mut database.settings, Some(&tool_name)).await
+                            {
+                                warn!(?err, "Failed to forget the saved tool permissions");
+                            }
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::Green),
+                                style::Print(format!("\nReset tool '{}' to the default permission level.", tool_name)),
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        } else {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(self.theme.error),
+                                style::Print(format!(
+                                    "\nTool '{}' does not exist or is already in default settings.",
+                                    tool_name
+                                )),
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                    },
+                    Some(ToolsSubcommand::Help) => {
+                        queue!(
+                            self.output,
+                            style::Print("\n"),
+                            style::Print(command::ToolsSubcommand::help_text()),
+                        )?;
+                    },
+                    None => {
+                        // No subcommand - print the current tools and their permissions.
+                        // Determine how to format the output nicely.
+                        let terminal_width = self.terminal_width();
+                        let mut origin_tools = BTreeMap::<_, Vec<_>>::new();
+                        for spec in self.conversation_state.tool_manager.schema.values() {
+                            if spec.name != DUMMY_TOOL_NAME {
+                                origin_tools.entry(&spec.tool_origin).or_default().push(spec);
+                            }
+                        }
+                        let longest = origin_tools
+                            .values()
+                            .flatten()
+                            .map(|spec| spec.name.len())
+                            .max()
+                            .unwrap_or(0);
+
+                        queue!(
+                            self.output,
+                            style::Print("\n"),
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print({
+                                // Adding 2 because of "- " preceding every tool name
+                                let width = (longest + 2).saturating_sub("Tool".len()) + 4;
+                                format!("Tool{:>width$}Permission", "", width = width)
+                            }),
+                            style::SetAttribute(Attribute::Reset),
+                            style::Print("\n"),
+                            style::Print("▔".repeat(terminal_width)),
+                        )?;
+
+                        for (origin, tools) in origin_tools.iter_mut() {
+                            tools.sort_by_key(|spec| &spec.name);
+
+                            let to_display = tools.iter().fold(String::new(), |mut acc, spec| {
+                                let width = longest - spec.name.len() + 4;
+                                let label = match self.conversation_state.disabled_tools.contains(&spec.name) {
+                                    true => format!("  {}", "disabled".dark_grey()),
+                                    false => self.tool_permissions.display_label(&spec.name),
+                                };
+                                acc.push_str(&format!("- {}{:>width$}{}\n", spec.name, "", label, width = width));
+                                // The first line of the description, as much of it as fits
+                                let description = spec.description.lines().next().unwrap_or_default().trim();
+                                if !description.is_empty() {
+                                    let description = match description.len() > terminal_width.saturating_sub(5) {
+                                        true => {
+                                            format!("{}…", truncate_safe(description, terminal_width.saturating_sub(6)))
+                                        },
+                                        false => description.to_string(),
+                                    };
+                                    acc.push_str(&format!("    {}\n", description.dark_grey()));
+                                }
+                                acc
+                            });
+
+                            let _ = queue!(
+                                self.output,
+                                style::SetAttribute(Attribute::Bold),
+                                style::Print(format!("{}:\n", origin)),
+                                style::SetAttribute(Attribute::Reset),
+                                style::Print(to_display),
+                                style::Print("\n")
+                            );
+                        }
+
+                        let loading = self.conversation_state.tool_manager.pending_clients().await;
+                        if !loading.is_empty() {
+                            queue!(
+                                self.output,
+                                style::SetAttribute(Attribute::Bold),
+                                style::Print("Servers still loading"),
+                                style::SetAttribute(Attribute::Reset),
+                                style::Print("\n"),
+                                style::Print("▔".repeat(terminal_width)),
+                            )?;
+                            for client in loading {
+                                queue!(self.output, style::Print(format!(" - {client}")), style::Print("\n"))?;
+                            }
+                        }
+
+                        queue!(
+                            self.output,
+                            style::Print("\nTrusted tools will run without confirmation."),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("\n{}\n", "* Default settings")),
+                            style::Print("\n💡 Use "),
+                            style::SetForegroundColor(Color::Green),
+                            style::Print("/tools help"),
+                            style::SetForegroundColor(Color::Reset),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(" to edit permissions, or to enable and disable tools.\n\n"),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                };
+
+                // Put spacing between previous output as to not be overwritten by
+                // during PromptUser.
+                self.output.flush()?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Prompts { subcommand } => {
+                match subcommand {
+                    Some(PromptsSubcommand::Help) => {
+                        queue!(self.output, style::Print(command::PromptsSubcommand::help_text()))?;
+                    },
+                    Some(PromptsSubcommand::Get { mut get_command }) => {
+                        let orig_input = get_command.orig_input.take();
+                        let prompts = match self.conversation_state.tool_manager.get_prompt(get_command).await {
+                            Ok(resp) => resp,
+                            Err(e) => {
+                                match e {
+                                    GetPromptError::AmbiguousPrompt(prompt_name, alt_msg) => {
+                                        queue!(
+                                            self.output,
+                                            style::Print("\n"),
+                                            style::SetForegroundColor(Color::Yellow),
+                                            style::Print("Prompt "),
+                                            style::SetForegroundColor(Color::Cyan),
+                                            style::Print(prompt_name),
+                                            style::SetForegroundColor(Color::Yellow),
+                                            style::Print(" is ambiguous. Use one of the following "),
+                                            style::SetForegroundColor(Color::Cyan),
+                                            style::Print(alt_msg),
+                                            style::SetForegroundColor(Color::Reset),
+                                        )?;
+                                    },
+                                    GetPromptError::PromptNotFound(prompt_name) => {
+                                        queue!(
+                                            self.output,
+                                            style::Print("\n"),
+                                            style::SetForegroundColor(Color::Yellow),
+                                            style::Print("Prompt "),
+                                            style::SetForegroundColor(Color::Cyan),
+                                            style::Print(prompt_name),
+                                            style::SetForegroundColor(Color::Yellow),
+                                            style::Print(" not found. Use "),
+                                            style::SetForegroundColor(Color::Cyan),
+                                            style::Print("/prompts list"),
+                                            style::SetForegroundColor(Color::Yellow),
+                                            style::Print(" to see available prompts.\n"),
+                                            style::SetForegroundColor(Color::Reset),
+                                        )?;
+                                    },
+                                    _ => return Err(ChatError::Custom(e.to_string().into())),
+                                }
+                                execute!(self.output, style::Print("\n"))?;
+                                return Ok(ChatState::PromptUser {
+                                    tool_uses: Some(tool_uses),
+                                    pending_tool_index,
+                                    skip_printing_tools: true,
+                                });
+                            },
+                        };
+                        if let Some(err) = prompts.error {
+                            // If we are running into error we should just display the error
+                            // and abort.
+                            let to_display = serde_json::json!(err);
+                            queue!(
+                                self.output,
+                                style::Print("\n"),
+                                style::SetAttribute(Attribute::Bold),
+                                style::Print("Error encountered while retrieving prompt:"),
+                                style::SetAttribute(Attribute::Reset),
+                                style::Print("\n"),
+                                style::SetForegroundColor(self.theme.error),
+                                style::Print(
+                                    serde_json::to_string_pretty(&to_display)
+                                        .unwrap_or_else(|_| format!("{:?}", &to_display))
+                                ),
+                                style::SetForegroundColor(Color::Reset),
+                                style::Print("\n"),
+                            )?;
+                        } else {
+                            let prompts = prompts
+                                .result
+                                .ok_or(ChatError::Custom("Result field missing from prompt/get request".into()))?;
+                            let prompts = serde_json::from_value::<PromptGetResult>(prompts).map_err(|e| {
+                                ChatError::Custom(format!("Failed to deserialize prompt/get result: {:?}", e).into())
+                            })?;
+                            self.pending_prompts.clear();
+                            self.pending_prompts.append(&mut VecDeque::from(prompts.messages));
+                            return Ok(ChatState::HandleInput {
+                                input: orig_input.unwrap_or_default(),
+                                tool_uses: Some(tool_uses),
+                                pending_tool_index,
+                            });
+                        }
+                    },
+                    subcommand => {
+                        let search_word = match subcommand {
+                            Some(PromptsSubcommand::List { search_word }) => search_word,
+                            _ => None,
+                        };
+                        let terminal_width = self.terminal_width();
+                        let mut prompts_wl = self.conversation_state.tool_manager.prompts.write().map_err(|e| {
+                            ChatError::Custom(
+                                format!("Poison error encountered while retrieving prompts: {}", e).into(),
+                            )
+                        })?;
+                        self.conversation_state.tool_manager.refresh_prompts(&mut prompts_wl)?;
+                        let mut longest_name = "";
+                        let arg_pos = {
+                            let optimal_case = UnicodeWidthStr::width(longest_name) + terminal_width / 4;
+                            if optimal_case > terminal_width {
+                                terminal_width / 3
+                            } else {
+                                optimal_case
+                            }
+                        };
+                        queue!(
+                            self.output,
+                            style::Print("\n"),
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print("Prompt"),
+                            style::SetAttribute(Attribute::Reset),
+                            style::Print({
+                                let name_width = UnicodeWidthStr::width("Prompt");
+                                let padding = arg_pos.saturating_sub(name_width);
+                                " ".repeat(padding)
+                            }),
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print("Arguments (* = required)"),
+                            style::SetAttribute(Attribute::Reset),
+                            style::Print("\n"),
+                            style::Print(format!("{}\n", "▔".repeat(terminal_width))),
+                        )?;
+                        let mut prompts_by_server: Vec<_> = prompts_wl
+                            .iter()
+                            .fold(
+                                HashMap::<&String, Vec<&PromptBundle>>::new(),
+                                |mut acc, (prompt_name, bundles)| {
+                                    if prompt_name.contains(search_word.as_deref().unwrap_or("")) {
+                                        if prompt_name.len() > longest_name.len() {
+                                            longest_name = prompt_name.as_str();
+                                        }
+                                        for bundle in bundles {
+                                            acc.entry(&bundle.server_name)
+                                                .and_modify(|b| b.push(bundle))
+                                                .or_insert(vec![bundle]);
+                                        }
+                                    }
+                                    acc
+                                },
+                            )
+                            .into_iter()
+                            .collect();
+                        prompts_by_server.sort_by_key(|(server_name, _)| server_name.as_str());
+
+                        for (i, (server_name, bundles)) in prompts_by_server.iter_mut().enumerate() {
+                            bundles.sort_by_key(|bundle| &bundle.prompt_get.name);
+
+                            if i > 0 {
+                                queue!(self.output, style::Print("\n"))?;
+                            }
+                            queue!(
+                                self.output,
+                                style::SetAttribute(Attribute::Bold),
+                                style::Print(server_name),
+                                style::Print(" (MCP):"),
+                                style::SetAttribute(Attribute::Reset),
+                                style::Print("\n"),
+                            )?;
+                            for bundle in bundles {
+                                queue!(
+                                    self.output,
+                                    style::Print("- "),
+                                    style::Print(&bundle.prompt_get.name),
+                                    style::Print({
+                                        if bundle
+                                            .prompt_get
+                                            .arguments
+                                            .as_ref()
+                                            .is_some_and(|args| !args.is_empty())
+                                        {
+                                            let name_width = UnicodeWidthStr::width(bundle.prompt_get.name.as_str());
+                                            let padding =
+                                                arg_pos.saturating_sub(name_width) - UnicodeWidthStr::width("- ");
+                                            " ".repeat(padding)
+                                        } else {
+                                            "\n".to_owned()
+                                        }
+                                    })
+                                )?;
+                                if let Some(args) = bundle.prompt_get.arguments.as_ref() {
+                                    for (i, arg) in args.iter().enumerate() {
+                                        queue!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::DarkGrey),
+                                            style::Print(match arg.required {
+                                                Some(true) => format!("{}*", arg.name),
+                                                _ => arg.name.clone(),
+                                            }),
+                                            style::SetForegroundColor(Color::Reset),
+                                            style::Print(if i < args.len() - 1 { ", " } else { "\n" }),
+                                        )?;
+                                    }
+                                }
+                            }
+                        }
+                    },
+                }
+                execute!(self.output, style::Print("\n"))?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Usage => {
+                let limit = self
+                    .conversation_state
+                    .context_manager
+                    .as_ref()
+                    .map_or(CONTEXT_FILES_MAX_SIZE, ContextManager::max_context_files_size);
+                let state = self.conversation_state.backend_conversation_state(true, true).await;
+
+                if !state.dropped_context_files.is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkYellow),
+                        style::Print(format!(
+                            "\n{} Run ",
+                            dropped_files_warning(&state.dropped_context_files, limit)
+                        )),
+                        style::SetForegroundColor(Color::DarkGreen),
+                        style::Print("/context show "),
+                        style::SetForegroundColor(Color::DarkYellow),
+                        style::Print("to learn more.\n"),
+                        style::SetForegroundColor(style::Color::Reset)
+                    )?;
+                }
+
+                let data = state.calculate_conversation_size();
+                let tools = tools_char_count(state.tools);
+                let summary = CharCount::from(self.conversation_state.latest_summary().map_or(0, str::len));
+                let pins = CharCount::from(self.conversation_state.pins().iter().map(String::len).sum::<usize>());
+                let context_files = CharCount::from(
+                    data.context_messages
+                        .value()
+                        .saturating_sub(summary.value() + pins.value()),
+                );
+                let categories = [
+                    UsageCategory {
+                        name: "Context files",
+                        tokens: context_files.into(),
+                        color: Color::DarkCyan,
+                    },
+                    UsageCategory {
+                        name: "Summary",
+                        tokens: summary.into(),
+                        color: Color::Cyan,
+                    },
+                    UsageCategory {
+                        name: "Pinned",
+                        tokens: pins.into(),
+                        color: Color::Green,
+                    },
+                    UsageCategory {
+                        name: "Tool schemas",
+                        tokens: tools.into(),
+                        color: Color::DarkYellow,
+                    },
+                    UsageCategory {
+                        name: "Q responses",
+                        tokens: data.assistant_messages.into(),
+                        color: Color::Blue,
+                    },
+                    UsageCategory {
+                        name: "Your prompts",
+                        tokens: data.user_messages.into(),
+                        color: Color::Magenta,
+                    },
+                ];
+
+                // set a max width for the progress bar for better aesthetic
+                let progress_bar_width = std::cmp::min(self.terminal_width(), 80);
+                print_context_window(&mut self.output, &categories, progress_bar_width)?;
+
+                let usage = *self.conversation_state.usage();
+                queue!(
+                    self.output,
+                    style::SetAttribute(Attribute::Bold),
+                    style::Print("\nThis session\n"),
+                    style::SetAttribute(Attribute::Reset),
+                    style::Print(format!(
+                        "{} requests, ~{} input tokens, ~{} output tokens\n",
+                        usage.requests, usage.input_tokens, usage.output_tokens
+                    )),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("Estimated from the size of each request and response\n"),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+
+                queue!(
+                    self.output,
+                    style::SetAttribute(Attribute::Bold),
+                    style::Print("\n💡 Pro Tips:\n"),
+                    style::SetAttribute(Attribute::Reset),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("Run "),
+                    style::SetForegroundColor(Color::DarkGreen),
+                    style::Print("/compact"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(" to replace the conversation history with its summary\n"),
+                    style::Print("Run "),
+                    style::SetForegroundColor(Color::DarkGreen),
+                    style::Print("/clear"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(" to erase the entire chat history\n"),
+                    style::Print("Run "),
+                    style::SetForegroundColor(Color::DarkGreen),
+                    style::Print("/context show"),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(" to see tokens per context file\n\n"),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Load { path } => {
+                macro_rules! tri {
+                    ($v:expr) => {
+                        match $v {
+                            Ok(v) => v,
+                            Err(err) => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!("\nFailed to import from {}: {}\n\n", &path, &err)),
+                                    style::SetAttribute(Attribute::Reset)
+                                )?;
+                                return Ok(ChatState::PromptUser {
+                                    tool_uses: Some(tool_uses),
+                                    pending_tool_index,
+                                    skip_printing_tools: true,
+                                });
+                            },
+                        }
+                    };
+                }
+
+                let contents = tri!(self.ctx.fs().read_to_string(&path).await);
+                let new_state: ConversationState = tri!(serde_json::from_str(&contents));
+                self.restore_conversation(new_state).await;
+
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(format!("\n✔ Imported conversation state from {}\n\n", &path)),
+                    style::SetAttribute(Attribute::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: None,
+                    pending_tool_index: None,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Save { path, force } => {
+                macro_rules! tri {
+                    ($v:expr) => {
+                        match $v {
+                            Ok(v) => v,
+                            Err(err) => {
+                                execute!(
+                                    self.output,
+                                    style::SetForegroundColor(self.theme.error),
+                                    style::Print(format!("\nFailed to export to {}: {}\n\n", &path, &err)),
+                                    style::SetAttribute(Attribute::Reset)
+                                )?;
+                                return Ok(ChatState::PromptUser {
+                                    tool_uses: Some(tool_uses),
+                                    pending_tool_index,
+                                    skip_printing_tools: true,
+                                });
+                            },
+                        }
+                    };
+                }
+
+                let contents = tri!(serde_json::to_string_pretty(&self.conversation_state));
+                if self.ctx.fs().exists(&path) && !force {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!(
+                            "\nFile at {} already exists. To overwrite, use -f or --force\n\n",
+                            &path
+                        )),
+                        style::SetAttribute(Attribute::Reset)
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                }
+                tri!(self.ctx.fs().write(&path, contents).await);
+
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(format!("\n✔ Exported conversation state to {}\n\n", &path)),
+                    style::SetAttribute(Attribute::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: None,
+                    pending_tool_index: None,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::LoadSession { name: None } => {
+                self.print_saved_sessions().await?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::LoadSession { name: Some(name) } => {
+                let loaded = match directories::chat_sessions_dir() {
+                    Ok(dir) => load_session(&self.ctx, &dir, &name).await,
+                    Err(err) => Err(err.into()),
+                };
+                match loaded {
+                    Ok(new_state) => {
+                        self.restore_conversation(new_state).await;
+                        self.session_name = Some(name.clone());
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Loaded the conversation saved as {name}\n\n")),
+                            style::SetAttribute(Attribute::Reset)
+                        )?;
+                        ChatState::PromptUser {
+                            tool_uses: None,
+                            pending_tool_index: None,
+                            skip_printing_tools: true,
+                        }
+                    },
+                    Err(err) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("\nFailed to load the conversation: {err}\n\n")),
+                            style::SetAttribute(Attribute::Reset)
+                        )?;
+                        ChatState::PromptUser {
+                            tool_uses: Some(tool_uses),
+                            pending_tool_index,
+                            skip_printing_tools: true,
+                        }
+                    },
+                }
+            },
+            Command::SaveSession { name, force } => {
+                let name = name
+                    .or_else(|| self.session_name.clone())
+                    .unwrap_or_else(|| self.new_session_name());
+                match self.save_session(&name, force).await {
+                    Ok(()) => {
+                        self.session_name = Some(name.clone());
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Saved the conversation as {name}\n")),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!(
+                                "Continue it later with /load {name} or {CLI_BINARY_NAME} chat --resume {name}\n\n"
+                            )),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                        self.sync_sessions().await?;
+                    },
+                    Err(err) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nFailed to save the conversation as {name}: {err}\n\n")),
+                        style::SetAttribute(Attribute::Reset)
+                    )?,
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Export { path, format, force } => {
+                if self.ctx.fs().exists(&path) && !force {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!(
+                            "\nFile at {} already exists. To overwrite, use -f or --force\n\n",
+                            &path
+                        )),
+                        style::SetAttribute(Attribute::Reset)
+                    )?;
+                } else {
+                    let result = match export::export(&self.conversation_state, format) {
+                        Ok(contents) => self.ctx.fs().write(&path, contents).await.map_err(Into::into),
+                        Err(err) => Err(err),
+                    };
+                    match result {
+                        Ok(()) => execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Exported the conversation to {}\n\n", &path)),
+                            style::SetAttribute(Attribute::Reset)
+                        )?,
+                        Err(err) => execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("\nFailed to export to {}: {}\n\n", &path, &err)),
+                            style::SetAttribute(Attribute::Reset)
+                        )?,
+                    }
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Share { html } => {
+                let result = match ShareTarget::resolve(
+                    self.github_token.as_deref(),
+                    html,
+                    self.conversation_state.conversation_id(),
+                ) {
+                    Ok(target) => share::share(&self.ctx, &self.conversation_state, &target)
+                        .await
+                        .map(|shared| (target, shared)),
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok((target, (link, redacted))) => {
+                        let shared = match target {
+                            ShareTarget::Gist { .. } => "as a secret gist, anyone with the link can see it",
+                            ShareTarget::Html(_) => "as an HTML file",
+                        };
+                        queue!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Shared the conversation {shared}:\n")),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print(format!("  {link}\n")),
+                        )?;
+                        if redacted > 0 {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print(format!(
+                                    "Redacted {redacted} secret{} such as keys and tokens, check it before passing it on\n",
+                                    if redacted == 1 { "" } else { "s" }
+                                )),
+                            )?;
+                        }
+                        execute!(self.output, style::SetForegroundColor(Color::Reset), style::Print("\n"))?;
+                    },
+                    Err(err) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nFailed to share the conversation: {err}\n\n")),
+                        style::SetAttribute(Attribute::Reset)
+                    )?,
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Fork { name } => {
+                match self
+                    .branches
+                    .fork(name, &self.conversation_state, self.session_name.clone())
+                {
+                    Ok(name) => {
+                        // Saving the fork should not replace the conversation it was forked from
+                        self.session_name = None;
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Forked the conversation into the branch {name}\n")),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print("Go back with /branches, the other branches are kept as they are\n\n"),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    },
+                    Err(err) => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\n{err}\n\n")),
+                        style::SetAttribute(Attribute::Reset)
+                    )?,
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::New { name } => {
+                match self
+                    .branches
+                    .new_conversation(name, &self.conversation_state, self.session_name.clone())
+                {
+                    Ok(name) => {
+                        let conversation_id = Alphanumeric.sample_string(&mut rand::rng(), 9);
+                        let state = self.conversation_state.new_conversation(&conversation_id);
+                        self.restore_conversation(state).await;
+                        self.session_name = None;
+                        let previous = self.branches.others().last().map(|branch| branch.name.clone());
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Started the conversation {name}\n")),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!(
+                                "Go back with /switch {}, the other conversations are kept as they are\n\n",
+                                previous.unwrap_or_default()
+                            )),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                        ChatState::PromptUser {
+                            tool_uses: None,
+                            pending_tool_index: None,
+                            skip_printing_tools: true,
+                        }
+                    },
+                    Err(err) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("\n{err}\n\n")),
+                            style::SetAttribute(Attribute::Reset)
+                        )?;
+                        ChatState::PromptUser {
+                            tool_uses: Some(tool_uses),
+                            pending_tool_index,
+                            skip_printing_tools: true,
+                        }
+                    },
+                }
+            },
+            Command::Branches { name: None } => {
+                self.print_branches()?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Branches { name: Some(name) } => {
+                match self
+                    .branches
+                    .switch(&name, &self.conversation_state, self.session_name.clone())
+                {
+                    Ok(branch) => {
+                        self.restore_conversation(branch.state).await;
+                        self.session_name = branch.session_name;
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Switched to the branch {name}\n\n")),
+                            style::SetAttribute(Attribute::Reset)
+                        )?;
+                        ChatState::PromptUser {
+                            tool_uses: None,
+                            pending_tool_index: None,
+                            skip_printing_tools: true,
+                        }
+                    },
+                    Err(err) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("\n{err}\n\n")),
+                            style::SetAttribute(Attribute::Reset)
+                        )?;
+                        ChatState::PromptUser {
+                            tool_uses: Some(tool_uses),
+                            pending_tool_index,
+                            skip_printing_tools: true,
+                        }
+                    },
+                }
+            },
+            Command::Pin { index: None, .. } => {
+                self.print_user_prompts("pin", "Use /pin <n> to pin a message.")?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Forget { range: None } => {
+                self.print_user_prompts(
+                    "forget",
+                    "Use /forget <n> or /forget <n>-<m> to stop sending messages to the model.",
+                )?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Forget {
+                range: Some((first, last)),
+            } => {
+                let prompts = self.conversation_state.user_prompts();
+                let history_len = self.conversation_state.history().len();
+                let Some(&(start, _)) = prompts.get(first - 1) else {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nThere is no message {first}, see /forget\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                };
+                // Along with the responses and tool uses up to the next message
+                let end = prompts.get(last).map_or(history_len, |(i, _)| *i);
+
+                let forgotten = self.conversation_state.forget_history(start..end);
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(format!(
+                        "\n✔ Forgot {} message(s), they won't be sent to the model anymore:\n",
+                        forgotten.iter().filter(|(user, _)| user.prompt().is_some()).count()
+                    )),
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::SetAttribute(Attribute::CrossedOut),
+                )?;
+                for (user, assistant) in &forgotten {
+                    let Some(prompt) = user.prompt() else {
+                        continue;
+                    };
+                    let response = assistant.content().lines().find(|line| !line.trim().is_empty());
+                    execute!(
+                        self.output,
+                        style::Print(format!(
+                            "  > {}\n",
+                            truncate_safe(prompt.lines().next().unwrap_or_default(), 80)
+                        )),
+                        style::Print(format!("    {}\n", truncate_safe(response.unwrap_or_default(), 80))),
+                    )?;
+                }
+                execute!(
+                    self.output,
+                    style::SetAttribute(Attribute::Reset),
+                    style::SetForegroundColor(Color::Reset),
+                    style::Print("\n")
+                )?;
+
+                // Pending tool uses belong to the last exchange
+                match end == history_len {
+                    true => ChatState::PromptUser {
+                        tool_uses: None,
+                        pending_tool_index: None,
+                        skip_printing_tools: true,
+                    },
+                    false => ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    },
+                }
+            },
+            Command::Pin {
+                index: Some(n),
+                remove: true,
+            } => {
+                match self.conversation_state.unpin(n - 1) {
+                    Some(pin) => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!(
+                            "\n✔ Unpinned: {}\n\n",
+                            truncate_safe(pin.lines().next().unwrap_or_default(), 100)
+                        )),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                    None => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nThere is no pin {n}, see /pins\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Pin {
+                index: Some(n),
+                remove: false,
+            } => {
+                let prompt = self
+                    .conversation_state
+                    .user_prompts()
+                    .get(n - 1)
+                    .map(|(_, prompt)| (*prompt).to_string());
+                match prompt {
+                    Some(prompt) => {
+                        let first_line = truncate_safe(prompt.lines().next().unwrap_or_default(), 100).to_string();
+                        let message = match self.conversation_state.pin(prompt) {
+                            true => format!("\n✔ Pinned: {first_line}\n\n"),
+                            false => format!("\nAlready pinned: {first_line}\n\n"),
+                        };
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(message),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    },
+                    None => execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!("\nThere is no message {n}, see /pin\n\n")),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Pins => {
+                let pins = self.conversation_state.pins();
+                if pins.is_empty() {
+                    execute!(
+                        self.output,
+                        style::Print("\nNo pinned messages, pin one with /pin <n>\n\n")
+                    )?;
+                } else {
+                    execute!(self.output, style::Print("\n"))?;
+                    for (n, pin) in pins.iter().enumerate() {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("{:>3}. ", n + 1)),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print(truncate_safe(pin.lines().next().unwrap_or_default(), 100)),
+                            style::Print("\n")
+                        )?;
+                    }
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nUse /pin --remove <n> to unpin a message.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Tag { tags, remove } => {
+                if tags.is_empty() {
+                    let tags = self.conversation_state.tags();
+                    let message = match tags.is_empty() {
+                        true => "\nThe conversation has no tags, add some with /tag <tag>\n\n".to_string(),
+                        false => format!(
+                            "\nTags: {}\n\n",
+                            tags.iter().map(String::as_str).collect::<Vec<_>>().join(", ")
+                        ),
+                    };
+                    execute!(self.output, style::Print(message))?;
+                } else {
+                    for tag in &tags {
+                        match remove {
+                            true => self.conversation_state.remove_tag(tag),
+                            false => self.conversation_state.add_tag(tag.clone()),
+                        };
+                    }
+                    let done = match remove {
+                        true => "Untagged",
+                        false => "Tagged",
+                    };
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!("\n✔ {done} the conversation {}\n", tags.join(", "))),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+
+                    // Keeps the saved conversation in step, otherwise the tags are saved with it
+                    let saved = match self.session_name.clone() {
+                        Some(name) => Some(self.save_session(&name, true).await.map(|_| name)),
+                        None => None,
+                    };
+                    match saved {
+                        Some(Ok(name)) => execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("Updated the conversation saved as {name}\n\n")),
+                            style::SetForegroundColor(Color::Reset)
+                        )?,
+                        Some(Err(err)) => execute!(
+                            self.output,
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("Failed to update the saved conversation: {err}\n\n")),
+                            style::SetAttribute(Attribute::Reset)
+                        )?,
+                        None => execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!(
+                                "Save it with /save <name> to find it with {CLI_BINARY_NAME} chat search --tag {}\n\n",
+                                tags[0]
+                            )),
+                            style::SetForegroundColor(Color::Reset)
+                        )?,
+                    }
+                }
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Mcp => {
+                let terminal_width = self.terminal_width();
+                let loaded_servers = self.conversation_state.tool_manager.mcp_load_record.lock().await;
+                let still_loading = self
+                    .conversation_state
+                    .tool_manager
+                    .pending_clients()
+                    .await
+                    .into_iter()
+                    .map(|name| format!(" - {name}\n"))
+                    .collect::<Vec<_>>()
+                    .join("");
+                for (server_name, msg) in loaded_servers.iter() {
+                    let msg = msg
+                        .iter()
+                        .map(|record| match record {
+                            LoadingRecord::Err(content)
+                            | LoadingRecord::Warn(content)
+                            | LoadingRecord::Success(content) => content.clone(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n--- tools refreshed ---\n");
+                    queue!(
+                        self.output,
+                        style::Print(server_name),
+                        style::Print("\n"),
+                        style::Print(format!("{}\n", "▔".repeat(terminal_width))),
+                        style::Print(msg),
+                        style::Print("\n")
+                    )?;
+                }
+                if !still_loading.is_empty() {
+                    queue!(
+                        self.output,
+                        style::Print("Still loading:\n"),
+                        style::Print(format!("{}\n", "▔".repeat(terminal_width))),
+                        style::Print(still_loading),
+                        style::Print("\n")
+                    )?;
+                }
+                self.output.flush()?;
+                ChatState::PromptUser {
+                    tool_uses: None,
+                    pending_tool_index: None,
+                    skip_printing_tools: true,
+                }
+            },
+        })
+    }
+
+    async fn tool_use_execute(
+        &mut self,
+        telemetry: &TelemetryThread,
+        mut tool_uses: Vec<QueuedTool>,
+    ) -> Result<ChatState, ChatError> {
+        // Verify tools have permissions.
+        for index in 0..tool_uses.len() {
+            let tool = &mut tool_uses[index];
+            // Manually accepted by the user or otherwise verified already, or denied or disabled,
+            // which the model is told of rather than the user asked
+            if tool.accepted || self.is_refused(tool) {
+                continue;
+            }
+
+            // Nothing to accept when the tool is only described
+            if self.dry_run && tool.tool.requires_acceptance(&self.ctx) {
+                self.print_tool_descriptions(tool, false).await?;
+                tool.accepted = true;
+                continue;
+            }
+
+            let allowed = self.is_allowed(tool);
+            if !allowed && self.interactive {
+                self.notifier
+                    .notify(&self.ctx, &mut self.output, Notification::ApprovalNeeded)?;
+            }
+
+            self.print_tool_descriptions(tool, allowed).await?;
+
+            if allowed {
+                tool.accepted = true;
+                continue;
+            }
+
+            let pending_tool_index = Some(index);
+            if !self.interactive {
+                // Cannot request in non-interactive, so fail.
+                return Err(ChatError::NonInteractiveToolApproval);
+            }
+
+            // The similar uses further in the queue are described now, to be approved along with
+            // this one
+            self.approval_batch = self.approval_batch(&tool_uses, index);
+            let batched = self
+                .approval_batch
+                .as_ref()
+                .map(|batch| batch.indices[1..].to_vec())
+                .unwrap_or_default();
+            for batched in batched {
+                self.print_tool_descriptions(&tool_uses[batched], false).await?;
+            }
+
+            return Ok(ChatState::PromptUser {
+                tool_uses: Some(tool_uses),
+                pending_tool_index,
+                skip_printing_tools: false,
+            });
+        }
+
+        // Execute the requested tools.
+        let mut tool_results = vec![];
+        let mut image_blocks: Vec<RichImageBlock> = Vec::new();
+        let mut cache_misses = HashMap::new();
+
+        // The tools that only read run at the same time when several are asked for in a row, their
+        // output kept until each completes. The others run in order, so that a read asked for after a
+        // write or a command sees what it did.
+        let mut tool_uses = tool_uses.into_iter().peekable();
+        while let Some(tool) = tool_uses.next() {
+            let mut group = vec![tool];
+            if self.runs_in_parallel(&group[0]) {
+                while let Some(tool) = tool_uses.next_if(|tool| self.runs_in_parallel(tool)) {
+                    group.push(tool);
+                }
+
+                // The reads of files unchanged since the same read earlier in the session aren't run
+                // again, which is only known once the tools asked for before them have run. The
+                // others have what they read noted, their result being kept once they've run.
+                let mut uncached = Vec::new();
+                for tool in group {
+                    let (tool_use_id, output) = match self.tool_cache.lookup(&self.ctx, &tool).await {
+                        Lookup::Hit { tool_use_id, output } => (tool_use_id, output),
+                        Lookup::Miss(miss) => {
+                            cache_misses.insert(tool.id.clone(), miss);
+                            uncached.push(tool);
+                            continue;
+                        },
+                        Lookup::Uncached => {
+                            uncached.push(tool);
+                            continue;
+                        },
+                    };
+                    // The model is pointed at the earlier result rather than given it again while it has it
+                    let output = match self.conversation_state.has_tool_result(&tool_use_id) {
+                        true => OutputKind::Text(format!(
+                            "The files read are unchanged since the identical tool use {tool_use_id} earlier in the \
+                             conversation, whose result still applies."
+                        )),
+                        false => output,
+                    };
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nUnchanged since the same use earlier, not run again (see /cache)\n"),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.finish_tool_use(
+                        &tool,
+                        Ok(InvokeOutput { output }),
+                        Duration::ZERO,
+                        &mut tool_results,
+                        &mut image_blocks,
+                    )
+                    .await?;
+                    let post_hooks = self
+                        .run_tool_hooks(HookTrigger::PostToolUse, &tool, tool_results.last())
+                        .await?;
+                    append_hook_outputs(tool_results.last_mut(), &post_hooks);
+                }
+                group = uncached;
+            }
+            if group.len() > 1 {
+                let ctx = Arc::clone(&self.ctx);
+                let timeouts = self.tool_timeouts.clone();
+                let mut running = stream::iter(group.iter().map(|tool| {
+                    let ctx = &ctx;
+                    let timeout = timeouts.get(&tool.name);
+                    async move {
+                        let mut updates = Vec::new();
+                        let tool_start = std::time::Instant::now();
+                        // Not asking whether to keep waiting, the other tools still running
+                        let invoke_result = match timeout {
+                            Some(timeout) => tokio::time::timeout(timeout, tool.tool.invoke(ctx, &mut updates))
+                                .await
+                                .unwrap_or_else(|_| Err(ToolTimedOut(timeout).into())),
+                            None => tool.tool.invoke(ctx, &mut updates).await,
+                        };
+                        (tool, updates, invoke_result, tool_start.elapsed())
+                    }
+                }))
+                .buffer_unordered(MAX_PARALLEL_TOOLS);
+                while let Some((tool, updates, invoke_result, tool_time)) = running.next().await {
+                    self.output.write_all(&updates)?;
+                    if let (Ok(output), Some(miss)) = (&invoke_result, cache_misses.remove(&tool.id)) {
+                        self.tool_cache.insert(miss, &tool.id, output);
+                    }
+                    self.finish_tool_use(tool, invoke_result, tool_time, &mut tool_results, &mut image_blocks)
+                        .await?;
+                    let post_hooks = self
+                        .run_tool_hooks(HookTrigger::PostToolUse, tool, tool_results.last())
+                        .await?;
+                    append_hook_outputs(tool_results.last_mut(), &post_hooks);
+                }
+                continue;
+            }
+
+            for tool in group {
+                // The model may still ask for a tool disabled since it was last told of the tools
+                if self.conversation_state.disabled_tools.contains(&tool.name) {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!(
+                            "\n{} is disabled, see /tools enable to enable it again\n",
+                            tool.tool.display_name()
+                        )),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.audit(&tool, Decision::Denied, None);
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(format!(
+                            "The {} tool is disabled by the user and can't be used.",
+                            tool.name
+                        ))],
+                        status: ToolResultStatus::Error,
+                    });
+                    continue;
+                }
+
+                if self.tool_permissions.is_denied(&tool.name) {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!(
+                            "\n{} is denied, see /tools to allow it again\n",
+                            tool.tool.display_name()
+                        )),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.audit(&tool, Decision::Denied, None);
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(format!(
+                            "The user denied the use of the {} tool. Don't use it again, do without it or ask the user.",
+                            tool.name
+                        ))],
+                        status: ToolResultStatus::Error,
+                    });
+                    continue;
+                }
+
+                if let Some(pattern) = self.tool_permissions.rules.denying(&self.ctx, &tool.name, &tool.tool) {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(self.theme.error),
+                        style::Print(format!(
+                            "\n{} is denied by {} in the deniedTools of mcp.json\n",
+                            tool.tool.display_name(),
+                            pattern.pattern
+                        )),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.audit(&tool, Decision::Denied, None);
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(format!(
+                            "The user's configuration denies this use of the {} tool (matching {}). Don't retry it, do \
+                             without it or ask the user.",
+                            tool.name, pattern.pattern
+                        ))],
+                        status: ToolResultStatus::Error,
+                    });
+                    continue;
+                }
+
+                if self.is_refused_as_read_only(&tool) {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print(format!(
+                            "\nNot executed: {} can change things in read-only mode\n",
+                            tool.name
+                        )),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.audit(&tool, Decision::ReadOnly, None);
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(format!(
+                            "Not executed: the user turned on read-only mode, where tools that can change anything, like \
+                             this use of {}, are refused. Don't retry it, use only tools that read or tell the user what \
+                             they would need to run themselves.",
+                            tool.name
+                        ))],
+                        status: ToolResultStatus::Error,
+                    });
+                    continue;
+                }
+
+                if self.dry_run && tool.tool.requires_acceptance(&self.ctx) {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("\nNot executed (dry run)\n"),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.audit(&tool, Decision::DryRun, None);
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(
+                            "Not executed (dry run): the user is reviewing what you would do, so nothing was run or \
+                             changed. Carry on with the rest of the plan as if this had succeeded, without retrying it."
+                                .to_string(),
+                        )],
+                        status: ToolResultStatus::Success,
+                    });
+                    continue;
+                }
+
+                let pre_hooks = self.run_tool_hooks(HookTrigger::PreToolUse, &tool, None).await?;
+                if let Some((hook, run)) = pre_hooks.iter().find(|(_, run)| !run.success) {
+                    self.audit(&tool, Decision::StoppedByHook, None);
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(format!(
+                            "The user's hook '{}' stopped this use of the {} tool before it ran:\n{}",
+                            hook.name, tool.name, run.output
+                        ))],
+                        status: ToolResultStatus::Error,
+                    });
+                    continue;
+                }
+
+                if let Tool::FsWrite(fs_write) = &tool.tool {
+                    let path = sanitize_path_tool_arg(&self.ctx, fs_write.path());
+                    self.checkpoints.snapshot(&self.ctx, &path).await;
+                }
+
+                let tool_start = std::time::Instant::now();
+                let invoke_result = self.invoke_tool(&tool).await;
+                if let (Ok(output), Some(miss)) = (&invoke_result, cache_misses.remove(&tool.id)) {
+                    self.tool_cache.insert(miss, &tool.id, output);
+                }
+                self.finish_tool_use(
+                    &tool,
+                    invoke_result,
+                    tool_start.elapsed(),
+                    &mut tool_results,
+                    &mut image_blocks,
+                )
+                .await?;
+                let post_hooks = self
+                    .run_tool_hooks(HookTrigger::PostToolUse, &tool, tool_results.last())
+                    .await?;
+                append_hook_outputs(tool_results.last_mut(), pre_hooks.iter().chain(&post_hooks));
+            }
+        }
+
+        if !image_blocks.is_empty() {
+            let images = image_blocks.into_iter().map(|(block, _)| block).collect();
+            self.conversation_state
+                .add_tool_results_with_images(tool_results, images);
+            execute!(
+                self.output,
+                style::SetAttribute(Attribute::Reset),
+                style::SetForegroundColor(Color::Reset),
+                style::Print("\n")
+            )?;
+        } else {
+            self.conversation_state.add_tool_results(tool_results);
+        }
+        if let Some(state) = self.auto_compact().await? {
+            return Ok(state);
+        }
+        if self.interactive {
+            execute!(self.output, cursor::Hide)?;
+            execute!(self.output, style::Print("\n"), style::SetAttribute(Attribute::Reset))?;
+            self.spinner = Some(self.spinner_config.start("Thinking..."));
+        }
+
+        self.send_tool_use_telemetry(telemetry).await;
+        return Ok(ChatState::HandleResponseStream(
+            self.client
+                .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
+                .await?,
+        ));
+    }
+
+    /// Runs `tool`, stopping it once it runs for longer than its timeout unless the user chooses to
+    /// keep waiting.
+    async fn invoke_tool(&mut self, tool: &QueuedTool) -> Result<InvokeOutput> {
+        // The user can stop a command running in a terminal themselves, maybe still typing into it
+        let timeout = match &tool.tool {
+            Tool::ExecuteBash(execute_bash) if execute_bash.pty => {
+                // Which gets what's typed
+                self.stop_typeahead()?;
+                None
+            },
+            _ => self.tool_timeouts.get(&tool.name),
+        };
+        let Some(timeout) = timeout else {
+            return tool.tool.invoke(&self.ctx, &mut self.output).await;
+        };
+        let ctx = Arc::clone(&self.ctx);
+        let mut output = self.output.clone();
+        let invoke = tool.tool.invoke(&ctx, &mut output);
+        tokio::pin!(invoke);
+        let mut waited = Duration::ZERO;
+        loop {
+            if let Ok(invoke_result) = tokio::time::timeout(timeout, &mut invoke).await {
+                return invoke_result;
+            }
+            waited += timeout;
+            // Dropping `invoke` stops the tool, killing the commands of `execute_bash`
+            if !self.interactive || !self.keep_waiting(tool, waited).await? {
+                return Err(ToolTimedOut(waited).into());
+            }
+        }
+    }
+
+    /// Asks whether to keep waiting for `tool`, which has been running for `waited`.
+    async fn keep_waiting(&mut self, tool: &QueuedTool, waited: Duration) -> Result<bool> {
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::Yellow),
+            style::Print(format!(
+                "\n\n{} is still running after {}s. ",
+                tool.tool.display_name(),
+                waited.as_secs()
+            )),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("Keep waiting? ["),
+            style::SetForegroundColor(Color::Green),
+            style::Print("y"),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("/"),
+            style::SetForegroundColor(Color::Green),
+            style::Print("n"),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("]:\n\n"),
+            style::SetForegroundColor(Color::Reset),
+        )?;
+        let user_input = self
+            .read_user_input("> ".yellow().to_string().as_str(), true)
+            .await
+            .unwrap_or_default();
+        Ok(["y", "Y"].contains(&user_input.trim()))
+    }
+
+    /// Whether `tool` can run along with others, having no side effects nor needing the terminal.
+    fn runs_in_parallel(&self, tool: &QueuedTool) -> bool {
+        matches!(
+            tool.tool,
+            Tool::FsRead(_) | Tool::GrepSearch(_) | Tool::GlobFiles(_) | Tool::ProcessOutput(_)
+        ) && !self.conversation_state.disabled_tools.contains(&tool.name)
+            && !self.tool_permissions.is_denied(&tool.name)
+            && !self.is_denied_by_rules(tool)
+            && self.tool_hooks(HookTrigger::PreToolUse, tool).is_empty()
+    }
+
+    /// Whether `tool` isn't run, being disabled, denied or refused in read-only mode, which the
+    /// model is told of rather than the user asked.
+    fn is_refused(&self, tool: &QueuedTool) -> bool {
+        self.tool_permissions.is_denied(&tool.name)
+            || self.is_denied_by_rules(tool)
+            || self.is_refused_as_read_only(tool)
+            || self.conversation_state.disabled_tools.contains(&tool.name)
+    }
+
+    /// Whether `tool` runs without asking, being trusted, allowed by a pattern or harmless.
+    fn is_allowed(&self, tool: &QueuedTool) -> bool {
+        // If there is an override, we will use it. Otherwise fall back to Tool's default.
+        let file_trusted = match &tool.tool {
+            Tool::FsWrite(fs_write) => self
+                .tool_permissions
+                .is_file_trusted(sanitize_path_tool_arg(&self.ctx, fs_write.path())),
+            _ => false,
+        };
+        self.tool_permissions.trust_all
+            || (self.tool_permissions.has(&tool.name) && self.tool_permissions.is_trusted(&tool.name))
+            || file_trusted
+            || self.tool_permissions.rules.allows(&self.ctx, &tool.name, &tool.tool)
+            || self
+                .tool_permissions
+                .is_pattern_trusted(&self.ctx, &tool.name, &tool.tool)
+            || !tool.tool.requires_acceptance(&self.ctx)
+    }
+
+    /// The tool use at `index` along with the uses of the same tool further in `tool_uses` that
+    /// need approval as well, so that the user answers once for all of them rather than for each.
+    /// `None` when there are no such uses.
+    fn approval_batch(&self, tool_uses: &[QueuedTool], index: usize) -> Option<ApprovalBatch> {
+        let name = &tool_uses[index].name;
+        let indices = std::iter::once(index)
+            .chain((index + 1..tool_uses.len()).filter(|&other| {
+                let tool = &tool_uses[other];
+                &tool.name == name && !tool.accepted && !self.is_refused(tool) && !self.is_allowed(tool)
+            }))
+            .collect::<Vec<_>>();
+        if indices.len() < 2 {
+            return None;
+        }
+        let tools = indices.iter().map(|&index| &tool_uses[index].tool).collect::<Vec<_>>();
+        Some(ApprovalBatch {
+            pattern: covering_pattern(&self.ctx, name, &tools),
+            indices,
+        })
+    }
+
+    /// The batch of the tool use at `index`, when it is pending approval along with others.
+    fn pending_batch(&self, index: usize) -> Option<ApprovalBatch> {
+        self.approval_batch
+            .clone()
+            .filter(|batch| batch.indices.first() == Some(&index))
+    }
+
+    /// Whether `tool` is refused for being able to change things while in read-only mode.
+    fn is_refused_as_read_only(&self, tool: &QueuedTool) -> bool {
+        self.read_only
+            && tool
+                .tool
+                .mutates(self.conversation_state.tool_manager.schema.get(&tool.name))
+    }
+
+    /// The enabled hooks of `trigger` that run for `tool`.
+    fn tool_hooks(&self, trigger: HookTrigger, tool: &QueuedTool) -> Vec<Hook> {
+        let Some(context_manager) = &self.conversation_state.context_manager else {
+            return Vec::new();
+        };
+        context_manager
+            .tool_hooks(trigger)
+            .into_iter()
+            .filter(|hook| hook.runs_for(&self.ctx, &tool.name, &tool.tool))
+            .collect()
+    }
+
+    /// Runs the hooks of `trigger` for `tool` one after the other, a
+    /// [HookTrigger::PostToolUse] hook being told of `result`. Stops at the first
+    /// [HookTrigger::PreToolUse] hook failing, which stops the tool.
+    async fn run_tool_hooks(
+        &mut self,
+        trigger: HookTrigger,
+        tool: &QueuedTool,
+        result: Option<&ToolUseResult>,
+    ) -> Result<Vec<(Hook, ToolHookOutput)>, ChatError> {
+        let hooks = self.tool_hooks(trigger.clone(), tool);
+        if hooks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tool_response = result.map(|result| {
+            let content = result
+                .content
+                .iter()
+                .map(|block| match block {
+                    ToolUseResultBlock::Text(text) => serde_json::Value::String(text.clone()),
+                    ToolUseResultBlock::Json(json) => json.clone(),
+                })
+                .collect::<Vec<_>>();
+            serde_json::json!({
+                "success": matches!(result.status, ToolResultStatus::Success),
+                "content": content,
+            })
+        });
+        let event = serde_json::json!({
+            "hook_event_name": trigger.to_string(),
+            "tool_name": tool.name,
+            "tool_use_id": tool.id,
+            "tool_input": tool.input,
+            "tool_response": tool_response,
+        });
+
+        let mut runs = Vec::with_capacity(hooks.len());
+        for hook in hooks {
+            let run = match &self.conversation_state.context_manager {
+                Some(context_manager) => context_manager.hook_executor.run_tool_hook(&hook, &event).await,
+                None => break,
+            };
+            let (color, symbol, outcome) = match (run.success, &trigger) {
+                (true, _) => (Color::DarkGrey, "✓", "ran"),
+                (false, HookTrigger::PreToolUse) => (self.theme.error, "✗", "stopped the tool"),
+                (false, _) => (Color::Yellow, "✗", "failed"),
+            };
+            queue!(
+                self.output,
+                style::SetForegroundColor(color),
+                style::Print(format!(" {symbol} Hook '{}' ({trigger}) {outcome}\n", hook.name)),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+            if !run.success {
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!("{}\n", run.output.trim_end())),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+            }
+            let stops = !run.success && trigger == HookTrigger::PreToolUse;
+            runs.push((hook, run));
+            if stops {
+                break;
+            }
+        }
+        self.output.flush()?;
+        Ok(runs)
+    }
+
+    /// Whether a pattern of `deniedTools` in `mcp.json` denies the use of `tool`.
+    fn is_denied_by_rules(&self, tool: &QueuedTool) -> bool {
+        self.tool_permissions
+            .rules
+            .denying(&self.ctx, &tool.name, &tool.tool)
+            .is_some()
+    }
+
+    /// Shows the result of `tool` and adds it to `tool_results`, shortened to
+    /// `chat.toolOutput.maxBytes` and `chat.toolOutput.maxLines`, along with its images to
+    /// `image_blocks`.
+    async fn finish_tool_use(
+        &mut self,
+        tool: &QueuedTool,
+        invoke_result: Result<InvokeOutput>,
+        tool_time: Duration,
+        tool_results: &mut Vec<ToolUseResult>,
+        image_blocks: &mut Vec<RichImageBlock>,
+    ) -> Result<(), ChatError> {
+        self.log_transcript(&LogEntry::ToolResult {
+            id: &tool.id,
+            name: &tool.name,
+            success: invoke_result.is_ok(),
+        });
+        let decision = match tool.approved {
+            true => Decision::Approved,
+            false => Decision::Trusted,
+        };
+        self.audit(tool, decision, Some(&Outcome::of(&invoke_result, tool_time)));
+
+        let mut tool_telemetry = self.tool_use_telemetry_events.entry(tool.id.clone());
+        tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_accepted = true);
+
+        if self.interactive && self.spinner.is_some() {
+            queue!(
+                self.output,
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                cursor::MoveToColumn(0),
+                cursor::Show
+            )?;
+        }
+        execute!(self.output, style::Print("\n"))?;
+
+        if let Tool::Custom(ct) = &tool.tool {
+            tool_telemetry = tool_telemetry.and_modify(|ev| {
+                ev.custom_tool_call_latency = Some(tool_time.as_secs() as usize);
+                ev.input_token_size = Some(ct.get_input_token_size());
+                ev.is_custom_tool = true;
+            });
+        }
+        let tool_time = format!("{}.{}", tool_time.as_secs(), tool_time.subsec_millis());
+        match invoke_result {
+            Ok(mut result) => {
+                match result.output {
+                    OutputKind::Text(ref text) => {
+                        debug!("Output is Text: {}", text);
+                    },
+                    OutputKind::Json(ref json) => {
+                        debug!("Output is JSON: {}", json);
+                    },
+                    OutputKind::Images(ref image) => {
+                        image_blocks.extend(image.clone());
                     },
                 }
-                execute!(self.output, style::Print("\n"))?;
-                ChatState::PromptUser {
-                    tool_uses: Some(tool_uses),
-                    pending_tool_index,
-                    skip_printing_tools: true,
-                }
-            },
-            Command::Usage => {
-                let state = self.conversation_state.backend_conversation_state(true, true).await;
 
-                if !state.dropped_context_files.is_empty() {
+                debug!("tool result output: {:#?}", result);
+                execute!(
+                    self.output,
+                    style::Print(CONTINUATION_LINE),
+                    style::Print("\n"),
+                    style::SetForegroundColor(Color::Green),
+                    style::SetAttribute(Attribute::Bold),
+                    style::Print(format!(" ● Completed in {}s", tool_time)),
+                    style::SetForegroundColor(Color::Reset),
+                    style::Print("\n"),
+                )?;
+
+                tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_success = Some(true));
+                if let Tool::Custom(_) = &tool.tool {
+                    tool_telemetry
+                        .and_modify(|ev| ev.output_token_size = Some(TokenCounter::count_tokens(result.as_str())));
+                }
+                let full_output = tool_output::full_text(&result.output);
+                if self.tool_output_limits.cap(&mut result.output, &tool.id) {
+                    if let Err(err) = tool_output::save(&self.ctx, &tool.id, &full_output).await {
+                        warn!(?err, "Failed to save the full output of the tool");
+                    }
+                    self.last_shortened_tool_use = Some(tool.id.clone());
                     execute!(
                         self.output,
-                        style::SetForegroundColor(Color::DarkYellow),
-                        style::Print("\nSome context files are dropped due to size limit, please run "),
-                        style::SetForegroundColor(Color::DarkGreen),
-                        style::Print("/context show "),
-                        style::SetForegroundColor(Color::DarkYellow),
-                        style::Print("to learn more.\n"),
-                        style::SetForegroundColor(style::Color::Reset)
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!(
+                            "   The output was shortened for the model, see all of it with /expand {}\n",
+                            tool.id
+                        )),
+                        style::SetForegroundColor(Color::Reset),
                     )?;
                 }
+                tool_results.push(ToolUseResult {
+                    tool_use_id: tool.id.clone(),
+                    content: vec![result.into()],
+                    status: ToolResultStatus::Success,
+                });
+            },
+            Err(err) if err.is::<ToolTimedOut>() => {
+                execute!(
+                    self.output,
+                    style::Print(CONTINUATION_LINE),
+                    style::Print("\n"),
+                    style::SetAttribute(Attribute::Bold),
+                    style::SetForegroundColor(self.theme.error),
+                    style::Print(format!(" ● {err}, stopped\n\n")),
+                    style::SetAttribute(Attribute::Reset),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
 
-                let data = state.calculate_conversation_size();
+                tool_telemetry.and_modify(|ev| ev.is_success = Some(false));
+                let waited = err
+                    .downcast_ref::<ToolTimedOut>()
+                    .map_or(0, |timed_out| timed_out.0.as_secs());
+                tool_results.push(ToolUseResult {
+                    tool_use_id: tool.id.clone(),
+                    content: vec![ToolUseResultBlock::Json(serde_json::json!({
+                        "status": "timed_out",
+                        "timeoutSeconds": waited,
+                        "message": "The tool ran for longer than the user allows and was stopped, along with the \
+                                    processes it started. Try a quicker or narrower way, or ask the user to raise \
+                                    tools.timeoutMs.",
+                    }))],
+                    status: ToolResultStatus::Error,
+                });
+            },
+            Err(err) => {
+                error!(?err, "An error occurred processing the tool");
+                execute!(
+                    self.output,
+                    style::Print(CONTINUATION_LINE),
+                    style::Print("\n"),
+                    style::SetAttribute(Attribute::Bold),
+                    style::SetForegroundColor(self.theme.error),
+                    style::Print(format!(" ● Execution failed after {}s:\n", tool_time)),
+                    style::SetAttribute(Attribute::Reset),
+                    style::SetForegroundColor(self.theme.error),
+                    style::Print(&err),
+                    style::SetAttribute(Attribute::Reset),
+                    style::Print("\n\n"),
+                )?;
 
-                let context_token_count: TokenCount = data.context_messages.into();
-                let assistant_token_count: TokenCount = data.assistant_messages.into();
-                let user_token_count: TokenCount = data.user_messages.into();
-                let total_token_used: TokenCount =
-                    (data.context_messages + data.user_messages + data.assistant_messages).into();
+                tool_telemetry.and_modify(|ev| ev.is_success = Some(false));
+                tool_results.push(ToolUseResult {
+                    tool_use_id: tool.id.clone(),
+                    content: vec![ToolUseResultBlock::Text(format!(
+                        "An error occurred processing the tool: \n{}",
+                        &err
+                    ))],
+                    status: ToolResultStatus::Error,
+                });
+                if let ToolUseStatus::Idle = self.tool_use_status {
+                    self.tool_use_status = ToolUseStatus::RetryInProgress(
+                        self.conversation_state
+                            .message_id()
+                            .map_or("No utterance id found".to_string(), |v| v.to_string()),
+                    );
+                }
+            },
+        }
 
-                let window_width = self.terminal_width();
-                // set a max width for the progress bar for better aesthetic
-                let progress_bar_width = std::cmp::min(window_width, 80);
+        Ok(())
+    }
 
-                let context_width = ((context_token_count.value() as f64 / CONTEXT_WINDOW_SIZE as f64)
-                    * progress_bar_width as f64) as usize;
-                let assistant_width = ((assistant_token_count.value() as f64 / CONTEXT_WINDOW_SIZE as f64)
-                    * progress_bar_width as f64) as usize;
-                let user_width = ((user_token_count.value() as f64 / CONTEXT_WINDOW_SIZE as f64)
-                    * progress_bar_width as f64) as usize;
+    async fn handle_response(
+        &mut self,
+        database: &mut Database,
+        telemetry: &TelemetryThread,
+        response: SendMessageOutput,
+    ) -> Result<ChatState, ChatError> {
+        let escape_listener = match self.typeahead.take() {
+            Some(listener) => Some(listener),
+            None if self.interactive => EscapeListener::start(self.notifier.focus()),
+            None => None,
+        };
+        if let Some(listener) = &escape_listener {
+            listener.forget_pressed();
+        }
+        let result = self
+            .receive_response(database, telemetry, response, escape_listener.as_ref())
+            .await;
 
-                let left_over_width = progress_bar_width
-                    - std::cmp::min(context_width + assistant_width + user_width, progress_bar_width);
+        if let Some(listener) = &escape_listener {
+            listener.hide_typed(&mut self.output)?;
+        }
+        self.typeahead = escape_listener;
+        // Keep listening while the tools run, or keep what was typed for the next prompts
+        if !matches!(
+            result,
+            Ok(ChatState::ValidateTools(_) | ChatState::HandleResponseStream(_))
+        ) {
+            self.stop_typeahead()?;
+        }
+        // The pager can only read the terminal once the listener is done with it
+        if let Some(response) = self.page_pending.take() {
+            self.page_response(&response)?;
+        }
+        result
+    }
 
-                let is_overflow = (context_width + assistant_width + user_width) > progress_bar_width;
+    /// Stops listening to the terminal, keeping what was typed for the next prompts: the lines
+    /// submitted with Enter are sent once the current turn completes, and the rest pre-fills the
+    /// next prompt.
+    fn stop_typeahead(&mut self) -> std::io::Result<()> {
+        let Some(listener) = self.typeahead.take() else {
+            return Ok(());
+        };
+        listener.show_queued(&mut self.output)?;
+        let typed = listener.stop();
+        self.queued_messages.extend(typed.queued);
+        if !typed.pending.is_empty() && self.pending_input.is_none() {
+            self.pending_input = Some(typed.pending);
+        }
+        Ok(())
+    }
 
-                if is_overflow {
-                    queue!(
-                        self.output,
-                        style::Print(format!(
-                            "\nCurrent context window ({} of {}k tokens used)\n",
-                            total_token_used,
-                            CONTEXT_WINDOW_SIZE / 1000
-                        )),
-                        style::SetForegroundColor(Color::DarkRed),
-                        style::Print("█".repeat(progress_bar_width)),
-                        style::SetForegroundColor(Color::Reset),
-                        style::Print(" "),
-                        style::Print(format!(
-                            "{:.2}%",
-                            (total_token_used.value() as f32 / CONTEXT_WINDOW_SIZE as f32) * 100.0
-                        )),
-                    )?;
-                } else {
-                    queue!(
-                        self.output,
-                        style::Print(format!(
-                            "\nCurrent context window ({} of {}k tokens used)\n",
-                            total_token_used,
-                            CONTEXT_WINDOW_SIZE / 1000
-                        )),
-                        style::SetForegroundColor(Color::DarkCyan),
-                        // add a nice visual to mimic "tiny" progress, so the overral progress bar doesn't look too
-                        // empty
-                        style::Print("|".repeat(if context_width == 0 && *context_token_count > 0 {
-                            1
-                        } else {
-                            0
-                        })),
-                        style::Print("█".repeat(context_width)),
-                        style::SetForegroundColor(Color::Blue),
-                        style::Print("|".repeat(if assistant_width == 0 && *assistant_token_count > 0 {
-                            1
-                        } else {
-                            0
-                        })),
-                        style::Print("█".repeat(assistant_width)),
-                        style::SetForegroundColor(Color::Magenta),
-                        style::Print("|".repeat(if user_width == 0 && *user_token_count > 0 { 1 } else { 0 })),
-                        style::Print("█".repeat(user_width)),
-                        style::SetForegroundColor(Color::DarkGrey),
-                        style::Print("█".repeat(left_over_width)),
-                        style::Print(" "),
-                        style::SetForegroundColor(Color::Reset),
-                        style::Print(format!(
-                            "{:.2}%",
-                            (total_token_used.value() as f32 / CONTEXT_WINDOW_SIZE as f32) * 100.0
-                        )),
-                    )?;
-                }
+    /// Receives and prints the response until it ends, Ctrl+C is pressed, or Esc is pressed while
+    /// `escape_listener` is listening. What's typed meanwhile is shown whenever the response is at
+    /// the start of a line.
+    async fn receive_response(
+        &mut self,
+        database: &mut Database,
+        telemetry: &TelemetryThread,
+        response: SendMessageOutput,
+        escape_listener: Option<&EscapeListener>,
+    ) -> Result<ChatState, ChatError> {
+        let request_id = response.request_id().map(|s| s.to_string());
+        let mut buf = String::new();
+        let mut offset = 0;
+        let mut ended = false;
+        let mut parser = ResponseParser::new(response);
+        let mut state = ParseState::new(Some(self.terminal_width()));
+        state.text_color = self.theme.assistant;
+        if state.text_color != Color::Reset {
+            queue!(self.output, style::SetForegroundColor(state.text_color))?;
+        }
+
+        let mut tool_uses = Vec::new();
+        let mut tool_name_being_recvd: Option<String> = None;
+        let ctrl_c_stream = ctrl_c();
+        tokio::pin!(ctrl_c_stream);
+        let escape_pressed = async {
+            match escape_listener {
+                Some(listener) => listener.pressed().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(escape_pressed);
+        let typed_changed = || async move {
+            match escape_listener {
+                Some(listener) => listener.changed().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        if self.interactive && self.spinner.is_some() {
+            drop(self.spinner.take());
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Reset),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                cursor::MoveToColumn(0),
+                cursor::Show,
+                cursor::MoveUp(1),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+            )?;
+        }
+
+        loop {
+            let recv = tokio::select! {
+                recv = parser.recv() => recv,
+                Ok(_) = &mut ctrl_c_stream => {
+                    if let Some(listener) = escape_listener {
+                        listener.hide_typed(&mut self.output)?;
+                    }
+                    self.interrupted_at = Some(Instant::now());
+                    return self.interrupt_response(database, &buf);
+                },
+                _ = &mut escape_pressed => {
+                    if let Some(listener) = escape_listener {
+                        listener.hide_typed(&mut self.output)?;
+                    }
+                    return self.interrupt_response(database, &buf);
+                },
+                _ = typed_changed() => {
+                    if let Some(listener) = escape_listener {
+                        if self.spinner.is_none() && buf[..offset].ends_with('\n') {
+                            listener.show_typed(&mut self.output)?;
+                            execute!(self.output, style::SetForegroundColor(state.text_color))?;
+                        }
+                    }
+                    continue;
+                },
+            };
+            if let Some(listener) = escape_listener {
+                listener.hide_typed(&mut self.output)?;
+            }
+            match recv {
+                Ok(msg_event) => {
+                    trace!("Consumed: {:?}", msg_event);
+                    match msg_event {
+                        parser::ResponseEvent::ToolUseStart { name } => {
+                            // We need to flush the buffer here, otherwise text will not be
+                            // printed while we are receiving tool use events.
+                            buf.push('\n');
+                            tool_name_being_recvd = Some(name);
+                        },
+                        parser::ResponseEvent::AssistantText(text) => {
+                            buf.push_str(&text);
+                        },
+                        parser::ResponseEvent::ToolUse(tool_use) => {
+                            if self.interactive && self.spinner.is_some() {
+                                drop(self.spinner.take());
+                                queue!(
+                                    self.output,
+                                    terminal::Clear(terminal::ClearType::CurrentLine),
+                                    cursor::MoveToColumn(0),
+                                    cursor::Show
+                                )?;
+                            }
+                            tool_uses.push(tool_use);
+                            tool_name_being_recvd = None;
+                        },
+                        parser::ResponseEvent::EndStream { message } => {
+                            // This log is attempting to help debug instances where users encounter
+                            // the response timeout message.
+                            if message.content() == RESPONSE_TIMEOUT_CONTENT {
+                                error!(?request_id, ?message, "Encountered an unexpected model response");
+                            }
+                            self.log_assistant_message(&message);
+                            self.conversation_state.push_assistant_message(message, database);
+                            ended = true;
+                        },
+                    }
+                },
+                Err(recv_error) => {
+                    if let Some(request_id) = &recv_error.request_id {
+                        self.failed_request_ids.push(request_id.clone());
+                    };
+
+                    match recv_error.source {
+                        RecvErrorKind::StreamTimeout { source, duration } => {
+                            error!(
+                                recv_error.request_id,
+                                ?source,
+                                "Encountered a stream timeout after waiting for {}s",
+                                duration.as_secs()
+                            );
+                            if self.interactive {
+                                execute!(self.output, cursor::Hide)?;
+                                self.spinner = Some(self.spinner_config.start("Dividing up the work..."));
+                            }
+                            // For stream timeouts, we'll tell the model to try and split its response into
+                            // smaller chunks.
+                            self.conversation_state.push_assistant_message(
+                                AssistantMessage::new_response(None, RESPONSE_TIMEOUT_CONTENT.to_string()),
+                                database,
+                            );
+                            self.conversation_state
+                                .set_next_user_message(
+                                    "You took too long to respond - try to split up the work into smaller steps."
+                                        .to_string(),
+                                )
+                                .await;
+                            self.send_tool_use_telemetry(telemetry).await;
+                            return Ok(ChatState::HandleResponseStream(
+                                self.client
+                                    .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
+                                    .await?,
+                            ));
+                        },
+                        RecvErrorKind::UnexpectedToolUseEos {
+                            tool_use_id,
+                            name,
+                            message,
+                            time_elapsed,
+                        } => {
+                            error!(
+                                recv_error.request_id,
+                                tool_use_id, name, "The response stream ended before the entire tool use was received"
+                            );
+                            if self.interactive {
+                                drop(self.spinner.take());
+                                queue!(
+                                    self.output,
+                                    terminal::Clear(terminal::ClearType::CurrentLine),
+                                    cursor::MoveToColumn(0),
+                                    style::SetForegroundColor(Color::Yellow),
+                                    style::SetAttribute(Attribute::Bold),
+                                    style::Print(format!(
+                                        "Warning: received an unexpected error from the model after {:.2}s",
+                                        time_elapsed.as_secs_f64()
+                                    )),
+                                )?;
+                                if let Some(request_id) = recv_error.request_id {
+                                    queue!(
+                                        self.output,
+                                        style::Print(format!("\n         request_id: {}", request_id))
+                                    )?;
+                                }
+                                execute!(self.output, style::Print("\n\n"), style::SetAttribute(Attribute::Reset))?;
+                                self.spinner = Some(self.spinner_config.start("Trying to divide up the work..."));
+                            }
 
-                queue!(self.output, style::Print("\n\n"))?;
-                self.output.flush()?;
+                            self.conversation_state.push_assistant_message(*message, database);
+                            let tool_results = vec![ToolUseResult {
+                                    tool_use_id,
+                                    content: vec![ToolUseResultBlock::Text(
+                                        "The generated tool was too large, try again but this time split up the work between multiple tool uses".to_string(),
+                                    )],
+                                    status: ToolResultStatus::Error,
+                                }];
+                            self.conversation_state.add_tool_results(tool_results);
+                            self.send_tool_use_telemetry(telemetry).await;
+                            return Ok(ChatState::HandleResponseStream(
+                                self.client
+                                    .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
+                                    .await?,
+                            ));
+                        },
+                        _ => return Err(recv_error.into()),
+                    }
+                },
+            }
 
-                queue!(
-                    self.output,
-                    style::SetForegroundColor(Color::DarkCyan),
-                    style::Print("█ Context files: "),
-                    style::SetForegroundColor(Color::Reset),
-                    style::Print(format!(
-                        "~{} tokens ({:.2}%)\n",
-                        context_token_count,
-                        (context_token_count.value() as f32 / CONTEXT_WINDOW_SIZE as f32) * 100.0
-                    )),
-                    style::SetForegroundColor(Color::Blue),
-                    style::Print("█ Q responses: "),
-                    style::SetForegroundColor(Color::Reset),
-                    style::Print(format!(
-                        "  ~{} tokens ({:.2}%)\n",
-                        assistant_token_count,
-                        (assistant_token_count.value() as f32 / CONTEXT_WINDOW_SIZE as f32) * 100.0
-                    )),
-                    style::SetForegroundColor(Color::Magenta),
-                    style::Print("█ Your prompts: "),
-                    style::SetForegroundColor(Color::Reset),
-                    style::Print(format!(
-                        " ~{} tokens ({:.2}%)\n\n",
-                        user_token_count,
-                        (user_token_count.value() as f32 / CONTEXT_WINDOW_SIZE as f32) * 100.0
-                    )),
-                )?;
+            // Fix for the markdown parser copied over from q chat:
+            // this is a hack since otherwise the parser might report Incomplete with useful data
+            // still left in the buffer. I'm not sure how this is intended to be handled.
+            if ended {
+                buf.push('\n');
+            }
 
+            if tool_name_being_recvd.is_none() && !buf.is_empty() && self.interactive && self.spinner.is_some() {
+                drop(self.spinner.take());
                 queue!(
                     self.output,
-                    style::SetAttribute(Attribute::Bold),
-                    style::Print("\n💡 Pro Tips:\n"),
-                    style::SetAttribute(Attribute::Reset),
-                    style::SetForegroundColor(Color::DarkGrey),
-                    style::Print("Run "),
-                    style::SetForegroundColor(Color::DarkGreen),
-                    style::Print("/compact"),
-                    style::SetForegroundColor(Color::DarkGrey),
-                    style::Print(" to replace the conversation history with its summary\n"),
-                    style::Print("Run "),
-                    style::SetForegroundColor(Color::DarkGreen),
-                    style::Print("/clear"),
-                    style::SetForegroundColor(Color::DarkGrey),
-                    style::Print(" to erase the entire chat history\n"),
-                    style::Print("Run "),
-                    style::SetForegroundColor(Color::DarkGreen),
-                    style::Print("/context show"),
-                    style::SetForegroundColor(Color::DarkGrey),
-                    style::Print(" to see tokens per context file\n\n"),
-                    style::SetForegroundColor(Color::Reset),
+                    terminal::Clear(terminal::ClearType::CurrentLine),
+                    cursor::MoveToColumn(0),
+                    cursor::Show
                 )?;
+            }
 
-                ChatState::PromptUser {
-                    tool_uses: Some(tool_uses),
-                    pending_tool_index,
-                    skip_printing_tools: true,
-                }
-            },
-            Command::Load { path } => {
-                macro_rules! tri {
-                    ($v:expr) => {
-                        match $v {
-                            Ok(v) => v,
-                            Err(err) => {
-                                execute!(
-                                    self.output,
-                                    style::SetForegroundColor(Color::Red),
-                                    style::Print(format!("\nFailed to import from {}: {}\n\n", &path, &err)),
-                                    style::SetAttribute(Attribute::Reset)
-                                )?;
-                                return Ok(ChatState::PromptUser {
-                                    tool_uses: Some(tool_uses),
-                                    pending_tool_index,
-                                    skip_printing_tools: true,
-                                });
-                            },
-                        }
-                    };
+            // Print the response for normal cases
+            loop {
+                let input = Partial::new(&buf[offset..]);
+                match interpret_markdown(input, &mut self.output, &mut state) {
+                    Ok(parsed) => {
+                        offset += parsed.offset_from(&input);
+                        self.output.flush()?;
+                        state.newline = state.set_newline;
+                        state.set_newline = false;
+                    },
+                    Err(err) => match err.into_inner() {
+                        Some(err) => return Err(ChatError::Custom(err.to_string().into())),
+                        None => break, // Data was incomplete
+                    },
                 }
 
-                let contents = tri!(self.ctx.fs().read_to_string(&path).await);
-                let mut new_state: ConversationState = tri!(serde_json::from_str(&contents));
-                new_state
-                    .reload_serialized_state(Arc::clone(&self.ctx), Some(self.output.clone()))
-                    .await;
-                self.conversation_state = new_state;
+                // TODO: We should buffer output based on how much we have to parse, not as a constant
+                // Do not remove unless you are nabochay :)
+                std::thread::sleep(Duration::from_millis(8));
+            }
 
-                execute!(
-                    self.output,
-                    style::SetForegroundColor(Color::Green),
-                    style::Print(format!("\n✔ Imported conversation state from {}\n\n", &path)),
-                    style::SetAttribute(Attribute::Reset)
-                )?;
+            // Set spinner after showing all of the assistant text content so far.
+            if let (Some(_name), true) = (&tool_name_being_recvd, self.interactive) {
+                queue!(self.output, cursor::Hide)?;
+                self.spinner = Some(self.spinner_config.start("Thinking..."));
+            }
 
-                ChatState::PromptUser {
-                    tool_uses: None,
-                    pending_tool_index: None,
-                    skip_printing_tools: true,
-                }
-            },
-            Command::Save { path, force } => {
-                macro_rules! tri {
-                    ($v:expr) => {
-                        match $v {
-                            Ok(v) => v,
-                            Err(err) => {
-                                execute!(
-                                    self.output,
-                                    style::SetForegroundColor(Color::Red),
-                                    style::Print(format!("\nFailed to export to {}: {}\n\n", &path, &err)),
-                                    style::SetAttribute(Attribute::Reset)
-                                )?;
-                                return Ok(ChatState::PromptUser {
-                                    tool_uses: Some(tool_uses),
-                                    pending_tool_index,
-                                    skip_printing_tools: true,
-                                });
-                            },
-                        }
-                    };
+            if let Some(listener) = escape_listener {
+                if !ended && self.spinner.is_none() && buf[..offset].ends_with('\n') {
+                    listener.show_typed(&mut self.output)?;
+                    queue!(self.output, style::SetForegroundColor(state.text_color))?;
                 }
+            }
 
-                let contents = tri!(serde_json::to_string_pretty(&self.conversation_state));
-                if self.ctx.fs().exists(&path) && !force {
-                    execute!(
-                        self.output,
-                        style::SetForegroundColor(Color::Red),
-                        style::Print(format!(
-                            "\nFile at {} already exists. To overwrite, use -f or --force\n\n",
-                            &path
-                        )),
-                        style::SetAttribute(Attribute::Reset)
-                    )?;
-                    return Ok(ChatState::PromptUser {
-                        tool_uses: Some(tool_uses),
-                        pending_tool_index,
-                        skip_printing_tools: true,
-                    });
+            if ended {
+                if let Some(message_id) = self.conversation_state.message_id() {
+                    telemetry
+                        .send_chat_added_message(
+                            self.conversation_state.conversation_id().to_owned(),
+                            message_id.to_owned(),
+                            self.conversation_state.context_message_length(),
+                        )
+                        .ok();
                 }
-                tri!(self.ctx.fs().write(&path, contents).await);
-
-                execute!(
-                    self.output,
-                    style::SetForegroundColor(Color::Green),
-                    style::Print(format!("\n✔ Exported conversation state to {}\n\n", &path)),
-                    style::SetAttribute(Attribute::Reset)
-                )?;
 
-                ChatState::PromptUser {
-                    tool_uses: None,
-                    pending_tool_index: None,
-                    skip_printing_tools: true,
-                }
-            },
-            Command::Mcp => {
-                let terminal_width = self.terminal_width();
-                let loaded_servers = self.conversation_state.tool_manager.mcp_load_record.lock().await;
-                let still_loading = self
-                    .conversation_state
-                    .tool_manager
-                    .pending_clients()
-                    .await
-                    .into_iter()
-                    .map(|name| format!(" - {name}\n"))
-                    .collect::<Vec<_>>()
-                    .join("");
-                for (server_name, msg) in loaded_servers.iter() {
-                    let msg = msg
-                        .iter()
-                        .map(|record| match record {
-                            LoadingRecord::Err(content)
-                            | LoadingRecord::Warn(content)
-                            | LoadingRecord::Success(content) => content.clone(),
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n--- tools refreshed ---\n");
-                    queue!(
-                        self.output,
-                        style::Print(server_name),
-                        style::Print("\n"),
-                        style::Print(format!("{}\n", "▔".repeat(terminal_width))),
-                        style::Print(msg),
-                        style::Print("\n")
-                    )?;
-                }
-                if !still_loading.is_empty() {
-                    queue!(
-                        self.output,
-                        style::Print("Still loading:\n"),
-                        style::Print(format!("{}\n", "▔".repeat(terminal_width))),
-                        style::Print(still_loading),
-                        style::Print("\n")
-                    )?;
+                // Tools that need approval notify once they are validated
+                if self.interactive && tool_uses.is_empty() {
+                    self.notifier
+                        .notify(&self.ctx, &mut self.output, Notification::ResponseReady)?;
                 }
-                self.output.flush()?;
-                ChatState::PromptUser {
-                    tool_uses: None,
-                    pending_tool_index: None,
-                    skip_printing_tools: true,
+                if tool_uses.is_empty() {
+                    if self.conversation_state.title().is_none()
+                        && self.conversation_state.user_prompts().len() >= TITLE_AFTER_PROMPTS
+                    {
+                        if let Some(title) = generate_title(&self.conversation_state) {
+                            self.conversation_state.set_title(title);
+                        }
+                    }
+                    self.autosave.turn_completed(&self.ctx, &self.conversation_state).await;
                 }
-            },
-        })
-    }
-
-    async fn tool_use_execute(
-        &mut self,
-        database: &Database,
-        telemetry: &TelemetryThread,
-        mut tool_uses: Vec<QueuedTool>,
-    ) -> Result<ChatState, ChatError> {
-        // Verify tools have permissions.
-        for (index, tool) in tool_uses.iter_mut().enumerate() {
-            // Manually accepted by the user or otherwise verified already.
-            if tool.accepted {
-                continue;
-            }
-
-            // If there is an override, we will use it. Otherwise fall back to Tool's default.
-            let allowed = self.tool_permissions.trust_all
-                || (self.tool_permissions.has(&tool.name) && self.tool_permissions.is_trusted(&tool.name))
-                || !tool.tool.requires_acceptance(&self.ctx);
-
-            if database
-                .settings
-                .get_bool(Setting::ChatEnableNotifications)
-                .unwrap_or(false)
-            {
-                play_notification_bell(!allowed);
-            }
 
-            self.print_tool_descriptions(tool, allowed).await?;
+                if self.interactive {
+                    queue!(self.output, style::ResetColor, style::SetAttribute(Attribute::Reset))?;
+                    execute!(self.output, style::Print("\n"))?;
 
-            if allowed {
-                tool.accepted = true;
-                continue;
-            }
+                    for (i, citation) in &state.citations {
+                        queue!(
+                            self.output,
+                            style::Print("\n"),
+                            style::SetForegroundColor(Color::Blue),
+                            style::Print(format!("[^{i}]: ")),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("{citation}\n")),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    }
 
-            let pending_tool_index = Some(index);
-            if !self.interactive {
-                // Cannot request in non-interactive, so fail.
-                return Err(ChatError::NonInteractiveToolApproval);
+                    if tool_uses.is_empty() && self.exceeds_terminal_height(&buf)? {
+                        if self.autopage {
+                            self.page_pending = Some(buf.clone());
+                        } else {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print("\nUse /page to review this response in your pager.\n"),
+                                style::SetForegroundColor(Color::Reset)
+                            )?;
+                        }
+                    }
+                }
+
+                break;
             }
+        }
 
-            return Ok(ChatState::PromptUser {
-                tool_uses: Some(tool_uses),
-                pending_tool_index,
+        if !tool_uses.is_empty() {
+            Ok(ChatState::ValidateTools(tool_uses))
+        } else {
+            Ok(ChatState::PromptUser {
+                tool_uses: None,
+                pending_tool_index: None,
                 skip_printing_tools: false,
-            });
+            })
         }
+    }
 
-        // Execute the requested tools.
-        let mut tool_results = vec![];
-        let mut image_blocks: Vec<RichImageBlock> = Vec::new();
-
-        for tool in tool_uses {
-            let mut tool_telemetry = self.tool_use_telemetry_events.entry(tool.id.clone());
-            tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_accepted = true);
-
-            let tool_start = std::time::Instant::now();
-            let invoke_result = tool.tool.invoke(&self.ctx, &mut self.output).await;
+    async fn validate_tools(
+        &mut self,
+        telemetry: &TelemetryThread,
+        tool_uses: Vec<AssistantToolUse>,
+    ) -> Result<ChatState, ChatError> {
+        let conv_id = self.conversation_state.conversation_id().to_owned();
+        debug!(?tool_uses, "Validating tool uses");
+        let mut queued_tools: Vec<QueuedTool> = Vec::new();
+        let mut tool_results: Vec<ToolUseResult> = Vec::new();
 
-            if self.interactive && self.spinner.is_some() {
-                queue!(
-                    self.output,
-                    terminal::Clear(terminal::ClearType::CurrentLine),
-                    cursor::MoveToColumn(0),
-                    cursor::Show
-                )?;
-            }
-            execute!(self.output, style::Print("\n"))?;
+        for tool_use in tool_uses {
+            let tool_use_id = tool_use.id.clone();
+            let tool_use_name = tool_use.name.clone();
+            let tool_use_input = tool_use.args.clone();
+            let mut tool_telemetry = ToolUseEventBuilder::new(conv_id.clone(), tool_use.id.clone())
+                .set_tool_use_id(tool_use_id.clone())
+                .set_tool_name(tool_use.name.clone())
+                .utterance_id(self.conversation_state.message_id().map(|s| s.to_string()));
+            match self.conversation_state.tool_manager.get_tool_from_tool_use(tool_use) {
+                Ok(mut tool) => {
+                    // Apply non-Q-generated context to tools
+                    self.contextualize_tool(&mut tool);
 
-            let tool_time = std::time::Instant::now().duration_since(tool_start);
-            if let Tool::Custom(ct) = &tool.tool {
-                tool_telemetry = tool_telemetry.and_modify(|ev| {
-                    ev.custom_tool_call_latency = Some(tool_time.as_secs() as usize);
-                    ev.input_token_size = Some(ct.get_input_token_size());
-                    ev.is_custom_tool = true;
-                });
-            }
-            let tool_time = format!("{}.{}", tool_time.as_secs(), tool_time.subsec_millis());
-            match invoke_result {
-                Ok(result) => {
-                    match result.output {
-                        OutputKind::Text(ref text) => {
-                            debug!("Output is Text: {}", text);
-                        },
-                        OutputKind::Json(ref json) => {
-                            debug!("Output is JSON: {}", json);
+                    let sandboxed = self.check_sandbox(&tool);
+                    match sandboxed.and(tool.validate(&self.ctx).await) {
+                        Ok(()) => {
+                            tool_telemetry.is_valid = Some(true);
+                            queued_tools.push(QueuedTool {
+                                id: tool_use_id.clone(),
+                                name: tool_use_name,
+                                tool,
+                                accepted: false,
+                                approved: false,
+                                input: tool_use_input,
+                            });
                         },
-                        OutputKind::Images(ref image) => {
-                            image_blocks.extend(image.clone());
+                        Err(err) => {
+                            tool_telemetry.is_valid = Some(false);
+                            tool_results.push(ToolUseResult {
+                                tool_use_id: tool_use_id.clone(),
+                                content: vec![ToolUseResultBlock::Text(format!(
+                                    "Failed to validate tool parameters: {err}"
+                                ))],
+                                status: ToolResultStatus::Error,
+                            });
                         },
-                    }
-
-                    debug!("tool result output: {:#?}", result);
-                    execute!(
-                        self.output,
-                        style::Print(CONTINUATION_LINE),
-                        style::Print("\n"),
-                        style::SetForegroundColor(Color::Green),
-                        style::SetAttribute(Attribute::Bold),
-                        style::Print(format!(" ● Completed in {}s", tool_time)),
-                        style::SetForegroundColor(Color::Reset),
-                        style::Print("\n"),
-                    )?;
-
-                    tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_success = Some(true));
-                    if let Tool::Custom(_) = &tool.tool {
-                        tool_telemetry
-                            .and_modify(|ev| ev.output_token_size = Some(TokenCounter::count_tokens(result.as_str())));
-                    }
-                    tool_results.push(ToolUseResult {
-                        tool_use_id: tool.id,
-                        content: vec![result.into()],
-                        status: ToolResultStatus::Success,
-                    });
+                    };
                 },
                 Err(err) => {
-                    error!(?err, "An error occurred processing the tool");
-                    execute!(
-                        self.output,
-                        style::Print(CONTINUATION_LINE),
-                        style::Print("\n"),
-                        style::SetAttribute(Attribute::Bold),
-                        style::SetForegroundColor(Color::Red),
-                        style::Print(format!(" ● Execution failed after {}s:\n", tool_time)),
-                        style::SetAttribute(Attribute::Reset),
-                        style::SetForegroundColor(Color::Red),
-                        style::Print(&err),
-                        style::SetAttribute(Attribute::Reset),
-                        style::Print("\n\n"),
-                    )?;
-
-                    tool_telemetry.and_modify(|ev| ev.is_success = Some(false));
-                    tool_results.push(ToolUseResult {
-                        tool_use_id: tool.id,
-                        content: vec![ToolUseResultBlock::Text(format!(
-                            "An error occurred processing the tool: \n{}",
-                            &err
-                        ))],
-                        status: ToolResultStatus::Error,
-                    });
-                    if let ToolUseStatus::Idle = self.tool_use_status {
-                        self.tool_use_status = ToolUseStatus::RetryInProgress(
-                            self.conversation_state
-                                .message_id()
-                                .map_or("No utterance id found".to_string(), |v| v.to_string()),
-                        );
-                    }
+                    tool_telemetry.is_valid = Some(false);
+                    tool_results.push(err.into());
                 },
             }
+            self.tool_use_telemetry_events.insert(tool_use_id, tool_telemetry);
         }
 
-        if !image_blocks.is_empty() {
-            let images = image_blocks.into_iter().map(|(block, _)| block).collect();
-            self.conversation_state
-                .add_tool_results_with_images(tool_results, images);
-            execute!(
+        // If we have any validation errors, then return them immediately to the model.
+        if !tool_results.is_empty() {
+            debug!(?tool_results, "Error found in the model tools");
+            queue!(
                 self.output,
+                style::SetAttribute(Attribute::Bold),
+                style::Print("Tool validation failed: "),
                 style::SetAttribute(Attribute::Reset),
-                style::SetForegroundColor(Color::Reset),
-                style::Print("\n")
             )?;
-        } else {
+            for tool_result in &tool_results {
+                for block in &tool_result.content {
+                    let content: Option<Cow<'_, str>> = match block {
+                        ToolUseResultBlock::Text(t) => Some(t.as_str().into()),
+                        ToolUseResultBlock::Json(d) => serde_json::to_string(d)
+                            .map_err(|err| error!(?err, "failed to serialize tool result content"))
+                            .map(Into::into)
+                            .ok(),
+                    };
+                    if let Some(content) = content {
+                        queue!(
+                            self.output,
+                            style::Print("\n"),
+                            style::SetForegroundColor(self.theme.error),
+                            style::Print(format!("{}\n", content)),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    }
+                }
+            }
             self.conversation_state.add_tool_results(tool_results);
-        }
-        if self.interactive {
-            execute!(self.output, cursor::Hide)?;
-            execute!(self.output, style::Print("\n"), style::SetAttribute(Attribute::Reset))?;
-            self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
-        }
+            self.send_tool_use_telemetry(telemetry).await;
+            if let ToolUseStatus::Idle = self.tool_use_status {
+                self.tool_use_status = ToolUseStatus::RetryInProgress(
+                    self.conversation_state
+                        .message_id()
+                        .map_or("No utterance id found".to_string(), |v| v.to_string()),
+                );
+            }
 
-        self.send_tool_use_telemetry(telemetry).await;
-        return Ok(ChatState::HandleResponseStream(
-            self.client
+            let response = self
+                .client
                 .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                .await?,
-        ));
+                .await?;
+            return Ok(ChatState::HandleResponseStream(response));
+        }
+
+        Ok(ChatState::ExecuteTools(queued_tools))
     }
 
-    async fn handle_response(
-        &mut self,
-        database: &mut Database,
-        telemetry: &TelemetryThread,
-        response: SendMessageOutput,
-    ) -> Result<ChatState, ChatError> {
-        let request_id = response.request_id().map(|s| s.to_string());
-        let mut buf = String::new();
-        let mut offset = 0;
-        let mut ended = false;
-        let mut parser = ResponseParser::new(response);
-        let mut state = ParseState::new(Some(self.terminal_width()));
+    /// Fails for the files written to outside of the workspace of the sandbox, if any.
+    fn check_sandbox(&self, tool: &Tool) -> Result<()> {
+        match (tool, &self.sandbox) {
+            (Tool::FsWrite(fs_write), Some(sandbox)) => {
+                let cwd = self.ctx.env().current_dir()?;
+                sandbox.check_path(&cwd, sanitize_path_tool_arg(&self.ctx, fs_write.path()))
+            },
+            _ => Ok(()),
+        }
+    }
 
-        let mut tool_uses = Vec::new();
-        let mut tool_name_being_recvd: Option<String> = None;
+    /// Apply program context to tools that Q may not have.
+    // We cannot attach this any other way because Tools are constructed by deserializing
+    // output from Amazon Q.
+    // TODO: Is there a better way?
+    fn contextualize_tool(&self, tool: &mut Tool) {
+        match tool {
+            Tool::ExecuteBash(execute_bash) => {
+                execute_bash.sandbox = self.sandbox.clone();
+                execute_bash.env = self.tool_env.clone();
+                // Only with the user at a terminal to type into it
+                execute_bash.pty = cfg!(unix)
+                    && self.interactive
+                    && !execute_bash.background
+                    && self.shell_pty.unwrap_or(execute_bash.interactive);
+                execute_bash.processes = self.background_processes.clone();
+            },
+            Tool::Command(command_tool) => {
+                command_tool.sandbox = self.sandbox.clone();
+                command_tool.env = self.tool_env.clone();
+            },
+            Tool::ProcessOutput(process_output) => process_output.processes = self.background_processes.clone(),
+            Tool::UseAws(use_aws) => use_aws.scope = self.aws_scope.clone(),
+            Tool::HttpRequest(http_request) => http_request.config = self.http_config.clone(),
+            Tool::GithubIssue(github_issue) => github_issue.config = self.github_issue_config.clone(),
+            Tool::GhIssue(gh_issue) => {
+                gh_issue.set_context(GhIssueContext {
+                    // Ideally we avoid cloning, but this function is not called very often.
+                    // Using references with lifetimes requires a large refactor, and Arc<Mutex<T>>
+                    // seems like overkill and may incur some performance cost anyway.
+                    context_manager: self.conversation_state.context_manager.clone(),
+                    transcript: self.conversation_state.transcript.clone(),
+                    failed_request_ids: self.failed_request_ids.clone(),
+                    tool_permissions: self.tool_permissions.permissions.clone(),
+                    interactive: self.interactive,
+                });
+            },
+            _ => (),
+        };
+    }
 
+    /// Ends the response being received when the user interrupted it, keeping the text `received`
+    /// so far in the conversation.
+    fn interrupt_response(&mut self, database: &mut Database, received: &str) -> Result<ChatState, ChatError> {
         if self.interactive && self.spinner.is_some() {
             drop(self.spinner.take());
+            queue!(
+                self.output,
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                cursor::MoveToColumn(0),
+                cursor::Show
+            )?;
+        }
+        execute!(
+            self.output,
+            style::ResetColor,
+            style::SetAttribute(Attribute::Reset),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("\n\n(Response interrupted)\n"),
+            style::SetForegroundColor(Color::Reset),
+        )?;
+
+        if self.conversation_state.next_user_message().is_some() {
+            let content = match received.trim_end() {
+                "" => RESPONSE_INTERRUPTED_CONTENT.to_string(),
+                received => format!("{received}\n\n{RESPONSE_INTERRUPTED_CONTENT}"),
+            };
+            let message = AssistantMessage::new_response(None, content);
+            self.log_assistant_message(&message);
+            self.conversation_state.push_assistant_message(message, database);
+        }
+
+        Ok(ChatState::PromptUser {
+            tool_uses: None,
+            pending_tool_index: None,
+            skip_printing_tools: false,
+        })
+    }
+
+    async fn print_tool_descriptions(&mut self, tool_use: &QueuedTool, trusted: bool) -> Result<(), ChatError> {
+        queue!(
+            self.output,
+            style::SetForegroundColor(self.theme.tool),
+            style::Print(format!(
+                "🛠️  Using tool: {}{}",
+                tool_use.tool.display_name(),
+                if trusted { " (trusted)".dark_green() } else { "".reset() }
+            )),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+        if let Tool::Custom(ref tool) = tool_use.tool {
             queue!(
                 self.output,
                 style::SetForegroundColor(Color::Reset),
-                terminal::Clear(terminal::ClearType::CurrentLine),
-                cursor::MoveToColumn(0),
-                cursor::Show,
-                cursor::MoveUp(1),
-                terminal::Clear(terminal::ClearType::CurrentLine),
+                style::Print(" from mcp server "),
+                style::SetForegroundColor(self.theme.tool),
+                style::Print(tool.client.get_server_name()),
+                style::SetForegroundColor(Color::Reset),
             )?;
         }
+        queue!(self.output, style::Print("\n"), style::Print(CONTINUATION_LINE))?;
+        queue!(self.output, style::Print("\n"))?;
+        queue!(self.output, style::Print(TOOL_BULLET))?;
+
+        self.output.flush()?;
+
+        tool_use
+            .tool
+            .queue_description(&self.ctx, &mut self.output)
+            .await
+            .map_err(|e| ChatError::Custom(format!("failed to print tool, `{}`: {}", tool_use.name, e).into()))?;
+
+        Ok(())
+    }
 
+    /// Helper function to read user input with a prompt and Ctrl+C handling
+    async fn read_user_input(&mut self, prompt: &str, exit_on_single_ctrl_c: bool) -> Option<String> {
+        // The prompt reads the terminal from here, e.g. to confirm a tool still running
+        if let Err(err) = self.stop_typeahead() {
+            error!(?err, "Failed to stop listening to the terminal");
+        }
+        let mut ctrl_c = false;
         loop {
-            match parser.recv().await {
-                Ok(msg_event) => {
-                    trace!("Consumed: {:?}", msg_event);
-                    match msg_event {
-                        parser::ResponseEvent::ToolUseStart { name } => {
-                            // We need to flush the buffer here, otherwise text will not be
-                            // printed while we are receiving tool use events.
-                            buf.push('\n');
-                            tool_name_being_recvd = Some(name);
-                        },
-                        parser::ResponseEvent::AssistantText(text) => {
-                            buf.push_str(&text);
-                        },
-                        parser::ResponseEvent::ToolUse(tool_use) => {
-                            if self.interactive && self.spinner.is_some() {
-                                drop(self.spinner.take());
-                                queue!(
-                                    self.output,
-                                    terminal::Clear(terminal::ClearType::CurrentLine),
-                                    cursor::MoveToColumn(0),
-                                    cursor::Show
-                                )?;
-                            }
-                            tool_uses.push(tool_use);
-                            tool_name_being_recvd = None;
-                        },
-                        parser::ResponseEvent::EndStream { message } => {
-                            // This log is attempting to help debug instances where users encounter
-                            // the response timeout message.
-                            if message.content() == RESPONSE_TIMEOUT_CONTENT {
-                                error!(?request_id, ?message, "Encountered an unexpected model response");
-                            }
-                            self.conversation_state.push_assistant_message(message, database);
-                            ended = true;
-                        },
+            let initial = self.pending_input.take();
+            let line = self
+                .input_source
+                .read_line_with_initial(Some(prompt), initial.as_deref());
+            // The editor keybindings end the prompt, to give the editor the terminal
+            if let Some(request) = self.input_source.take_editor_request() {
+                self.pending_input = self.run_editor_request(request).await;
+                continue;
+            }
+            match (line, ctrl_c) {
+                (Ok(Some(line)), _) => {
+                    if line.trim().is_empty() {
+                        continue; // Reprompt if the input is empty
                     }
+                    return Some(line);
                 },
-                Err(recv_error) => {
-                    if let Some(request_id) = &recv_error.request_id {
-                        self.failed_request_ids.push(request_id.clone());
-                    };
-
-                    match recv_error.source {
-                        RecvErrorKind::StreamTimeout { source, duration } => {
-                            error!(
-                                recv_error.request_id,
-                                ?source,
-                                "Encountered a stream timeout after waiting for {}s",
-                                duration.as_secs()
-                            );
-                            if self.interactive {
-                                execute!(self.output, cursor::Hide)?;
-                                self.spinner =
-                                    Some(Spinner::new(Spinners::Dots, "Dividing up the work...".to_string()));
-                            }
-                            // For stream timeouts, we'll tell the model to try and split its response into
-                            // smaller chunks.
-                            self.conversation_state.push_assistant_message(
-                                AssistantMessage::new_response(None, RESPONSE_TIMEOUT_CONTENT.to_string()),
-                                database,
-                            );
-                            self.conversation_state
-                                .set_next_user_message(
-                                    "You took too long to respond - try to split up the work into smaller steps."
-                                        .to_string(),
-                                )
-                                .await;
-                            self.send_tool_use_telemetry(telemetry).await;
-                            return Ok(ChatState::HandleResponseStream(
-                                self.client
-                                    .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                                    .await?,
-                            ));
-                        },
-                        RecvErrorKind::UnexpectedToolUseEos {
-                            tool_use_id,
-                            name,
-                            message,
-                            time_elapsed,
-                        } => {
-                            error!(
-                                recv_error.request_id,
-                                tool_use_id, name, "The response stream ended before the entire tool use was received"
-                            );
-                            if self.interactive {
-                                drop(self.spinner.take());
-                                queue!(
-                                    self.output,
-                                    terminal::Clear(terminal::ClearType::CurrentLine),
-                                    cursor::MoveToColumn(0),
-                                    style::SetForegroundColor(Color::Yellow),
-                                    style::SetAttribute(Attribute::Bold),
-                                    style::Print(format!(
-                                        "Warning: received an unexpected error from the model after {:.2}s",
-                                        time_elapsed.as_secs_f64()
-                                    )),
-                                )?;
-                                if let Some(request_id) = recv_error.request_id {
-                                    queue!(
-                                        self.output,
-                                        style::Print(format!("\n         request_id: {}", request_id))
-                                    )?;
-                                }
-                                execute!(self.output, style::Print("\n\n"), style::SetAttribute(Attribute::Reset))?;
-                                self.spinner = Some(Spinner::new(
-                                    Spinners::Dots,
-                                    "Trying to divide up the work...".to_string(),
-                                ));
-                            }
-
-                            self.conversation_state.push_assistant_message(*message, database);
-                            let tool_results = vec![ToolUseResult {
-                                    tool_use_id,
-                                    content: vec![ToolUseResultBlock::Text(
-                                        "The generated tool was too large, try again but this time split up the work between multiple tool uses".to_string(),
-                                    )],
-                                    status: ToolResultStatus::Error,
-                                }];
-                            self.conversation_state.add_tool_results(tool_results);
-                            self.send_tool_use_telemetry(telemetry).await;
-                            return Ok(ChatState::HandleResponseStream(
-                                self.client
-                                    .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                                    .await?,
-                            ));
-                        },
-                        _ => return Err(recv_error.into()),
+                (Ok(None), false) => {
+                    let just_interrupted = self
+                        .interrupted_at
+                        .take()
+                        .is_some_and(|at| at.elapsed() < DOUBLE_CTRL_C_WINDOW);
+                    if exit_on_single_ctrl_c || just_interrupted {
+                        return None;
                     }
+                    execute!(
+                        self.output,
+                        style::Print(format!(
+                            "\n(To exit the CLI, press Ctrl+C or Ctrl+D again or type {})\n\n",
+                            "/quit".green()
+                        ))
+                    )
+                    .unwrap_or_default();
+                    ctrl_c = true;
                 },
+                (Ok(None), true) => return None, // Exit if Ctrl+C was pressed twice
+                (Err(_), _) => return None,
             }
+        }
+    }
 
-            // Fix for the markdown parser copied over from q chat:
-            // this is a hack since otherwise the parser might report Incomplete with useful data
-            // still left in the buffer. I'm not sure how this is intended to be handled.
-            if ended {
-                buf.push('\n');
-            }
-
-            if tool_name_being_recvd.is_none() && !buf.is_empty() && self.interactive && self.spinner.is_some() {
-                drop(self.spinner.take());
-                queue!(
+    /// Carries out what an editor keybinding asked for, returning the buffer to prompt with next.
+    async fn run_editor_request(&mut self, request: EditorRequest) -> Option<String> {
+        let line = request.line().to_string();
+        let text = match self.editor.run_request(&request).await {
+            Ok(Some(text)) => {
+                self.input_source.undo_to(line);
+                text
+            },
+            Ok(None) => line,
+            // Leave the buffer untouched and say where the draft is
+            Err(err) => {
+                execute!(
                     self.output,
-                    terminal::Clear(terminal::ClearType::CurrentLine),
-                    cursor::MoveToColumn(0),
-                    cursor::Show
-                )?;
+                    style::SetForegroundColor(self.theme.error),
+                    style::Print(format!("{err}\n")),
+                    style::SetForegroundColor(Color::Reset)
+                )
+                .unwrap_or_default();
+                line
+            },
+        };
+        Some(text).filter(|text| !text.is_empty())
+    }
+
+    /// Helper function to generate a prompt based on the current context
+    async fn generate_tool_trust_prompt(&mut self) -> String {
+        let Some(template) = self.prompt_template.clone() else {
+            return prompt::generate_prompt(
+                self.conversation_state.current_profile(),
+                self.all_tools_trusted(),
+                self.input_source.edit_mode(),
+                &self.theme,
+            );
+        };
+
+        let uses = |name| prompt::template_uses(&template, name);
+        let tokens_used = if uses("tokens_used") || uses("context_percent") {
+            Some(TokenCount::from(self.conversation_state.calculate_char_count().await).value())
+        } else {
+            None
+        };
+        let cwd = self.ctx.env().current_dir().ok().map(|cwd| {
+            match self
+                .ctx
+                .env()
+                .home()
+                .and_then(|home| cwd.strip_prefix(home).ok().map(|p| p.to_owned()))
+            {
+                Some(relative) if relative.as_os_str().is_empty() => "~".to_string(),
+                Some(relative) => format!("~/{}", relative.display()),
+                None => cwd.display().to_string(),
             }
+        });
+        let variables = prompt::PromptVariables {
+            profile: self.conversation_state.current_profile().map(str::to_owned),
+            warning: self.all_tools_trusted(),
+            branch: if uses("branch") { prompt::git_branch() } else { None },
+            cwd,
+            tokens_used,
+            tokens_max: CONTEXT_WINDOW_SIZE,
+        };
+        prompt::generate_templated_prompt(&template, &variables, self.input_source.edit_mode())
+    }
 
-            // Print the response for normal cases
-            loop {
-                let input = Partial::new(&buf[offset..]);
-                match interpret_markdown(input, &mut self.output, &mut state) {
-                    Ok(parsed) => {
-                        offset += parsed.offset_from(&input);
-                        self.output.flush()?;
-                        state.newline = state.set_newline;
-                        state.set_newline = false;
-                    },
-                    Err(err) => match err.into_inner() {
-                        Some(err) => return Err(ChatError::Custom(err.to_string().into())),
-                        None => break, // Data was incomplete
-                    },
-                }
+    /// Compacts the older messages of the history instead of sending the next request when it is
+    /// estimated to fill more than [Self::auto_compact_threshold] percent of the context window.
+    /// The request is then sent by [Self::compact_history].
+    async fn auto_compact(&mut self) -> Result<Option<ChatState>, ChatError> {
+        if self.auto_compact_threshold == 0 || !self.conversation_state.can_create_summary_request().await {
+            return Ok(None);
+        }
+        let tokens = TokenCount::from(self.conversation_state.calculate_next_request_char_count().await);
+        let percent = tokens.value() * 100 / CONTEXT_WINDOW_SIZE;
+        if percent < self.auto_compact_threshold {
+            return Ok(None);
+        }
 
-                // TODO: We should buffer output based on how much we have to parse, not as a constant
-                // Do not remove unless you are nabochay :)
-                std::thread::sleep(Duration::from_millis(8));
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::Yellow),
+            style::Print(format!(
+                "\nThe context window is {percent}% full, summarizing the older messages...\n"
+            )),
+            style::SetForegroundColor(Color::Reset),
+        )?;
+        Ok(Some(ChatState::CompactHistory {
+            tool_uses: None,
+            pending_tool_index: None,
+            prompt: None,
+            show_summary: false,
+            help: false,
+            strategy: CompactStrategy {
+                keep_last: AUTO_COMPACT_KEEP_LAST.min(self.conversation_state.history().len() - 1),
+                ..Default::default()
+            },
+        }))
+    }
+
+    fn log_transcript(&self, entry: &LogEntry<'_>) {
+        if let Some(log) = &self.transcript_log {
+            log.append(self.conversation_state.conversation_id(), entry);
+        }
+    }
+
+    fn audit(&self, tool: &QueuedTool, decision: Decision, outcome: Option<&Outcome>) {
+        if let Some(log) = &self.audit_log {
+            log.append(&AuditRecord::new(
+                self.conversation_state.conversation_id(),
+                &tool.id,
+                &tool.name,
+                &tool.input,
+                decision,
+                outcome,
+            ));
+        }
+    }
+
+    /// Logs `message` along with the tools it asks to use, see [Self::transcript_log].
+    fn log_assistant_message(&self, message: &AssistantMessage) {
+        self.log_transcript(&LogEntry::Assistant {
+            content: message.content(),
+        });
+        for tool_use in message.tool_uses().unwrap_or_default() {
+            self.log_transcript(&LogEntry::ToolUse {
+                id: &tool_use.id,
+                name: &tool_use.name,
+                args: &tool_use.args,
+            });
+        }
+    }
+
+    async fn generate_status_line(&mut self) -> String {
+        let status = prompt::StatusLine {
+            profile: self.conversation_state.current_profile().map(str::to_owned),
+            trust_all: self.all_tools_trusted(),
+            trusted_tools: self
+                .tool_permissions
+                .permissions
+                .values()
+                .filter(|permission| permission.trusted)
+                .count(),
+            tokens_used: TokenCount::from(self.conversation_state.calculate_char_count().await).value(),
+            tokens_max: CONTEXT_WINDOW_SIZE,
+        };
+        prompt::generate_status_line(&status, self.terminal_width())
+    }
+
+    async fn send_tool_use_telemetry(&mut self, telemetry: &TelemetryThread) {
+        for (_, mut event) in self.tool_use_telemetry_events.drain() {
+            event.user_input_id = match self.tool_use_status {
+                ToolUseStatus::Idle => self.conversation_state.message_id(),
+                ToolUseStatus::RetryInProgress(ref id) => Some(id.as_str()),
             }
+            .map(|v| v.to_string());
 
-            // Set spinner after showing all of the assistant text content so far.
-            if let (Some(_name), true) = (&tool_name_being_recvd, self.interactive) {
-                queue!(self.output, cursor::Hide)?;
-                self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
+            telemetry.send_tool_use_suggested(event).ok();
+        }
+    }
+
+    /// Prints the lines of the conversation matching `pattern` along with the messages they are
+    /// in, see [find::find_in_conversation].
+    fn print_find_matches(&mut self, pattern: &Regex) -> Result<(), ChatError> {
+        let history = self.conversation_state.history();
+        let matches = find::find_in_conversation(
+            history
+                .iter()
+                .map(|(user, assistant)| (user.prompt(), assistant.content())),
+            pattern,
+        );
+        // Code blocks can only be copied from the last response
+        let last_response = history
+            .iter()
+            .rposition(|(_, assistant)| !assistant.content().trim().is_empty());
+
+        if matches.is_empty() {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(format!("\nNo matches for {}.\n\n", pattern)),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+            return Ok(());
+        }
+
+        execute!(self.output, style::Print("\n"))?;
+        for (n, found) in matches.iter().enumerate() {
+            let mut location = match found.author {
+                find::Author::User => format!("message {}", found.message),
+                find::Author::Assistant => format!("response to message {}", found.message),
+            };
+            if let (Some(block), true) = (found.code_block, Some(found.history_index) == last_response) {
+                location.push_str(&format!(", code block {block}"));
             }
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Green),
+                style::Print(format!("{:>3}. ", n + 1)),
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!("{location}\n")),
+                style::SetForegroundColor(Color::Reset),
+            )?;
 
-            if ended {
-                if let Some(message_id) = self.conversation_state.message_id() {
-                    telemetry
-                        .send_chat_added_message(
-                            self.conversation_state.conversation_id().to_owned(),
-                            message_id.to_owned(),
-                            self.conversation_state.context_message_length(),
-                        )
-                        .ok();
+            for (i, line) in found.context.iter().enumerate() {
+                let line = truncate_safe(line, 120);
+                queue!(self.output, style::Print("     "))?;
+                if i != found.line {
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!("{line}\n")),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    continue;
                 }
 
-                if self.interactive
-                    && database
-                        .settings
-                        .get_bool(Setting::ChatEnableNotifications)
-                        .unwrap_or(false)
-                {
-                    // For final responses (no tools suggested), always play the bell
-                    play_notification_bell(tool_uses.is_empty());
+                let mut end = 0;
+                for matched in pattern.find_iter(line) {
+                    queue!(
+                        self.output,
+                        style::Print(&line[end..matched.start()]),
+                        style::SetForegroundColor(Color::Yellow),
+                        style::SetAttribute(Attribute::Bold),
+                        style::Print(matched.as_str()),
+                        style::SetAttribute(Attribute::Reset),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    end = matched.end();
                 }
+                queue!(self.output, style::Print(format!("{}\n", &line[end..])))?;
+            }
+        }
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print(format!(
+                "\n{} match(es). Use /edit <n> to edit a message, or /copy <n> to copy a code block of the last response.\n\n",
+                matches.len()
+            )),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+        Ok(())
+    }
 
-                if self.interactive {
-                    queue!(self.output, style::ResetColor, style::SetAttribute(Attribute::Reset))?;
-                    execute!(self.output, style::Print("\n"))?;
+    /// Asks for confirmation before sending a prompt that is estimated to take more than
+    /// [Self::large_prompt_threshold] tokens along with the context, e.g. after an accidental
+    /// paste.
+    async fn confirm_large_prompt(&mut self, prompt: &str) -> Result<bool, ChatError> {
+        let context_length = self.conversation_state.context_message_length().unwrap_or_default();
+        let prompt_tokens = TokenCounter::count_tokens(prompt);
+        let total_tokens = TokenCount::from(CharCount::from(prompt.len() + context_length));
+        if self.large_prompt_threshold == 0 || *total_tokens <= self.large_prompt_threshold {
+            return Ok(true);
+        }
 
-                    for (i, citation) in &state.citations {
-                        queue!(
-                            self.output,
-                            style::Print("\n"),
-                            style::SetForegroundColor(Color::Blue),
-                            style::Print(format!("[^{i}]: ")),
-                            style::SetForegroundColor(Color::DarkGrey),
-                            style::Print(format!("{citation}\n")),
-                            style::SetForegroundColor(Color::Reset)
-                        )?;
-                    }
-                }
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::Yellow),
+            style::Print(format!(
+                "\nThis prompt is estimated at ~{} tokens ({} with context), more than the {} tokens allowed by chat.largePromptThreshold.\n",
+                prompt_tokens, total_tokens, self.large_prompt_threshold
+            )),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("Send it anyway? ["),
+            style::SetForegroundColor(Color::Green),
+            style::Print("y"),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("/"),
+            style::SetForegroundColor(Color::Green),
+            style::Print("n"),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("]:\n\n"),
+            style::SetForegroundColor(Color::Reset),
+        )?;
 
-                break;
-            }
+        let confirmed = self
+            .read_user_input("> ".yellow().to_string().as_str(), true)
+            .await
+            .is_some_and(|input| ["y", "Y"].contains(&input.trim()));
+        if !confirmed {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print("\nThe prompt was not sent.\n\n"),
+                style::SetForegroundColor(Color::Reset)
+            )?;
         }
+        Ok(confirmed)
+    }
 
-        if !tool_uses.is_empty() {
-            Ok(ChatState::ValidateTools(tool_uses))
+    /// Deletes the conversations saved more than `older_than` ago, see `chat.sessions.retention`.
+    async fn prune_expired_sessions(&mut self, older_than: Duration) -> Result<(), ChatError> {
+        let retention = Retention {
+            older_than: Some(older_than),
+            max_size: None,
+        };
+        let pruned = match prune(&self.ctx, retention, false).await {
+            Ok(pruned) => pruned,
+            Err(err) => {
+                warn!(?err, "Failed to delete the expired conversations");
+                return Ok(());
+            },
+        };
+        if self.interactive && !pruned.is_empty() {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(
+                    "Deleted {} older than chat.sessions.retention\n\n",
+                    pruned.summary()
+                )),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Offers to resume the conversation autosaved by a session that didn't exit cleanly, e.g.
+    /// because the terminal was closed. The autosave is removed either way.
+    async fn offer_autosave_resume(&mut self) -> Result<(), ChatError> {
+        let Some(unclean) = self.autosave.find_unclean_exit(&self.ctx).await else {
+            return Ok(());
+        };
+
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::Yellow),
+            style::Print("A previous session didn't exit cleanly. Resume the conversation it autosaved? "),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("["),
+            style::SetForegroundColor(Color::Green),
+            style::Print("y"),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("/"),
+            style::SetForegroundColor(Color::Green),
+            style::Print("n"),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("]:\n\n"),
+            style::SetForegroundColor(Color::Reset),
+        )?;
+        let user_input = self
+            .read_user_input("> ".yellow().to_string().as_str(), true)
+            .await
+            .unwrap_or_default();
+        let _ = self.ctx.fs().remove_file(&unclean.path).await;
+
+        if ["y", "Y"].contains(&user_input.trim()) {
+            let messages = unclean.conversation.history().len();
+            self.restore_conversation(unclean.conversation).await;
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Green),
+                style::Print(format!("\n✔ Resumed the previous session ({messages} messages)\n\n")),
+                style::SetForegroundColor(Color::Reset)
+            )?;
         } else {
-            Ok(ChatState::PromptUser {
-                tool_uses: None,
-                pending_tool_index: None,
-                skip_printing_tools: false,
-            })
+            execute!(self.output, style::Print("\n"))?;
         }
+        Ok(())
     }
 
-    async fn validate_tools(
-        &mut self,
-        telemetry: &TelemetryThread,
-        tool_uses: Vec<AssistantToolUse>,
-    ) -> Result<ChatState, ChatError> {
-        let conv_id = self.conversation_state.conversation_id().to_owned();
-        debug!(?tool_uses, "Validating tool uses");
-        let mut queued_tools: Vec<QueuedTool> = Vec::new();
-        let mut tool_results: Vec<ToolUseResult> = Vec::new();
+    /// Replaces the conversation with one that was saved, keeping the tools of this session.
+    async fn restore_conversation(&mut self, mut state: ConversationState) {
+        state
+            .reload_serialized_state(Arc::clone(&self.ctx), Some(self.output.clone()))
+            .await;
+        state.tool_manager = std::mem::take(&mut self.conversation_state.tool_manager);
+        state.redactor = std::mem::take(&mut self.conversation_state.redactor);
+        state.default_system_prompt = self.conversation_state.default_system_prompt.take();
+        state.index_retriever = self.conversation_state.index_retriever.take();
+        state.disabled_tools = std::mem::take(&mut self.conversation_state.disabled_tools);
+        state.update_state(true).await;
+        state.enforce_tool_use_history_invariants();
+        self.conversation_state = state;
+    }
 
-        for tool_use in tool_uses {
-            let tool_use_id = tool_use.id.clone();
-            let tool_use_name = tool_use.name.clone();
-            let mut tool_telemetry = ToolUseEventBuilder::new(conv_id.clone(), tool_use.id.clone())
-                .set_tool_use_id(tool_use_id.clone())
-                .set_tool_name(tool_use.name.clone())
-                .utterance_id(self.conversation_state.message_id().map(|s| s.to_string()));
-            match self.conversation_state.tool_manager.get_tool_from_tool_use(tool_use) {
-                Ok(mut tool) => {
-                    // Apply non-Q-generated context to tools
-                    self.contextualize_tool(&mut tool);
+    /// Saves the conversation as `name`. Another conversation already saved as `name` is only
+    /// replaced with `force`.
+    async fn save_session(&self, name: &str, force: bool) -> Result<()> {
+        let dir = directories::chat_sessions_dir()?;
+        let replaces_other = self.session_name.as_deref() != Some(name);
+        if replaces_other && !force && self.ctx.fs().exists(session_path(&dir, name)) {
+            bail!("a conversation is already saved as {name}. To overwrite it, use -f or --force");
+        }
+        save_session(&self.ctx, &dir, name, &self.conversation_state).await
+    }
 
-                    match tool.validate(&self.ctx).await {
-                        Ok(()) => {
-                            tool_telemetry.is_valid = Some(true);
-                            queued_tools.push(QueuedTool {
-                                id: tool_use_id.clone(),
-                                name: tool_use_name,
-                                tool,
-                                accepted: false,
-                            });
-                        },
-                        Err(err) => {
-                            tool_telemetry.is_valid = Some(false);
-                            tool_results.push(ToolUseResult {
-                                tool_use_id: tool_use_id.clone(),
-                                content: vec![ToolUseResultBlock::Text(format!(
-                                    "Failed to validate tool parameters: {err}"
-                                ))],
-                                status: ToolResultStatus::Error,
-                            });
-                        },
-                    };
-                },
-                Err(err) => {
-                    tool_telemetry.is_valid = Some(false);
-                    tool_results.push(err.into());
+    /// Syncs the saved conversations with `chat.sync.url` when it is set. Failing to only
+    /// warns, the conversation being saved here either way.
+    async fn sync_sessions(&mut self) -> Result<(), ChatError> {
+        let Some(backend) = self.sync_backend.clone() else {
+            return Ok(());
+        };
+        let result = async {
+            let remote = SyncRemote::connect(backend).await?;
+            let dir = directories::chat_sessions_dir()?;
+            sync::sync(&self.ctx, &remote, &dir, self.sync_history_path.as_deref()).await
+        }
+        .await;
+        let message = match result {
+            Ok(report) if report.failed.is_empty() => {
+                format!(
+                    "Synced with chat.sync.url: {} pushed, {} pulled\n\n",
+                    report.pushed.len(),
+                    report.pulled.len()
+                )
+            },
+            Ok(report) => format!(
+                "Failed to sync {} with chat.sync.url, see {CLI_BINARY_NAME} chat sessions sync\n\n",
+                report
+                    .failed
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(err) => format!("Failed to sync with chat.sync.url: {err}\n\n"),
+        };
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print(message),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+        Ok(())
+    }
+
+    /// Opens the system prompt of the current profile in the editor, see `/system edit`. Returns
+    /// what changed.
+    async fn edit_system_prompt(&mut self) -> Result<String> {
+        let Some(context_manager) = &self.conversation_state.context_manager else {
+            bail!("Context management is not available.");
+        };
+        let profile = context_manager.current_profile.clone();
+        let path = context_manager.system_prompt_path()?;
+        let existed = self.ctx.fs().exists(&path);
+        // A new prompt starts from chat.systemPrompt
+        let default = self.conversation_state.default_system_prompt.as_deref();
+        let initial_text = default.map(|text| format!("{text}\n")).unwrap_or_default();
+
+        let saved = self
+            .editor
+            .edit_file(&self.ctx.fs().chroot_path(&path), &initial_text)
+            .await
+            .map_err(|e| eyre::eyre!("{e}"))?;
+        let text = self.ctx.fs().read_to_string(&path).await.unwrap_or_default();
+        if !saved {
+            if !existed {
+                self.ctx.fs().remove_file(&path).await.ok();
+            }
+            return Ok(format!("The system prompt of the profile {profile} is unchanged."));
+        }
+        if text.trim().is_empty() {
+            self.ctx.fs().remove_file(&path).await?;
+            return Ok(match default {
+                Some(_) => {
+                    format!("Removed the system prompt of the profile {profile}, chat.systemPrompt is used instead.")
                 },
+                None => format!("Removed the system prompt of the profile {profile}."),
+            });
+        }
+        Ok(format!(
+            "Saved the system prompt of the profile {profile}, it's sent with every message and kept by /clear."
+        ))
+    }
+
+    /// Turns the tools named `tool_names` on or off for the session, see `/tools enable` and
+    /// `/tools disable`.
+    fn set_tools_enabled(
+        &mut self,
+        tool_names: HashSet<String>,
+        existing_tools: &HashSet<String>,
+        enable: bool,
+    ) -> Result<(), ChatError> {
+        let (valid_tools, invalid_tools): (Vec<String>, Vec<String>) = tool_names
+            .into_iter()
+            .partition(|tool_name| existing_tools.contains(tool_name));
+
+        if !invalid_tools.is_empty() {
+            queue!(
+                self.output,
+                style::SetForegroundColor(self.theme.error),
+                style::Print(format!(
+                    "\nCannot {} '{}', {}.",
+                    if enable { "enable" } else { "disable" },
+                    invalid_tools.join("', '"),
+                    if invalid_tools.len() > 1 {
+                        "they do not exist"
+                    } else {
+                        "it does not exist"
+                    }
+                )),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        if !valid_tools.is_empty() {
+            for tool_name in &valid_tools {
+                self.conversation_state.set_tool_enabled(tool_name, enable);
             }
-            self.tool_use_telemetry_events.insert(tool_use_id, tool_telemetry);
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Green),
+                if valid_tools.len() > 1 {
+                    style::Print(format!("\nTools '{}' are ", valid_tools.join("', '")))
+                } else {
+                    style::Print(format!("\nTool '{}' is ", valid_tools[0]))
+                },
+                style::Print(match enable {
+                    true => "available to the model again.",
+                    false => "hidden from the model for the rest of the session.",
+                }),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Adds the pages referenced with `@https://...` in `prompt` to the context. Those that can't
+    /// be fetched are left out with a warning, the prompt being sent either way.
+    async fn add_inline_urls(&mut self, prompt: &str) -> Result<(), ChatError> {
+        let Some(context_manager) = &mut self.conversation_state.context_manager else {
+            return Ok(());
+        };
+        for url in url_context::inline_urls(prompt) {
+            let message = match url_context::fetch_page(&self.ctx, url, &self.url_allowed_domains).await {
+                Ok(text) => {
+                    let message = format!(
+                        "🔗 Added {url} (~{} tkns) to the context\n",
+                        TokenCounter::count_tokens(&text)
+                    );
+                    context_manager.attach(url.to_string(), text);
+                    message
+                },
+                Err(err) => format!("Failed to add {url} to the context: {err}\n"),
+            };
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(message),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Adds the definitions referenced with `@path#name` in `prompt` to the context, along with
+    /// the imports of their files, rather than the whole files.
+    async fn add_inline_symbols(&mut self, prompt: &str) -> Result<(), ChatError> {
+        let Some(context_manager) = &mut self.conversation_state.context_manager else {
+            return Ok(());
+        };
+        let cwd = self.ctx.env().current_dir()?;
+        for (path, name) in symbol_context::symbol_references(prompt) {
+            let message = match symbol_context::load_symbol(&self.ctx, &cwd, path, name).await {
+                Ok(symbol) => {
+                    let message = format!(
+                        "🔎 Added {path}#{name} (lines {}-{}, ~{} tkns) to the context\n",
+                        symbol.start_line,
+                        symbol.end_line,
+                        TokenCounter::count_tokens(&symbol.text)
+                    );
+                    context_manager.attach(format!("{path}#{name}"), symbol.text);
+                    message
+                },
+                Err(err) => format!("Failed to add {path}#{name} to the context: {err}\n"),
+            };
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(message),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// A name for saving the conversation the first time, made from its title unless another
+    /// conversation is already saved under it.
+    fn new_session_name(&self) -> String {
+        let title = self
+            .conversation_state
+            .title()
+            .map(str::to_string)
+            .or_else(|| generate_title(&self.conversation_state));
+        title
+            .as_deref()
+            .and_then(name_from_title)
+            .filter(|name| {
+                directories::chat_sessions_dir().is_ok_and(|dir| !self.ctx.fs().exists(session_path(&dir, name)))
+            })
+            .unwrap_or_else(default_session_name)
+    }
+
+    async fn print_saved_sessions(&mut self) -> Result<(), ChatError> {
+        let sessions = match directories::chat_sessions_dir() {
+            Ok(dir) => list_sessions(&self.ctx, &dir).await?,
+            Err(_) => Vec::new(),
+        };
+        if sessions.is_empty() {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print("\nNo conversations are saved yet. Save this one with /save <name>\n\n"),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+            return Ok(());
         }
 
-        // If we have any validation errors, then return them immediately to the model.
-        if !tool_results.is_empty() {
-            debug!(?tool_results, "Error found in the model tools");
+        queue!(self.output, style::Print("\nSaved conversations:\n"))?;
+        for session in sessions {
+            let saved = session
+                .modified
+                .map(|modified| format!("  saved {}", format_local_time(modified.into())))
+                .unwrap_or_default();
             queue!(
                 self.output,
-                style::SetAttribute(Attribute::Bold),
-                style::Print("Tool validation failed: "),
-                style::SetAttribute(Attribute::Reset),
+                style::Print(format!("  {}", session.name)),
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!("{saved}\n")),
+                style::SetForegroundColor(Color::Reset)
             )?;
-            for tool_result in &tool_results {
-                for block in &tool_result.content {
-                    let content: Option<Cow<'_, str>> = match block {
-                        ToolUseResultBlock::Text(t) => Some(t.as_str().into()),
-                        ToolUseResultBlock::Json(d) => serde_json::to_string(d)
-                            .map_err(|err| error!(?err, "failed to serialize tool result content"))
-                            .map(Into::into)
-                            .ok(),
-                    };
-                    if let Some(content) = content {
-                        queue!(
-                            self.output,
-                            style::Print("\n"),
-                            style::SetForegroundColor(Color::Red),
-                            style::Print(format!("{}\n", content)),
-                            style::SetForegroundColor(Color::Reset),
-                        )?;
-                    }
-                }
-            }
-            self.conversation_state.add_tool_results(tool_results);
-            self.send_tool_use_telemetry(telemetry).await;
-            if let ToolUseStatus::Idle = self.tool_use_status {
-                self.tool_use_status = ToolUseStatus::RetryInProgress(
-                    self.conversation_state
-                        .message_id()
-                        .map_or("No utterance id found".to_string(), |v| v.to_string()),
-                );
-            }
+        }
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("\nLoad one with /load <name>\n\n"),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+        Ok(())
+    }
 
-            let response = self
-                .client
-                .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                .await?;
-            return Ok(ChatState::HandleResponseStream(response));
+    /// Lists the messages of the user by number, marking the pinned ones, for commands taking such
+    /// a number like `/{command} <n>`.
+    fn print_user_prompts(&mut self, command: &str, hint: &str) -> Result<(), ChatError> {
+        let prompts = self.conversation_state.user_prompts();
+        if prompts.is_empty() {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(format!("\nNo messages to {command} yet.\n\n")),
+                style::SetForegroundColor(Color::Reset)
+            )?;
+            return Ok(());
         }
 
-        Ok(ChatState::ExecuteTools(queued_tools))
+        execute!(self.output, style::Print("\n"))?;
+        for (n, (_, prompt)) in prompts.iter().enumerate() {
+            let pinned = match self.conversation_state.pins().iter().any(|pin| pin == prompt) {
+                true => "📌 ",
+                false => "",
+            };
+            let first_line = prompt.lines().next().unwrap_or_default();
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Green),
+                style::Print(format!("{:>3}. ", n + 1)),
+                style::SetForegroundColor(Color::Reset),
+                style::Print(pinned),
+                style::Print(truncate_safe(first_line, 100)),
+                style::Print("\n")
+            )?;
+        }
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print(format!("\n{hint}\n\n")),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+        Ok(())
     }
 
-    /// Apply program context to tools that Q may not have.
-    // We cannot attach this any other way because Tools are constructed by deserializing
-    // output from Amazon Q.
-    // TODO: Is there a better way?
-    fn contextualize_tool(&self, tool: &mut Tool) {
-        #[allow(clippy::single_match)]
-        match tool {
-            Tool::GhIssue(gh_issue) => {
-                gh_issue.set_context(GhIssueContext {
-                    // Ideally we avoid cloning, but this function is not called very often.
-                    // Using references with lifetimes requires a large refactor, and Arc<Mutex<T>>
-                    // seems like overkill and may incur some performance cost anyway.
-                    context_manager: self.conversation_state.context_manager.clone(),
-                    transcript: self.conversation_state.transcript.clone(),
-                    failed_request_ids: self.failed_request_ids.clone(),
-                    tool_permissions: self.tool_permissions.permissions.clone(),
-                    interactive: self.interactive,
-                });
-            },
-            _ => (),
+    fn print_branches(&mut self) -> Result<(), ChatError> {
+        let turns = |state: &ConversationState| match state.history().len() {
+            1 => "  1 turn".to_string(),
+            n => format!("  {n} turns"),
         };
-    }
-
-    async fn print_tool_descriptions(&mut self, tool_use: &QueuedTool, trusted: bool) -> Result<(), ChatError> {
         queue!(
             self.output,
-            style::SetForegroundColor(Color::Magenta),
-            style::Print(format!(
-                "🛠️  Using tool: {}{}",
-                tool_use.tool.display_name(),
-                if trusted { " (trusted)".dark_green() } else { "".reset() }
-            )),
+            style::Print("\nBranches:\n"),
+            style::SetForegroundColor(Color::Green),
+            style::Print(format!("* {}", self.branches.current())),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print(format!("{}\n", turns(&self.conversation_state))),
             style::SetForegroundColor(Color::Reset)
         )?;
-        if let Tool::Custom(ref tool) = tool_use.tool {
+        for branch in self.branches.others() {
             queue!(
                 self.output,
-                style::SetForegroundColor(Color::Reset),
-                style::Print(" from mcp server "),
-                style::SetForegroundColor(Color::Magenta),
-                style::Print(tool.client.get_server_name()),
-                style::SetForegroundColor(Color::Reset),
+                style::Print(format!("  {}", branch.name)),
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!("{}\n", turns(&branch.state))),
+                style::SetForegroundColor(Color::Reset)
             )?;
         }
-        queue!(self.output, style::Print("\n"), style::Print(CONTINUATION_LINE))?;
-        queue!(self.output, style::Print("\n"))?;
-        queue!(self.output, style::Print(TOOL_BULLET))?;
-
-        self.output.flush()?;
-
-        tool_use
-            .tool
-            .queue_description(&self.ctx, &mut self.output)
-            .await
-            .map_err(|e| ChatError::Custom(format!("failed to print tool, `{}`: {}", tool_use.name, e).into()))?;
-
+        let hint = match self.branches.others().is_empty() {
+            true => {
+                "\nTry another approach without losing this one with /fork [name], or start another conversation with /new [name]\n\n"
+            },
+            false => "\nSwitch to one with /switch <name>\n\n",
+        };
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print(hint),
+            style::SetForegroundColor(Color::Reset)
+        )?;
         Ok(())
     }
 
-    /// Helper function to read user input with a prompt and Ctrl+C handling
-    fn read_user_input(&mut self, prompt: &str, exit_on_single_ctrl_c: bool) -> Option<String> {
-        let mut ctrl_c = false;
-        loop {
-            match (self.input_source.read_line(Some(prompt)), ctrl_c) {
-                (Ok(Some(line)), _) => {
-                    if line.trim().is_empty() {
-                        continue; // Reprompt if the input is empty
-                    }
-                    return Some(line);
-                },
-                (Ok(None), false) => {
-                    if exit_on_single_ctrl_c {
-                        return None;
-                    }
-                    execute!(
-                        self.output,
-                        style::Print(format!(
-                            "\n(To exit the CLI, press Ctrl+C or Ctrl+D again or type {})\n\n",
-                            "/quit".green()
-                        ))
-                    )
-                    .unwrap_or_default();
-                    ctrl_c = true;
-                },
-                (Ok(None), true) => return None, // Exit if Ctrl+C was pressed twice
-                (Err(_), _) => return None,
-            }
-        }
+    /// The text of the last response that has any.
+    fn last_response(&self) -> Option<String> {
+        self.conversation_state
+            .history()
+            .iter()
+            .rev()
+            .map(|(_, assistant)| assistant.content())
+            .find(|content| !content.trim().is_empty())
+            .map(str::to_owned)
     }
 
-    /// Helper function to generate a prompt based on the current context
-    fn generate_tool_trust_prompt(&self) -> String {
-        prompt::generate_prompt(self.conversation_state.current_profile(), self.all_tools_trusted())
+    /// Whether `response` takes more lines than the terminal has once rendered.
+    fn exceeds_terminal_height(&self, response: &str) -> Result<bool, ChatError> {
+        let Ok((_, rows)) = terminal::size() else {
+            return Ok(false);
+        };
+        let rendered = pager::render_markdown(response, self.terminal_width(), self.theme.assistant)?;
+        Ok(pager::line_count(&rendered) > usize::from(rows))
     }
 
-    async fn send_tool_use_telemetry(&mut self, telemetry: &TelemetryThread) {
-        for (_, mut event) in self.tool_use_telemetry_events.drain() {
-            event.user_input_id = match self.tool_use_status {
-                ToolUseStatus::Idle => self.conversation_state.message_id(),
-                ToolUseStatus::RetryInProgress(ref id) => Some(id.as_str()),
-            }
-            .map(|v| v.to_string());
-
-            telemetry.send_tool_use_suggested(event).ok();
+    /// Shows `response` in the pager, reporting any failure to start it.
+    fn page_response(&mut self, response: &str) -> Result<(), ChatError> {
+        let rendered = pager::render_markdown(response, self.terminal_width(), self.theme.assistant)?;
+        if let Err(err) = pager::page(&rendered) {
+            execute!(
+                self.output,
+                style::SetForegroundColor(self.theme.error),
+                style::Print(format!("\nError: {}\n\n", err)),
+                style::SetForegroundColor(Color::Reset)
+            )?;
         }
+        Ok(())
     }
 
     fn terminal_width(&self) -> usize {
@@ -3675,8 +7759,14 @@ fn print_hook_section(output: &mut impl Write, hooks: &HashMap<String, Hook>, tr
     let section = match trigger {
         HookTrigger::ConversationStart => "On Session Start",
         HookTrigger::PerPrompt => "Per User Message",
+        HookTrigger::PreToolUse => "Before Tool Use",
+        HookTrigger::PostToolUse => "After Tool Use",
     };
     let hooks: Vec<(&String, &Hook)> = hooks.iter().filter(|(_, h)| h.trigger == trigger).collect();
+    // Few use them, the sections of the tool use hooks are left out when empty
+    if hooks.is_empty() && trigger.is_tool_use() {
+        return Ok(());
+    }
 
     queue!(
         output,
@@ -3694,6 +7784,10 @@ fn print_hook_section(output: &mut impl Write, hooks: &HashMap<String, Hook>, tr
         )?;
     } else {
         for (name, hook) in hooks {
+            let name = match hook.tools.is_empty() {
+                true => name.to_string(),
+                false => format!("{name} ({})", hook.tools.join(", ")),
+            };
             if hook.disabled {
                 queue!(
                     output,
@@ -3709,6 +7803,51 @@ fn print_hook_section(output: &mut impl Write, hooks: &HashMap<String, Hook>, tr
     Ok(())
 }
 
+/// Adds the output of the tool use hooks with `append_output` to `result`, for the model to see.
+fn append_hook_outputs<'a>(
+    result: Option<&mut ToolUseResult>,
+    hooks: impl IntoIterator<Item = &'a (Hook, ToolHookOutput)>,
+) {
+    let Some(result) = result else {
+        return;
+    };
+    for (hook, run) in hooks {
+        if hook.append_output && !run.output.trim().is_empty() {
+            result.content.push(ToolUseResultBlock::Text(format!(
+                "Output of the user's {} hook '{}'{}:\n{}",
+                hook.trigger,
+                hook.name,
+                if run.success { "" } else { ", which failed" },
+                run.output
+            )));
+        }
+    }
+}
+
+/// Prints the files matched by context rules that are left out, see [SkippedFile].
+fn print_skipped_context_files(output: &mut impl Write, skipped: &[SkippedFile]) -> std::io::Result<()> {
+    if skipped.is_empty() {
+        return Ok(());
+    }
+    queue!(
+        output,
+        style::SetForegroundColor(Color::DarkYellow),
+        style::Print(format!(
+            "{} matched file{} left out, too large or not text:\n",
+            skipped.len(),
+            if skipped.len() == 1 { "" } else { "s" }
+        )),
+        style::SetForegroundColor(Color::DarkGrey),
+    )?;
+    for file in skipped.iter().take(10) {
+        queue!(output, style::Print(format!("  {file}\n")))?;
+    }
+    if skipped.len() > 10 {
+        queue!(output, style::Print(format!("  ({} more files)\n", skipped.len() - 10)))?;
+    }
+    execute!(output, style::SetForegroundColor(Color::Reset), style::Print("\n"))
+}
+
 /// Testing helper
 fn split_tool_use_event(value: &Map<String, serde_json::Value>) -> Vec<ChatResponseStream> {
     let tool_use_id = value.get("tool_use_id").unwrap().as_str().unwrap().to_string();
@@ -3743,37 +7882,98 @@ fn split_tool_use_event(value: &Map<String, serde_json::Value>) -> Vec<ChatRespo
     ]
 }
 
-/// Testing helper
-fn create_stream(model_responses: serde_json::Value) -> StreamingClient {
-    let mut mock = Vec::new();
-    for response in model_responses.as_array().unwrap() {
-        let mut stream = Vec::new();
-        for event in response.as_array().unwrap() {
-            match event {
-                serde_json::Value::String(assistant_text) => {
-                    stream.push(ChatResponseStream::AssistantResponseEvent {
-                        content: assistant_text.to_string(),
-                    });
-                },
-                serde_json::Value::Object(tool_use) => {
-                    stream.append(&mut split_tool_use_event(tool_use));
-                },
-                other => panic!("Unexpected value: {:?}", other),
-            }
-        }
-        mock.push(stream);
+/// Testing helper
+fn create_stream(model_responses: serde_json::Value) -> StreamingClient {
+    let mut mock = Vec::new();
+    for response in model_responses.as_array().unwrap() {
+        let mut stream = Vec::new();
+        for event in response.as_array().unwrap() {
+            match event {
+                serde_json::Value::String(assistant_text) => {
+                    stream.push(ChatResponseStream::AssistantResponseEvent {
+                        content: assistant_text.to_string(),
+                    });
+                },
+                serde_json::Value::Object(tool_use) => {
+                    stream.append(&mut split_tool_use_event(tool_use));
+                },
+                other => panic!("Unexpected value: {:?}", other),
+            }
+        }
+        mock.push(stream);
+    }
+    StreamingClient::mock(mock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Env;
+
+    #[tokio::test]
+    async fn test_flow() {
+        // let _ = tracing_subscriber::fmt::try_init();
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Sure, I'll create a file for you",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file.txt",
+                    }
+                }
+            ],
+            [
+                "Hope that looks good to you!",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![
+                "create a new file".to_string(),
+                "y".to_string(),
+                "exit".to_string(),
+            ]),
+            ChatFlags {
+                interactive: true,
+                ..Default::default()
+            },
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            None,
+        )
+        .await
+        .unwrap()
+        .try_chat(&mut database, &telemetry)
+        .await
+        .unwrap();
+
+        assert_eq!(ctx.fs().read_to_string("/file.txt").await.unwrap(), "Hello, world!\n");
     }
-    StreamingClient::mock(mock)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::platform::Env;
 
     #[tokio::test]
-    async fn test_flow() {
-        // let _ = tracing_subscriber::fmt::try_init();
+    async fn test_flow_dry_run() {
         let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
         let test_client = create_stream(serde_json::json!([
             [
@@ -3806,19 +8006,20 @@ mod tests {
             "fake_conv_id",
             SharedWriter::stdout(),
             None,
-            InputSource::new_mock(vec![
-                "create a new file".to_string(),
-                "y".to_string(),
-                "exit".to_string(),
-            ]),
-            true,
-            false,
+            InputSource::new_mock(vec!["create a new file".to_string(), "exit".to_string()]),
+            ChatFlags {
+                interactive: true,
+                dry_run: true,
+                ..Default::default()
+            },
+            None,
             test_client,
             || Some(80),
             tool_manager,
             None,
             tool_config,
             ToolPermissions::new(0),
+            None,
         )
         .await
         .unwrap()
@@ -3826,7 +8027,113 @@ mod tests {
         .await
         .unwrap();
 
-        assert_eq!(ctx.fs().read_to_string("/file.txt").await.unwrap(), "Hello, world!\n");
+        assert!(!ctx.fs().exists("/file.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_flow_read_only() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Sure, I'll create a file for you",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file.txt",
+                    }
+                }
+            ],
+            [
+                "I can't write files in read-only mode.",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        // Refused without asking, even with all tools trusted
+        let mut tool_permissions = ToolPermissions::new(0);
+        tool_permissions.trust_all = true;
+        ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec!["create a new file".to_string(), "exit".to_string()]),
+            ChatFlags {
+                interactive: true,
+                read_only: true,
+                ..Default::default()
+            },
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            tool_config,
+            tool_permissions,
+            None,
+        )
+        .await
+        .unwrap()
+        .try_chat(&mut database, &telemetry)
+        .await
+        .unwrap();
+
+        assert!(!ctx.fs().exists("/file.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_is_refused_as_read_only() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut database = Database::new().await.unwrap();
+
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        let mut chat_context = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![]),
+            ChatFlags {
+                interactive: true,
+                read_only: true,
+                ..Default::default()
+            },
+            None,
+            create_stream(serde_json::json!([])),
+            || Some(80),
+            ToolManager::default(),
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let execute_bash = |command: &str| QueuedTool {
+            id: "1".to_string(),
+            name: "execute_bash".to_string(),
+            accepted: false,
+            approved: false,
+            tool: Tool::ExecuteBash(serde_json::from_value(serde_json::json!({ "command": command })).unwrap()),
+            input: serde_json::json!({ "command": command }),
+        };
+        assert!(chat_context.is_refused_as_read_only(&execute_bash("rm -rf target")));
+        assert!(!chat_context.is_refused_as_read_only(&execute_bash("ls -la")));
+        chat_context.read_only = false;
+        assert!(!chat_context.is_refused_as_read_only(&execute_bash("rm -rf target")));
     }
 
     #[tokio::test]
@@ -3957,14 +8264,18 @@ mod tests {
                 "n".to_string(),             // cancel
                 "exit".to_string(),
             ]),
-            true,
-            false,
+            ChatFlags {
+                interactive: true,
+                ..Default::default()
+            },
+            None,
             test_client,
             || Some(80),
             tool_manager,
             None,
             tool_config,
             ToolPermissions::new(0),
+            None,
         )
         .await
         .unwrap()
@@ -4056,14 +8367,18 @@ mod tests {
                 "y".to_string(),
                 "exit".to_string(),
             ]),
-            true,
-            false,
+            ChatFlags {
+                interactive: true,
+                ..Default::default()
+            },
+            None,
             test_client,
             || Some(80),
             tool_manager,
             None,
             tool_config,
             ToolPermissions::new(0),
+            None,
         )
         .await
         .unwrap()
@@ -4077,6 +8392,184 @@ mod tests {
         assert_eq!(ctx.fs().read_to_string("/file4.txt").await.unwrap(), "Hello, world!\n");
     }
 
+    #[tokio::test]
+    async fn test_flow_read_after_write() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/file1.txt", "Hello, world!").await.unwrap();
+        ctx.fs().write("/file2.txt", "Hello, world!").await.unwrap();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Sure, I'll change the file and read it back",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_read",
+                    "args": {
+                        "mode": "Line",
+                        "path": "/file1.txt",
+                    }
+                },
+                {
+                    "tool_use_id": "2",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Goodbye, world!",
+                        "path": "/file2.txt",
+                    }
+                },
+                {
+                    "tool_use_id": "3",
+                    "name": "fs_read",
+                    "args": {
+                        "mode": "Line",
+                        "path": "/file2.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        let mut tool_permissions = ToolPermissions::new(0);
+        tool_permissions.trust_all = true;
+        let mut chat_context = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec!["change the file".to_string(), "exit".to_string()]),
+            ChatFlags {
+                interactive: true,
+                ..Default::default()
+            },
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            tool_config,
+            tool_permissions,
+            None,
+        )
+        .await
+        .unwrap();
+        chat_context.try_chat(&mut database, &telemetry).await.unwrap();
+
+        // The read asked for after the write runs after it, not along with the read before it
+        let read = chat_context
+            .conversation_state
+            .history()
+            .iter()
+            .filter_map(|(user, _)| user.tool_use_results())
+            .flatten()
+            .find(|result| result.tool_use_id == "3")
+            .unwrap();
+        assert!(matches!(&read.content[..], [ToolUseResultBlock::Text(text)] if text.contains("Goodbye, world!")));
+    }
+
+    #[tokio::test]
+    async fn test_flow_cached_read_after_write() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/file.txt", "Hello, world!").await.unwrap();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Sure, I'll read the file",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_read",
+                    "args": {
+                        "mode": "Line",
+                        "path": "/file.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+            [
+                "Sure, I'll change the file and read it again",
+                {
+                    "tool_use_id": "2",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Goodbye, world!",
+                        "path": "/file.txt",
+                    }
+                },
+                {
+                    "tool_use_id": "3",
+                    "name": "fs_read",
+                    "args": {
+                        "mode": "Line",
+                        "path": "/file.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        let mut tool_permissions = ToolPermissions::new(0);
+        tool_permissions.trust_all = true;
+        let mut chat_context = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![
+                "read the file".to_string(),
+                "change the file".to_string(),
+                "exit".to_string(),
+            ]),
+            ChatFlags {
+                interactive: true,
+                ..Default::default()
+            },
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            tool_config,
+            tool_permissions,
+            None,
+        )
+        .await
+        .unwrap();
+        chat_context.try_chat(&mut database, &telemetry).await.unwrap();
+
+        // Looked up in the cache once the write before it has run, rather than taken as unchanged
+        let read = chat_context
+            .conversation_state
+            .history()
+            .iter()
+            .filter_map(|(user, _)| user.tool_use_results())
+            .flatten()
+            .find(|result| result.tool_use_id == "3")
+            .unwrap();
+        assert!(matches!(&read.content[..], [ToolUseResultBlock::Text(text)] if text.contains("Goodbye, world!")));
+        assert_eq!(chat_context.tool_cache.hits(), 0);
+    }
+
     #[tokio::test]
     async fn test_flow_tools_trust_all() {
         // let _ = tracing_subscriber::fmt::try_init();
@@ -4134,14 +8627,18 @@ mod tests {
                 "create a new file".to_string(),
                 "exit".to_string(),
             ]),
-            true,
-            false,
+            ChatFlags {
+                interactive: true,
+                ..Default::default()
+            },
+            None,
             test_client,
             || Some(80),
             tool_manager,
             None,
             tool_config,
             ToolPermissions::new(0),
+            None,
         )
         .await
         .unwrap()
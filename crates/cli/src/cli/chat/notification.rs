@@ -0,0 +1,257 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicU8,
+    Ordering,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+
+/// Terminals known to handle the bell character well.
+const BELL_COMPATIBLE_TERMS: &[&str] = &[
+    "xterm",
+    "screen",
+    "tmux",
+    "rxvt",
+    "linux",
+    "konsole",
+    "gnome",
+    "alacritty",
+    "iterm2",
+];
+
+/// How long a turn has to take before notifying when away, unless `chat.notificationThreshold` is
+/// set.
+const DEFAULT_THRESHOLD: Duration = Duration::from_secs(10);
+
+/// Why the user is notified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notification {
+    /// The response is complete and Q is waiting for the next prompt.
+    ResponseReady,
+    /// A tool needs to be approved before the turn can continue.
+    ApprovalNeeded,
+}
+
+impl Notification {
+    fn message(self) -> &'static str {
+        match self {
+            Notification::ResponseReady => "Amazon Q: response ready",
+            Notification::ApprovalNeeded => "Amazon Q: approval needed",
+        }
+    }
+}
+
+/// Whether the terminal window is focused, as last reported by the terminal.
+///
+/// Focus is only reported while an [EscapeListener](super::interrupt::EscapeListener) is
+/// listening, and terminals don't report the current focus when reporting starts. The terminal is
+/// taken to be focused when the user submits input, see [Notifier::start_turn], until a report
+/// says otherwise.
+#[derive(Debug, Default)]
+pub struct Focus(AtomicU8);
+
+impl Focus {
+    // Unknown until set, as 0
+    const FOCUSED: u8 = 1;
+    const UNFOCUSED: u8 = 2;
+
+    pub fn set(&self, focused: bool) {
+        let focus = if focused { Self::FOCUSED } else { Self::UNFOCUSED };
+        self.0.store(focus, Ordering::Relaxed);
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.0.load(Ordering::Relaxed) == Self::FOCUSED
+    }
+
+    /// Updates the focus from the focus reports (`\x1b[I` and `\x1b[O`) in `input`.
+    pub fn update_from(&self, input: &[u8]) {
+        for report in input.windows(3) {
+            match report {
+                b"\x1b[I" => self.set(true),
+                b"\x1b[O" => self.set(false),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Notifies the user when a response is ready or a tool needs approval.
+///
+/// With `chat.enableNotifications`, the bell rings every time. With `chat.notifyWhenAway`, a
+/// desktop notification is sent instead when a turn that took longer than
+/// `chat.notificationThreshold` seconds finishes while the terminal is in the background, so that
+/// the user can switch away during long tool runs, and nothing is sent otherwise.
+///
+/// A turn starts whenever the user submits input, so a chain of tool uses is timed as a whole.
+#[derive(Debug)]
+pub struct Notifier {
+    always: bool,
+    when_away: bool,
+    threshold: Duration,
+    turn_started: Instant,
+    focus: Arc<Focus>,
+}
+
+impl Notifier {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            always: settings.get_bool(Setting::ChatEnableNotifications).unwrap_or(false),
+            when_away: settings.get_bool(Setting::ChatNotifyWhenAway).unwrap_or(false),
+            threshold: settings
+                .get_int(Setting::ChatNotificationThreshold)
+                .and_then(|secs| u64::try_from(secs).ok())
+                .map_or(DEFAULT_THRESHOLD, Duration::from_secs),
+            turn_started: Instant::now(),
+            focus: Arc::new(Focus::default()),
+        }
+    }
+
+    /// The focus state to update while the terminal reports focus changes.
+    pub fn focus(&self) -> Arc<Focus> {
+        Arc::clone(&self.focus)
+    }
+
+    /// Starts timing a turn, the user having just submitted input in the terminal.
+    pub fn start_turn(&mut self) {
+        self.turn_started = Instant::now();
+        self.focus.set(true);
+    }
+
+    pub fn notify(&self, ctx: &Context, output: &mut impl Write, notification: Notification) -> std::io::Result<()> {
+        let away = self.turn_started.elapsed() >= self.threshold && !self.focus.is_focused();
+        let sequence = match (self.when_away && away, self.always) {
+            (true, _) => notification_sequence(ctx, notification),
+            (false, true) => bell(ctx),
+            (false, false) => None,
+        };
+
+        match sequence {
+            Some(sequence) => {
+                output.write_all(sequence.as_bytes())?;
+                output.flush()
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// A desktop notification (OSC 9) on terminals known to show one, and the bell on other
+/// terminals that handle it.
+fn notification_sequence(ctx: &Context, notification: Notification) -> Option<String> {
+    let env = ctx.env();
+    let supports_osc9 = env.get_os("TMUX").is_none()
+        && (env.get_os("WT_SESSION").is_some()
+            || env
+                .get("TERM_PROGRAM")
+                .is_ok_and(|program| ["iTerm.app", "WezTerm", "ghostty"].contains(&program.as_str())));
+    if supports_osc9 {
+        return Some(format!("\x1b]9;{}\x07", notification.message()));
+    }
+    bell(ctx)
+}
+
+/// The bell on terminals that handle it.
+fn bell(ctx: &Context) -> Option<String> {
+    ctx.env()
+        .get("TERM")
+        .is_ok_and(|term| {
+            BELL_COMPATIBLE_TERMS
+                .iter()
+                .any(|compatible| term.starts_with(compatible))
+        })
+        .then(|| "\x07".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Env;
+
+    #[test]
+    fn test_focus_update_from() {
+        let focus = Focus::default();
+        assert!(!focus.is_focused());
+
+        focus.update_from(b"ab\x1b[Icd");
+        assert!(focus.is_focused());
+        focus.update_from(b"\x1b[I\x1b[O");
+        assert!(!focus.is_focused());
+        focus.update_from(b"\x1b[A");
+        assert!(!focus.is_focused());
+    }
+
+    fn context(vars: &[(&str, &str)]) -> Arc<Context> {
+        Context::builder().with_env(Env::from_slice(vars)).build_fake()
+    }
+
+    #[test]
+    fn test_notification_sequence() {
+        let ctx = context(&[("TERM_PROGRAM", "WezTerm")]);
+        assert_eq!(
+            notification_sequence(&ctx, Notification::ApprovalNeeded).as_deref(),
+            Some("\x1b]9;Amazon Q: approval needed\x07")
+        );
+
+        // Desktop notifications aren't passed through tmux, and other terminals only get the bell
+        let ctx = context(&[
+            ("TERM_PROGRAM", "WezTerm"),
+            ("TERM", "tmux-256color"),
+            ("TMUX", "/tmp/tmux-1000/default,1,0"),
+        ]);
+        assert_eq!(
+            notification_sequence(&ctx, Notification::ResponseReady).as_deref(),
+            Some("\x07")
+        );
+        let ctx = context(&[("TERM", "dumb")]);
+        assert_eq!(notification_sequence(&ctx, Notification::ResponseReady), None);
+    }
+
+    fn notified(ctx: &Context, notifier: &Notifier) -> String {
+        let mut output = Vec::new();
+        notifier.notify(ctx, &mut output, Notification::ResponseReady).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_notifier() {
+        let ctx = context(&[("TERM", "xterm-256color"), ("TERM_PROGRAM", "WezTerm")]);
+        let mut settings = Settings::new().await.unwrap();
+        assert_eq!(notified(&ctx, &Notifier::from_settings(&settings)), "");
+
+        // The bell rings every time
+        settings.set(Setting::ChatEnableNotifications, true).await.unwrap();
+        let mut notifier = Notifier::from_settings(&settings);
+        notifier.start_turn();
+        assert_eq!(notified(&ctx, &notifier), "\x07");
+
+        // Away, only long turns are notified, while the terminal isn't focused
+        settings.set(Setting::ChatEnableNotifications, false).await.unwrap();
+        settings.set(Setting::ChatNotifyWhenAway, true).await.unwrap();
+        settings.set(Setting::ChatNotificationThreshold, 0).await.unwrap();
+        let mut notifier = Notifier::from_settings(&settings);
+        notifier.start_turn();
+        assert_eq!(notified(&ctx, &notifier), "");
+        notifier.focus().set(false);
+        assert_eq!(notified(&ctx, &notifier), "\x1b]9;Amazon Q: response ready\x07");
+
+        settings.set(Setting::ChatNotificationThreshold, 60).await.unwrap();
+        let notifier = Notifier::from_settings(&settings);
+        notifier.focus().set(false);
+        assert_eq!(notified(&ctx, &notifier), "");
+
+        // With both, the bell rings when the turn isn't notified otherwise
+        settings.set(Setting::ChatEnableNotifications, true).await.unwrap();
+        let notifier = Notifier::from_settings(&settings);
+        assert_eq!(notified(&ctx, &notifier), "\x07");
+    }
+}
@@ -0,0 +1,125 @@
+use std::env;
+use std::io::Write;
+use std::process::{
+    Command as ProcessCommand,
+    Stdio,
+};
+
+use crossterm::style::Color;
+use winnow::Partial;
+use winnow::stream::Offset;
+
+use super::ChatError;
+use super::parse::{
+    ParseState,
+    interpret_markdown,
+};
+
+#[cfg(unix)]
+const DEFAULT_PAGER: &str = "less";
+#[cfg(windows)]
+const DEFAULT_PAGER: &str = "more";
+
+/// Options for `less` when `$LESS` isn't set: keep the ANSI styling, quit right away if the
+/// content fits on one screen and leave it on screen after quitting.
+const DEFAULT_LESS_OPTIONS: &str = "FRX";
+
+/// Renders `markdown` with ANSI styling the same way responses are printed in the chat.
+pub fn render_markdown(markdown: &str, terminal_width: usize, text_color: Color) -> Result<Vec<u8>, ChatError> {
+    // The parser may report incomplete data for the last line without a trailing newline
+    let markdown = format!("{markdown}\n");
+    let mut rendered = Vec::new();
+    let mut state = ParseState::new(Some(terminal_width));
+    state.text_color = text_color;
+
+    let mut offset = 0;
+    loop {
+        let input = Partial::new(&markdown[offset..]);
+        match interpret_markdown(input, &mut rendered, &mut state) {
+            Ok(parsed) => {
+                offset += parsed.offset_from(&input);
+                state.newline = state.set_newline;
+                state.set_newline = false;
+            },
+            Err(err) => match err.into_inner() {
+                Some(err) => return Err(ChatError::Custom(err.to_string().into())),
+                None => break,
+            },
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// The number of lines `rendered` takes on screen. Lines are already wrapped to the terminal
+/// width when rendering.
+pub fn line_count(rendered: &[u8]) -> usize {
+    rendered.split(|b| *b == b'\n').filter(|line| !line.is_empty()).count()
+}
+
+/// Shows `content` in `$PAGER`, waiting until the pager exits.
+pub fn page(content: &[u8]) -> Result<(), ChatError> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_owned());
+    let mut parts =
+        shlex::split(&pager).ok_or_else(|| ChatError::Custom("Failed to parse the pager command".into()))?;
+    if parts.is_empty() {
+        return Err(ChatError::Custom("The pager command is empty".into()));
+    }
+
+    let mut cmd = ProcessCommand::new(parts.remove(0));
+    cmd.args(parts).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        cmd.env("LESS", DEFAULT_LESS_OPTIONS);
+    }
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|err| ChatError::Custom(format!("Failed to start the pager '{pager}': {err}").into()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager closes its input when it quits early, which isn't an error
+        if let Err(err) = stdin.write_all(content) {
+            if err.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(err.into());
+            }
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let rendered = render_markdown(
+            "# Title\n\nSome **bold** text\n\n```rust\nfn main() {}\n```",
+            80,
+            Color::Reset,
+        )
+        .unwrap();
+        let text = String::from_utf8(strip_ansi_escapes::strip(&rendered)).unwrap();
+        assert!(text.contains("Title"));
+        assert!(text.contains("Some bold text"));
+        assert!(text.contains("fn main() {}"));
+        assert!(!text.contains("**"));
+
+        // Styling is kept
+        assert_ne!(rendered, strip_ansi_escapes::strip(&rendered));
+    }
+
+    #[test]
+    fn test_line_count() {
+        assert_eq!(line_count(b""), 0);
+        assert_eq!(line_count(b"one\ntwo\n\nthree\n"), 3);
+        assert_eq!(line_count(b"one"), 1);
+
+        // Long lines are wrapped when rendering
+        let rendered = render_markdown(&"word ".repeat(40), 40, Color::Reset).unwrap();
+        assert!(line_count(&rendered) >= 5);
+    }
+}
@@ -90,6 +90,8 @@ pub struct ParseState {
     pub set_newline: bool,
     pub newline: bool,
     pub citations: Vec<(String, String)>,
+    /// The color of plain text, see [super::theme::Theme::assistant].
+    pub text_color: Color,
 }
 
 impl ParseState {
@@ -104,6 +106,7 @@ impl ParseState {
             set_newline: false,
             newline: true,
             citations: vec![],
+            text_color: Color::Reset,
         }
     }
 }
@@ -286,7 +289,7 @@ fn code<'a, 'b>(
         queue_newline_or_advance(&mut o, state, out.width())?;
         queue(&mut o, style::SetForegroundColor(Color::Green))?;
         queue(&mut o, style::Print(out))?;
-        queue(&mut o, style::ResetColor)
+        queue_text_color(&mut o, state)
     }
 }
 
@@ -395,7 +398,7 @@ fn citation<'a, 'b>(
         queue_newline_or_advance(&mut o, state, num.width() + 1)?;
         queue(&mut o, style::SetForegroundColor(URL_TEXT_COLOR))?;
         queue(&mut o, style::Print(format!("[^{num}]")))?;
-        queue(&mut o, style::ResetColor)
+        queue_text_color(&mut o, state)
     }
 }
 
@@ -434,7 +437,7 @@ fn url<'a, 'b>(
         queue(&mut o, style::SetForegroundColor(URL_LINK_COLOR))?;
         state.column += link.width();
         queue(&mut o, style::Print(link))?;
-        queue(&mut o, style::ResetColor)
+        queue_text_color(&mut o, state)
     }
 }
 
@@ -494,6 +497,9 @@ fn line_ending<'a, 'b>(
 
         queue(&mut o, style::ResetColor)?;
         queue(&mut o, style::SetAttribute(style::Attribute::Reset))?;
+        if state.text_color != Color::Reset {
+            queue(&mut o, style::SetForegroundColor(state.text_color))?;
+        }
         queue(&mut o, style::Print("\n"))
     }
 }
@@ -534,6 +540,15 @@ fn queue_newline_or_advance<'a, 'b>(
     Ok(())
 }
 
+/// Goes back to the color of plain text after styled text such as code or links.
+fn queue_text_color<'a>(o: &mut impl Write, state: &ParseState) -> Result<(), ErrMode<Error<'a>>> {
+    queue(o, style::ResetColor)?;
+    match state.text_color {
+        Color::Reset => Ok(()),
+        color => queue(o, style::SetForegroundColor(color)),
+    }
+}
+
 fn queue<'a>(o: &mut impl Write, command: impl Command) -> Result<(), ErrMode<Error<'a>>> {
     use crossterm::QueueableCommand;
     o.queue(command).map_err(|err| ErrMode::Cut(Error::Stdio(err)))?;
@@ -573,7 +588,7 @@ fn codeblock_end<'a, 'b>(
     move |i| {
         "```".parse_next(i)?;
         state.in_codeblock = false;
-        queue(&mut o, style::ResetColor)
+        queue_text_color(&mut o, state)
     }
 }
 
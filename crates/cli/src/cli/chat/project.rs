@@ -0,0 +1,264 @@
+use std::path::Path;
+
+use super::consts::PROJECT_CONTEXT_MAX_CHARS;
+use super::context::Gitignore;
+use super::util::truncate_safe;
+use crate::platform::Context;
+
+/// The manifests summarized in the overview, in the order they are listed.
+const MANIFESTS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "build.gradle.kts",
+    "Gemfile",
+    "composer.json",
+    "CMakeLists.txt",
+    "Makefile",
+];
+
+/// The first of these found is excerpted in the overview.
+const READMES: &[&str] = &["README.md", "README", "README.rst", "README.txt", "readme.md"];
+
+/// In characters, how much of the README and of manifests that aren't summarized is excerpted.
+const EXCERPT_MAX_CHARS: usize = 1500;
+
+/// How many entries of the directory layout are listed.
+const LAYOUT_MAX_ENTRIES: usize = 60;
+
+/// How many dependency names of each kind are listed.
+const DEPENDENCIES_MAX: usize = 30;
+
+/// An overview of the project in `dir` for new conversations to know about, see
+/// `chat.autoContext`: its manifests, the start of its README and its directory layout. [None]
+/// when `dir` has neither of these nor is a git repository.
+pub async fn project_overview(ctx: &Context, dir: &Path) -> Option<String> {
+    let mut sections = Vec::new();
+    for name in MANIFESTS {
+        if let Ok(contents) = ctx.fs().read_to_string(dir.join(name)).await {
+            sections.push(format!("{name}:\n{}", summarize_manifest(name, &contents)));
+        }
+    }
+    for name in READMES {
+        if let Ok(contents) = ctx.fs().read_to_string(dir.join(name)).await {
+            sections.push(format!("{name} (start):\n{}", excerpt(&contents)));
+            break;
+        }
+    }
+    if sections.is_empty() && !ctx.fs().exists(dir.join(".git")) {
+        return None;
+    }
+    if let Some(layout) = layout(ctx, dir).await.filter(|layout| !layout.is_empty()) {
+        sections.push(format!("Directory layout:\n{layout}"));
+    }
+
+    let mut overview = format!("Project in {}\n\n{}", dir.display(), sections.join("\n\n"));
+    if overview.len() > PROJECT_CONTEXT_MAX_CHARS {
+        overview = format!("{}\n[...]", truncate_safe(&overview, PROJECT_CONTEXT_MAX_CHARS));
+    }
+    Some(overview)
+}
+
+/// The name, description and dependencies of Cargo and npm manifests. The start of other
+/// manifests, or of those that can't be parsed.
+fn summarize_manifest(name: &str, contents: &str) -> String {
+    let summary = match name {
+        "Cargo.toml" => summarize_cargo(contents),
+        "package.json" => summarize_package_json(contents),
+        _ => None,
+    };
+    summary.unwrap_or_else(|| excerpt(contents))
+}
+
+fn summarize_cargo(contents: &str) -> Option<String> {
+    let manifest = contents.parse::<toml::Table>().ok()?;
+    let mut lines = Vec::new();
+    if let Some(package) = manifest.get("package").and_then(toml::Value::as_table) {
+        for key in ["name", "version", "description", "edition"] {
+            if let Some(value) = package.get(key).and_then(toml::Value::as_str) {
+                lines.push(format!("{key}: {value}"));
+            }
+        }
+    }
+    if let Some(workspace) = manifest.get("workspace").and_then(toml::Value::as_table) {
+        if let Some(members) = workspace.get("members").and_then(toml::Value::as_array) {
+            let members = members.iter().filter_map(toml::Value::as_str).collect::<Vec<_>>();
+            lines.push(format!("workspace members: {}", members.join(", ")));
+        }
+    }
+    for key in ["dependencies", "dev-dependencies"] {
+        let dependencies = manifest
+            .get(key)
+            .or_else(|| manifest.get("workspace").and_then(|workspace| workspace.get(key)))
+            .and_then(toml::Value::as_table);
+        if let Some(dependencies) = dependencies {
+            lines.push(format!("{key}: {}", names(dependencies.keys())));
+        }
+    }
+    Some(lines.join("\n"))
+}
+
+fn summarize_package_json(contents: &str) -> Option<String> {
+    let manifest = serde_json::from_str::<serde_json::Value>(contents).ok()?;
+    let mut lines = Vec::new();
+    for key in ["name", "version", "description"] {
+        if let Some(value) = manifest[key].as_str() {
+            lines.push(format!("{key}: {value}"));
+        }
+    }
+    for key in ["scripts", "dependencies", "devDependencies"] {
+        if let Some(entries) = manifest[key].as_object() {
+            lines.push(format!("{key}: {}", names(entries.keys())));
+        }
+    }
+    Some(lines.join("\n"))
+}
+
+/// The first names, and how many more there are.
+fn names<'a>(names: impl ExactSizeIterator<Item = &'a String>) -> String {
+    let count = names.len();
+    let mut listed = names
+        .take(DEPENDENCIES_MAX)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if count > DEPENDENCIES_MAX {
+        listed.push_str(&format!(" and {} more", count - DEPENDENCIES_MAX));
+    }
+    listed
+}
+
+fn excerpt(contents: &str) -> String {
+    let contents = contents.trim();
+    match contents.len() > EXCERPT_MAX_CHARS {
+        true => format!("{}\n[...]", truncate_safe(contents, EXCERPT_MAX_CHARS)),
+        false => contents.to_string(),
+    }
+}
+
+/// The entries of `dir` and of its directories, leaving out hidden ones and those ignored by
+/// `.gitignore`.
+async fn layout(ctx: &Context, dir: &Path) -> Option<String> {
+    // Entries are listed with their chrooted paths in tests, so the ignore rules need to be too
+    let dir = &ctx.fs().chroot_path(dir);
+    let mut gitignore = Gitignore::default();
+    gitignore.add_file(ctx, dir).await;
+
+    let mut lines = Vec::new();
+    let mut more = 0;
+    for (path, is_dir) in list_dir(ctx, dir, &gitignore).await? {
+        let name = path.file_name()?.to_string_lossy().to_string();
+        if lines.len() >= LAYOUT_MAX_ENTRIES {
+            more += 1;
+            continue;
+        }
+        if !is_dir {
+            lines.push(name);
+            continue;
+        }
+        lines.push(format!("{name}/"));
+        gitignore.add_file(ctx, &path).await;
+        for (child, is_dir) in list_dir(ctx, &path, &gitignore).await.unwrap_or_default() {
+            let child = child.file_name()?.to_string_lossy().to_string();
+            match lines.len() < LAYOUT_MAX_ENTRIES {
+                true => lines.push(format!("  {child}{}", if is_dir { "/" } else { "" })),
+                false => more += 1,
+            }
+        }
+    }
+    if more > 0 {
+        lines.push(format!("({more} more entries)"));
+    }
+    Some(lines.join("\n"))
+}
+
+/// The entries of `dir` that aren't hidden nor ignored, sorted, along with whether each is a
+/// directory.
+async fn list_dir(ctx: &Context, dir: &Path, gitignore: &Gitignore) -> Option<Vec<(std::path::PathBuf, bool)>> {
+    let mut read_dir = ctx.fs().read_dir(dir).await.ok()?;
+    let mut entries = Vec::new();
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let path = entry.path();
+        let is_dir = entry.file_type().await.is_ok_and(|file_type| file_type.is_dir());
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && !gitignore.is_ignored(&path, is_dir) {
+            entries.push((path, is_dir));
+        }
+    }
+    entries.sort();
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_manifest() {
+        let cargo = r#"
+[package]
+name = "widget"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+serde = "1"
+tokio = { version = "1", features = ["full"] }
+
+[dev-dependencies]
+insta = "1"
+"#;
+        assert_eq!(
+            summarize_manifest("Cargo.toml", cargo),
+            "name: widget\nversion: 0.1.0\nedition: 2021\ndependencies: serde, tokio\ndev-dependencies: insta"
+        );
+
+        let package = r#"{
+            "name": "web",
+            "description": "The web client",
+            "scripts": { "build": "vite build", "test": "vitest" },
+            "dependencies": { "react": "^18" }
+        }"#;
+        assert_eq!(
+            summarize_manifest("package.json", package),
+            "name: web\ndescription: The web client\nscripts: build, test\ndependencies: react"
+        );
+
+        assert_eq!(
+            summarize_manifest("go.mod", "module example.com/app\n\ngo 1.22\n"),
+            "module example.com/app\n\ngo 1.22"
+        );
+        assert_eq!(summarize_manifest("Cargo.toml", "not = [toml"), "not = [toml");
+    }
+
+    #[tokio::test]
+    async fn test_project_overview() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let dir = ctx.env().current_dir().unwrap().join("project");
+        assert_eq!(project_overview(&ctx, &dir).await, None);
+
+        ctx.fs().create_dir_all(dir.join("src/bin")).await.unwrap();
+        ctx.fs().create_dir_all(dir.join("target/debug")).await.unwrap();
+        ctx.fs().write(dir.join(".gitignore"), "target/\n").await.unwrap();
+        ctx.fs()
+            .write(dir.join("Cargo.toml"), "[package]\nname = \"widget\"\n")
+            .await
+            .unwrap();
+        ctx.fs()
+            .write(dir.join("README.md"), "# Widget\n\nMakes widgets.\n")
+            .await
+            .unwrap();
+        ctx.fs().write(dir.join("src/main.rs"), "fn main() {}").await.unwrap();
+
+        assert_eq!(
+            project_overview(&ctx, &dir).await.unwrap(),
+            format!(
+                "Project in {}\n\nCargo.toml:\nname: widget\n\nREADME.md (start):\n# Widget\n\nMakes widgets.\n\nDirectory layout:\nCargo.toml\nREADME.md\nsrc/\n  bin/\n  main.rs",
+                dir.display()
+            )
+        );
+    }
+}
@@ -1,10 +1,19 @@
 use std::borrow::Cow;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
 
 use crossterm::style::Stylize;
 use eyre::Result;
 use rustyline::completion::{
     Completer,
     FilenameCompleter,
+    Pair,
     extract_word,
 };
 use rustyline::error::ReadlineError;
@@ -12,6 +21,11 @@ use rustyline::highlight::{
     CmdKind,
     Highlighter,
 };
+use rustyline::hint::{
+    Hint,
+    Hinter,
+    HistoryHinter,
+};
 use rustyline::history::DefaultHistory;
 use rustyline::validate::{
     ValidationContext,
@@ -22,32 +36,87 @@ use rustyline::{
     Cmd,
     Completer,
     CompletionType,
+    ConditionalEventHandler,
     Config,
     Context,
     EditMode,
     Editor,
+    Event,
+    EventContext,
     EventHandler,
     Helper,
-    Hinter,
     KeyCode,
     KeyEvent,
     Modifiers,
+    Movement,
+    RepeatCount,
 };
 use winnow::stream::AsChar;
 
+use super::snippet::{
+    SnippetExpander,
+    Snippets,
+};
+use super::theme::Theme;
 use crate::database::Database;
-use crate::database::settings::Setting;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// How many prompts are kept in the history file by default, see `chat.history.size`.
+const DEFAULT_HISTORY_SIZE: usize = 1000;
 
 pub const COMMANDS: &[&str] = &[
     "/clear",
+    "/clear-screen",
     "/help",
     "/editor",
+    "/edit",
+    "/quote",
+    "/copy",
+    "/paste",
+    "/paste --as-context",
+    "/page",
+    "/expand",
+    "/expand --attach",
+    "/cache",
+    "/cache clear",
+    "/ps",
+    "/logs",
+    "/kill",
+    "/find",
+    "/undo",
+    "/checkpoint list",
+    "/revert",
+    "/revert all",
+    "/retry",
+    "/retry --fresh",
+    "/set-mode",
+    "/set-mode vi",
+    "/set-mode emacs",
+    "/multiline",
+    "/multiline on",
+    "/multiline off",
+    "/dry-run",
+    "/dry-run on",
+    "/dry-run off",
+    "/readonly",
+    "/readonly on",
+    "/readonly off",
+    "/draft",
+    "/draft help",
+    "/draft restore",
     "/issue",
     // "/acceptall", /// Functional, but deprecated in favor of /tools trustall
     "/quit",
     "/tools",
+    "/tools help",
+    "/tools schema",
     "/tools trust",
     "/tools untrust",
+    "/tools enable",
+    "/tools disable",
     "/tools trustall",
     "/tools reset",
     "/profile",
@@ -57,15 +126,29 @@ pub const COMMANDS: &[&str] = &[
     "/profile delete",
     "/profile rename",
     "/profile set",
+    "/mcp",
+    "/prompts",
+    "/prompts help",
+    "/prompts list",
+    "/prompts get",
+    "/context",
     "/context help",
     "/context show",
     "/context show --expand",
     "/context add",
     "/context add --global",
+    "/context add --diff",
+    "/context add-url",
+    "/context add-cmd",
     "/context rm",
     "/context rm --global",
+    "/context rm --diff",
     "/context clear",
     "/context clear --global",
+    "/context save",
+    "/context reset",
+    "/context diff",
+    "/context hooks",
     "/context hooks help",
     "/context hooks add",
     "/context hooks rm",
@@ -73,33 +156,348 @@ pub const COMMANDS: &[&str] = &[
     "/context hooks disable",
     "/context hooks enable-all",
     "/context hooks disable-all",
+    "/rules",
+    "/rules help",
+    "/rules enable",
+    "/rules disable",
+    "/redaction",
+    "/redaction show",
+    "/redaction help",
+    "/system",
+    "/system show",
+    "/system edit",
+    "/system help",
     "/compact",
     "/compact help",
+    "/pin",
+    "/pins",
+    "/forget",
     "/usage",
     "/save",
     "/load",
+    "/export",
+    "/share",
+    "/fork",
+    "/new",
+    "/branches",
+    "/switch",
+    "/tag",
 ];
 
-pub fn generate_prompt(current_profile: Option<&str>, warning: bool) -> String {
-    let warning_symbol = if warning { "!".red().to_string() } else { "".to_string() };
+pub fn generate_prompt(
+    current_profile: Option<&str>,
+    warning: bool,
+    edit_mode: Option<EditMode>,
+    theme: &Theme,
+) -> String {
+    let mode_part = edit_mode_indicator(edit_mode);
+    let warning_symbol = if warning {
+        "!".with(theme.error).to_string()
+    } else {
+        "".to_string()
+    };
     let profile_part = current_profile
         .filter(|&p| p != "default")
-        .map(|p| format!("[{p}] ").cyan().to_string())
+        .map(|p| format!("[{p}] ").with(theme.profile).to_string())
         .unwrap_or_default();
 
-    format!("{profile_part}{warning_symbol}{}", "> ".magenta())
+    format!("{mode_part}{profile_part}{warning_symbol}{}", "> ".with(theme.prompt))
 }
 
-/// Complete commands that start with a slash
-fn complete_command(word: &str, start: usize) -> (usize, Vec<String>) {
-    (
-        start,
-        COMMANDS
-            .iter()
-            .filter(|p| p.starts_with(word))
-            .map(|s| (*s).to_owned())
-            .collect(),
-    )
+fn edit_mode_indicator(edit_mode: Option<EditMode>) -> String {
+    match edit_mode {
+        Some(EditMode::Vi) => "(vi) ".dark_grey().to_string(),
+        _ => "".to_string(),
+    }
+}
+
+/// Values available to a `chat.prompt` template. Those that are expensive to compute are only
+/// filled in when the template uses them, see [template_uses].
+#[derive(Debug, Default)]
+pub struct PromptVariables {
+    pub profile: Option<String>,
+    /// Whether all tools are trusted, shown as `!` by `{warning}`.
+    pub warning: bool,
+    pub branch: Option<String>,
+    pub cwd: Option<String>,
+    pub tokens_used: Option<usize>,
+    pub tokens_max: usize,
+}
+
+/// Whether `template` references the variable `name`.
+pub fn template_uses(template: &str, name: &str) -> bool {
+    template.contains(&format!("{{{name}}}"))
+}
+
+/// Renders the prompt from a `chat.prompt` template such as
+/// `{profile} [{tokens_used}/{tokens_max}] > `, see [expand_variables].
+pub fn generate_templated_prompt(template: &str, variables: &PromptVariables, edit_mode: Option<EditMode>) -> String {
+    let mut prompt = edit_mode_indicator(edit_mode);
+    prompt.push_str(&expand_variables(template, |name| {
+        Some(match name {
+            "profile" => variables.profile.clone().unwrap_or_default(),
+            "warning" => if variables.warning { "!" } else { "" }.to_string(),
+            "branch" => variables.branch.clone().unwrap_or_default(),
+            "cwd" => variables.cwd.clone().unwrap_or_default(),
+            "tokens_used" => variables.tokens_used.unwrap_or_default().to_string(),
+            "tokens_max" => variables.tokens_max.to_string(),
+            "context_percent" => match variables.tokens_max {
+                0 => "0".to_string(),
+                max => (variables.tokens_used.unwrap_or_default() * 100 / max).to_string(),
+            },
+            _ => return None,
+        })
+    }));
+    prompt
+}
+
+/// Replaces the `{name}` variables in `template` with their `value`. Unknown variables are kept
+/// as written, and `{{` and `}}` stand for literal braces.
+pub fn expand_variables(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(stripped) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            expanded.push_str(&rest[..1]);
+            rest = stripped;
+            continue;
+        }
+
+        let Some(end) = rest.find('}').filter(|_| rest.starts_with('{')) else {
+            expanded.push_str(&rest[..1]);
+            rest = &rest[1..];
+            continue;
+        };
+        match value(&rest[1..end]) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The state of the session summarized by the status line, shown above the prompt with
+/// `chat.statusLine`.
+#[derive(Debug, Clone, Default)]
+pub struct StatusLine {
+    pub profile: Option<String>,
+    /// Whether every tool runs without asking, see [super::tools::ToolPermissions].
+    pub trust_all: bool,
+    /// How many tools were trusted for the session otherwise.
+    pub trusted_tools: usize,
+    pub tokens_used: usize,
+    pub tokens_max: usize,
+}
+
+/// Renders the status line, e.g. `profile: default • trust: 2 tools • context: 12% (24000/200000
+/// tokens)`, cut to fit in `width` columns.
+pub fn generate_status_line(status: &StatusLine, width: usize) -> String {
+    let trust = match (status.trust_all, status.trusted_tools) {
+        (true, _) => "all tools".to_string(),
+        (false, 0) => "ask".to_string(),
+        (false, 1) => "1 tool".to_string(),
+        (false, n) => format!("{n} tools"),
+    };
+    let context_percent = match status.tokens_max {
+        0 => 0,
+        max => status.tokens_used * 100 / max,
+    };
+    let line = format!(
+        "profile: {} • trust: {} • context: {}% ({}/{} tokens)",
+        status.profile.as_deref().unwrap_or("default"),
+        trust,
+        context_percent,
+        status.tokens_used,
+        status.tokens_max
+    );
+
+    match line.chars().count() > width {
+        true => line
+            .chars()
+            .take(width.saturating_sub(1))
+            .chain(['…'])
+            .collect::<String>(),
+        false => line,
+    }
+    .dark_grey()
+    .to_string()
+}
+
+/// The branch checked out in the current directory, if it's in a git repository.
+pub fn git_branch() -> Option<String> {
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !branch.is_empty()).then_some(branch)
+}
+
+/// Resolves the edit mode from `chat.editMode.vi`, or else `chat.editMode`.
+pub fn edit_mode_from_settings(settings: &Settings) -> EditMode {
+    match settings.get_bool(Setting::ChatEditModeVi) {
+        Some(true) => EditMode::Vi,
+        Some(false) => EditMode::Emacs,
+        None => match settings.get_string(Setting::ChatEditMode).as_deref() {
+            Some("vi" | "vim") => EditMode::Vi,
+            _ => EditMode::Emacs,
+        },
+    }
+}
+
+/// A short description of a command in [COMMANDS], shown next to it when completing.
+fn command_description(command: &str) -> Option<&'static str> {
+    Some(match command {
+        "/clear" => "Clear the conversation history",
+        "/clear-screen" => "Clear the screen, keeping the conversation",
+        "/help" => "Show the help dialogue",
+        "/editor" => "Compose a prompt in $EDITOR",
+        "/edit" => "Edit an earlier message and continue from it",
+        "/quote" => "Quote the last response in your next prompt",
+        "/copy" => "Copy the last response or one of its code blocks",
+        "/paste" => "Paste the clipboard into your next prompt",
+        "/paste --as-context" => "Attach the clipboard to the context of this conversation",
+        "/page" => "Show the last response in your pager",
+        "/expand" => "Show the full output of a shortened tool result",
+        "/expand --attach" => "Attach the full output of a tool result to the context",
+        "/cache" => "Show how many tool results are reused",
+        "/cache clear" => "Forget the cached tool results",
+        "/ps" => "List the commands running in the background",
+        "/logs" => "Show the output of a background command",
+        "/kill" => "Stop a background command",
+        "/find" => "Search the conversation with a regex",
+        "/undo" => "Remove the last exchanges from the conversation",
+        "/checkpoint list" => "List the turns whose file changes can be reverted",
+        "/revert" => "Undo the file changes of a turn and the next ones",
+        "/revert all" => "Undo all the file changes made in the session",
+        "/retry" => "Send your last message again for a new response",
+        "/retry --fresh" => "Retry and ask for a different approach",
+        "/set-mode" => "Switch between vi and emacs key bindings",
+        "/set-mode vi" => "Use vi key bindings for this session",
+        "/set-mode emacs" => "Use emacs key bindings for this session",
+        "/multiline" => "Toggle multi-line mode, where an empty line sends the prompt",
+        "/multiline on" => "Make Enter insert a newline, and an empty line send the prompt",
+        "/multiline off" => "Make Enter send the prompt again",
+        "/dry-run" => "Toggle dry-run mode, where commands and file changes are only shown",
+        "/dry-run on" => "Show the commands and file changes the model asks for without running them",
+        "/dry-run off" => "Run the commands and write the files the model asks for again",
+        "/readonly" => "Toggle read-only mode, where the tools that can change anything are refused",
+        "/readonly on" => "Refuse file writes, shell commands that aren't read-only and MCP tools not marked read-only",
+        "/readonly off" => "Allow the tools that can change things again",
+        "/draft" => "Manage the unsent draft from $EDITOR",
+        "/draft help" => "Show an explanation for the draft command",
+        "/draft restore" => "Load the most recent draft into the prompt",
+        "/issue" => "Report an issue or make a feature request",
+        "/quit" => "Quit the application",
+        "/tools" => "View and manage tools and permissions",
+        "/tools help" => "Show an explanation for the tools command",
+        "/tools schema" => "Show the input schema for all tools",
+        "/tools trust" => "Trust tools for the session",
+        "/tools untrust" => "Revert tools to per-request confirmation",
+        "/tools enable" => "Make disabled tools available again",
+        "/tools disable" => "Hide tools from the model for the session",
+        "/tools trustall" => "Trust all tools",
+        "/tools reset" => "Reset tools to default permission levels",
+        "/profile" => "Manage profiles",
+        "/profile help" => "Show an explanation for the profile command",
+        "/profile list" => "List all available profiles",
+        "/profile create" => "Create a new profile",
+        "/profile delete" => "Delete a profile",
+        "/profile rename" => "Rename a profile",
+        "/profile set" => "Switch to a profile",
+        "/mcp" => "See the loaded MCP servers",
+        "/prompts" => "View and retrieve prompts",
+        "/prompts help" => "Show an explanation for the prompts command",
+        "/prompts list" => "List or search available prompts",
+        "/prompts get" => "Retrieve and send a prompt",
+        "/context" => "Manage context files and hooks",
+        "/context help" => "Show an explanation for the context command",
+        "/context show" => "Display the context configuration",
+        "/context show --expand" => "Display the context configuration and file contents",
+        "/context add" => "Add files to the profile context",
+        "/context add --global" => "Add files to the global context",
+        "/context add --diff" => "Add the output of git diff to the context, --staged for the staged changes",
+        "/context add-url" => "Add the text of a web page to the context of this conversation",
+        "/context add-cmd" => "Add the output of a shell command to the context of this conversation",
+        "/context rm" => "Remove files from the profile context",
+        "/context rm --global" => "Remove files from the global context",
+        "/context rm --diff" => "Stop adding git diff to the context",
+        "/context clear" => "Remove all files from the profile context",
+        "/context clear --global" => "Remove all files from the global context",
+        "/context save" => "Save the profile rules for chat sessions in this directory",
+        "/context reset" => "Delete the rules saved in this directory",
+        "/context diff" => "Show which context files changed between the last two requests",
+        "/context hooks" => "View and manage context hooks",
+        "/context hooks help" => "Show an explanation for context hooks",
+        "/context hooks add" => "Add a new context hook",
+        "/context hooks rm" => "Remove a context hook",
+        "/context hooks enable" => "Enable a context hook",
+        "/context hooks disable" => "Disable a context hook",
+        "/context hooks enable-all" => "Enable all context hooks",
+        "/context hooks disable-all" => "Disable all context hooks",
+        "/rules" => "List the project rules from .amazonq/rules",
+        "/rules help" => "Show an explanation for the rules command",
+        "/rules enable" => "Include project rules in the context again",
+        "/rules disable" => "Leave project rules out of the context of this conversation",
+        "/redaction" => "Show what was masked before sending",
+        "/redaction show" => "List the secrets masked in prompts and context files",
+        "/redaction help" => "Show an explanation for the redaction command",
+        "/system" => "Show the system prompt sent with every conversation",
+        "/system show" => "Show the system prompt and where it is from",
+        "/system edit" => "Edit the system prompt of the current profile in $EDITOR",
+        "/system help" => "Show an explanation for the system command",
+        "/compact" => "Summarize the conversation to free up context space",
+        "/compact help" => "Show an explanation for the compact command",
+        "/pin" => "Pin a message so that compaction never drops it",
+        "/pins" => "List the pinned messages",
+        "/forget" => "Stop sending messages to the model, e.g. a pasted secret",
+        "/usage" => "Show the context window usage",
+        "/save" => "Save the conversation by name, or to a JSON file",
+        "/load" => "Load a saved conversation or JSON file, or list saved conversations",
+        "/export" => "Export the conversation to Markdown, HTML or JSON",
+        "/share" => "Share the conversation as a secret gist or an HTML file, without its secrets",
+        "/fork" => "Copy the conversation into a new branch and continue on it",
+        "/new" => "Start a new conversation, keeping this one to switch back to",
+        "/branches" => "List the branches of the conversation, or switch to one",
+        "/switch" => "Switch to another branch or conversation started with /new",
+        "/tag" => "Tag the conversation to find it with q chat search",
+        _ => return None,
+    })
+}
+
+/// Complete commands that start with a slash, one word at a time, so `/context ` lists the
+/// subcommands of `/context` rather than every context command.
+fn complete_command(word: &str, start: usize) -> (usize, Vec<Pair>) {
+    let mut commands: Vec<&str> = Vec::new();
+    for command in COMMANDS.iter().filter(|c| c.starts_with(word)) {
+        let command = match command[word.len()..].find(' ') {
+            Some(i) => &command[..word.len() + i],
+            None => command,
+        };
+        if !commands.contains(&command) {
+            commands.push(command);
+        }
+    }
+
+    let width = commands.iter().map(|c| c.len()).max().unwrap_or_default();
+    let candidates = commands
+        .into_iter()
+        .map(|command| Pair {
+            display: match command_description(command) {
+                Some(description) => format!("{command:<width$}  {description}"),
+                None => command.to_owned(),
+            },
+            replacement: command.to_owned(),
+        })
+        .collect();
+
+    (start, candidates)
 }
 
 /// A wrapper around FilenameCompleter that provides enhanced path detection
@@ -138,6 +536,107 @@ impl PathCompleter {
     }
 }
 
+/// The files git lists for `@path` references, listed on the first completion of a prompt and
+/// kept until the next prompt, so that pressing Tab again doesn't list the workspace again.
+#[derive(Debug, Default)]
+pub struct WorkspaceFiles(Mutex<Listed>);
+
+#[derive(Debug, Default)]
+enum Listed {
+    #[default]
+    NotYet,
+    /// The files tracked or not ignored by git.
+    Git(Vec<String>),
+    /// Outside a repository, where the directory of each reference is listed instead.
+    NotGit,
+}
+
+impl WorkspaceFiles {
+    /// Forgets the files listed for the previous prompt.
+    pub fn reset(&self) {
+        if let Ok(mut files) = self.0.lock() {
+            *files = Listed::NotYet;
+        }
+    }
+
+    /// Completes `@path` references to files in the workspace, relative to the current directory
+    /// and one directory at a time. Inside a git repository only files not ignored by .gitignore
+    /// are offered.
+    fn complete(&self, word: &str) -> Vec<String> {
+        let Some(partial) = word.strip_prefix('@') else {
+            return Vec::new();
+        };
+        let Ok(mut files) = self.0.lock() else {
+            return Vec::new();
+        };
+        if matches!(*files, Listed::NotYet) {
+            *files = git_files().map_or(Listed::NotGit, Listed::Git);
+        }
+        match &*files {
+            Listed::Git(files) => reference_candidates(partial, files),
+            _ => reference_candidates(partial, &list_directory(partial)),
+        }
+    }
+}
+
+/// Files tracked or not ignored by git under the current directory, `None` outside a repository.
+fn git_files() -> Option<Vec<String>> {
+    let output = std::process::Command::new("git")
+        .args(["ls-files", "-z", "--cached", "--others", "--exclude-standard"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    )
+}
+
+/// The entries of the directory `partial` points into, skipping hidden ones. Directories end with
+/// a slash.
+fn list_directory(partial: &str) -> Vec<String> {
+    let dir = match partial.rfind('/') {
+        Some(i) => &partial[..=i],
+        None => "",
+    };
+    let Ok(entries) = std::fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') {
+                return None;
+            }
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            Some(format!("{dir}{name}{}", if is_dir { "/" } else { "" }))
+        })
+        .collect()
+}
+
+/// Completes `partial` against `files` up to the end of the next path segment, so directories are
+/// offered before the files inside them.
+fn reference_candidates(partial: &str, files: &[String]) -> Vec<String> {
+    let mut candidates: Vec<String> = files
+        .iter()
+        .filter(|file| file.starts_with(partial))
+        .map(|file| match file[partial.len()..].find('/') {
+            Some(i) => format!("@{}", &file[..=partial.len() + i]),
+            None => format!("@{file}"),
+        })
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
 pub struct PromptCompleter {
     sender: std::sync::mpsc::Sender<Option<String>>,
     receiver: std::sync::mpsc::Receiver<Vec<String>>,
@@ -168,6 +667,7 @@ impl PromptCompleter {
 pub struct ChatCompleter {
     path_completer: PathCompleter,
     prompt_completer: PromptCompleter,
+    workspace_files: WorkspaceFiles,
 }
 
 impl ChatCompleter {
@@ -175,12 +675,21 @@ impl ChatCompleter {
         Self {
             path_completer: PathCompleter::new(),
             prompt_completer: PromptCompleter::new(sender, receiver),
+            workspace_files: WorkspaceFiles::default(),
         }
     }
 }
 
+/// A completion candidate displayed as it will be inserted.
+fn plain_candidate(replacement: String) -> Pair {
+    Pair {
+        display: replacement.clone(),
+        replacement,
+    }
+}
+
 impl Completer for ChatCompleter {
-    type Candidate = String;
+    type Candidate = Pair;
 
     fn complete(
         &self,
@@ -190,7 +699,10 @@ impl Completer for ChatCompleter {
     ) -> Result<(usize, Vec<Self::Candidate>), ReadlineError> {
         let (start, word) = extract_word(line, pos, None, |c| c.is_space());
 
-        // Handle command completion
+        // Handle command completion, including subcommands and arguments for a command line
+        if line.starts_with('/') && !line[..pos].contains('\n') {
+            return Ok(complete_command(&line[..pos], 0));
+        }
         if word.starts_with('/') {
             return Ok(complete_command(word, start));
         }
@@ -199,15 +711,22 @@ impl Completer for ChatCompleter {
             let search_word = line.strip_prefix('@').unwrap_or("");
             if let Ok(completions) = self.prompt_completer.complete_prompt(search_word) {
                 if !completions.is_empty() {
-                    return Ok((0, completions));
+                    return Ok((0, completions.into_iter().map(plain_candidate).collect()));
                 }
             }
         }
 
+        if word.starts_with('@') {
+            let completions = self.workspace_files.complete(word);
+            if !completions.is_empty() {
+                return Ok((start, completions.into_iter().map(plain_candidate).collect()));
+            }
+        }
+
         // Handle file path completion as fallback
         if let Ok((pos, completions)) = self.path_completer.complete_path(line, pos, _ctx) {
             if !completions.is_empty() {
-                return Ok((pos, completions));
+                return Ok((pos, completions.into_iter().map(plain_candidate).collect()));
             }
         }
 
@@ -216,34 +735,340 @@ impl Completer for ChatCompleter {
     }
 }
 
+/// Whether `line` opens or closes a fenced code block.
+fn is_fence(line: &str) -> bool {
+    let line = line.trim();
+    // Unless the block is opened and closed on the same line
+    line.starts_with("```") && (line.len() == 3 || !line[3..].ends_with("```"))
+}
+
+/// Whether `input` is a command, `/` or `!`, whose backslashes are sent as typed.
+fn is_command(input: &str) -> bool {
+    input.starts_with('/') || input.starts_with('!')
+}
+
+/// Whether `line` ends with a single backslash, continuing it on the next line. Two stand for a
+/// literal one, e.g. `C:\\` for `C:\`.
+fn is_continued(line: &str) -> bool {
+    line.ends_with('\\') && !line.ends_with("\\\\")
+}
+
+/// Whether Enter should insert a newline rather than submit `input`: it ends with a backslash
+/// and isn't a command, or has a code fence that isn't closed yet.
+pub fn is_incomplete(input: &str) -> bool {
+    let open_fence = input.lines().filter(|line| is_fence(line)).count() % 2 == 1;
+    open_fence || (!is_command(input) && is_continued(input))
+}
+
+/// Removes the backslashes used to continue `input` on the next line, and turns the doubled ones
+/// ending a line into one, outside of code blocks and commands.
+pub fn join_continuations(input: &str) -> String {
+    if is_command(input) {
+        return input.to_string();
+    }
+    let mut in_fence = false;
+    let mut lines = input.split('\n').peekable();
+    let mut joined = String::with_capacity(input.len());
+    while let Some(line) = lines.next() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        match line.strip_suffix('\\') {
+            _ if in_fence => joined.push_str(line),
+            Some(literal) if literal.ends_with('\\') => joined.push_str(literal),
+            Some(continued) if lines.peek().is_some() => joined.push_str(continued),
+            _ => joined.push_str(line),
+        }
+        if lines.peek().is_some() {
+            joined.push('\n');
+        }
+    }
+    joined
+}
+
+/// Whether Enter submits `input` in multi-line mode, where it otherwise inserts a newline.
+///
+/// An empty last line submits, and so does Enter on a single line command (`/` or `!`) so that
+/// commands don't need a blank line after them.
+pub fn is_multiline_submit(input: &str) -> bool {
+    let single_command = !input.contains('\n') && (input.starts_with('/') || input.starts_with('!'));
+    input.trim().is_empty() || single_command || input.ends_with('\n')
+}
+
 /// Custom validator for multi-line input
-pub struct MultiLineValidator;
+#[derive(Default)]
+pub struct MultiLineValidator {
+    /// Whether the buffer spans several lines, which switches to the continuation prompt.
+    continuing: AtomicBool,
+    /// Whether multi-line mode is on, see `/multiline`.
+    pub multiline: Arc<AtomicBool>,
+}
 
 impl Validator for MultiLineValidator {
     fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
         let input = ctx.input();
-
-        // Check for explicit multi-line markers
-        if input.starts_with("```") && !input.ends_with("```") {
+        if is_incomplete(input) || (self.multiline.load(Ordering::Relaxed) && !is_multiline_submit(input)) {
+            self.continuing.store(true, Ordering::Relaxed);
             return Ok(ValidationResult::Incomplete);
         }
 
-        // Check for backslash continuation
-        if input.ends_with('\\') {
-            return Ok(ValidationResult::Incomplete);
+        Ok(ValidationResult::Valid(None))
+    }
+}
+
+/// Submits the prompt on Alt+Enter in multi-line mode, and inserts a newline otherwise.
+pub struct MultilineSubmitHandler(Arc<AtomicBool>);
+
+impl ConditionalEventHandler for MultilineSubmitHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, _ctx: &EventContext<'_>) -> Option<Cmd> {
+        match self.0.load(Ordering::Relaxed) {
+            // Unlike Enter, accepting the line skips the validator
+            true => Some(Cmd::AcceptLine),
+            false => Some(Cmd::Insert(1, "\n".to_string())),
         }
+    }
+}
 
-        Ok(ValidationResult::Valid(None))
+/// Submits a non-empty prompt on Ctrl+D in multi-line mode. An empty prompt still exits.
+pub struct MultilineEofHandler(Arc<AtomicBool>);
+
+impl ConditionalEventHandler for MultilineEofHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        (self.0.load(Ordering::Relaxed) && !ctx.line().is_empty()).then_some(Cmd::AcceptLine)
+    }
+}
+
+/// Tracks bracketed pastes, so that the number of lines pasted can be shown next to the buffer.
+///
+/// rustyline inserts a bracketed paste as a single edit, so pasted newlines never submit the prompt
+/// and pasted control characters never trigger keybindings.
+#[derive(Debug, Clone, Default)]
+pub struct PasteTracker {
+    /// How many newlines the buffer had before the first paste into it.
+    newlines_before: Arc<Mutex<Option<usize>>>,
+}
+
+impl PasteTracker {
+    /// Forgets about the previous buffer's pastes.
+    pub fn reset(&self) {
+        if let Ok(mut before) = self.newlines_before.lock() {
+            *before = None;
+        }
+    }
+}
+
+impl ConditionalEventHandler for PasteTracker {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        if let Ok(mut before) = self.newlines_before.lock() {
+            before.get_or_insert(ctx.line().matches('\n').count());
+        }
+        // Let rustyline read and insert the pasted text itself
+        None
+    }
+}
+
+/// Redo for the prompt being typed, on top of rustyline's own undo.
+///
+/// rustyline can undo edits (Ctrl+_) but not redo them, so the buffer is remembered before each
+/// undo and put back on redo (Alt+_). Whole-buffer replacements are undone in one step rather than
+/// the two rustyline takes to delete and insert, and so is an editor round trip, which starts a
+/// new prompt whose changes rustyline can't undo back to what was typed before.
+#[derive(Debug, Clone, Default)]
+pub struct LineUndo(Arc<Mutex<LineUndoState>>);
+
+#[derive(Debug, Default)]
+struct LineUndoState {
+    /// The buffer before each undo, the most recent last.
+    redos: Vec<String>,
+    /// The buffer left by the last undo or redo. Any other buffer means that it was edited since,
+    /// and the redos no longer apply.
+    undone: Option<String>,
+    /// Whether an undo or redo was just made and the buffer it left is yet to be seen.
+    pending: bool,
+    /// The buffer left by the last whole-buffer replacement, along with the number of changes
+    /// rustyline made for it.
+    replaced: Option<(String, RepeatCount)>,
+    /// The buffer a prompt was started with and the one it replaced, put back by the first undo.
+    restore: Option<(String, String)>,
+}
+
+impl LineUndo {
+    /// Forgets about the previous buffer's edits.
+    pub fn reset(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            *state = LineUndoState::default();
+        }
+    }
+
+    /// Notes that the prompt starts with `text` in place of `before`, e.g. after an editor round
+    /// trip.
+    pub fn record_restore(&self, text: &str, before: String) {
+        if let Ok(mut state) = self.0.lock() {
+            state.restore = Some((text.to_owned(), before));
+        }
+    }
+
+    /// Called with the buffer whenever it is redrawn.
+    fn observe(&self, line: &str) {
+        let Ok(mut state) = self.0.lock() else {
+            return;
+        };
+        if state.pending {
+            state.pending = false;
+            state.undone = Some(line.to_owned());
+        } else if state.undone.as_deref() != Some(line) {
+            state.redos.clear();
+            state.undone = None;
+        }
+    }
+
+    fn undo(&self, line: &str, n: RepeatCount) -> Cmd {
+        let Ok(mut state) = self.0.lock() else {
+            return Cmd::Undo(n);
+        };
+        state.redos.push(line.to_owned());
+        state.pending = true;
+        if let Some((_, before)) = state.restore.take_if(|(text, _)| text.as_str() == line) {
+            return Cmd::Replace(Movement::WholeBuffer, Some(before));
+        }
+        match state.replaced.take() {
+            Some((replaced, changes)) if replaced == line && changes > 0 => Cmd::Undo(changes),
+            _ => Cmd::Undo(n),
+        }
+    }
+
+    fn redo(&self, line: &str) -> Option<Cmd> {
+        let mut state = self.0.lock().ok()?;
+        if state.undone.as_deref() != Some(line) {
+            return None;
+        }
+        let text = state.redos.pop()?;
+        state.pending = true;
+        state.record_replacement(line, &text);
+        Some(Cmd::Replace(Movement::WholeBuffer, Some(text)))
+    }
+}
+
+impl LineUndoState {
+    fn record_replacement(&mut self, line: &str, text: &str) {
+        // Deleting an empty buffer or inserting nothing isn't recorded as a change
+        let changes = usize::from(!line.is_empty()) + usize::from(!text.is_empty());
+        self.replaced = Some((text.to_owned(), changes));
+    }
+}
+
+/// Bound to Ctrl+_, see [LineUndo].
+pub struct UndoHandler(pub LineUndo);
+
+impl ConditionalEventHandler for UndoHandler {
+    fn handle(&self, _evt: &Event, n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        Some(self.0.undo(ctx.line(), n))
+    }
+}
+
+/// Bound to Alt+_, see [LineUndo].
+pub struct RedoHandler(pub LineUndo);
+
+impl ConditionalEventHandler for RedoHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        Some(self.0.redo(ctx.line()).unwrap_or(Cmd::Noop))
     }
 }
 
-#[derive(Helper, Completer, Hinter)]
+/// The `[Pasted N lines]` placeholder shown after a multi-line paste. It can't be accepted into
+/// the buffer like other hints.
+pub struct PasteHint(String);
+
+impl Hint for PasteHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Hinter for PasteTracker {
+    type Hint = PasteHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<PasteHint> {
+        let mut before = self.newlines_before.lock().ok()?;
+        if line.is_empty() {
+            *before = None;
+            return None;
+        }
+
+        let pasted_lines = line.matches('\n').count().checked_sub((*before)?)? + 1;
+        (pos == line.len() && pasted_lines > 1).then(|| PasteHint(format!("  [Pasted {pasted_lines} lines]")))
+    }
+}
+
+/// A hint shown after the cursor, either a paste placeholder or a suggestion from the history.
+pub enum ChatHint {
+    Paste(PasteHint),
+    History(String),
+}
+
+impl Hint for ChatHint {
+    fn display(&self) -> &str {
+        match self {
+            ChatHint::Paste(hint) => hint.display(),
+            ChatHint::History(suggestion) => suggestion,
+        }
+    }
+
+    fn completion(&self) -> Option<&str> {
+        match self {
+            ChatHint::Paste(hint) => hint.completion(),
+            ChatHint::History(suggestion) => Some(suggestion),
+        }
+    }
+}
+
+/// Accepts the history suggestion with End, like Right already does, when the cursor is at the
+/// end of the buffer.
+struct AcceptHintHandler;
+
+impl ConditionalEventHandler for AcceptHintHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        (ctx.has_hint() && ctx.pos() == ctx.line().len()).then_some(Cmd::CompleteHint)
+    }
+}
+
+#[derive(Helper, Completer)]
 pub struct ChatHelper {
     #[rustyline(Completer)]
     completer: ChatCompleter,
-    #[rustyline(Hinter)]
-    hinter: (),
-    validator: MultiLineValidator,
+    pub paste_tracker: PasteTracker,
+    pub line_undo: LineUndo,
+    history_hinter: HistoryHinter,
+    pub validator: MultiLineValidator,
+    /// Whether the hint being shown is a history suggestion, which is dimmed.
+    suggesting: AtomicBool,
+}
+
+impl ChatHelper {
+    /// Forgets the files listed to complete the previous prompt's `@path` references.
+    pub fn reset_workspace_files(&self) {
+        self.completer.workspace_files.reset();
+    }
+}
+
+impl Hinter for ChatHelper {
+    type Hint = ChatHint;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<ChatHint> {
+        // Called on every redraw, so every edit of the buffer is seen
+        self.line_undo.observe(line);
+        let hint = match self.paste_tracker.hint(line, pos, ctx) {
+            Some(hint) => Some(ChatHint::Paste(hint)),
+            None => self.history_hinter.hint(line, pos, ctx).map(ChatHint::History),
+        };
+        self.suggesting
+            .store(matches!(hint, Some(ChatHint::History(_))), Ordering::Relaxed);
+        hint
+    }
 }
 
 impl Validator for ChatHelper {
@@ -254,10 +1079,26 @@ impl Validator for ChatHelper {
 
 impl Highlighter for ChatHelper {
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if self.suggesting.load(Ordering::Relaxed) {
+            return Cow::Owned(format!("\x1b[2m{hint}\x1b[m"));
+        }
         Cow::Owned(format!("\x1b[1m{hint}\x1b[m"))
     }
 
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, default: bool) -> Cow<'b, str> {
+        if !default || !self.validator.continuing.load(Ordering::Relaxed) {
+            return Cow::Borrowed(prompt);
+        }
+
+        // Same width as "> ", so that rustyline's layout of the prompt stays correct
+        match prompt.rfind("> ") {
+            Some(index) => Cow::Owned(format!("{}… {}", &prompt[..index], &prompt[index + "> ".len()..])),
+            None => Cow::Borrowed(prompt),
+        }
+    }
+
     fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        self.validator.continuing.store(line.contains('\n'), Ordering::Relaxed);
         Cow::Borrowed(line)
     }
 
@@ -271,27 +1112,77 @@ pub fn rl(
     sender: std::sync::mpsc::Sender<Option<String>>,
     receiver: std::sync::mpsc::Receiver<Vec<String>>,
 ) -> Result<Editor<ChatHelper, DefaultHistory>> {
-    let edit_mode = match database.settings.get_string(Setting::ChatEditMode).as_deref() {
-        Some("vi" | "vim") => EditMode::Vi,
-        _ => EditMode::Emacs,
-    };
+    let edit_mode = edit_mode_from_settings(&database.settings);
+    let history_size = database
+        .settings
+        .get_int(Setting::ChatHistorySize)
+        .and_then(|size| usize::try_from(size).ok())
+        .unwrap_or(DEFAULT_HISTORY_SIZE);
     let config = Config::builder()
         .history_ignore_space(true)
+        .history_ignore_dups(true)?
+        .max_history_size(history_size)?
         .completion_type(CompletionType::List)
+        .bracketed_paste(true)
         .edit_mode(edit_mode)
         .build();
+    let paste_tracker = PasteTracker::default();
+    let line_undo = LineUndo::default();
+    let multiline = Arc::new(AtomicBool::new(
+        database.settings.get_bool(Setting::ChatMultiline).unwrap_or(false),
+    ));
     let h = ChatHelper {
         completer: ChatCompleter::new(sender, receiver),
-        hinter: (),
-        validator: MultiLineValidator,
+        paste_tracker: paste_tracker.clone(),
+        line_undo: line_undo.clone(),
+        history_hinter: HistoryHinter::new(),
+        validator: MultiLineValidator {
+            continuing: AtomicBool::new(false),
+            multiline: Arc::clone(&multiline),
+        },
+        suggesting: AtomicBool::new(false),
     };
     let mut rl = Editor::with_config(config)?;
     rl.set_helper(Some(h));
 
-    // Add custom keybinding for Alt+Enter to insert a newline
+    rl.bind_sequence(
+        KeyEvent(KeyCode::BracketedPasteStart, Modifiers::NONE),
+        EventHandler::Conditional(Box::new(paste_tracker)),
+    );
+
+    rl.bind_sequence(
+        KeyEvent(KeyCode::End, Modifiers::NONE),
+        EventHandler::Conditional(Box::new(AcceptHintHandler)),
+    );
+
+    rl.bind_sequence(
+        KeyEvent(KeyCode::Tab, Modifiers::NONE),
+        EventHandler::Conditional(Box::new(SnippetExpander(Snippets::from_settings(&database.settings)))),
+    );
+
+    rl.bind_sequence(
+        KeyEvent(KeyCode::Char('_'), Modifiers::CTRL),
+        EventHandler::Conditional(Box::new(UndoHandler(line_undo.clone()))),
+    );
+    rl.bind_sequence(
+        KeyEvent(KeyCode::Char('_'), Modifiers::ALT),
+        EventHandler::Conditional(Box::new(RedoHandler(line_undo))),
+    );
+
+    // Add custom keybinding for Alt+Enter to insert a newline, or to submit in multi-line mode
     rl.bind_sequence(
         KeyEvent(KeyCode::Enter, Modifiers::ALT),
-        EventHandler::Simple(Cmd::Insert(1, "\n".to_string())),
+        EventHandler::Conditional(Box::new(MultilineSubmitHandler(Arc::clone(&multiline)))),
+    );
+    rl.bind_sequence(
+        KeyEvent(KeyCode::Char('d'), Modifiers::CTRL),
+        EventHandler::Conditional(Box::new(MultilineEofHandler(multiline))),
+    );
+
+    // Ctrl+L only clears the screen in both edit modes, see /clear-screen
+    rl.bind_sequence(
+        KeyEvent(KeyCode::Char('l'), Modifiers::CTRL),
+        EventHandler::Simple(Cmd::ClearScreen),
     );
 
     // Add custom keybinding for Ctrl+J to insert a newline
@@ -310,21 +1201,270 @@ mod tests {
     #[test]
     fn test_generate_prompt() {
         // Test default prompt (no profile)
-        assert_eq!(generate_prompt(None, false), "> ".magenta().to_string());
+        assert_eq!(
+            generate_prompt(None, false, None, &Theme::DARK),
+            "> ".magenta().to_string()
+        );
         // Test default prompt with warning
-        assert_eq!(generate_prompt(None, true), format!("{}{}", "!".red(), "> ".magenta()));
+        assert_eq!(
+            generate_prompt(None, true, None, &Theme::DARK),
+            format!("{}{}", "!".red(), "> ".magenta())
+        );
         // Test default profile (should be same as no profile)
-        assert_eq!(generate_prompt(Some("default"), false), "> ".magenta().to_string());
+        assert_eq!(
+            generate_prompt(Some("default"), false, None, &Theme::DARK),
+            "> ".magenta().to_string()
+        );
         // Test custom profile
         assert_eq!(
-            generate_prompt(Some("test-profile"), false),
+            generate_prompt(Some("test-profile"), false, None, &Theme::DARK),
             format!("{}{}", "[test-profile] ".cyan(), "> ".magenta())
         );
         // Test another custom profile with warning
         assert_eq!(
-            generate_prompt(Some("dev"), true),
+            generate_prompt(Some("dev"), true, None, &Theme::DARK),
             format!("{}{}{}", "[dev] ".cyan(), "!".red(), "> ".magenta())
         );
+        // Test the vi mode indicator, emacs is the default and isn't shown
+        assert_eq!(
+            generate_prompt(Some("dev"), false, Some(EditMode::Vi), &Theme::DARK),
+            format!("{}{}{}", "(vi) ".dark_grey(), "[dev] ".cyan(), "> ".magenta())
+        );
+        assert_eq!(
+            generate_prompt(None, false, Some(EditMode::Emacs), &Theme::DARK),
+            "> ".magenta().to_string()
+        );
+    }
+
+    #[test]
+    fn test_generate_templated_prompt() {
+        let variables = PromptVariables {
+            profile: Some("dev".to_string()),
+            warning: true,
+            branch: Some("main".to_string()),
+            cwd: Some("~/src/app".to_string()),
+            tokens_used: Some(50_000),
+            tokens_max: 200_000,
+        };
+
+        assert_eq!(
+            generate_templated_prompt("{profile} [{tokens_used}/{tokens_max}] > ", &variables, None),
+            "dev [50000/200000] > "
+        );
+        assert_eq!(
+            generate_templated_prompt("{cwd} ({branch}) {context_percent}%{warning}> ", &variables, None),
+            "~/src/app (main) 25%!> "
+        );
+        // Unknown variables and escaped braces
+        assert_eq!(
+            generate_templated_prompt("{model} {{tokens}} } {unclosed > ", &variables, None),
+            "{model} {tokens} } {unclosed > "
+        );
+        assert_eq!(
+            generate_templated_prompt("> ", &PromptVariables::default(), Some(EditMode::Vi)),
+            format!("{}> ", "(vi) ".dark_grey())
+        );
+
+        assert!(template_uses("{profile} {tokens_used} > ", "tokens_used"));
+        assert!(!template_uses("{profile} > ", "tokens_used"));
+    }
+
+    #[tokio::test]
+    async fn test_edit_mode_from_settings() {
+        let mut database = Database::new().await.unwrap();
+        assert_eq!(edit_mode_from_settings(&database.settings), EditMode::Emacs);
+
+        database.settings.set(Setting::ChatEditMode, "vi").await.unwrap();
+        assert_eq!(edit_mode_from_settings(&database.settings), EditMode::Vi);
+
+        // chat.editMode.vi takes precedence
+        database.settings.set(Setting::ChatEditModeVi, false).await.unwrap();
+        assert_eq!(edit_mode_from_settings(&database.settings), EditMode::Emacs);
+    }
+
+    #[tokio::test]
+    async fn test_history_size_and_dedup() {
+        let mut database = Database::new().await.unwrap();
+        database.settings.set(Setting::ChatHistorySize, 2).await.unwrap();
+        let (prompt_request_sender, _) = std::sync::mpsc::channel::<Option<String>>();
+        let (_, prompt_response_receiver) = std::sync::mpsc::channel::<Vec<String>>();
+        let mut rl = rl(&database, prompt_request_sender, prompt_response_receiver).unwrap();
+
+        for line in ["first", "second", "second", "third"] {
+            let _ = rl.add_history_entry(line);
+        }
+        let history = rl.history().iter().collect::<Vec<_>>();
+        assert_eq!(history, vec!["second", "third"]);
+    }
+
+    #[test]
+    fn test_paste_hint() {
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let tracker = PasteTracker::default();
+        let hint = |line: &str, pos: usize| tracker.hint(line, pos, &ctx).map(|hint| hint.0);
+
+        // Nothing was pasted
+        assert_eq!(hint("typed\nby hand", 14), None);
+
+        // Pasted three lines after a line typed by hand
+        *tracker.newlines_before.lock().unwrap() = Some(1);
+        assert_eq!(
+            hint("typed\nline 1\nline 2\nline 3", 26),
+            Some("  [Pasted 3 lines]".to_string())
+        );
+        // Only shown with the cursor at the end of the buffer
+        assert_eq!(hint("typed\nline 1\nline 2\nline 3", 2), None);
+
+        // A new buffer starts over
+        assert_eq!(hint("", 0), None);
+        assert!(tracker.newlines_before.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_generate_status_line() {
+        let mut status = StatusLine {
+            profile: Some("dev".to_string()),
+            trust_all: false,
+            trusted_tools: 2,
+            tokens_used: 24_000,
+            tokens_max: 200_000,
+        };
+        assert_eq!(
+            generate_status_line(&status, 80),
+            "profile: dev • trust: 2 tools • context: 12% (24000/200000 tokens)"
+                .dark_grey()
+                .to_string()
+        );
+
+        status.profile = None;
+        status.trust_all = true;
+        assert_eq!(
+            generate_status_line(&status, 30),
+            "profile: default • trust: all…".dark_grey().to_string()
+        );
+    }
+
+    #[test]
+    fn test_line_undo() {
+        let undo = LineUndo::default();
+        undo.observe("fix the tests");
+        assert_eq!(undo.undo("fix the tests", 1), Cmd::Undo(1));
+        undo.observe("fix the ");
+        assert_eq!(
+            undo.redo("fix the "),
+            Some(Cmd::Replace(Movement::WholeBuffer, Some("fix the tests".to_string())))
+        );
+        undo.observe("fix the tests");
+        // Undoing the redo takes rustyline two changes
+        assert_eq!(undo.undo("fix the tests", 1), Cmd::Undo(2));
+
+        // Editing after an undo drops the redos
+        undo.observe("fix the ");
+        undo.observe("fix the b");
+        assert_eq!(undo.redo("fix the b"), None);
+
+        // An editor round trip, which started a new prompt, is undone in one step
+        undo.reset();
+        undo.record_restore("from the editor", "draft".to_string());
+        undo.observe("from the editor");
+        assert_eq!(
+            undo.undo("from the editor", 1),
+            Cmd::Replace(Movement::WholeBuffer, Some("draft".to_string()))
+        );
+        undo.observe("draft");
+        assert_eq!(
+            undo.redo("draft"),
+            Some(Cmd::Replace(Movement::WholeBuffer, Some("from the editor".to_string())))
+        );
+        undo.observe("from the editor");
+        assert_eq!(undo.undo("from the editor", 1), Cmd::Undo(2));
+
+        undo.reset();
+        assert_eq!(undo.redo("replaced and edited"), None);
+    }
+
+    #[test]
+    fn test_history_hint() {
+        use rustyline::history::History;
+
+        let (prompt_request_sender, _) = std::sync::mpsc::channel::<Option<String>>();
+        let (_, prompt_response_receiver) = std::sync::mpsc::channel::<Vec<String>>();
+        let helper = ChatHelper {
+            completer: ChatCompleter::new(prompt_request_sender, prompt_response_receiver),
+            paste_tracker: PasteTracker::default(),
+            line_undo: LineUndo::default(),
+            history_hinter: HistoryHinter::new(),
+            validator: MultiLineValidator::default(),
+            suggesting: AtomicBool::new(false),
+        };
+        let mut history = DefaultHistory::new();
+        history.add("run the tests and fix failures").unwrap();
+        history.add("explain this").unwrap();
+        let ctx = Context::new(&history);
+
+        let hint = helper.hint("run the", 7, &ctx).unwrap();
+        assert_eq!(hint.display(), " tests and fix failures");
+        assert_eq!(hint.completion(), Some(" tests and fix failures"));
+        assert!(helper.suggesting.load(Ordering::Relaxed));
+
+        // Only suggested with the cursor at the end of the buffer
+        assert!(helper.hint("run the", 3, &ctx).is_none());
+        assert!(helper.hint("nothing like it", 15, &ctx).is_none());
+
+        // A paste placeholder takes precedence and can't be accepted
+        *helper.paste_tracker.newlines_before.lock().unwrap() = Some(0);
+        let hint = helper.hint("run the\ntests", 13, &ctx).unwrap();
+        assert_eq!(hint.display(), "  [Pasted 2 lines]");
+        assert_eq!(hint.completion(), None);
+        assert!(!helper.suggesting.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("first line \\"));
+        assert!(is_incomplete("```rust\nfn main() {}"));
+        assert!(is_incomplete("look at this:\n```\ncode"));
+        assert!(is_incomplete("```"));
+
+        assert!(!is_incomplete("hello"));
+        assert!(!is_incomplete("```rust\nfn main() {}\n```"));
+        assert!(!is_incomplete("inline ```code``` is fine"));
+        assert!(!is_incomplete("```code```"));
+        // Two backslashes stand for one, and commands are sent as typed
+        assert!(!is_incomplete("C:\\\\"));
+        assert!(!is_incomplete("!dir C:\\"));
+        assert!(!is_incomplete("/context add C:\\"));
+    }
+
+    #[test]
+    fn test_is_multiline_submit() {
+        assert!(is_multiline_submit(""));
+        assert!(is_multiline_submit("first paragraph\n\nsecond paragraph\n"));
+        assert!(is_multiline_submit("/multiline off"));
+        assert!(is_multiline_submit("!ls -la"));
+
+        assert!(!is_multiline_submit("first paragraph"));
+        assert!(!is_multiline_submit("first paragraph\n\nsecond paragraph"));
+        assert!(!is_multiline_submit("/path/to/file looks wrong\nwhy?"));
+    }
+
+    #[test]
+    fn test_join_continuations() {
+        assert_eq!(join_continuations("first \\\nsecond"), "first \nsecond");
+        assert_eq!(join_continuations("no continuation"), "no continuation");
+        // Backslashes inside code blocks are part of the code
+        assert_eq!(
+            join_continuations("```sh\nls \\\n  -la\n```"),
+            "```sh\nls \\\n  -la\n```"
+        );
+        // A trailing backslash on the last line is kept
+        assert_eq!(join_continuations("C:\\"), "C:\\");
+        // Two are a literal one, on any line
+        assert_eq!(join_continuations("C:\\\\"), "C:\\");
+        assert_eq!(join_continuations("in C:\\\\\nwhat's there?"), "in C:\\\nwhat's there?");
+        // Commands are left alone
+        assert_eq!(join_continuations("!echo a \\\\"), "!echo a \\\\");
     }
 
     #[test]
@@ -346,7 +1486,85 @@ mod tests {
         assert_eq!(start, 0);
 
         // Verify completions contain expected commands
-        assert!(completions.contains(&"/help".to_string()));
+        assert!(completions.iter().any(|c| c.replacement == "/help"));
+    }
+
+    #[test]
+    fn test_chat_completer_subcommand_completion() {
+        let (prompt_request_sender, _) = std::sync::mpsc::channel::<Option<String>>();
+        let (_, prompt_response_receiver) = std::sync::mpsc::channel::<Vec<String>>();
+        let completer = ChatCompleter::new(prompt_request_sender, prompt_response_receiver);
+        let empty_history = DefaultHistory::new();
+        let ctx = Context::new(&empty_history);
+        let complete = |line: &str| {
+            let (start, completions) = completer.complete(line, line.len(), &ctx).unwrap();
+            assert_eq!(start, 0);
+            completions.into_iter().map(|c| c.replacement).collect::<Vec<_>>()
+        };
+
+        // Only the next word is completed
+        assert_eq!(complete("/con"), vec!["/context"]);
+        assert_eq!(complete("/context hooks e"), vec![
+            "/context hooks enable",
+            "/context hooks enable-all"
+        ]);
+        assert!(complete("/context ").contains(&"/context hooks".to_string()));
+        assert!(!complete("/context ").contains(&"/context hooks add".to_string()));
+
+        // Arguments
+        assert_eq!(complete("/set-mode "), vec!["/set-mode vi", "/set-mode emacs"]);
+        assert_eq!(complete("/context add --"), vec![
+            "/context add --global",
+            "/context add --diff"
+        ]);
+
+        // Descriptions are shown next to the command
+        let (_, completions) = completer.complete("/draft r", 8, &ctx).unwrap();
+        assert_eq!(
+            completions[0].display,
+            "/draft restore  Load the most recent draft into the prompt"
+        );
+    }
+
+    #[test]
+    fn test_reference_candidates() {
+        let files = ["Cargo.toml", "src/main.rs", "src/cli/mod.rs", "src/cli/chat/mod.rs"]
+            .map(String::from)
+            .to_vec();
+
+        assert_eq!(reference_candidates("", &files), vec!["@Cargo.toml", "@src/"]);
+        assert_eq!(reference_candidates("src/", &files), vec!["@src/cli/", "@src/main.rs"]);
+        assert_eq!(reference_candidates("src/cli/c", &files), vec!["@src/cli/chat/"]);
+        assert!(reference_candidates("docs/", &files).is_empty());
+    }
+
+    #[test]
+    fn test_workspace_files() {
+        // The files listed for a prompt are kept until the next one
+        let workspace_files = WorkspaceFiles::default();
+        *workspace_files.0.lock().unwrap() = Listed::Git(vec!["src/main.rs".to_string()]);
+        assert_eq!(workspace_files.complete("@src/"), vec!["@src/main.rs"]);
+        assert!(workspace_files.complete("src/").is_empty());
+        workspace_files.reset();
+        assert!(matches!(*workspace_files.0.lock().unwrap(), Listed::NotYet));
+    }
+
+    #[test]
+    fn test_list_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        std::fs::write(dir.path().join("src/.hidden"), "").unwrap();
+
+        let partial = format!("{}/src/", dir.path().display());
+        assert_eq!(list_directory(&partial), vec![format!("{partial}main.rs")]);
+    }
+
+    #[test]
+    fn test_command_descriptions() {
+        for command in COMMANDS {
+            assert!(command_description(command).is_some(), "{command} has no description");
+        }
     }
 
     #[test]
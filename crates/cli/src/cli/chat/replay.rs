@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::io::{
+    IsTerminal,
+    Write,
+};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use crossterm::event::{
+    self,
+    Event,
+    KeyCode,
+    KeyEvent,
+    KeyEventKind,
+    KeyModifiers,
+};
+use crossterm::style::{
+    Attribute,
+    Color,
+};
+use crossterm::{
+    cursor,
+    queue,
+    style,
+    terminal,
+};
+use eyre::bail;
+use winnow::Partial;
+use winnow::stream::Offset;
+
+use super::cli::ChatReplay;
+use super::conversation_state::ConversationState;
+use super::message::{
+    AssistantToolUse,
+    UserMessageContent,
+};
+use super::parse::{
+    ParseState,
+    interpret_markdown,
+};
+use super::session::{
+    generate_title,
+    load_session,
+};
+use super::theme::Theme;
+use super::util::shared_writer::SharedWriter;
+use super::{
+    CONTINUATION_LINE,
+    TOOL_BULLET,
+};
+use crate::api_client::model::ToolResultStatus;
+use crate::database::Database;
+use crate::platform::Context;
+use crate::util::directories;
+
+/// Something shown while replaying a conversation.
+#[derive(Debug)]
+enum ReplayEvent<'a> {
+    Prompt(&'a str),
+    Response(&'a str),
+    ToolUse(&'a AssistantToolUse),
+    ToolResult {
+        name: &'a str,
+        success: bool,
+    },
+    /// A tool use that was rejected or interrupted.
+    Cancelled {
+        name: &'a str,
+    },
+}
+
+/// How the replay moves on to the next turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Advance {
+    Keypress,
+    After(Duration),
+    /// When not in a terminal, e.g. piped to a file.
+    Immediately,
+}
+
+/// The history of `state` split into turns, each starting with a prompt and going on with the
+/// responses, tool uses and tool results until the next prompt.
+fn turns(state: &ConversationState) -> Vec<Vec<ReplayEvent<'_>>> {
+    fn end_turn<'a>(turns: &mut Vec<Vec<ReplayEvent<'a>>>, turn: &mut Vec<ReplayEvent<'a>>) {
+        if !turn.is_empty() {
+            turns.push(std::mem::take(turn));
+        }
+    }
+
+    // Tool results only have the id of their tool use
+    let mut tool_names = HashMap::new();
+    let mut turns = Vec::new();
+    let mut turn = Vec::new();
+    for (user, assistant) in state.history() {
+        match &user.content {
+            UserMessageContent::Prompt { prompt } => {
+                end_turn(&mut turns, &mut turn);
+                turn.push(ReplayEvent::Prompt(prompt));
+            },
+            UserMessageContent::CancelledToolUses {
+                prompt,
+                tool_use_results,
+            } => {
+                turn.extend(tool_use_results.iter().map(|result| ReplayEvent::Cancelled {
+                    name: tool_names.get(result.tool_use_id.as_str()).copied().unwrap_or("tool"),
+                }));
+                if let Some(prompt) = prompt {
+                    end_turn(&mut turns, &mut turn);
+                    turn.push(ReplayEvent::Prompt(prompt));
+                }
+            },
+            UserMessageContent::ToolUseResults { tool_use_results } => {
+                turn.extend(tool_use_results.iter().map(|result| ReplayEvent::ToolResult {
+                    name: tool_names.get(result.tool_use_id.as_str()).copied().unwrap_or("tool"),
+                    success: matches!(result.status, ToolResultStatus::Success),
+                }));
+            },
+        }
+
+        if !assistant.content().trim().is_empty() {
+            turn.push(ReplayEvent::Response(assistant.content()));
+        }
+        for tool_use in assistant.tool_uses().unwrap_or_default() {
+            tool_names.insert(tool_use.id.as_str(), tool_use.name.as_str());
+            turn.push(ReplayEvent::ToolUse(tool_use));
+        }
+    }
+    end_turn(&mut turns, &mut turn);
+    turns
+}
+
+fn print_event(output: &mut impl Write, theme: &Theme, event: &ReplayEvent<'_>) -> eyre::Result<()> {
+    match event {
+        ReplayEvent::Prompt(prompt) => queue!(
+            output,
+            style::SetForegroundColor(theme.prompt),
+            style::Print("\n> "),
+            style::SetForegroundColor(Color::Reset),
+            style::Print(format!("{}\n\n", prompt.trim_end())),
+        )?,
+        ReplayEvent::Response(content) => {
+            queue!(output, style::SetForegroundColor(theme.assistant))?;
+            print_markdown(output, content)?;
+            queue!(output, style::SetForegroundColor(Color::Reset), style::Print("\n"))?;
+        },
+        ReplayEvent::ToolUse(tool_use) => {
+            let args = serde_json::to_string_pretty(&tool_use.args).unwrap_or_default();
+            queue!(
+                output,
+                style::SetForegroundColor(theme.tool),
+                style::Print(format!("🛠️  Using tool: {}", tool_use.name)),
+                style::SetForegroundColor(Color::Reset),
+                style::Print(format!("\n{CONTINUATION_LINE}\n{TOOL_BULLET}")),
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(args.replace('\n', "\n   ")),
+                style::SetForegroundColor(Color::Reset),
+                style::Print("\n"),
+            )?;
+        },
+        ReplayEvent::ToolResult { name, success } => {
+            let (color, status) = match success {
+                true => (Color::Green, "Completed"),
+                false => (theme.error, "Failed"),
+            };
+            queue!(
+                output,
+                style::SetForegroundColor(color),
+                style::Print(format!("\n{TOOL_BULLET}{status} {name}\n\n")),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        },
+        ReplayEvent::Cancelled { name } => queue!(
+            output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print(format!("\n{TOOL_BULLET}Cancelled {name}\n\n")),
+            style::SetForegroundColor(Color::Reset),
+        )?,
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Prints `content` the way responses are printed in the chat.
+fn print_markdown(output: &mut impl Write, content: &str) -> eyre::Result<()> {
+    // The parser only reports the end of the last line once it is followed by a newline
+    let buf = format!("{}\n", content.trim_end());
+    let mut state = ParseState::new(terminal::size().ok().map(|(width, _)| width as usize));
+    let mut offset = 0;
+    loop {
+        let input = Partial::new(&buf[offset..]);
+        match interpret_markdown(input, &mut *output, &mut state) {
+            Ok(parsed) => {
+                offset += parsed.offset_from(&input);
+                state.newline = state.set_newline;
+                state.set_newline = false;
+            },
+            Err(err) => match err.into_inner() {
+                Some(err) => bail!(err.to_string()),
+                None => break, // Data was incomplete
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Waits to show turn `turn` of `count`, returns whether to go on.
+async fn wait(output: &mut impl Write, advance: Advance, turn: usize, count: usize) -> eyre::Result<bool> {
+    let hint = match advance {
+        Advance::Keypress => "Enter or Space for the next turn, q to quit",
+        Advance::After(_) => "Ctrl+C to quit",
+        Advance::Immediately => return Ok(true),
+    };
+    queue!(
+        output,
+        style::SetForegroundColor(Color::DarkGrey),
+        style::Print(format!("─── Turn {turn} of {count} · {hint}")),
+        style::SetForegroundColor(Color::Reset),
+    )?;
+    output.flush()?;
+
+    let next = match advance {
+        Advance::After(delay) => {
+            tokio::time::sleep(delay).await;
+            true
+        },
+        _ => wait_for_key()?,
+    };
+    queue!(
+        output,
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        cursor::MoveToColumn(0),
+    )?;
+    output.flush()?;
+    Ok(next)
+}
+
+/// Reads keys until one is for the next turn or for quitting, then returns whether to go on.
+fn wait_for_key() -> eyre::Result<bool> {
+    terminal::enable_raw_mode()?;
+    let next = loop {
+        match event::read() {
+            Ok(Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            })) => match code {
+                KeyCode::Enter | KeyCode::Char(' ' | 'n') | KeyCode::Right | KeyCode::Down => break Ok(true),
+                KeyCode::Char('q') | KeyCode::Esc => break Ok(false),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break Ok(false),
+                _ => (),
+            },
+            Ok(_) => (),
+            Err(err) => break Err(err),
+        }
+    };
+    terminal::disable_raw_mode()?;
+    Ok(next?)
+}
+
+pub async fn execute_replay(database: &Database, args: ChatReplay) -> eyre::Result<ExitCode> {
+    let ctx = Context::new();
+    let state = load_session(&ctx, &directories::chat_sessions_dir()?, &args.session).await?;
+    let turns = turns(&state);
+    if turns.is_empty() {
+        bail!("The conversation saved as {} has no messages to replay", args.session);
+    }
+
+    let theme = Theme::from_settings(&database.settings);
+    if theme.is_no_color() {
+        style::force_color_output(false);
+    }
+    let mut output = SharedWriter::stdout();
+    let advance = match args.delay {
+        Some(seconds) => Advance::After(Duration::from_secs(seconds)),
+        None if std::io::stdin().is_terminal() && std::io::stdout().is_terminal() => Advance::Keypress,
+        None => Advance::Immediately,
+    };
+
+    let title = state
+        .title()
+        .map(str::to_string)
+        .or_else(|| generate_title(&state))
+        .unwrap_or_default();
+    queue!(
+        output,
+        style::SetAttribute(Attribute::Bold),
+        style::Print(format!("\nReplaying {}", args.session)),
+        style::SetAttribute(Attribute::Reset),
+        style::SetForegroundColor(Color::DarkGrey),
+        style::Print(format!("  {title} ({} turns)\n", turns.len())),
+        style::SetForegroundColor(Color::Reset),
+    )?;
+
+    let mut quit = false;
+    for (index, turn) in turns.iter().enumerate() {
+        if index > 0 && !wait(&mut output, advance, index + 1, turns.len()).await? {
+            quit = true;
+            break;
+        }
+        for event in turn {
+            print_event(&mut output, &theme, event)?;
+        }
+    }
+
+    queue!(
+        output,
+        style::SetForegroundColor(Color::DarkGrey),
+        style::Print(match quit {
+            true => "\nStopped the replay\n\n",
+            false => "\nEnd of the replay\n\n",
+        }),
+        style::SetForegroundColor(Color::Reset),
+    )?;
+    output.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cli::chat::message::{
+        AssistantMessage,
+        ToolUseResult,
+        ToolUseResultBlock,
+        UserMessage,
+    };
+    use crate::cli::chat::tool_manager::ToolManager;
+
+    fn tool_use(id: &str) -> AssistantToolUse {
+        AssistantToolUse {
+            id: id.to_string(),
+            name: "fs_read".to_string(),
+            orig_name: "fs_read".to_string(),
+            args: serde_json::json!({ "path": "README.md" }),
+            orig_args: serde_json::json!({ "path": "README.md" }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_turns() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut state = ConversationState::new(
+            Arc::clone(&ctx),
+            "id",
+            HashMap::new(),
+            None,
+            None,
+            ToolManager::default(),
+        )
+        .await;
+        state.push_history_entry(
+            UserMessage::new_prompt("What does the README say?".to_string()),
+            AssistantMessage::new_tool_use(None, "Reading it.".to_string(), vec![tool_use("t1")]),
+        );
+        state.push_history_entry(
+            UserMessage::new_tool_use_results(vec![ToolUseResult {
+                tool_use_id: "t1".to_string(),
+                content: vec![ToolUseResultBlock::Text("hello".to_string())],
+                status: ToolResultStatus::Success,
+            }]),
+            AssistantMessage::new_tool_use(None, String::new(), vec![tool_use("t2")]),
+        );
+        state.push_history_entry(
+            UserMessage::new_cancelled_tool_uses(Some("Never mind, thanks".to_string()), ["t2"].into_iter()),
+            AssistantMessage::new_response(None, "You're welcome!".to_string()),
+        );
+
+        let turns = turns(&state);
+        assert_eq!(turns.len(), 2);
+        assert!(matches!(turns[0][..], [
+            ReplayEvent::Prompt("What does the README say?"),
+            ReplayEvent::Response("Reading it."),
+            ReplayEvent::ToolUse(_),
+            ReplayEvent::ToolResult {
+                name: "fs_read",
+                success: true
+            },
+            ReplayEvent::ToolUse(_),
+            ReplayEvent::Cancelled { name: "fs_read" },
+        ]));
+        assert!(matches!(turns[1][..], [
+            ReplayEvent::Prompt("Never mind, thanks"),
+            ReplayEvent::Response("You're welcome!"),
+        ]));
+    }
+}
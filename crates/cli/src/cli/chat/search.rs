@@ -0,0 +1,207 @@
+use std::collections::BTreeSet;
+use std::io::Write as _;
+use std::process::ExitCode;
+
+use eyre::bail;
+
+use super::cli::ChatSearch;
+use super::conversation_state::ConversationState;
+use super::session::{
+    format_local_time,
+    load_session,
+    search_sessions,
+};
+use super::util::shared_writer::SharedWriter;
+use crate::platform::Context;
+use crate::util::{
+    CLI_BINARY_NAME,
+    directories,
+};
+
+/// How many snippets are shown for each matching session.
+const MAX_SNIPPETS: usize = 3;
+
+/// How much of a message is shown around a match, in characters.
+const SNIPPET_LEN: usize = 80;
+
+/// The lowercase words of `text` that are worth searching for.
+pub fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(str::to_lowercase)
+}
+
+/// The words of the prompts and responses of `state`, kept in the session index.
+pub fn conversation_terms(state: &ConversationState) -> BTreeSet<String> {
+    messages(state).flat_map(terms).collect()
+}
+
+fn messages(state: &ConversationState) -> impl Iterator<Item = &str> {
+    state
+        .history()
+        .iter()
+        .flat_map(|(user, assistant)| [user.prompt().unwrap_or_default(), assistant.content()])
+}
+
+/// Up to [MAX_SNIPPETS] excerpts of the messages of `state` around any of `terms`.
+fn snippets(state: &ConversationState, terms: &[String]) -> Vec<String> {
+    messages(state)
+        .flat_map(str::lines)
+        .filter_map(|line| snippet(line, terms))
+        .take(MAX_SNIPPETS)
+        .collect()
+}
+
+/// The part of `line` around the first of `terms` in it, if any.
+fn snippet(line: &str, terms: &[String]) -> Option<String> {
+    let lowercase = line.to_lowercase();
+    let start = terms.iter().filter_map(|term| lowercase.find(term.as_str())).min()?;
+    // The offset only maps back to `line` when lowercasing kept the lengths of its characters
+    let start = match lowercase.len() == line.len() && line.is_char_boundary(start) {
+        true => start,
+        false => 0,
+    };
+
+    let skipped = line[..start].chars().count().saturating_sub(SNIPPET_LEN / 4);
+    let mut snippet = line.chars().skip(skipped).take(SNIPPET_LEN).collect::<String>();
+    if skipped > 0 {
+        snippet.insert(0, '…');
+    }
+    if skipped + SNIPPET_LEN < line.chars().count() {
+        snippet.push('…');
+    }
+    Some(snippet.trim().to_string())
+}
+
+pub async fn execute_search(args: ChatSearch) -> eyre::Result<ExitCode> {
+    let ctx = Context::new();
+    let mut output = SharedWriter::stdout();
+    let dir = directories::chat_sessions_dir()?;
+
+    let query = terms(&args.query).collect::<Vec<_>>();
+    if query.is_empty() && args.tag.is_none() {
+        bail!("Nothing to search for, pass words of at least two letters or --tag");
+    }
+
+    let sessions = search_sessions(&ctx, &dir, &query, args.tag.as_deref()).await?;
+    if sessions.is_empty() {
+        writeln!(output, "\nNo saved conversation matches '{}'\n", args.query)?;
+        output.flush()?;
+        return Ok(ExitCode::FAILURE);
+    }
+
+    for (session, metadata) in &sessions {
+        let tags = match metadata.tags.is_empty() {
+            true => String::new(),
+            false => format!(
+                "  [{}]",
+                metadata.tags.iter().map(String::as_str).collect::<Vec<_>>().join(", ")
+            ),
+        };
+        writeln!(output, "\n{}  {}{tags}", session.name, metadata.title)?;
+        writeln!(output, "  saved {}", format_local_time(metadata.updated))?;
+        if query.is_empty() {
+            continue;
+        }
+        let Ok(state) = load_session(&ctx, &dir, &session.name).await else {
+            continue;
+        };
+        for snippet in snippets(&state, &query) {
+            writeln!(output, "    {snippet}")?;
+        }
+    }
+    writeln!(output, "\nResume one with {CLI_BINARY_NAME} chat --resume <name>\n")?;
+
+    output.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cli::chat::message::AssistantMessage;
+    use crate::cli::chat::session::{
+        SavedSession,
+        save_session,
+    };
+    use crate::cli::chat::tool_manager::ToolManager;
+    use crate::database::Database;
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(terms("DynamoDB throttling, a 5xx-error").collect::<Vec<_>>(), vec![
+            "dynamodb",
+            "throttling",
+            "5xx",
+            "error"
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut database = Database::new().await.unwrap();
+        let dir = std::path::Path::new("/sessions");
+        let mut state = ConversationState::new(
+            Arc::clone(&ctx),
+            "id",
+            HashMap::new(),
+            None,
+            None,
+            ToolManager::default(),
+        )
+        .await;
+        state
+            .set_next_user_message("Why is DynamoDB throttling my writes?".to_string())
+            .await;
+        state.push_assistant_message(
+            AssistantMessage::new_response(None, "The table is throttled on its partition key.".to_string()),
+            &mut database,
+        );
+        save_session(&ctx, dir, "dynamo", &state).await.unwrap();
+        state.add_tag("infra".to_string());
+        save_session(&ctx, dir, "tagged", &state).await.unwrap();
+
+        let names = |sessions: Vec<(SavedSession, _)>| {
+            let mut names = sessions
+                .into_iter()
+                .map(|(session, _)| session.name)
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let query = terms("dynamodb throttl").collect::<Vec<_>>();
+        assert_eq!(names(search_sessions(&ctx, dir, &query, None).await.unwrap()), vec![
+            "dynamo", "tagged"
+        ]);
+        assert_eq!(
+            names(search_sessions(&ctx, dir, &query, Some("infra")).await.unwrap()),
+            vec!["tagged"]
+        );
+        let query = terms("dynamodb kinesis").collect::<Vec<_>>();
+        assert!(search_sessions(&ctx, dir, &query, None).await.unwrap().is_empty());
+
+        let query = terms("throttl").collect::<Vec<_>>();
+        assert_eq!(snippets(&state, &query), vec![
+            "Why is DynamoDB throttling my writes?",
+            "The table is throttled on its partition key."
+        ]);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let terms = vec!["needle".to_string()];
+        assert_eq!(snippet("no match", &terms), None);
+        assert_eq!(snippet("A Needle here", &terms), Some("A Needle here".to_string()));
+
+        let line = format!("{} needle {}", "a".repeat(100), "b".repeat(100));
+        let snippet = snippet(&line, &terms).unwrap();
+        let before = "a".repeat(SNIPPET_LEN / 4 - 1);
+        assert!(snippet.starts_with(&format!("…{before} needle b")));
+        assert!(snippet.ends_with("b…"));
+        assert_eq!(snippet.chars().count(), SNIPPET_LEN + 2);
+    }
+}
@@ -0,0 +1,118 @@
+//! Secrets saved to the secret store with `q chat secrets set NAME`, for the settings to refer to
+//! as `{{secret:NAME}}` rather than holding them in plain text, e.g. the tokens of
+//! `tools.httpRequest.headers`.
+
+use std::io::{
+    IsTerminal,
+    Read,
+    Write,
+};
+use std::process::ExitCode;
+
+use eyre::{
+    Result,
+    bail,
+};
+
+use super::cli::{
+    ChatSecrets,
+    SecretsAction,
+};
+use super::util::shared_writer::SharedWriter;
+use crate::database::Database;
+
+/// Keeps the secrets of chat apart from those of the login in the store.
+const KEY_PREFIX: &str = "chat.secret.";
+
+fn key(name: &str) -> Result<String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        bail!("'{name}' isn't a valid secret name, use letters, digits, '_', '-' and '.'");
+    }
+    Ok(format!("{KEY_PREFIX}{name}"))
+}
+
+/// The secret saved as `name`, failing when it isn't saved.
+pub async fn get(database: &Database, name: &str) -> Result<String> {
+    match database.get_secret(&key(name)?).await? {
+        Some(secret) => Ok(secret.0),
+        None => bail!("the secret {name} isn't saved, save it with: q chat secrets set {name}"),
+    }
+}
+
+/// `template` with each `{{secret:NAME}}` replaced by the secret saved as NAME, failing when one
+/// isn't saved.
+pub async fn resolve(database: &Database, template: &str) -> Result<String> {
+    let mut resolved = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{secret:") {
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        let name = rest[start + "{{secret:".len()..end].trim();
+        let secret = get(database, name).await?;
+        resolved.push_str(&rest[..start]);
+        resolved.push_str(&secret);
+        rest = &rest[end + 2..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+pub async fn execute_secrets(database: &Database, args: ChatSecrets) -> Result<ExitCode> {
+    let mut output = SharedWriter::stdout();
+    match args.action {
+        SecretsAction::Set { name } => {
+            let key = key(&name)?;
+            let value = match std::io::stdin().is_terminal() {
+                true => dialoguer::Password::new()
+                    .with_prompt(format!("Value of {name}"))
+                    .interact()?,
+                false => {
+                    let mut value = String::new();
+                    std::io::stdin().read_to_string(&mut value)?;
+                    value.trim_end_matches(['\r', '\n']).to_string()
+                },
+            };
+            if value.is_empty() {
+                bail!("The value of {name} is empty");
+            }
+            database.set_secret(&key, &value).await?;
+            writeln!(
+                output,
+                "\n✓ Saved the secret {name}, refer to it as {{{{secret:{name}}}}}\n"
+            )?;
+        },
+        SecretsAction::Delete { name } => {
+            database.delete_secret(&key(&name)?).await?;
+            writeln!(output, "\n✓ Deleted the secret {name}\n")?;
+        },
+    }
+    output.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let database = Database::new().await.unwrap();
+        database
+            .set_secret(&key("internal-api").unwrap(), "s3cr3t")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            resolve(&database, "Bearer {{secret:internal-api}}").await.unwrap(),
+            "Bearer s3cr3t"
+        );
+        assert_eq!(resolve(&database, "no secret").await.unwrap(), "no secret");
+        assert!(resolve(&database, "{{secret:missing}}").await.is_err());
+        assert!(resolve(&database, "{{secret:../auth}}").await.is_err());
+    }
+}
@@ -0,0 +1,909 @@
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::io::Write as _;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::ExitCode;
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use super::autosave::{
+    Autosave,
+    list_autosaves,
+    prune_autosaves,
+};
+use super::cli::{
+    ChatSessions,
+    SessionsAction,
+    parse_age,
+};
+use super::conversation_state::ConversationState;
+use super::search::conversation_terms;
+use super::sync::{
+    SyncBackend,
+    SyncRemote,
+    history_path,
+    sync,
+};
+use super::util::shared_writer::SharedWriter;
+use super::util::truncate_safe;
+use crate::database::Database;
+use crate::database::settings::Setting;
+use crate::platform::Context;
+use crate::util::{
+    CLI_BINARY_NAME,
+    directories,
+};
+
+/// The file in the sessions directory keeping the [SessionMetadata] of every session. It is
+/// hidden so that it can't be mistaken for a session.
+const INDEX_FILE: &str = ".index.json";
+
+/// How long titles are, in bytes.
+const MAX_TITLE_LEN: usize = 60;
+
+/// How long the names made from titles are, in bytes.
+const MAX_NAME_LEN: usize = 40;
+
+/// Phrases that say nothing about what a conversation is about, dropped from the start of titles.
+const TITLE_FILLERS: &[&str] = &[
+    "hi",
+    "hello",
+    "hey",
+    "please",
+    "can you",
+    "could you",
+    "would you",
+    "will you",
+    "help me",
+    "i want to",
+    "i need to",
+    "i'd like to",
+    "i would like to",
+    "let's",
+];
+
+/// Which conversation `q chat --resume` continues.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resume {
+    /// The last conversation from the current directory.
+    Directory,
+    /// A conversation saved with `/save <name>`.
+    Session(String),
+}
+
+/// A conversation saved with `/save <name>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedSession {
+    pub name: String,
+    pub modified: Option<SystemTime>,
+    /// The size of the file, in bytes.
+    pub size: u64,
+}
+
+/// Which conversations [prune] deletes, from `q chat sessions prune` or `chat.sessions.retention`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// The saved and autosaved conversations saved longer ago than this.
+    pub older_than: Option<Duration>,
+    /// The oldest saved conversations, once the more recent ones take this many bytes.
+    pub max_size: Option<u64>,
+}
+
+/// The conversations deleted by [prune].
+#[derive(Default)]
+pub struct Pruned {
+    /// The oldest first.
+    pub sessions: Vec<SavedSession>,
+    pub autosaves: Vec<Autosave>,
+}
+
+impl Pruned {
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty() && self.autosaves.is_empty()
+    }
+
+    /// E.g. `3 saved conversations (1.2 MB) and 1 autosave`.
+    pub fn summary(&self) -> String {
+        let plural = |count: usize, noun: &str| match count {
+            1 => format!("1 {noun}"),
+            count => format!("{count} {noun}s"),
+        };
+        let size = self.sessions.iter().map(|session| session.size).sum();
+        let sessions = format!(
+            "{} ({})",
+            plural(self.sessions.len(), "saved conversation"),
+            format_size(size)
+        );
+        match (self.sessions.is_empty(), self.autosaves.is_empty()) {
+            (false, true) => sessions,
+            (true, false) => plural(self.autosaves.len(), "autosave"),
+            _ => format!("{sessions} and {}", plural(self.autosaves.len(), "autosave")),
+        }
+    }
+}
+
+/// What `q chat sessions` shows about a saved session. Kept in an index next to the sessions, so
+/// that listing them doesn't read every conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionMetadata {
+    /// The title of the conversation, see [generate_title].
+    pub title: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated: OffsetDateTime,
+    pub messages: usize,
+    /// The directory the conversation was started from.
+    pub directory: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+}
+
+impl SessionMetadata {
+    /// The metadata of `state`, which was last saved at `updated`.
+    pub fn new(state: &ConversationState, updated: OffsetDateTime) -> Self {
+        let first = state.history().front().map(|(user, _)| user);
+        let title = state
+            .title()
+            .map(str::to_string)
+            .or_else(|| generate_title(state))
+            .unwrap_or_default();
+        Self {
+            title,
+            created: first.and_then(|user| user.timestamp).unwrap_or(updated),
+            updated,
+            messages: state.history().len() * 2,
+            directory: first
+                .and_then(|user| user.env_context.current_working_directory())
+                .map(str::to_string),
+            tags: state.tags().clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionIndex {
+    sessions: BTreeMap<String, SessionMetadata>,
+    /// The words of the messages of each session, for [search_sessions].
+    #[serde(default)]
+    terms: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SessionIndex {
+    fn insert(&mut self, name: &str, metadata: SessionMetadata, state: &ConversationState) {
+        self.sessions.insert(name.to_string(), metadata);
+        self.terms.insert(name.to_string(), conversation_terms(state));
+    }
+
+    fn remove(&mut self, name: &str) -> Option<(SessionMetadata, BTreeSet<String>)> {
+        let terms = self.terms.remove(name).unwrap_or_default();
+        self.sessions.remove(name).map(|metadata| (metadata, terms))
+    }
+}
+
+/// The index of `dir`, empty when it is missing or can't be read, in which case it is rebuilt by
+/// [list_sessions_with_metadata].
+async fn read_index(ctx: &Context, dir: &Path) -> SessionIndex {
+    let Ok(contents) = ctx.fs().read_to_string(dir.join(INDEX_FILE)).await else {
+        return SessionIndex::default();
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|err| {
+        warn!(?err, "Ignoring the invalid session index");
+        SessionIndex::default()
+    })
+}
+
+async fn write_index(ctx: &Context, dir: &Path, index: &SessionIndex) -> eyre::Result<()> {
+    ctx.fs()
+        .write(dir.join(INDEX_FILE), serde_json::to_string_pretty(index)?)
+        .await?;
+    Ok(())
+}
+
+/// A session name made from `title`, e.g. `fixing-the-login-bug` for "Fixing the login bug!".
+pub fn name_from_title(title: &str) -> Option<String> {
+    let mut name = String::new();
+    for c in title.chars() {
+        match c.is_ascii_alphanumeric() {
+            true => name.push(c.to_ascii_lowercase()),
+            false if !name.is_empty() && !name.ends_with('-') => name.push('-'),
+            false => (),
+        }
+        if name.len() >= MAX_NAME_LEN {
+            break;
+        }
+    }
+    let name = name.trim_end_matches('-');
+    is_session_name(name).then(|| name.to_string())
+}
+
+/// A short title for `state`, made from the first of its prompts that says what the conversation
+/// is about, i.e. that is more than a greeting or a single word.
+pub fn generate_title(state: &ConversationState) -> Option<String> {
+    let titles = state
+        .history()
+        .iter()
+        .filter_map(|(user, _)| user.prompt())
+        .filter_map(title_from_prompt)
+        .collect::<Vec<_>>();
+    titles
+        .iter()
+        .find(|title| title.contains(' '))
+        .or(titles.first())
+        .cloned()
+}
+
+/// The first line of `prompt` outside of code blocks, without the filler words it starts with,
+/// capitalized and cut to [MAX_TITLE_LEN] at a whole word.
+fn title_from_prompt(prompt: &str) -> Option<String> {
+    let mut line = prompt
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with("```"))
+        .find(|line| !line.is_empty())?;
+    while let Some(rest) = TITLE_FILLERS.iter().find_map(|filler| {
+        let rest = line.get(filler.len()..)?;
+        let is_word = rest.is_empty() || rest.starts_with(|c: char| !c.is_alphanumeric() && c != '\'');
+        (line[..filler.len()].eq_ignore_ascii_case(filler) && is_word)
+            .then(|| rest.trim_start_matches(|c: char| c.is_whitespace() || matches!(c, ',' | '!' | '.')))
+    }) {
+        line = rest;
+    }
+    let line = line.trim_end_matches(|c: char| c.is_whitespace() || matches!(c, '.' | '!' | '?' | ':'));
+
+    let mut chars = line.chars();
+    let first = chars.next()?;
+    let line = format!("{}{}", first.to_uppercase(), chars.as_str());
+    let title = truncate_safe(&line, MAX_TITLE_LEN);
+    Some(match title.len() < line.len() {
+        // Cut at the last whole word
+        true => format!(
+            "{}…",
+            title.rsplit_once(' ').map_or(title, |(words, _)| words).trim_end()
+        ),
+        false => title.to_string(),
+    })
+}
+
+/// Whether `name` can name a saved session rather than a file path, i.e. it only has letters,
+/// digits, `-` and `_`.
+pub fn is_session_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The name `/save` uses when none is given, e.g. `2025-04-30-153012`.
+pub fn default_session_name() -> String {
+    let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+    format!("{}-{:02}{:02}{:02}", now.date(), now.hour(), now.minute(), now.second())
+}
+
+pub fn session_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.json"))
+}
+
+/// The sessions saved in `dir`, the most recently saved first.
+pub async fn list_sessions(ctx: &Context, dir: &Path) -> std::io::Result<Vec<SavedSession>> {
+    let mut sessions = Vec::new();
+    let mut entries = match ctx.fs().read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(sessions),
+        Err(err) => return Err(err),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+            .filter(|name| is_session_name(name))
+        else {
+            continue;
+        };
+        let metadata = entry.metadata().await.ok();
+        sessions.push(SavedSession {
+            name: name.to_owned(),
+            modified: metadata.as_ref().and_then(|metadata| metadata.modified().ok()),
+            size: metadata.map(|metadata| metadata.len()).unwrap_or_default(),
+        });
+    }
+    sessions.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.name.cmp(&b.name)));
+    Ok(sessions)
+}
+
+/// Writes `state` to the session called `name` in `dir`.
+pub async fn save_session(ctx: &Context, dir: &Path, name: &str, state: &ConversationState) -> eyre::Result<()> {
+    save_session_at(ctx, dir, name, state, OffsetDateTime::now_utc()).await
+}
+
+/// Like [save_session], for a session that was last saved at `updated`, e.g. on another machine.
+pub async fn save_session_at(
+    ctx: &Context,
+    dir: &Path,
+    name: &str,
+    state: &ConversationState,
+    updated: OffsetDateTime,
+) -> eyre::Result<()> {
+    let contents = serde_json::to_string_pretty(state)?;
+    ctx.fs().create_dir_all(dir).await?;
+    ctx.fs().write(session_path(dir, name), contents).await?;
+
+    let mut index = read_index(ctx, dir).await;
+    let mut metadata = SessionMetadata::new(state, updated);
+    if let Some(previous) = index.sessions.get(name) {
+        metadata.created = metadata.created.min(previous.created);
+    }
+    index.insert(name, metadata, state);
+    write_index(ctx, dir, &index).await
+}
+
+/// The sessions saved in `dir` like [list_sessions], along with their metadata. Sessions missing
+/// from the index, e.g. saved before it existed, are read to add them.
+pub async fn list_sessions_with_metadata(
+    ctx: &Context,
+    dir: &Path,
+) -> eyre::Result<Vec<(SavedSession, SessionMetadata)>> {
+    let sessions = list_sessions(ctx, dir).await?;
+    let mut index = read_index(ctx, dir).await;
+    let indexed = index.sessions.len() + index.terms.len();
+    let exists = |name: &String| sessions.iter().any(|session| &session.name == name);
+    index.sessions.retain(|name, _| exists(name));
+    index.terms.retain(|name, _| exists(name));
+    let mut changed = index.sessions.len() + index.terms.len() != indexed;
+
+    let mut listed = Vec::new();
+    for session in sessions {
+        let metadata = match (
+            index.sessions.get(&session.name),
+            index.terms.contains_key(&session.name),
+        ) {
+            (Some(metadata), true) => metadata.clone(),
+            _ => {
+                let contents = ctx.fs().read_to_string(session_path(dir, &session.name)).await?;
+                let Ok(state) = serde_json::from_str::<ConversationState>(&contents) else {
+                    warn!(name = session.name, "Skipping the session that can't be read");
+                    continue;
+                };
+                let updated = session
+                    .modified
+                    .map_or_else(OffsetDateTime::now_utc, OffsetDateTime::from);
+                let metadata = SessionMetadata::new(&state, updated);
+                index.insert(&session.name, metadata.clone(), &state);
+                changed = true;
+                metadata
+            },
+        };
+        listed.push((session, metadata));
+    }
+
+    if changed {
+        if let Err(err) = write_index(ctx, dir, &index).await {
+            warn!(?err, "Failed to update the session index");
+        }
+    }
+    Ok(listed)
+}
+
+/// Deletes the session called `name` from `dir`.
+pub async fn delete_session(ctx: &Context, dir: &Path, name: &str) -> eyre::Result<()> {
+    let path = session_path(dir, name);
+    if !is_session_name(name) || !ctx.fs().exists(&path) {
+        eyre::bail!("No conversation is saved as '{name}'");
+    }
+    ctx.fs().remove_file(&path).await?;
+
+    let mut index = read_index(ctx, dir).await;
+    if index.remove(name).is_some() {
+        write_index(ctx, dir, &index).await?;
+    }
+    Ok(())
+}
+
+/// Deletes the sessions of `dir` that `retention` doesn't keep as of `now`, or only lists them with
+/// `dry_run`. The index is only read when some are deleted.
+pub async fn prune_sessions(
+    ctx: &Context,
+    dir: &Path,
+    retention: Retention,
+    now: SystemTime,
+    dry_run: bool,
+) -> eyre::Result<Vec<SavedSession>> {
+    let mut kept_size = 0;
+    let mut full = false;
+    let mut pruned = Vec::new();
+    // The most recent first, so that the oldest go once the size is over
+    for session in list_sessions(ctx, dir).await? {
+        let expired = retention.older_than.is_some_and(|older_than| {
+            session
+                .modified
+                .is_some_and(|modified| now.duration_since(modified).is_ok_and(|age| age > older_than))
+        });
+        full = full
+            || retention
+                .max_size
+                .is_some_and(|max_size| kept_size + session.size > max_size);
+        match expired || full {
+            true => pruned.push(session),
+            false => kept_size += session.size,
+        }
+    }
+
+    if !dry_run && !pruned.is_empty() {
+        let mut index = read_index(ctx, dir).await;
+        for session in &pruned {
+            ctx.fs().remove_file(session_path(dir, &session.name)).await?;
+            index.remove(&session.name);
+        }
+        write_index(ctx, dir, &index).await?;
+    }
+    pruned.reverse();
+    Ok(pruned)
+}
+
+/// Deletes the saved and autosaved conversations that `retention` doesn't keep, see
+/// [prune_sessions] and [prune_autosaves].
+pub async fn prune(ctx: &Context, retention: Retention, dry_run: bool) -> eyre::Result<Pruned> {
+    let now = SystemTime::now();
+    let sessions = prune_sessions(ctx, &directories::chat_sessions_dir()?, retention, now, dry_run).await?;
+    let autosaves = match retention.older_than {
+        Some(older_than) => prune_autosaves(ctx, &directories::chat_autosave_dir()?, older_than, now, dry_run).await?,
+        None => Vec::new(),
+    };
+    Ok(Pruned { sessions, autosaves })
+}
+
+/// The retention set with `chat.sessions.retention`, e.g. `30d`.
+pub fn retention_setting(database: &Database) -> Option<Duration> {
+    let value = database.settings.get_string(Setting::ChatSessionsRetention)?;
+    parse_age(&value)
+        .inspect_err(|err| warn!(err, "Ignoring the invalid chat.sessions.retention"))
+        .ok()
+}
+
+/// Formats `bytes` for people, e.g. `1.2 MB`.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        1_048_576..1_073_741_824 => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1} GB", bytes as f64 / 1_073_741_824.0),
+    }
+}
+
+/// Renames the session called `from` in `dir` to `to`. Another session called `to` is only
+/// replaced with `force`.
+pub async fn rename_session(ctx: &Context, dir: &Path, from: &str, to: &str, force: bool) -> eyre::Result<()> {
+    let path = session_path(dir, from);
+    if !is_session_name(from) || !ctx.fs().exists(&path) {
+        eyre::bail!("No conversation is saved as '{from}'");
+    }
+    if !is_session_name(to) {
+        eyre::bail!("'{to}' is not a valid name, use only letters, digits, - and _");
+    }
+    let new_path = session_path(dir, to);
+    if ctx.fs().exists(&new_path) && !force {
+        eyre::bail!("A conversation is already saved as '{to}'. To replace it, use --force");
+    }
+    if from == to {
+        return Ok(());
+    }
+    ctx.fs().rename(&path, &new_path).await?;
+
+    let mut index = read_index(ctx, dir).await;
+    index.remove(to);
+    if let Some((metadata, terms)) = index.remove(from) {
+        index.sessions.insert(to.to_string(), metadata);
+        index.terms.insert(to.to_string(), terms);
+    }
+    write_index(ctx, dir, &index).await
+}
+
+/// The sessions saved in `dir` whose messages have a word starting with each of `terms`, and
+/// that are tagged `tag` if any, the most recently saved first.
+pub async fn search_sessions(
+    ctx: &Context,
+    dir: &Path,
+    terms: &[String],
+    tag: Option<&str>,
+) -> eyre::Result<Vec<(SavedSession, SessionMetadata)>> {
+    // Brings the index up to date first
+    let sessions = list_sessions_with_metadata(ctx, dir).await?;
+    let index = read_index(ctx, dir).await;
+    Ok(sessions
+        .into_iter()
+        .filter(|(_, metadata)| tag.is_none_or(|tag| metadata.tags.contains(tag)))
+        .filter(|(session, _)| {
+            let Some(words) = index.terms.get(&session.name) else {
+                return false;
+            };
+            terms.iter().all(|term| {
+                words
+                    .range::<String, _>(term..)
+                    .next()
+                    .is_some_and(|word| word.starts_with(term.as_str()))
+            })
+        })
+        .collect())
+}
+
+/// Reads the session called `name` from `dir`. The state still has to be reloaded, see
+/// [ConversationState::reload_serialized_state].
+pub async fn load_session(ctx: &Context, dir: &Path, name: &str) -> eyre::Result<ConversationState> {
+    let path = session_path(dir, name);
+    if !ctx.fs().exists(&path) {
+        let names = list_sessions(ctx, dir)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|session| session.name)
+            .collect::<Vec<_>>();
+        match names.is_empty() {
+            true => eyre::bail!("No conversation is saved as '{name}'. Save one with /save <name>"),
+            false => eyre::bail!(
+                "No conversation is saved as '{name}'. Saved conversations: {}",
+                names.join(", ")
+            ),
+        }
+    }
+    let contents = ctx.fs().read_to_string(&path).await?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Formats `time` in the local time zone, e.g. `2025-04-30 15:30`.
+pub fn format_local_time(time: OffsetDateTime) -> String {
+    let time = time::UtcOffset::current_local_offset().map_or(time, |offset| time.to_offset(offset));
+    format!("{} {:02}:{:02}", time.date(), time.hour(), time.minute())
+}
+
+pub async fn execute_sessions(database: &Database, args: ChatSessions) -> eyre::Result<ExitCode> {
+    let ctx = Context::new();
+    let mut output = SharedWriter::stdout();
+    let dir = directories::chat_sessions_dir()?;
+
+    match args.action.unwrap_or(SessionsAction::List) {
+        SessionsAction::List => print_sessions(&ctx, &mut output, &dir).await?,
+        SessionsAction::Delete { name } => {
+            delete_session(&ctx, &dir, &name).await?;
+            writeln!(output, "\n✓ Deleted the conversation saved as '{name}'\n")?;
+        },
+        SessionsAction::Rename { name, new_name, force } => {
+            rename_session(&ctx, &dir, &name, &new_name, force).await?;
+            writeln!(output, "\n✓ Renamed the conversation '{name}' to '{new_name}'\n")?;
+        },
+        SessionsAction::Prune {
+            older_than,
+            max_size,
+            dry_run,
+        } => {
+            let older_than = match (older_than, max_size) {
+                (None, None) => match retention_setting(database) {
+                    Some(older_than) => Some(older_than),
+                    None => eyre::bail!(
+                        "Nothing to prune by, pass --older-than or --max-size, or set chat.sessions.retention"
+                    ),
+                },
+                (older_than, _) => older_than,
+            };
+            let pruned = prune(&ctx, Retention { older_than, max_size }, dry_run).await?;
+            if pruned.is_empty() {
+                writeln!(output, "\nNo conversation to delete\n")?;
+                output.flush()?;
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            writeln!(output)?;
+            for session in &pruned.sessions {
+                let saved = session
+                    .modified
+                    .map(|modified| format!(", saved {}", format_local_time(modified.into())))
+                    .unwrap_or_default();
+                writeln!(output, "  {}  {}{saved}", session.name, format_size(session.size))?;
+            }
+            for autosave in &pruned.autosaves {
+                let metadata = SessionMetadata::new(
+                    &autosave.conversation,
+                    autosave
+                        .modified
+                        .map_or_else(OffsetDateTime::now_utc, OffsetDateTime::from),
+                );
+                writeln!(output, "  (autosave)  {}", title(&metadata))?;
+            }
+            match dry_run {
+                true => writeln!(output, "\nWould delete {}\n", pruned.summary())?,
+                false => writeln!(output, "\n✓ Deleted {}\n", pruned.summary())?,
+            }
+        },
+        SessionsAction::Sync => {
+            let Some(backend) = SyncBackend::from_settings(&database.settings).await? else {
+                eyre::bail!("Nothing to sync with, set chat.sync.url to s3://bucket/prefix or a WebDAV URL");
+            };
+            let remote = SyncRemote::connect(backend).await?;
+            let report = sync(&ctx, &remote, &dir, history_path(&database.settings).as_deref()).await?;
+            writeln!(output)?;
+            for name in &report.pushed {
+                writeln!(output, "  ↑ {name}")?;
+            }
+            for name in &report.pulled {
+                writeln!(output, "  ↓ {name}")?;
+            }
+            for (name, err) in &report.failed {
+                writeln!(output, "  ✗ {name}: {err}")?;
+            }
+            writeln!(
+                output,
+                "\n✓ Pushed {}, pulled {} and added {} prompts to the history\n",
+                report.pushed.len(),
+                report.pulled.len(),
+                report.history_added
+            )?;
+        },
+    }
+
+    output.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn print_sessions(ctx: &Context, output: &mut SharedWriter, dir: &Path) -> eyre::Result<()> {
+    let sessions = list_sessions_with_metadata(ctx, dir).await?;
+    let autosaves = match directories::chat_autosave_dir() {
+        Ok(dir) => list_autosaves(ctx, &dir).await.unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+    .into_iter()
+    .filter(|autosave| !autosave.conversation.history().is_empty())
+    .collect::<Vec<_>>();
+
+    if sessions.is_empty() && autosaves.is_empty() {
+        writeln!(
+            output,
+            "\nNo conversations are saved yet. Save one in {CLI_BINARY_NAME} chat with /save <name>\n"
+        )?;
+        return Ok(());
+    }
+
+    if !sessions.is_empty() {
+        writeln!(output, "\nSaved conversations:")?;
+        let width = sessions
+            .iter()
+            .map(|(session, _)| session.name.len())
+            .max()
+            .unwrap_or_default();
+        for (session, metadata) in &sessions {
+            writeln!(output, "\n  {:<width$}  {}", session.name, title(metadata))?;
+            writeln!(output, "  {:<width$}  {}", "", details(metadata))?;
+        }
+        writeln!(output, "\nResume one with {CLI_BINARY_NAME} chat --resume <name>")?;
+    }
+
+    if !autosaves.is_empty() {
+        writeln!(output, "\nAutosaved conversations:")?;
+        for autosave in &autosaves {
+            let updated = autosave
+                .modified
+                .map_or_else(OffsetDateTime::now_utc, OffsetDateTime::from);
+            let metadata = SessionMetadata::new(&autosave.conversation, updated);
+            let status = match autosave.running {
+                true => "still running",
+                false => "exited uncleanly",
+            };
+            writeln!(output, "\n  {} ({status})", title(&metadata))?;
+            writeln!(output, "  {}", details(&metadata))?;
+        }
+        writeln!(
+            output,
+            "\nThe last one that exited uncleanly is offered the next time {CLI_BINARY_NAME} chat starts"
+        )?;
+    }
+    writeln!(output)?;
+    Ok(())
+}
+
+fn title(metadata: &SessionMetadata) -> &str {
+    match metadata.title.is_empty() {
+        true => "(no prompt)",
+        false => &metadata.title,
+    }
+}
+
+fn details(metadata: &SessionMetadata) -> String {
+    let mut details = format!(
+        "{} messages, started {}, saved {}",
+        metadata.messages,
+        format_local_time(metadata.created),
+        format_local_time(metadata.updated)
+    );
+    if let Some(directory) = &metadata.directory {
+        details.push_str(&format!(", in {directory}"));
+    }
+    if !metadata.tags.is_empty() {
+        let tags = metadata.tags.iter().map(String::as_str).collect::<Vec<_>>();
+        details.push_str(&format!(", tagged {}", tags.join(", ")));
+    }
+    details
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cli::chat::message::AssistantMessage;
+    use crate::cli::chat::tool_manager::ToolManager;
+    use crate::database::Database;
+
+    #[test]
+    fn test_is_session_name() {
+        assert!(is_session_name("debugging-auth_2"));
+        assert!(!is_session_name(""));
+        assert!(!is_session_name("my conversation"));
+        assert!(!is_session_name("conversation.json"));
+        assert!(!is_session_name("../conversation"));
+    }
+
+    #[test]
+    fn test_name_from_title() {
+        assert_eq!(
+            name_from_title("Fixing the login bug!"),
+            Some("fixing-the-login-bug".to_string())
+        );
+        assert_eq!(name_from_title("!!!"), None);
+        assert_eq!(name_from_title(&"a ".repeat(40)).map(|name| name.len()), Some(39));
+    }
+
+    #[test]
+    fn test_title_from_prompt() {
+        assert_eq!(
+            title_from_prompt("Hi! Can you help me fix the flaky auth tests?").as_deref(),
+            Some("Fix the flaky auth tests")
+        );
+        assert_eq!(title_from_prompt("\n```\npanic at main.rs:3\n```").as_deref(), None);
+        assert_eq!(title_from_prompt("hello").as_deref(), None);
+        assert_eq!(
+            title_from_prompt("highlight the syntax").as_deref(),
+            Some("Highlight the syntax")
+        );
+        assert_eq!(title_from_prompt("thanks").as_deref(), Some("Thanks"));
+    }
+
+    #[tokio::test]
+    async fn test_prune_sessions() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let dir = Path::new("/sessions");
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        ctx.fs().create_dir_all(dir).await.unwrap();
+        for (name, size, age) in [("a", 100, 3), ("b", 200, 2), ("c", 300, 1)] {
+            let path = session_path(dir, name);
+            ctx.fs().write(&path, "x".repeat(size)).await.unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(ctx.fs().chroot_path(&path))
+                .unwrap()
+                .set_modified(now - day * age)
+                .unwrap();
+        }
+
+        let pruned = |retention: Retention, dry_run: bool| {
+            let ctx = Arc::clone(&ctx);
+            async move {
+                prune_sessions(&ctx, dir, retention, now, dry_run)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|session| session.name)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let older_than = |days: u32| Retention {
+            older_than: Some(day * days),
+            max_size: None,
+        };
+        let max_size = |bytes: u64| Retention {
+            older_than: None,
+            max_size: Some(bytes),
+        };
+        assert_eq!(pruned(older_than(4), true).await, Vec::<String>::new());
+        assert_eq!(pruned(older_than(0), true).await, vec!["a", "b", "c"]);
+        assert_eq!(pruned(max_size(500), true).await, vec!["a"]);
+        // Once a session doesn't fit, the older ones go too
+        assert_eq!(pruned(max_size(400), true).await, vec!["a", "b"]);
+        assert!(ctx.fs().exists(session_path(dir, "a")));
+
+        assert_eq!(pruned(older_than(2), false).await, vec!["a"]);
+        assert!(!ctx.fs().exists(session_path(dir, "a")));
+        assert!(ctx.fs().exists(session_path(dir, "b")));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(12), "12 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(500 << 20), "500.0 MB");
+    }
+
+    #[tokio::test]
+    async fn test_list_sessions() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let dir = Path::new("/sessions");
+        assert!(list_sessions(&ctx, dir).await.unwrap().is_empty());
+
+        ctx.fs().create_dir_all(dir).await.unwrap();
+        ctx.fs().write(dir.join("debugging.json"), "{}").await.unwrap();
+        ctx.fs().write(dir.join("notes.txt"), "").await.unwrap();
+        ctx.fs().write(dir.join("not a session.json"), "{}").await.unwrap();
+        let names = list_sessions(&ctx, dir)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|session| session.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["debugging".to_string()]);
+
+        let err = load_session(&ctx, dir, "missing").await.unwrap_err();
+        assert!(err.to_string().contains("Saved conversations: debugging"));
+    }
+
+    #[tokio::test]
+    async fn test_session_index() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut database = Database::new().await.unwrap();
+        let dir = Path::new("/sessions");
+        let mut state = ConversationState::new(
+            Arc::clone(&ctx),
+            "id",
+            HashMap::new(),
+            None,
+            None,
+            ToolManager::default(),
+        )
+        .await;
+        state
+            .set_next_user_message(
+                "\nWhy does login fail with a 403 right after the token is refreshed?\nSee the logs".to_string(),
+            )
+            .await;
+        state.push_assistant_message(
+            AssistantMessage::new_response(None, "Let me look.".to_string()),
+            &mut database,
+        );
+        save_session(&ctx, dir, "login", &state).await.unwrap();
+
+        // Saved before the index existed
+        ctx.fs()
+            .write(dir.join("old.json"), serde_json::to_string(&state).unwrap())
+            .await
+            .unwrap();
+        let sessions = list_sessions_with_metadata(&ctx, dir).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        for (_, metadata) in &sessions {
+            assert_eq!(
+                metadata.title,
+                "Why does login fail with a 403 right after the token is…"
+            );
+            assert_eq!(metadata.messages, 2);
+        }
+        assert!(read_index(&ctx, dir).await.sessions.contains_key("old"));
+
+        assert!(rename_session(&ctx, dir, "old", "login", false).await.is_err());
+        rename_session(&ctx, dir, "old", "older", false).await.unwrap();
+        delete_session(&ctx, dir, "login").await.unwrap();
+        assert!(delete_session(&ctx, dir, "login").await.is_err());
+        let names = read_index(&ctx, dir).await.sessions.into_keys().collect::<Vec<_>>();
+        assert_eq!(names, vec!["older".to_string()]);
+        assert!(ctx.fs().exists(dir.join("older.json")));
+    }
+}
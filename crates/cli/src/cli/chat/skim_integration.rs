@@ -1,9 +1,11 @@
+use std::collections::HashSet;
 use std::io::{
     BufReader,
     Cursor,
     Write,
     stdout,
 };
+use std::sync::Mutex;
 
 use crossterm::execute;
 use crossterm::terminal::{
@@ -24,6 +26,7 @@ use skim::prelude::*;
 use tempfile::NamedTempFile;
 
 use super::context::ContextManager;
+use super::editor::create_line_replacement_command;
 
 pub fn select_profile_with_skim(context_manager: &ContextManager) -> Result<Option<String>> {
     let profiles = context_manager.list_profiles_blocking()?;
@@ -66,6 +69,85 @@ impl ConditionalEventHandler for SkimCommandSelector {
     }
 }
 
+/// Fuzzy searches the prompt history, bound to Ctrl+R in place of rustyline's substring search.
+pub struct SkimHistorySelector {
+    history: Arc<Mutex<Vec<String>>>,
+}
+
+impl SkimHistorySelector {
+    pub fn new(history: Arc<Mutex<Vec<String>>>) -> Self {
+        Self { history }
+    }
+}
+
+impl ConditionalEventHandler for SkimHistorySelector {
+    fn handle(&self, _evt: &rustyline::Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        let history = match self.history.lock() {
+            Ok(history) => history.clone(),
+            Err(_) => return Some(Cmd::Noop),
+        };
+
+        match select_history_with_skim(&history, ctx.line()) {
+            Ok(Some(prompt)) => Some(create_line_replacement_command(prompt)),
+            // Skim took over the screen, so the prompt has to be redrawn either way
+            _ => Some(Cmd::Repaint),
+        }
+    }
+}
+
+/// A prompt from the history, shown on a single line.
+struct HistoryItem {
+    prompt: String,
+    line: String,
+}
+
+impl SkimItem for HistoryItem {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.line)
+    }
+
+    fn output(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.prompt)
+    }
+}
+
+/// The prompts to search, most recent first and without duplicates.
+fn history_candidates(history: &[String]) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    history
+        .iter()
+        .rev()
+        .map(|prompt| prompt.as_str())
+        .filter(|prompt| !prompt.trim().is_empty() && seen.insert(*prompt))
+        .collect()
+}
+
+/// Fuzzy search `history`, starting from `query`, and return the selected prompt
+pub fn select_history_with_skim(history: &[String], query: &str) -> Result<Option<String>> {
+    let candidates = history_candidates(history);
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    let mut options = create_skim_options("Search history: ", false)?;
+    options.query = (!query.is_empty()).then(|| query.to_string());
+
+    let (sender, items): (SkimItemSender, SkimItemReceiver) = unbounded();
+    for prompt in candidates {
+        let _ = sender.send(Arc::new(HistoryItem {
+            prompt: prompt.to_string(),
+            // Multi-line prompts are listed on one line
+            line: prompt.replace('\n', " ⏎ "),
+        }));
+    }
+    drop(sender);
+
+    match run_skim_with_options(&options, items)? {
+        Some(items) if !items.is_empty() => Ok(Some(items[0].output().to_string())),
+        _ => Ok(None),
+    }
+}
+
 pub fn get_available_commands() -> Vec<String> {
     // Import the COMMANDS array directly from prompt.rs
     // This is the single source of truth for available commands
@@ -343,10 +425,14 @@ impl CommandType {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashSet;
-
     use super::*;
 
+    #[test]
+    fn test_history_candidates() {
+        let history = ["first", "second", "", "first", "multi\nline"].map(String::from);
+        assert_eq!(history_candidates(&history), vec!["multi\nline", "first", "second"]);
+    }
+
     /// Test to verify that all hardcoded command strings in select_command
     /// are present in the COMMANDS array from prompt.rs
     #[test]
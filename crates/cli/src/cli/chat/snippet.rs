@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use rustyline::{
+    Cmd,
+    ConditionalEventHandler,
+    Event,
+    EventContext,
+    Movement,
+    RepeatCount,
+};
+
+use super::prompt::{
+    expand_variables,
+    git_branch,
+};
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// Reusable prompt text from `chat.snippets`, a JSON object mapping snippet names to their text,
+/// e.g. `{ "review": "Review the changes on {branch} for bugs and missing tests." }`.
+///
+/// Typing `!name` and pressing Tab expands the snippet in place. The text may use the `{cwd}`,
+/// `{branch}` and `{date}` placeholders, which are filled in when expanding.
+#[derive(Debug, Clone, Default)]
+pub struct Snippets(HashMap<String, String>);
+
+impl Snippets {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let snippets = settings
+            .get(Setting::ChatSnippets)
+            .and_then(|value| value.as_object())
+            .map(|snippets| {
+                snippets
+                    .iter()
+                    .filter_map(|(name, text)| Some((name.clone(), text.as_str()?.to_owned())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self(snippets)
+    }
+
+    /// The text of the snippet called `name`, with its placeholders filled in.
+    pub fn expand(&self, name: &str) -> Option<String> {
+        let text = self.0.get(name)?;
+        Some(expand_variables(text, |placeholder| match placeholder {
+            "cwd" => std::env::current_dir()
+                .ok()
+                .map(|cwd| cwd.to_string_lossy().into_owned()),
+            "branch" => Some(git_branch().unwrap_or_default()),
+            "date" => Some(
+                time::OffsetDateTime::now_local()
+                    .unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+                    .date()
+                    .to_string(),
+            ),
+            _ => None,
+        }))
+    }
+}
+
+/// The snippet reference (`!name`) ending at `pos` in `line`, if any.
+fn snippet_reference(line: &str, pos: usize) -> Option<&str> {
+    let before = line.get(..pos)?;
+    let word = &before[before.rfind(char::is_whitespace).map_or(0, |i| i + 1)..];
+    word.strip_prefix('!')
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_'))
+}
+
+/// Expands the snippet typed before the cursor on Tab, and completes as usual otherwise.
+pub struct SnippetExpander(pub Snippets);
+
+impl ConditionalEventHandler for SnippetExpander {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext<'_>) -> Option<Cmd> {
+        let name = snippet_reference(ctx.line(), ctx.pos())?;
+        let text = self.0.expand(name)?;
+        // The `!` is removed along with the name
+        Some(Cmd::Replace(
+            Movement::BackwardChar(name.chars().count() + 1),
+            Some(text),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippet_reference() {
+        assert_eq!(snippet_reference("!review", 7), Some("review"));
+        assert_eq!(snippet_reference("please !fix-tests now", 17), Some("fix-tests"));
+        assert_eq!(snippet_reference("please !fix-tests now", 10), Some("fi"));
+        assert_eq!(snippet_reference("!", 1), None);
+        assert_eq!(snippet_reference("review", 6), None);
+        assert_eq!(snippet_reference("!ls -la", 7), None);
+        assert_eq!(snippet_reference("!cat /etc/hosts", 15), None);
+    }
+
+    #[tokio::test]
+    async fn test_snippets_from_settings() {
+        let mut settings = Settings::new().await.unwrap();
+        assert!(Snippets::from_settings(&settings).expand("review").is_none());
+
+        settings
+            .set(
+                Setting::ChatSnippets,
+                serde_json::json!({
+                    "review": "Review {{carefully}} in {unknown}",
+                    "here": "Look at {cwd}",
+                    "invalid": 42,
+                }),
+            )
+            .await
+            .unwrap();
+        let snippets = Snippets::from_settings(&settings);
+        assert_eq!(snippets.expand("review").unwrap(), "Review {carefully} in {unknown}");
+        assert_eq!(
+            snippets.expand("here").unwrap(),
+            format!("Look at {}", std::env::current_dir().unwrap().display())
+        );
+        assert!(snippets.expand("invalid").is_none());
+        assert!(snippets.expand("missing").is_none());
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::Write;
+
+use spinners::{
+    Spinner,
+    Spinners,
+};
+
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+
+/// How the spinner shown while waiting on the model looks, from `chat.spinner.style`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpinnerStyle {
+    /// Braille dots going around.
+    #[default]
+    Braille,
+    /// Periods filling up, for fonts without braille characters.
+    Dots,
+    /// The message printed once without animating, so that screen recordings and logs aren't
+    /// filled with redraws. Used with `--no-spinner` and in CI.
+    Plain,
+}
+
+impl SpinnerStyle {
+    fn parse(style: &str) -> Option<Self> {
+        match style.to_lowercase().as_str() {
+            "braille" => Some(Self::Braille),
+            "dots" => Some(Self::Dots),
+            "plain" | "none" | "off" => Some(Self::Plain),
+            _ => None,
+        }
+    }
+}
+
+/// The spinner settings for a chat session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpinnerConfig {
+    pub style: SpinnerStyle,
+    /// Whether the time spent waiting is shown next to the spinner, from `chat.spinner.elapsed`.
+    pub elapsed: bool,
+}
+
+impl SpinnerConfig {
+    pub fn resolve(no_spinner: bool, ctx: &Context, settings: &Settings) -> Self {
+        let style = match no_spinner || ctx.env().in_ci() {
+            true => SpinnerStyle::Plain,
+            false => settings
+                .get_string(Setting::ChatSpinnerStyle)
+                .and_then(|style| SpinnerStyle::parse(&style))
+                .unwrap_or_default(),
+        };
+        Self {
+            style,
+            elapsed: settings.get_bool(Setting::ChatSpinnerElapsed).unwrap_or(false),
+        }
+    }
+
+    /// Shows `message` along with the spinner until the returned [ChatSpinner] is dropped. The
+    /// line is left for the caller to clear.
+    pub fn start(&self, message: impl Into<String>) -> ChatSpinner {
+        let message = message.into();
+        let frames = match self.style {
+            SpinnerStyle::Braille => Spinners::Dots,
+            SpinnerStyle::Dots => Spinners::SimpleDots,
+            SpinnerStyle::Plain => {
+                // Spinners draw on stderr
+                let mut stderr = std::io::stderr();
+                let _ = write!(stderr, "{message}");
+                let _ = stderr.flush();
+                return ChatSpinner::Plain;
+            },
+        };
+        ChatSpinner::Animated(match self.elapsed {
+            true => Spinner::with_timer(frames, message),
+            false => Spinner::new(frames, message),
+        })
+    }
+}
+
+/// A spinner started by [SpinnerConfig::start].
+pub enum ChatSpinner {
+    Animated(Spinner),
+    Plain,
+}
+
+impl ChatSpinner {
+    pub fn stop(&mut self) {
+        if let ChatSpinner::Animated(spinner) = self {
+            spinner.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Env;
+
+    #[tokio::test]
+    async fn test_spinner_config_resolve() {
+        let ctx = Context::builder().with_env(Env::from_slice(&[])).build_fake();
+        let mut settings = Settings::new().await.unwrap();
+        assert_eq!(SpinnerConfig::resolve(false, &ctx, &settings), SpinnerConfig::default());
+
+        settings.set(Setting::ChatSpinnerStyle, "Dots").await.unwrap();
+        settings.set(Setting::ChatSpinnerElapsed, true).await.unwrap();
+        assert_eq!(SpinnerConfig::resolve(false, &ctx, &settings), SpinnerConfig {
+            style: SpinnerStyle::Dots,
+            elapsed: true,
+        });
+
+        // --no-spinner and CI always print a plain line
+        assert_eq!(SpinnerConfig::resolve(true, &ctx, &settings).style, SpinnerStyle::Plain);
+        let ci = Context::builder()
+            .with_env(Env::from_slice(&[("CI", "true")]))
+            .build_fake();
+        assert_eq!(SpinnerConfig::resolve(false, &ci, &settings).style, SpinnerStyle::Plain);
+
+        settings.set(Setting::ChatSpinnerStyle, "unknown").await.unwrap();
+        assert_eq!(
+            SpinnerConfig::resolve(false, &ctx, &settings).style,
+            SpinnerStyle::Braille
+        );
+    }
+}
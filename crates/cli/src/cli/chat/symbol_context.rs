@@ -0,0 +1,560 @@
+use std::path::Path;
+
+use eyre::{
+    Result,
+    bail,
+};
+use regex::Regex;
+
+use crate::platform::Context;
+
+/// The words starting lines that call a function rather than define it.
+const CALL_KEYWORDS: &[&str] = &["return", "new", "else", "throw", "await", "yield", "case", "delete"];
+
+/// The languages definitions can be found in, by how their blocks and imports are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Rust,
+    Python,
+    Go,
+    JavaScript,
+    /// Java, Kotlin, Scala and C#.
+    Jvm,
+    /// C and C++.
+    C,
+}
+
+impl Language {
+    fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        Some(match extension.as_str() {
+            "rs" => Self::Rust,
+            "py" | "pyi" => Self::Python,
+            "go" => Self::Go,
+            "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" => Self::JavaScript,
+            "java" | "kt" | "kts" | "scala" | "cs" => Self::Jvm,
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => Self::C,
+            _ => return None,
+        })
+    }
+
+    /// The patterns of the lines defining `name`, those of the declarations with a keyword first,
+    /// then those of the methods and functions written without one.
+    fn definition_patterns(self, name: &str) -> Vec<String> {
+        let name = regex::escape(name);
+        match self {
+            Self::Rust => vec![
+                format!(
+                    r#"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:const|async|unsafe|extern\s+"[^"]*")\s+)*(?:fn|struct|enum|union|trait|type|mod|const|static(?:\s+mut)?)\s+{name}\b"#
+                ),
+                format!(r"^\s*macro_rules!\s*{name}\b"),
+                format!(r"^\s*(?:unsafe\s+)?impl(?:<[^>]*>)?\s+(?:[\w:<>, ]+\s+for\s+)?{name}\b"),
+            ],
+            Self::Python => vec![
+                format!(r"^\s*(?:async\s+def|def|class)\s+{name}\b"),
+                format!(r"^{name}\s*(?::[^=]*)?="),
+            ],
+            Self::Go => vec![format!(
+                r"^\s*(?:func\s+(?:\([^)]*\)\s*)?|type\s+|var\s+|const\s+){name}\b"
+            )],
+            Self::JavaScript => vec![
+                format!(
+                    r"^\s*(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(?:function\s*\*?|class|interface|type|enum|namespace|const|let|var)\s*{name}\b"
+                ),
+                format!(
+                    r"^\s*(?:(?:public|private|protected|static|async|readonly|get|set|override)\s+)*\*?{name}\s*(?:<[^>]*>)?\s*\("
+                ),
+            ],
+            Self::Jvm => vec![
+                format!(
+                    r"^\s*(?:(?:public|private|protected|internal|static|final|abstract|sealed|open|data|override|partial|readonly|case)\s+)*(?:class|interface|enum|record|object|struct|trait|fun|def|val|var)\s+{name}\b"
+                ),
+                format!(r"^\s*(?:@\w+\s+)*(?:[\w<>\[\],.?]+\s+)+{name}\s*\("),
+            ],
+            Self::C => vec![
+                format!(r"^\s*(?:typedef\s+)?(?:struct|union|enum|class|namespace)\s+{name}\b"),
+                format!(r"^\s*#\s*define\s+{name}\b"),
+                format!(r"^\s*(?:[\w:<>,]+[\s*&]+)+{name}\s*\("),
+            ],
+        }
+    }
+
+    /// Whether `line` starts an import.
+    fn is_import(self, line: &str) -> bool {
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        match self {
+            Self::Rust => {
+                keyword == "use"
+                    || line.starts_with("extern crate ")
+                    || (keyword.starts_with("pub") && line.contains(" use "))
+            },
+            Self::Python => keyword == "import" || keyword == "from",
+            Self::Go => keyword == "import",
+            Self::JavaScript => {
+                keyword == "import" || (matches!(keyword, "const" | "let" | "var") && line.contains("require("))
+            },
+            Self::Jvm => keyword == "import" || keyword == "using",
+            Self::C => {
+                (keyword.starts_with('#') && line.contains("include")) || keyword == "using" || keyword == "import"
+            },
+        }
+    }
+
+    /// Whether the import started by `first` goes on after `line`.
+    fn import_continues(self, first: &str, line: &str) -> bool {
+        match self {
+            Self::Rust => !line.contains(';'),
+            Self::Python => (first.contains('(') && !line.contains(')')) || line.ends_with('\\'),
+            Self::Go => first.contains('(') && !line.contains(')'),
+            Self::JavaScript => first.contains('{') && !line.contains('}'),
+            Self::Jvm | Self::C => false,
+        }
+    }
+
+    /// Whether `line` comes before a definition as a part of it: doc comments, attributes,
+    /// decorators and annotations.
+    fn is_preamble(self, line: &str) -> bool {
+        let line = line.trim_start();
+        match self {
+            Self::Python => line.starts_with('#') || line.starts_with('@'),
+            Self::Rust => line.starts_with("//") || line.starts_with("#["),
+            _ => {
+                line.starts_with("//")
+                    || line.starts_with("/*")
+                    || line.starts_with('*')
+                    || line.starts_with('@')
+                    || line.starts_with('[')
+            },
+        }
+    }
+}
+
+/// A definition referenced with `@path#name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// The first and last lines of the definition, from 1, its doc comments and attributes
+    /// included.
+    pub start_line: usize,
+    pub end_line: usize,
+    /// The imports of the file followed by the definition.
+    pub text: String,
+}
+
+/// The `@path#name` references in `prompt`, for the definitions to add to the context.
+pub fn symbol_references(prompt: &str) -> Vec<(&str, &str)> {
+    let mut references = Vec::new();
+    for word in prompt.split_whitespace() {
+        let Some(word) = word.trim_start_matches('`').strip_prefix('@') else {
+            continue;
+        };
+        if word.starts_with("https://") || word.starts_with("http://") {
+            continue;
+        }
+        let word = word.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'', '`']);
+        let Some((path, name)) = word.rsplit_once('#') else {
+            continue;
+        };
+        let is_identifier = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !path.is_empty() && is_identifier && !references.contains(&(path, name)) {
+            references.push((path, name));
+        }
+    }
+    references
+}
+
+/// Reads the definition of `name` from the file at `path`, relative to `cwd`.
+pub async fn load_symbol(ctx: &Context, cwd: &Path, path: &str, name: &str) -> Result<Symbol> {
+    if Language::from_path(path).is_none() {
+        bail!("definitions can't be found in this kind of file, reference @{path} instead");
+    }
+    let source = ctx.fs().read_to_string(cwd.join(path)).await?;
+    match find_symbol(path, &source, name) {
+        Some(symbol) => Ok(symbol),
+        None => bail!("{path} has no definition of {name}"),
+    }
+}
+
+/// The definition of `name` in `source`, the contents of the file at `path`, along with the
+/// imports of the file. The first definition is taken when there are several.
+pub fn find_symbol(path: &str, source: &str, name: &str) -> Option<Symbol> {
+    let language = Language::from_path(path)?;
+    let lines = source.lines().collect::<Vec<_>>();
+    let start = language
+        .definition_patterns(name)
+        .iter()
+        .filter_map(|pattern| Regex::new(pattern).ok())
+        .find_map(|regex| {
+            lines.iter().position(|line| {
+                regex.find(line).is_some_and(|found| {
+                    // Patterns of functions without a keyword also match their calls and
+                    // prototypes
+                    let first_word = line.split_whitespace().next().unwrap_or_default();
+                    !found.as_str().ends_with('(')
+                        || !(line.trim_end().ends_with(';') || CALL_KEYWORDS.contains(&first_word))
+                })
+            })
+        })?;
+    let end = match language {
+        Language::Python => indented_block_end(&lines, start),
+        _ => block_end(&lines, start, language),
+    };
+    let first = (0..start)
+        .rev()
+        .take_while(|&index| language.is_preamble(lines[index]))
+        .last()
+        .unwrap_or(start);
+
+    let imports = imports(&lines, language);
+    let definition = lines[first..=end].join("\n");
+    let text = match imports.is_empty() {
+        true => definition,
+        false => format!("{}\n\n{definition}", imports.join("\n")),
+    };
+    Some(Symbol {
+        start_line: first + 1,
+        end_line: end + 1,
+        text,
+    })
+}
+
+/// The top level imports of a file.
+fn imports<'a>(lines: &[&'a str], language: Language) -> Vec<&'a str> {
+    let mut imports = Vec::new();
+    let mut index = 0;
+    while index < lines.len() {
+        let first = lines[index];
+        if first.starts_with(char::is_whitespace) || !language.is_import(first) {
+            index += 1;
+            continue;
+        }
+        imports.push(first);
+        let mut line = first;
+        while language.import_continues(first, line) && index + 1 < lines.len() {
+            index += 1;
+            line = lines[index];
+            imports.push(line);
+        }
+        index += 1;
+    }
+    imports
+}
+
+/// The last line of the definition starting at `start` in a language with braces: the line
+/// closing its block, or ending the declaration when it has none.
+fn block_end(lines: &[&str], start: usize, language: Language) -> usize {
+    let indentation = indentation(lines[start]);
+    let mut depth = 0;
+    let mut parens = 0;
+    let mut opened = false;
+    let mut in_comment = false;
+    let mut in_string = None;
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_comment {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    in_comment = false;
+                }
+                continue;
+            }
+            if let Some(quote) = in_string {
+                match c {
+                    '\\' => {
+                        chars.next();
+                    },
+                    c if c == quote => in_string = None,
+                    _ => (),
+                }
+                continue;
+            }
+            match c {
+                '/' if chars.peek() == Some(&'/') => break,
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    in_comment = true;
+                },
+                '"' | '`' => in_string = Some(c),
+                // A char literal, else the lifetime `'a`
+                '\'' if language == Language::Rust => {
+                    let mut lookahead = chars.clone();
+                    match (lookahead.next(), lookahead.next()) {
+                        (Some('\\'), _) => {
+                            for c in chars.by_ref() {
+                                if c == '\'' {
+                                    break;
+                                }
+                            }
+                        },
+                        (Some(_), Some('\'')) => {
+                            chars.next();
+                            chars.next();
+                        },
+                        _ => (),
+                    }
+                },
+                '\'' => in_string = Some(c),
+                '(' | '[' if !opened => parens += 1,
+                ')' | ']' if !opened => parens -= 1,
+                // Braces in the signature, e.g. of default arguments, don't start the block
+                '{' if opened || parens == 0 => {
+                    depth += 1;
+                    opened = true;
+                },
+                '}' if opened => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return index;
+                    }
+                },
+                ';' if !opened && parens == 0 => return index,
+                _ => (),
+            }
+        }
+        // Strings in single quotes don't span lines
+        if in_string == Some('\'') {
+            in_string = None;
+        }
+        if !opened && parens <= 0 && in_string.is_none() && !continues(lines, index, indentation) {
+            return index;
+        }
+    }
+    lines.len() - 1
+}
+
+/// Whether the declaration without a block yet goes on after the line at `index`, e.g. a
+/// signature with its `where` clause or its opening brace on the next line.
+fn continues(lines: &[&str], index: usize, indentation: usize) -> bool {
+    const CONTINUING_ENDS: &[&str] = &[",", "(", "=", "->", "=>", "|", "&", "+", ":", "\\", "<"];
+    const CONTINUING_STARTS: &[&str] = &[
+        "{",
+        "where",
+        "->",
+        "=>",
+        ":",
+        ".",
+        "|",
+        "&",
+        "+",
+        "=",
+        "extends",
+        "implements",
+        "throws",
+    ];
+    let line = lines[index].trim_end();
+    if CONTINUING_ENDS.iter().any(|end| line.ends_with(end)) {
+        return true;
+    }
+    let Some(next) = lines[index + 1..].iter().find(|line| !line.trim().is_empty()) else {
+        return false;
+    };
+    let trimmed = next.trim_start();
+    CONTINUING_STARTS.iter().any(|start| trimmed.starts_with(start))
+        || (self::indentation(next) > indentation && !trimmed.starts_with("//") && !trimmed.starts_with('#'))
+}
+
+/// The last line of the Python definition starting at `start`: its signature, which can span
+/// lines, followed by the lines indented more than it.
+fn indented_block_end(lines: &[&str], start: usize) -> usize {
+    let indentation = indentation(lines[start]);
+    let mut parens = 0;
+    let mut header_end = start;
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        for c in line.chars() {
+            match c {
+                '(' | '[' | '{' => parens += 1,
+                ')' | ']' | '}' => parens -= 1,
+                '#' => break,
+                _ => (),
+            }
+        }
+        header_end = index;
+        if parens <= 0 {
+            break;
+        }
+    }
+    if !lines[header_end].trim_end().ends_with(':') {
+        return header_end;
+    }
+    let mut end = header_end;
+    for (index, line) in lines.iter().enumerate().skip(header_end + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if self::indentation(line) <= indentation {
+            break;
+        }
+        end = index;
+    }
+    end
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(path: &str, source: &str, name: &str) -> Option<String> {
+        let symbol = find_symbol(path, source, name)?;
+        // Without the imports
+        let lines = source.lines().collect::<Vec<_>>();
+        assert!(
+            symbol
+                .text
+                .ends_with(&lines[symbol.start_line - 1..symbol.end_line].join("\n"))
+        );
+        Some(lines[symbol.start_line - 1..symbol.end_line].join("\n"))
+    }
+
+    #[test]
+    fn test_symbol_references() {
+        assert_eq!(
+            symbol_references("Why does @src/lib.rs#parse_config, unlike `@src/main.rs#run`, fail?"),
+            vec![("src/lib.rs", "parse_config"), ("src/main.rs", "run")]
+        );
+        assert!(symbol_references("See @https://example.com/#intro, @docs/spec.pdf#page=2 and @src/lib.rs").is_empty());
+    }
+
+    #[test]
+    fn test_find_rust_symbol() {
+        let source = r#"use std::collections::HashMap;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+struct Marker;
+
+/// Parses the configuration.
+#[tracing::instrument]
+pub fn parse_config<'a, T>(text: &'a str) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    let open = '{';
+    // A brace in a comment }
+    let s = "}";
+    if text.is_empty() {
+        bail!("empty");
+    }
+    Ok(toml::from_str(text)?)
+}
+
+fn other() {}
+"#;
+        let symbol = find_symbol("src/lib.rs", source, "parse_config").unwrap();
+        assert_eq!((symbol.start_line, symbol.end_line), (9, 22));
+        assert!(symbol.text.starts_with(
+            "use std::collections::HashMap;\nuse serde::{\n    Deserialize,\n    Serialize,\n};\n\n/// Parses"
+        ));
+        assert!(symbol.text.ends_with("Ok(toml::from_str(text)?)\n}"));
+        assert_eq!(definition("src/lib.rs", source, "Marker").unwrap(), "struct Marker;");
+        assert_eq!(definition("src/lib.rs", source, "other").unwrap(), "fn other() {}");
+        assert_eq!(find_symbol("src/lib.rs", source, "missing"), None);
+        assert_eq!(find_symbol("notes.txt", source, "other"), None);
+    }
+
+    #[test]
+    fn test_find_python_symbol() {
+        let source = "import os
+from typing import (
+    Any,
+)
+
+@cache
+def load(
+    path: str,
+) -> Any:
+    with open(path) as f:
+
+        return f.read()
+
+class Config:
+    pass
+
+TIMEOUT = 30
+";
+        let symbol = find_symbol("app/config.py", source, "load").unwrap();
+        assert_eq!((symbol.start_line, symbol.end_line), (6, 12));
+        assert!(
+            symbol
+                .text
+                .starts_with("import os\nfrom typing import (\n    Any,\n)\n\n@cache\n")
+        );
+        assert_eq!(
+            definition("app/config.py", source, "Config").unwrap(),
+            "class Config:\n    pass"
+        );
+        assert_eq!(definition("app/config.py", source, "TIMEOUT").unwrap(), "TIMEOUT = 30");
+    }
+
+    #[test]
+    fn test_find_symbol_in_braces_languages() {
+        let source = "import { readFile } from 'fs';
+import {
+  join,
+} from 'path'
+
+export async function load(path: string, options = { strict: true }) {
+  const text = `} ${path}`;
+  return text;
+}
+
+export type Mode = 'a' | 'b'
+class Loader {
+  private parse(text: string): Ast {
+    return parser(text);
+  }
+}
+";
+        let symbol = find_symbol("src/load.ts", source, "load").unwrap();
+        assert_eq!((symbol.start_line, symbol.end_line), (6, 9));
+        assert!(
+            symbol
+                .text
+                .starts_with("import { readFile } from 'fs';\nimport {\n  join,\n} from 'path'\n\n")
+        );
+        assert_eq!(
+            definition("src/load.ts", source, "Mode").unwrap(),
+            "export type Mode = 'a' | 'b'"
+        );
+        assert_eq!(
+            definition("src/load.ts", source, "parse").unwrap(),
+            "  private parse(text: string): Ast {\n    return parser(text);\n  }"
+        );
+
+        let source = "package retry
+
+import (
+\t\"time\"
+)
+
+// Do retries f.
+func (p *Policy) Do(f func() error) error {
+\treturn f()
+}
+";
+        let symbol = find_symbol("retry.go", source, "Do").unwrap();
+        assert_eq!((symbol.start_line, symbol.end_line), (7, 10));
+        assert!(symbol.text.starts_with("import (\n\t\"time\"\n)\n\n// Do retries f."));
+
+        let source = "import java.util.List;
+
+class Retry {
+    @Override
+    public static <T> List<T> withRetries(int attempts)
+            throws IOException {
+        return List.of();
+    }
+}
+";
+        assert_eq!(
+            definition("Retry.java", source, "withRetries").unwrap(),
+            "    @Override\n    public static <T> List<T> withRetries(int attempts)\n            throws IOException {\n        return List.of();\n    }"
+        );
+    }
+}
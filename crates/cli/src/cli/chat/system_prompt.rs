@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::PathBuf;
+
+use tracing::warn;
+
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// The name of the file with the system prompt of a profile, in its directory.
+pub const PROFILE_SYSTEM_PROMPT_FILENAME: &str = "system-prompt.md";
+
+/// Instructions the user gave for every conversation, sent before anything else in the context
+/// and kept by `/clear`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemPrompt {
+    pub text: String,
+    pub source: SystemPromptSource,
+}
+
+/// Where a [SystemPrompt] is from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemPromptSource {
+    /// The file of the profile, written with `/system edit`.
+    Profile(PathBuf),
+    /// `chat.systemPrompt`, for the profiles without their own.
+    Setting,
+}
+
+/// `chat.systemPrompt`, either the instructions or the path of a file with them.
+pub fn from_settings(settings: &Settings) -> Option<String> {
+    let value = settings.get_string(Setting::ChatSystemPrompt)?;
+    let value = value.trim();
+    let is_path =
+        !value.contains('\n') && (value.starts_with('/') || value.starts_with('~') || value.starts_with("./"));
+    let text = match is_path {
+        true => {
+            let path = shellexpand::tilde(value);
+            match fs::read_to_string(path.as_ref()) {
+                Ok(text) => text,
+                Err(err) => {
+                    warn!(?err, %path, "Failed to read the system prompt");
+                    return None;
+                },
+            }
+        },
+        false => value.to_string(),
+    };
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_from_settings() {
+        let mut settings = Settings::new().await.unwrap();
+        assert_eq!(from_settings(&settings), None);
+
+        settings
+            .set(Setting::ChatSystemPrompt, " Always answer in Portuguese. ")
+            .await
+            .unwrap();
+        assert_eq!(
+            from_settings(&settings).as_deref(),
+            Some("Always answer in Portuguese.")
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system.md");
+        fs::write(&path, "Prefer CDK over Terraform.\n").unwrap();
+        settings
+            .set(Setting::ChatSystemPrompt, path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+        assert_eq!(from_settings(&settings).as_deref(), Some("Prefer CDK over Terraform."));
+
+        settings
+            .set(
+                Setting::ChatSystemPrompt,
+                dir.path().join("missing.md").to_string_lossy().to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(from_settings(&settings), None);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs;
+
+use crossterm::style::Color;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// Colors of the chat UI, selected with `chat.theme`.
+///
+/// `chat.theme` is either the name of a built-in theme (see [Theme::builtin]) or the path to a
+/// JSON file overriding some colors of a built-in theme, e.g.
+/// `{ "base": "light", "prompt": "blue", "tool": "#d33682" }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// The `>` of the prompt.
+    pub prompt: Color,
+    /// The `[profile]` shown in the prompt.
+    pub profile: Color,
+    /// Plain text of the assistant's responses.
+    pub assistant: Color,
+    /// The `Using tool` banners shown before a tool runs.
+    pub tool: Color,
+    /// Errors, and the `!` shown in the prompt when all tools are trusted.
+    pub error: Color,
+}
+
+impl Theme {
+    pub const DARK: Theme = Theme {
+        prompt: Color::Magenta,
+        profile: Color::Cyan,
+        assistant: Color::Reset,
+        tool: Color::Magenta,
+        error: Color::Red,
+    };
+    pub const LIGHT: Theme = Theme {
+        prompt: Color::DarkMagenta,
+        profile: Color::DarkCyan,
+        assistant: Color::Reset,
+        tool: Color::DarkMagenta,
+        error: Color::DarkRed,
+    };
+    pub const NO_COLOR: Theme = Theme {
+        prompt: Color::Reset,
+        profile: Color::Reset,
+        assistant: Color::Reset,
+        tool: Color::Reset,
+        error: Color::Reset,
+    };
+    pub const SOLARIZED: Theme = Theme {
+        prompt: Color::Rgb { r: 211, g: 54, b: 130 },
+        profile: Color::Rgb { r: 42, g: 161, b: 152 },
+        assistant: Color::Rgb { r: 131, g: 148, b: 150 },
+        tool: Color::Rgb { r: 108, g: 113, b: 196 },
+        error: Color::Rgb { r: 220, g: 50, b: 47 },
+    };
+
+    pub fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Self::DARK),
+            "light" => Some(Self::LIGHT),
+            "solarized" => Some(Self::SOLARIZED),
+            "no-color" => Some(Self::NO_COLOR),
+            _ => None,
+        }
+    }
+
+    /// Resolves the theme from `chat.theme`, falling back to the dark theme. The no-color theme is
+    /// always used when `NO_COLOR` is set.
+    pub fn from_settings(settings: &Settings) -> Theme {
+        if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return Self::NO_COLOR;
+        }
+
+        let Some(value) = settings.get_string(Setting::ChatTheme) else {
+            return Self::DARK;
+        };
+        if let Some(theme) = Self::builtin(&value) {
+            return theme;
+        }
+
+        let path = shellexpand::tilde(&value);
+        match fs::read_to_string(path.as_ref())
+            .map_err(|err| err.to_string())
+            .and_then(|content| Self::from_json(&content))
+        {
+            Ok(theme) => theme,
+            Err(err) => {
+                warn!(%err, %path, "Failed to load the chat theme");
+                Self::DARK
+            },
+        }
+    }
+
+    /// Parses a user-defined theme file, see [Theme].
+    fn from_json(content: &str) -> Result<Theme, String> {
+        let file: ThemeFile = serde_json::from_str(content).map_err(|err| err.to_string())?;
+        let mut theme = match file.base.as_deref() {
+            Some(base) => Self::builtin(base).ok_or_else(|| format!("Unknown base theme: {base}"))?,
+            None => Self::DARK,
+        };
+
+        for (color, value) in [
+            (&mut theme.prompt, file.prompt),
+            (&mut theme.profile, file.profile),
+            (&mut theme.assistant, file.assistant),
+            (&mut theme.tool, file.tool),
+            (&mut theme.error, file.error),
+        ] {
+            if let Some(value) = value {
+                *color = parse_color(&value).ok_or_else(|| format!("Invalid color: {value}"))?;
+            }
+        }
+
+        Ok(theme)
+    }
+
+    pub fn is_no_color(&self) -> bool {
+        *self == Self::NO_COLOR
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DARK
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ThemeFile {
+    base: Option<String>,
+    prompt: Option<String>,
+    profile: Option<String>,
+    assistant: Option<String>,
+    tool: Option<String>,
+    error: Option<String>,
+}
+
+/// Parses a color name such as `dark_magenta`, or a hex color such as `#d33682`.
+fn parse_color(value: &str) -> Option<Color> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+
+    Color::try_from(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("dark_magenta"), Some(Color::DarkMagenta));
+        assert_eq!(parse_color("Red"), Some(Color::Red));
+        assert_eq!(parse_color("#d33682"), Some(Color::Rgb { r: 211, g: 54, b: 130 }));
+        assert_eq!(parse_color("#d3368"), None);
+        assert_eq!(parse_color("#gggggg"), None);
+        assert_eq!(parse_color("mauve"), None);
+    }
+
+    #[test]
+    fn test_theme_from_json() {
+        let theme = Theme::from_json(r##"{ "base": "light", "prompt": "blue", "tool": "#6c71c4" }"##).unwrap();
+        assert_eq!(theme, Theme {
+            prompt: Color::Blue,
+            tool: Color::Rgb { r: 108, g: 113, b: 196 },
+            ..Theme::LIGHT
+        });
+
+        assert_eq!(Theme::from_json("{}").unwrap(), Theme::DARK);
+        assert!(Theme::from_json(r#"{ "base": "sepia" }"#).is_err());
+        assert!(Theme::from_json(r#"{ "error": "mauve" }"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_theme_from_settings() {
+        let mut settings = Settings::new().await.unwrap();
+        assert_eq!(Theme::from_settings(&settings), Theme::DARK);
+
+        settings.set(Setting::ChatTheme, "solarized").await.unwrap();
+        assert_eq!(Theme::from_settings(&settings), Theme::SOLARIZED);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theme.json");
+        fs::write(&path, r#"{ "base": "no-color", "error": "red" }"#).unwrap();
+        settings.set(Setting::ChatTheme, path.to_string_lossy()).await.unwrap();
+        assert_eq!(Theme::from_settings(&settings), Theme {
+            error: Color::Red,
+            ..Theme::NO_COLOR
+        });
+
+        // Missing files fall back to the default theme
+        settings.set(Setting::ChatTheme, "/does/not/exist.json").await.unwrap();
+        assert_eq!(Theme::from_settings(&settings), Theme::DARK);
+    }
+}
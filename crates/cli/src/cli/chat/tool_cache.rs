@@ -0,0 +1,266 @@
+//! The results of the tools that only read the workspace, `fs_read`, `grep_search` and
+//! `glob_files`, kept for the session so that the same use again isn't run again while the files
+//! it read are unchanged. The model is then pointed at the earlier result when it's still in the
+//! conversation, rather than given the same output twice. `/cache clear` forgets them.
+//!
+//! A use is the same when the tool, its arguments and the current directory are. Files are taken
+//! to be unchanged while their modification time and size are, and the files of a directory while
+//! the same files are found in it.
+
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use super::context::walk_dir;
+use super::tools::fs_read::FsRead;
+use super::tools::{
+    InvokeOutput,
+    OutputKind,
+    QueuedTool,
+    Tool,
+    sanitize_path_tool_arg,
+};
+use crate::platform::Context;
+
+/// The modification time and size of each file a use reads, or of each file and directory found
+/// when listing a directory, along with their path.
+type Fingerprint = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+#[derive(Debug)]
+struct Entry {
+    fingerprint: Fingerprint,
+    /// The use whose result this is.
+    tool_use_id: String,
+    output: OutputKind,
+}
+
+/// What's known of a use before it runs.
+#[derive(Debug)]
+pub enum Lookup {
+    /// An earlier use of the same files, unchanged since.
+    Hit { tool_use_id: String, output: OutputKind },
+    /// The result is saved with [ToolCache::insert] once the use has run.
+    Miss(Miss),
+    /// The tool isn't one whose results are kept.
+    Uncached,
+}
+
+#[derive(Debug)]
+pub struct Miss {
+    key: String,
+    fingerprint: Fingerprint,
+}
+
+#[derive(Debug, Default)]
+pub struct ToolCache {
+    entries: HashMap<String, Entry>,
+    /// How many uses were answered from the cache, shown by `/cache`.
+    hits: usize,
+}
+
+impl ToolCache {
+    /// Looks `tool` up, taking the fingerprint of the files it reads as they are before it runs.
+    pub async fn lookup(&mut self, ctx: &Context, tool: &QueuedTool) -> Lookup {
+        let Ok(cwd) = ctx.env().current_dir() else {
+            return Lookup::Uncached;
+        };
+        let Some(fingerprint) = fingerprint(ctx, &tool.tool).await else {
+            return Lookup::Uncached;
+        };
+        let key = format!("{}\0{}\0{}", tool.name, cwd.display(), tool.input);
+        match self.entries.get(&key) {
+            Some(entry) if entry.fingerprint == fingerprint => {
+                self.hits += 1;
+                Lookup::Hit {
+                    tool_use_id: entry.tool_use_id.clone(),
+                    output: copy(&entry.output).unwrap_or_default(),
+                }
+            },
+            _ => Lookup::Miss(Miss { key, fingerprint }),
+        }
+    }
+
+    /// Keeps `output`, the result of the use `tool_use_id` that `miss` was looked up for.
+    pub fn insert(&mut self, miss: Miss, tool_use_id: &str, output: &InvokeOutput) {
+        if let Some(output) = copy(&output.output) {
+            self.entries.insert(miss.key, Entry {
+                fingerprint: miss.fingerprint,
+                tool_use_id: tool_use_id.to_string(),
+                output,
+            });
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.hits = 0;
+    }
+}
+
+/// A copy of `output`, `None` for images, which aren't kept.
+fn copy(output: &OutputKind) -> Option<OutputKind> {
+    match output {
+        OutputKind::Text(text) => Some(OutputKind::Text(text.clone())),
+        OutputKind::Json(json) => Some(OutputKind::Json(json.clone())),
+        OutputKind::Images(_) => None,
+    }
+}
+
+/// The fingerprint of the files `tool` reads, `None` for the tools whose results aren't kept.
+async fn fingerprint(ctx: &Context, tool: &Tool) -> Option<Fingerprint> {
+    match tool {
+        Tool::FsRead(FsRead::Line(fs_line)) => Some(vec![stat(ctx, sanitize_path_tool_arg(ctx, &fs_line.path)).await]),
+        Tool::FsRead(FsRead::Search(fs_search)) => {
+            Some(vec![stat(ctx, sanitize_path_tool_arg(ctx, &fs_search.path)).await])
+        },
+        Tool::FsRead(FsRead::Directory(fs_directory)) => {
+            let path = sanitize_path_tool_arg(ctx, &fs_directory.path);
+            Some(listing(ctx, path, fs_directory.depth.unwrap_or_default()).await)
+        },
+        Tool::GrepSearch(grep_search) => Some(walked(ctx, grep_search.path()).await),
+        Tool::GlobFiles(glob_files) => Some(walked(ctx, glob_files.path()).await),
+        _ => None,
+    }
+}
+
+async fn stat(ctx: &Context, path: PathBuf) -> (PathBuf, Option<SystemTime>, u64) {
+    match ctx.fs().metadata(&path).await {
+        Ok(metadata) => (path, metadata.modified().ok(), metadata.len()),
+        Err(_) => (path, None, 0),
+    }
+}
+
+/// The entries of `path` and of its directories `depth` levels down, as `fs_read` lists them.
+async fn listing(ctx: &Context, path: PathBuf, depth: usize) -> Fingerprint {
+    let mut fingerprint = Vec::new();
+    let mut dirs = VecDeque::from([(path, 0)]);
+    while let Some((dir, dir_depth)) = dirs.pop_front() {
+        let Ok(mut entries) = ctx.fs().read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if metadata.is_dir() && dir_depth < depth {
+                dirs.push_back((entry.path(), dir_depth + 1));
+            }
+            fingerprint.push((entry.path(), metadata.modified().ok(), metadata.len()));
+        }
+    }
+    fingerprint.sort();
+    fingerprint
+}
+
+/// The files under `path` that `grep_search` and `glob_files` go through.
+async fn walked(ctx: &Context, path: &str) -> Fingerprint {
+    let root = ctx.fs().chroot_path(sanitize_path_tool_arg(ctx, path));
+    let files = match ctx.fs().metadata(&root).await {
+        Ok(metadata) if metadata.is_dir() => walk_dir(ctx, &root, None).await.unwrap_or_default(),
+        _ => vec![root],
+    };
+    let mut fingerprint = Vec::with_capacity(files.len());
+    for file in files {
+        fingerprint.push(stat(ctx, file).await);
+    }
+    fingerprint
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(name: &str, input: serde_json::Value) -> QueuedTool {
+        let tool = match name {
+            "fs_read" => Tool::FsRead(serde_json::from_value::<FsRead>(input.clone()).unwrap()),
+            _ => Tool::GrepSearch(serde_json::from_value(input.clone()).unwrap()),
+        };
+        QueuedTool {
+            id: format!("tooluse_{name}"),
+            name: name.to_string(),
+            accepted: true,
+            approved: false,
+            tool,
+            input,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let fs = ctx.fs();
+        fs.create_dir_all("/project/src").await.unwrap();
+        fs.write("/project/src/main.rs", "fn main() {}\n").await.unwrap();
+        let mut cache = ToolCache::default();
+        let output = InvokeOutput {
+            output: OutputKind::Text("fn main() {}".to_string()),
+        };
+
+        let read = queued(
+            "fs_read",
+            serde_json::json!({ "mode": "Line", "path": "/project/src/main.rs" }),
+        );
+        let Lookup::Miss(miss) = cache.lookup(&ctx, &read).await else {
+            panic!("expected a miss");
+        };
+        cache.insert(miss, &read.id, &output);
+        assert!(matches!(
+            cache.lookup(&ctx, &read).await,
+            Lookup::Hit { tool_use_id, .. } if tool_use_id == "tooluse_fs_read"
+        ));
+        assert_eq!(cache.hits(), 1);
+
+        // Other arguments are another use
+        let lines = queued(
+            "fs_read",
+            serde_json::json!({ "mode": "Line", "path": "/project/src/main.rs", "end_line": 1 }),
+        );
+        assert!(matches!(cache.lookup(&ctx, &lines).await, Lookup::Miss(_)));
+
+        // A change to the file is seen
+        fs.write("/project/src/main.rs", "fn main() { run(); }\n")
+            .await
+            .unwrap();
+        assert!(matches!(cache.lookup(&ctx, &read).await, Lookup::Miss(_)));
+
+        // And so is a new file in a directory searched
+        let grep = queued(
+            "grep_search",
+            serde_json::json!({ "pattern": "main", "path": "/project" }),
+        );
+        let Lookup::Miss(miss) = cache.lookup(&ctx, &grep).await else {
+            panic!("expected a miss");
+        };
+        assert!(miss.fingerprint.iter().any(|(path, ..)| path.ends_with("src/main.rs")));
+        cache.insert(miss, &grep.id, &output);
+        assert!(matches!(cache.lookup(&ctx, &grep).await, Lookup::Hit { .. }));
+        fs.write("/project/src/lib.rs", "").await.unwrap();
+        assert!(matches!(cache.lookup(&ctx, &grep).await, Lookup::Miss(_)));
+
+        let execute_bash = QueuedTool {
+            id: "tooluse_execute_bash".to_string(),
+            name: "execute_bash".to_string(),
+            accepted: true,
+            approved: false,
+            tool: Tool::ExecuteBash(serde_json::from_value(serde_json::json!({ "command": "ls" })).unwrap()),
+            input: serde_json::json!({ "command": "ls" }),
+        };
+        assert!(matches!(cache.lookup(&ctx, &execute_bash).await, Lookup::Uncached));
+
+        assert_eq!(cache.count(), 2);
+        cache.clear();
+        assert_eq!(cache.count(), 0);
+        assert!(matches!(cache.lookup(&ctx, &read).await, Lookup::Miss(_)));
+    }
+}
@@ -71,6 +71,10 @@ use crate::cli::chat::server_messenger::{
     ServerMessengerBuilder,
     UpdateEventMessage,
 };
+use crate::cli::chat::tools::command_tool::{
+    CommandTool,
+    CommandToolConfig,
+};
 use crate::cli::chat::tools::custom_tool::{
     CustomTool,
     CustomToolClient,
@@ -80,6 +84,11 @@ use crate::cli::chat::tools::execute_bash::ExecuteBash;
 use crate::cli::chat::tools::fs_read::FsRead;
 use crate::cli::chat::tools::fs_write::FsWrite;
 use crate::cli::chat::tools::gh_issue::GhIssue;
+use crate::cli::chat::tools::github_issue::GithubIssue;
+use crate::cli::chat::tools::glob_files::GlobFiles;
+use crate::cli::chat::tools::grep_search::GrepSearch;
+use crate::cli::chat::tools::http_request::HttpRequest;
+use crate::cli::chat::tools::process_output::ProcessOutput;
 use crate::cli::chat::tools::thinking::Thinking;
 use crate::cli::chat::tools::use_aws::UseAws;
 use crate::cli::chat::tools::{
@@ -102,6 +111,20 @@ const NAMESPACE_DELIMITER: &str = "___";
 // This applies for both mcp server and tool name since in the end the tool name as seen by the
 // model is just {server_name}{NAMESPACE_DELIMITER}{tool_name}
 const VALID_TOOL_NAME: &str = "^[a-zA-Z][a-zA-Z0-9_]*$";
+/// The names of the built-in tools, which the tools of the config can't take.
+const NATIVE_TOOL_NAMES: &[&str] = &[
+    "fs_read",
+    "fs_write",
+    "grep_search",
+    "glob_files",
+    "execute_bash",
+    "process_output",
+    "use_aws",
+    "http_request",
+    "create_github_issue",
+    "report_issue",
+    "thinking",
+];
 const SPINNER_CHARS: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
 
 pub fn workspace_mcp_config_path(ctx: &Context) -> eyre::Result<PathBuf> {
@@ -173,6 +196,19 @@ pub enum LoadingRecord {
 #[serde(rename_all = "camelCase")]
 pub struct McpServerConfig {
     pub mcp_servers: HashMap<String, CustomToolConfig>,
+    /// Tools running a command, defined without an MCP server, see [CommandTool].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tools: HashMap<String, CommandToolConfig>,
+    /// Patterns of the tools run without asking, see [crate::cli::chat::tools::rules].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_tools: Vec<String>,
+    /// Patterns of the tools never run, see [crate::cli::chat::tools::rules].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_tools: Vec<String>,
+    /// Variables set for the commands of `execute_bash` and of `tools`, see
+    /// [crate::cli::chat::tools::environment].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
 }
 
 impl McpServerConfig {
@@ -186,7 +222,7 @@ impl McpServerConfig {
         let conf = match (global_buf, local_buf) {
             (Some(global_buf), Some(local_buf)) => {
                 let mut global_conf = Self::from_slice(&global_buf, output, "global")?;
-                let local_conf = Self::from_slice(&local_buf, output, "local")?;
+                let local_conf = Self::from_workspace_slice(&local_buf, output)?;
                 for (server_name, config) in local_conf.mcp_servers {
                     if global_conf.mcp_servers.insert(server_name.clone(), config).is_some() {
                         queue!(
@@ -202,9 +238,27 @@ impl McpServerConfig {
                         )?;
                     }
                 }
+                for (tool_name, config) in local_conf.tools {
+                    if global_conf.tools.insert(tool_name.clone(), config).is_some() {
+                        queue!(
+                            output,
+                            style::SetForegroundColor(style::Color::Yellow),
+                            style::Print("WARNING: "),
+                            style::ResetColor,
+                            style::Print("Tool config conflict for "),
+                            style::SetForegroundColor(style::Color::Green),
+                            style::Print(tool_name),
+                            style::ResetColor,
+                            style::Print(". Using workspace version.\n")
+                        )?;
+                    }
+                }
+                // Both apply, a tool denied by either being denied
+                global_conf.denied_tools.extend(local_conf.denied_tools);
+                global_conf.env.extend(local_conf.env);
                 global_conf
             },
-            (None, Some(local_buf)) => Self::from_slice(&local_buf, output, "local")?,
+            (None, Some(local_buf)) => Self::from_workspace_slice(&local_buf, output)?,
             (Some(global_buf), None) => Self::from_slice(&global_buf, output, "global")?,
             _ => Default::default(),
         };
@@ -223,6 +277,24 @@ impl McpServerConfig {
         Ok(())
     }
 
+    /// Reads the config of the workspace, whose `allowedTools` are ignored: a repository could
+    /// otherwise let its own tools and commands run without asking.
+    fn from_workspace_slice(slice: &[u8], output: &mut impl Write) -> eyre::Result<McpServerConfig> {
+        let mut conf = Self::from_slice(slice, output, "local")?;
+        if !conf.allowed_tools.is_empty() {
+            queue!(
+                output,
+                style::SetForegroundColor(style::Color::Yellow),
+                style::Print("WARNING: "),
+                style::ResetColor,
+                style::Print("Ignoring the allowedTools of the workspace mcp config, "),
+                style::Print("add them to ~/.aws/amazonq/mcp.json to trust them.\n"),
+            )?;
+            conf.allowed_tools.clear();
+        }
+        Ok(conf)
+    }
+
     fn from_slice(slice: &[u8], output: &mut impl Write, location: &str) -> eyre::Result<McpServerConfig> {
         match serde_json::from_slice::<Self>(slice) {
             Ok(config) => Ok(config),
@@ -281,10 +353,35 @@ impl ToolManagerBuilder {
         telemetry: &TelemetryThread,
         mut output: Box<dyn Write + Send + Sync + 'static>,
     ) -> eyre::Result<ToolManager> {
-        let McpServerConfig { mcp_servers } = self.mcp_server_config.ok_or(eyre::eyre!("Missing mcp server config"))?;
+        let McpServerConfig {
+            mcp_servers,
+            tools,
+            env,
+            ..
+        } = self.mcp_server_config.ok_or(eyre::eyre!("Missing mcp server config"))?;
         debug_assert!(self.conversation_id.is_some());
         let conversation_id = self.conversation_id.ok_or(eyre::eyre!("Missing conversation id"))?;
         let regex = regex::Regex::new(VALID_TOOL_NAME)?;
+        let mut command_tools = HashMap::new();
+        for (tool_name, config) in tools {
+            if regex.is_match(&tool_name)
+                && !tool_name.contains(NAMESPACE_DELIMITER)
+                && !NATIVE_TOOL_NAMES.contains(&tool_name.as_str())
+            {
+                command_tools.insert(tool_name, config);
+                continue;
+            }
+            queue!(
+                output,
+                style::SetForegroundColor(style::Color::Yellow),
+                style::Print("WARNING: "),
+                style::ResetColor,
+                style::Print(format!(
+                    "Skipping the tool {tool_name}, its name must be letters, digits and single underscores, and not \
+                     one of a built-in tool.\n"
+                )),
+            )?;
+        }
         let mut hasher = DefaultHasher::new();
         let is_interactive = self.is_interactive;
         let pre_initialized = mcp_servers
@@ -687,6 +784,8 @@ impl ToolManagerBuilder {
             has_new_stuff,
             is_interactive,
             mcp_load_record: load_record,
+            command_tools,
+            env,
             ..Default::default()
         })
     }
@@ -776,6 +875,12 @@ pub struct ToolManager {
     /// invalid characters).
     /// The value is the load message (i.e. load time, warnings, and errors)
     pub mcp_load_record: Arc<Mutex<HashMap<String, Vec<LoadingRecord>>>>,
+
+    /// The tools running a command defined in the config, by name.
+    pub command_tools: HashMap<String, CommandToolConfig>,
+
+    /// The variables set for commands, from `env` in the config.
+    pub env: HashMap<String, String>,
 }
 
 impl Clone for ToolManager {
@@ -790,6 +895,8 @@ impl Clone for ToolManager {
             schema: self.schema.clone(),
             is_interactive: self.is_interactive,
             mcp_load_record: self.mcp_load_record.clone(),
+            command_tools: self.command_tools.clone(),
+            env: self.env.clone(),
             ..Default::default()
         }
     }
@@ -809,6 +916,15 @@ impl ToolManager {
             if !crate::cli::chat::tools::thinking::Thinking::is_enabled(database) {
                 tool_specs.remove("thinking");
             }
+            if !HttpRequest::is_enabled(&database.settings) {
+                tool_specs.remove("http_request");
+            }
+            if !GithubIssue::is_enabled(&database.settings) {
+                tool_specs.remove("create_github_issue");
+            }
+            for (tool_name, config) in &self.command_tools {
+                tool_specs.insert(tool_name.clone(), config.spec(tool_name));
+            }
             tool_specs
         };
         let load_tools = self
@@ -914,10 +1030,30 @@ impl ToolManager {
         Ok(match value.name.as_str() {
             "fs_read" => Tool::FsRead(serde_json::from_value::<FsRead>(value.args).map_err(map_err)?),
             "fs_write" => Tool::FsWrite(serde_json::from_value::<FsWrite>(value.args).map_err(map_err)?),
+            "grep_search" => Tool::GrepSearch(serde_json::from_value::<GrepSearch>(value.args).map_err(map_err)?),
+            "glob_files" => Tool::GlobFiles(serde_json::from_value::<GlobFiles>(value.args).map_err(map_err)?),
             "execute_bash" => Tool::ExecuteBash(serde_json::from_value::<ExecuteBash>(value.args).map_err(map_err)?),
+            "process_output" => {
+                Tool::ProcessOutput(serde_json::from_value::<ProcessOutput>(value.args).map_err(map_err)?)
+            },
             "use_aws" => Tool::UseAws(serde_json::from_value::<UseAws>(value.args).map_err(map_err)?),
+            "http_request" => Tool::HttpRequest(serde_json::from_value::<HttpRequest>(value.args).map_err(map_err)?),
+            "create_github_issue" => {
+                Tool::GithubIssue(serde_json::from_value::<GithubIssue>(value.args).map_err(map_err)?)
+            },
             "report_issue" => Tool::GhIssue(serde_json::from_value::<GhIssue>(value.args).map_err(map_err)?),
             "thinking" => Tool::Thinking(serde_json::from_value::<Thinking>(value.args).map_err(map_err)?),
+            name if self.command_tools.contains_key(name) => {
+                let config = self.command_tools[name].clone();
+                let command_tool = CommandTool::new(name, config, value.args).map_err(|err| ToolResult {
+                    tool_use_id: value.id.clone(),
+                    content: vec![ToolResultContentBlock::Text(format!(
+                        "Failed to validate tool parameters: {err}"
+                    ))],
+                    status: ToolResultStatus::Error,
+                })?;
+                Tool::Command(command_tool)
+            },
             // Note that this name is namespaced with server_name{DELIMITER}tool_name
             name => {
                 // Note: tn_map also has tools that underwent no transformation. In otherwords, if
@@ -1484,4 +1620,58 @@ mod tests {
         let sanitized = sanitize_name(with_delim, &regex, &mut hasher);
         assert_eq!(sanitized, "abc");
     }
+
+    #[test]
+    fn test_workspace_allowed_tools_ignored() {
+        let mut output = Vec::new();
+        let conf = McpServerConfig::from_workspace_slice(
+            br#"{
+                "mcpServers": {},
+                "allowedTools": ["execute_bash"],
+                "deniedTools": ["execute_bash(rm *)"]
+            }"#,
+            &mut output,
+        )
+        .unwrap();
+        assert!(conf.allowed_tools.is_empty());
+        assert_eq!(conf.denied_tools, ["execute_bash(rm *)"]);
+        assert!(String::from_utf8(output).unwrap().contains("Ignoring the allowedTools"));
+    }
+
+    #[test]
+    fn test_command_tool_from_tool_use() {
+        let config = serde_json::from_str::<McpServerConfig>(
+            r#"{
+                "mcpServers": {},
+                "tools": {
+                    "query_internal_api": {
+                        "description": "Queries the internal API",
+                        "command": "curl -s https://internal.example.com/{{path}}"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let tool_manager = ToolManager {
+            command_tools: config.tools,
+            ..Default::default()
+        };
+        let tool_use = |args| AssistantToolUse {
+            id: "tooluse_1".to_string(),
+            name: "query_internal_api".to_string(),
+            orig_name: "query_internal_api".to_string(),
+            args,
+            orig_args: serde_json::Value::Null,
+        };
+
+        let tool = tool_manager
+            .get_tool_from_tool_use(tool_use(serde_json::json!({ "path": "status" })))
+            .unwrap();
+        assert!(matches!(&tool, Tool::Command(command_tool) if command_tool.command().ends_with("/status")));
+        assert!(
+            tool_manager
+                .get_tool_from_tool_use(tool_use(serde_json::json!(["status"])))
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,266 @@
+//! Caps the output of tools added to the conversation to its first and last lines, saving all of
+//! it under the data directory for `/expand` to show or attach.
+//!
+//! The output of a tool use is saved as `<tool use id>.txt`. Those older than [MAX_AGE] are
+//! removed whenever one is saved.
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use eyre::Result;
+use serde_json::Value;
+use tracing::debug;
+
+use super::tools::OutputKind;
+use super::util::truncate_safe;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+use crate::util::directories;
+
+/// The most bytes of a tool output added to the conversation by default.
+const DEFAULT_MAX_BYTES: usize = 30_000;
+
+/// The most lines of a tool output added to the conversation by default.
+const DEFAULT_MAX_LINES: usize = 400;
+
+/// How long the full output of a tool is kept.
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How much of the output of a tool is added to the conversation, of each string for JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLimits {
+    /// From `chat.toolOutput.maxBytes`.
+    pub max_bytes: usize,
+    /// From `chat.toolOutput.maxLines`.
+    pub max_lines: usize,
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_lines: DEFAULT_MAX_LINES,
+        }
+    }
+}
+
+impl OutputLimits {
+    /// The limits of `settings`, 0 meaning none.
+    pub fn from_settings(settings: &Settings) -> Self {
+        let limit = |setting: Setting, default: usize| match settings.get_int(setting) {
+            Some(0) => usize::MAX,
+            Some(limit) => usize::try_from(limit).unwrap_or(default),
+            None => default,
+        };
+        Self {
+            max_bytes: limit(Setting::ChatToolOutputMaxBytes, DEFAULT_MAX_BYTES),
+            max_lines: limit(Setting::ChatToolOutputMaxLines, DEFAULT_MAX_LINES),
+        }
+    }
+
+    /// Shortens the text of `output` over the limits, returning whether it did.
+    pub fn cap(&self, output: &mut OutputKind, tool_use_id: &str) -> bool {
+        match output {
+            OutputKind::Text(text) => self.cap_text(text, tool_use_id),
+            OutputKind::Json(json) => self.cap_json(json, tool_use_id),
+            OutputKind::Images(_) => false,
+        }
+    }
+
+    fn cap_json(&self, json: &mut Value, tool_use_id: &str) -> bool {
+        match json {
+            Value::String(text) => self.cap_text(text, tool_use_id),
+            Value::Array(values) => values
+                .iter_mut()
+                .fold(false, |capped, value| self.cap_json(value, tool_use_id) || capped),
+            Value::Object(values) => values
+                .values_mut()
+                .fold(false, |capped, value| self.cap_json(value, tool_use_id) || capped),
+            _ => false,
+        }
+    }
+
+    fn cap_text(&self, text: &mut String, tool_use_id: &str) -> bool {
+        match self.elide(text, tool_use_id) {
+            Some(elided) => {
+                *text = elided;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// `text` with the lines in its middle replaced by a marker when it is over the limits, half of
+    /// them going to its first lines and half to its last.
+    fn elide(&self, text: &str, tool_use_id: &str) -> Option<String> {
+        if text.len() <= self.max_bytes && text.lines().count() <= self.max_lines {
+            return None;
+        }
+        let (max_bytes, max_lines) = (self.max_bytes / 2, self.max_lines / 2);
+
+        let mut head = 0;
+        for (index, line) in text.split_inclusive('\n').enumerate() {
+            if index >= max_lines || head + line.len() > max_bytes {
+                break;
+            }
+            head += line.len();
+        }
+        if head == 0 {
+            // A first line too long to keep whole, as minified files have
+            head = truncate_safe(text, max_bytes).len();
+        }
+
+        let rest = &text[head..];
+        let mut tail = rest.len();
+        for (index, line) in rest.split_inclusive('\n').rev().enumerate() {
+            if index >= max_lines || rest.len() - tail + line.len() > max_bytes {
+                break;
+            }
+            tail -= line.len();
+        }
+        if tail == rest.len() {
+            tail = rest.len().saturating_sub(max_bytes);
+            while !rest.is_char_boundary(tail) {
+                tail += 1;
+            }
+        }
+
+        let elided = &rest[..tail];
+        Some(format!(
+            "{}\n[... {} lines ({} bytes) not shown, the user can attach them with /expand {} --attach ...]\n{}",
+            text[..head].trim_end_matches('\n'),
+            elided.lines().count(),
+            elided.len(),
+            tool_use_id,
+            &rest[tail..]
+        ))
+    }
+}
+
+/// `output` as text to save, the strings of a JSON object with their keys as headings so that
+/// their lines are readable.
+pub fn full_text(output: &OutputKind) -> String {
+    match output {
+        OutputKind::Text(text) => text.clone(),
+        OutputKind::Json(Value::Object(values)) => values
+            .iter()
+            .map(|(key, value)| match value {
+                Value::String(text) => format!("{key}:\n{text}\n\n"),
+                value => format!("{key}: {value}\n\n"),
+            })
+            .collect(),
+        OutputKind::Json(json) => serde_json::to_string_pretty(json).unwrap_or_default(),
+        OutputKind::Images(_) => String::new(),
+    }
+}
+
+fn output_path(tool_use_id: &str) -> Result<PathBuf> {
+    // The id comes from the model, which could make it a path
+    let name = tool_use_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .collect::<String>();
+    Ok(directories::chat_tool_output_dir()?.join(format!("{name}.txt")))
+}
+
+/// Saves `output`, the full output of the tool use `tool_use_id`.
+pub async fn save(ctx: &Context, tool_use_id: &str, output: &str) -> Result<()> {
+    let path = output_path(tool_use_id)?;
+    if let Some(dir) = path.parent() {
+        ctx.fs().create_dir_all(dir).await?;
+        prune(ctx, dir).await;
+    }
+    ctx.fs().write(&path, output).await?;
+    Ok(())
+}
+
+/// The full output of the tool use `tool_use_id`, saved with [save].
+pub async fn load(ctx: &Context, tool_use_id: &str) -> Result<String> {
+    Ok(ctx.fs().read_to_string(output_path(tool_use_id)?).await?)
+}
+
+/// Removes the outputs of `dir` saved more than [MAX_AGE] ago.
+async fn prune(ctx: &Context, dir: &Path) {
+    let Ok(mut entries) = ctx.fs().read_dir(dir).await else {
+        return;
+    };
+    let now = SystemTime::now();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let modified = entry
+            .metadata()
+            .await
+            .ok()
+            .and_then(|metadata| metadata.modified().ok());
+        if modified.is_some_and(|modified| now.duration_since(modified).unwrap_or_default() > MAX_AGE) {
+            debug!(name = ?entry.file_name(), "removing an old tool output");
+            // Joined to `dir` rather than using the entry's path, which is outside of a chroot
+            let _ = ctx.fs().remove_file(dir.join(entry.file_name())).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elide() {
+        let limits = OutputLimits {
+            max_bytes: 1000,
+            max_lines: 10,
+        };
+        let short = "line 1\nline 2\n";
+        assert_eq!(limits.elide(short, "tooluse_1"), None);
+
+        let log = (1..=100).map(|n| format!("line {n}\n")).collect::<Vec<_>>().concat();
+        let elided = limits.elide(&log, "tooluse_1").unwrap();
+        assert!(elided.starts_with("line 1\nline 2\nline 3\nline 4\nline 5\n[... 90 lines"));
+        assert!(elided.contains("/expand tooluse_1 --attach"));
+        assert!(elided.ends_with("...]\nline 96\nline 97\nline 98\nline 99\nline 100\n"));
+
+        // A single line over the byte limit keeps its start and end
+        let minified = "é".repeat(1000);
+        let elided = limits.elide(&minified, "tooluse_2").unwrap();
+        assert!(elided.starts_with(&"é".repeat(250)));
+        assert!(elided.ends_with(&"é".repeat(250)));
+        assert!(elided.contains("[... 1 lines (1000 bytes) not shown"));
+    }
+
+    #[test]
+    fn test_cap_json() {
+        let limits = OutputLimits {
+            max_bytes: 100,
+            max_lines: 4,
+        };
+        let mut output = OutputKind::Json(serde_json::json!({
+            "exit_status": "0",
+            "stdout": "a\nb\nc\nd\ne\nf\n",
+            "stderr": "",
+        }));
+        let full = full_text(&output);
+        assert!(limits.cap(&mut output, "tooluse_1"));
+        let OutputKind::Json(json) = &output else {
+            panic!("Expected JSON output");
+        };
+        assert_eq!(json["exit_status"], "0");
+        assert!(json["stdout"].as_str().unwrap().starts_with("a\nb\n[... 2 lines"));
+        assert!(full.contains("stdout:\na\nb\nc\nd\ne\nf\n"));
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        save(&ctx, "tooluse_../abc", "all of the output").await.unwrap();
+        assert_eq!(load(&ctx, "tooluse_abc").await.unwrap(), "all of the output");
+        assert!(load(&ctx, "tooluse_def").await.is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Tools defined in the `tools` of `mcp.json`, each running a command made from a template and the
+//! arguments the model gives, so that a script becomes a tool without writing an MCP server:
+//!
+//! ```json
+//! {
+//!   "tools": {
+//!     "run_terraform_plan": {
+//!       "description": "Shows the changes terraform would make to an environment",
+//!       "inputSchema": {
+//!         "type": "object",
+//!         "properties": { "env": { "type": "string", "enum": ["dev", "prod"] } },
+//!         "required": ["env"]
+//!       },
+//!       "command": "terraform plan -no-color -var-file={{env}}.tfvars",
+//!       "cwd": "infra",
+//!       "env": { "TF_IN_AUTOMATION": "1" }
+//!     }
+//!   }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crossterm::{
+    queue,
+    style,
+};
+use eyre::{
+    Result,
+    bail,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+
+use super::environment::ToolEnv;
+use super::execute_bash::ExecuteBash;
+use super::sandbox::Sandbox;
+use super::{
+    InputSchema,
+    InvokeOutput,
+    ToolOrigin,
+    ToolSpec,
+    sanitize_path_tool_arg,
+};
+use crate::cli::chat::CONTINUATION_LINE;
+use crate::platform::Context;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandToolConfig {
+    pub description: String,
+    /// The JSON schema of the arguments, an object without properties by default.
+    #[serde(default = "default_input_schema")]
+    pub input_schema: Value,
+    /// Run with bash, each `{{name}}` replaced by the argument `name` quoted for the shell.
+    pub command: String,
+    /// The directory the command runs in, the current one by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Whether the command runs without asking first, as the read-only commands of `execute_bash`
+    /// do.
+    #[serde(default)]
+    pub trusted: bool,
+}
+
+fn default_input_schema() -> Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+impl CommandToolConfig {
+    /// The specification of the tool told to the model, named `name`.
+    pub fn spec(&self, name: &str) -> ToolSpec {
+        ToolSpec {
+            name: name.to_string(),
+            description: self.description.clone(),
+            input_schema: InputSchema(self.input_schema.clone()),
+            tool_origin: ToolOrigin::UserDefined,
+            annotations: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandTool {
+    pub name: String,
+    pub config: CommandToolConfig,
+    pub args: serde_json::Map<String, Value>,
+    /// Where the command runs, on the host when missing, see `chat.sandbox.backend`.
+    pub sandbox: Option<Sandbox>,
+    /// The variables of the environment the command gets besides those of its `env`.
+    pub env: ToolEnv,
+}
+
+impl CommandTool {
+    pub fn new(name: &str, config: CommandToolConfig, args: Value) -> Result<Self> {
+        let args = match args {
+            Value::Object(args) => args,
+            Value::Null => serde_json::Map::new(),
+            _ => bail!("the arguments of {name} must be an object"),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            config,
+            args,
+            sandbox: None,
+            env: ToolEnv::default(),
+        })
+    }
+
+    pub fn requires_acceptance(&self) -> bool {
+        !self.config.trusted
+    }
+
+    /// The command of the template with the arguments in place of their `{{name}}`, those missing
+    /// being empty.
+    pub fn command(&self) -> String {
+        let mut command = String::with_capacity(self.config.command.len());
+        let mut rest = self.config.command.as_str();
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+                break;
+            };
+            command.push_str(&rest[..start]);
+            let value = match self.args.get(rest[start + 2..end].trim()) {
+                Some(Value::String(value)) => value.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(value) => value.to_string(),
+            };
+            command.push_str(&quote(&value));
+            rest = &rest[end + 2..];
+        }
+        command.push_str(rest);
+        command
+    }
+
+    /// The script run with bash, setting up the environment and directory of the command.
+    fn script(&self) -> String {
+        let mut env = self.config.env.iter().collect::<Vec<_>>();
+        env.sort();
+        let mut script = String::new();
+        for (key, value) in env {
+            let _ = writeln!(script, "export {key}={}", quote(value));
+        }
+        if let Some(cwd) = &self.config.cwd {
+            let _ = writeln!(script, "cd {} || exit 1", quote(&shellexpand::tilde(cwd)));
+        }
+        script.push_str(&self.command());
+        script
+    }
+
+    pub async fn invoke(&self, updates: impl Write) -> Result<InvokeOutput> {
+        // Run as `execute_bash` would the script, for the same output
+        let execute_bash = ExecuteBash {
+            command: self.script(),
+            summary: None,
+            interactive: false,
+            background: false,
+            sandbox: self.sandbox.clone(),
+            env: self.env.clone(),
+            pty: false,
+            processes: Default::default(),
+        };
+        execute_bash.invoke(updates).await
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("I will run the command of "),
+            style::SetForegroundColor(style::Color::Green),
+            style::Print(&self.name),
+            style::ResetColor,
+            style::Print(":\n"),
+            style::SetForegroundColor(style::Color::Green),
+            style::Print(self.command()),
+            style::Print("\n"),
+            style::ResetColor,
+        )?;
+        if let Some(cwd) = &self.config.cwd {
+            queue!(
+                updates,
+                style::Print(CONTINUATION_LINE),
+                style::Print(format!(" in {cwd}\n")),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        let required = self.config.input_schema.get("required").and_then(Value::as_array);
+        for name in required.into_iter().flatten().filter_map(Value::as_str) {
+            if !self.args.contains_key(name) {
+                bail!("missing the required argument '{name}'");
+            }
+        }
+        for key in self.config.env.keys() {
+            let mut chars = key.chars();
+            let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                bail!(
+                    "'{key}' in the env of {} isn't a valid environment variable name",
+                    self.name
+                );
+            }
+        }
+        if let Some(cwd) = &self.config.cwd {
+            if !sanitize_path_tool_arg(ctx, cwd).exists() {
+                bail!("the directory '{cwd}' of {} doesn't exist", self.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn quote(value: &str) -> String {
+    // Only fails on nul bytes, which no argument of a command can have
+    shlex::try_quote(value).map_or_else(|_| "''".to_string(), |quoted| quoted.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terraform_plan(args: Value) -> CommandTool {
+        let config = serde_json::from_value::<CommandToolConfig>(serde_json::json!({
+            "description": "Shows the changes terraform would make",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "env": { "type": "string" }, "target": { "type": "string" } },
+                "required": ["env"]
+            },
+            "command": "terraform plan -var-file={{env}}.tfvars -target={{ target }}",
+            "cwd": "infra",
+            "env": { "TF_IN_AUTOMATION": "1" }
+        }))
+        .unwrap();
+        CommandTool::new("run_terraform_plan", config, args).unwrap()
+    }
+
+    #[test]
+    fn test_command() {
+        let tool = terraform_plan(serde_json::json!({ "env": "dev", "target": "module.a b" }));
+        assert_eq!(
+            tool.command(),
+            "terraform plan -var-file=dev.tfvars -target='module.a b'"
+        );
+        assert_eq!(
+            tool.script(),
+            "export TF_IN_AUTOMATION=1\ncd infra || exit 1\nterraform plan -var-file=dev.tfvars -target='module.a b'"
+        );
+
+        // Arguments can't break out of their quotes
+        let tool = terraform_plan(serde_json::json!({ "env": "dev; rm -rf ~" }));
+        assert_eq!(
+            tool.command(),
+            "terraform plan -var-file='dev; rm -rf ~'.tfvars -target=''"
+        );
+
+        assert!(CommandTool::new("run_terraform_plan", tool.config, serde_json::json!("dev")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().create_dir_all("/infra").await.unwrap();
+
+        let mut tool = terraform_plan(serde_json::json!({ "target": "module.a" }));
+        tool.config.cwd = Some("/infra".to_string());
+        assert!(tool.validate(&ctx).await.is_err());
+
+        let mut tool = terraform_plan(serde_json::json!({ "env": "dev" }));
+        tool.config.cwd = Some("/infra".to_string());
+        assert!(tool.validate(&ctx).await.is_ok());
+        tool.config.env.insert("BAD NAME".to_string(), "1".to_string());
+        assert!(tool.validate(&ctx).await.is_err());
+    }
+}
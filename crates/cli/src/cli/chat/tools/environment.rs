@@ -0,0 +1,156 @@
+//! The environment of the commands of `execute_bash` and of the tools of `tools` in `mcp.json`,
+//! which don't get the credentials of the user unless they let them through:
+//!
+//! - `tools.env.allowlist`: when set, the only variables passed, e.g. `PATH,HOME,LANG,LC_*`
+//! - `tools.env.denylist`: the variables never passed, [DEFAULT_DENYLIST] unless set, and none when
+//!   set to an empty list
+//! - `env` in `mcp.json`: variables set for every command, whatever the lists
+//!
+//! The lists are of glob patterns matched ignoring case, as a JSON array or separated by commas.
+
+use std::collections::HashMap;
+
+use eyre::{
+    Result,
+    bail,
+};
+use globset::{
+    GlobBuilder,
+    GlobSet,
+    GlobSetBuilder,
+};
+
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// The variables not passed when `tools.env.denylist` isn't set, those usually holding secrets.
+pub const DEFAULT_DENYLIST: &[&str] = &[
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_SECURITY_TOKEN",
+    "*_SECRET",
+    "*_SECRET_*",
+    "*_TOKEN",
+    "*_PASSWORD",
+    "*_API_KEY",
+    "*_PRIVATE_KEY",
+];
+
+#[derive(Debug, Clone)]
+pub struct ToolEnv {
+    allowlist: Option<GlobSet>,
+    denylist: GlobSet,
+    /// From `env` in `mcp.json`.
+    injected: HashMap<String, String>,
+}
+
+impl Default for ToolEnv {
+    fn default() -> Self {
+        Self {
+            allowlist: None,
+            denylist: glob_set("tools.env.denylist", DEFAULT_DENYLIST).unwrap_or_else(|_| GlobSet::empty()),
+            injected: HashMap::new(),
+        }
+    }
+}
+
+impl ToolEnv {
+    /// The environment of `settings` with the variables of `injected` set. Errors on an invalid
+    /// pattern rather than passing what the user meant to keep from commands.
+    pub fn from_settings(settings: &Settings, injected: HashMap<String, String>) -> Result<Self> {
+        let allowlist = match list(settings, Setting::ToolsEnvAllowlist) {
+            Some(allowlist) => Some(glob_set("tools.env.allowlist", &allowlist)?),
+            None => None,
+        };
+        let denylist = match list(settings, Setting::ToolsEnvDenylist) {
+            Some(denylist) => glob_set("tools.env.denylist", &denylist)?,
+            None => glob_set("tools.env.denylist", DEFAULT_DENYLIST)?,
+        };
+        Ok(Self {
+            allowlist,
+            denylist,
+            injected,
+        })
+    }
+
+    /// Whether the variable `name` of the environment of the chat is passed to commands.
+    pub fn passes(&self, name: &str) -> bool {
+        self.allowlist.as_ref().is_none_or(|allowlist| allowlist.is_match(name)) && !self.denylist.is_match(name)
+    }
+
+    /// Removes from the environment of `cmd` the variables not passed and sets those of `mcp.json`.
+    pub fn apply(&self, cmd: &mut tokio::process::Command) {
+        for (name, _) in std::env::vars_os() {
+            if !self.passes(&name.to_string_lossy()) {
+                cmd.env_remove(name);
+            }
+        }
+        cmd.envs(&self.injected);
+    }
+}
+
+/// The patterns of `setting`, `None` when it isn't set.
+pub(super) fn list(settings: &Settings, setting: Setting) -> Option<Vec<String>> {
+    let value = settings.get(setting)?;
+    let patterns: Vec<String> = match value.as_array() {
+        Some(patterns) => patterns
+            .iter()
+            .filter_map(|pattern| pattern.as_str())
+            .map(str::to_string)
+            .collect(),
+        None => value
+            .as_str()?
+            .split(',')
+            .map(|pattern| pattern.trim().to_string())
+            .collect(),
+    };
+    Some(patterns.into_iter().filter(|pattern| !pattern.is_empty()).collect())
+}
+
+/// The case-insensitive glob patterns of `setting`, failing on an invalid one.
+pub(super) fn glob_set(setting: &str, patterns: &[impl AsRef<str>]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        match GlobBuilder::new(pattern).case_insensitive(true).build() {
+            Ok(glob) => builder.add(glob),
+            Err(err) => bail!("Invalid pattern '{pattern}' in {setting}: {err}"),
+        };
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_passes() {
+        let mut settings = Settings::new().await.unwrap();
+        let env = ToolEnv::from_settings(&settings, HashMap::new()).unwrap();
+        assert!(env.passes("PATH"));
+        assert!(env.passes("AWS_PROFILE"));
+        assert!(!env.passes("AWS_SECRET_ACCESS_KEY"));
+        assert!(!env.passes("github_token"));
+        assert!(!env.passes("DB_PASSWORD"));
+
+        settings
+            .set(Setting::ToolsEnvAllowlist, "PATH, HOME, LC_*, GITHUB_TOKEN")
+            .await
+            .unwrap();
+        settings
+            .set(Setting::ToolsEnvDenylist, serde_json::json!([]))
+            .await
+            .unwrap();
+        let env = ToolEnv::from_settings(&settings, HashMap::new()).unwrap();
+        assert!(env.passes("PATH"));
+        assert!(env.passes("LC_ALL"));
+        assert!(env.passes("GITHUB_TOKEN"));
+        assert!(!env.passes("AWS_PROFILE"));
+
+        settings.set(Setting::ToolsEnvDenylist, "PATH,[").await.unwrap();
+        assert!(ToolEnv::from_settings(&settings, HashMap::new()).is_err());
+    }
+}
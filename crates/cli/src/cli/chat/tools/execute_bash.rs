@@ -5,6 +5,7 @@ use std::process::{
     Stdio,
 };
 use std::str::from_utf8;
+use std::time::Duration;
 
 use crossterm::queue;
 use crossterm::style::{
@@ -20,7 +21,19 @@ use tokio::io::AsyncBufReadExt;
 use tokio::select;
 use tracing::error;
 
+use super::super::background::{
+    BackgroundProcesses,
+    ProcessStatus,
+};
 use super::super::util::truncate_safe;
+use super::environment::ToolEnv;
+use super::fs_write::stylize_output_if_able;
+#[cfg(unix)]
+use super::pty::{
+    self,
+    PtyExit,
+};
+use super::sandbox::Sandbox;
 use super::{
     InvokeOutput,
     MAX_TOOL_RESPONSE_SIZE,
@@ -31,16 +44,52 @@ use crate::cli::chat::{
     PURPOSE_ARROW,
 };
 use crate::platform::Context;
+use crate::util::process::{
+    Pid,
+    kill_process_group,
+};
+
+/// How long a command started in the background is waited for, to tell the model whether it
+/// failed right away and what it printed when starting.
+const BACKGROUND_STARTUP: Duration = Duration::from_secs(2);
+
+/// How many of the lines printed by a command started in the background are returned.
+const BACKGROUND_STARTUP_LINES: usize = 50;
+
 const READONLY_COMMANDS: &[&str] = &["ls", "cat", "echo", "pwd", "which", "head", "tail", "find", "grep"];
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExecuteBash {
     pub command: String,
     pub summary: Option<String>,
+    /// Whether the command needs a terminal to interact with the user, as told by the model.
+    #[serde(default)]
+    pub interactive: bool,
+    /// Whether the command keeps running in the background, e.g. a dev server, as told by the
+    /// model.
+    #[serde(default)]
+    pub background: bool,
+    /// Where the command runs, on the host when missing, see `chat.sandbox.backend`.
+    #[serde(skip)]
+    pub sandbox: Option<Sandbox>,
+    /// The variables of the environment the command gets, see `tools.env.allowlist`.
+    #[serde(skip)]
+    pub env: ToolEnv,
+    /// Whether the command runs under a pseudo-terminal the user can type into, see
+    /// `chat.shell.pty`.
+    #[serde(skip)]
+    pub pty: bool,
+    /// Where the command is tracked when it runs in the background.
+    #[serde(skip)]
+    pub processes: BackgroundProcesses,
 }
 
 impl ExecuteBash {
     pub fn requires_acceptance(&self) -> bool {
+        // It keeps running after the tool use, whatever it is
+        if self.background {
+            return true;
+        }
         let Some(args) = shlex::split(&self.command) else {
             return true;
         };
@@ -97,8 +146,31 @@ impl ExecuteBash {
         false
     }
 
-    pub async fn invoke(&self, updates: impl Write) -> Result<InvokeOutput> {
-        let output = run_command(&self.command, MAX_TOOL_RESPONSE_SIZE / 3, Some(updates)).await?;
+    pub async fn invoke(&self, mut updates: impl Write) -> Result<InvokeOutput> {
+        if self.background {
+            return self.invoke_in_background(updates).await;
+        }
+        #[cfg(unix)]
+        if self.pty {
+            return self.invoke_in_pty(updates).await;
+        }
+
+        let output = run_command(
+            &self.command,
+            self.sandbox.as_ref(),
+            &self.env,
+            MAX_TOOL_RESPONSE_SIZE / 3,
+            Some(&mut updates),
+        )
+        .await?;
+        let status = match output.exit_status {
+            Some(0) => None,
+            Some(code) => Some(format!("Exited with status {code}")),
+            None => Some("Terminated by a signal".to_string()),
+        };
+        if let Some(status) = status {
+            queue_status(&mut updates, &status, Color::Red)?;
+        }
         let result = serde_json::json!({
             "exit_status": output.exit_status.unwrap_or(0).to_string(),
             "stdout": output.stdout,
@@ -110,21 +182,114 @@ impl ExecuteBash {
         })
     }
 
-    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+    /// Starts the command in the background, returning what it printed in its first seconds, or
+    /// all it printed when it exits before.
+    async fn invoke_in_background(&self, mut updates: impl Write) -> Result<InvokeOutput> {
+        let id = self.processes.spawn(&self.command, self.sandbox.as_ref(), &self.env)?;
+        let status = self
+            .processes
+            .wait(id, BACKGROUND_STARTUP)
+            .await
+            .unwrap_or(ProcessStatus::Running);
+        let output = self
+            .processes
+            .output(id, BACKGROUND_STARTUP_LINES)
+            .unwrap_or_default()
+            .join("\n");
+        for line in output.lines() {
+            writeln!(updates, "{line}")?;
+        }
+
+        let mut result = serde_json::json!({ "process_id": id, "output": output });
+        match status {
+            ProcessStatus::Running => {
+                queue_status(
+                    &mut updates,
+                    &format!("Running in the background as process {id}, see /ps"),
+                    Color::DarkGrey,
+                )?;
+                result["exit_status"] = "running".into();
+                result["note"] = format!(
+                    "The command is still running as background process {id}, read its output later with the process_output tool"
+                )
+                .into();
+            },
+            ProcessStatus::Exited(code) => {
+                match code {
+                    Some(0) => (),
+                    Some(code) => queue_status(&mut updates, &format!("Exited with status {code}"), Color::Red)?,
+                    None => queue_status(&mut updates, "Terminated by a signal", Color::Red)?,
+                }
+                result["exit_status"] = code.unwrap_or(0).to_string().into();
+            },
+            ProcessStatus::Killed => result["exit_status"] = "killed".into(),
+        }
+
+        Ok(InvokeOutput {
+            output: OutputKind::Json(result),
+        })
+    }
+
+    /// Runs the command under a pseudo-terminal, its output all going to stdout.
+    #[cfg(unix)]
+    async fn invoke_in_pty(&self, mut updates: impl Write) -> Result<InvokeOutput> {
+        let command_line = command_line(&self.command, self.sandbox.as_ref());
+        let run = pty::run(&command_line, &self.env, MAX_TOOL_RESPONSE_SIZE / 3, &mut updates).await?;
+        let (exit_status, status) = match &run.exit {
+            PtyExit::Exited(Some(0)) => ("0".to_string(), None),
+            PtyExit::Exited(Some(code)) => (code.to_string(), Some(format!("Exited with status {code}"))),
+            PtyExit::Exited(None) => ("0".to_string(), Some("Terminated by a signal".to_string())),
+            PtyExit::Killed => ("killed".to_string(), Some("The user killed the command".to_string())),
+            PtyExit::Background { pid, log } => (
+                "running".to_string(),
+                Some(format!(
+                    "The user left the command running in the background as process {pid}, its output going to {}",
+                    log.display()
+                )),
+            ),
+        };
+        let mut result = serde_json::json!({
+            "exit_status": exit_status,
+            "stdout": run.output,
+            "stderr": "",
+        });
+        if let Some(status) = status {
+            let color = match run.exit {
+                PtyExit::Background { .. } => Color::DarkGrey,
+                _ => Color::Red,
+            };
+            queue_status(&mut updates, &status, color)?;
+            // Told to the model, which would otherwise take the command for finished
+            if !matches!(run.exit, PtyExit::Exited(_)) {
+                result["note"] = serde_json::Value::String(status);
+            }
+        }
+
+        Ok(InvokeOutput {
+            output: OutputKind::Json(result),
+        })
+    }
+
+    pub fn queue_description(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
         queue!(updates, style::Print("I will run the following shell command: "),)?;
 
         // TODO: Could use graphemes for a better heuristic
-        if self.command.len() > 20 {
+        if self.command.len() > 20 || self.command.contains('\n') {
             queue!(updates, style::Print("\n"),)?;
         }
 
-        queue!(
-            updates,
-            style::SetForegroundColor(Color::Green),
-            style::Print(&self.command),
-            style::Print("\n"),
-            style::ResetColor
-        )?;
+        // Highlighted as a shell script when the terminal supports it
+        let command = stylize_output_if_able(ctx, "command.sh", &self.command);
+        match command.truecolor {
+            true => queue!(updates, style::Print(command.content), style::ResetColor)?,
+            false => queue!(
+                updates,
+                style::SetForegroundColor(Color::Green),
+                style::Print(&self.command),
+                style::Print("\n"),
+                style::ResetColor
+            )?,
+        }
 
         // Add the summary if available
         if let Some(summary) = &self.summary {
@@ -141,6 +306,31 @@ impl ExecuteBash {
             )?;
         }
 
+        if let Some(sandbox) = &self.sandbox {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!("Runs in the {} sandbox\n", sandbox.backend)),
+                style::ResetColor,
+            )?;
+        }
+
+        if self.background {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print("Keeps running in the background, see /ps, /logs and /kill\n"),
+                style::ResetColor,
+            )?;
+        } else if self.pty {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print("Runs in a terminal you can type into, Ctrl+] to background or kill it\n"),
+                style::ResetColor,
+            )?;
+        }
+
         queue!(updates, style::Print("\n"))?;
 
         Ok(())
@@ -152,6 +342,19 @@ impl ExecuteBash {
     }
 }
 
+/// Prints how the command ended, on a line of its own.
+fn queue_status(updates: &mut impl Write, status: &str, color: Color) -> Result<()> {
+    queue!(
+        updates,
+        style::SetForegroundColor(color),
+        style::Print(status),
+        style::Print("\n"),
+        style::ResetColor
+    )?;
+    updates.flush()?;
+    Ok(())
+}
+
 pub struct CommandResult {
     pub exit_status: Option<i32>,
     /// Truncated stdout
@@ -160,26 +363,60 @@ pub struct CommandResult {
     pub stderr: String,
 }
 
+/// Kills the process group of a command when dropped before the command exits, as it is when the
+/// tool times out or is interrupted, killing only `bash` leaving what it started running.
+pub(super) struct ProcessGroupGuard(pub(super) Option<u32>);
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            if let Err(err) = kill_process_group(Pid::from_u32(pid)) {
+                error!(%err, "Failed to kill the command");
+            }
+        }
+    }
+}
+
+/// The program and arguments running `command` with bash, in `sandbox` if any.
+pub fn command_line(command: &str, sandbox: Option<&Sandbox>) -> Vec<String> {
+    match sandbox {
+        Some(sandbox) => sandbox.command_line(command),
+        None => vec!["bash".to_string(), "-c".to_string(), command.to_string()],
+    }
+}
+
 /// Run a bash command.
 /// # Arguments
+/// * `sandbox` - the sandbox to run the command in, on the host when `None`
+/// * `env` - the variables of the environment passed to the command
 /// * `max_result_size` - max size of output streams, truncating if required
 /// * `updates` - output stream to push informational messages about the progress
 /// # Returns
 /// A [`CommandResult`]
 pub async fn run_command<W: Write>(
     command: &str,
+    sandbox: Option<&Sandbox>,
+    env: &ToolEnv,
     max_result_size: usize,
     mut updates: Option<W>,
 ) -> Result<CommandResult> {
+    let command_line = command_line(command, sandbox);
+
     // We need to maintain a handle on stderr and stdout, but pipe it to the terminal as well
-    let mut child = tokio::process::Command::new("bash")
-        .arg("-c")
-        .arg(command)
-        .stdin(Stdio::inherit())
+    let mut cmd = tokio::process::Command::new(&command_line[0]);
+    cmd.args(&command_line[1..])
+        .stdin(Stdio::null())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+        .stderr(Stdio::piped());
+    env.apply(&mut cmd);
+    // In a process group of its own to stop everything the command started along with it. Not
+    // reading from the terminal, which a background process group would be stopped for.
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let mut child = cmd
         .spawn()
         .wrap_err_with(|| format!("Unable to spawn command '{}'", command))?;
+    let mut process_group = ProcessGroupGuard(child.id());
 
     let stdout_final: String;
     let stderr_final: String;
@@ -232,6 +469,7 @@ pub async fn run_command<W: Write>(
             };
         }
         .wrap_err_with(|| format!("No exit status for '{}'", command))?;
+        process_group.0 = None;
 
         u.flush()?;
 
@@ -247,6 +485,7 @@ pub async fn run_command<W: Write>(
             .wait_with_output()
             .await
             .wrap_err_with(|| format!("No exit status for '{}'", command))?;
+        process_group.0 = None;
 
         exit_status = output.status;
         stdout_final = from_utf8(&output.stdout).unwrap_or_default().to_string();
@@ -280,6 +519,27 @@ pub async fn run_command<W: Write>(
 mod tests {
     use super::*;
 
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_run_command_stopped_kills_process_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let command = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+        let env = ToolEnv::default();
+        let run = run_command(&command, None, &env, MAX_TOOL_RESPONSE_SIZE, Some(std::io::sink()));
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(500), run)
+                .await
+                .is_err()
+        );
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        // Gone, or a zombie left for init to reap
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(stat.map_or(true, |stat| stat.contains(") Z ")));
+    }
+
     #[ignore = "todo: fix failing on musl for some reason"]
     #[tokio::test]
     async fn test_execute_bash_tool() {
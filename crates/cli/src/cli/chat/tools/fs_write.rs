@@ -252,15 +252,19 @@ impl FsWrite {
         Ok(())
     }
 
-    fn print_relative_path(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
-        let cwd = ctx.env().current_dir()?;
-        let path = match self {
+    /// The path of the file written to, as given by the model.
+    pub fn path(&self) -> &str {
+        match self {
             FsWrite::Create { path, .. } => path,
             FsWrite::StrReplace { path, .. } => path,
             FsWrite::Insert { path, .. } => path,
             FsWrite::Append { path, .. } => path,
-        };
-        let relative_path = format_path(cwd, path);
+        }
+    }
+
+    fn print_relative_path(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
+        let cwd = ctx.env().current_dir()?;
+        let relative_path = format_path(cwd, self.path());
         queue!(
             updates,
             style::Print("Path: "),
@@ -358,6 +362,9 @@ fn get_lines_with_context(
     )
 }
 
+/// How many unchanged lines [print_diff] shows around the changes.
+const DIFF_CONTEXT_LINES: usize = 3;
+
 /// Prints a git-diff style comparison between `old_str` and `new_str`.
 /// - `start_line` - 1-indexed line number that `old_str` and `new_str` start at.
 fn print_diff(
@@ -388,74 +395,106 @@ fn print_diff(
             _ => " ".to_string(),
         }
     }
-    for change in diff.iter_all_changes() {
-        // Define the colors per line.
-        let (text_color, gutter_bg_color, line_bg_color) = match (change.tag(), new_str.truecolor) {
-            (similar::ChangeTag::Equal, true) => (style::Color::Reset, new_str.gutter_bg, new_str.line_bg),
-            (similar::ChangeTag::Delete, true) => (
-                style::Color::Reset,
-                style::Color::Rgb { r: 79, g: 40, b: 40 },
-                style::Color::Rgb { r: 36, g: 25, b: 28 },
-            ),
-            (similar::ChangeTag::Insert, true) => (
-                style::Color::Reset,
-                style::Color::Rgb { r: 40, g: 67, b: 43 },
-                style::Color::Rgb { r: 24, g: 38, b: 30 },
-            ),
-            (similar::ChangeTag::Equal, false) => (style::Color::Reset, new_str.gutter_bg, new_str.line_bg),
-            (similar::ChangeTag::Delete, false) => (style::Color::Red, new_str.gutter_bg, new_str.line_bg),
-            (similar::ChangeTag::Insert, false) => (style::Color::Green, new_str.gutter_bg, new_str.line_bg),
-        };
-        // Define the change tag character to print, if any.
-        let sign = match change.tag() {
-            similar::ChangeTag::Equal => " ",
-            similar::ChangeTag::Delete => "-",
-            similar::ChangeTag::Insert => "+",
-        };
-
-        let old_i_str = fmt_index(change.old_index(), start_line);
-        let new_i_str = fmt_index(change.new_index(), start_line);
-
-        // Print the gutter and line numbers.
-        queue!(updates, style::SetBackgroundColor(gutter_bg_color))?;
-        queue!(
-            updates,
-            style::SetForegroundColor(text_color),
-            style::Print(sign),
-            style::Print(" ")
-        )?;
-        queue!(
-            updates,
-            style::Print(format!(
-                "{:>old_line_num_width$}",
-                old_i_str,
-                old_line_num_width = old_line_num_width
-            ))
-        )?;
-        if sign == " " {
-            queue!(updates, style::Print(", "))?;
-        } else {
-            queue!(updates, style::Print("  "))?;
+    // The unchanged lines away from the changes are left out, e.g. when a whole file is replaced,
+    // each group of changes then starting with a unified diff hunk header
+    let hunks = diff.grouped_ops(DIFF_CONTEXT_LINES);
+    let shown = hunks
+        .iter()
+        .flatten()
+        .map(|op| diff.iter_changes(op).count())
+        .sum::<usize>();
+    let elided = shown < diff.iter_all_changes().count();
+    for hunk in &hunks {
+        if elided {
+            let (old, new) = match (hunk.first(), hunk.last()) {
+                (Some(first), Some(last)) => (
+                    first.old_range().start..last.old_range().end,
+                    first.new_range().start..last.new_range().end,
+                ),
+                _ => continue,
+            };
+            queue!(
+                updates,
+                style::SetForegroundColor(style::Color::Cyan),
+                style::Print(format!(
+                    "@@ -{},{} +{},{} @@\n",
+                    old.start + start_line,
+                    old.len(),
+                    new.start + start_line,
+                    new.len()
+                )),
+                style::ResetColor,
+            )?;
+        }
+        for change in hunk.iter().flat_map(|op| diff.iter_changes(op)) {
+            // Define the colors per line.
+            let (text_color, gutter_bg_color, line_bg_color) = match (change.tag(), new_str.truecolor) {
+                (similar::ChangeTag::Equal, true) => (style::Color::Reset, new_str.gutter_bg, new_str.line_bg),
+                (similar::ChangeTag::Delete, true) => (
+                    style::Color::Reset,
+                    style::Color::Rgb { r: 79, g: 40, b: 40 },
+                    style::Color::Rgb { r: 36, g: 25, b: 28 },
+                ),
+                (similar::ChangeTag::Insert, true) => (
+                    style::Color::Reset,
+                    style::Color::Rgb { r: 40, g: 67, b: 43 },
+                    style::Color::Rgb { r: 24, g: 38, b: 30 },
+                ),
+                (similar::ChangeTag::Equal, false) => (style::Color::Reset, new_str.gutter_bg, new_str.line_bg),
+                (similar::ChangeTag::Delete, false) => (style::Color::Red, new_str.gutter_bg, new_str.line_bg),
+                (similar::ChangeTag::Insert, false) => (style::Color::Green, new_str.gutter_bg, new_str.line_bg),
+            };
+            // Define the change tag character to print, if any.
+            let sign = match change.tag() {
+                similar::ChangeTag::Equal => " ",
+                similar::ChangeTag::Delete => "-",
+                similar::ChangeTag::Insert => "+",
+            };
+
+            let old_i_str = fmt_index(change.old_index(), start_line);
+            let new_i_str = fmt_index(change.new_index(), start_line);
+
+            // Print the gutter and line numbers.
+            queue!(updates, style::SetBackgroundColor(gutter_bg_color))?;
+            queue!(
+                updates,
+                style::SetForegroundColor(text_color),
+                style::Print(sign),
+                style::Print(" ")
+            )?;
+            queue!(
+                updates,
+                style::Print(format!(
+                    "{:>old_line_num_width$}",
+                    old_i_str,
+                    old_line_num_width = old_line_num_width
+                ))
+            )?;
+            if sign == " " {
+                queue!(updates, style::Print(", "))?;
+            } else {
+                queue!(updates, style::Print("  "))?;
+            }
+            queue!(
+                updates,
+                style::Print(format!(
+                    "{:>new_line_num_width$}",
+                    new_i_str,
+                    new_line_num_width = new_line_num_width
+                ))
+            )?;
+            // Print the line.
+            queue!(
+                updates,
+                style::SetForegroundColor(style::Color::Reset),
+                style::Print(":"),
+                style::SetForegroundColor(text_color),
+                style::SetBackgroundColor(line_bg_color),
+                style::Print(" "),
+                style::Print(change),
+                style::ResetColor,
+            )?;
         }
-        queue!(
-            updates,
-            style::Print(format!(
-                "{:>new_line_num_width$}",
-                new_i_str,
-                new_line_num_width = new_line_num_width
-            ))
-        )?;
-        // Print the line.
-        queue!(
-            updates,
-            style::SetForegroundColor(style::Color::Reset),
-            style::Print(":"),
-            style::SetForegroundColor(text_color),
-            style::SetBackgroundColor(line_bg_color),
-            style::Print(" "),
-            style::Print(change),
-            style::ResetColor,
-        )?;
     }
     queue!(
         updates,
@@ -488,7 +527,7 @@ fn terminal_width_required_for_line_count(line_count: usize) -> usize {
     line_count.to_string().chars().count()
 }
 
-fn stylize_output_if_able(ctx: &Context, path: impl AsRef<Path>, file_text: &str) -> StylizedFile {
+pub(super) fn stylize_output_if_able(ctx: &Context, path: impl AsRef<Path>, file_text: &str) -> StylizedFile {
     if supports_truecolor(ctx) {
         match stylized_file(path, file_text) {
             Ok(s) => return s,
@@ -507,12 +546,12 @@ fn stylize_output_if_able(ctx: &Context, path: impl AsRef<Path>, file_text: &str
 
 /// Represents a [String] that is potentially stylized with truecolor escape codes.
 #[derive(Debug)]
-struct StylizedFile {
+pub(super) struct StylizedFile {
     /// Whether or not the file is stylized with 24bit color.
-    truecolor: bool,
+    pub truecolor: bool,
     /// File content. If [Self::truecolor] is true, then it has escape codes for styling with 24bit
     /// color.
-    content: String,
+    pub content: String,
     /// Background color for the gutter.
     gutter_bg: style::Color,
     /// Background color for the line content.
@@ -941,6 +980,28 @@ mod tests {
         assert_eq!(get_lines_with_context(content, 4, 100, 2), ("World!\nhow\n", 2, "", 6));
     }
 
+    #[test]
+    fn test_print_diff_hunks() {
+        let old = (1..=20).map(|i| format!("line {i:02}\n")).collect::<Vec<_>>().concat();
+        let new = old.replace("line 10\n", "line ten\n");
+        let file = |content: &str| StylizedFile {
+            content: content.to_string(),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        print_diff(&mut out, &file(&old), &file(&new), 1).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("@@ -7,7 +7,7 @@"));
+        assert!(out.contains("line ten"));
+        assert!(out.contains("line 07") && out.contains("line 13"));
+        assert!(!out.contains("line 06") && !out.contains("line 14"));
+
+        // Nothing is left out of short changes, shown without a header
+        let mut out = Vec::new();
+        print_diff(&mut out, &file("a\nb\n"), &file("a\nc\n"), 1).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("@@"));
+    }
+
     #[test]
     fn test_gutter_width() {
         assert_eq!(terminal_width_required_for_line_count(1), 1);
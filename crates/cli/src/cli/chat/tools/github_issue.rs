@@ -0,0 +1,275 @@
+//! Files issues with a title, body and labels in the GitHub repository of
+//! `tools.githubIssue.repo`, the tool not being offered to the model until one is set. Unlike
+//! `report_issue`, which opens the browser on an issue about this CLI, the issue is created through
+//! the API, after asking.
+//!
+//! The token is the secret saved with `q chat secrets set github-token`, or with the name in
+//! `tools.githubIssue.tokenSecret`, and needs to be allowed to write issues. GitHub Enterprise is
+//! reached with `tools.githubIssue.apiUrl`, e.g. `https://github.example.com/api/v3`.
+
+use std::io::Write;
+use std::time::Duration;
+
+use crossterm::{
+    queue,
+    style,
+};
+use eyre::{
+    Context as _,
+    Result,
+    bail,
+};
+use reqwest::header::{
+    ACCEPT,
+    AUTHORIZATION,
+};
+use serde::Deserialize;
+
+use super::{
+    InvokeOutput,
+    OutputKind,
+};
+use crate::cli::chat::{
+    CONTINUATION_LINE,
+    secrets,
+};
+use crate::database::Database;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+use crate::request::new_client;
+
+/// The secret holding the token when `tools.githubIssue.tokenSecret` isn't set.
+const DEFAULT_TOKEN_SECRET: &str = "github-token";
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// How long creating an issue can take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many lines of the body of an issue its preview shows.
+const PREVIEW_LINES: usize = 20;
+
+/// Where issues are filed, from the settings.
+#[derive(Debug, Clone, Default)]
+pub struct GithubIssueConfig {
+    /// `owner/name`, the tool being disabled without it.
+    pub repo: Option<String>,
+    /// The name of the secret holding the token.
+    pub token_secret: String,
+    pub api_url: String,
+}
+
+impl GithubIssueConfig {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            repo: settings
+                .get_string(Setting::ToolsGithubIssueRepo)
+                .filter(|repo| !repo.is_empty()),
+            token_secret: settings
+                .get_string(Setting::ToolsGithubIssueTokenSecret)
+                .unwrap_or_else(|| DEFAULT_TOKEN_SECRET.to_string()),
+            api_url: settings
+                .get_string(Setting::ToolsGithubIssueApiUrl)
+                .unwrap_or_else(|| DEFAULT_API_URL.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GithubIssue {
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Set from the settings rather than by the model.
+    #[serde(skip)]
+    pub config: GithubIssueConfig,
+}
+
+/// The part of the issue created that the model is told about.
+#[derive(Debug, Deserialize)]
+struct CreatedIssue {
+    number: u64,
+    html_url: String,
+}
+
+impl GithubIssue {
+    /// Whether the tool is offered to the model, only once a repository is set.
+    pub fn is_enabled(settings: &Settings) -> bool {
+        GithubIssueConfig::from_settings(settings).repo.is_some()
+    }
+
+    pub fn repo(&self) -> &str {
+        self.config.repo.as_deref().unwrap_or_default()
+    }
+
+    pub async fn invoke(&self, mut updates: impl Write) -> Result<InvokeOutput> {
+        let database = Database::new().await?;
+        let token = secrets::get(&database, &self.config.token_secret).await?;
+        let url = format!(
+            "{}/repos/{}/issues",
+            self.config.api_url.trim_end_matches('/'),
+            self.repo()
+        );
+        let response = new_client()?
+            .post(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .header(ACCEPT, "application/vnd.github+json")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .json(&serde_json::json!({
+                "title": self.title,
+                "body": self.body,
+                "labels": self.labels,
+            }))
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to send the request to {url}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response
+                .json::<serde_json::Value>()
+                .await
+                .ok()
+                .and_then(|error| Some(error.get("message")?.as_str()?.to_string()))
+                .unwrap_or_default();
+            bail!(
+                "GitHub refused to create the issue in {}: {status} {message}",
+                self.repo()
+            );
+        }
+        let issue = response.json::<CreatedIssue>().await?;
+        queue!(
+            updates,
+            style::Print(format!("Filed {}#{}: {}\n", self.repo(), issue.number, issue.html_url))
+        )?;
+        Ok(InvokeOutput {
+            output: OutputKind::Json(serde_json::json!({
+                "number": issue.number,
+                "url": issue.html_url,
+            })),
+        })
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("I will file an issue in "),
+            style::SetForegroundColor(style::Color::Green),
+            style::Print(self.repo()),
+            style::ResetColor,
+            style::Print(":\n"),
+            style::SetForegroundColor(style::Color::Green),
+            style::Print(format!("{}\n", self.title)),
+            style::ResetColor,
+        )?;
+        if !self.labels.is_empty() {
+            queue!(updates, style::Print(format!("Labels: {}\n", self.labels.join(", "))))?;
+        }
+        if !self.body.is_empty() {
+            queue!(updates, style::Print("\n"))?;
+            for line in self.body.lines().take(PREVIEW_LINES) {
+                queue!(updates, style::Print(format!("{line}\n")))?;
+            }
+            let lines = self.body.lines().count();
+            if lines > PREVIEW_LINES {
+                queue!(
+                    updates,
+                    style::Print(CONTINUATION_LINE),
+                    style::Print(format!(" {} more lines\n", lines - PREVIEW_LINES)),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn validate(&mut self, _ctx: &Context) -> Result<()> {
+        let Some(repo) = &self.config.repo else {
+            bail!("no repository is set for issues, set one with: q settings tools.githubIssue.repo OWNER/NAME");
+        };
+        let valid = repo.split('/').count() == 2
+            && repo
+                .split('/')
+                .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)));
+        if !valid {
+            bail!("'{repo}' in tools.githubIssue.repo isn't of the form OWNER/NAME");
+        }
+        if self.title.trim().is_empty() {
+            bail!("the title of the issue is empty");
+        }
+        if self.labels.iter().any(|label| label.trim().is_empty()) {
+            bail!("the labels of the issue can't be empty");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn github_issue(repo: Option<&str>, value: serde_json::Value) -> GithubIssue {
+        let mut github_issue = serde_json::from_value::<GithubIssue>(value).unwrap();
+        github_issue.config = GithubIssueConfig {
+            repo: repo.map(str::to_string),
+            token_secret: DEFAULT_TOKEN_SECRET.to_string(),
+            api_url: DEFAULT_API_URL.to_string(),
+        };
+        github_issue
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let ctx = Context::new();
+        let issue = serde_json::json!({
+            "title": "test_parse_args is flaky",
+            "body": "Fails one run in ten on CI",
+            "labels": ["bug", "flaky-test"],
+        });
+        assert!(
+            github_issue(Some("acme/app"), issue.clone())
+                .validate(&ctx)
+                .await
+                .is_ok()
+        );
+        assert!(github_issue(None, issue.clone()).validate(&ctx).await.is_err());
+        for repo in ["acme", "acme/app/issues", "acme/", "acme/app?x=1"] {
+            assert!(
+                github_issue(Some(repo), issue.clone()).validate(&ctx).await.is_err(),
+                "{repo}"
+            );
+        }
+        assert!(
+            github_issue(Some("acme/app"), serde_json::json!({ "title": " " }))
+                .validate(&ctx)
+                .await
+                .is_err()
+        );
+        assert!(
+            github_issue(Some("acme/app"), serde_json::json!({ "title": "a", "labels": [""] }))
+                .validate(&ctx)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_queue_description() {
+        let issue = github_issue(
+            Some("acme/app"),
+            serde_json::json!({ "title": "Flaky test", "body": "Fails on CI", "labels": ["bug"] }),
+        );
+        let mut description = Vec::new();
+        issue.queue_description(&mut description).unwrap();
+        let description = String::from_utf8_lossy(&description);
+        assert!(description.contains("acme/app"));
+        assert!(description.contains("Flaky test"));
+        assert!(description.contains("Labels: bug"));
+        assert!(description.contains("Fails on CI"));
+    }
+}
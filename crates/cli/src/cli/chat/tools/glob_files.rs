@@ -0,0 +1,175 @@
+//! The `glob_files` tool, finding the files of the workspace whose path matches a glob pattern
+//! without running a command, leaving out those ignored by `.gitignore`.
+
+use std::io::Write;
+use std::path::Path;
+
+use crossterm::queue;
+use crossterm::style::{
+    self,
+    Color,
+};
+use eyre::{
+    Result,
+    bail,
+};
+use glob::Pattern;
+use serde::Deserialize;
+
+use super::{
+    InvokeOutput,
+    OutputKind,
+    format_path,
+    sanitize_path_tool_arg,
+};
+use crate::cli::chat::context::{
+    GLOB_OPTIONS,
+    walk_dir,
+};
+use crate::platform::Context;
+
+/// How many files are listed when the model doesn't say.
+const DEFAULT_MAX_RESULTS: usize = 500;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GlobFiles {
+    /// e.g. `**/*.rs` or `src/**/test_*.py`, matching file names at any depth when without a `/`.
+    pub pattern: String,
+    /// The directory to look in, the current one by default.
+    pub path: Option<String>,
+    pub max_results: Option<usize>,
+}
+
+impl GlobFiles {
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(".")
+    }
+
+    pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        if let Err(err) = Pattern::new(&self.pattern) {
+            bail!("Invalid pattern '{}': {err}", self.pattern);
+        }
+        let path = sanitize_path_tool_arg(ctx, self.path());
+        if !ctx
+            .fs()
+            .symlink_metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            bail!("Directory not found: {}", self.path());
+        }
+        Ok(())
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("Finding the files matching "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(&self.pattern),
+            style::ResetColor,
+            style::Print(" in "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(self.path()),
+            style::ResetColor,
+        )?;
+        Ok(())
+    }
+
+    pub async fn invoke(&self, ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
+        let pattern = Pattern::new(&self.pattern)?;
+        let max_results = self.max_results.unwrap_or(DEFAULT_MAX_RESULTS);
+        // The paths walked are those of the chroot in tests
+        let root = ctx.fs().chroot_path(sanitize_path_tool_arg(ctx, self.path()));
+        let cwd = ctx.fs().chroot_path(ctx.env().current_dir()?);
+
+        let mut files = Vec::new();
+        let mut truncated = false;
+        for path in walk_dir(ctx, &root, None).await? {
+            let Ok(relative) = path.strip_prefix(&root) else {
+                continue;
+            };
+            if !matches(&pattern, relative) {
+                continue;
+            }
+            if files.len() == max_results {
+                truncated = true;
+                break;
+            }
+            files.push(format_path(&cwd, &path));
+        }
+
+        queue!(
+            updates,
+            style::Print(format!(
+                "Found {}{} files\n",
+                files.len(),
+                if truncated { "+" } else { "" }
+            ))
+        )?;
+        Ok(InvokeOutput {
+            output: OutputKind::Json(serde_json::json!({
+                "files": files,
+                "truncated": truncated,
+            })),
+        })
+    }
+}
+
+/// Whether `relative`, a path relative to where the search started, matches `pattern`. Patterns
+/// without a `/` match the name of files at any depth, as in `.gitignore` files.
+pub fn matches(pattern: &Pattern, relative: &Path) -> bool {
+    match pattern.as_str().contains('/') {
+        true => pattern.matches_path_with(relative, GLOB_OPTIONS),
+        false => relative
+            .file_name()
+            .is_some_and(|name| pattern.matches_with(&name.to_string_lossy(), GLOB_OPTIONS)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let pattern = |pattern: &str| Pattern::new(pattern).unwrap();
+        assert!(matches(&pattern("*.rs"), Path::new("src/cli/main.rs")));
+        assert!(!matches(&pattern("*.rs"), Path::new("src/main.rs.orig")));
+        assert!(matches(&pattern("src/**/*.rs"), Path::new("src/cli/main.rs")));
+        assert!(!matches(&pattern("src/*.rs"), Path::new("src/cli/main.rs")));
+        assert!(matches(&pattern("**/test_*.py"), Path::new("tests/test_api.py")));
+    }
+
+    #[tokio::test]
+    async fn test_invoke() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let fs = ctx.fs();
+        fs.create_dir_all("/project/src/cli").await.unwrap();
+        fs.create_dir_all("/project/target").await.unwrap();
+        fs.write("/project/.gitignore", "target/\n").await.unwrap();
+        fs.write("/project/src/main.rs", "fn main() {}").await.unwrap();
+        fs.write("/project/src/cli/mod.rs", "mod cli;").await.unwrap();
+        fs.write("/project/target/build.rs", "").await.unwrap();
+        fs.write("/project/README.md", "").await.unwrap();
+
+        let mut glob_files = serde_json::from_value::<GlobFiles>(serde_json::json!({
+            "pattern": "*.rs",
+            "path": "/project",
+        }))
+        .unwrap();
+        glob_files.validate(&ctx).await.unwrap();
+        let output = glob_files.invoke(&ctx, &mut std::io::sink()).await.unwrap();
+        let OutputKind::Json(json) = output.output else {
+            panic!("expected JSON output");
+        };
+        let files = json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| file.as_str().unwrap().trim_start_matches('/').to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["project/src/cli/mod.rs", "project/src/main.rs"]);
+        assert_eq!(json["truncated"], false);
+    }
+}
@@ -0,0 +1,283 @@
+//! The `grep_search` tool, searching the files of the workspace for a regular expression without
+//! running a command. As with ripgrep, the files ignored by `.gitignore` are left out, and so are
+//! binary and large ones.
+
+use std::io::Write;
+
+use crossterm::queue;
+use crossterm::style::{
+    self,
+    Color,
+};
+use eyre::{
+    Result,
+    bail,
+};
+use glob::Pattern;
+use regex::{
+    Regex,
+    RegexBuilder,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::glob_files::matches;
+use super::{
+    InvokeOutput,
+    OutputKind,
+    format_path,
+    sanitize_path_tool_arg,
+};
+use crate::cli::chat::context::walk_dir;
+use crate::platform::Context;
+
+/// How many matches are returned when the model doesn't say.
+const DEFAULT_MAX_RESULTS: usize = 100;
+
+/// Files bigger than this aren't searched, being generated or data more often than not.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// How much of the start of a file is checked for a nul byte to tell that it's binary.
+const BINARY_CHECK_BYTES: usize = 8 * 1024;
+
+/// How much of a matching line is returned.
+const MAX_SNIPPET_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GrepSearch {
+    /// A regular expression, in the syntax of the `regex` crate.
+    pub pattern: String,
+    /// The file or directory to search, the current directory by default.
+    pub path: Option<String>,
+    /// Only searches the files matching this glob, e.g. `*.rs`.
+    pub include: Option<String>,
+    #[serde(default)]
+    pub case_insensitive: bool,
+    pub max_results: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GrepMatch {
+    /// Relative to the current directory when under it.
+    pub file: String,
+    /// Counted from 1.
+    pub line: usize,
+    pub snippet: String,
+}
+
+impl GrepSearch {
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(".")
+    }
+
+    fn regex(&self) -> Result<Regex> {
+        Ok(RegexBuilder::new(&self.pattern)
+            .case_insensitive(self.case_insensitive)
+            .build()?)
+    }
+
+    pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        if let Err(err) = self.regex() {
+            bail!("Invalid pattern '{}': {err}", self.pattern);
+        }
+        if let Some(include) = &self.include {
+            if let Err(err) = Pattern::new(include) {
+                bail!("Invalid include glob '{include}': {err}");
+            }
+        }
+        let path = sanitize_path_tool_arg(ctx, self.path());
+        if ctx.fs().symlink_metadata(&path).await.is_err() {
+            bail!("Path not found: {}", self.path());
+        }
+        Ok(())
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("Searching for "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(&self.pattern),
+            style::ResetColor,
+            style::Print(" in "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(self.path()),
+            style::ResetColor,
+        )?;
+        if let Some(include) = &self.include {
+            queue!(
+                updates,
+                style::Print(" (files matching "),
+                style::SetForegroundColor(Color::Green),
+                style::Print(include),
+                style::ResetColor,
+                style::Print(")"),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub async fn invoke(&self, ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
+        let regex = self.regex()?;
+        let include = self.include.as_deref().map(Pattern::new).transpose()?;
+        let max_results = self.max_results.unwrap_or(DEFAULT_MAX_RESULTS);
+        // The paths walked are those of the chroot in tests
+        let root = ctx.fs().chroot_path(sanitize_path_tool_arg(ctx, self.path()));
+        let cwd = ctx.fs().chroot_path(ctx.env().current_dir()?);
+        let files = match ctx.fs().symlink_metadata(&root).await?.is_dir() {
+            true => walk_dir(ctx, &root, None).await?,
+            false => vec![root.clone()],
+        };
+
+        let mut results = Vec::new();
+        let mut files_matched = 0;
+        let mut truncated = false;
+        'files: for path in files {
+            if let (Some(include), Ok(relative)) = (&include, path.strip_prefix(&root)) {
+                // A file given as the path is searched whatever its name
+                if !relative.as_os_str().is_empty() && !matches(include, relative) {
+                    continue;
+                }
+            }
+            if ctx
+                .fs()
+                .symlink_metadata(&path)
+                .await
+                .is_ok_and(|metadata| metadata.len() > MAX_FILE_BYTES)
+            {
+                continue;
+            }
+            let Ok(contents) = ctx.fs().read(&path).await else {
+                continue;
+            };
+            if contents[..contents.len().min(BINARY_CHECK_BYTES)].contains(&0) {
+                continue;
+            }
+
+            let mut matched = false;
+            for (i, line) in String::from_utf8_lossy(&contents).lines().enumerate() {
+                if !regex.is_match(line) {
+                    continue;
+                }
+                if results.len() == max_results {
+                    truncated = true;
+                    break 'files;
+                }
+                matched = true;
+                results.push(GrepMatch {
+                    file: format_path(&cwd, &path),
+                    line: i + 1,
+                    snippet: snippet(line),
+                });
+            }
+            files_matched += usize::from(matched);
+        }
+
+        queue!(
+            updates,
+            style::Print(format!(
+                "Found {}{} matches in {files_matched} files\n",
+                results.len(),
+                if truncated { "+" } else { "" }
+            ))
+        )?;
+        Ok(InvokeOutput {
+            output: OutputKind::Json(serde_json::json!({
+                "matches": results,
+                "truncated": truncated,
+            })),
+        })
+    }
+}
+
+/// `line` trimmed, cut at [MAX_SNIPPET_CHARS].
+fn snippet(line: &str) -> String {
+    let line = line.trim();
+    match line.char_indices().nth(MAX_SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grep_search(value: serde_json::Value) -> GrepSearch {
+        serde_json::from_value(value).unwrap()
+    }
+
+    async fn search(ctx: &Context, value: serde_json::Value) -> serde_json::Value {
+        let mut grep_search = grep_search(value);
+        grep_search.validate(ctx).await.unwrap();
+        match grep_search.invoke(ctx, &mut std::io::sink()).await.unwrap().output {
+            OutputKind::Json(json) => json,
+            _ => panic!("expected JSON output"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invoke() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let fs = ctx.fs();
+        fs.create_dir_all("/project/src").await.unwrap();
+        fs.create_dir_all("/project/target").await.unwrap();
+        fs.write("/project/.gitignore", "target/\n").await.unwrap();
+        fs.write("/project/src/main.rs", "fn main() {\n    run_app();\n}\n")
+            .await
+            .unwrap();
+        fs.write("/project/src/app.py", "def run_app():\n    pass\n")
+            .await
+            .unwrap();
+        fs.write("/project/target/main.rs", "run_app();\n").await.unwrap();
+        fs.write("/project/src/app.bin", b"run_app\0").await.unwrap();
+
+        let json = search(&ctx, serde_json::json!({ "pattern": "run_app", "path": "/project" })).await;
+        let matches = json["matches"].as_array().unwrap();
+        assert_eq!(matches.len(), 2, "{json}");
+        assert!(matches[0]["file"].as_str().unwrap().ends_with("project/src/app.py"));
+        assert_eq!(matches[0]["line"], 1);
+        assert_eq!(matches[0]["snippet"], "def run_app():");
+        assert!(matches[1]["file"].as_str().unwrap().ends_with("project/src/main.rs"));
+        assert_eq!(matches[1]["line"], 2);
+        assert_eq!(matches[1]["snippet"], "run_app();");
+        assert_eq!(json["truncated"], false);
+
+        let json = search(
+            &ctx,
+            serde_json::json!({ "pattern": "RUN_APP", "path": "/project", "include": "*.rs", "case_insensitive": true }),
+        )
+        .await;
+        assert_eq!(json["matches"].as_array().unwrap().len(), 1, "{json}");
+
+        let json = search(
+            &ctx,
+            serde_json::json!({ "pattern": "run_app", "path": "/project", "max_results": 1 }),
+        )
+        .await;
+        assert_eq!(json["matches"].as_array().unwrap().len(), 1);
+        assert_eq!(json["truncated"], true);
+
+        assert!(
+            grep_search(serde_json::json!({ "pattern": "(" }))
+                .validate(&ctx)
+                .await
+                .is_err()
+        );
+        assert!(
+            grep_search(serde_json::json!({ "pattern": "a", "path": "/missing" }))
+                .validate(&ctx)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(snippet("   let a = 1;  "), "let a = 1;");
+        let long = "x".repeat(MAX_SNIPPET_CHARS + 10);
+        assert_eq!(snippet(&long), format!("{}...", "x".repeat(MAX_SNIPPET_CHARS)));
+    }
+}
@@ -0,0 +1,366 @@
+//! Sends GET and POST requests to the domains of `tools.httpRequest.allowedDomains`, e.g. internal
+//! APIs and package registries, the tool not being offered to the model until some are set.
+//!
+//! The headers of `tools.httpRequest.headers` are added to the requests to their domain, each
+//! `{{secret:NAME}}` in their values replaced by the secret saved with `q chat secrets set NAME`,
+//! which neither the model nor the preview of the request see:
+//!
+//! ```json
+//! { "api.internal.example.com": { "Authorization": "Bearer {{secret:internal-api}}" } }
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+
+use crossterm::{
+    queue,
+    style,
+};
+use eyre::{
+    Context as _,
+    Result,
+    bail,
+};
+use reqwest::header::{
+    CONTENT_TYPE,
+    HeaderName,
+    HeaderValue,
+    LOCATION,
+};
+use serde::Deserialize;
+use url::Url;
+
+use super::environment::list;
+use super::{
+    InvokeOutput,
+    OutputKind,
+};
+use crate::cli::chat::url_context::is_allowed;
+use crate::cli::chat::{
+    CONTINUATION_LINE,
+    secrets,
+};
+use crate::database::Database;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+use crate::request::new_client_without_redirects;
+
+/// How much of a response is kept when `tools.httpRequest.maxBytes` isn't set.
+const DEFAULT_MAX_BYTES: usize = 100 * 1024;
+
+/// How long a request can take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many lines of the body of a request its preview shows.
+const PREVIEW_LINES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+    Get,
+    Post,
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Method::Get => write!(f, "GET"),
+            Method::Post => write!(f, "POST"),
+        }
+    }
+}
+
+/// Where requests can go and what is added to them, from the settings.
+#[derive(Debug, Clone, Default)]
+pub struct HttpConfig {
+    /// The domains requests can go to along with their subdomains, none when empty.
+    pub allowed_domains: Vec<String>,
+    /// The headers added to the requests to each domain, with their `{{secret:NAME}}` unresolved.
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+    /// How much of the body of a response is kept.
+    pub max_bytes: usize,
+}
+
+impl HttpConfig {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let headers = settings
+            .get(Setting::ToolsHttpRequestHeaders)
+            .and_then(|headers| serde_json::from_value(headers.clone()).ok())
+            .unwrap_or_default();
+        Self {
+            allowed_domains: list(settings, Setting::ToolsHttpRequestAllowedDomains).unwrap_or_default(),
+            headers,
+            max_bytes: settings
+                .get_int(Setting::ToolsHttpRequestMaxBytes)
+                .and_then(|max_bytes| usize::try_from(max_bytes).ok())
+                .unwrap_or(DEFAULT_MAX_BYTES),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+    /// Set from the settings rather than by the model.
+    #[serde(skip)]
+    pub config: HttpConfig,
+}
+
+impl HttpRequest {
+    /// Whether the tool is offered to the model, only once some domains are allowed.
+    pub fn is_enabled(settings: &Settings) -> bool {
+        !HttpConfig::from_settings(settings).allowed_domains.is_empty()
+    }
+
+    /// Whether the request can change anything, only GET requests being taken to not.
+    pub fn mutates(&self) -> bool {
+        self.method != Method::Get
+    }
+
+    /// The headers sent, those of the model but for the ones configured for any domain, then those
+    /// configured for the domain. The values of the configured ones still have their
+    /// `{{secret:NAME}}`.
+    fn headers(&self, url: &Url) -> BTreeMap<String, String> {
+        let configured = self
+            .config
+            .headers
+            .values()
+            .flat_map(|headers| headers.keys().map(|name| name.to_lowercase()))
+            .collect::<Vec<_>>();
+        let mut headers = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.clone()))
+            .filter(|(name, _)| !configured.contains(name))
+            .collect::<BTreeMap<_, _>>();
+        for (domain, domain_headers) in &self.config.headers {
+            if is_allowed(url, std::slice::from_ref(domain)) {
+                for (name, value) in domain_headers {
+                    headers.insert(name.to_lowercase(), value.clone());
+                }
+            }
+        }
+        headers
+    }
+
+    pub async fn invoke(&self, mut updates: impl Write) -> Result<InvokeOutput> {
+        let url = Url::parse(&self.url)?;
+        let database = Database::new().await?;
+        let mut request = new_client_without_redirects()?
+            .request(
+                match self.method {
+                    Method::Get => reqwest::Method::GET,
+                    Method::Post => reqwest::Method::POST,
+                },
+                url.clone(),
+            )
+            .timeout(REQUEST_TIMEOUT);
+        for (name, value) in self.headers(&url) {
+            let value = secrets::resolve(&database, &value).await?;
+            request = request.header(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(&value)?);
+        }
+        if let Some(body) = &self.body {
+            request = request.body(body.clone());
+        }
+
+        let mut response = request
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to send the request to {url}"))?;
+        let status = response.status();
+        let content_type = header(&response, CONTENT_TYPE);
+        let location = header(&response, LOCATION);
+        let mut body = Vec::new();
+        let mut truncated = false;
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > self.config.max_bytes {
+                body.extend_from_slice(&chunk[..self.config.max_bytes - body.len()]);
+                truncated = true;
+                break;
+            }
+            body.extend_from_slice(&chunk);
+        }
+        queue!(
+            updates,
+            style::Print(format!("{status}, {} bytes received\n", body.len()))
+        )?;
+
+        let textual = content_type.is_empty()
+            || content_type.starts_with("text/")
+            || ["json", "xml", "javascript", "yaml", "x-www-form-urlencoded"]
+                .iter()
+                .any(|kind| content_type.contains(kind));
+        let body = match textual {
+            true => String::from_utf8_lossy(&body).into_owned(),
+            false => format!("[{} bytes of {content_type}, not shown]", body.len()),
+        };
+        let mut result = serde_json::json!({
+            "status": status.as_u16(),
+            "content_type": content_type,
+            "body": body,
+        });
+        let mut notes = Vec::new();
+        if !location.is_empty() {
+            result["location"] = location.into();
+            notes.push("Redirects aren't followed, send another request to the location if it's allowed".to_string());
+        }
+        if truncated {
+            notes.push(format!("The body was cut at {} bytes", self.config.max_bytes));
+        }
+        if !notes.is_empty() {
+            result["note"] = notes.join(". ").into();
+        }
+        Ok(InvokeOutput {
+            output: OutputKind::Json(result),
+        })
+    }
+
+    /// Previews the request in full, the secrets of its headers left as `{{secret:NAME}}`.
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("I will send the request:\n"),
+            style::SetForegroundColor(style::Color::Green),
+            style::Print(format!("{} {}\n", self.method, self.url)),
+            style::ResetColor,
+        )?;
+        if let Ok(url) = Url::parse(&self.url) {
+            for (name, value) in self.headers(&url) {
+                queue!(updates, style::Print(format!("{name}: {value}\n")))?;
+            }
+        }
+        if let Some(body) = &self.body {
+            queue!(updates, style::Print("\n"))?;
+            for line in body.lines().take(PREVIEW_LINES) {
+                queue!(updates, style::Print(format!("{line}\n")))?;
+            }
+            let lines = body.lines().count();
+            if lines > PREVIEW_LINES {
+                queue!(
+                    updates,
+                    style::Print(CONTINUATION_LINE),
+                    style::Print(format!(" {} more lines\n", lines - PREVIEW_LINES)),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn validate(&mut self, _ctx: &Context) -> Result<()> {
+        let url = Url::parse(&self.url).wrap_err("invalid URL")?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("only http and https URLs can be requested");
+        }
+        let host = url.host_str().unwrap_or_default();
+        if self.config.allowed_domains.is_empty() || !is_allowed(&url, &self.config.allowed_domains) {
+            bail!(
+                "{host} isn't one of the domains the user allows, which are {}",
+                self.config.allowed_domains.join(", ")
+            );
+        }
+        let configured = self
+            .config
+            .headers
+            .iter()
+            .any(|(domain, headers)| !headers.is_empty() && is_allowed(&url, std::slice::from_ref(domain)));
+        if configured && url.scheme() != "https" {
+            bail!("the headers the user sets for {host} are only sent over https, use an https URL");
+        }
+        if self.method == Method::Get && self.body.is_some() {
+            bail!("GET requests can't have a body");
+        }
+        for (name, value) in &self.headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() || HeaderValue::from_str(value).is_err() {
+                bail!("'{name}: {value}' isn't a valid header");
+            }
+            if value.contains("{{secret:") {
+                bail!("'{name}' can't refer to a secret, only the headers the user sets for a domain can");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value of the header `name` of `response`, empty when it has none.
+fn header(response: &reqwest::Response, name: HeaderName) -> String {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_request(value: serde_json::Value) -> HttpRequest {
+        let mut http_request = serde_json::from_value::<HttpRequest>(value).unwrap();
+        http_request.config = HttpConfig {
+            allowed_domains: vec!["example.com".to_string(), "registry.npmjs.org".to_string()],
+            headers: BTreeMap::from([(
+                "api.example.com".to_string(),
+                BTreeMap::from([("Authorization".to_string(), "Bearer {{secret:api}}".to_string())]),
+            )]),
+            max_bytes: DEFAULT_MAX_BYTES,
+        };
+        http_request
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let ctx = Context::new();
+        let mut request =
+            http_request(serde_json::json!({ "method": "GET", "url": "https://api.example.com/v1/items" }));
+        assert!(request.validate(&ctx).await.is_ok());
+        assert!(!request.mutates());
+        // Only the domains with headers set need https
+        let mut request = http_request(serde_json::json!({ "method": "GET", "url": "http://example.com" }));
+        assert!(request.validate(&ctx).await.is_ok());
+
+        for value in [
+            serde_json::json!({ "method": "GET", "url": "https://evil.com/?q=example.com" }),
+            serde_json::json!({ "method": "GET", "url": "file:///etc/passwd" }),
+            serde_json::json!({ "method": "GET", "url": "https://example.com", "body": "{}" }),
+            serde_json::json!({ "method": "POST", "url": "https://example.com", "headers": { "Bad Name": "1" } }),
+            serde_json::json!({ "method": "GET", "url": "https://example.com", "headers": { "X-Token": "{{secret:api}}" } }),
+            serde_json::json!({ "method": "GET", "url": "http://api.example.com/v1/items" }),
+        ] {
+            assert!(http_request(value.clone()).validate(&ctx).await.is_err(), "{value}");
+        }
+
+        let mut request = http_request(serde_json::json!({ "method": "GET", "url": "https://example.com" }));
+        request.config.allowed_domains.clear();
+        assert!(request.validate(&ctx).await.is_err());
+    }
+
+    #[test]
+    fn test_headers() {
+        let request = http_request(serde_json::json!({
+            "method": "POST",
+            "url": "https://api.example.com/v1/items",
+            "headers": { "Content-Type": "application/json", "authorization": "Bearer guessed" },
+            "body": "{}"
+        }));
+        assert!(request.mutates());
+        let url = Url::parse(&request.url).unwrap();
+        assert_eq!(
+            request.headers(&url),
+            BTreeMap::from([
+                ("authorization".to_string(), "Bearer {{secret:api}}".to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+            ])
+        );
+        let url = Url::parse("https://registry.npmjs.org/react").unwrap();
+        assert!(!request.headers(&url).contains_key("authorization"));
+    }
+}
@@ -1,18 +1,34 @@
+pub mod command_tool;
 pub mod custom_tool;
+pub mod environment;
 pub mod execute_bash;
 pub mod fs_read;
 pub mod fs_write;
 pub mod gh_issue;
+pub mod github_issue;
+pub mod glob_files;
+pub mod grep_search;
+pub mod http_request;
+pub mod process_output;
+#[cfg(unix)]
+pub mod pty;
+pub mod rules;
+pub mod sandbox;
 pub mod thinking;
 pub mod use_aws;
 
-use std::collections::HashMap;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
 use std::io::Write;
 use std::path::{
     Path,
     PathBuf,
 };
+use std::time::Duration;
 
+use command_tool::CommandTool;
 use crossterm::style::Stylize;
 use custom_tool::CustomTool;
 use execute_bash::ExecuteBash;
@@ -20,6 +36,15 @@ use eyre::Result;
 use fs_read::FsRead;
 use fs_write::FsWrite;
 use gh_issue::GhIssue;
+use github_issue::GithubIssue;
+use glob_files::GlobFiles;
+use grep_search::GrepSearch;
+use http_request::HttpRequest;
+use process_output::ProcessOutput;
+use rules::{
+    ToolPattern,
+    ToolRules,
+};
 use serde::{
     Deserialize,
     Serialize,
@@ -29,8 +54,14 @@ use use_aws::UseAws;
 
 use super::consts::MAX_TOOL_RESPONSE_SIZE;
 use super::util::images::RichImageBlocks;
-use crate::database::Database;
-use crate::database::settings::Setting;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::database::{
+    Database,
+    DatabaseError,
+};
 use crate::platform::Context;
 
 /// Represents an executable tool use.
@@ -39,9 +70,15 @@ use crate::platform::Context;
 pub enum Tool {
     FsRead(FsRead),
     FsWrite(FsWrite),
+    GrepSearch(GrepSearch),
+    GlobFiles(GlobFiles),
     ExecuteBash(ExecuteBash),
+    ProcessOutput(ProcessOutput),
     UseAws(UseAws),
+    HttpRequest(HttpRequest),
+    GithubIssue(GithubIssue),
     Custom(CustomTool),
+    Command(CommandTool),
     GhIssue(GhIssue),
     Thinking(Thinking),
 }
@@ -52,9 +89,15 @@ impl Tool {
         match self {
             Tool::FsRead(_) => "fs_read",
             Tool::FsWrite(_) => "fs_write",
+            Tool::GrepSearch(_) => "grep_search",
+            Tool::GlobFiles(_) => "glob_files",
             Tool::ExecuteBash(_) => "execute_bash",
+            Tool::ProcessOutput(_) => "process_output",
             Tool::UseAws(_) => "use_aws",
+            Tool::HttpRequest(_) => "http_request",
+            Tool::GithubIssue(_) => "create_github_issue",
             Tool::Custom(custom_tool) => &custom_tool.name,
+            Tool::Command(command_tool) => &command_tool.name,
             Tool::GhIssue(_) => "gh_issue",
             Tool::Thinking(_) => "thinking (prerelease)",
         }
@@ -66,22 +109,55 @@ impl Tool {
         match self {
             Tool::FsRead(_) => false,
             Tool::FsWrite(_) => true,
+            Tool::GrepSearch(_) | Tool::GlobFiles(_) => false,
             Tool::ExecuteBash(execute_bash) => execute_bash.requires_acceptance(),
+            Tool::ProcessOutput(_) => false,
             Tool::UseAws(use_aws) => use_aws.requires_acceptance(),
+            Tool::HttpRequest(_) => true,
+            Tool::GithubIssue(_) => true,
             Tool::Custom(_) => true,
+            Tool::Command(command_tool) => command_tool.requires_acceptance(),
             Tool::GhIssue(_) => false,
             Tool::Thinking(_) => false,
         }
     }
 
+    /// Whether the tool can change anything, refused in read-only mode. Only the commands of
+    /// `execute_bash` and the operations of `use_aws` run without asking can't, nor GET requests,
+    /// and the tools of MCP servers that `spec` marks read-only.
+    pub fn mutates(&self, spec: Option<&ToolSpec>) -> bool {
+        match self {
+            Tool::FsRead(_)
+            | Tool::GrepSearch(_)
+            | Tool::GlobFiles(_)
+            | Tool::ProcessOutput(_)
+            | Tool::GhIssue(_)
+            | Tool::Thinking(_) => false,
+            Tool::FsWrite(_) | Tool::GithubIssue(_) | Tool::Command(_) => true,
+            Tool::ExecuteBash(execute_bash) => execute_bash.requires_acceptance(),
+            Tool::UseAws(use_aws) => !use_aws.is_read_only(),
+            Tool::HttpRequest(http_request) => http_request.mutates(),
+            Tool::Custom(_) => !spec
+                .and_then(|spec| spec.annotations.as_ref())
+                .and_then(|annotations| annotations.read_only_hint)
+                .unwrap_or(false),
+        }
+    }
+
     /// Invokes the tool asynchronously
     pub async fn invoke(&self, context: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
         match self {
             Tool::FsRead(fs_read) => fs_read.invoke(context, updates).await,
             Tool::FsWrite(fs_write) => fs_write.invoke(context, updates).await,
+            Tool::GrepSearch(grep_search) => grep_search.invoke(context, updates).await,
+            Tool::GlobFiles(glob_files) => glob_files.invoke(context, updates).await,
             Tool::ExecuteBash(execute_bash) => execute_bash.invoke(updates).await,
+            Tool::ProcessOutput(process_output) => process_output.invoke(updates).await,
             Tool::UseAws(use_aws) => use_aws.invoke(context, updates).await,
+            Tool::HttpRequest(http_request) => http_request.invoke(updates).await,
+            Tool::GithubIssue(github_issue) => github_issue.invoke(updates).await,
             Tool::Custom(custom_tool) => custom_tool.invoke(context, updates).await,
+            Tool::Command(command_tool) => command_tool.invoke(updates).await,
             Tool::GhIssue(gh_issue) => gh_issue.invoke(updates).await,
             Tool::Thinking(think) => think.invoke(updates).await,
         }
@@ -92,9 +168,15 @@ impl Tool {
         match self {
             Tool::FsRead(fs_read) => fs_read.queue_description(ctx, updates).await,
             Tool::FsWrite(fs_write) => fs_write.queue_description(ctx, updates),
-            Tool::ExecuteBash(execute_bash) => execute_bash.queue_description(updates),
+            Tool::GrepSearch(grep_search) => grep_search.queue_description(updates),
+            Tool::GlobFiles(glob_files) => glob_files.queue_description(updates),
+            Tool::ExecuteBash(execute_bash) => execute_bash.queue_description(ctx, updates),
+            Tool::ProcessOutput(process_output) => process_output.queue_description(updates),
             Tool::UseAws(use_aws) => use_aws.queue_description(updates),
+            Tool::HttpRequest(http_request) => http_request.queue_description(updates),
+            Tool::GithubIssue(github_issue) => github_issue.queue_description(updates),
             Tool::Custom(custom_tool) => custom_tool.queue_description(updates),
+            Tool::Command(command_tool) => command_tool.queue_description(updates),
             Tool::GhIssue(gh_issue) => gh_issue.queue_description(updates),
             Tool::Thinking(thinking) => thinking.queue_description(updates),
         }
@@ -105,9 +187,15 @@ impl Tool {
         match self {
             Tool::FsRead(fs_read) => fs_read.validate(ctx).await,
             Tool::FsWrite(fs_write) => fs_write.validate(ctx).await,
+            Tool::GrepSearch(grep_search) => grep_search.validate(ctx).await,
+            Tool::GlobFiles(glob_files) => glob_files.validate(ctx).await,
             Tool::ExecuteBash(execute_bash) => execute_bash.validate(ctx).await,
+            Tool::ProcessOutput(process_output) => process_output.validate(ctx).await,
             Tool::UseAws(use_aws) => use_aws.validate(ctx).await,
+            Tool::HttpRequest(http_request) => http_request.validate(ctx).await,
+            Tool::GithubIssue(github_issue) => github_issue.validate(ctx).await,
             Tool::Custom(custom_tool) => custom_tool.validate(ctx).await,
+            Tool::Command(command_tool) => command_tool.validate(ctx).await,
             Tool::GhIssue(gh_issue) => gh_issue.validate(ctx).await,
             Tool::Thinking(think) => think.validate(ctx).await,
         }
@@ -127,6 +215,15 @@ pub struct ToolPermissions {
     // We need this field for any stragglers
     pub trust_all: bool,
     pub permissions: HashMap<String, ToolPermission>,
+    /// The tools never run, even with [Self::trust_all], the model being told they were denied.
+    pub denied: HashSet<String>,
+    /// The files `fs_write` can change without confirmation for the rest of the session.
+    pub trusted_files: HashSet<PathBuf>,
+    /// The patterns, e.g. `fs_write(src/**)`, trusted for the rest of the session when approving
+    /// similar tool uses at once.
+    pub trusted_patterns: Vec<ToolPattern>,
+    /// The `allowedTools` and `deniedTools` of `mcp.json`, kept by [Self::reset].
+    pub rules: ToolRules,
 }
 
 impl ToolPermissions {
@@ -134,6 +231,10 @@ impl ToolPermissions {
         Self {
             trust_all: false,
             permissions: HashMap::with_capacity(capacity),
+            denied: HashSet::new(),
+            trusted_files: HashSet::new(),
+            trusted_patterns: Vec::new(),
+            rules: ToolRules::default(),
         }
     }
 
@@ -145,7 +246,12 @@ impl ToolPermissions {
         let mut permissions = Self::new(trusted_tools.len());
         permissions.trust_all = trust_all;
 
-        trusted_tools.iter().for_each(|tool| permissions.trust_tool(&tool));
+        trusted_tools.iter().for_each(|tool| permissions.trust_tool(tool));
+        permissions.denied = database
+            .settings
+            .get_string_array(Setting::DeniedTools)
+            .into_iter()
+            .collect();
 
         permissions
     }
@@ -174,46 +280,138 @@ impl ToolPermissions {
         self
     }
 
+    pub fn with_rules(mut self, rules: ToolRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
     pub fn is_trusted(&self, tool_name: &str) -> bool {
-        self.trust_all || self.permissions.get(tool_name).is_some_and(|perm| perm.trusted)
+        !self.is_denied(tool_name)
+            && (self.trust_all || self.permissions.get(tool_name).is_some_and(|perm| perm.trusted))
+    }
+
+    pub fn is_denied(&self, tool_name: &str) -> bool {
+        self.denied.contains(tool_name)
+    }
+
+    /// Whether `fs_write` was trusted to change `path` with [Self::trust_file].
+    pub fn is_file_trusted(&self, path: impl AsRef<Path>) -> bool {
+        !self.is_denied("fs_write") && self.trusted_files.contains(path.as_ref())
+    }
+
+    pub fn trust_file(&mut self, path: impl AsRef<Path>) {
+        self.trusted_files.insert(path.as_ref().to_path_buf());
+    }
+
+    /// Whether a pattern trusted with [Self::trust_pattern] lets the use of `tool` run.
+    pub fn is_pattern_trusted(&self, ctx: &Context, tool_name: &str, tool: &Tool) -> bool {
+        !self.is_denied(tool_name)
+            && self
+                .trusted_patterns
+                .iter()
+                .any(|pattern| pattern.allows_use(ctx, tool_name, tool))
+    }
+
+    pub fn trust_pattern(&mut self, pattern: ToolPattern) {
+        self.trusted_patterns.push(pattern);
     }
 
     /// Returns a label to describe the permission status for a given tool.
     pub fn display_label(&self, tool_name: &str) -> String {
-        if self.has(tool_name) || self.trust_all {
+        if self.is_denied(tool_name) {
+            format!("  {}", "denied".red().bold())
+        } else if self.rules.denies_tool(tool_name) {
+            format!("  {}", "denied by mcp.json".red().bold())
+        } else if self.has(tool_name) || self.trust_all {
             if self.is_trusted(tool_name) {
                 format!("  {}", "trusted".dark_green().bold())
             } else {
                 format!("  {}", "not trusted".dark_grey())
             }
+        } else if tool_name == "fs_write" && !self.trusted_files.is_empty() {
+            let files = match self.trusted_files.len() {
+                1 => "1 file".to_string(),
+                n => format!("{n} files"),
+            };
+            format!("  {}", format!("trusted for {files}").dark_green())
+        } else if self.trusted_patterns.iter().any(|pattern| pattern.is_for(tool_name)) {
+            let patterns = self
+                .trusted_patterns
+                .iter()
+                .filter(|pattern| pattern.is_for(tool_name))
+                .map(|pattern| pattern.pattern.as_str())
+                .collect::<Vec<_>>();
+            format!("  {}", format!("trusted for {}", patterns.join(", ")).dark_green())
+        } else if self.rules.allows_tool(tool_name) {
+            format!("  {}", "trusted by mcp.json".dark_green().bold())
         } else {
             self.default_permission_label(tool_name)
         }
     }
 
     pub fn trust_tool(&mut self, tool_name: &str) {
+        self.denied.remove(tool_name);
         self.permissions
             .insert(tool_name.to_string(), ToolPermission { trusted: true });
     }
 
     pub fn untrust_tool(&mut self, tool_name: &str) {
         self.trust_all = false;
+        self.denied.remove(tool_name);
         self.permissions
             .insert(tool_name.to_string(), ToolPermission { trusted: false });
     }
 
+    pub fn deny_tool(&mut self, tool_name: &str) {
+        self.permissions.remove(tool_name);
+        self.denied.insert(tool_name.to_string());
+    }
+
     pub fn reset(&mut self) {
         self.trust_all = false;
         self.permissions.clear();
+        self.denied.clear();
+        self.trusted_files.clear();
+        self.trusted_patterns.clear();
     }
 
     pub fn reset_tool(&mut self, tool_name: &str) {
         self.trust_all = false;
         self.permissions.remove(tool_name);
+        self.denied.remove(tool_name);
+        if tool_name == "fs_write" {
+            self.trusted_files.clear();
+        }
+        self.trusted_patterns.retain(|pattern| !pattern.is_for(tool_name));
     }
 
     pub fn has(&self, tool_name: &str) -> bool {
-        self.permissions.contains_key(tool_name)
+        self.permissions.contains_key(tool_name) || self.denied.contains(tool_name)
+    }
+
+    /// Trusts `tool_name` in this session and the next ones, saving it to `tools.trusted`.
+    pub async fn always_allow(&mut self, settings: &mut Settings, tool_name: &str) -> Result<(), DatabaseError> {
+        self.trust_tool(tool_name);
+        save_decision(settings, tool_name, Some(Setting::TrustedTools)).await
+    }
+
+    /// Denies `tool_name` in this session and the next ones, saving it to `tools.denied`.
+    pub async fn always_deny(&mut self, settings: &mut Settings, tool_name: &str) -> Result<(), DatabaseError> {
+        self.deny_tool(tool_name);
+        save_decision(settings, tool_name, Some(Setting::DeniedTools)).await
+    }
+
+    /// Forgets the decisions saved by [Self::always_allow] and [Self::always_deny] for
+    /// `tool_name`, or for all the tools.
+    pub async fn forget_saved(settings: &mut Settings, tool_name: Option<&str>) -> Result<(), DatabaseError> {
+        match tool_name {
+            Some(tool_name) => save_decision(settings, tool_name, None).await,
+            None => {
+                settings.remove(Setting::TrustedTools).await?;
+                settings.remove(Setting::DeniedTools).await?;
+                Ok(())
+            },
+        }
     }
 
     /// Provide default permission labels for the built-in set of tools.
@@ -222,6 +420,7 @@ impl ToolPermissions {
         let label = match tool_name {
             "fs_read" => "trusted".dark_green().bold(),
             "fs_write" => "not trusted".dark_grey(),
+            "grep_search" | "glob_files" | "process_output" => "trusted".dark_green().bold(),
             "execute_bash" => "trust read-only commands".dark_grey(),
             "use_aws" => "trust read-only commands".dark_grey(),
             "report_issue" => "trusted".dark_green().bold(),
@@ -234,6 +433,27 @@ impl ToolPermissions {
     }
 }
 
+/// Saves `tool_name` to the list of `decision`, `tools.trusted` or `tools.denied`, and removes it
+/// from the other one.
+async fn save_decision(
+    settings: &mut Settings,
+    tool_name: &str,
+    decision: Option<Setting>,
+) -> Result<(), DatabaseError> {
+    for setting in [Setting::TrustedTools, Setting::DeniedTools] {
+        let mut tools = settings.get_string_array(setting);
+        let had_tool = tools.iter().any(|tool| tool == tool_name);
+        tools.retain(|tool| tool != tool_name);
+        if decision == Some(setting) {
+            tools.push(tool_name.to_string());
+        } else if !had_tool {
+            continue;
+        }
+        settings.set(setting, serde_json::json!(tools)).await?;
+    }
+    Ok(())
+}
+
 /// A tool specification to be sent to the model as part of a conversation. Maps to
 /// [BedrockToolSpecification].
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -244,12 +464,29 @@ pub struct ToolSpec {
     pub input_schema: InputSchema,
     #[serde(skip_serializing, default = "tool_origin")]
     pub tool_origin: ToolOrigin,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+/// What an MCP server tells of what a tool does, see
+/// <https://modelcontextprotocol.io/specification/2025-03-26/server/tools#tool>.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    /// Whether the tool doesn't change anything, `false` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+    /// Whether the changes of a tool that isn't read-only can destroy something, `true` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destructive_hint: Option<bool>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub enum ToolOrigin {
     Native,
     McpServer(String),
+    /// Defined in the `tools` of `mcp.json`, see [CommandTool].
+    UserDefined,
 }
 
 impl<'de> Deserialize<'de> for ToolOrigin {
@@ -260,6 +497,8 @@ impl<'de> Deserialize<'de> for ToolOrigin {
         let s = String::deserialize(deserializer)?;
         if s == "native___" {
             Ok(ToolOrigin::Native)
+        } else if s == "user___" {
+            Ok(ToolOrigin::UserDefined)
         } else {
             Ok(ToolOrigin::McpServer(s))
         }
@@ -274,6 +513,7 @@ impl Serialize for ToolOrigin {
         match self {
             ToolOrigin::Native => serializer.serialize_str("native___"),
             ToolOrigin::McpServer(server) => serializer.serialize_str(server),
+            ToolOrigin::UserDefined => serializer.serialize_str("user___"),
         }
     }
 }
@@ -283,6 +523,7 @@ impl std::fmt::Display for ToolOrigin {
         match self {
             ToolOrigin::Native => write!(f, "Built-in"),
             ToolOrigin::McpServer(server) => write!(f, "{} (MCP)", server),
+            ToolOrigin::UserDefined => write!(f, "User-defined"),
         }
     }
 }
@@ -296,7 +537,21 @@ pub struct QueuedTool {
     pub id: String,
     pub name: String,
     pub accepted: bool,
+    /// Whether the user accepted the tool when asked, rather than it being trusted.
+    pub approved: bool,
     pub tool: Tool,
+    /// The arguments as the model gave them, told to the tool use hooks.
+    pub input: serde_json::Value,
+}
+
+/// Similar uses of a tool asked for at once, the user approving them with a single answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApprovalBatch {
+    /// The indices of the uses in the queue, the first being the one pending approval.
+    pub indices: Vec<usize>,
+    /// The pattern covering all of them, e.g. `fs_write(src/**)`, which the user can trust for the
+    /// session.
+    pub pattern: Option<String>,
 }
 
 /// The schema specification describing a tool's fields.
@@ -333,6 +588,48 @@ impl Default for OutputKind {
     }
 }
 
+/// How long tools can run before they are stopped, from `tools.timeoutMs` for every tool and
+/// `tools.timeouts` for some, an object of tool names to milliseconds. A timeout of 0 means none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolTimeouts {
+    default: Option<Duration>,
+    tools: HashMap<String, Option<Duration>>,
+}
+
+impl ToolTimeouts {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let timeout = |ms: u64| (ms > 0).then(|| Duration::from_millis(ms));
+        let default = settings
+            .get(Setting::ToolsTimeoutMs)
+            .and_then(|ms| ms.as_u64())
+            .and_then(timeout);
+        let tools = settings
+            .get(Setting::ToolsTimeouts)
+            .and_then(|tools| tools.as_object())
+            .map(|tools| {
+                tools
+                    .iter()
+                    .filter_map(|(name, ms)| Some((name.clone(), timeout(ms.as_u64()?))))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { default, tools }
+    }
+
+    /// The timeout of the tool named `tool_name`, `None` when it can run for as long as it takes.
+    pub fn get(&self, tool_name: &str) -> Option<Duration> {
+        match self.tools.get(tool_name) {
+            Some(timeout) => *timeout,
+            None => self.default,
+        }
+    }
+}
+
+/// The error of a tool stopped for running longer than its [ToolTimeouts].
+#[derive(Debug, thiserror::Error)]
+#[error("Timed out after {}s", .0.as_secs_f64())]
+pub struct ToolTimedOut(pub Duration);
+
 /// Performs tilde expansion and other required sanitization modifications for handling tool use
 /// path arguments.
 ///
@@ -387,7 +684,7 @@ fn absolute_to_relative(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> Result
 }
 
 /// Small helper for formatting the path as a relative path, if able.
-fn format_path(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> String {
+pub fn format_path(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> String {
     absolute_to_relative(cwd, path.as_ref())
         .map(|p| p.to_string_lossy().to_string())
         // If we have three consecutive ".." then it should probably just stay as an absolute path.
@@ -409,6 +706,14 @@ fn supports_truecolor(ctx: &Context) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use custom_tool::{
+        CustomToolClient,
+        CustomToolConfig,
+        default_timeout,
+    };
+
     use super::*;
     use crate::database::{Database, settings::Setting};
     use crate::platform::EnvProvider;
@@ -494,6 +799,40 @@ mod tests {
         assert!(permissions.is_trusted("my_mcp_tool"));
     }
 
+    #[test]
+    fn test_tool_permissions_trusted_files() {
+        let mut permissions = ToolPermissions::new(0);
+        permissions.trust_file("/project/src/main.rs");
+        assert!(permissions.is_file_trusted("/project/src/main.rs"));
+        assert!(!permissions.is_file_trusted("/project/src/lib.rs"));
+        assert!(!permissions.is_trusted("fs_write"));
+
+        permissions.deny_tool("fs_write");
+        assert!(!permissions.is_file_trusted("/project/src/main.rs"));
+
+        permissions.reset_tool("fs_write");
+        assert!(!permissions.is_file_trusted("/project/src/main.rs"));
+    }
+
+    #[test]
+    fn test_tool_permissions_trusted_patterns() {
+        let ctx = Context::new();
+        let bash = |command: &str| {
+            Tool::ExecuteBash(serde_json::from_value(serde_json::json!({ "command": command })).unwrap())
+        };
+        let mut permissions = ToolPermissions::new(0);
+        permissions.trust_pattern(ToolPattern::parse("execute_bash(cargo *)").unwrap());
+        assert!(permissions.is_pattern_trusted(&ctx, "execute_bash", &bash("cargo build")));
+        assert!(!permissions.is_pattern_trusted(&ctx, "execute_bash", &bash("rm -rf target")));
+        assert!(!permissions.is_trusted("execute_bash"));
+
+        permissions.deny_tool("execute_bash");
+        assert!(!permissions.is_pattern_trusted(&ctx, "execute_bash", &bash("cargo build")));
+
+        permissions.reset_tool("execute_bash");
+        assert!(!permissions.is_pattern_trusted(&ctx, "execute_bash", &bash("cargo build")));
+    }
+
     #[tokio::test]
     async fn test_tool_permissions_cli_overrides() {
         let mut database = Database::new().await.unwrap();
@@ -528,4 +867,107 @@ mod tests {
         assert!(permissions.is_trusted("fs_write"));
         assert!(!permissions.is_trusted("execute_bash"));
     }
+
+    #[tokio::test]
+    async fn test_tool_permissions_saved_decisions() {
+        let mut database = Database::new().await.unwrap();
+        let mut permissions = ToolPermissions::from_database(&database);
+
+        permissions
+            .always_allow(&mut database.settings, "fs_write")
+            .await
+            .unwrap();
+        permissions
+            .always_deny(&mut database.settings, "execute_bash")
+            .await
+            .unwrap();
+        permissions.trust_all = true;
+        assert!(permissions.is_trusted("fs_write"));
+        assert!(permissions.is_denied("execute_bash"));
+        assert!(!permissions.is_trusted("execute_bash"));
+
+        let permissions = ToolPermissions::from_database(&database);
+        assert!(permissions.is_trusted("fs_write"));
+        assert!(permissions.is_denied("execute_bash"));
+
+        // Allowing a denied tool takes it off the denied ones
+        let mut permissions = permissions;
+        permissions
+            .always_allow(&mut database.settings, "execute_bash")
+            .await
+            .unwrap();
+        assert_eq!(database.settings.get_string_array(Setting::TrustedTools), vec![
+            "fs_write".to_string(),
+            "execute_bash".to_string()
+        ]);
+        assert!(database.settings.get_string_array(Setting::DeniedTools).is_empty());
+
+        ToolPermissions::forget_saved(&mut database.settings, Some("fs_write"))
+            .await
+            .unwrap();
+        assert_eq!(database.settings.get_string_array(Setting::TrustedTools), vec![
+            "execute_bash".to_string()
+        ]);
+        ToolPermissions::forget_saved(&mut database.settings, None)
+            .await
+            .unwrap();
+        assert!(database.settings.get_string_array(Setting::TrustedTools).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tool_timeouts() {
+        let mut settings = Settings::new().await.unwrap();
+        assert_eq!(ToolTimeouts::from_settings(&settings).get("execute_bash"), None);
+
+        settings.set(Setting::ToolsTimeoutMs, 120_000).await.unwrap();
+        settings
+            .set(
+                Setting::ToolsTimeouts,
+                serde_json::json!({ "execute_bash": 600_000, "fs_read": 0 }),
+            )
+            .await
+            .unwrap();
+        let timeouts = ToolTimeouts::from_settings(&settings);
+        assert_eq!(timeouts.get("execute_bash"), Some(Duration::from_secs(600)));
+        assert_eq!(timeouts.get("use_aws"), Some(Duration::from_secs(120)));
+        assert_eq!(timeouts.get("fs_read"), None);
+    }
+
+    #[tokio::test]
+    async fn test_mutates() {
+        let execute_bash = |command: &str| {
+            Tool::ExecuteBash(serde_json::from_value(serde_json::json!({ "command": command })).unwrap())
+        };
+        assert!(!execute_bash("ls -la | grep .git").mutates(None));
+        assert!(execute_bash("rm -rf target").mutates(None));
+
+        let spec = serde_json::from_value::<ToolSpec>(serde_json::json!({
+            "name": "get_issue",
+            "description": "Gets an issue",
+            "inputSchema": { "type": "object" },
+            "annotations": { "readOnlyHint": true }
+        }))
+        .unwrap();
+        assert_eq!(spec.annotations.as_ref().unwrap().read_only_hint, Some(true));
+        let client = CustomToolClient::from_config("github".to_string(), CustomToolConfig {
+            command: "true".to_string(),
+            args: Vec::new(),
+            env: None,
+            timeout: default_timeout(),
+        })
+        .unwrap();
+        let get_issue = Tool::Custom(CustomTool {
+            name: "get_issue".to_string(),
+            client: Arc::new(client),
+            method: "tools/call".to_string(),
+            params: None,
+        });
+        assert!(!get_issue.mutates(Some(&spec)));
+        let spec = ToolSpec {
+            annotations: None,
+            ..spec
+        };
+        assert!(get_issue.mutates(Some(&spec)));
+        assert!(get_issue.mutates(None));
+    }
 }
@@ -0,0 +1,143 @@
+//! The `process_output` tool, reading the recent output of a command `execute_bash` started in the
+//! background and whether it's still running, e.g. to check that a dev server came up or what a
+//! build in watch mode printed after a change.
+
+use std::io::Write;
+
+use crossterm::queue;
+use crossterm::style::{
+    self,
+    Color,
+};
+use eyre::{
+    Result,
+    bail,
+};
+use serde::Deserialize;
+
+use super::{
+    InvokeOutput,
+    OutputKind,
+};
+use crate::cli::chat::background::{
+    BackgroundProcesses,
+    format_elapsed,
+};
+use crate::platform::Context;
+
+/// How many lines are returned when the model doesn't say.
+const DEFAULT_LINES: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProcessOutput {
+    /// The id `execute_bash` returned, as listed by `/ps`.
+    pub process_id: usize,
+    /// How many of the last lines to return.
+    pub lines: Option<usize>,
+    /// The background commands of the chat.
+    #[serde(skip)]
+    pub processes: BackgroundProcesses,
+}
+
+impl ProcessOutput {
+    pub async fn validate(&mut self, _ctx: &Context) -> Result<()> {
+        if self.processes.get(self.process_id).is_none() {
+            let ids = self
+                .processes
+                .list()
+                .iter()
+                .map(|process| process.id.to_string())
+                .collect::<Vec<_>>();
+            match ids.is_empty() {
+                true => bail!("There's no background process {}, none was started", self.process_id),
+                false => bail!(
+                    "There's no background process {}, the processes are {}",
+                    self.process_id,
+                    ids.join(", ")
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("Reading the output of background process "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(self.process_id),
+            style::ResetColor,
+        )?;
+        if let Some(process) = self.processes.get(self.process_id) {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(" ({})", process.command)),
+                style::ResetColor,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub async fn invoke(&self, updates: &mut impl Write) -> Result<InvokeOutput> {
+        let (Some(process), Some(output)) = (
+            self.processes.get(self.process_id),
+            self.processes
+                .output(self.process_id, self.lines.unwrap_or(DEFAULT_LINES)),
+        ) else {
+            bail!("There's no background process {}", self.process_id);
+        };
+        queue!(
+            updates,
+            style::Print(format!("{} lines, the process is {}\n", output.len(), process.status))
+        )?;
+        Ok(InvokeOutput {
+            output: OutputKind::Json(serde_json::json!({
+                "command": process.command,
+                "status": process.status.to_string(),
+                "started": format!("{} ago", format_elapsed(process.started.elapsed())),
+                "output": output.join("\n"),
+            })),
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::cli::chat::tools::environment::ToolEnv;
+
+    #[tokio::test]
+    async fn test_invoke() {
+        let ctx = Context::new();
+        let processes = BackgroundProcesses::default();
+        let id = processes
+            .spawn("echo ready; sleep 60", None, &ToolEnv::default())
+            .unwrap();
+        let mut process_output =
+            serde_json::from_value::<ProcessOutput>(serde_json::json!({ "process_id": id })).unwrap();
+        process_output.processes = processes.clone();
+        process_output.validate(&ctx).await.unwrap();
+
+        let mut json = serde_json::Value::Null;
+        for _ in 0..100 {
+            let output = process_output.invoke(&mut std::io::sink()).await.unwrap();
+            let OutputKind::Json(output) = output.output else {
+                panic!("expected JSON output");
+            };
+            json = output;
+            if json["output"] == "ready" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(json["output"], "ready", "{json}");
+        assert_eq!(json["status"], "running");
+        processes.kill(id);
+
+        process_output.process_id = id + 1;
+        assert!(process_output.validate(&ctx).await.is_err());
+    }
+}
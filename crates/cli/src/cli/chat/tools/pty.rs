@@ -0,0 +1,347 @@
+//! Runs the commands of `execute_bash` under a pseudo-terminal, for those needing one: password
+//! prompts, `ssh`, installers with an interface of their own. Their output is shown as it comes
+//! and what the user types goes to them while they run, Ctrl+] opening a menu to leave the
+//! command running in the background or kill it.
+
+use std::fs::File;
+use std::io::{
+    Read,
+    Write,
+};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use eyre::{
+    Context as EyreContext,
+    Result,
+};
+use nix::pty::{
+    Winsize,
+    openpty,
+};
+use nix::sys::termios::{
+    SetArg,
+    Termios,
+    cfmakeraw,
+    tcgetattr,
+    tcsetattr,
+};
+use tokio::select;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use super::environment::ToolEnv;
+use super::execute_bash::ProcessGroupGuard;
+
+/// Ctrl+], the key opening the menu, as `telnet` has it.
+const MENU_KEY: u8 = 0x1d;
+
+/// How the command run under the pseudo-terminal ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PtyExit {
+    /// With its exit status, `None` when terminated by a signal.
+    Exited(Option<i32>),
+    /// Killed by the user from the menu.
+    Killed,
+    /// Left running by the user, its output from then on going to `log`.
+    Background { pid: u32, log: PathBuf },
+}
+
+pub struct PtyResult {
+    pub exit: PtyExit,
+    /// The end of what the command wrote, without escape sequences.
+    pub output: String,
+}
+
+/// Runs `command_line` with `env` under a pseudo-terminal the size of the terminal, writing its
+/// output to `updates` as it comes and keeping at most `max_result_size` bytes of its end.
+pub async fn run(
+    command_line: &[String],
+    env: &ToolEnv,
+    max_result_size: usize,
+    mut updates: impl Write,
+) -> Result<PtyResult> {
+    let (columns, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+    let winsize = Winsize {
+        ws_row: rows,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let pty = openpty(Some(&winsize), None).wrap_err("Unable to open a pseudo-terminal")?;
+    // Not inherited by the command, which would keep the pseudo-terminal open after it exits
+    unsafe { libc::fcntl(pty.master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    let mut master = File::from(pty.master);
+
+    let mut cmd = tokio::process::Command::new(&command_line[0]);
+    cmd.args(&command_line[1..])
+        .stdin(Stdio::from(pty.slave.try_clone()?))
+        .stdout(Stdio::from(pty.slave.try_clone()?))
+        .stderr(Stdio::from(pty.slave));
+    env.apply(&mut cmd);
+    // In a session of its own with the pseudo-terminal as its controlling terminal, for `sudo`
+    // and `ssh` to prompt on it. Also makes it the leader of a process group of its own.
+    unsafe {
+        cmd.pre_exec(|| {
+            nix::unistd::setsid()?;
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd
+        .spawn()
+        .wrap_err_with(|| format!("Unable to spawn command '{}'", command_line.join(" ")))?;
+    // Closes the slave side in this process, for reads of the master to end once the command exits
+    drop(cmd);
+    let pid = child.id().unwrap_or_default();
+    let mut process_group = ProcessGroupGuard(child.id());
+
+    let (output_sender, mut output_receiver) = mpsc::unbounded_channel();
+    std::thread::spawn({
+        let mut master = master.try_clone()?;
+        move || {
+            let mut buf = [0; 4096];
+            // Fails with EIO once the command and what it started exit
+            while let Ok(n @ 1..) = master.read(&mut buf) {
+                if output_sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let (key_sender, mut key_receiver) = mpsc::unbounded_channel();
+    let mut keys = RawInput::start(key_sender);
+    let mut output = Vec::new();
+    let mut in_menu = false;
+    let exit = loop {
+        select! {
+            chunk = output_receiver.recv() => match chunk {
+                Some(chunk) => {
+                    updates.write_all(&chunk)?;
+                    updates.flush()?;
+                    keep_end(&mut output, &chunk, max_result_size * 2);
+                },
+                None => break PtyExit::Exited(child.wait().await?.code()),
+            },
+            typed = key_receiver.recv(), if keys.is_some() => {
+                let Some(typed) = typed else {
+                    keys = None;
+                    continue;
+                };
+                if in_menu {
+                    in_menu = false;
+                    match typed.first().copied() {
+                        Some(b'b' | b'B') => break PtyExit::Background {
+                            pid,
+                            log: std::env::temp_dir().join(format!("q-command-{pid}.log")),
+                        },
+                        Some(b'k' | b'K') => break PtyExit::Killed,
+                        _ => write!(updates, "\r\nContinuing, Ctrl+] for the menu\r\n")?,
+                    }
+                    updates.flush()?;
+                    continue;
+                }
+                let (before, menu) = match typed.iter().position(|&key| key == MENU_KEY) {
+                    Some(at) => (&typed[..at], true),
+                    None => (&typed[..], false),
+                };
+                if let Err(err) = master.write_all(before) {
+                    error!(%err, "Failed to send the keys to the command");
+                }
+                if menu {
+                    in_menu = true;
+                    write!(
+                        updates,
+                        "\r\n[b] keep running in the background  [k] kill  [any other key] continue\r\n"
+                    )?;
+                    updates.flush()?;
+                }
+            },
+            status = child.wait() => {
+                let status = status?;
+                // What the command wrote just before exiting may not be read yet
+                let _ = tokio::time::timeout(Duration::from_millis(100), async {
+                    while let Some(chunk) = output_receiver.recv().await {
+                        let _ = updates.write_all(&chunk);
+                        keep_end(&mut output, &chunk, max_result_size * 2);
+                    }
+                })
+                .await;
+                break PtyExit::Exited(status.code());
+            },
+        }
+    };
+    drop(keys);
+
+    match &exit {
+        PtyExit::Exited(_) => process_group.0 = None,
+        PtyExit::Killed => {
+            drop(process_group);
+            let _ = child.wait().await;
+        },
+        PtyExit::Background { log, .. } => {
+            process_group.0 = None;
+            let mut log = File::create(log).wrap_err("Unable to create the log of the command")?;
+            let _ = log.write_all(&output);
+            // Kept reading for the command not to block on writing, until it exits or the chat does
+            tokio::spawn(async move {
+                while let Some(chunk) = output_receiver.recv().await {
+                    let _ = log.write_all(&chunk);
+                }
+                let _ = child.wait().await;
+            });
+        },
+    }
+    updates.flush()?;
+
+    Ok(PtyResult {
+        exit,
+        output: clean_output(&output, max_result_size),
+    })
+}
+
+/// Appends `chunk` to `output`, dropping its start past `max` bytes.
+fn keep_end(output: &mut Vec<u8>, chunk: &[u8], max: usize) {
+    output.extend_from_slice(chunk);
+    if output.len() > max {
+        output.drain(..output.len() - max);
+    }
+}
+
+/// The text of `output` as it was left on the terminal, without escape sequences, lines redrawn
+/// with `\r` (e.g. progress bars) only keeping their last state. At most the last `max` bytes.
+fn clean_output(output: &[u8], max: usize) -> String {
+    let text = String::from_utf8_lossy(output);
+    let lines = text
+        .split('\n')
+        .map(|line| {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            line.rsplit('\r').next().unwrap_or(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = strip_ansi_escapes::strip_str(lines);
+    let text = text.trim_end();
+    if text.len() <= max {
+        return text.to_string();
+    }
+    let mut start = text.len() - max;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("truncated ... {}", &text[start..])
+}
+
+/// Reads the keys typed by the user with the terminal in raw mode, each key going to the command
+/// as is, Ctrl+C included. Restores the terminal when dropped.
+struct RawInput {
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+    original: Termios,
+}
+
+impl RawInput {
+    /// Starts reading, or returns `None` if stdin isn't a terminal.
+    fn start(sender: mpsc::UnboundedSender<Vec<u8>>) -> Option<Self> {
+        let stdin = std::io::stdin();
+        let original = tcgetattr(&stdin).ok()?;
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(&stdin, SetArg::TCSANOW, &raw).ok()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = std::thread::spawn({
+            let stop = Arc::clone(&stop);
+            move || read_until_stopped(&sender, &stop)
+        });
+        Some(Self {
+            stop,
+            reader: Some(reader),
+            original,
+        })
+    }
+}
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        let _ = tcsetattr(std::io::stdin(), SetArg::TCSANOW, &self.original);
+    }
+}
+
+fn read_until_stopped(sender: &mpsc::UnboundedSender<Vec<u8>>, stop: &AtomicBool) {
+    let fd = std::io::stdin().as_raw_fd();
+    let mut buf = [0; 1024];
+    while !stop.load(Ordering::Relaxed) {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Wake up regularly to notice when reading stops
+        let ready = unsafe { libc::poll(&mut pollfd, 1, 50) };
+        if ready <= 0 {
+            continue;
+        }
+        match nix::unistd::read(fd, &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_output() {
+        assert_eq!(clean_output(b"\x1b[32mok\x1b[0m\r\ndone\r\n", 100), "ok\ndone");
+        // Only the last state of a progress bar
+        assert_eq!(
+            clean_output(b"[#   ] 25%\r[##  ] 50%\r[####] 100%\r\n", 100),
+            "[####] 100%"
+        );
+        assert_eq!(
+            clean_output("line 1\r\nline 2 é\r\n".as_bytes(), 9),
+            "truncated ... line 2 é"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_run() {
+        let command_line = ["bash", "-c", "test -t 0 && test -t 1 && echo \"in a terminal\"; exit 3"].map(String::from);
+        let result = run(&command_line, &ToolEnv::default(), 1000, std::io::sink())
+            .await
+            .unwrap();
+        assert_eq!(result.exit, PtyExit::Exited(Some(3)));
+        assert_eq!(result.output, "in a terminal");
+    }
+
+    #[test]
+    fn test_keep_end() {
+        let mut output = Vec::new();
+        keep_end(&mut output, b"abcdef", 4);
+        keep_end(&mut output, b"gh", 4);
+        assert_eq!(output, b"efgh");
+    }
+}
@@ -0,0 +1,460 @@
+//! The `allowedTools` and `deniedTools` of `mcp.json`, patterns of the tool uses run without asking
+//! and of those never run, checked before the user would be asked:
+//!
+//! ```json
+//! {
+//!   "allowedTools": ["fs_read", "@github/get_*", "execute_bash(git status)", "execute_bash(git log*)"],
+//!   "deniedTools": ["@github/delete_*", "execute_bash(rm *)", "fs_write(/etc/*)"]
+//! }
+//! ```
+//!
+//! A pattern is a glob on the name of a tool, `@server/` standing for the tools of an MCP server,
+//! optionally followed by a glob on its argument in parentheses:
+//! - the command of `execute_bash` and of the tools defined in `mcp.json`. A denied pattern matches
+//!   any of the commands chained in it, while an allowed one only applies to a command chaining
+//!   none, so that `git *` doesn't let `git status && rm -rf ~` through.
+//! - the path of `fs_write`, `grep_search` and `glob_files` and the paths of `fs_read`, absolute or
+//!   relative to the current directory.
+//! - the `service operation` of `use_aws`, as in `use_aws(s3 list*)`.
+//! - the repository of `create_github_issue`, as in `create_github_issue(acme/*)`.
+//!
+//! Denied patterns win over allowed ones and over the tools trusted with `/tools`. Only the
+//! `allowedTools` of the global config apply, those of a workspace's `.amazonq/mcp.json` being
+//! ignored, while the `deniedTools` of both do.
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use convert_case::{
+    Case,
+    Casing,
+};
+use eyre::{
+    Result,
+    WrapErr,
+    bail,
+};
+use globset::{
+    Glob,
+    GlobMatcher,
+};
+
+use super::Tool;
+use super::fs_read::FsRead;
+use super::sandbox::normalize;
+use crate::platform::Context;
+
+#[derive(Debug, Clone)]
+pub struct ToolPattern {
+    /// The pattern as written in `mcp.json`.
+    pub pattern: String,
+    name: GlobMatcher,
+    argument: Option<GlobMatcher>,
+}
+
+impl ToolPattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim();
+        let (name, argument) = match pattern.split_once('(') {
+            Some((name, argument)) => match argument.strip_suffix(')') {
+                Some(argument) => (name.trim(), Some(argument.trim())),
+                None => bail!("'{pattern}' is missing the ')' closing its argument"),
+            },
+            None => (pattern, None),
+        };
+        // The tools of a server are named `<server>___<tool>`, with the server in snake case
+        let name = match name.strip_prefix('@') {
+            Some(server_tool) => {
+                let (server, tool) = server_tool.split_once('/').unwrap_or((server_tool, "*"));
+                format!("{}___{tool}", server.to_case(Case::Snake))
+            },
+            None => name.to_string(),
+        };
+        let glob = |glob: &str| -> Result<GlobMatcher> {
+            Ok(Glob::new(glob)
+                .wrap_err_with(|| format!("'{pattern}' isn't a valid pattern"))?
+                .compile_matcher())
+        };
+        Ok(Self {
+            pattern: pattern.to_string(),
+            name: glob(&name)?,
+            argument: argument.map(glob).transpose()?,
+        })
+    }
+
+    /// Whether the pattern matches the use of `tool`, named `tool_name`, as a denied one would.
+    pub fn matches(&self, ctx: &Context, tool_name: &str, tool: &Tool) -> bool {
+        self.denies(tool_name, &Arguments::of(ctx, tool))
+    }
+
+    /// Whether the pattern lets the use of `tool`, named `tool_name`, run as an allowed one would.
+    pub fn allows_use(&self, ctx: &Context, tool_name: &str, tool: &Tool) -> bool {
+        self.allows(tool_name, &Arguments::of(ctx, tool))
+    }
+
+    /// Whether the name of the pattern matches `tool_name`, whatever its argument.
+    pub fn is_for(&self, tool_name: &str) -> bool {
+        self.name.is_match(tool_name)
+    }
+
+    /// Whether the pattern matches all the uses of `tool_name`, having no argument.
+    fn matches_tool(&self, tool_name: &str) -> bool {
+        self.argument.is_none() && self.name.is_match(tool_name)
+    }
+
+    /// Whether the pattern denies the use of `tool_name` with `arguments`.
+    fn denies(&self, tool_name: &str, arguments: &Arguments) -> bool {
+        if !self.name.is_match(tool_name) {
+            return false;
+        }
+        let Some(argument) = &self.argument else {
+            return true;
+        };
+        match arguments {
+            Arguments::None => false,
+            Arguments::Command(command) => {
+                argument.is_match(command.trim()) || chained_commands(command).any(|command| argument.is_match(command))
+            },
+            Arguments::Paths(paths) => paths.iter().flatten().any(|path| argument.is_match(path)),
+            Arguments::Text(text) => argument.is_match(text),
+        }
+    }
+
+    /// Whether the pattern allows the use of `tool_name` with `arguments`.
+    fn allows(&self, tool_name: &str, arguments: &Arguments) -> bool {
+        if !self.name.is_match(tool_name) {
+            return false;
+        }
+        let Some(argument) = &self.argument else {
+            return true;
+        };
+        match arguments {
+            Arguments::None => false,
+            Arguments::Command(command) => !is_chained(command) && argument.is_match(command.trim()),
+            Arguments::Paths(paths) => {
+                !paths.is_empty() && paths.iter().all(|path| path.iter().any(|path| argument.is_match(path)))
+            },
+            Arguments::Text(text) => argument.is_match(text),
+        }
+    }
+}
+
+/// The patterns of `allowedTools` and `deniedTools`.
+#[derive(Debug, Clone, Default)]
+pub struct ToolRules {
+    pub allowed: Vec<ToolPattern>,
+    pub denied: Vec<ToolPattern>,
+}
+
+impl ToolRules {
+    /// Fails on an invalid pattern rather than skipping it, which for `deniedTools` would run what
+    /// the user meant to deny.
+    pub fn new(allowed: &[String], denied: &[String]) -> Result<Self> {
+        let parse = |patterns: &[String], key: &str| -> Result<Vec<ToolPattern>> {
+            patterns
+                .iter()
+                .map(|pattern| ToolPattern::parse(pattern).wrap_err_with(|| format!("Invalid {key} in mcp.json")))
+                .collect()
+        };
+        Ok(Self {
+            allowed: parse(allowed, "allowedTools")?,
+            denied: parse(denied, "deniedTools")?,
+        })
+    }
+
+    /// The pattern of `deniedTools` matching the use of `tool`, named `tool_name`, if any.
+    pub fn denying(&self, ctx: &Context, tool_name: &str, tool: &Tool) -> Option<&ToolPattern> {
+        if self.denied.is_empty() {
+            return None;
+        }
+        let arguments = Arguments::of(ctx, tool);
+        self.denied.iter().find(|pattern| pattern.denies(tool_name, &arguments))
+    }
+
+    /// Whether a pattern of `allowedTools` lets the use of `tool`, named `tool_name`, run without
+    /// asking.
+    pub fn allows(&self, ctx: &Context, tool_name: &str, tool: &Tool) -> bool {
+        if self.allowed.is_empty() {
+            return false;
+        }
+        let arguments = Arguments::of(ctx, tool);
+        self.allowed.iter().any(|pattern| pattern.allows(tool_name, &arguments))
+    }
+
+    /// Whether all the uses of `tool_name` are denied, for `/tools`.
+    pub fn denies_tool(&self, tool_name: &str) -> bool {
+        self.denied.iter().any(|pattern| pattern.matches_tool(tool_name))
+    }
+
+    /// Whether all the uses of `tool_name` are allowed, for `/tools`.
+    pub fn allows_tool(&self, tool_name: &str) -> bool {
+        self.allowed.iter().any(|pattern| pattern.matches_tool(tool_name))
+    }
+}
+
+/// The narrowest pattern allowing all the uses of `tool_name` in `tools`, offered to trust them at
+/// once when the model asks for several: the directory their paths are in, as in
+/// `fs_write(src/**)`, or the words their commands start with, as in `execute_bash(cargo *)`.
+/// `None` when they have nothing in common short of the whole tool.
+pub fn covering_pattern(ctx: &Context, tool_name: &str, tools: &[&Tool]) -> Option<String> {
+    let arguments = tools.iter().map(|tool| Arguments::of(ctx, tool)).collect::<Vec<_>>();
+    let cwd = ctx.env().current_dir().unwrap_or_default();
+    covering_argument(&arguments, &cwd).map(|argument| format!("{tool_name}({argument})"))
+}
+
+fn covering_argument(arguments: &[Arguments], cwd: &Path) -> Option<String> {
+    // Anything that a glob or a pattern would read as more than itself is left alone
+    let is_literal = |text: &str| !text.contains(['*', '?', '[', ']', '{', '}', '\\', '(', ')']);
+    match arguments.first()? {
+        Arguments::Paths(_) => {
+            let mut common: Option<PathBuf> = None;
+            for arguments in arguments {
+                let Arguments::Paths(paths) = arguments else {
+                    return None;
+                };
+                for forms in paths {
+                    let dir = Path::new(forms.first()?).parent()?;
+                    common = Some(match common {
+                        Some(common) => common
+                            .components()
+                            .zip(dir.components())
+                            .take_while(|(a, b)| a == b)
+                            .map(|(a, _)| a)
+                            .collect(),
+                        None => dir.to_path_buf(),
+                    });
+                }
+            }
+            // Not the whole file system
+            let common = common.filter(|common| common.parent().is_some())?;
+            let dir = match common.strip_prefix(cwd) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => &common,
+            };
+            let dir = dir.to_string_lossy();
+            is_literal(&dir).then(|| format!("{}/**", dir.trim_end_matches('/')))
+        },
+        Arguments::Command(_) | Arguments::Text(_) => {
+            let mut texts = Vec::new();
+            for arguments in arguments {
+                match arguments {
+                    Arguments::Command(command) if !is_chained(command) => texts.push(command.as_str()),
+                    Arguments::Text(text) => texts.push(text.as_str()),
+                    _ => return None,
+                }
+            }
+            let mut words = texts.first()?.split_whitespace().collect::<Vec<_>>();
+            for text in &texts[1..] {
+                let common = words
+                    .iter()
+                    .zip(text.split_whitespace())
+                    .take_while(|(a, b)| *a == b)
+                    .count();
+                words.truncate(common);
+            }
+            let prefix = words.join(" ");
+            if prefix.is_empty() || !is_literal(&prefix) {
+                return None;
+            }
+            match texts.iter().all(|text| text.split_whitespace().count() == words.len()) {
+                true => Some(prefix),
+                false => Some(format!("{prefix} *")),
+            }
+        },
+        Arguments::None => None,
+    }
+}
+
+/// What the argument of a pattern is matched against for a tool use.
+#[derive(Debug)]
+enum Arguments {
+    /// The tool has nothing to match, only the patterns without an argument applying to it.
+    None,
+    /// The command run with bash.
+    Command(String),
+    /// The paths read or written, each absolute and, when under the current directory, relative to
+    /// it.
+    Paths(Vec<Vec<String>>),
+    Text(String),
+}
+
+impl Arguments {
+    fn of(ctx: &Context, tool: &Tool) -> Self {
+        let paths = |paths: &[&str]| {
+            let home = ctx.env().home().unwrap_or_default();
+            let cwd = ctx.env().current_dir().unwrap_or_default();
+            Self::paths(paths, &home, &cwd)
+        };
+        match tool {
+            Tool::ExecuteBash(execute_bash) => Self::Command(execute_bash.command.clone()),
+            Tool::Command(command_tool) => Self::Command(command_tool.command()),
+            Tool::FsWrite(fs_write) => paths(&[fs_write.path()]),
+            Tool::FsRead(FsRead::Line(fs_line)) => paths(&[fs_line.path.as_str()]),
+            Tool::FsRead(FsRead::Directory(fs_directory)) => paths(&[fs_directory.path.as_str()]),
+            Tool::FsRead(FsRead::Search(fs_search)) => paths(&[fs_search.path.as_str()]),
+            Tool::GrepSearch(grep_search) => paths(&[grep_search.path()]),
+            Tool::GlobFiles(glob_files) => paths(&[glob_files.path()]),
+            Tool::FsRead(FsRead::Image(fs_image)) => {
+                paths(&fs_image.image_paths.iter().map(String::as_str).collect::<Vec<_>>())
+            },
+            Tool::UseAws(use_aws) => Self::Text(format!("{} {}", use_aws.service_name, use_aws.operation_name)),
+            Tool::HttpRequest(http_request) => Self::Text(format!("{} {}", http_request.method, http_request.url)),
+            Tool::GithubIssue(github_issue) => Self::Text(github_issue.repo().to_string()),
+            Tool::ProcessOutput(_) | Tool::Custom(_) | Tool::GhIssue(_) | Tool::Thinking(_) => Self::None,
+        }
+    }
+
+    /// The paths as matched, resolved without the `..` that could get them past a pattern.
+    fn paths(paths: &[&str], home: &Path, cwd: &Path) -> Self {
+        Self::Paths(
+            paths
+                .iter()
+                .map(|path| {
+                    let path = match path.strip_prefix("~/") {
+                        Some(path) => home.join(path),
+                        None if *path == "~" => home.to_path_buf(),
+                        None => PathBuf::from(path),
+                    };
+                    let path = normalize(&cwd.join(path));
+                    let mut forms = vec![path.to_string_lossy().into_owned()];
+                    if let Ok(relative) = path.strip_prefix(cwd) {
+                        forms.push(relative.to_string_lossy().into_owned());
+                    }
+                    forms
+                })
+                .collect(),
+        )
+    }
+}
+
+const CHAINING: &[char] = &[';', '&', '|', '\n', '(', ')', '`', '<', '>'];
+
+/// Whether `command` runs more than one command or redirects its output.
+fn is_chained(command: &str) -> bool {
+    command.contains(CHAINING)
+}
+
+/// The commands chained in `command`, without the variables assigned before them.
+fn chained_commands(command: &str) -> impl Iterator<Item = &str> {
+    command
+        .split(CHAINING)
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .map(|command| {
+            let mut command = command;
+            while let Some((word, rest)) = command.split_once(char::is_whitespace) {
+                let is_assignment = word.split_once('=').is_some_and(|(name, _)| {
+                    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                });
+                if !is_assignment {
+                    break;
+                }
+                command = rest.trim_start();
+            }
+            command
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(command: &str) -> Arguments {
+        Arguments::Command(command.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        let pattern = ToolPattern::parse("@github/get_*").unwrap();
+        assert!(pattern.matches_tool("github___get_issue"));
+        assert!(!pattern.matches_tool("github___create_issue"));
+        assert!(!pattern.matches_tool("get_issue"));
+
+        let pattern = ToolPattern::parse("@my-server").unwrap();
+        assert!(pattern.matches_tool("my_server___anything"));
+
+        let pattern = ToolPattern::parse(" execute_bash(git *) ").unwrap();
+        assert_eq!(pattern.pattern, "execute_bash(git *)");
+        assert!(!pattern.matches_tool("execute_bash"));
+
+        assert!(ToolPattern::parse("execute_bash(git *").is_err());
+        assert!(ToolPattern::parse("fs_[read").is_err());
+        assert!(ToolRules::new(&[], &["fs_[read".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let git = ToolPattern::parse("execute_bash(git *)").unwrap();
+        assert!(git.allows("execute_bash", &command("git log --oneline")));
+        assert!(!git.allows("execute_bash", &command("git status && rm -rf ~")));
+        assert!(!git.allows("execute_bash", &command("git log > /etc/passwd")));
+        assert!(!git.allows("execute_bash", &command("gitk")));
+        assert!(!git.allows("my_tool", &command("git log")));
+
+        let rm = ToolPattern::parse("execute_bash(rm *)").unwrap();
+        assert!(rm.denies("execute_bash", &command("rm -rf target")));
+        assert!(rm.denies("execute_bash", &command("cargo build && rm -rf target")));
+        assert!(rm.denies("execute_bash", &command("ls; FORCE=1 rm -rf target")));
+        assert!(rm.denies("execute_bash", &command("echo $(rm -rf target)")));
+        assert!(!rm.denies("execute_bash", &command("cargo build")));
+        assert!(!rm.denies("use_aws", &Arguments::Text("rm -rf target".to_string())));
+    }
+
+    #[test]
+    fn test_paths() {
+        let (home, cwd) = (Path::new("/home/user"), Path::new("/home/user/project"));
+        let etc = ToolPattern::parse("fs_write(/etc/*)").unwrap();
+        assert!(etc.denies("fs_write", &Arguments::paths(&["/etc/hosts"], home, cwd)));
+        assert!(etc.denies("fs_write", &Arguments::paths(&["../../../etc/hosts"], home, cwd)));
+        assert!(!etc.denies("fs_write", &Arguments::paths(&["etc/hosts"], home, cwd)));
+
+        let src = ToolPattern::parse("fs_*(src/*)").unwrap();
+        assert!(src.allows("fs_write", &Arguments::paths(&["src/main.rs"], home, cwd)));
+        assert!(src.allows("fs_write", &Arguments::paths(&["~/project/src/main.rs"], home, cwd)));
+        assert!(!src.allows("fs_write", &Arguments::paths(&["src/../../.bashrc"], home, cwd)));
+        assert!(src.allows("fs_read", &Arguments::paths(&["src/a.png", "src/b.png"], home, cwd)));
+        assert!(!src.allows("fs_read", &Arguments::paths(&["src/a.png", "b.png"], home, cwd)));
+        assert!(!src.allows("fs_read", &Arguments::None));
+    }
+
+    #[test]
+    fn test_covering_argument() {
+        let (home, cwd) = (Path::new("/home/user"), Path::new("/home/user/project"));
+        let paths = |paths: &[&[&str]]| {
+            paths
+                .iter()
+                .map(|paths| Arguments::paths(paths, home, cwd))
+                .collect::<Vec<_>>()
+        };
+        let commands = |commands: &[&str]| commands.iter().map(|c| command(c)).collect::<Vec<_>>();
+
+        let writes = paths(&[&["src/cli/mod.rs"], &["src/main.rs"], &["src/cli/chat/mod.rs"]]);
+        assert_eq!(covering_argument(&writes, cwd).as_deref(), Some("src/**"));
+        let pattern = ToolPattern::parse("fs_write(src/**)").unwrap();
+        assert!(writes.iter().all(|arguments| pattern.allows("fs_write", arguments)));
+
+        assert_eq!(
+            covering_argument(&paths(&[&["README.md"], &["src/main.rs"]]), cwd).as_deref(),
+            Some("/home/user/project/**")
+        );
+        assert_eq!(covering_argument(&paths(&[&["/etc/hosts"], &["/tmp/a"]]), cwd), None);
+        assert_eq!(covering_argument(&paths(&[&["app/[id]/page.ts"]]), cwd), None);
+
+        assert_eq!(
+            covering_argument(&commands(&["cargo build", "cargo test --workspace"]), cwd).as_deref(),
+            Some("cargo *")
+        );
+        assert_eq!(
+            covering_argument(&commands(&["npm run lint", "npm run lint"]), cwd).as_deref(),
+            Some("npm run lint")
+        );
+        assert_eq!(covering_argument(&commands(&["cargo build", "ls"]), cwd), None);
+        assert_eq!(
+            covering_argument(&commands(&["cargo build", "cargo test && rm -rf ~"]), cwd),
+            None
+        );
+        assert_eq!(covering_argument(&[Arguments::None, Arguments::None], cwd), None);
+    }
+}
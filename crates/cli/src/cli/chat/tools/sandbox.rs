@@ -0,0 +1,291 @@
+//! Runs the commands of `execute_bash` inside a sandbox rather than directly on the host, and
+//! keeps `fs_write` to the workspace mounted in it, selected with `chat.sandbox.backend`.
+
+use std::path::{
+    Component,
+    Path,
+    PathBuf,
+};
+
+use eyre::{
+    Result,
+    bail,
+};
+
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// The image commands run in with Docker when `chat.sandbox.image` isn't set.
+const DEFAULT_IMAGE: &str = "ubuntu:24.04";
+
+/// What commands are run with, from `chat.sandbox.backend`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxBackend {
+    /// A throwaway container of `chat.sandbox.image` with the workspace mounted.
+    Docker,
+    /// The host read-only except for the workspace, using bubblewrap (`bwrap`).
+    Bubblewrap,
+    /// The host read-only except for the workspace, using firejail.
+    Firejail,
+    /// Another, restricted, user of the host through `sudo -u`, its permissions deciding what
+    /// commands can touch.
+    User(String),
+}
+
+impl std::fmt::Display for SandboxBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxBackend::Docker => write!(f, "docker"),
+            SandboxBackend::Bubblewrap => write!(f, "bubblewrap"),
+            SandboxBackend::Firejail => write!(f, "firejail"),
+            SandboxBackend::User(user) => write!(f, "{user} user"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    pub backend: SandboxBackend,
+    /// The only directory commands and `fs_write` can change, from `chat.sandbox.workspace`, the
+    /// current directory by default.
+    pub workspace: PathBuf,
+    /// Whether commands can reach the network, from `chat.sandbox.network`, on by default. Not
+    /// enforced by [SandboxBackend::User].
+    pub network: bool,
+    /// The image of [SandboxBackend::Docker].
+    pub image: String,
+}
+
+impl Sandbox {
+    /// The sandbox configured in `settings`, if any, with the workspace defaulting to `cwd`.
+    ///
+    /// Errors on a backend it doesn't know rather than running commands on the host when the
+    /// user meant them not to be.
+    pub fn from_settings(settings: &Settings, cwd: &Path) -> Result<Option<Self>> {
+        let Some(backend) = settings.get_string(Setting::ChatSandboxBackend) else {
+            return Ok(None);
+        };
+        let backend = match backend.to_lowercase().as_str() {
+            "" | "none" | "host" => return Ok(None),
+            "docker" => SandboxBackend::Docker,
+            "bubblewrap" | "bwrap" => SandboxBackend::Bubblewrap,
+            "firejail" => SandboxBackend::Firejail,
+            "user" => match settings.get_string(Setting::ChatSandboxUser) {
+                Some(user) if !user.is_empty() => SandboxBackend::User(user),
+                _ => bail!("chat.sandbox.backend user needs the user to run commands as in chat.sandbox.user"),
+            },
+            other => bail!("Unknown chat.sandbox.backend '{other}', use docker, bubblewrap, firejail, user or host"),
+        };
+        let workspace = match settings.get_string(Setting::ChatSandboxWorkspace) {
+            Some(workspace) => cwd.join(workspace),
+            None => cwd.to_path_buf(),
+        };
+        Ok(Some(Self {
+            backend,
+            workspace: normalize(&workspace),
+            network: settings.get_bool(Setting::ChatSandboxNetwork).unwrap_or(true),
+            image: settings
+                .get_string(Setting::ChatSandboxImage)
+                .unwrap_or_else(|| DEFAULT_IMAGE.to_string()),
+        }))
+    }
+
+    /// The program and arguments running `command` with bash inside the sandbox.
+    pub fn command_line(&self, command: &str) -> Vec<String> {
+        let workspace = self.workspace.to_string_lossy().into_owned();
+        let mut args: Vec<String> = match &self.backend {
+            SandboxBackend::Docker => {
+                let mut args = vec!["docker".into(), "run".into(), "--rm".into(), "-i".into()];
+                if !self.network {
+                    args.extend(["--network".into(), "none".into()]);
+                }
+                args.extend([
+                    "-v".into(),
+                    format!("{workspace}:{workspace}"),
+                    "-w".into(),
+                    workspace,
+                    self.image.clone(),
+                ]);
+                args
+            },
+            SandboxBackend::Bubblewrap => {
+                let mut args = vec![
+                    "bwrap".into(),
+                    "--ro-bind".into(),
+                    "/".into(),
+                    "/".into(),
+                    "--dev".into(),
+                    "/dev".into(),
+                    "--proc".into(),
+                    "/proc".into(),
+                    "--tmpfs".into(),
+                    "/tmp".into(),
+                    "--bind".into(),
+                    workspace.clone(),
+                    workspace.clone(),
+                    "--chdir".into(),
+                    workspace,
+                    "--die-with-parent".into(),
+                ];
+                if !self.network {
+                    args.push("--unshare-net".into());
+                }
+                args
+            },
+            SandboxBackend::Firejail => {
+                let mut args = vec![
+                    "firejail".into(),
+                    "--quiet".into(),
+                    "--read-only=/".into(),
+                    format!("--read-write={workspace}"),
+                    "--private-tmp".into(),
+                ];
+                if !self.network {
+                    args.push("--net=none".into());
+                }
+                args
+            },
+            SandboxBackend::User(user) => vec!["sudo".into(), "-n".into(), "-u".into(), user.clone(), "--".into()],
+        };
+        args.extend(["bash".into(), "-c".into(), command.into()]);
+        args
+    }
+
+    /// Fails unless `path` is inside the workspace, `cwd` being what a relative path is relative
+    /// to.
+    pub fn check_path(&self, cwd: &Path, path: impl AsRef<Path>) -> Result<()> {
+        let path = normalize(&cwd.join(path));
+        if !path.starts_with(&self.workspace) {
+            bail!(
+                "{} is outside of the sandbox, only files in {} can be written to",
+                path.display(),
+                self.workspace.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `path` without its `.` and `..` components, resolved without touching the file system since
+/// the files written to may not exist yet.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_from_settings() {
+        let cwd = Path::new("/home/user/project");
+        let mut settings = Settings::new().await.unwrap();
+        assert_eq!(Sandbox::from_settings(&settings, cwd).unwrap(), None);
+
+        settings.set(Setting::ChatSandboxBackend, "host").await.unwrap();
+        assert_eq!(Sandbox::from_settings(&settings, cwd).unwrap(), None);
+
+        settings.set(Setting::ChatSandboxBackend, "Docker").await.unwrap();
+        settings.set(Setting::ChatSandboxNetwork, false).await.unwrap();
+        settings.set(Setting::ChatSandboxWorkspace, "..").await.unwrap();
+        assert_eq!(
+            Sandbox::from_settings(&settings, cwd).unwrap(),
+            Some(Sandbox {
+                backend: SandboxBackend::Docker,
+                workspace: PathBuf::from("/home/user"),
+                network: false,
+                image: DEFAULT_IMAGE.to_string(),
+            })
+        );
+
+        settings.set(Setting::ChatSandboxBackend, "user").await.unwrap();
+        assert!(Sandbox::from_settings(&settings, cwd).is_err());
+        settings.set(Setting::ChatSandboxUser, "agent").await.unwrap();
+        assert_eq!(
+            Sandbox::from_settings(&settings, cwd).unwrap().unwrap().backend,
+            SandboxBackend::User("agent".to_string())
+        );
+
+        settings.set(Setting::ChatSandboxBackend, "chroot").await.unwrap();
+        assert!(Sandbox::from_settings(&settings, cwd).is_err());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut sandbox = Sandbox {
+            backend: SandboxBackend::Docker,
+            workspace: PathBuf::from("/work"),
+            network: false,
+            image: "alpine".to_string(),
+        };
+        assert_eq!(sandbox.command_line("ls -la"), [
+            "docker",
+            "run",
+            "--rm",
+            "-i",
+            "--network",
+            "none",
+            "-v",
+            "/work:/work",
+            "-w",
+            "/work",
+            "alpine",
+            "bash",
+            "-c",
+            "ls -la"
+        ]);
+
+        sandbox.backend = SandboxBackend::Firejail;
+        sandbox.network = true;
+        assert_eq!(sandbox.command_line("ls"), [
+            "firejail",
+            "--quiet",
+            "--read-only=/",
+            "--read-write=/work",
+            "--private-tmp",
+            "bash",
+            "-c",
+            "ls"
+        ]);
+
+        sandbox.backend = SandboxBackend::Bubblewrap;
+        sandbox.network = false;
+        let args = sandbox.command_line("ls");
+        assert_eq!(args.first().map(String::as_str), Some("bwrap"));
+        assert!(args.contains(&"--unshare-net".to_string()));
+        assert!(args.ends_with(&["bash".to_string(), "-c".to_string(), "ls".to_string()]));
+
+        sandbox.backend = SandboxBackend::User("agent".to_string());
+        assert_eq!(sandbox.command_line("ls"), [
+            "sudo", "-n", "-u", "agent", "--", "bash", "-c", "ls"
+        ]);
+    }
+
+    #[test]
+    fn test_check_path() {
+        let sandbox = Sandbox {
+            backend: SandboxBackend::Bubblewrap,
+            workspace: PathBuf::from("/work"),
+            network: true,
+            image: DEFAULT_IMAGE.to_string(),
+        };
+        let cwd = Path::new("/work/src");
+        assert!(sandbox.check_path(cwd, "main.rs").is_ok());
+        assert!(sandbox.check_path(cwd, "/work/Cargo.toml").is_ok());
+        assert!(sandbox.check_path(cwd, "../../etc/passwd").is_err());
+        assert!(sandbox.check_path(cwd, "/work/../etc/passwd").is_err());
+        assert!(sandbox.check_path(cwd, "/workspace/file").is_err());
+    }
+}
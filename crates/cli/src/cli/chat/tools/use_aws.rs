@@ -14,14 +14,24 @@ use crossterm::{
 use eyre::{
     Result,
     WrapErr,
+    bail,
 };
+use globset::GlobSet;
 use serde::Deserialize;
 
+use super::environment::{
+    glob_set,
+    list,
+};
 use super::{
     InvokeOutput,
     MAX_TOOL_RESPONSE_SIZE,
     OutputKind,
 };
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
 use crate::platform::Context;
 
 const READONLY_OPS: [&str; 6] = ["get", "describe", "list", "ls", "search", "batch_get"];
@@ -32,6 +42,52 @@ const USER_AGENT_APP_NAME: &str = "AmazonQ-For-CLI";
 const USER_AGENT_VERSION_KEY: &str = "Version";
 const USER_AGENT_VERSION_VALUE: &str = env!("CARGO_PKG_VERSION");
 
+/// What `use_aws` may call, from the settings:
+///
+/// - `tools.useAws.profile`: the only profile used, whatever the model asks for
+/// - `tools.useAws.regions`: the only regions called, e.g. `us-east-1,eu-west-1`
+/// - `tools.useAws.allowedOperations`: when set, the only operations called, as `service:operation`
+///   glob patterns, e.g. `ecs:list-*,ecs:describe-*,logs:*`
+/// - `tools.useAws.allowWrite`: whether operations that can change things are called at all, off by
+///   default
+/// - `tools.useAws.askAlways`: whether the user is asked before every call, and not only those that
+///   can change things
+#[derive(Debug, Clone, Default)]
+pub struct AwsScope {
+    pub profile: Option<String>,
+    pub regions: Vec<String>,
+    pub allowed_operations: Option<(Vec<String>, GlobSet)>,
+    pub allow_write: bool,
+    pub ask_always: bool,
+}
+
+impl AwsScope {
+    /// The scope of `settings`. Errors on an invalid pattern rather than allowing operations the
+    /// user meant to keep the model from.
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        let allowed_operations = match list(settings, Setting::ToolsUseAwsAllowedOperations) {
+            Some(patterns) => {
+                let patterns = patterns
+                    .into_iter()
+                    .map(|pattern| pattern.to_lowercase())
+                    .collect::<Vec<_>>();
+                let set = glob_set("tools.useAws.allowedOperations", &patterns)?;
+                Some((patterns, set))
+            },
+            None => None,
+        };
+        Ok(Self {
+            profile: settings
+                .get_string(Setting::ToolsUseAwsProfile)
+                .filter(|profile| !profile.is_empty()),
+            regions: list(settings, Setting::ToolsUseAwsRegions).unwrap_or_default(),
+            allowed_operations,
+            allow_write: settings.get_bool(Setting::ToolsUseAwsAllowWrite).unwrap_or(false),
+            ask_always: settings.get_bool(Setting::ToolsUseAwsAskAlways).unwrap_or(false),
+        })
+    }
+}
+
 // TODO: we should perhaps composite this struct with an interface that we can use to mock the
 // actual cli with. That will allow us to more thoroughly test it.
 #[derive(Debug, Clone, Deserialize)]
@@ -42,11 +98,19 @@ pub struct UseAws {
     pub region: String,
     pub profile_name: Option<String>,
     pub label: Option<String>,
+    /// Set from the settings rather than by the model.
+    #[serde(skip)]
+    pub scope: AwsScope,
 }
 
 impl UseAws {
+    /// Whether the operation only reads, going by its name.
+    pub fn is_read_only(&self) -> bool {
+        READONLY_OPS.iter().any(|op| self.operation_name.starts_with(op))
+    }
+
     pub fn requires_acceptance(&self) -> bool {
-        !READONLY_OPS.iter().any(|op| self.operation_name.starts_with(op))
+        self.scope.ask_always || !self.is_read_only()
     }
 
     pub async fn invoke(&self, _ctx: &Context, _updates: impl Write) -> Result<InvokeOutput> {
@@ -168,7 +232,42 @@ impl UseAws {
         Ok(())
     }
 
+    /// Fails unless the call is within [AwsScope], telling the model what it can call instead.
     pub async fn validate(&mut self, _ctx: &Context) -> Result<()> {
+        if let Some(profile) = &self.scope.profile {
+            match &self.profile_name {
+                Some(profile_name) if profile_name != profile => {
+                    bail!("only the AWS profile {profile} can be used, not {profile_name}")
+                },
+                _ => self.profile_name = Some(profile.clone()),
+            }
+        }
+        if !self.scope.regions.is_empty() && !self.scope.regions.contains(&self.region) {
+            bail!(
+                "the region {} can't be used, only {}",
+                self.region,
+                self.scope.regions.join(", ")
+            );
+        }
+        let operation = format!(
+            "{}:{}",
+            self.service_name.to_lowercase(),
+            self.operation_name.to_case(Case::Kebab)
+        );
+        if let Some((patterns, allowed)) = &self.scope.allowed_operations {
+            if !allowed.is_match(&operation) {
+                bail!(
+                    "{operation} isn't one of the operations the user allows, which are {}",
+                    patterns.join(", ")
+                );
+            }
+        }
+        if !self.scope.allow_write && !self.is_read_only() {
+            bail!(
+                "{operation} can change things and use_aws is read-only, only operations that get, describe or list \
+                 can be called unless the user allows others with: q settings tools.useAws.allowWrite true"
+            );
+        }
         Ok(())
     }
 
@@ -227,6 +326,72 @@ mod tests {
         assert!(cmd.requires_acceptance());
     }
 
+    #[tokio::test]
+    async fn test_validate_scope() {
+        let ctx = Context::new();
+        let mut settings = Settings::new().await.unwrap();
+        let scoped = |scope: &AwsScope, operation: &str, region: &str, profile: Option<&str>| {
+            let (service_name, operation_name) = operation.split_once(':').unwrap();
+            let mut use_aws = serde_json::from_value::<UseAws>(serde_json::json!({
+                "service_name": service_name,
+                "operation_name": operation_name,
+                "region": region,
+                "profile_name": profile,
+            }))
+            .unwrap();
+            use_aws.scope = scope.clone();
+            use_aws
+        };
+
+        // Read-only by default
+        let scope = AwsScope::from_settings(&settings).unwrap();
+        let mut cmd = scoped(&scope, "ecs:list-tasks", "us-west-2", Some("dev"));
+        assert!(cmd.validate(&ctx).await.is_ok());
+        assert!(!cmd.requires_acceptance());
+        let mut cmd = scoped(&scope, "ecs:update-service", "us-west-2", None);
+        assert!(cmd.validate(&ctx).await.is_err());
+
+        settings.set(Setting::ToolsUseAwsProfile, "ops").await.unwrap();
+        settings
+            .set(Setting::ToolsUseAwsRegions, "us-east-1, eu-west-1")
+            .await
+            .unwrap();
+        settings
+            .set(Setting::ToolsUseAwsAllowedOperations, "ECS:list-*,ecs:update-*")
+            .await
+            .unwrap();
+        settings.set(Setting::ToolsUseAwsAllowWrite, true).await.unwrap();
+        settings.set(Setting::ToolsUseAwsAskAlways, true).await.unwrap();
+        let scope = AwsScope::from_settings(&settings).unwrap();
+
+        let mut cmd = scoped(&scope, "ecs:list-tasks", "eu-west-1", None);
+        assert!(cmd.validate(&ctx).await.is_ok());
+        assert_eq!(cmd.profile_name.as_deref(), Some("ops"));
+        assert!(cmd.requires_acceptance());
+        // Operations as the SDKs name them match the patterns too
+        let mut cmd = scoped(&scope, "ecs:UpdateService", "us-east-1", Some("ops"));
+        assert!(cmd.validate(&ctx).await.is_ok());
+
+        for (operation, region, profile) in [
+            ("ecs:list-tasks", "eu-west-1", "dev"),
+            ("ecs:list-tasks", "us-west-2", "ops"),
+            ("ecs:delete-service", "us-east-1", "ops"),
+            ("s3:list-buckets", "us-east-1", "ops"),
+        ] {
+            let mut cmd = scoped(&scope, operation, region, Some(profile));
+            assert!(
+                cmd.validate(&ctx).await.is_err(),
+                "{operation} in {region} as {profile}"
+            );
+        }
+
+        settings
+            .set(Setting::ToolsUseAwsAllowedOperations, "ecs:[")
+            .await
+            .unwrap();
+        assert!(AwsScope::from_settings(&settings).is_err());
+    }
+
     #[test]
     fn test_use_aws_deser() {
         let cmd = use_aws! {{
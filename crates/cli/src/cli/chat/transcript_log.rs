@@ -0,0 +1,173 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tracing::warn;
+
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// Something that happened in the chat, appended to the [TranscriptLog].
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogEntry<'a> {
+    User {
+        prompt: &'a str,
+    },
+    Assistant {
+        content: &'a str,
+    },
+    /// A tool the assistant asked to use.
+    ToolUse {
+        id: &'a str,
+        name: &'a str,
+        args: &'a serde_json::Value,
+    },
+    /// A tool that was run, or that failed to.
+    ToolResult {
+        id: &'a str,
+        name: &'a str,
+        success: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    conversation_id: &'a str,
+    #[serde(flatten)]
+    entry: &'a LogEntry<'a>,
+}
+
+/// Appends every prompt, response and tool use of the chat to `chat.transcript.path` as it happens,
+/// whether or not the conversation is saved.
+///
+/// Files ending in `.jsonl` get a JSON object per line, any other file plain text.
+#[derive(Debug)]
+pub struct TranscriptLog {
+    path: PathBuf,
+    jsonl: bool,
+}
+
+impl TranscriptLog {
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        let path = settings.get_string(Setting::ChatTranscriptPath)?;
+        Some(Self::new(shellexpand::tilde(&path).as_ref()))
+    }
+
+    fn new(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        Self {
+            jsonl: path.extension().is_some_and(|extension| extension == "jsonl"),
+            path,
+        }
+    }
+
+    /// Appends `entry`. Failing to write is logged, so that the chat can go on.
+    pub fn append(&self, conversation_id: &str, entry: &LogEntry<'_>) {
+        let time = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+        let line = match self.jsonl {
+            true => match serde_json::to_string(&Record {
+                time,
+                conversation_id,
+                entry,
+            }) {
+                Ok(json) => json,
+                Err(err) => {
+                    warn!(?err, "Failed to serialize a transcript entry");
+                    return;
+                },
+            },
+            false => format_entry(&time, conversation_id, entry),
+        };
+
+        if let Err(err) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{line}"))
+        {
+            warn!(?err, path = %self.path.display(), "Failed to append to the transcript");
+        }
+    }
+}
+
+/// One plain text line for `entry`, indenting the lines of multi-line messages.
+fn format_entry(time: &str, conversation_id: &str, entry: &LogEntry<'_>) -> String {
+    let (kind, text) = match entry {
+        LogEntry::User { prompt } => ("user", (*prompt).to_string()),
+        LogEntry::Assistant { content } => ("assistant", (*content).to_string()),
+        LogEntry::ToolUse { id, name, args } => ("tool_use", format!("{name} ({id}) {args}")),
+        LogEntry::ToolResult { id, name, success } => (
+            "tool_result",
+            format!("{name} ({id}) {}", if *success { "succeeded" } else { "failed" }),
+        ),
+    };
+    format!(
+        "[{time}] [{conversation_id}] {kind}: {}",
+        text.trim_end().replace('\n', "\n    ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = serde_json::json!({ "path": "README.md" });
+        let entries = [
+            LogEntry::User { prompt: "read it" },
+            LogEntry::ToolUse {
+                id: "t1",
+                name: "fs_read",
+                args: &args,
+            },
+            LogEntry::ToolResult {
+                id: "t1",
+                name: "fs_read",
+                success: true,
+            },
+            LogEntry::Assistant {
+                content: "It says:\nhello",
+            },
+        ];
+
+        let log = TranscriptLog::new(dir.path().join("chat.jsonl"));
+        for entry in &entries {
+            log.append("conv", entry);
+        }
+        let content = std::fs::read_to_string(dir.path().join("chat.jsonl")).unwrap();
+        let records = content
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0]["type"], "user");
+        assert_eq!(records[0]["conversation_id"], "conv");
+        assert_eq!(records[1]["args"]["path"], "README.md");
+        assert_eq!(records[2]["success"], true);
+
+        let log = TranscriptLog::new(dir.path().join("chat.log"));
+        for entry in &entries {
+            log.append("conv", entry);
+        }
+        let content = std::fs::read_to_string(dir.path().join("chat.log")).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].ends_with("] [conv] user: read it"));
+        assert!(lines[1].ends_with(r#"tool_use: fs_read (t1) {"path":"README.md"}"#));
+        assert!(lines[2].ends_with("tool_result: fs_read (t1) succeeded"));
+        assert!(lines[3].ends_with("assistant: It says:"));
+        assert_eq!(lines[4], "    hello");
+    }
+}
@@ -0,0 +1,303 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use eyre::{
+    Context as _,
+    Result,
+    bail,
+};
+use regex::Regex;
+use url::Url;
+
+use super::consts::URL_CONTEXT_MAX_CHARS;
+use super::context_cache::{
+    self,
+    URL_MAX_AGE,
+};
+use super::util::truncate_safe;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+use crate::platform::Context;
+use crate::request::new_client_without_redirects;
+
+/// How long fetching a page can take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How many redirects are followed when fetching a page.
+const MAX_REDIRECTS: usize = 5;
+
+/// The most of a page read, the markup of HTML pages taking up most of it.
+const MAX_PAGE_BYTES: usize = 10 * URL_CONTEXT_MAX_CHARS;
+
+/// Elements left out of the text of pages, along with what they contain.
+static HIDDEN_ELEMENTS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)<(script|style|noscript|head|svg|nav|footer|iframe|template)\b.*?</(?:script|style|noscript|head|svg|nav|footer|iframe|template)\s*>|<!--.*?-->")
+        .expect("valid regex")
+});
+static PRE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<pre\b[^>]*>(.*?)</pre\s*>").expect("valid regex"));
+static HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<h([1-6])\b[^>]*>(.*?)</h[1-6]\s*>").expect("valid regex"));
+static LIST_ITEM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<li\b[^>]*>").expect("valid regex"));
+static INLINE_CODE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)</?code\b[^>]*>").expect("valid regex"));
+static BLOCK_BREAK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)<(?:br|/?p|/?div|/?section|/?article|/?table|/?tr|/?ul|/?ol|/?blockquote|hr)\b[^>]*>")
+        .expect("valid regex")
+});
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<[^>]*>").expect("valid regex"));
+static SPACES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[ \t\r\f\v]+").expect("valid regex"));
+static BLANK_LINES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\n\s*\n\s*\n+").expect("valid regex"));
+static ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&(#[0-9]+|#x[0-9a-fA-F]+|[a-zA-Z]+);").expect("valid regex"));
+
+/// The domains pages can be fetched from, from `chat.urlContext.allowedDomains`. None when
+/// empty, fetching pages being opted into.
+pub fn allowed_domains(settings: &Settings) -> Vec<String> {
+    let mut domains = settings.get_string_array(Setting::ChatUrlContextAllowedDomains);
+    if domains.is_empty() {
+        if let Some(domains_list) = settings.get_string(Setting::ChatUrlContextAllowedDomains) {
+            domains = domains_list
+                .split(',')
+                .map(|domain| domain.trim().to_string())
+                .collect();
+        }
+    }
+    domains.retain(|domain| !domain.is_empty());
+    domains
+}
+
+/// Whether `url` is on one of the `allowed_domains` or their subdomains.
+pub fn is_allowed(url: &Url, allowed_domains: &[String]) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    allowed_domains.iter().any(|domain| {
+        let domain = domain.trim_start_matches("*.").to_lowercase();
+        host == domain || host.ends_with(&format!(".{domain}"))
+    })
+}
+
+/// The `@https://...` references in `prompt`, for the pages to add to the context.
+pub fn inline_urls(prompt: &str) -> Vec<&str> {
+    prompt
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .filter(|word| word.starts_with("https://") || word.starts_with("http://"))
+        .map(|url| url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'']))
+        .collect()
+}
+
+/// Fetches the page at `url` as readable text, at most [URL_CONTEXT_MAX_CHARS] of it, reusing the
+/// text fetched in the last [URL_MAX_AGE].
+pub async fn fetch_page(ctx: &Context, url: &str, allowed_domains: &[String]) -> Result<String> {
+    let mut url = Url::parse(url).wrap_err("invalid URL")?;
+    check_url(&url, allowed_domains)?;
+
+    let key = context_cache::url_key(url.as_str());
+    if let Some(text) = context_cache::get(ctx, &key, Some(URL_MAX_AGE)).await {
+        return Ok(text);
+    }
+
+    // Redirects are followed only to the domains allowed
+    let client = new_client_without_redirects()?;
+    let mut redirects = 0;
+    let mut response = loop {
+        let response = client
+            .get(url.clone())
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to fetch {url}"))?;
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|value| value.to_str().ok());
+        let Some(location) = location.filter(|_| response.status().is_redirection()) else {
+            break response;
+        };
+        if redirects == MAX_REDIRECTS {
+            bail!("{url} redirected more than {MAX_REDIRECTS} times");
+        }
+        redirects += 1;
+        let next = url
+            .join(location)
+            .wrap_err_with(|| format!("{url} redirected to an invalid URL"))?;
+        if let Err(err) = check_url(&next, allowed_domains) {
+            bail!("{url} redirected to {next}, but {err}");
+        }
+        url = next;
+    };
+    let status = response.status();
+    if !status.is_success() {
+        bail!("{url} returned {status}");
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+    let mut body = Vec::new();
+    let mut truncated = false;
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_PAGE_BYTES {
+            body.extend_from_slice(&chunk[..MAX_PAGE_BYTES - body.len()]);
+            truncated = true;
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = String::from_utf8_lossy(&body).into_owned();
+
+    let text = if content_type.contains("html") || (content_type.is_empty() && body.trim_start().starts_with('<')) {
+        html_to_text(&body)
+    } else if content_type.starts_with("text/") || content_type.contains("json") || content_type.contains("xml") {
+        body
+    } else {
+        bail!("{url} isn't a text page ({content_type})");
+    };
+    let text = match truncated || text.len() > URL_CONTEXT_MAX_CHARS {
+        true => format!(
+            "{}\n[... truncated to fit the context]",
+            truncate_safe(&text, URL_CONTEXT_MAX_CHARS)
+        ),
+        false => text,
+    };
+    context_cache::put(ctx, &key, &text).await;
+    Ok(text)
+}
+
+/// Fails unless `url` is an http or https URL on one of the `allowed_domains`.
+fn check_url(url: &Url, allowed_domains: &[String]) -> Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("only http and https URLs can be added");
+    }
+    if allowed_domains.is_empty() {
+        bail!("no domains are allowed yet, add some to chat.urlContext.allowedDomains");
+    }
+    if !is_allowed(url, allowed_domains) {
+        bail!(
+            "{} isn't in chat.urlContext.allowedDomains",
+            url.host_str().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// The readable text of an HTML page, with headings, lists and code blocks as markdown.
+pub fn html_to_text(html: &str) -> String {
+    let html = HIDDEN_ELEMENTS.replace_all(html, "");
+    let html = PRE.replace_all(&html, |captures: &regex::Captures<'_>| {
+        // Kept apart from the whitespace collapsing below
+        let code = TAG.replace_all(&captures[1], "");
+        format!("\n\n```\n{}\n```\n\n", code.trim_matches('\n')).replace(' ', "\u{0}")
+    });
+    let html = HEADING.replace_all(&html, |captures: &regex::Captures<'_>| {
+        let level = captures[1].parse().unwrap_or(1);
+        format!(
+            "\n\n{} {}\n\n",
+            "#".repeat(level),
+            TAG.replace_all(&captures[2], "").trim()
+        )
+    });
+    let html = LIST_ITEM.replace_all(&html, "\n- ");
+    let html = INLINE_CODE.replace_all(&html, "`");
+    let html = BLOCK_BREAK.replace_all(&html, "\n");
+    let text = TAG.replace_all(&html, "");
+    let text = SPACES.replace_all(&text, " ");
+    let text = text.lines().map(str::trim).collect::<Vec<_>>().join("\n");
+    let text = BLANK_LINES.replace_all(&text, "\n\n");
+    decode_entities(text.trim()).replace('\u{0}', " ")
+}
+
+/// `text` with its HTML and XML character references decoded.
+pub fn decode_entities(text: &str) -> String {
+    ENTITY
+        .replace_all(text, |captures: &regex::Captures<'_>| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity
+                        .strip_prefix('#')
+                        .and_then(|decimal| decimal.parse().ok())
+                        .and_then(char::from_u32),
+                },
+            };
+            decoded.map_or_else(|| captures[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_to_text() {
+        let html = r#"<!doctype html>
+<html><head><title>Docs</title><style>body { color: red }</style></head>
+<body>
+<nav><a href="/">Home</a></nav>
+<h1>Getting <em>started</em></h1>
+<p>Install it with <code>cargo install</code> &amp; run:</p>
+<pre><code>q chat
+  --resume</code></pre>
+<ul><li>Fast</li><li>Small &lt;1 MB&gt;</li></ul>
+<script>track()</script>
+<footer>&copy; 2025</footer>
+</body></html>"#;
+        assert_eq!(
+            html_to_text(html),
+            "# Getting started\n\nInstall it with `cargo install` & run:\n\n```\nq chat\n  --resume\n```\n\n- Fast\n- Small <1 MB>"
+        );
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let url = Url::parse("https://docs.rs/tokio/latest").unwrap();
+        assert!(!is_allowed(&url, &[]));
+        assert!(is_allowed(&url, &["docs.rs".to_string()]));
+        assert!(is_allowed(&Url::parse("https://api.docs.rs").unwrap(), &[
+            "*.docs.rs".to_string()
+        ]));
+        assert!(!is_allowed(&url, &["s.rs".to_string(), "example.com".to_string()]));
+        assert!(!is_allowed(&Url::parse("https://evildocs.rs").unwrap(), &[
+            "docs.rs".to_string()
+        ]));
+    }
+
+    #[test]
+    fn test_inline_urls() {
+        assert_eq!(
+            inline_urls(
+                "Summarize @https://docs.rs/tokio/latest/tokio/, and compare it with @http://example.com/a?b=c."
+            ),
+            vec!["https://docs.rs/tokio/latest/tokio/", "http://example.com/a?b=c"]
+        );
+        assert!(inline_urls("see https://example.com or @file.rs").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_page_checks() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        assert!(fetch_page(&ctx, "ftp://example.com/file", &[]).await.is_err());
+        assert!(fetch_page(&ctx, "not a url", &[]).await.is_err());
+        let err = fetch_page(&ctx, "https://example.com", &[]).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no domains are allowed yet, add some to chat.urlContext.allowedDomains"
+        );
+        let err = fetch_page(&ctx, "https://example.com", &["docs.rs".to_string()])
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "example.com isn't in chat.urlContext.allowedDomains");
+    }
+}
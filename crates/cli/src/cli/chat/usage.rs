@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use crossterm::style::Color;
+use crossterm::{
+    queue,
+    style,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::consts::CONTEXT_WINDOW_SIZE;
+use super::token_counter::{
+    CharCount,
+    TokenCount,
+};
+
+/// Tokens sent to and received from the model over a conversation, shown by `/usage`.
+///
+/// The service doesn't report usage, so these are estimated from the size of each request and
+/// response, see [TokenCount].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionUsage {
+    pub requests: usize,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+}
+
+impl SessionUsage {
+    /// Counts a request of `input` chars that was answered with `output` chars.
+    pub fn record(&mut self, input: CharCount, output: CharCount) {
+        self.requests += 1;
+        self.input_tokens += TokenCount::from(input).value();
+        self.output_tokens += TokenCount::from(output).value();
+    }
+}
+
+/// A part of the context window shown by `/usage`.
+pub struct UsageCategory {
+    pub name: &'static str,
+    pub tokens: TokenCount,
+    pub color: Color,
+}
+
+fn percent(tokens: usize) -> f32 {
+    (tokens as f32 / CONTEXT_WINDOW_SIZE as f32) * 100.0
+}
+
+/// How many columns each of `categories` takes in a bar of `width` columns standing for the
+/// whole context window.
+fn bar_widths(categories: &[UsageCategory], width: usize) -> Vec<usize> {
+    categories
+        .iter()
+        .map(|category| ((category.tokens.value() as f64 / CONTEXT_WINDOW_SIZE as f64) * width as f64) as usize)
+        .collect()
+}
+
+/// Prints a bar of `width` columns with a segment for each of `categories`, followed by a line
+/// with the tokens of each.
+pub fn print_context_window(
+    output: &mut impl Write,
+    categories: &[UsageCategory],
+    width: usize,
+) -> Result<(), std::io::Error> {
+    let total = categories.iter().map(|category| category.tokens.value()).sum::<usize>();
+    let widths = bar_widths(categories, width);
+    let used_width = widths.iter().sum::<usize>();
+
+    queue!(
+        output,
+        style::Print(format!(
+            "\nCurrent context window ({} of {}k tokens used)\n",
+            total,
+            CONTEXT_WINDOW_SIZE / 1000
+        )),
+    )?;
+    if used_width > width {
+        queue!(
+            output,
+            style::SetForegroundColor(Color::DarkRed),
+            style::Print("█".repeat(width)),
+        )?;
+    } else {
+        for (category, category_width) in categories.iter().zip(&widths) {
+            queue!(output, style::SetForegroundColor(category.color))?;
+            // add a nice visual to mimic "tiny" progress, so the overall progress bar doesn't look
+            // too empty
+            if *category_width == 0 && category.tokens.value() > 0 {
+                queue!(output, style::Print("|"))?;
+            }
+            queue!(output, style::Print("█".repeat(*category_width)))?;
+        }
+        queue!(
+            output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("█".repeat(width - used_width)),
+        )?;
+    }
+    queue!(
+        output,
+        style::SetForegroundColor(Color::Reset),
+        style::Print(format!(" {:.2}%\n\n", percent(total))),
+    )?;
+
+    let name_width = categories
+        .iter()
+        .map(|category| category.name.chars().count())
+        .max()
+        .unwrap_or_default();
+    for category in categories {
+        queue!(
+            output,
+            style::SetForegroundColor(category.color),
+            style::Print(format!(
+                "█ {:width$} ",
+                format!("{}:", category.name),
+                width = name_width + 1
+            )),
+            style::SetForegroundColor(Color::Reset),
+            style::Print(format!(
+                "~{} tokens ({:.2}%)\n",
+                category.tokens,
+                percent(category.tokens.value())
+            )),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(tokens: usize) -> UsageCategory {
+        UsageCategory {
+            name: "test",
+            tokens: TokenCount::from(CharCount::from(tokens * 3)),
+            color: Color::Reset,
+        }
+    }
+
+    #[test]
+    fn test_bar_widths() {
+        let categories = [
+            category(CONTEXT_WINDOW_SIZE / 2),
+            category(CONTEXT_WINDOW_SIZE / 4),
+            category(10),
+        ];
+        assert_eq!(bar_widths(&categories, 80), vec![40, 20, 0]);
+    }
+
+    #[test]
+    fn test_session_usage() {
+        let mut usage = SessionUsage::default();
+        usage.record(CharCount::from(300), CharCount::from(30));
+        usage.record(CharCount::from(600), CharCount::from(60));
+        assert_eq!(usage, SessionUsage {
+            requests: 2,
+            input_tokens: 300,
+            output_tokens: 30,
+        });
+    }
+}
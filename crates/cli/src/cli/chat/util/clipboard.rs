@@ -0,0 +1,233 @@
+use std::io::Write;
+use std::process::{
+    Command,
+    Stdio,
+};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::platform::Context;
+
+/// Where [copy_to_clipboard] copied the text to, or where [paste_from_clipboard] read it from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clipboard {
+    /// The system clipboard, through a command such as `pbcopy`.
+    Command(&'static str),
+    /// The clipboard of the terminal emulator, through an OSC 52 escape sequence. This reaches the
+    /// local clipboard from an SSH session as long as the terminal supports it.
+    Osc52,
+}
+
+impl std::fmt::Display for Clipboard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Clipboard::Command(command) => write!(f, "the clipboard (using {command})"),
+            Clipboard::Osc52 => write!(f, "the terminal clipboard"),
+        }
+    }
+}
+
+/// Commands that write their stdin to the system clipboard, tried in order.
+fn clipboard_commands(ctx: &Context) -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(windows) {
+        vec![("clip", &[])]
+    } else {
+        let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+        if ctx.env().get_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(("wl-copy", &[]));
+        }
+        commands.push(("xclip", &["-selection", "clipboard"]));
+        commands.push(("xsel", &["--clipboard", "--input"]));
+        commands
+    }
+}
+
+/// Commands that print the contents of the system clipboard, tried in order.
+fn paste_commands(ctx: &Context) -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbpaste", &[])]
+    } else if cfg!(windows) {
+        vec![("powershell", &["-NoProfile", "-Command", "Get-Clipboard"])]
+    } else {
+        let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+        if ctx.env().get_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(("wl-paste", &["--no-newline"]));
+        }
+        commands.push(("xclip", &["-selection", "clipboard", "-o"]));
+        commands.push(("xsel", &["--clipboard", "--output"]));
+        commands
+    }
+}
+
+/// Copies `text` to the system clipboard, or to the terminal's clipboard through `output` when
+/// in an SSH session or when no clipboard command is available.
+pub fn copy_to_clipboard(ctx: &Context, output: &mut impl Write, text: &str) -> std::io::Result<Clipboard> {
+    if !ctx.env().in_ssh() {
+        for (command, args) in clipboard_commands(ctx) {
+            if run_clipboard_command(command, args, text).is_ok() {
+                return Ok(Clipboard::Command(command));
+            }
+        }
+    }
+
+    output.write_all(osc52_sequence(text, ctx.env().get_os("TMUX").is_some()).as_bytes())?;
+    output.flush()?;
+    Ok(Clipboard::Osc52)
+}
+
+/// Reads the system clipboard, or the terminal's clipboard through `output` when in an SSH
+/// session or when no clipboard command is available.
+pub fn paste_from_clipboard(ctx: &Context, output: &mut impl Write) -> std::io::Result<(String, Clipboard)> {
+    if !ctx.env().in_ssh() {
+        for (command, args) in paste_commands(ctx) {
+            if let Ok(text) = run_paste_command(command, args) {
+                return Ok((text, Clipboard::Command(command)));
+            }
+        }
+    }
+
+    let text = read_osc52_clipboard(output, ctx.env().get_os("TMUX").is_some())?;
+    Ok((text, Clipboard::Osc52))
+}
+
+fn run_paste_command(command: &str, args: &[&str]) -> std::io::Result<String> {
+    let output = Command::new(command)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(std::io::Error::other(format!("{command} failed"))),
+    }
+}
+
+/// How long the terminal has to answer an OSC 52 query.
+const OSC52_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Asks the terminal for its clipboard with an OSC 52 query, which terminals answer with the
+/// same sequence as the one setting it. Many terminals only answer once allowed to in their
+/// settings.
+#[cfg(unix)]
+fn read_osc52_clipboard(output: &mut impl Write, in_tmux: bool) -> std::io::Result<String> {
+    use std::os::fd::AsRawFd;
+    use std::time::Instant;
+
+    let unanswered = || {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "no clipboard command is available and the terminal didn't answer the OSC 52 query",
+        )
+    };
+
+    crossterm::terminal::enable_raw_mode()?;
+    let result = (|| {
+        output.write_all(wrap_for_tmux("\x1b]52;c;?\x07".to_string(), in_tmux).as_bytes())?;
+        output.flush()?;
+
+        let stdin = std::io::stdin().as_raw_fd();
+        let deadline = Instant::now() + OSC52_REPLY_TIMEOUT;
+        let mut reply = Vec::new();
+        loop {
+            if let Some(text) = parse_osc52_reply(&reply) {
+                return Ok(text);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut pollfd = libc::pollfd {
+                fd: stdin,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as libc::c_int) };
+            if ready <= 0 {
+                return Err(unanswered());
+            }
+            let mut buf = [0; 4096];
+            match nix::unistd::read(stdin, &mut buf) {
+                Ok(0) | Err(_) => return Err(unanswered()),
+                Ok(n) => reply.extend_from_slice(&buf[..n]),
+            }
+        }
+    })();
+    crossterm::terminal::disable_raw_mode()?;
+    result
+}
+
+#[cfg(not(unix))]
+fn read_osc52_clipboard(_output: &mut impl Write, _in_tmux: bool) -> std::io::Result<String> {
+    Err(std::io::Error::other("no clipboard command is available"))
+}
+
+/// The clipboard contents in the terminal's answer to an OSC 52 query, once it is complete. It
+/// ends with either BEL or ST.
+fn parse_osc52_reply(reply: &[u8]) -> Option<String> {
+    let start = reply.windows(4).position(|window| window == b"]52;")? + 4;
+    let rest = &reply[start..];
+    let data_start = rest.iter().position(|&byte| byte == b';')? + 1;
+    let data = &rest[data_start..];
+    let end = data
+        .iter()
+        .position(|&byte| byte == b'\x07')
+        .or_else(|| data.windows(2).position(|window| window == b"\x1b\\"))?;
+    let decoded = STANDARD.decode(&data[..end]).unwrap_or_default();
+    Some(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+fn run_clipboard_command(command: &str, args: &[&str], text: &str) -> std::io::Result<()> {
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(std::io::Error::other(format!("{command} failed"))),
+    }
+}
+
+/// The OSC 52 sequence that sets the clipboard to `text`, wrapped so that tmux passes it through
+/// to the terminal.
+fn osc52_sequence(text: &str, in_tmux: bool) -> String {
+    wrap_for_tmux(format!("\x1b]52;c;{}\x07", STANDARD.encode(text)), in_tmux)
+}
+
+fn wrap_for_tmux(sequence: String, in_tmux: bool) -> String {
+    match in_tmux {
+        true => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+        false => sequence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(osc52_sequence("hello", false), "\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(
+            osc52_sequence("hello", true),
+            "\x1bPtmux;\x1b\x1b]52;c;aGVsbG8=\x07\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_parse_osc52_reply() {
+        assert_eq!(parse_osc52_reply(b"\x1b]52;c;aGVsbG8=\x07"), Some("hello".to_string()));
+        assert_eq!(
+            parse_osc52_reply(b"\x1b]52;c;aGVsbG8=\x1b\\"),
+            Some("hello".to_string())
+        );
+        assert_eq!(parse_osc52_reply(b"\x1b]52;c;aGVs"), None);
+        assert_eq!(parse_osc52_reply(b""), None);
+    }
+}
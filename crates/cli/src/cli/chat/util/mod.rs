@@ -1,3 +1,4 @@
+pub mod clipboard;
 pub mod images;
 pub mod issue;
 pub mod shared_writer;
@@ -13,7 +14,6 @@ use aws_smithy_types::{
 use eyre::Result;
 
 use super::ChatError;
-use super::token_counter::TokenCounter;
 use crate::util::system_info::in_cloudshell;
 
 const GOV_REGIONS: &[&str] = &["us-gov-east-1", "us-gov-west-1"];
@@ -48,6 +48,27 @@ pub fn truncate_safe(s: &str, max_bytes: usize) -> &str {
     &s[..byte_count]
 }
 
+/// The content of the fenced code blocks in `text`, in order. A block left open at the end of the
+/// text, e.g. in an interrupted response, counts as well.
+pub fn fenced_code_blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for line in text.lines() {
+        let is_fence = line.trim_start().starts_with("```");
+        match (current.as_mut(), is_fence) {
+            (None, true) => current = Some(Vec::new()),
+            (Some(lines), true) => {
+                blocks.push(lines.join("\n"));
+                current = None;
+            },
+            (Some(lines), false) => lines.push(line),
+            (None, false) => (),
+        }
+    }
+    blocks.extend(current.map(|lines| lines.join("\n")));
+    blocks
+}
+
 pub fn animate_output(output: &mut impl Write, bytes: &[u8]) -> Result<(), ChatError> {
     for b in bytes.chunks(12) {
         output.write_all(b)?;
@@ -56,57 +77,6 @@ pub fn animate_output(output: &mut impl Write, bytes: &[u8]) -> Result<(), ChatE
     Ok(())
 }
 
-/// Play the terminal bell notification sound
-pub fn play_notification_bell(requires_confirmation: bool) {
-    // Don't play bell for tools that don't require confirmation
-    if !requires_confirmation {
-        return;
-    }
-
-    // Check if we should play the bell based on terminal type
-    if should_play_bell() {
-        print!("\x07"); // ASCII bell character
-        std::io::stdout().flush().unwrap();
-    }
-}
-
-/// Determine if we should play the bell based on terminal type
-fn should_play_bell() -> bool {
-    // Get the TERM environment variable
-    if let Ok(term) = std::env::var("TERM") {
-        // List of terminals known to handle bell character well
-        let bell_compatible_terms = [
-            "xterm",
-            "xterm-256color",
-            "screen",
-            "screen-256color",
-            "tmux",
-            "tmux-256color",
-            "rxvt",
-            "rxvt-unicode",
-            "linux",
-            "konsole",
-            "gnome",
-            "gnome-256color",
-            "alacritty",
-            "iterm2",
-        ];
-
-        // Check if the current terminal is in the compatible list
-        for compatible_term in bell_compatible_terms.iter() {
-            if term.starts_with(compatible_term) {
-                return true;
-            }
-        }
-
-        // For other terminals, don't play the bell
-        return false;
-    }
-
-    // If TERM is not set, default to not playing the bell
-    false
-}
-
 /// This is a simple greedy algorithm that drops the largest files first
 /// until the total size is below the limit
 ///
@@ -115,22 +85,6 @@ fn should_play_bell() -> bool {
 ///   sorted but the content will not be changed.
 ///
 /// Returns the dropped files
-pub fn drop_matched_context_files(files: &mut [(String, String)], limit: usize) -> Result<Vec<(String, String)>> {
-    files.sort_by(|a, b| TokenCounter::count_tokens(&b.1).cmp(&TokenCounter::count_tokens(&a.1)));
-    let mut total_size = 0;
-    let mut dropped_files = Vec::new();
-
-    for (filename, content) in files.iter() {
-        let size = TokenCounter::count_tokens(content);
-        if total_size + size > limit {
-            dropped_files.push((filename.clone(), content.clone()));
-        } else {
-            total_size += size;
-        }
-    }
-    Ok(dropped_files)
-}
-
 pub fn serde_value_to_document(value: serde_json::Value) -> Document {
     match value {
         serde_json::Value::Null => Document::Null,
@@ -196,24 +150,13 @@ mod tests {
     }
 
     #[test]
-    fn test_drop_matched_context_files() {
-        let mut files = vec![
-            ("file1".to_string(), "This is a test file".to_string()),
-            (
-                "file3".to_string(),
-                "Yet another test file that's has the largest context file".to_string(),
-            ),
-        ];
-        let limit = 10;
-
-        let dropped_files = drop_matched_context_files(&mut files, limit).unwrap();
-        assert_eq!(dropped_files.len(), 1);
-        assert_eq!(dropped_files[0].0, "file3");
-        assert_eq!(files.len(), 2);
-
-        for (filename, _) in dropped_files.iter() {
-            files.retain(|(f, _)| f != filename);
-        }
-        assert_eq!(files.len(), 1);
+    fn test_fenced_code_blocks() {
+        let text = "Run this:\n```sh\ncargo build\n```\nthen:\n\n  ```rust\nfn main() {\n}\n  ```\ninline ```code``` is not a block\n```\nunclosed";
+        assert_eq!(fenced_code_blocks(text), vec![
+            "cargo build",
+            "fn main() {\n}",
+            "unclosed"
+        ]);
+        assert!(fenced_code_blocks("no code here").is_empty());
     }
 }
@@ -21,6 +21,8 @@ use spinners::{
 };
 
 use super::OutputFormat;
+use super::chat::EditorCommand;
+use crate::database::settings::Settings;
 use crate::platform::diagnostics::Diagnostics;
 
 #[derive(Debug, Args, PartialEq, Eq)]
@@ -50,7 +52,10 @@ impl DiagnosticArgs {
             })?;
         }
 
-        let diagnostics = Diagnostics::new().await;
+        let mut diagnostics = Diagnostics::new().await;
+        if let Ok(settings) = Settings::new().await {
+            diagnostics.editor = Some(EditorCommand::resolve(None, &settings).diagnostic());
+        }
 
         if let Some(mut sp) = spinner {
             sp.stop();
@@ -35,8 +35,14 @@ use tracing::{
 };
 use user::UserSubcommand;
 
-use crate::cli::chat::cli::Mcp;
-use crate::cli::chat::mcp;
+use crate::cli::chat::cli::{
+    Index,
+    Mcp,
+};
+use crate::cli::chat::{
+    index,
+    mcp,
+};
 use crate::logging::{
     LogArgs,
     initialize_logging,
@@ -109,6 +115,9 @@ pub enum CliRootCommands {
     /// Model Context Protocol (MCP)
     #[command(subcommand)]
     Mcp(Mcp),
+    /// Index the workspace so that chat can find the files relevant to each prompt
+    #[command(subcommand)]
+    Index(Index),
 }
 
 impl CliRootCommands {
@@ -124,6 +133,7 @@ impl CliRootCommands {
             CliRootCommands::Version { .. } => "version",
             CliRootCommands::Chat { .. } => "chat",
             CliRootCommands::Mcp(_) => "mcp",
+            CliRootCommands::Index(_) => "index",
         }
     }
 }
@@ -213,6 +223,7 @@ impl Cli {
                 CliRootCommands::Version { changelog } => Self::print_version(changelog),
                 CliRootCommands::Chat(args) => chat::launch_chat(&mut database, &telemetry, args).await,
                 CliRootCommands::Mcp(args) => mcp::execute_mcp(args).await,
+                CliRootCommands::Index(args) => index::execute_index(&database, args).await,
             },
             // Root command
             None => chat::launch_chat(&mut database, &telemetry, chat::cli::Chat::default()).await,
@@ -315,13 +326,26 @@ impl Cli {
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
     use super::*;
     use crate::cli::chat::cli::{
+        ChatAudit,
+        ChatExport,
+        ChatImport,
+        ChatReplay,
+        ChatSearch,
+        ChatSecrets,
+        ChatSessions,
+        ChatSubcommand,
+        ExportFormat,
         McpAdd,
         McpImport,
         McpList,
         McpRemove,
         Scope,
+        SecretsAction,
+        SessionsAction,
     };
 
     #[test]
@@ -369,11 +393,16 @@ mod test {
             subcommand: Some(CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })),
             verbose: 2,
             help_all: false,
@@ -408,11 +437,16 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: Some("my-profile".to_string()),
                 trust_all_tools: false,
                 trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })
         );
     }
@@ -424,11 +458,16 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: Some("Hello".to_string()),
                 profile: Some("my-profile".to_string()),
                 trust_all_tools: false,
                 trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })
         );
     }
@@ -440,11 +479,37 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: true,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: Some("my-profile".to_string()),
                 trust_all_tools: false,
                 trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_resume_session() {
+        assert_parse!(
+            ["chat", "--resume", "debugging"],
+            CliRootCommands::Chat(Chat {
+                accept_all: false,
+                no_interactive: false,
+                resume: Some(Some("debugging".to_string())),
+                input: None,
+                profile: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })
         );
     }
@@ -456,11 +521,16 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: true,
-                resume: true,
+                resume: Some(None),
                 input: None,
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })
         );
         assert_parse!(
@@ -468,11 +538,16 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: true,
-                resume: true,
+                resume: Some(None),
                 input: None,
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })
         );
     }
@@ -484,11 +559,16 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 trust_all_tools: true,
                 trust_tools: None,
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })
         );
     }
@@ -500,11 +580,16 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: Some(vec!["".to_string()]),
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
             })
         );
     }
@@ -516,14 +601,256 @@ mod test {
             CliRootCommands::Chat(Chat {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 trust_all_tools: false,
                 trust_tools: Some(vec!["fs_read".to_string(), "fs_write".to_string()]),
+                editor: None,
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_editor() {
+        assert_parse!(
+            ["chat", "--editor", "code --wait"],
+            CliRootCommands::Chat(Chat {
+                accept_all: false,
+                no_interactive: false,
+                resume: None,
+                input: None,
+                profile: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                editor: Some("code --wait".to_string()),
+                no_spinner: false,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_no_spinner() {
+        assert_parse!(
+            ["chat", "--no-spinner"],
+            CliRootCommands::Chat(Chat {
+                accept_all: false,
+                no_interactive: false,
+                resume: None,
+                input: None,
+                profile: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                editor: None,
+                no_spinner: true,
+                dry_run: false,
+                read_only: false,
+                subcommand: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_dry_run() {
+        assert_parse!(
+            ["chat", "--dry-run"],
+            CliRootCommands::Chat(Chat {
+                dry_run: true,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_read_only() {
+        assert_parse!(
+            ["chat", "--read-only"],
+            CliRootCommands::Chat(Chat {
+                read_only: true,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_export() {
+        assert_parse!(
+            ["chat", "export", "--session", "debugging", "-o", "debugging.html"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Export(ChatExport {
+                    session: Some("debugging".to_string()),
+                    output: Some("debugging.html".to_string()),
+                    format: None,
+                })),
+                ..Default::default()
+            })
+        );
+        assert_parse!(
+            ["chat", "export", "--format", "md"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Export(ChatExport {
+                    session: None,
+                    output: None,
+                    format: Some(ExportFormat::Markdown),
+                })),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_search() {
+        assert_parse!(
+            ["chat", "search", "dynamodb throttling", "--tag", "infra"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Search(ChatSearch {
+                    query: "dynamodb throttling".to_string(),
+                    tag: Some("infra".to_string()),
+                })),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_import() {
+        assert_parse!(
+            ["chat", "import", "conversations.json", "--title", "sqs"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Import(ChatImport {
+                    file: "conversations.json".to_string(),
+                    name: None,
+                    title: Some("sqs".to_string()),
+                    force: false,
+                })),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_replay() {
+        assert_parse!(
+            ["chat", "replay", "debugging", "--delay", "2"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Replay(ChatReplay {
+                    session: "debugging".to_string(),
+                    delay: Some(2),
+                })),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_audit() {
+        assert_parse!(
+            ["chat", "audit", "--tool", "execute_bash", "--since", "12h", "-n", "10"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Audit(ChatAudit {
+                    tool: Some("execute_bash".to_string()),
+                    since: Some(Duration::from_secs(12 * 60 * 60)),
+                    limit: 10,
+                    json: false,
+                })),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_secrets() {
+        assert_parse!(
+            ["chat", "secrets", "set", "internal-api"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Secrets(ChatSecrets {
+                    action: SecretsAction::Set {
+                        name: "internal-api".to_string()
+                    },
+                })),
+                ..Default::default()
             })
         );
     }
+
+    #[test]
+    fn test_chat_sessions_prune() {
+        assert_parse!(
+            [
+                "chat",
+                "sessions",
+                "prune",
+                "--older-than",
+                "30d",
+                "--max-size",
+                "500MB"
+            ],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Sessions(ChatSessions {
+                    action: Some(SessionsAction::Prune {
+                        older_than: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+                        max_size: Some(500 * 1024 * 1024),
+                        dry_run: false,
+                    }),
+                })),
+                ..Default::default()
+            })
+        );
+        assert!(Cli::try_parse_from(["q", "chat", "sessions", "prune", "--older-than", "30"]).is_err());
+        assert!(Cli::try_parse_from(["q", "chat", "sessions", "prune", "--max-size", "5TB"]).is_err());
+    }
+
+    #[test]
+    fn test_chat_sessions() {
+        assert_parse!(
+            ["chat", "sessions"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Sessions(ChatSessions { action: None })),
+                ..Default::default()
+            })
+        );
+        assert_parse!(
+            ["chat", "sessions", "rm", "debugging"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Sessions(ChatSessions {
+                    action: Some(SessionsAction::Delete {
+                        name: "debugging".to_string()
+                    }),
+                })),
+                ..Default::default()
+            })
+        );
+        assert_parse!(
+            ["chat", "sessions", "rename", "debugging", "auth", "-f"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Sessions(ChatSessions {
+                    action: Some(SessionsAction::Rename {
+                        name: "debugging".to_string(),
+                        new_name: "auth".to_string(),
+                        force: true,
+                    }),
+                })),
+                ..Default::default()
+            })
+        );
+        assert_parse!(
+            ["chat", "sessions", "sync"],
+            CliRootCommands::Chat(Chat {
+                subcommand: Some(ChatSubcommand::Sessions(ChatSessions {
+                    action: Some(SessionsAction::Sync),
+                })),
+                ..Default::default()
+            })
+        );
+    }
+
     #[test]
     fn test_mcp_subcomman_add() {
         assert_parse!(
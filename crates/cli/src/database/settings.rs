@@ -15,7 +15,7 @@ use tokio::io::{
 
 use super::DatabaseError;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Setting {
     TelemetryEnabled,
     OldClientId,
@@ -25,13 +25,72 @@ pub enum Setting {
     ChatGreetingEnabled,
     ApiTimeout,
     ChatEditMode,
+    ChatEditModeVi,
+    ChatEditModeTemplate,
+    ChatEditModeAutoOpenLines,
+    ChatEditModeIncludeHistory,
+    ChatEditModeWatchGracePeriod,
+    ChatEditModeEditor,
+    ChatEditModeFileExtension,
+    ChatAutopage,
     ChatEnableNotifications,
+    ChatLargePromptThreshold,
+    ChatNotificationThreshold,
+    ChatNotifyWhenAway,
+    ChatHistoryEnabled,
+    ChatHistorySize,
+    ChatTheme,
+    ChatPrompt,
+    ChatStatusLine,
+    ChatMultiline,
+    ChatSpinnerStyle,
+    ChatSpinnerElapsed,
+    ChatAutosaveTurns,
+    ChatAutoCompactThreshold,
+    ChatTranscriptPath,
+    ChatAuditEnabled,
+    ChatAuditPath,
+    ChatSessionsRetention,
+    ChatShareGithubToken,
+    ChatSyncUrl,
+    ChatSyncRegion,
+    ChatAutoContext,
+    ChatUrlContextAllowedDomains,
+    ChatRedactionEnabled,
+    ChatRedactionPatterns,
+    ChatSystemPrompt,
+    ChatIndexTopK,
+    ChatSnippets,
+    ChatSandboxBackend,
+    ChatSandboxImage,
+    ChatSandboxNetwork,
+    ChatSandboxWorkspace,
+    ChatSandboxUser,
+    ChatShellPty,
+    ChatToolOutputMaxBytes,
+    ChatToolOutputMaxLines,
     ApiCodeWhispererService,
     ApiQService,
     McpInitTimeout,
     McpNoInteractiveTimeout,
     McpLoadedBefore,
     TrustedTools,
+    DeniedTools,
+    ToolsTimeoutMs,
+    ToolsTimeouts,
+    ToolsEnvAllowlist,
+    ToolsEnvDenylist,
+    ToolsUseAwsProfile,
+    ToolsUseAwsRegions,
+    ToolsUseAwsAllowWrite,
+    ToolsUseAwsAllowedOperations,
+    ToolsUseAwsAskAlways,
+    ToolsHttpRequestAllowedDomains,
+    ToolsHttpRequestHeaders,
+    ToolsHttpRequestMaxBytes,
+    ToolsGithubIssueRepo,
+    ToolsGithubIssueTokenSecret,
+    ToolsGithubIssueApiUrl,
     TrustAllTools,
 }
 
@@ -46,13 +105,72 @@ impl AsRef<str> for Setting {
             Self::ChatGreetingEnabled => "chat.greeting.enabled",
             Self::ApiTimeout => "api.timeout",
             Self::ChatEditMode => "chat.editMode",
+            Self::ChatEditModeVi => "chat.editMode.vi",
+            Self::ChatEditModeTemplate => "chat.editMode.template",
+            Self::ChatEditModeAutoOpenLines => "chat.editMode.autoOpenLines",
+            Self::ChatEditModeIncludeHistory => "chat.editMode.includeHistory",
+            Self::ChatEditModeWatchGracePeriod => "chat.editMode.watchGracePeriod",
+            Self::ChatEditModeEditor => "chat.editMode.editor",
+            Self::ChatEditModeFileExtension => "chat.editMode.fileExtension",
+            Self::ChatAutopage => "chat.autopage",
             Self::ChatEnableNotifications => "chat.enableNotifications",
+            Self::ChatLargePromptThreshold => "chat.largePromptThreshold",
+            Self::ChatNotificationThreshold => "chat.notificationThreshold",
+            Self::ChatNotifyWhenAway => "chat.notifyWhenAway",
+            Self::ChatHistoryEnabled => "chat.history.enabled",
+            Self::ChatHistorySize => "chat.history.size",
+            Self::ChatTheme => "chat.theme",
+            Self::ChatPrompt => "chat.prompt",
+            Self::ChatStatusLine => "chat.statusLine",
+            Self::ChatMultiline => "chat.multiline",
+            Self::ChatSpinnerStyle => "chat.spinner.style",
+            Self::ChatSpinnerElapsed => "chat.spinner.elapsed",
+            Self::ChatAutosaveTurns => "chat.autosave.turns",
+            Self::ChatAutoCompactThreshold => "chat.autoCompact.threshold",
+            Self::ChatTranscriptPath => "chat.transcript.path",
+            Self::ChatAuditEnabled => "chat.audit.enabled",
+            Self::ChatAuditPath => "chat.audit.path",
+            Self::ChatSessionsRetention => "chat.sessions.retention",
+            Self::ChatShareGithubToken => "chat.share.githubToken",
+            Self::ChatSyncUrl => "chat.sync.url",
+            Self::ChatSyncRegion => "chat.sync.region",
+            Self::ChatAutoContext => "chat.autoContext",
+            Self::ChatUrlContextAllowedDomains => "chat.urlContext.allowedDomains",
+            Self::ChatRedactionEnabled => "chat.redaction.enabled",
+            Self::ChatRedactionPatterns => "chat.redaction.patterns",
+            Self::ChatSystemPrompt => "chat.systemPrompt",
+            Self::ChatIndexTopK => "chat.index.topK",
+            Self::ChatSnippets => "chat.snippets",
+            Self::ChatSandboxBackend => "chat.sandbox.backend",
+            Self::ChatSandboxImage => "chat.sandbox.image",
+            Self::ChatSandboxNetwork => "chat.sandbox.network",
+            Self::ChatSandboxWorkspace => "chat.sandbox.workspace",
+            Self::ChatSandboxUser => "chat.sandbox.user",
+            Self::ChatShellPty => "chat.shell.pty",
+            Self::ChatToolOutputMaxBytes => "chat.toolOutput.maxBytes",
+            Self::ChatToolOutputMaxLines => "chat.toolOutput.maxLines",
             Self::ApiCodeWhispererService => "api.codewhisperer.service",
             Self::ApiQService => "api.q.service",
             Self::McpInitTimeout => "mcp.initTimeout",
             Self::McpNoInteractiveTimeout => "mcp.noInteractiveTimeout",
             Self::McpLoadedBefore => "mcp.loadedBefore",
             Self::TrustedTools => "tools.trusted",
+            Self::DeniedTools => "tools.denied",
+            Self::ToolsTimeoutMs => "tools.timeoutMs",
+            Self::ToolsTimeouts => "tools.timeouts",
+            Self::ToolsEnvAllowlist => "tools.env.allowlist",
+            Self::ToolsEnvDenylist => "tools.env.denylist",
+            Self::ToolsUseAwsProfile => "tools.useAws.profile",
+            Self::ToolsUseAwsRegions => "tools.useAws.regions",
+            Self::ToolsUseAwsAllowWrite => "tools.useAws.allowWrite",
+            Self::ToolsUseAwsAllowedOperations => "tools.useAws.allowedOperations",
+            Self::ToolsUseAwsAskAlways => "tools.useAws.askAlways",
+            Self::ToolsHttpRequestAllowedDomains => "tools.httpRequest.allowedDomains",
+            Self::ToolsHttpRequestHeaders => "tools.httpRequest.headers",
+            Self::ToolsHttpRequestMaxBytes => "tools.httpRequest.maxBytes",
+            Self::ToolsGithubIssueRepo => "tools.githubIssue.repo",
+            Self::ToolsGithubIssueTokenSecret => "tools.githubIssue.tokenSecret",
+            Self::ToolsGithubIssueApiUrl => "tools.githubIssue.apiUrl",
             Self::TrustAllTools => "tools.trustAll",
         }
     }
@@ -77,13 +195,72 @@ impl TryFrom<&str> for Setting {
             "chat.greeting.enabled" => Ok(Self::ChatGreetingEnabled),
             "api.timeout" => Ok(Self::ApiTimeout),
             "chat.editMode" => Ok(Self::ChatEditMode),
+            "chat.editMode.vi" => Ok(Self::ChatEditModeVi),
+            "chat.editMode.template" => Ok(Self::ChatEditModeTemplate),
+            "chat.editMode.autoOpenLines" => Ok(Self::ChatEditModeAutoOpenLines),
+            "chat.editMode.includeHistory" => Ok(Self::ChatEditModeIncludeHistory),
+            "chat.editMode.watchGracePeriod" => Ok(Self::ChatEditModeWatchGracePeriod),
+            "chat.editMode.editor" => Ok(Self::ChatEditModeEditor),
+            "chat.editMode.fileExtension" => Ok(Self::ChatEditModeFileExtension),
+            "chat.autopage" => Ok(Self::ChatAutopage),
             "chat.enableNotifications" => Ok(Self::ChatEnableNotifications),
+            "chat.largePromptThreshold" => Ok(Self::ChatLargePromptThreshold),
+            "chat.notificationThreshold" => Ok(Self::ChatNotificationThreshold),
+            "chat.notifyWhenAway" => Ok(Self::ChatNotifyWhenAway),
+            "chat.history.enabled" => Ok(Self::ChatHistoryEnabled),
+            "chat.history.size" => Ok(Self::ChatHistorySize),
+            "chat.theme" => Ok(Self::ChatTheme),
+            "chat.prompt" => Ok(Self::ChatPrompt),
+            "chat.statusLine" => Ok(Self::ChatStatusLine),
+            "chat.multiline" => Ok(Self::ChatMultiline),
+            "chat.spinner.style" => Ok(Self::ChatSpinnerStyle),
+            "chat.spinner.elapsed" => Ok(Self::ChatSpinnerElapsed),
+            "chat.autosave.turns" => Ok(Self::ChatAutosaveTurns),
+            "chat.autoCompact.threshold" => Ok(Self::ChatAutoCompactThreshold),
+            "chat.transcript.path" => Ok(Self::ChatTranscriptPath),
+            "chat.audit.enabled" => Ok(Self::ChatAuditEnabled),
+            "chat.audit.path" => Ok(Self::ChatAuditPath),
+            "chat.sessions.retention" => Ok(Self::ChatSessionsRetention),
+            "chat.share.githubToken" => Ok(Self::ChatShareGithubToken),
+            "chat.sync.url" => Ok(Self::ChatSyncUrl),
+            "chat.sync.region" => Ok(Self::ChatSyncRegion),
+            "chat.autoContext" => Ok(Self::ChatAutoContext),
+            "chat.urlContext.allowedDomains" => Ok(Self::ChatUrlContextAllowedDomains),
+            "chat.redaction.enabled" => Ok(Self::ChatRedactionEnabled),
+            "chat.redaction.patterns" => Ok(Self::ChatRedactionPatterns),
+            "chat.systemPrompt" => Ok(Self::ChatSystemPrompt),
+            "chat.index.topK" => Ok(Self::ChatIndexTopK),
+            "chat.snippets" => Ok(Self::ChatSnippets),
+            "chat.sandbox.backend" => Ok(Self::ChatSandboxBackend),
+            "chat.sandbox.image" => Ok(Self::ChatSandboxImage),
+            "chat.sandbox.network" => Ok(Self::ChatSandboxNetwork),
+            "chat.sandbox.workspace" => Ok(Self::ChatSandboxWorkspace),
+            "chat.sandbox.user" => Ok(Self::ChatSandboxUser),
+            "chat.shell.pty" => Ok(Self::ChatShellPty),
+            "chat.toolOutput.maxBytes" => Ok(Self::ChatToolOutputMaxBytes),
+            "chat.toolOutput.maxLines" => Ok(Self::ChatToolOutputMaxLines),
             "api.codewhisperer.service" => Ok(Self::ApiCodeWhispererService),
             "api.q.service" => Ok(Self::ApiQService),
             "mcp.initTimeout" => Ok(Self::McpInitTimeout),
             "mcp.noInteractiveTimeout" => Ok(Self::McpNoInteractiveTimeout),
             "mcp.loadedBefore" => Ok(Self::McpLoadedBefore),
             "tools.trusted" => Ok(Self::TrustedTools),
+            "tools.denied" => Ok(Self::DeniedTools),
+            "tools.timeoutMs" => Ok(Self::ToolsTimeoutMs),
+            "tools.timeouts" => Ok(Self::ToolsTimeouts),
+            "tools.env.allowlist" => Ok(Self::ToolsEnvAllowlist),
+            "tools.env.denylist" => Ok(Self::ToolsEnvDenylist),
+            "tools.useAws.profile" => Ok(Self::ToolsUseAwsProfile),
+            "tools.useAws.regions" => Ok(Self::ToolsUseAwsRegions),
+            "tools.useAws.allowWrite" => Ok(Self::ToolsUseAwsAllowWrite),
+            "tools.useAws.allowedOperations" => Ok(Self::ToolsUseAwsAllowedOperations),
+            "tools.useAws.askAlways" => Ok(Self::ToolsUseAwsAskAlways),
+            "tools.httpRequest.allowedDomains" => Ok(Self::ToolsHttpRequestAllowedDomains),
+            "tools.httpRequest.headers" => Ok(Self::ToolsHttpRequestHeaders),
+            "tools.httpRequest.maxBytes" => Ok(Self::ToolsHttpRequestMaxBytes),
+            "tools.githubIssue.repo" => Ok(Self::ToolsGithubIssueRepo),
+            "tools.githubIssue.tokenSecret" => Ok(Self::ToolsGithubIssueTokenSecret),
+            "tools.githubIssue.apiUrl" => Ok(Self::ToolsGithubIssueApiUrl),
             "tools.trustAll" => Ok(Self::TrustAllTools),
             _ => Err(DatabaseError::InvalidSetting(value.to_string())),
         }
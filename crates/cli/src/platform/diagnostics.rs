@@ -204,6 +204,19 @@ impl CurrentEnvironment {
     }
 }
 
+/// The editor used by `q chat`, see `chat.editMode.editor`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EditorDiagnostic {
+    pub command: String,
+    pub source: String,
+    /// The program and arguments the command is split into.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parsed: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Diagnostics {
@@ -211,6 +224,8 @@ pub struct Diagnostics {
     pub build_details: BuildDetails,
     pub system_info: SystemInfo,
     pub environment: CurrentEnvironment,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub editor: Option<EditorDiagnostic>,
     #[serde(flatten)]
     pub environment_variables: EnvVarDiagnostic,
 }
@@ -221,6 +236,7 @@ impl Diagnostics {
             build_details: BuildDetails::new(),
             system_info: SystemInfo::new(),
             environment: CurrentEnvironment::new().await,
+            editor: None,
             environment_variables: EnvVarDiagnostic::new(),
         }
     }
@@ -240,4 +256,18 @@ mod tests {
         let toml = diagnostics.user_readable().unwrap();
         assert!(!toml.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_diagnostics_editor() {
+        let mut diagnostics = Diagnostics::new().await;
+        diagnostics.editor = Some(EditorDiagnostic {
+            command: "code --wait".into(),
+            source: "chat.editMode.editor".into(),
+            parsed: Some(vec!["code".into(), "--wait".into(), "<file>".into()]),
+            error: None,
+        });
+        let toml = diagnostics.user_readable().unwrap();
+        assert!(toml.contains("[editor]"), "{toml}");
+        assert!(toml.contains("command = \"code --wait\""), "{toml}");
+    }
 }
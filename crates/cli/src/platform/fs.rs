@@ -312,6 +312,18 @@ impl Fs {
         }
     }
 
+    /// Query the metadata about a file, following symlinks.
+    ///
+    /// This is a proxy to [`tokio::fs::metadata`]
+    pub async fn metadata(&self, path: impl AsRef<Path>) -> io::Result<std::fs::Metadata> {
+        use inner::Inner;
+        match &self.0 {
+            Inner::Real => fs::metadata(path).await,
+            Inner::Chroot(root) => fs::metadata(append(root.path(), path)).await,
+            Inner::Fake(_) => panic!("unimplemented"),
+        }
+    }
+
     /// Query the metadata about a file without following symlinks.
     ///
     /// This is a proxy to [`tokio::fs::symlink_metadata`]
@@ -64,6 +64,17 @@ pub fn fig_data_dir() -> Result<PathBuf> {
         .join("amazon-q"))
 }
 
+/// The q state directory, used for data that should survive restarts but isn't worth backing up
+///
+/// - Linux: `$XDG_STATE_HOME/amazon-q` or `$HOME/.local/state/amazon-q`
+/// - MacOS: `$HOME/Library/Application Support/amazon-q`
+pub fn state_dir() -> Result<PathBuf> {
+    match dirs::state_dir() {
+        Some(dir) => Ok(dir.join("amazon-q")),
+        None => fig_data_dir(),
+    }
+}
+
 /// Get the macos tempdir from the `confstr` function
 ///
 /// See: <https://man7.org/linux/man-pages/man3/confstr.3.html>
@@ -123,6 +134,53 @@ pub fn chat_profiles_dir(ctx: &Context) -> Result<PathBuf> {
     Ok(home_dir(ctx)?.join(".aws").join("amazonq").join("profiles"))
 }
 
+/// The directory of the editor scratch buffers of the `q chat` sessions.
+pub fn chat_scratch_dir() -> Result<PathBuf> {
+    state_dir()
+}
+
+/// The path to the chat prompt history, shared by all chat sessions
+pub fn chat_history_path() -> Result<PathBuf> {
+    Ok(fig_data_dir()?.join("history"))
+}
+
+/// The directory of the conversations saved by name with `/save` in `q chat`
+pub fn chat_sessions_dir() -> Result<PathBuf> {
+    Ok(fig_data_dir()?.join("sessions"))
+}
+
+/// The directory of the conversations autosaved by `q chat`, to resume them after an unclean exit
+pub fn chat_autosave_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("autosave"))
+}
+
+/// The directory of the workspace indexes built with `q index build`, one file per workspace
+pub fn chat_index_dir() -> Result<PathBuf> {
+    Ok(fig_data_dir()?.join("index"))
+}
+
+/// The directory of the text extracted from documents and fetched from URLs for `q chat`, reused
+/// across sessions
+pub fn chat_context_cache_dir() -> Result<PathBuf> {
+    Ok(fig_data_dir()?.join("context-cache"))
+}
+
+/// The directory of the full output of the tools shortened in `q chat` conversations, for
+/// `/expand`
+pub fn chat_tool_output_dir() -> Result<PathBuf> {
+    Ok(fig_data_dir()?.join("tool-output"))
+}
+
+/// The directory of the conversations shared as HTML files with `/share` in `q chat`
+pub fn chat_shares_dir() -> Result<PathBuf> {
+    Ok(fig_data_dir()?.join("shares"))
+}
+
+/// The path to the log of the tools used in `q chat`, kept with `chat.audit.enabled`
+pub fn chat_audit_log_path() -> Result<PathBuf> {
+    Ok(fig_data_dir()?.join("audit.jsonl"))
+}
+
 /// The path to the fig settings file
 pub fn settings_path() -> Result<PathBuf> {
     Ok(fig_data_dir()?.join("settings.json"))
@@ -141,6 +199,8 @@ mod linux_tests {
     fn all_paths() {
         assert!(logs_dir().is_ok());
         assert!(settings_path().is_ok());
+        assert!(chat_scratch_dir().is_ok());
+        assert!(chat_history_path().is_ok());
     }
 }
 
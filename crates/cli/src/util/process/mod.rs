@@ -1,4 +1,8 @@
 pub use sysinfo::Pid;
+use sysinfo::{
+    ProcessesToUpdate,
+    System,
+};
 
 #[cfg(target_os = "windows")]
 mod windows;
@@ -9,3 +13,10 @@ pub use windows::*;
 mod unix;
 #[cfg(not(windows))]
 pub use unix::*;
+
+/// Whether the process `pid` is still running.
+pub fn is_running(pid: Pid) -> bool {
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+    system.process(pid).is_some()
+}
@@ -6,6 +6,12 @@ pub fn terminate_process(pid: Pid) -> Result<(), String> {
     nix::sys::signal::kill(nix_pid, Signal::SIGTERM).map_err(|e| format!("Failed to terminate process: {}", e))
 }
 
+/// Kills the process group led by `pid`, so that the children of a shell go along with it.
+pub fn kill_process_group(pid: Pid) -> Result<(), String> {
+    let nix_pid = nix::unistd::Pid::from_raw(pid.as_u32() as i32);
+    nix::sys::signal::killpg(nix_pid, Signal::SIGKILL).map_err(|e| format!("Failed to kill process group: {}", e))
+}
+
 #[cfg(test)]
 #[cfg(not(windows))]
 mod tests {
@@ -11,6 +11,11 @@ use windows::Win32::System::Threading::{
     TerminateProcess,
 };
 
+/// Terminates `pid`, Windows having no process groups to kill its children along with it.
+pub fn kill_process_group(pid: Pid) -> Result<(), String> {
+    terminate_process(pid)
+}
+
 /// Terminate a process on Windows using the Windows API
 pub fn terminate_process(pid: Pid) -> Result<(), String> {
     unsafe {